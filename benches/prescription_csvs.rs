@@ -0,0 +1,55 @@
+//! Throughput of the multi-CSV prescription writer on a synthetic Prescripcion.xml.
+//!
+//! `cargo bench --bench prescription_csvs --features test-util`; set
+//! `CIMA_BENCH_RECORDS` to change the number of prescriptions, 100 000 by default.
+
+use cima_rs::parser::{ParserOptions, parse_prescription_xml_to_csvs_with_options};
+use cima_rs::testing::fixtures::generate_prescription_xml;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::fs::File;
+use std::time::Duration;
+
+const DEFAULT_RECORDS: usize = 100_000;
+
+fn records() -> usize {
+    std::env::var("CIMA_BENCH_RECORDS")
+        .ok()
+        .and_then(|records| records.parse().ok())
+        .unwrap_or(DEFAULT_RECORDS)
+}
+
+fn bench_prescription_csvs(c: &mut Criterion) {
+    let records = records();
+    let work_dir = tempfile::tempdir().unwrap();
+    let xml_path = work_dir.path().join("Prescripcion.xml");
+    generate_prescription_xml(records, 42, File::create(&xml_path).unwrap()).unwrap();
+    let output_dir = work_dir.path().join("csv");
+    std::fs::create_dir(&output_dir).unwrap();
+
+    let mut group = c.benchmark_group("prescription_csvs");
+    group
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(20))
+        .throughput(Throughput::Elements(records as u64));
+    for (name, workers) in [("1 worker", 1), ("4 workers", 4)] {
+        let options = ParserOptions {
+            workers,
+            ..Default::default()
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let report = parse_prescription_xml_to_csvs_with_options(
+                    xml_path.as_path(),
+                    output_dir.as_path(),
+                    &options,
+                )
+                .unwrap();
+                assert_eq!(report.records, records);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_prescription_csvs);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+//! Streams Prescripcion.xml as Arrow record batches and hands them over to polars.
+//!
+//! polars ships its own Arrow implementation, so the batches are exchanged through an
+//! Arrow IPC file, which polars memory-maps without copying:
+//!
+//! ```text
+//! cargo run --example arrow_batches --features arrow -- Prescripcion.xml prescriptions.arrow
+//! ```
+//!
+//! ```python
+//! import polars as pl
+//! df = pl.read_ipc("prescriptions.arrow", memory_map=True)
+//! ```
+//!
+//! or from Rust with `polars::prelude::IpcReader::new(File::open("prescriptions.arrow")?).finish()`.
+
+use anyhow::Result;
+use arrow_ipc::writer::FileWriter;
+use cima_rs::parser::prescriptions_to_record_batches;
+use clap::Parser;
+use std::fs::File;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(name = "arrow_batches")]
+#[command(about = "Convert Prescripcion.xml into an Arrow IPC file", long_about = None)]
+struct Args {
+    /// Path to Prescripcion.xml
+    xml: PathBuf,
+
+    /// Arrow IPC file to write
+    output: PathBuf,
+
+    /// Rows per record batch
+    #[arg(short, long, default_value = "8192")]
+    batch_size: usize,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let batches = prescriptions_to_record_batches(&args.xml, args.batch_size)?;
+    let schema = batches.schema();
+    println!("Schema:");
+    for field in schema.fields() {
+        println!("  {}: {}", field.name(), field.data_type());
+    }
+
+    let mut writer = FileWriter::try_new(File::create(&args.output)?, &schema)?;
+    let mut rows = 0;
+    for batch in batches {
+        let batch = batch?;
+        rows += batch.num_rows();
+        writer.write(&batch)?;
+    }
+    writer.finish()?;
+
+    println!("✓ Wrote {} prescriptions to {:?}", rows, args.output);
+    Ok(())
+}
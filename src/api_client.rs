@@ -1,154 +1,602 @@
 use anyhow::{Context, Result};
-use reqwest::Client;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
 use serde::de::DeserializeOwned;
+use std::fmt;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 use tracing::instrument;
 
+#[cfg(feature = "record-replay")]
+use crate::cassette::{Cassette, RecordMode};
+
+use crate::endpoints::{Endpoint, QueryParams};
+
+pub use crate::USER_AGENT;
+
 const BASE_URL: &str = "https://cima.aemps.es/cima/rest";
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// Error status answered by the API, found in the chain of the errors of
+/// [`CimaClient`] so callers can tell a missing resource from other failures
+#[derive(Debug, Clone)]
+pub struct ApiStatusError {
+    /// Status of the response
+    pub status: StatusCode,
+    /// URL of the request
+    pub url: String,
+}
+
+impl fmt::Display for ApiStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "API returned error status {}: {}", self.status, self.url)
+    }
+}
+
+impl std::error::Error for ApiStatusError {}
+
+/// Method of [`CimaClient`] that failed and the parameters identifying its request,
+/// found in the chain of the errors of every endpoint method
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointError {
+    /// Name of the method, such as `get_medication`
+    pub method: &'static str,
+    /// Identifying parameters under their API names, such as `nregistro`, `cn`,
+    /// `maestra` or `pagina`
+    pub params: Vec<(&'static str, String)>,
+}
+
+impl EndpointError {
+    pub(crate) fn new(method: &'static str, params: Vec<(&'static str, String)>) -> Self {
+        Self { method, params }
+    }
+
+    /// Value of the parameter `name`, if it was sent
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for EndpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed", self.method)?;
+        for (i, (key, value)) in self.params.iter().enumerate() {
+            let separator = if i == 0 { " for " } else { ", " };
+            write!(f, "{separator}{key}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Configuration of a [`CimaClient`]
+#[derive(Debug, Clone)]
+pub struct CimaClientOptions {
+    /// Base URL of the REST API, the AEMPS one by default
+    pub base_url: String,
+    /// Time limit of each request, 30 seconds by default
+    pub timeout: Duration,
+    /// Time limit for connecting to the server, only bounded by `timeout` by default
+    pub connect_timeout: Option<Duration>,
+    /// `User-Agent` header of the requests, [`USER_AGENT`] by default
+    pub user_agent: String,
+    /// Maximum number of requests started per second, shared by the clones of the
+    /// client. Unlimited by default.
+    pub rate_limit: Option<f64>,
+    /// Retries of the requests failing with a connection error, a timeout or a
+    /// transient status (429, 500, 502, 503 or 504)
+    pub retry: RetryPolicy,
+    /// Cassette recording the requests or answering them instead of the API
+    #[cfg(feature = "record-replay")]
+    pub cassette: Option<Arc<Cassette>>,
+}
+
+impl Default for CimaClientOptions {
+    fn default() -> Self {
+        Self {
+            base_url: BASE_URL.to_string(),
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: None,
+            user_agent: USER_AGENT.to_string(),
+            rate_limit: None,
+            retry: RetryPolicy::default(),
+            #[cfg(feature = "record-replay")]
+            cassette: None,
+        }
+    }
+}
+
+/// How a [`CimaClient`] retries requests failing with a connection error, a timeout, a
+/// connection broken while sending or a transient status: 429 Too Many Requests, 500
+/// Internal Server Error, 502 Bad Gateway, 503 Service Unavailable or 504 Gateway
+/// Timeout
+///
+/// Other error statuses are never retried. The delay before retry `n` (from 0) is
+/// `initial_delay * multiplier^n`, shifted at random by up to `jitter` times itself so
+/// clients failing together do not retry together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts of each request, the first one included, 3 by default; 1 disables
+    /// retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, 500 ms by default
+    pub initial_delay: Duration,
+    /// Factor applied to the delay after every retry, 2 by default
+    pub multiplier: f64,
+    /// Fraction of the delay added or removed at random, from 0 to 1, 0.2 by default
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Policy sending each request once
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Delay before retry `retry`, counted from 0
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self.initial_delay.as_secs_f64()
+            * self
+                .multiplier
+                .max(0.0)
+                .powi(retry.min(i32::MAX as u32) as i32);
+        let jitter = self.jitter.clamp(0.0, 1.0) * (2.0 * random_fraction() - 1.0);
+        Duration::try_from_secs_f64(delay * (1.0 + jitter)).unwrap_or(Duration::MAX)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(500),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+/// Whether a response with `status` may succeed when sent again, see [`RetryPolicy`]
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Whether a request failing with `error` may succeed when sent again: the connection
+/// could not be made, timed out or broke while the request was sent. Malformed
+/// responses, redirect loops and requests that could not be built are not retried.
+fn is_transient_error(error: &reqwest::Error) -> bool {
+    if error.is_connect() || error.is_timeout() {
+        return true;
+    }
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        if cause.is::<std::io::Error>() {
+            return error.is_request();
+        }
+        source = cause.source();
+    }
+    false
+}
+
+/// Number in `[0, 1)` that differs between calls, for the jitter of retries
+fn random_fraction() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+    // Every RandomState has new keys, so hashing nothing still gives a fresh value
+    let bits = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Builder of a [`CimaClient`], starting from the defaults of [`CimaClientOptions`]
+///
+/// ```
+/// # use cima_rs::CimaClient;
+/// # use std::time::Duration;
+/// let client = CimaClient::builder()
+///     .timeout(Duration::from_secs(120))
+///     .user_agent("my-exporter/1.0")
+///     .build()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CimaClientBuilder {
+    options: CimaClientOptions,
+}
+
+impl CimaClientBuilder {
+    /// Builder with the default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Base URL of the REST API
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.options.base_url = base_url.to_string();
+        self
+    }
+
+    /// Time limit of each request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = timeout;
+        self
+    }
+
+    /// Time limit for connecting to the server
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.options.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// `User-Agent` header of the requests
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.options.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Maximum number of requests started per second
+    pub fn rate_limit(mut self, requests_per_second: f64) -> Self {
+        self.options.rate_limit = Some(requests_per_second);
+        self
+    }
+
+    /// Retries of the requests failing with a transient error
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.options.retry = retry;
+        self
+    }
+
+    /// Times a request failing with a transient error is retried, keeping the delays of
+    /// the retry policy
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.options.retry.max_attempts = max_retries.saturating_add(1);
+        self
+    }
+
+    /// Delay before the first retry, growing by the multiplier of the retry policy for
+    /// every further one
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.options.retry.initial_delay = initial_backoff;
+        self
+    }
+
+    /// Cassette recording the requests or answering them instead of the API
+    #[cfg(feature = "record-replay")]
+    pub fn cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.options.cassette = Some(cassette);
+        self
+    }
+
+    /// Creates the client
+    pub fn build(self) -> Result<CimaClient> {
+        CimaClient::with_options(self.options)
+    }
+}
+
+impl From<CimaClientOptions> for CimaClientBuilder {
+    fn from(options: CimaClientOptions) -> Self {
+        Self { options }
+    }
+}
+
 /// Client for interacting with the CIMA REST API
 #[derive(Clone, Debug)]
 pub struct CimaClient {
     base_url: String,
     pub(crate) client: Client,
+    /// Interval between requests and start time of the next one, with a rate limit
+    limiter: Option<(Duration, Arc<Mutex<Instant>>)>,
+    retry: RetryPolicy,
+    #[cfg(feature = "record-replay")]
+    cassette: Option<Arc<Cassette>>,
 }
 
 impl CimaClient {
     /// Create a new CIMA client with default configuration
     pub fn new() -> Result<Self> {
-        Self::with_base_url(BASE_URL)
+        Self::builder().build()
+    }
+
+    /// Builder of a client with a custom timeout, user agent or base URL
+    pub fn builder() -> CimaClientBuilder {
+        CimaClientBuilder::new()
     }
 
     /// Create a client with a custom base URL (useful for testing)
+    #[deprecated(note = "use `CimaClient::builder().base_url(..).build()`")]
     pub fn with_base_url(base_url: &str) -> Result<Self> {
-        tracing::debug!(base_url, "Creating CIMA client");
+        Self::builder().base_url(base_url).build()
+    }
 
-        let client = Client::builder()
-            .timeout(DEFAULT_TIMEOUT)
-            .user_agent("cima-rs/0.0.1")
-            .build()
-            .context("Failed to create HTTP client")?;
+    /// Create a client with the timeout, rate limit and retries of `options`
+    pub fn with_options(options: CimaClientOptions) -> Result<Self> {
+        tracing::debug!(?options, "Creating CIMA client");
+
+        let mut client = Client::builder()
+            .timeout(options.timeout)
+            .user_agent(&options.user_agent);
+        if let Some(connect_timeout) = options.connect_timeout {
+            client = client.connect_timeout(connect_timeout);
+        }
+        let client = client.build().context("Failed to create HTTP client")?;
+        let limiter = options.rate_limit.filter(|rate| *rate > 0.0).map(|rate| {
+            let interval = Duration::from_secs_f64(1.0 / rate);
+            (interval, Arc::new(Mutex::new(Instant::now())))
+        });
 
         Ok(Self {
-            base_url: base_url.to_string(),
+            base_url: options.base_url,
             client,
+            limiter,
+            retry: options.retry,
+            #[cfg(feature = "record-replay")]
+            cassette: options.cassette,
         })
     }
 
-    /// Construye una URL completa para un endpoint
-    pub(crate) fn build_url(&self, endpoint: &str) -> String {
-        format!("{}/{}", self.base_url, endpoint)
+    /// Create a client recording its requests to the cassette at `path`, or answering
+    /// them from it, after `mode`
+    #[cfg(feature = "record-replay")]
+    pub fn with_cassette(path: impl AsRef<std::path::Path>, mode: RecordMode) -> Result<Self> {
+        Self::builder()
+            .cassette(Arc::new(Cassette::open(path, mode)?))
+            .build()
     }
 
-    /// Realiza una petición GET y deserializa la respuesta JSON
-    #[instrument(skip(self), fields(url))]
-    pub(crate) async fn get<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
-        let url = self.build_url(endpoint);
-        tracing::Span::current().record("url", &url);
-
-        tracing::debug!("Sending GET request");
+    /// Sends `request` once the rate limit allows it, retrying it as configured, or
+    /// answers it from the cassette of the client
+    ///
+    /// Connection errors, timeouts and responses with a 429, 500, 502, 503 or 504
+    /// status are sent again under the [`RetryPolicy`]; once the attempts run out, the
+    /// last error or response is returned. Responses with any other status, errors
+    /// included, are returned at once for the caller to handle.
+    pub(crate) async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let started = Instant::now();
+        let result = self.send_once(request).await;
+        let (url, status) = match &result {
+            Ok(response) => (
+                Some(response.url().as_str()),
+                Some(response.status().as_u16()),
+            ),
+            Err(e) => (
+                e.downcast_ref::<reqwest::Error>()
+                    .and_then(|e| e.url())
+                    .map(|url| url.as_str()),
+                None,
+            ),
+        };
+        crate::telemetry::record_request(
+            url.map_or("unknown", |url| self.endpoint_of(url)),
+            status,
+            started.elapsed(),
+        );
+        result
+    }
 
-        let response = self
-            .client
-            .get(&url)
-            .send()
-            .await
-            .with_context(|| format!("Failed to send GET request to {}", url))?;
+    /// Sends `request` to the server or the cassette, see [`Self::send`]
+    async fn send_once(&self, request: RequestBuilder) -> Result<Response> {
+        #[cfg(feature = "record-replay")]
+        if let Some(cassette) = &self.cassette {
+            return cassette
+                .send(request, |request| self.send_live(request))
+                .await;
+        }
+        Ok(self.send_live(request).await?)
+    }
 
-        let status = response.status();
-        tracing::debug!(%status, "Received response");
+    /// First path segment of `url` below the base URL, such as `medicamento`, or
+    /// `external` for the documents served elsewhere
+    fn endpoint_of<'a>(&self, url: &'a str) -> &'a str {
+        url.strip_prefix(self.base_url.as_str())
+            .and_then(|path| path.strip_prefix('/'))
+            .and_then(|path| path.split(['/', '?']).next())
+            .unwrap_or("external")
+    }
 
-        if !status.is_success() {
-            tracing::error!(%status, %url, "API returned error status");
-            anyhow::bail!("API returned error status {}: {}", status, url);
+    /// Sends `request` to the server, see [`Self::send`]
+    async fn send_live(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let max_attempts = self.retry.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            let cloned = request
+                .try_clone()
+                .expect("requests without a streaming body can be cloned");
+            self.wait_for_rate_limit().await;
+            let result = cloned.send().await;
+            let retryable = match &result {
+                Ok(response) => is_transient_status(response.status()),
+                Err(e) => is_transient_error(e),
+            };
+            if !retryable || attempt >= max_attempts {
+                return result;
+            }
+            let delay = self.retry.delay(attempt - 1);
+            match &result {
+                Ok(response) => tracing::warn!(
+                    status = %response.status(),
+                    url = %response.url(),
+                    attempt,
+                    max_attempts,
+                    ?delay,
+                    "Retrying request"
+                ),
+                Err(e) => tracing::warn!(
+                    error = %e,
+                    attempt,
+                    max_attempts,
+                    ?delay,
+                    "Retrying request"
+                ),
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
         }
+    }
 
-        response
-            .json::<T>()
-            .await
-            .with_context(|| format!("Failed to deserialize JSON response from {}", url))
+    /// Waits until the next request may start under the rate limit
+    async fn wait_for_rate_limit(&self) {
+        let Some((interval, next)) = &self.limiter else {
+            return;
+        };
+        let start = {
+            let mut next = next.lock().await;
+            let start = (*next).max(Instant::now());
+            *next = start + *interval;
+            start
+        };
+        tokio::time::sleep_until(start).await;
     }
 
-    /// Realiza una petición GET con parámetros query
-    #[instrument(skip(self, params), fields(url, param_count = params.len()))]
-    pub(crate) async fn get_with_params<T: DeserializeOwned>(
+    /// URL of `endpoint` with the query string of `params`
+    pub(crate) fn build_url<P: QueryParams + ?Sized>(
         &self,
-        endpoint: &str,
-        params: &[(&str, String)],
-    ) -> Result<T> {
-        let mut url = self.build_url(endpoint);
-
-        // Build query string manually
-        if !params.is_empty() {
-            url.push('?');
-            for (i, (key, value)) in params.iter().enumerate() {
-                if i > 0 {
-                    url.push('&');
-                }
-                url.push_str(key);
-                url.push('=');
-                url.push_str(&urlencoding::encode(value));
-            }
+        endpoint: &Endpoint,
+        params: &P,
+    ) -> Result<String> {
+        let mut url = endpoint.url(&self.base_url)?;
+        for (i, (key, value)) in params.query().iter().enumerate() {
+            url.push(if i == 0 { '?' } else { '&' });
+            url.push_str(key);
+            url.push('=');
+            url.push_str(&urlencoding::encode(value));
         }
+        Ok(url)
+    }
 
-        tracing::Span::current().record("url", &url);
-        tracing::debug!(params = ?params, "Sending GET request with parameters");
+    /// Sends a GET request to `endpoint` with the query string of `params` and
+    /// deserializes its JSON response
+    ///
+    /// This is the low-level escape hatch for the paths the crate does not wrap yet,
+    /// with the rate limit, retries, cassette and tracing of the typed methods, which
+    /// are built on it. Error statuses fail with an [`ApiStatusError`].
+    ///
+    /// ```no_run
+    /// use cima_rs::{CimaClient, Endpoint};
+    ///
+    /// # async fn example() -> anyhow::Result<()> {
+    /// let client = CimaClient::new()?;
+    /// let params = vec![("nregistro", "51347".to_string())];
+    /// let sections: serde_json::Value = client
+    ///     .request(&Endpoint::raw("docSegmentado/secciones/1")?, &params)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn request<T: DeserializeOwned, P: QueryParams + ?Sized>(
+        &self,
+        endpoint: &Endpoint,
+        params: &P,
+    ) -> Result<T> {
+        let (url, response) = self.execute(Method::GET, endpoint, params, None).await?;
+        json(&url, check_status(&url, response)?).await
+    }
 
-        let response = self
-            .client
-            .get(&url)
-            .send()
+    /// Same as [`Self::request`], but returns the body of the response as text
+    pub async fn request_text<P: QueryParams + ?Sized>(
+        &self,
+        endpoint: &Endpoint,
+        params: &P,
+    ) -> Result<String> {
+        let (url, response) = self.execute(Method::GET, endpoint, params, None).await?;
+        check_status(&url, response)?
+            .text()
             .await
-            .with_context(|| format!("Failed to send GET request to {}", url))?;
+            .with_context(|| format!("Failed to read response from {}", url))
+    }
 
+    /// Same as [`Self::request`], but `None` when the API answers 204 No Content or
+    /// 404 Not Found
+    pub(crate) async fn request_optional<T: DeserializeOwned, P: QueryParams + ?Sized>(
+        &self,
+        endpoint: &Endpoint,
+        params: &P,
+    ) -> Result<Option<T>> {
+        let (url, response) = self.execute(Method::GET, endpoint, params, None).await?;
         let status = response.status();
-        tracing::debug!(%status, "Received response");
-
-        if !status.is_success() {
-            tracing::error!(%status, %url, "API returned error status");
-            anyhow::bail!("API returned error status {}: {}", status, url);
+        if status == StatusCode::NO_CONTENT || status == StatusCode::NOT_FOUND {
+            return Ok(None);
         }
-
-        response
-            .json::<T>()
-            .await
-            .with_context(|| format!("Failed to deserialize JSON response from {}", url))
+        json(&url, check_status(&url, response)?).await.map(Some)
     }
 
-    /// Realiza una petición POST con body JSON
-    #[instrument(skip(self, body), fields(url))]
+    /// Sends `body` as JSON in a POST request to `endpoint` and deserializes its JSON
+    /// response
     pub(crate) async fn post<T: DeserializeOwned, B: serde::Serialize + ?Sized>(
         &self,
-        endpoint: &str,
+        endpoint: &Endpoint,
         body: &B,
     ) -> Result<T> {
-        let url = self.build_url(endpoint);
-        tracing::Span::current().record("url", &url);
+        let body = serde_json::to_vec(body).context("Failed to serialize request body")?;
+        let (url, response) = self
+            .execute(Method::POST, endpoint, &(), Some(body))
+            .await?;
+        json(&url, check_status(&url, response)?).await
+    }
 
-        tracing::debug!("Sending POST request");
+    /// Builds the request of every API call from `endpoint` and `params`, and sends it
+    #[instrument(name = "request", skip_all, fields(%endpoint, url, status))]
+    async fn execute<P: QueryParams + ?Sized>(
+        &self,
+        method: Method,
+        endpoint: &Endpoint,
+        params: &P,
+        body: Option<Vec<u8>>,
+    ) -> Result<(String, Response)> {
+        let url = self.build_url(endpoint, params)?;
+        let span = tracing::Span::current();
+        span.record("url", &url);
+        tracing::debug!(%method, "Sending request");
 
+        let mut request = self.client.request(method.clone(), &url);
+        if let Some(body) = body {
+            request = request.header(CONTENT_TYPE, "application/json").body(body);
+        }
         let response = self
-            .client
-            .post(&url)
-            .json(body)
-            .send()
+            .send(request)
             .await
-            .with_context(|| format!("Failed to send POST request to {}", url))?;
+            .with_context(|| format!("Failed to send {} request to {}", method, url))?;
 
         let status = response.status();
+        span.record("status", status.as_u16());
         tracing::debug!(%status, "Received response");
+        Ok((url, response))
+    }
+}
 
-        if !status.is_success() {
-            tracing::error!(%status, %url, "API returned error status");
-            anyhow::bail!("API returned error status {}: {}", status, url);
+/// `response` if its status is a success, an [`ApiStatusError`] otherwise
+fn check_status(url: &str, response: Response) -> Result<Response> {
+    let status = response.status();
+    if !status.is_success() {
+        tracing::error!(%status, %url, "API returned error status");
+        return Err(ApiStatusError {
+            status,
+            url: url.to_string(),
         }
-
-        response
-            .json::<T>()
-            .await
-            .with_context(|| format!("Failed to deserialize JSON response from {}", url))
+        .into());
     }
+    Ok(response)
+}
+
+/// Deserializes the JSON body of `response`, requested from `url`
+async fn json<T: DeserializeOwned>(url: &str, response: Response) -> Result<T> {
+    response
+        .json::<T>()
+        .await
+        .with_context(|| format!("Failed to deserialize JSON response from {}", url))
 }
 
 impl Default for CimaClient {
@@ -165,14 +613,25 @@ mod tests {
     fn test_build_url() {
         let client = CimaClient::new().unwrap();
         assert_eq!(
-            client.build_url("medicamento"),
+            client.build_url(&Endpoint::Medication, &()).unwrap(),
             "https://cima.aemps.es/cima/rest/medicamento"
         );
     }
 
     #[test]
     fn test_custom_base_url() {
-        let client = CimaClient::with_base_url("http://localhost:8080").unwrap();
-        assert_eq!(client.build_url("test"), "http://localhost:8080/test");
+        let client = CimaClient::builder()
+            .base_url("http://localhost:8080")
+            .build()
+            .unwrap();
+        let endpoint = Endpoint::raw("test").unwrap();
+        let params = vec![
+            ("nombre", "ácido & co".to_string()),
+            ("pagina", "2".to_string()),
+        ];
+        assert_eq!(
+            client.build_url(&endpoint, &params).unwrap(),
+            "http://localhost:8080/test?nombre=%C3%A1cido%20%26%20co&pagina=2"
+        );
     }
 }
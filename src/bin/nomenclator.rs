@@ -1,20 +1,51 @@
-use cima_rs::downloader::download_and_extract_nomenclator;
+#[path = "nomenclator/config.rs"]
+mod config;
+
+use anyhow::Context;
+use chrono::NaiveDate;
+use cima_rs::downloader::{
+    DownloadOptions, DownloadOutcome, DownloadProgress, DownloadProgressCallback, ExtractionReport,
+    NOMENCLATOR_DUMP_URL, download_and_extract, download_and_extract_nomenclator_with_options,
+    download_client_builder,
+};
+use cima_rs::enrich::{PRESCRIPTION_NREGISTRO_CSV, enrich_prescriptions_with_nregistro};
+use cima_rs::export::{CsvExport, CsvRecord, write_clinical_descriptions_csv};
+use cima_rs::parser::schema::{DICTIONARY_TABLES, PRESCRIPTION_TABLES, Table};
 use cima_rs::parser::{
-    parse_atc_xml_to_csv, parse_dcp_xml_to_csv, parse_dcpf_xml_to_csv, parse_dcsa_xml_to_csv,
-    parse_envases_xml_to_csv, parse_excipientes_xml_to_csv,
-    parse_forma_farmaceutica_simplificada_xml_to_csv, parse_forma_farmaceutica_xml_to_csv,
-    parse_laboratorio_xml_to_csv, parse_prescription_xml_to_csvs,
-    parse_principio_activo_xml_to_csv, parse_situacion_registro_xml_to_csv,
-    parse_unidad_contenido_xml_to_csv, parse_via_administracion_xml_to_csv,
+    CsvDirStats, DiffOptions, FileOutcome, FileProgressCallback, FileStatus, NomenclatorFile,
+    NomenclatorOptions, NomenclatorReport, OnError, OutputFormat, PRESCRIPTION_XML, ParseStats,
+    ParserOptions, ProgressCallback, RecordError, RowChangeKind, StatsOptions, TableDiff,
+    csv_dir_stats_with_options, diff_csv_dirs_with_options, generate_dictionary_enums,
+    generate_postgres_schema, parse_all_nomenclator, previous_row_counts, render_summary,
+    validate_nomenclator_output,
 };
 use cima_rs::{
-    CimaClient, MasterDataParams, MasterDataType, SearchMedicationsParams,
-    SearchPresentationsParams,
+    ApiStatusError, ChangeAspect, ChangeRecord, ChangeType, CimaClient, ClinicalDescription,
+    ClinicalDescriptionFetchOpts, DocumentDownload, DocumentDownloadOptions,
+    DocumentDownloadStatus, DocumentType, MasterDataParams, MasterDataType, Medication,
+    MedicationId, MedicationSummary, PaginatedResponse, PhotoDownload, PhotoType,
+    SearchClinicalDescriptionParams, SearchMedicationsParams, SearchPresentationsParams, Section,
+    SupplyProblem, TechnicalSheetQuery,
+};
+use clap::builder::{PossibleValue, PossibleValuesParser, StringValueParser, TypedValueParser};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{
+    ArgAction, ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
 };
-use clap::{Parser, Subcommand};
-use futures::stream::{self, StreamExt};
+use clap_complete::Shell;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures::{Stream, StreamExt, TryStreamExt, stream};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tracing_subscriber::EnvFilter;
 
 #[derive(Parser, Debug)]
@@ -23,9 +54,15 @@ use tracing_subscriber::EnvFilter;
     version,
     about = "A tool to work with AEMPS CIMA nomenclator data",
     long_about = "This tool provides access to AEMPS CIMA (Centro de Información Online de Medicamentos) \
-                  data through both XML/CSV conversion and REST API queries."
+                  data through both XML/CSV conversion and REST API queries.",
+    after_long_help = EXIT_CODES_HELP
 )]
 struct Args {
+    /// Configuration file with the defaults of the options, ~/.config/cima-rs/config.toml
+    /// if it exists
+    #[arg(long, global = true, env = config::CONFIG_ENV)]
+    config: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -39,6 +76,7 @@ enum Commands {
             short,
             long,
             default_value = "csv_output",
+            env = "CIMA_OUTPUT_DIR",
             help = "Output directory for CSV files"
         )]
         output_dir: PathBuf,
@@ -48,32 +86,256 @@ enum Commands {
             short,
             long,
             default_value = "nomenclator_data",
+            env = "CIMA_WORK_DIR",
             help = "Working directory for XML files"
         )]
         work_dir: PathBuf,
 
         /// Number of concurrent parsing tasks (defaults to number of CPU cores)
-        #[arg(short, long, help = "Number of concurrent parsing tasks")]
+        #[arg(
+            short,
+            long,
+            env = "CIMA_CONCURRENCY",
+            help = "Number of concurrent parsing tasks"
+        )]
         concurrency: Option<usize>,
+
+        /// Format of the generated files
+        #[arg(
+            long = "output-format",
+            visible_alias = "format",
+            value_enum,
+            default_value_t = OutputKind::Csv
+        )]
+        format: OutputKind,
+
+        /// Compress every generated CSV or NDJSON file with gzip, adding `.gz` to its name
+        #[arg(long, conflicts_with_all = ["incremental", "validate", "enrich_nregistro"])]
+        compress: bool,
+
+        /// Convert only these files, given by short name (atc, laboratorios,
+        /// prescripciones...) or XML file name
+        #[arg(long, num_args = 1.., value_delimiter = ',', value_parser = NomenclatorFileParser)]
+        only: Vec<NomenclatorFile>,
+
+        /// Leave out these files, given like --only
+        #[arg(long, num_args = 1.., value_delimiter = ',', value_parser = NomenclatorFileParser)]
+        exclude: Vec<NomenclatorFile>,
+
+        /// Check that every code referenced by the CSV files exists in its dictionary
+        #[arg(long)]
+        validate: bool,
+
+        /// Skip XML files unchanged since the last run, as recorded in manifest.json
+        #[arg(long)]
+        incremental: bool,
+
+        /// Skip records that fail to deserialize instead of stopping, and report them
+        #[arg(long)]
+        skip_errors: bool,
+
+        /// Write the skipped records to parse_errors.json in the output directory
+        #[arg(long, requires = "skip_errors")]
+        errors_json: bool,
+
+        /// Write the row counts of every parsed file to report.json in the output directory
+        #[arg(long)]
+        report_json: bool,
+
+        /// Look up the CIMA registration number of every prescription through the REST API
+        /// and write them to prescription_nregistro.csv
+        #[arg(long)]
+        enrich_nregistro: bool,
+
+        /// Download the nomenclator again even if the work directory already holds it
+        #[arg(long)]
+        force_download: bool,
+
+        /// Parse the XML files already in the work directory without downloading anything
+        #[arg(long, conflicts_with_all = ["force_download", "keep_zip", "mirrors", "zip_cache"])]
+        skip_download: bool,
+
+        /// Keep the downloaded ZIP file in the work directory after extracting it
+        #[arg(long)]
+        keep_zip: bool,
+
+        /// URL of the nomenclator ZIP file, the AEMPS one by default
+        #[arg(long, env = "CIMA_NOMENCLATOR_URL")]
+        download_url: Option<String>,
+
+        /// Copy of the nomenclator ZIP file tried when the download fails, can be repeated
+        #[arg(long = "mirror")]
+        mirrors: Vec<String>,
+
+        /// Directory keeping the downloaded ZIP files across runs, so that another work
+        /// directory can be filled without downloading the same edition again
+        #[arg(long)]
+        zip_cache: Option<PathBuf>,
+
+        /// Print only the summary, without progress bars, e.g. for CI logs
+        #[arg(long)]
+        no_progress: bool,
+
+        /// Client of the API used by --enrich-nregistro
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Download and extract a ZIP file published by the AEMPS, the nomenclator by default
+    Download {
+        /// URL of the ZIP file
+        #[arg(long, default_value = NOMENCLATOR_DUMP_URL)]
+        source_url: String,
+
+        /// Directory where the ZIP file will be extracted
+        #[arg(short, long, default_value = "nomenclator_data")]
+        dir: PathBuf,
+
+        /// Download again even if the directory already holds the extracted files
+        #[arg(long)]
+        force: bool,
+
+        /// Keep the downloaded ZIP file in the directory after extracting it
+        #[arg(long)]
+        keep_zip: bool,
+
+        /// Copy of the ZIP file tried when the download fails, can be repeated
+        #[arg(long = "mirror")]
+        mirrors: Vec<String>,
+
+        /// Directory keeping the downloaded ZIP files across runs
+        #[arg(long)]
+        zip_cache: Option<PathBuf>,
+    },
+    /// Compare the CSV files of two output directories, such as those of two weekly runs
+    Diff {
+        /// Output directory of the previous run
+        old_dir: PathBuf,
+
+        /// Output directory of the new run
+        new_dir: PathBuf,
+
+        /// Compare only these tables, can be repeated or separated by commas
+        #[arg(long, value_delimiter = ',', value_parser = PossibleValuesParser::new(table_names()))]
+        table: Vec<String>,
+
+        /// Print the changes as text, as a JSON object with the counts per table, or as
+        /// CSV rows with the logs on standard error
+        #[arg(long, value_enum, default_value_t = DiffFormat::Text)]
+        format: DiffFormat,
+    },
+    /// Summarize the CSV files of an output directory: row counts, sizes and anomalies
+    Stats {
+        /// Output directory of the csv command
+        output_dir: PathBuf,
+
+        /// report.json of the run to compare the row counts with, the one in the output
+        /// directory by default
+        #[arg(long)]
+        previous: Option<PathBuf>,
+
+        /// Print the summary as text or as a JSON object
+        #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+        format: StatsFormat,
+    },
+    /// Load an output directory of the csv command into a SQLite database (`sqlite`
+    /// feature)
+    Db {
+        /// Directory holding the generated CSV files
+        #[arg(long)]
+        input: PathBuf,
+
+        /// SQLite database file to create
+        #[arg(long)]
+        db: PathBuf,
+
+        /// Replace the database file if it already exists
+        #[arg(long)]
+        recreate: bool,
+    },
+    /// Generate Rust enums from the small dictionaries
+    Codegen {
+        /// Directory holding the dictionary XML files, or the CSV files generated from them
+        #[arg(short, long, default_value = "nomenclator_data")]
+        dir: PathBuf,
+
+        /// Rust file to write, standard output if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print the shell completion script of this tool
+    Completions {
+        /// Shell to generate the completions for
+        #[arg(value_enum)]
+        shell: Shell,
     },
     /// Query the CIMA REST API
     Api {
+        /// Print the text summaries, or the responses of the API as JSON on standard
+        /// output with the logs on standard error
+        #[arg(long, value_enum, default_value_t = ApiFormat::Text, global = true)]
+        format: ApiFormat,
+
+        #[command(flatten)]
+        client: ClientArgs,
+
         #[command(subcommand)]
         api_command: ApiCommands,
     },
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ApiFormat {
+    /// Human readable summaries
+    Text,
+    /// Responses of the API serialized as JSON
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DiffFormat {
+    /// One line per changed row, then the counts per table
+    Text,
+    /// The changed rows and the counts per table as a JSON object
+    Json,
+    /// One CSV row per changed row
+    Csv,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum StatsFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputKind {
+    /// CSV files, one per dictionary and prescription table
+    Csv,
+    /// One JSON object per line, a file per dictionary and prescriptions.ndjson with
+    /// the nested prescription entities
+    Ndjson,
+    /// Parquet files, one per dictionary and prescription table (`parquet` feature)
+    Parquet,
+    /// SQLite database `nomenclator.sqlite` in the output directory (`sqlite` feature)
+    Sqlite,
+}
+
 #[derive(Subcommand, Debug)]
 enum ApiCommands {
     /// Query medication information
+    #[command(group(ArgGroup::new("identifier").required(true).multiple(true)))]
     Medicamento {
-        /// Registration number
-        #[arg(long, group = "identifier")]
-        nregistro: Option<String>,
+        /// Registration number, can be repeated or separated by commas
+        #[arg(long, group = "identifier", value_delimiter = ',')]
+        nregistro: Vec<String>,
 
-        /// National code
-        #[arg(long, group = "identifier")]
-        cn: Option<String>,
+        /// National code, can be repeated or separated by commas
+        #[arg(long, group = "identifier", value_delimiter = ',')]
+        cn: Vec<String>,
+
+        /// Number of medications requested at the same time
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
 
         /// Show presentations
         #[arg(short, long)]
@@ -82,6 +344,15 @@ enum ApiCommands {
         /// Show active ingredients
         #[arg(short, long)]
         activos: bool,
+
+        /// Print only these fields, separated by commas; a single field prints just
+        /// its value
+        #[arg(long, value_enum, value_delimiter = ',')]
+        fields: Vec<MedicamentoField>,
+
+        /// File to write instead of standard output
+        #[arg(long)]
+        out: Option<PathBuf>,
     },
     /// Search medications
     SearchMedicamentos {
@@ -113,6 +384,30 @@ enum ApiCommands {
         #[arg(long)]
         triangulo: bool,
 
+        #[command(flatten)]
+        pages: PageArgs,
+
+        #[command(flatten)]
+        export: ExportArgs,
+
+        /// Maximum number of results [default: 10, all of them with --all]
+        #[arg(short, long)]
+        limit: Option<usize>,
+    },
+    /// Search medications by the text of their technical data sheet
+    BuscarFt {
+        /// Section of the technical data sheet, such as 4.1 (repeat with --texto)
+        #[arg(long, required = true)]
+        seccion: Vec<String>,
+
+        /// Text to search in the preceding section
+        #[arg(long, required = true)]
+        texto: Vec<String>,
+
+        /// The following section must not contain the text
+        #[arg(long, action = ArgAction::Count)]
+        no_contiene: u8,
+
         /// Limit results
         #[arg(short, long, default_value = "10")]
         limit: usize,
@@ -125,33 +420,93 @@ enum ApiCommands {
     },
     /// Search presentations
     SearchPresentaciones {
+        /// National code
+        #[arg(long)]
+        cn: Option<String>,
+
         /// Registration number
         #[arg(long)]
         nregistro: Option<String>,
 
+        /// Medication name
+        #[arg(long)]
+        nombre: Option<String>,
+
+        /// ATC code or description
+        #[arg(long)]
+        atc: Option<String>,
+
         /// VMP code
         #[arg(long)]
         vmp: Option<String>,
 
+        /// VMPP code
+        #[arg(long)]
+        vmpp: Option<String>,
+
+        /// Active ingredient ID
+        #[arg(long)]
+        principio_activo_id: Option<i32>,
+
         /// Only commercialized
         #[arg(long)]
         comercializados: bool,
 
-        /// Limit results
-        #[arg(short, long, default_value = "10")]
-        limit: usize,
+        /// Only narcotics
+        #[arg(long)]
+        estupefacientes: bool,
+
+        /// Only psychotropics
+        #[arg(long)]
+        psicotropos: bool,
+
+        #[command(flatten)]
+        pages: PageArgs,
+
+        #[command(flatten)]
+        export: ExportArgs,
+
+        /// Maximum number of results [default: 10, all of them with --all]
+        #[arg(short, long)]
+        limit: Option<usize>,
     },
     /// Get supply problems
     SupplyProblems {
         /// National code (if not provided, returns all)
         #[arg(long)]
         cn: Option<String>,
+
+        /// Keep only the problems that are still active
+        #[arg(long)]
+        activos: bool,
+
+        /// Sort the results, after fetching every requested page
+        #[arg(long, value_enum)]
+        sort: Option<SupplySort>,
+
+        #[command(flatten)]
+        pages: PageArgs,
+
+        #[command(flatten)]
+        export: ExportArgs,
+
+        /// Maximum number of results [default: all of them]
+        #[arg(short, long)]
+        limit: Option<usize>,
     },
     /// Get safety notes for a medication
     SafetyNotes {
         /// Registration number
         #[arg(long)]
         nregistro: String,
+
+        /// Only the notes published on or after this date (dd/mm/yyyy or yyyy-mm-dd)
+        #[arg(long, value_parser = parse_date)]
+        desde: Option<NaiveDate>,
+
+        /// Directory to download the documents of the notes to
+        #[arg(long)]
+        download: Option<PathBuf>,
     },
     /// Get change log
     Changes {
@@ -162,556 +517,2917 @@ enum ApiCommands {
         /// Limit to specific registration numbers
         #[arg(long)]
         nregistro: Vec<String>,
+
+        /// Keep only the changes of these types, can be repeated or separated by commas
+        #[arg(long, value_enum, value_delimiter = ',')]
+        tipo: Vec<CambioTipo>,
+
+        /// Keep only the changes touching any of these aspects, can be repeated or
+        /// separated by commas
+        #[arg(long, value_enum, value_delimiter = ',')]
+        cambio: Vec<Cambio>,
+
+        #[command(flatten)]
+        pages: PageArgs,
+
+        #[command(flatten)]
+        export: ExportArgs,
+
+        /// Maximum number of results [default: all of them]
+        #[arg(short, long)]
+        limit: Option<usize>,
+    },
+    /// Poll the change log, printing each new change until interrupted with Ctrl-C
+    Watch {
+        /// Date from which to watch changes (format: dd/mm/yyyy)
+        #[arg(long)]
+        desde: String,
+
+        /// Time between polls, such as 90s, 15m or 1h
+        #[arg(long, default_value = "15m", value_parser = parse_interval)]
+        interval: Duration,
+
+        /// Keep only the changes of these registration numbers, can be repeated or
+        /// separated by commas
+        #[arg(long, value_delimiter = ',')]
+        nregistro: Vec<String>,
+
+        /// Shell command run for each change, with the change as JSON on its standard input
+        #[arg(long)]
+        exec: Option<String>,
     },
     /// Query master data catalogs
+    #[command(group(ArgGroup::new("filter").required(true).multiple(true)))]
     Maestra {
-        /// Type of master data: pa (principios activos), ff (formas farmaceuticas),
-        /// va (vias administracion), lab (laboratorios), atc (codigos ATC)
-        #[arg(long)]
-        tipo: String,
+        /// Type of master data
+        #[arg(long, value_enum)]
+        tipo: MaestraTipo,
 
         /// Name filter
-        #[arg(long)]
+        #[arg(long, group = "filter")]
         nombre: Option<String>,
 
-        /// Limit results
-        #[arg(short, long, default_value = "20")]
-        limit: usize,
-    },
-}
+        /// Numeric identifier
+        #[arg(long, group = "filter")]
+        id: Option<i32>,
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    // Initialize tracing subscriber
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-        )
-        .init();
+        /// Alphanumeric code
+        #[arg(long, group = "filter")]
+        codigo: Option<String>,
 
-    let args = Args::parse();
+        /// Only narcotics (active ingredients)
+        #[arg(long, group = "filter")]
+        estupefaciente: bool,
 
-    match args.command {
-        Commands::Csv {
-            output_dir,
-            work_dir,
-            concurrency,
-        } => process_csv(output_dir, work_dir, concurrency).await,
-        Commands::Api { api_command } => process_api(api_command).await,
-    }
-}
+        /// Only psychotropics (active ingredients)
+        #[arg(long, group = "filter")]
+        psicotropo: bool,
 
-async fn process_csv(
-    output_dir: PathBuf,
-    work_dir: PathBuf,
-    concurrency: Option<usize>,
-) -> anyhow::Result<()> {
-    // Ensure directories exist
-    fs::create_dir_all(&output_dir)?;
-    fs::create_dir_all(&work_dir)?;
+        /// 1: only elements used by medications, 0: all of them
+        #[arg(
+            long,
+            group = "filter",
+            value_parser = PossibleValuesParser::new(["0", "1"]).map(|value| u8::from(value == "1"))
+        )]
+        en_uso: Option<u8>,
 
-    // Determine concurrency level based on CPU cores
-    let num_cores = num_cpus::get();
-    let concurrency = concurrency.unwrap_or(num_cores);
+        #[command(flatten)]
+        pages: PageArgs,
 
-    tracing::info!(work_dir = ?work_dir, "Target work directory");
-    tracing::info!(output_dir = ?output_dir, "Target output directory");
-    tracing::info!(num_cores, "Available CPU cores");
-    tracing::info!(concurrency, "Concurrency level");
+        /// Maximum number of results [default: 20, all of them with --all]
+        #[arg(short, long)]
+        limit: Option<usize>,
+    },
+    /// Search clinical descriptions (VMP/VMPP)
+    Vmpp {
+        /// Medication name
+        #[arg(long)]
+        nombre: Option<String>,
 
-    // 1. Download and extract
-    tracing::info!("Downloading and extracting AEMPS Nomenclator data");
-    download_and_extract_nomenclator(&work_dir).await?;
-
-    // 2. Define files to parse
-    let mapping = vec![
-        (
-            "DICCIONARIO_ATC.xml",
-            "atc.csv",
-            parse_atc_xml_to_csv as fn(PathBuf, PathBuf) -> anyhow::Result<()>,
-        ),
-        (
-            "DICCIONARIO_DCP.xml",
-            "dcp.csv",
-            parse_dcp_xml_to_csv as fn(PathBuf, PathBuf) -> anyhow::Result<()>,
-        ),
-        ("DICCIONARIO_DCPF.xml", "dcpf.csv", parse_dcpf_xml_to_csv),
-        ("DICCIONARIO_DCSA.xml", "dcsa.csv", parse_dcsa_xml_to_csv),
-        (
-            "DICCIONARIO_ENVASES.xml",
-            "envases.csv",
-            parse_envases_xml_to_csv,
-        ),
-        (
-            "DICCIONARIO_EXCIPIENTES_DECL_OBLIGATORIA.xml",
-            "excipientes.csv",
-            parse_excipientes_xml_to_csv,
-        ),
-        (
-            "DICCIONARIO_FORMA_FARMACEUTICA.xml",
-            "forma_farmaceutica.csv",
-            parse_forma_farmaceutica_xml_to_csv,
-        ),
-        (
-            "DICCIONARIO_FORMA_FARMACEUTICA_SIMPLIFICADAS.xml",
-            "forma_farmaceutica_simplificada.csv",
-            parse_forma_farmaceutica_simplificada_xml_to_csv,
-        ),
-        (
-            "DICCIONARIO_LABORATORIOS.xml",
-            "laboratorios.csv",
-            parse_laboratorio_xml_to_csv,
-        ),
-        (
-            "DICCIONARIO_PRINCIPIOS_ACTIVOS.xml",
-            "principios_activos.csv",
-            parse_principio_activo_xml_to_csv,
-        ),
-        (
-            "DICCIONARIO_SITUACION_REGISTRO.xml",
-            "situacion_registro.csv",
-            parse_situacion_registro_xml_to_csv,
-        ),
-        (
-            "DICCIONARIO_UNIDAD_CONTENIDO.xml",
-            "unidad_contenido.csv",
-            parse_unidad_contenido_xml_to_csv,
-        ),
-        (
-            "DICCIONARIO_VIAS_ADMINISTRACION.xml",
-            "vias_administracion.csv",
-            parse_via_administracion_xml_to_csv,
-        ),
-        // Note: Prescripcion.xml is handled separately below (generates multiple CSVs)
-    ];
+        /// Active ingredient name
+        #[arg(long)]
+        principio_activo: Option<String>,
 
-    // 3. Process dictionary files in parallel using tokio streams
-    tracing::info!(
-        file_count = mapping.len(),
-        concurrency,
-        "Parsing dictionary files"
-    );
+        /// ATC code or description
+        #[arg(long)]
+        atc: Option<String>,
 
-    let results: Vec<_> = stream::iter(mapping)
-        .map(|(xml_name, csv_name, parser_fn)| {
-            let xml_path = work_dir.join(xml_name);
-            let csv_path = output_dir.join(csv_name);
-            let xml_name = xml_name.to_string();
-            let csv_name = csv_name.to_string();
-
-            async move {
-                if !xml_path.exists() {
-                    tracing::warn!(file = %xml_name, "File not found, skipping");
-                    return Ok((xml_name, csv_name, false));
-                }
+        /// Pharmaceutical form name
+        #[arg(long)]
+        forma: Option<String>,
 
-                // Spawn blocking task for CPU-bound XML parsing
-                tracing::debug!(xml = %xml_name, csv = %csv_name, "Starting parse task");
-                let result =
-                    tokio::task::spawn_blocking(move || parser_fn(xml_path, csv_path)).await;
+        /// Dose
+        #[arg(long)]
+        dosis: Option<String>,
 
-                match result {
-                    Ok(Ok(())) => {
-                        tracing::info!(xml = %xml_name, csv = %csv_name, "Completed parse");
-                        Ok((xml_name, csv_name, true))
-                    }
-                    Ok(Err(e)) => {
-                        tracing::error!(xml = %xml_name, error = %e, "Parse failed");
-                        Err(e)
-                    }
-                    Err(e) => {
-                        tracing::error!(xml = %xml_name, error = %e, "Task join failed");
-                        Err(anyhow::anyhow!("Task join error: {}", e))
-                    }
-                }
-            }
-        })
-        .buffer_unordered(concurrency)
-        .collect()
-        .await;
-
-    // 4. Handle Prescription XML separately (generates multiple CSVs)
-    let prescription_result = {
-        let xml_path = work_dir.join("Prescripcion.xml");
-        if xml_path.exists() {
-            tracing::info!("Parsing Prescripcion.xml to 7 CSV files");
-            match parse_prescription_xml_to_csvs(&xml_path, &output_dir) {
-                Ok(()) => {
-                    tracing::info!("Completed all prescription CSV files");
-                    println!("✓ Completed: prescriptions.csv");
-                    println!("✓ Completed: prescription_forms.csv");
-                    println!("✓ Completed: prescription_active_ingredients.csv");
-                    println!("✓ Completed: prescription_admin_routes.csv");
-                    println!("✓ Completed: prescription_atc.csv");
-                    println!("✓ Completed: prescription_atc_duplicates.csv");
-                    println!("✓ Completed: prescription_supply_problems.csv");
-                    Ok(())
-                }
-                Err(e) => {
-                    tracing::error!(error = ?e, "Failed to parse Prescripcion.xml");
-                    // Print full error chain for debugging
-                    eprintln!("Prescription parse error: {:#}", e);
-                    Err(e)
-                }
-            }
-        } else {
-            tracing::warn!("Prescripcion.xml not found, skipping");
-            Ok(())
-        }
-    };
+        /// Group the VMPPs under their VMP
+        #[arg(long)]
+        arbol: bool,
 
-    // 5. Report results
-    let successful = results.iter().filter(|r| r.is_ok()).count();
-    let failed = results.iter().filter(|r| r.is_err()).count();
-    let prescription_success = prescription_result.is_ok();
+        /// Only keep descriptions with at least this many commercialized presentations
+        /// (applied client-side when exporting)
+        #[arg(long)]
+        min_comercializadas: Option<i32>,
 
-    tracing::info!(
-        successful,
-        failed,
-        prescription_success,
-        "CSV parsing completed"
-    );
+        /// Export all result pages to a CSV file
+        #[arg(long)]
+        export: Option<PathBuf>,
 
-    println!("\n━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-    println!("Summary:");
-    println!("  ✓ Dictionary files successful: {}", successful);
-    if failed > 0 {
-        println!("  ✗ Dictionary files failed: {}", failed);
-    }
-    if prescription_success {
-        println!("  ✓ Prescription parsing: Success (7 CSV files)");
-    } else {
-        println!("  ✗ Prescription parsing: Failed");
-    }
-    println!("  📁 Output directory: {:?}", output_dir);
-    println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+        /// Fetch every result page instead of the first one
+        #[arg(long, conflicts_with = "export")]
+        all: bool,
 
-    if failed > 0 || !prescription_success {
-        anyhow::bail!("Some files failed to parse");
-    }
+        /// Limit results (ignored with --all)
+        #[arg(short, long, default_value = "10")]
+        limit: usize,
+    },
+    /// Show the sections of a technical data sheet or package leaflet
+    Doc {
+        /// Registration number
+        #[arg(long)]
+        nregistro: String,
 
-    Ok(())
-}
+        /// Document: ft (technical data sheet) or p (package leaflet)
+        #[arg(long, value_enum)]
+        tipo: SegmentedDoc,
 
-async fn process_api(api_command: ApiCommands) -> anyhow::Result<()> {
-    tracing::debug!("Creating CIMA client for API query");
-    let client = CimaClient::new()?;
+        /// Section to show, such as 4.3, the whole document if not given
+        #[arg(long, conflicts_with = "list")]
+        seccion: Option<String>,
 
-    match api_command {
-        ApiCommands::Medicamento {
-            nregistro,
-            cn,
-            presentaciones,
-            activos,
-        } => {
-            let med = client
-                .get_medication(nregistro.as_deref(), cn.as_deref())
-                .await?;
+        /// List the sections instead of showing their content
+        #[arg(long)]
+        list: bool,
 
-            println!("=== Medicamento ===");
-            println!("Nº Registro: {}", med.nregistro);
-            println!("Nombre: {}", med.name);
-            println!("Laboratorio: {}", med.labtitular);
-            println!("Principios Activos: {}", med.pactivos);
-            println!("Condiciones de prescripción: {}", med.cpresc);
+        /// Show the content as HTML instead of plain text
+        #[arg(long, conflicts_with = "list")]
+        html: bool,
 
-            if let Some(comerc) = med.commercialized {
-                println!("Comercializado: {}", if comerc { "Sí" } else { "No" });
-            }
+        /// File to write instead of standard output
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Download the PDF documents of several medications into a directory
+    #[command(group(ArgGroup::new("source").required(true).multiple(true)))]
+    DownloadDocs {
+        /// Document to download
+        #[arg(long, value_enum)]
+        tipo: DownloadDoc,
+
+        /// Directory to write the documents and report.json to
+        #[arg(long)]
+        out: PathBuf,
 
-            if let Some(triangulo) = med.black_triangle
-                && triangulo
-            {
-                println!("⚠️  Triángulo negro (medicamento bajo vigilancia adicional)");
-            }
+        /// Registration number, can be repeated or separated by commas
+        #[arg(long, group = "source", value_delimiter = ',')]
+        nregistro: Vec<String>,
+
+        /// File with one registration number per line, `#` starts a comment
+        #[arg(long, group = "source")]
+        from_file: Option<PathBuf>,
+
+        /// Number of documents downloaded at the same time
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+
+        /// Download again the documents already in the directory
+        #[arg(long)]
+        overwrite: bool,
+
+        /// Download the HTML version instead of the PDF
+        #[arg(long)]
+        html: bool,
+
+        /// Exit successfully even if some documents could not be downloaded
+        #[arg(long)]
+        allow_partial: bool,
+    },
+    /// Download the photos of a medication
+    Fotos {
+        /// Registration number
+        #[arg(long)]
+        nregistro: String,
+
+        /// Only the photos of this type
+        #[arg(long, value_enum)]
+        tipo: Option<FotoTipo>,
+
+        /// Directory to write the photos to
+        #[arg(long)]
+        out: PathBuf,
+    },
+}
+
+/// Client of the CIMA REST API, for `nomenclator api` and `--enrich-nregistro`
+#[derive(clap::Args, Debug)]
+struct ClientArgs {
+    /// Base URL of the CIMA REST API, the AEMPS one by default
+    #[arg(long, env = "CIMA_API_URL", global = true)]
+    base_url: Option<String>,
+
+    /// Maximum number of requests per second sent to the API, unlimited by default
+    #[arg(long, env = "CIMA_RATE_LIMIT", global = true)]
+    rate_limit: Option<f64>,
+
+    /// Times a request failing with a connection error, a timeout or a 429, 500,
+    /// 502, 503 or 504 status is retried
+    #[arg(long, env = "CIMA_RETRIES", default_value_t = 2, global = true)]
+    retries: u32,
+
+    /// Time limit of each request to the API, in seconds
+    #[arg(long, env = "CIMA_TIMEOUT", default_value_t = 30, global = true)]
+    timeout: u64,
+}
+
+impl ClientArgs {
+    /// Client with the base URL, rate limit, retries and timeout given
+    fn client(&self) -> anyhow::Result<CimaClient> {
+        let mut builder = CimaClient::builder()
+            .max_retries(self.retries)
+            .timeout(Duration::from_secs(self.timeout));
+        if let Some(base_url) = &self.base_url {
+            builder = builder.base_url(base_url);
+        }
+        if let Some(rate_limit) = self.rate_limit {
+            builder = builder.rate_limit(rate_limit);
+        }
+        builder.build()
+    }
+}
+
+/// Page selection of the paginated `nomenclator api` subcommands
+#[derive(clap::Args, Debug)]
+struct PageArgs {
+    /// Page of results to request
+    #[arg(long, conflicts_with = "all")]
+    page: Option<u32>,
+
+    /// Request every page, printing the results as they arrive
+    #[arg(long)]
+    all: bool,
+}
+
+impl PageArgs {
+    /// `limit` if given, otherwise no maximum with `--all` and `default` on a single page
+    fn limit(&self, limit: Option<usize>, default: usize) -> usize {
+        limit.unwrap_or(if self.all { usize::MAX } else { default })
+    }
+}
+
+/// CSV export of the paginated `nomenclator api` subcommands
+#[derive(clap::Args, Debug, Default)]
+struct ExportArgs {
+    /// Also write the results to a CSV file
+    #[arg(long)]
+    export: Option<PathBuf>,
+
+    /// Overwrite the --export file if it already exists
+    #[arg(long, requires = "export")]
+    force: bool,
+}
+
+impl ExportArgs {
+    /// Creates the export file, refusing to replace an existing one without `--force`
+    fn create<T: CsvRecord>(&self) -> anyhow::Result<Option<CsvExport<T>>> {
+        let Some(path) = &self.export else {
+            return Ok(None);
+        };
+        if path.exists() && !self.force {
+            return Err(exit_error(
+                EXIT_USAGE,
+                format!("{:?} already exists, use --force to overwrite it", path),
+            ));
+        }
+        CsvExport::create(path)
+            .with_context(|| format!("Failed to create {:?}", path))
+            .map(Some)
+    }
+}
+
+/// Order of `nomenclator api supply-problems`
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SupplySort {
+    /// Start date, oldest first
+    Inicio,
+    /// National code
+    Cn,
+    /// Presentation name
+    Nombre,
+}
+
+impl SupplySort {
+    fn sort(self, problems: &mut [SupplyProblem]) {
+        match self {
+            SupplySort::Inicio => problems.sort_by_key(|p| p.fini),
+            SupplySort::Cn => problems.sort_by(|a, b| a.cn.cmp(&b.cn)),
+            SupplySort::Nombre => problems.sort_by(|a, b| a.name.cmp(&b.name)),
+        }
+    }
+}
+
+/// Type of change of `nomenclator api changes`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum CambioTipo {
+    /// Medicamento nuevo
+    Nuevo,
+    /// Medicamento dado de baja
+    Baja,
+    /// Medicamento modificado
+    Modificado,
+}
+
+impl From<CambioTipo> for ChangeType {
+    fn from(tipo: CambioTipo) -> Self {
+        match tipo {
+            CambioTipo::Nuevo => ChangeType::New,
+            CambioTipo::Baja => ChangeType::Deleted,
+            CambioTipo::Modificado => ChangeType::Modified,
+        }
+    }
+}
+
+/// What changed, in `nomenclator api changes`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Cambio {
+    /// Estado de autorización
+    Estado,
+    /// Comercialización
+    Comerc,
+    /// Prospecto
+    Prosp,
+    /// Ficha técnica
+    Ft,
+    /// Problemas de suministro
+    Psum,
+    /// Notas de seguridad
+    #[value(alias = "notasSeguridad")]
+    NotasSeguridad,
+    /// Materiales informativos
+    Matinf,
+    /// Otros cambios
+    Otros,
+}
+
+impl From<Cambio> for ChangeAspect {
+    fn from(cambio: Cambio) -> Self {
+        match cambio {
+            Cambio::Estado => ChangeAspect::Status,
+            Cambio::Comerc => ChangeAspect::Commercialization,
+            Cambio::Prosp => ChangeAspect::Leaflet,
+            Cambio::Ft => ChangeAspect::TechnicalSheet,
+            Cambio::Psum => ChangeAspect::SupplyProblems,
+            Cambio::NotasSeguridad => ChangeAspect::SafetyNotes,
+            Cambio::Matinf => ChangeAspect::InformativeMaterials,
+            Cambio::Otros => ChangeAspect::Other,
+        }
+    }
+}
+
+/// Catalog of `nomenclator api maestra`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum MaestraTipo {
+    /// Principios activos (active ingredients)
+    Pa,
+    /// Formas farmacéuticas (pharmaceutical forms)
+    Ff,
+    /// Vías de administración (administration routes)
+    Va,
+    /// Laboratorios (laboratories)
+    Lab,
+    /// Códigos ATC (ATC codes)
+    Atc,
+    /// Principios activos SNOMED
+    PaSnomed,
+    /// Formas farmacéuticas simplificadas SNOMED
+    FfSnomed,
+    /// Vías de administración SNOMED
+    VaSnomed,
+    /// Medicamentos
+    Med,
+    /// Medicamentos comercializados SNOMED
+    MedSnomed,
+}
+
+impl From<MaestraTipo> for MasterDataType {
+    fn from(tipo: MaestraTipo) -> Self {
+        match tipo {
+            MaestraTipo::Pa => MasterDataType::ActiveIngredients,
+            MaestraTipo::Ff => MasterDataType::PharmaceuticalForms,
+            MaestraTipo::Va => MasterDataType::AdministrationRoutes,
+            MaestraTipo::Lab => MasterDataType::Laboratories,
+            MaestraTipo::Atc => MasterDataType::AtcCodes,
+            MaestraTipo::PaSnomed => MasterDataType::ActiveIngredientsSNOMED,
+            MaestraTipo::FfSnomed => MasterDataType::SimplifiedPharmaceuticalFormsSNOMED,
+            MaestraTipo::VaSnomed => MasterDataType::AdministrationRoutesSNOMED,
+            MaestraTipo::Med => MasterDataType::Medications,
+            MaestraTipo::MedSnomed => MasterDataType::CommercializedMedicationsSNOMED,
+        }
+    }
+}
+
+/// Document of `nomenclator api doc`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SegmentedDoc {
+    /// Technical data sheet (ficha técnica)
+    Ft,
+    /// Package leaflet (prospecto)
+    P,
+}
+
+impl SegmentedDoc {
+    fn document_type(self) -> DocumentType {
+        match self {
+            SegmentedDoc::Ft => DocumentType::TechnicalSheet,
+            SegmentedDoc::P => DocumentType::PackageLeaflet,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SegmentedDoc::Ft => "ficha técnica",
+            SegmentedDoc::P => "prospecto",
+        }
+    }
+}
+
+/// Document of `nomenclator api download-docs`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DownloadDoc {
+    /// Technical data sheet (ficha técnica)
+    Ft,
+    /// Package leaflet (prospecto)
+    P,
+    /// Public evaluation report (informe público de evaluación)
+    Ipe,
+}
+
+impl From<DownloadDoc> for DocumentType {
+    fn from(doc: DownloadDoc) -> Self {
+        match doc {
+            DownloadDoc::Ft => DocumentType::TechnicalSheet,
+            DownloadDoc::P => DocumentType::PackageLeaflet,
+            DownloadDoc::Ipe => DocumentType::PublicReport,
+        }
+    }
+}
+
+/// Field of `nomenclator api medicamento --fields`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum MedicamentoField {
+    /// Registration number
+    Nregistro,
+    /// Medication name
+    Nombre,
+    /// Active ingredients
+    Pactivos,
+    /// Holder laboratory
+    Labtitular,
+    /// Prescription conditions
+    Cpresc,
+    /// Whether any presentation is commercialized
+    Comercializado,
+    /// Whether it needs a prescription
+    Receta,
+    /// Whether it has supply problems
+    Psum,
+    /// URL of the technical data sheet
+    #[value(name = "url_ft")]
+    UrlFt,
+    /// URL of the package leaflet
+    #[value(name = "url_prospecto")]
+    UrlProspecto,
+    /// ATC codes
+    Atcs,
+}
+
+impl MedicamentoField {
+    fn name(self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    fn value(self, med: &Medication) -> serde_json::Value {
+        let document_url = |doc_type: DocumentType| {
+            med.docs
+                .iter()
+                .find(|doc| doc.doc_type == doc_type as u8)
+                .map(|doc| doc.url.clone())
+        };
+        match self {
+            MedicamentoField::Nregistro => med.nregistro.clone().into(),
+            MedicamentoField::Nombre => med.name.clone().into(),
+            MedicamentoField::Pactivos => med.pactivos.clone().into(),
+            MedicamentoField::Labtitular => med.labtitular.clone().into(),
+            MedicamentoField::Cpresc => med.cpresc.clone().into(),
+            MedicamentoField::Comercializado => med.commercialized.into(),
+            MedicamentoField::Receta => med.prescription_required.into(),
+            MedicamentoField::Psum => med.psum.into(),
+            MedicamentoField::UrlFt => document_url(DocumentType::TechnicalSheet).into(),
+            MedicamentoField::UrlProspecto => document_url(DocumentType::PackageLeaflet).into(),
+            MedicamentoField::Atcs => med
+                .atcs
+                .iter()
+                .map(|atc| atc.code.clone())
+                .collect::<Vec<_>>()
+                .into(),
+        }
+    }
+}
+
+/// Photo type of `nomenclator api fotos`
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum FotoTipo {
+    /// Packaging material (materialas)
+    Envase,
+    /// Pharmaceutical form (formafarmac)
+    Forma,
+}
+
+impl From<FotoTipo> for PhotoType {
+    fn from(tipo: FotoTipo) -> Self {
+        match tipo {
+            FotoTipo::Envase => PhotoType::Packaging,
+            FotoTipo::Forma => PhotoType::PharmaceuticalForm,
+        }
+    }
+}
+
+/// Exit code of invalid arguments, also used by clap for its own errors
+const EXIT_USAGE: u8 = 2;
+/// Exit code of a failed request to the API or download
+const EXIT_API: u8 = 3;
+/// Exit code when the requested medication, document or section does not exist
+const EXIT_NOT_FOUND: u8 = 4;
+/// Exit code of a batch operation where some of the items failed
+const EXIT_PARTIAL: u8 = 5;
+/// Exit code of a failure parsing or converting the nomenclator files, or of
+/// dangling references found by `csv --validate`
+const EXIT_PIPELINE: u8 = 6;
+
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  Success
+  1  Any other error
+  2  Invalid arguments
+  3  API or network error
+  4  Not found
+  5  Some items of a batch operation failed
+  6  Parse or conversion failure, or dangling references found by csv --validate";
+
+/// Error that ends the program with `code`
+#[derive(Debug)]
+struct ExitError {
+    code: u8,
+    message: String,
+}
+
+impl std::fmt::Display for ExitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ExitError {}
+
+fn exit_error(code: u8, message: impl Into<String>) -> anyhow::Error {
+    ExitError {
+        code,
+        message: message.into(),
+    }
+    .into()
+}
+
+/// Exit code of `error`: the code of an [`ExitError`] or of an API error in its chain,
+/// otherwise `default`
+fn exit_code(error: &anyhow::Error, default: u8) -> u8 {
+    for cause in error.chain() {
+        if let Some(error) = cause.downcast_ref::<ExitError>() {
+            return error.code;
+        }
+        let status = if let Some(error) = cause.downcast_ref::<ApiStatusError>() {
+            Some(error.status)
+        } else if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            error.status()
+        } else {
+            continue;
+        };
+        return match status {
+            Some(reqwest::StatusCode::NOT_FOUND) => EXIT_NOT_FOUND,
+            _ => EXIT_API,
+        };
+    }
+    default
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    // The configuration file only gives defaults, so parse twice: first to find it, then
+    // with its values as the defaults of the arguments
+    let config_path = Args::from_arg_matches(&Args::command().get_matches())
+        .unwrap_or_else(|e| e.exit())
+        .config;
+    let loaded = config::load(config_path.as_deref()).and_then(|config| {
+        let (command, warnings) = match &config {
+            Some(config) => config.apply(Args::command())?,
+            None => (Args::command(), Vec::new()),
+        };
+        Ok((config, command, warnings))
+    });
+    let (config, command, config_warnings) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let matches = command.get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // Initialize tracing subscriber, keeping standard output for the JSON responses
+    let subscriber = tracing_subscriber::fmt().with_env_filter(
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    );
+    if matches!(
+        args.command,
+        Commands::Api {
+            format: ApiFormat::Json,
+            ..
+        } | Commands::Diff {
+            format: DiffFormat::Json | DiffFormat::Csv,
+            ..
+        } | Commands::Stats {
+            format: StatsFormat::Json,
+            ..
+        }
+    ) {
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
+    if let Some(config) = &config {
+        tracing::debug!(path = ?config.path, "Using configuration file");
+    }
+    for warning in config_warnings {
+        tracing::warn!("{}", warning);
+    }
+
+    // Errors without a more specific code take the one of the kind of command
+    let default_code = match args.command {
+        Commands::Api { .. } | Commands::Download { .. } => EXIT_API,
+        Commands::Csv { .. }
+        | Commands::Diff { .. }
+        | Commands::Stats { .. }
+        | Commands::Db { .. }
+        | Commands::Codegen { .. } => EXIT_PIPELINE,
+        Commands::Completions { .. } => 1,
+    };
+    match run(args, &matches).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit_code(&e, default_code))
+        }
+    }
+}
+
+/// Runs the command of `args`
+async fn run(args: Args, matches: &ArgMatches) -> anyhow::Result<()> {
+    match args.command {
+        Commands::Csv {
+            output_dir,
+            work_dir,
+            concurrency,
+            format,
+            compress,
+            only,
+            exclude,
+            validate,
+            incremental,
+            skip_errors,
+            errors_json,
+            report_json,
+            enrich_nregistro,
+            force_download,
+            skip_download,
+            keep_zip,
+            download_url,
+            mirrors,
+            zip_cache,
+            no_progress,
+            client,
+        } => {
+            if concurrency == Some(0) {
+                return Err(exit_error(
+                    EXIT_USAGE,
+                    "--concurrency must be greater than 0",
+                ));
+            }
+            let progress = (!no_progress).then(MultiProgress::new);
+            let download = if skip_download {
+                None
+            } else {
+                Some(DownloadOptions {
+                    force: force_download,
+                    keep_zip,
+                    progress: progress.as_ref().map(download_progress_bar).transpose()?,
+                    url: download_url.unwrap_or_else(|| NOMENCLATOR_DUMP_URL.to_string()),
+                    mirrors,
+                    zip_cache_dir: zip_cache,
+                    client: Some(download_client()?),
+                    ..Default::default()
+                })
+            };
+            let files = selected_files(only, &exclude)?;
+            let csv_only = [
+                ("--validate", validate),
+                ("--incremental", incremental),
+                ("--enrich-nregistro", enrich_nregistro),
+            ];
+            if format != OutputKind::Csv
+                && let Some((flag, _)) = csv_only.iter().find(|(_, set)| *set)
+            {
+                return Err(exit_error(
+                    EXIT_USAGE,
+                    format!("{} needs --output-format csv", flag),
+                ));
+            }
+            if compress && matches!(format, OutputKind::Parquet | OutputKind::Sqlite) {
+                return Err(exit_error(
+                    EXIT_USAGE,
+                    "--compress only applies to --output-format csv and ndjson",
+                ));
+            }
+            match format {
+                OutputKind::Csv | OutputKind::Ndjson => {
+                    process_csv(
+                        output_dir,
+                        work_dir,
+                        concurrency,
+                        files,
+                        if format == OutputKind::Ndjson {
+                            OutputFormat::NdJson
+                        } else {
+                            OutputFormat::Csv
+                        },
+                        compress,
+                        validate,
+                        incremental,
+                        skip_errors,
+                        errors_json,
+                        report_json,
+                        enrich_nregistro.then(|| client.client()).transpose()?,
+                        download,
+                        progress,
+                    )
+                    .await
+                }
+                #[cfg(feature = "parquet")]
+                OutputKind::Parquet => process_parquet(output_dir, work_dir, files, download).await,
+                #[cfg(feature = "sqlite")]
+                OutputKind::Sqlite => {
+                    if files != NomenclatorFile::all() {
+                        return Err(exit_error(
+                            EXIT_USAGE,
+                            "--only and --exclude are not supported with --output-format sqlite",
+                        ));
+                    }
+                    process_sqlite(output_dir, work_dir, download).await
+                }
+                #[cfg(not(all(feature = "parquet", feature = "sqlite")))]
+                format => Err(missing_feature(format)),
+            }
+        }
+        Commands::Download {
+            source_url,
+            dir,
+            force,
+            keep_zip,
+            mirrors,
+            zip_cache,
+        } => {
+            let options = DownloadOptions {
+                force,
+                keep_zip,
+                progress: Some(download_progress_bar(&MultiProgress::new())?),
+                mirrors,
+                zip_cache_dir: zip_cache,
+                client: Some(download_client()?),
+                ..Default::default()
+            };
+            let report = download_and_extract(&source_url, &dir, &options).await?;
+            print_extraction_report(&report);
+            Ok(())
+        }
+        Commands::Diff {
+            old_dir,
+            new_dir,
+            table,
+            format,
+        } => process_diff(&old_dir, &new_dir, &table, format),
+        Commands::Stats {
+            output_dir,
+            previous,
+            format,
+        } => process_stats(&output_dir, previous.as_deref(), format),
+        Commands::Db {
+            input,
+            db,
+            recreate,
+        } => {
+            #[cfg(feature = "sqlite")]
+            {
+                process_db(&input, &db, recreate)
+            }
+            #[cfg(not(feature = "sqlite"))]
+            {
+                let _ = (input, db, recreate);
+                Err(exit_error(
+                    EXIT_USAGE,
+                    "db needs the 'sqlite' cargo feature, which this binary was built without; \
+                     reinstall it with `cargo install cima-rs --features sqlite`",
+                ))
+            }
+        }
+        Commands::Codegen { dir, output } => process_codegen(&dir, output.as_deref()),
+        Commands::Completions { shell } => {
+            let bin_name = env!("CARGO_BIN_NAME");
+            clap_complete::generate(
+                shell,
+                &mut Args::command(),
+                bin_name,
+                &mut std::io::stdout(),
+            );
+            Ok(())
+        }
+        Commands::Api {
+            format,
+            client,
+            api_command,
+        } => {
+            let api_matches = matches
+                .subcommand_matches("api")
+                .context("Missing api arguments")?;
+            process_api(api_command, api_matches, format, client.client()?).await
+        }
+    }
+}
+
+/// Names of the tables compared by `diff`
+fn table_names() -> Vec<&'static str> {
+    DICTIONARY_TABLES
+        .iter()
+        .chain(PRESCRIPTION_TABLES.iter())
+        .map(Table::name)
+        .collect()
+}
+
+fn process_diff(
+    old_dir: &Path,
+    new_dir: &Path,
+    tables: &[String],
+    format: DiffFormat,
+) -> anyhow::Result<()> {
+    for dir in [old_dir, new_dir] {
+        if !dir.is_dir() {
+            return Err(exit_error(
+                EXIT_USAGE,
+                format!("{} is not a directory", dir.display()),
+            ));
+        }
+    }
+    let mut options = DiffOptions::default();
+    if !tables.is_empty() {
+        options
+            .tables
+            .retain(|table| tables.iter().any(|name| name == table.name()));
+    }
+    tracing::info!(old_dir = ?old_dir, new_dir = ?new_dir, "Comparing output directories");
+
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let diffs = match format {
+        DiffFormat::Text => {
+            let diffs = diff_csv_dirs_with_options(old_dir, new_dir, &options, |change| {
+                let mark = match change.kind {
+                    RowChangeKind::Added => '+',
+                    RowChangeKind::Removed => '-',
+                    RowChangeKind::Changed => '~',
+                };
+                write!(out, "{} {} {}", mark, change.table, change.key.join("/"))?;
+                if !change.columns.is_empty() {
+                    write!(out, ": {}", change.columns.join(", "))?;
+                }
+                writeln!(out)?;
+                Ok(())
+            })?;
+            writeln!(out, "{}", render_diff_summary(&diffs))?;
+            diffs
+        }
+        DiffFormat::Json => {
+            // Written as they come so that the changes are not held in memory
+            write!(out, "{{\"changes\":[")?;
+            let mut first = true;
+            let diffs = diff_csv_dirs_with_options(old_dir, new_dir, &options, |change| {
+                if !std::mem::take(&mut first) {
+                    write!(out, ",")?;
+                }
+                serde_json::to_writer(&mut out, &change)?;
+                Ok(())
+            })?;
+            write!(out, "],\"tables\":")?;
+            serde_json::to_writer(&mut out, &diffs)?;
+            writeln!(out, "}}")?;
+            diffs
+        }
+        DiffFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut out);
+            writer.write_record(["table", "change", "key", "columns"])?;
+            let diffs = diff_csv_dirs_with_options(old_dir, new_dir, &options, |change| {
+                let kind = match change.kind {
+                    RowChangeKind::Added => "added",
+                    RowChangeKind::Removed => "removed",
+                    RowChangeKind::Changed => "changed",
+                };
+                writer.write_record([
+                    change.table,
+                    kind,
+                    &change.key.join("/"),
+                    &change.columns.join(";"),
+                ])?;
+                Ok(())
+            })?;
+            writer.flush()?;
+            diffs
+        }
+    };
+    out.flush()?;
+    if format != DiffFormat::Text {
+        for line in render_diff_summary(&diffs).lines() {
+            tracing::info!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+fn process_stats(
+    output_dir: &Path,
+    previous: Option<&Path>,
+    format: StatsFormat,
+) -> anyhow::Result<()> {
+    if !output_dir.is_dir() {
+        return Err(exit_error(
+            EXIT_USAGE,
+            format!("{} is not a directory", output_dir.display()),
+        ));
+    }
+    let default_report = output_dir.join(REPORT_JSON);
+    let previous = match previous {
+        Some(path) => Some(path),
+        None => Some(default_report.as_path()).filter(|path| path.is_file()),
+    };
+    let options = StatsOptions {
+        previous: previous.map(previous_row_counts).transpose()?,
+        ..Default::default()
+    };
+    let stats = csv_dir_stats_with_options(output_dir, &options)?;
+    match format {
+        StatsFormat::Json => print_json(&stats),
+        StatsFormat::Text => {
+            print!("{}", render_stats(&stats));
+            Ok(())
+        }
+    }
+}
+
+/// Files, prescription counts and anomalies of `stats` as a report
+fn render_stats(stats: &CsvDirStats) -> String {
+    let mut out = String::new();
+    let width = stats
+        .files
+        .iter()
+        .map(|file| file.file.len())
+        .max()
+        .unwrap_or_default();
+    out.push_str("Files:\n");
+    if stats.files.is_empty() {
+        out.push_str("  No CSV files\n");
+    }
+    for file in &stats.files {
+        out.push_str(&format!(
+            "  {:<width$} {:>9} rows {:>11}\n",
+            file.file,
+            file.rows,
+            HumanBytes(file.bytes).to_string()
+        ));
+    }
+    if let Some(prescriptions) = &stats.prescriptions {
+        out.push_str(&format!(
+            "\nPrescriptions: {}\n  Commercialized:     {}\n  Not commercialized: {}\n  \
+             Psychotropic:       {}\n",
+            prescriptions.total,
+            prescriptions.commercialized,
+            prescriptions.not_commercialized,
+            prescriptions.psychotropic
+        ));
+        if !prescriptions.top_laboratories.is_empty() {
+            out.push_str("\nTop laboratories:\n");
+        }
+        for (i, laboratory) in prescriptions.top_laboratories.iter().enumerate() {
+            let name = match &laboratory.name {
+                Some(name) => format!("{} ({})", name, laboratory.code),
+                None => laboratory.code.clone(),
+            };
+            out.push_str(&format!(
+                "  {:>2}. {}: {}\n",
+                i + 1,
+                name,
+                laboratory.prescriptions
+            ));
+        }
+    }
+    if !stats.anomalies.is_empty() {
+        out.push_str("\nAnomalies:\n");
+    }
+    for anomaly in &stats.anomalies {
+        out.push_str(&format!("  ⚠ {}\n", anomaly));
+    }
+    out
+}
+
+/// Counts of added, removed and changed rows of every compared table
+fn render_diff_summary(diffs: &[TableDiff]) -> String {
+    if diffs.is_empty() {
+        return "No tables to compare".to_string();
+    }
+    diffs
+        .iter()
+        .map(|diff| {
+            format!(
+                "{}: {} added, {} removed, {} changed, {} unchanged",
+                diff.table, diff.added, diff.removed, diff.changed, diff.unchanged
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn process_codegen(dir: &Path, output: Option<&Path>) -> anyhow::Result<()> {
+    let source = generate_dictionary_enums(dir)?;
+    match output {
+        Some(path) => {
+            fs::write(path, source)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("✓ Dictionary enums written to {}", path.display());
+        }
+        None => print!("{source}"),
+    }
+    Ok(())
+}
+
+/// Parser of `--only` and `--exclude`, offering the short names as possible values
+#[derive(Clone)]
+struct NomenclatorFileParser;
+
+impl TypedValueParser for NomenclatorFileParser {
+    type Value = NomenclatorFile;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<NomenclatorFile, clap::Error> {
+        let value = StringValueParser::new().parse_ref(cmd, arg, value)?;
+        value.parse().map_err(|e: anyhow::Error| {
+            clap::Error::raw(ErrorKind::ValueValidation, format!("{}\n", e)).with_cmd(cmd)
+        })
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(
+            NomenclatorFile::all()
+                .into_iter()
+                .map(|file| PossibleValue::new(file.short_name())),
+        ))
+    }
+}
+
+/// Files of `--only`, every one when empty, minus those of `--exclude`.
+fn selected_files(
+    only: Vec<NomenclatorFile>,
+    exclude: &[NomenclatorFile],
+) -> anyhow::Result<Vec<NomenclatorFile>> {
+    let only = if only.is_empty() {
+        NomenclatorFile::all()
+    } else {
+        only
+    };
+    let files: Vec<_> = NomenclatorFile::all()
+        .into_iter()
+        .filter(|file| only.contains(file) && !exclude.contains(file))
+        .collect();
+    if files.is_empty() {
+        return Err(exit_error(
+            EXIT_USAGE,
+            "--only and --exclude leave no nomenclator file to convert",
+        ));
+    }
+    Ok(files)
+}
+
+/// Error for an output format whose cargo feature the binary was built without.
+#[cfg(not(all(feature = "parquet", feature = "sqlite")))]
+fn missing_feature(format: OutputKind) -> anyhow::Error {
+    let name = format
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default();
+    exit_error(
+        EXIT_USAGE,
+        format!(
+            "--output-format {name} needs the '{name}' cargo feature, which this binary was \
+             built without; reinstall it with `cargo install cima-rs --features {name}`"
+        ),
+    )
+}
+
+/// Parses the selected files to Parquet files in `output_dir`.
+#[cfg(feature = "parquet")]
+async fn process_parquet(
+    output_dir: PathBuf,
+    work_dir: PathBuf,
+    files: Vec<NomenclatorFile>,
+    download: Option<DownloadOptions>,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(&output_dir)?;
+    prepare_work_dir(&work_dir, &files, download.as_ref()).await?;
+
+    tokio::task::spawn_blocking(move || {
+        for file in files {
+            let xml_path = work_dir.join(file.xml_filename());
+            if !xml_path.is_file() {
+                tracing::warn!(file = %file.xml_filename(), "File not found, skipping");
+                continue;
+            }
+            let parquet_files = match file {
+                NomenclatorFile::Dictionary(kind) => {
+                    let name = format!("{}.parquet", kind.short_name());
+                    kind.parse_parquet(&xml_path, &output_dir.join(&name))?;
+                    vec![name]
+                }
+                NomenclatorFile::Prescriptions => {
+                    cima_rs::parser::parse_prescription_xml_to_parquet(&xml_path, &output_dir)?;
+                    cima_rs::parser::PRESCRIPTION_CSV_FILES
+                        .iter()
+                        .map(|csv| csv.replace(".csv", ".parquet"))
+                        .collect()
+                }
+            };
+            for name in parquet_files {
+                println!("✓ Completed: {}", name);
+            }
+        }
+        anyhow::Ok(())
+    })
+    .await?
+}
+
+#[cfg(feature = "sqlite")]
+async fn process_sqlite(
+    output_dir: PathBuf,
+    work_dir: PathBuf,
+    download: Option<DownloadOptions>,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(&output_dir)?;
+    prepare_work_dir(&work_dir, &NomenclatorFile::all(), download.as_ref()).await?;
+
+    let db_path = output_dir.join("nomenclator.sqlite");
+    tracing::info!(db = ?db_path, "Loading nomenclator into SQLite");
+    let target = db_path.clone();
+    tokio::task::spawn_blocking(move || {
+        cima_rs::parser::parse_nomenclator_to_sqlite(work_dir, target)
+    })
+    .await??;
+
+    println!("✓ Completed: {}", db_path.display());
+    Ok(())
+}
+
+/// Loads the CSV files of `input` into a new SQLite database at `db_path`.
+#[cfg(feature = "sqlite")]
+fn process_db(input: &Path, db_path: &Path, recreate: bool) -> anyhow::Result<()> {
+    if !input.is_dir() {
+        return Err(exit_error(
+            EXIT_USAGE,
+            format!("{} is not a directory", input.display()),
+        ));
+    }
+    if db_path.exists() {
+        if !recreate {
+            return Err(exit_error(
+                EXIT_USAGE,
+                format!(
+                    "{} already exists, use --recreate to replace it",
+                    db_path.display()
+                ),
+            ));
+        }
+        fs::remove_file(db_path)
+            .with_context(|| format!("Failed to remove {}", db_path.display()))?;
+    }
+    tracing::info!(input = ?input, db = ?db_path, "Loading CSV files into SQLite");
+    let mut conn = rusqlite::Connection::open(db_path)
+        .with_context(|| format!("Failed to open {}", db_path.display()))?;
+    let counts = cima_rs::parser::load_csv_dir_into_sqlite(input, &mut conn)?;
+    for (table, rows) in counts {
+        println!("✓ {}: {} rows", table, rows);
+    }
+    println!("✓ Completed: {}", db_path.display());
+    Ok(())
+}
+
+/// Client the ZIP files are downloaded with, going through the proxies set in the
+/// `HTTPS_PROXY` and `HTTP_PROXY` environment variables.
+fn download_client() -> anyhow::Result<reqwest::Client> {
+    download_client_builder()
+        .build()
+        .context("Failed to create HTTP client")
+}
+
+/// Downloads the nomenclator into `work_dir` and prints whether it was up to date.
+/// Downloads and extracts the nomenclator into `work_dir`, or with `--skip-download`
+/// (`download` is `None`) checks the XML files already there.
+async fn prepare_work_dir(
+    work_dir: &Path,
+    files: &[NomenclatorFile],
+    download: Option<&DownloadOptions>,
+) -> anyhow::Result<()> {
+    let Some(download) = download else {
+        return check_work_dir(work_dir, files);
+    };
+    fs::create_dir_all(work_dir)?;
+
+    tracing::info!("Downloading and extracting AEMPS Nomenclator data");
+    let report = download_and_extract_nomenclator_with_options(work_dir, download).await?;
+    print_extraction_report(&report);
+    Ok(())
+}
+
+/// Warns about every selected XML file missing from `work_dir`, failing if none is there.
+fn check_work_dir(work_dir: &Path, files: &[NomenclatorFile]) -> anyhow::Result<()> {
+    let mut found = 0;
+    for xml_file in files.iter().map(|file| file.xml_filename()) {
+        if work_dir.join(xml_file).is_file() {
+            found += 1;
+        } else {
+            tracing::warn!(file = %xml_file, "File not found in the work directory");
+        }
+    }
+    if found == 0 {
+        anyhow::bail!(
+            "No nomenclator XML files in {}, run without --skip-download to download them",
+            work_dir.display()
+        );
+    }
+
+    tracing::info!(files = found, work_dir = ?work_dir, "Skipping the download");
+    Ok(())
+}
+
+/// Prints where the archive of `report` came from and what was extracted.
+fn print_extraction_report(report: &ExtractionReport) {
+    match &report.outcome {
+        DownloadOutcome::UpToDate => println!("= Up to date: {}", report.target_dir.display()),
+        DownloadOutcome::Downloaded {
+            url,
+            bytes,
+            last_modified,
+            retries,
+            cached,
+        } => {
+            if *retries > 0 {
+                tracing::warn!(retries, "Download succeeded after retrying");
+            }
+            println!(
+                "✓ {}: {} ({}, last modified {})",
+                if *cached { "Cached" } else { "Downloaded" },
+                url,
+                HumanBytes(*bytes),
+                last_modified.as_deref().unwrap_or("unknown")
+            )
+        }
+    }
+    println!(
+        "  {} files, {} {}",
+        report.files.len(),
+        HumanBytes(report.extracted_bytes()),
+        if report.skipped() {
+            "already extracted"
+        } else {
+            "extracted"
+        }
+    );
+}
 
-            if let Some(huerfano) = med.orphan
-                && huerfano
-            {
-                println!("💊 Medicamento huérfano");
+/// File listing the records skipped with `--skip-errors`
+const PARSE_ERRORS_JSON: &str = "parse_errors.json";
+
+/// File holding the row counts written with `--report-json`
+const REPORT_JSON: &str = "report.json";
+
+/// Entry of parse_errors.json
+#[derive(Serialize)]
+struct FileError<'a> {
+    file: &'a str,
+    #[serde(flatten)]
+    error: &'a RecordError,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process_csv(
+    output_dir: PathBuf,
+    work_dir: PathBuf,
+    concurrency: Option<usize>,
+    files: Vec<NomenclatorFile>,
+    format: OutputFormat,
+    compress: bool,
+    validate: bool,
+    incremental: bool,
+    skip_errors: bool,
+    errors_json: bool,
+    report_json: bool,
+    enrich_client: Option<CimaClient>,
+    download: Option<DownloadOptions>,
+    progress: Option<MultiProgress>,
+) -> anyhow::Result<()> {
+    // Ensure directories exist
+    fs::create_dir_all(&output_dir)?;
+
+    // Determine concurrency level based on CPU cores
+    let num_cores = num_cpus::get();
+    let concurrency = concurrency.unwrap_or(num_cores);
+    if concurrency > num_cores {
+        tracing::warn!(
+            concurrency,
+            num_cores,
+            "Concurrency above the number of CPU cores, parsing may be slower"
+        );
+    }
+
+    tracing::info!(work_dir = ?work_dir, "Target work directory");
+    tracing::info!(output_dir = ?output_dir, "Target output directory");
+    tracing::info!(num_cores, "Available CPU cores");
+    tracing::info!(concurrency, "Concurrency level");
+
+    // 1. Download and extract, unless --skip-download
+    prepare_work_dir(&work_dir, &files, download.as_ref()).await?;
+
+    // 2. Parse the dictionaries and Prescripcion.xml
+    let xml_path = work_dir.join(PRESCRIPTION_XML);
+    let options = NomenclatorOptions {
+        parser: ParserOptions {
+            skip_unchanged: incremental,
+            on_error: if skip_errors {
+                OnError::SkipAndReport
+            } else {
+                OnError::Fail
+            },
+            ..Default::default()
+        },
+        concurrency,
+        prescription_progress: match &progress {
+            Some(multi) if files.contains(&NomenclatorFile::Prescriptions) && xml_path.exists() => {
+                Some(prescription_progress_bar(multi, &xml_path)?)
+            }
+            _ => None,
+        },
+        dictionary_progress: progress
+            .as_ref()
+            .map(dictionary_progress_bars)
+            .transpose()?,
+        files,
+        format,
+    };
+    let mut report = parse_all_nomenclator(&work_dir, &output_dir, options).await?;
+    if let Some(multi) = &progress {
+        multi.clear()?;
+    }
+    if compress {
+        compress_outputs(&output_dir, &mut report)?;
+    }
+    for file in &report.files {
+        match &file.status {
+            FileStatus::Parsed(parsed) if parsed.unchanged => {
+                println!("= Unchanged: {}", file.xml_file)
+            }
+            FileStatus::Parsed(_) => {
+                for output_file in &file.output_files {
+                    println!("✓ Completed: {}", output_file);
+                }
+            }
+            FileStatus::Failed(e) if file.xml_file == PRESCRIPTION_XML => {
+                // Print full error chain for debugging
+                eprintln!("Prescription parse error: {:#}", e);
+            }
+            FileStatus::Excluded => println!("- Excluded: {}", file.xml_file),
+            FileStatus::Failed(_) | FileStatus::Skipped => {}
+        }
+    }
+
+    let stats = report.stats();
+    print_stats(&stats);
+    if report_json {
+        let path = output_dir.join(REPORT_JSON);
+        let file = fs::File::create(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        serde_json::to_writer_pretty(file, &stats)?;
+        println!("✓ Completed: {}", REPORT_JSON);
+    }
+
+    // 3. Generate PostgreSQL scripts for the CSV files
+    if format == OutputFormat::Csv && !compress {
+        generate_postgres_schema(&output_dir)?;
+        println!("✓ Completed: schema.sql, import.sql");
+    }
+
+    // 4. Optionally resolve the registration numbers of the prescriptions
+    let prescriptions_parsed = report
+        .file(PRESCRIPTION_XML)
+        .is_some_and(|outcome| outcome.report().is_some());
+    let enrichment = if let Some(client) = enrich_client
+        && prescriptions_parsed
+    {
+        let enrichment =
+            enrich_prescriptions_with_nregistro(&output_dir, &client, concurrency).await?;
+        println!("✓ Completed: {}", PRESCRIPTION_NREGISTRO_CSV);
+        Some(enrichment)
+    } else {
+        None
+    };
+
+    // 5. Optionally check the references between the generated files
+    let validation = if validate {
+        Some(validate_nomenclator_output(&output_dir)?)
+    } else {
+        None
+    };
+
+    // 6. Collect the records skipped as malformed
+    let parse_errors: Vec<FileError> = report
+        .files
+        .iter()
+        .filter_map(|outcome| Some((outcome.xml_file, outcome.report()?)))
+        .flat_map(|(file, parsed)| {
+            parsed
+                .errors
+                .iter()
+                .map(move |error| FileError { file, error })
+        })
+        .collect();
+    if errors_json {
+        let path = output_dir.join(PARSE_ERRORS_JSON);
+        let file = fs::File::create(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        serde_json::to_writer_pretty(file, &parse_errors)?;
+        println!("✓ Completed: {}", PARSE_ERRORS_JSON);
+    }
+
+    // 7. Report results
+    let is_dictionary = |outcome: &&FileOutcome| outcome.xml_file != PRESCRIPTION_XML;
+    let successful = report.parsed().filter(is_dictionary).count();
+    let failed = report.failed().filter(is_dictionary).count();
+    let unchanged = report
+        .parsed()
+        .filter(|outcome| outcome.report().is_some_and(|parsed| parsed.unchanged))
+        .count();
+    let prescription_success = !matches!(
+        report.file(PRESCRIPTION_XML).map(|outcome| &outcome.status),
+        Some(FileStatus::Failed(_))
+    );
+
+    tracing::info!(
+        successful,
+        failed,
+        prescription_success,
+        "CSV parsing completed"
+    );
+
+    println!("\n━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+    println!("Summary:");
+    print!("{}", render_summary(&report));
+    if incremental {
+        println!("  = Unchanged XML files skipped: {}", unchanged);
+    }
+    if skip_errors {
+        println!("  ✗ Malformed records skipped: {}", parse_errors.len());
+    }
+    if let Some(enrichment) = &enrichment {
+        println!(
+            "  ✓ Registration numbers: {} resolved, {} unresolved",
+            enrichment.resolved,
+            enrichment.unresolved.len()
+        );
+    }
+    if let Some(report) = &validation {
+        if report.is_valid() {
+            println!("  ✓ Validation: all references resolved");
+        } else {
+            println!("  ✗ Validation: {} dangling references", report.missing());
+        }
+    }
+    println!("  📁 Output directory: {:?}", output_dir);
+    println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+    if let Some(report) = &validation {
+        println!("\nValidation:\n{}", report);
+    }
+
+    if failed > 0 || !prescription_success {
+        anyhow::bail!("Some files failed to parse");
+    }
+    if let Some(report) = validation.filter(|report| !report.is_valid()) {
+        return Err(exit_error(
+            EXIT_PIPELINE,
+            format!("Validation found {} dangling references", report.missing()),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Replaces the files written for `report` in `output_dir` with gzip copies named
+/// after them with a `.gz` extension, renaming them in the report.
+fn compress_outputs(output_dir: &Path, report: &mut NomenclatorReport) -> anyhow::Result<()> {
+    for outcome in &mut report.files {
+        if outcome.report().is_none() {
+            continue;
+        }
+        for name in &mut outcome.output_files {
+            let path = output_dir.join(&*name);
+            let compressed = format!("{}.gz", name);
+            let compressed_path = output_dir.join(&compressed);
+            let mut input = fs::File::open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            let output = fs::File::create(&compressed_path)
+                .with_context(|| format!("Failed to create {}", compressed_path.display()))?;
+            let mut encoder = GzEncoder::new(output, Compression::default());
+            std::io::copy(&mut input, &mut encoder)
+                .and_then(|_| encoder.finish())
+                .with_context(|| format!("Failed to compress {}", path.display()))?;
+            fs::remove_file(&path)?;
+            *name = compressed;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the row counts of each output file, under the XML file it comes from.
+fn print_stats(stats: &BTreeMap<&str, ParseStats>) {
+    if stats.is_empty() {
+        return;
+    }
+    println!(
+        "\n{:<48} {:>10} {:>10} {:>10} {:>7} {:>9}",
+        "File", "Read", "Written", "Duplicates", "Errors", "Time"
+    );
+    for (xml_file, file) in stats {
+        println!(
+            "{:<48} {:>10} {:>10} {:>10} {:>7} {:>8.1}s",
+            xml_file,
+            file.rows_read,
+            file.total_rows_written(),
+            file.duplicates,
+            file.errors,
+            file.elapsed.as_secs_f64()
+        );
+        if file.rows_written.len() > 1 {
+            for (csv_file, rows) in &file.rows_written {
+                println!("  {:<46} {:>10} {:>10}", csv_file, "", rows);
+            }
+        }
+    }
+    println!();
+}
+
+/// Progress callback drawing a bar over the size of `xml_path` in `multi`.
+fn prescription_progress_bar(
+    multi: &MultiProgress,
+    xml_path: &Path,
+) -> anyhow::Result<ProgressCallback> {
+    let bar = multi.add(ProgressBar::new(fs::metadata(xml_path)?.len()));
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{spinner} [{elapsed_precise}] [{wide_bar}] {percent}% {msg}",
+        )?
+        .progress_chars("=> "),
+    );
+    Ok(Arc::new(move |progress| {
+        // Positions are counted in the decoded input, which may be longer than the file
+        if let Some(bytes_read) = progress.bytes_read {
+            bar.set_position(bytes_read.min(bar.length().unwrap_or(bytes_read)));
+        }
+        bar.set_message(format!("{} prescriptions", progress.records));
+        if progress.done {
+            bar.finish_and_clear();
+        }
+    }))
+}
+
+/// Progress callback drawing a spinner in `multi` for each dictionary being parsed.
+fn dictionary_progress_bars(multi: &MultiProgress) -> anyhow::Result<FileProgressCallback> {
+    let style = ProgressStyle::with_template("{spinner} [{elapsed_precise}] {prefix:<36} {msg}")?;
+    let multi = multi.clone();
+    let bars = Mutex::new(HashMap::new());
+    Ok(Arc::new(move |xml_file, progress| {
+        let mut bars = bars.lock().unwrap_or_else(|e| e.into_inner());
+        let bar = bars.entry(xml_file).or_insert_with(|| {
+            let bar = multi.add(
+                ProgressBar::new_spinner()
+                    .with_style(style.clone())
+                    .with_prefix(xml_file),
+            );
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bar
+        });
+        // The parser and parse_all_nomenclator both send a final update
+        if bar.is_finished() {
+            return;
+        }
+        bar.set_message(format!("{} records", progress.records));
+        if progress.done {
+            bar.finish_and_clear();
+        }
+    }))
+}
+
+/// Progress bar in `multi` following the download, then the extraction of the
+/// nomenclator archive.
+fn download_progress_bar(multi: &MultiProgress) -> anyhow::Result<DownloadProgressCallback> {
+    let bar = multi.add(ProgressBar::no_length());
+    let downloading = ProgressStyle::with_template(
+        "{spinner} [{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} {bytes_per_sec}",
+    )?
+    .progress_chars("=> ");
+    let extracting = ProgressStyle::with_template(
+        "{spinner} [{elapsed_precise}] [{wide_bar}] {pos}/{len} {msg}",
+    )?
+    .progress_chars("=> ");
+    bar.set_style(downloading);
+    Ok(Arc::new(move |progress| match progress {
+        DownloadProgress::Downloading { bytes, total } => {
+            if let Some(total) = total {
+                bar.set_length(total);
+            }
+            bar.set_position(bytes);
+        }
+        DownloadProgress::Extracting { files, total, name } => {
+            if files == 1 {
+                bar.set_style(extracting.clone());
+                bar.set_length(total as u64);
+            }
+            bar.set_position(files as u64);
+            bar.set_message(name);
+            if files == total {
+                bar.finish_and_clear();
+            }
+        }
+    }))
+}
+
+/// Prints a status message, on standard error when standard output holds JSON.
+fn print_status(json: bool, message: &str) {
+    if json {
+        eprintln!("{message}");
+    } else {
+        println!("{message}");
+    }
+}
+
+/// Writes `output` to the file `out`, or prints it without one.
+fn write_output(out: Option<&Path>, json: bool, output: &str) -> anyhow::Result<()> {
+    match out {
+        Some(path) => {
+            fs::write(path, output)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            print_status(json, &format!("✓ Written to {}", path.display()));
+        }
+        None => print!("{output}"),
+    }
+    Ok(())
+}
+
+/// Prints `value` as pretty JSON on standard output.
+fn print_json<T: Serialize>(value: &T) -> anyhow::Result<()> {
+    let stdout = std::io::stdout().lock();
+    serde_json::to_writer_pretty(stdout, value).context("Failed to write JSON")?;
+    println!();
+    Ok(())
+}
+
+async fn process_api(
+    api_command: ApiCommands,
+    api_matches: &ArgMatches,
+    format: ApiFormat,
+    client: CimaClient,
+) -> anyhow::Result<()> {
+    let json = format == ApiFormat::Json;
+
+    match api_command {
+        ApiCommands::Medicamento {
+            nregistro,
+            cn,
+            concurrency,
+            presentaciones,
+            activos,
+            fields,
+            out,
+        } => {
+            let ids: Vec<MedicationId> = nregistro
+                .into_iter()
+                .map(MedicationId::RegistrationNumber)
+                .chain(cn.into_iter().map(MedicationId::NationalCode))
+                .collect();
+            let render = |med: &Medication| {
+                if fields.is_empty() {
+                    render_medication_details(med, presentaciones, activos)
+                } else {
+                    render_medication_fields(med, &fields)
+                }
+            };
+            let mut results = client.get_medications(&ids, concurrency).await;
+            if ids.len() == 1 {
+                let med = results.remove(0)?;
+                let output = if json {
+                    format!(
+                        "{}\n",
+                        serde_json::to_string_pretty(&medication_json(&med, &fields)?)?
+                    )
+                } else {
+                    render(&med)
+                };
+                return write_output(out.as_deref(), json, &output);
+            }
+
+            let mut output = String::new();
+            let mut medications = Vec::new();
+            let mut failed = Vec::new();
+            for (id, result) in ids.iter().zip(results) {
+                match result {
+                    Ok(med) => {
+                        if json {
+                            medications.push(medication_json(&med, &fields)?);
+                        } else {
+                            output.push_str(&format!("\n━━━━━━━━━━ {} ━━━━━━━━━━\n", id));
+                            output.push_str(&render(&med));
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("✗ {}: {:#}", id, e);
+                        failed.push(id.to_string());
+                    }
+                }
+            }
+            if json {
+                output = format!("{}\n", serde_json::to_string_pretty(&medications)?);
+            }
+            write_output(out.as_deref(), json, &output)?;
+            if !failed.is_empty() {
+                return Err(exit_error(
+                    EXIT_PARTIAL,
+                    format!(
+                        "{} of {} medications could not be fetched: {}",
+                        failed.len(),
+                        ids.len(),
+                        failed.join(", ")
+                    ),
+                ));
+            }
+        }
+        ApiCommands::SearchMedicamentos {
+            nombre,
+            laboratorio,
+            principio_activo,
+            atc,
+            comercializados,
+            huerfanos,
+            triangulo,
+            pages,
+            export,
+            limit,
+        } => {
+            let params = SearchMedicationsParams {
+                name: nombre,
+                laboratory: laboratorio,
+                active_ingredient_1: principio_activo,
+                atc,
+                commercialized: if comercializados { Some(1) } else { None },
+                orphan: if huerfanos { Some(1) } else { None },
+                black_triangle: if triangulo { Some(1) } else { None },
+                page: pages.page,
+                ..Default::default()
+            };
+
+            let results = if pages.all {
+                client.search_medications_pages(&params).left_stream()
+            } else {
+                stream::once(client.search_medications(&params)).right_stream()
+            };
+            print_pages(
+                results,
+                "medications",
+                &pages,
+                pages.limit(limit, 10),
+                &export,
+                json,
+                print_medication,
+            )
+            .await?;
+        }
+        ApiCommands::BuscarFt {
+            seccion,
+            texto,
+            limit,
+            ..
+        } => {
+            let buscar_ft = api_matches
+                .subcommand_matches("buscar-ft")
+                .context("Missing buscar-ft arguments")?;
+            let queries = technical_sheet_queries(buscar_ft, seccion, texto)?;
+
+            let mut results = client.search_in_technical_sheet(&queries).await?;
+
+            tracing::info!("Found {} medications", results.len());
+
+            if json {
+                results.truncate(limit);
+                return print_json(&results);
+            }
+
+            for (i, med) in results.iter().take(limit).enumerate() {
+                print_medication(i + 1, med);
+            }
+
+            if results.len() > limit {
+                tracing::info!("Showing {} of {} results", limit, results.len());
+            }
+        }
+        ApiCommands::Presentacion { cn } => {
+            let pres = client.get_presentation(&cn).await?;
+            if json {
+                return print_json(&pres);
+            }
+
+            println!("=== Presentación ===");
+            println!("Código Nacional: {}", pres.cn);
+            println!("Nombre: {}", pres.name);
+            println!(
+                "Comercializada: {}",
+                if pres.commercialized { "Sí" } else { "No" }
+            );
+        }
+        ApiCommands::SearchPresentaciones {
+            cn,
+            nregistro,
+            nombre,
+            atc,
+            vmp,
+            vmpp,
+            principio_activo_id,
+            comercializados,
+            estupefacientes,
+            psicotropos,
+            pages,
+            export,
+            limit,
+        } => {
+            let params = SearchPresentationsParams {
+                national_code: cn,
+                registration_number: nregistro,
+                name: nombre,
+                atc,
+                vmp,
+                vmpp,
+                active_ingredient_id: principio_activo_id,
+                commercialized: comercializados.then_some(1),
+                narcotic: estupefacientes.then_some(1),
+                psychotropic: psicotropos.then_some(1),
+                page: pages.page,
+                ..Default::default()
+            };
+            params
+                .validate()
+                .map_err(|e| exit_error(EXIT_USAGE, e.to_string()))?;
+
+            let results = if pages.all {
+                client.search_presentations_pages(&params).left_stream()
+            } else {
+                stream::once(client.search_presentations(&params)).right_stream()
+            };
+            print_pages(
+                results,
+                "presentations",
+                &pages,
+                pages.limit(limit, 10),
+                &export,
+                json,
+                |n, p| {
+                    println!("{}. CN: {} - {}", n, p.cn, p.name);
+                    if p.commercialized {
+                        println!("   ✓ Comercializada");
+                    }
+                    println!();
+                },
+            )
+            .await?;
+        }
+        ApiCommands::SupplyProblems {
+            cn,
+            activos,
+            sort,
+            pages,
+            export,
+            limit,
+        } => {
+            let results = if pages.all {
+                client
+                    .get_supply_problems_pages(cn.as_deref())
+                    .left_stream()
+            } else {
+                stream::once(client.get_supply_problems_page(cn.as_deref(), pages.page))
+                    .right_stream()
+            };
+            let results = results.map_ok(move |mut page| {
+                if activos {
+                    page.results.retain(|prob| prob.active);
+                }
+                page
+            });
+            let results = match sort {
+                None => results.left_stream(),
+                Some(sort) => {
+                    let merged = results
+                        .try_fold(None, |merged: Option<PaginatedResponse<_>>, page| async {
+                            Ok(Some(match merged {
+                                Some(mut merged) => {
+                                    merged.results.extend(page.results);
+                                    merged
+                                }
+                                None => page,
+                            }))
+                        })
+                        .await?
+                        .map(|mut merged| {
+                            sort.sort(&mut merged.results);
+                            Ok(merged)
+                        });
+                    stream::iter(merged).right_stream()
+                }
+            };
+            print_pages(
+                results,
+                "supply problems",
+                &pages,
+                pages.limit(limit, usize::MAX),
+                &export,
+                json,
+                |n, prob| {
+                    println!("{}. CN: {} - {}", n, prob.cn, prob.name);
+                    println!("   Activo: {}", if prob.active { "Sí" } else { "No" });
+                    if let Some(inicio) = prob.start_date() {
+                        println!("   Inicio: {}", inicio.format("%d/%m/%Y"));
+                    }
+                    if let Some(fin) = prob.end_date() {
+                        let label = if prob.active { "Fin previsto" } else { "Fin" };
+                        println!("   {}: {}", label, fin.format("%d/%m/%Y"));
+                    }
+                    if let Some(duration) = prob.duration() {
+                        println!("   Duración: {} días", duration.num_days());
+                    }
+                    if let Some(obs) = &prob.observations {
+                        println!("   Observaciones: {}", obs);
+                    }
+                    println!();
+                },
+            )
+            .await?;
+        }
+        ApiCommands::SafetyNotes {
+            nregistro,
+            desde,
+            download,
+        } => {
+            let mut notas = client.get_safety_notes(&nregistro).await?;
+            if let Some(desde) = desde {
+                notas.retain(|nota| {
+                    nota.datetime()
+                        .is_some_and(|date| date.date_naive() >= desde)
+                });
             }
+            notas.sort_by_key(|nota| std::cmp::Reverse(nota.date));
 
-            if activos && !med.active_ingredients.is_empty() {
-                println!("\n=== Principios Activos ===");
-                for pa in &med.active_ingredients {
-                    print!("- {}", pa.name);
-                    if let (Some(cantidad), Some(unidad)) = (&pa.amount, &pa.unit) {
-                        print!(": {} {}", cantidad, unidad);
+            let downloads = match &download {
+                Some(dir) => {
+                    fs::create_dir_all(dir)
+                        .with_context(|| format!("Failed to create {:?}", dir))?;
+                    Some(client.download_safety_notes(&notas, dir).await)
+                }
+                None => None,
+            };
+            if json {
+                match &downloads {
+                    Some(downloads) => print_json(downloads)?,
+                    None => print_json(&notas)?,
+                }
+            } else if notas.is_empty() {
+                println!("Sin notas de seguridad");
+            } else {
+                println!("Notas de Seguridad: {}\n", notas.len());
+                for (i, nota) in notas.iter().enumerate() {
+                    let fecha = nota
+                        .datetime()
+                        .map(|date| date.format("%Y-%m-%d").to_string())
+                        .unwrap_or_default();
+                    println!("{}. {} {} - {}", i + 1, fecha, nota.num, nota.subject);
+                    println!("   URL: {}", nota.url);
+                    if let Some(download) = downloads.as_ref().map(|downloads| &downloads[i]) {
+                        println!("   {}", render_download_status(&download.status));
                     }
                     println!();
                 }
             }
 
-            if presentaciones && !med.presentations.is_empty() {
-                println!("\n=== Presentaciones ===");
-                for pres in &med.presentations {
-                    println!("- CN: {} - {}", pres.cn, pres.name);
-                    if pres.commercialized {
-                        println!("  ✓ Comercializada");
+            let failed = downloads
+                .iter()
+                .flatten()
+                .filter(|download| !download.status.is_success())
+                .count();
+            if failed > 0 {
+                return Err(exit_error(
+                    EXIT_PARTIAL,
+                    format!(
+                        "{} of {} safety notes could not be downloaded",
+                        failed,
+                        notas.len()
+                    ),
+                ));
+            }
+        }
+        ApiCommands::Changes {
+            desde,
+            nregistro,
+            tipo,
+            cambio,
+            pages,
+            export,
+            limit,
+        } => {
+            let nregs: Vec<&str> = nregistro.iter().map(|s| s.as_str()).collect();
+            let nregs_opt = if nregs.is_empty() {
+                None
+            } else {
+                Some(nregs.as_slice())
+            };
+
+            let types: Vec<ChangeType> = tipo.into_iter().map(ChangeType::from).collect();
+            let aspects: Vec<ChangeAspect> = cambio.into_iter().map(ChangeAspect::from).collect();
+            let keep = move |record: &ChangeRecord| {
+                (types.is_empty() || record.kind().is_some_and(|kind| types.contains(&kind)))
+                    && (aspects.is_empty() || record.aspects().any(|a| aspects.contains(&a)))
+            };
+
+            let results = if pages.all {
+                client.get_change_log_pages(&desde, nregs_opt).left_stream()
+            } else {
+                stream::once(client.get_change_log_page(&desde, nregs_opt, pages.page))
+                    .right_stream()
+            };
+            let results = results.map_ok(move |mut page| {
+                page.results.retain(&keep);
+                page
+            });
+            print_pages(
+                results,
+                "changes",
+                &pages,
+                pages.limit(limit, usize::MAX),
+                &export,
+                json,
+                print_change,
+            )
+            .await?;
+        }
+        ApiCommands::Watch {
+            desde,
+            interval,
+            nregistro,
+            exec,
+        } => {
+            let changes = client.watch_changes(&desde, interval);
+            let mut changes = std::pin::pin!(changes);
+            let mut ctrl_c = std::pin::pin!(tokio::signal::ctrl_c());
+            tracing::info!("Watching changes since {} every {:?}", desde, interval);
+
+            let mut shown = 0;
+            loop {
+                let change = tokio::select! {
+                    _ = &mut ctrl_c => break,
+                    change = changes.next() => change,
+                };
+                let cambio = match change {
+                    Some(Ok(cambio)) => cambio,
+                    Some(Err(e)) => {
+                        tracing::warn!("Failed to poll the change log, retrying later: {:#}", e);
+                        continue;
                     }
+                    None => break,
+                };
+                if !nregistro.is_empty() && !nregistro.contains(&cambio.nregistro) {
+                    continue;
                 }
-            }
 
-            if !med.docs.is_empty() {
-                println!("\n=== Documentos Disponibles ===");
-                for doc in &med.docs {
-                    let tipo = match doc.doc_type {
-                        1 => "Ficha Técnica",
-                        2 => "Prospecto",
-                        3 => "Informe Público Evaluación",
-                        4 => "Plan de gestión de riesgos",
-                        _ => "Otro",
-                    };
-                    println!("- {}: {}", tipo, doc.url);
+                shown += 1;
+                if json {
+                    println!("{}", serde_json::to_string(&cambio)?);
+                } else {
+                    print_change(shown, &cambio);
+                }
+                std::io::stdout()
+                    .flush()
+                    .context("Failed to write change")?;
+                if let Some(command) = &exec
+                    && let Err(e) = run_change_command(command, &cambio).await
+                {
+                    tracing::warn!("{:#}", e);
                 }
             }
+            std::io::stdout()
+                .flush()
+                .context("Failed to write changes")?;
+            tracing::info!("Stopped watching, {} changes shown", shown);
         }
-        ApiCommands::SearchMedicamentos {
+        ApiCommands::Maestra {
+            tipo,
+            nombre,
+            id,
+            codigo,
+            estupefaciente,
+            psicotropo,
+            en_uso,
+            pages,
+            limit,
+        } => {
+            let tipo_maestra = MasterDataType::from(tipo);
+            let params = MasterDataParams {
+                name: nombre,
+                id,
+                code: codigo,
+                narcotic: estupefaciente.then_some(1),
+                psychotropic: psicotropo.then_some(1),
+                in_use: en_uso,
+                page: pages.page,
+                ..Default::default()
+            };
+
+            let results = if pages.all {
+                client
+                    .get_master_data_pages(tipo_maestra, &params)
+                    .left_stream()
+            } else {
+                stream::once(client.get_master_data(tipo_maestra, &params)).right_stream()
+            };
+            print_pages(
+                results,
+                "items",
+                &pages,
+                pages.limit(limit, 20),
+                &ExportArgs::default(),
+                json,
+                |n, item| {
+                    print!("{}. {}", n, item.name);
+                    if let Some(codigo) = &item.code {
+                        print!(" ({})", codigo);
+                    } else if let Some(id) = item.id {
+                        print!(" (ID: {})", id);
+                    }
+                    println!();
+                },
+            )
+            .await?;
+        }
+        ApiCommands::Vmpp {
             nombre,
-            laboratorio,
             principio_activo,
             atc,
-            comercializados,
-            huerfanos,
-            triangulo,
+            forma,
+            dosis,
+            arbol,
+            min_comercializadas,
+            export,
+            all,
             limit,
         } => {
-            let params = SearchMedicationsParams {
+            let params = SearchClinicalDescriptionParams {
                 name: nombre,
-                laboratory: laboratorio,
-                active_ingredient_1: principio_activo,
+                active_ingredient: principio_activo,
                 atc,
-                commercialized: if comercializados { Some(1) } else { None },
-                orphan: if huerfanos { Some(1) } else { None },
-                black_triangle: if triangulo { Some(1) } else { None },
+                pharmaceutical_form: forma,
+                dose: dosis,
+                tree_mode: arbol,
                 ..Default::default()
             };
+            let opts = ClinicalDescriptionFetchOpts {
+                min_commercialized: min_comercializadas,
+            };
 
-            let response = client.search_medications(&params).await?;
+            if let Some(path) = export {
+                let fetch = client
+                    .search_all_clinical_descriptions_with_opts(&params, &opts)
+                    .await?;
+                write_clinical_descriptions_csv(&fetch.results, &path)?;
+
+                print_status(
+                    json,
+                    &format!(
+                        "✓ Exported {} clinical descriptions to {:?} ({} scanned)",
+                        fetch.kept(),
+                        path,
+                        fetch.scanned
+                    ),
+                );
+                return Ok(());
+            }
 
-            tracing::info!(
-                "Found {} total medications (page {} of {}, showing {} results)",
-                response.total_rows,
-                response.page,
-                response.total_rows.div_ceil(response.page_size),
-                response.results.len()
-            );
+            let descriptions = if all {
+                let fetch = client
+                    .search_all_clinical_descriptions_with_opts(&params, &opts)
+                    .await?;
+
+                tracing::info!(
+                    "Found {} clinical descriptions ({} scanned)",
+                    fetch.kept(),
+                    fetch.scanned
+                );
 
-            for (i, med) in response.results.iter().enumerate().take(limit) {
-                println!("{}. {} ({})", i + 1, med.name, med.nregistro);
-                println!("   Laboratorio: {}", med.labtitular);
-                if let Some(comerc) = med.commercialized {
-                    println!("   Comercializado: {}", if comerc { "Sí" } else { "No" });
+                if json {
+                    return print_json(&fetch.results);
                 }
-                println!();
-            }
+                fetch.results
+            } else {
+                let mut response = client.search_clinical_descriptions(&params).await?;
 
-            if response.results.len() > limit {
                 tracing::info!(
-                    "Showing {} of {} results from page",
-                    limit,
+                    "Found {} total clinical descriptions (page {} of {}, showing {} results)",
+                    response.total_rows,
+                    response.page,
+                    response.total_rows.div_ceil(response.page_size),
                     response.results.len()
                 );
+
+                let received = response.results.len();
+                response.results.retain(|d| opts.keep(d));
+                response.results.truncate(limit);
+                if json {
+                    return print_json(&response);
+                }
+                if received > limit {
+                    tracing::info!("Showing {} of {} results from page", limit, received);
+                }
+                response.results
+            };
+
+            if arbol {
+                print!("{}", render_clinical_description_tree(&descriptions));
+            } else {
+                print!("{}", render_clinical_descriptions(&descriptions));
             }
         }
-        ApiCommands::Presentacion { cn } => {
-            let pres = client.get_presentation(&cn).await?;
+        ApiCommands::Doc {
+            nregistro,
+            tipo,
+            seccion,
+            list,
+            html,
+            out,
+        } => {
+            let sections = if list {
+                client
+                    .get_document_sections(tipo.document_type(), &nregistro)
+                    .await?
+            } else {
+                client
+                    .get_document_content(tipo.document_type(), &nregistro, seccion.as_deref())
+                    .await?
+            };
+            if sections.is_empty() {
+                let message = match &seccion {
+                    Some(seccion) => format!(
+                        "La sección {} no está en el documento segmentado ({}) del medicamento {}",
+                        seccion,
+                        tipo.name(),
+                        nregistro
+                    ),
+                    None => format!(
+                        "No hay documento segmentado ({}) para el medicamento {}",
+                        tipo.name(),
+                        nregistro
+                    ),
+                };
+                return Err(exit_error(EXIT_NOT_FOUND, message));
+            }
 
-            println!("=== Presentación ===");
-            println!("Código Nacional: {}", pres.cn);
-            println!("Nombre: {}", pres.name);
-            println!(
-                "Comercializada: {}",
-                if pres.commercialized { "Sí" } else { "No" }
+            let output = if json {
+                format!("{}\n", serde_json::to_string_pretty(&sections)?)
+            } else if list {
+                render_section_index(&sections)
+            } else {
+                render_sections(&sections, html)
+            };
+            write_output(out.as_deref(), json, &output)?;
+        }
+        ApiCommands::DownloadDocs {
+            tipo,
+            out,
+            mut nregistro,
+            from_file,
+            concurrency,
+            overwrite,
+            html,
+            allow_partial,
+        } => {
+            if let Some(path) = &from_file {
+                nregistro.extend(read_id_list(path)?);
+            }
+            fs::create_dir_all(&out).with_context(|| format!("Failed to create {:?}", out))?;
+            let options = DocumentDownloadOptions {
+                concurrency,
+                overwrite,
+                html,
+            };
+            let downloads = client
+                .download_documents(tipo.into(), &nregistro, &out, &options)
+                .await;
+
+            let report = download_report(&downloads);
+            let report_path = out.join("report.json");
+            fs::write(&report_path, serde_json::to_string_pretty(&report)? + "\n")
+                .with_context(|| format!("Failed to write {:?}", report_path))?;
+            if json {
+                print_json(&report)?;
+            } else {
+                print_download_table(&downloads);
+            }
+
+            let failed = downloads
+                .iter()
+                .filter(|download| !download.status.is_success())
+                .count();
+            print_status(
+                json,
+                &format!(
+                    "✓ {} of {} documents in {:?}, report written to {:?}",
+                    downloads.len() - failed,
+                    downloads.len(),
+                    out,
+                    report_path
+                ),
             );
+            if failed > 0 && !allow_partial {
+                return Err(exit_error(
+                    EXIT_PARTIAL,
+                    format!(
+                        "{} of {} documents could not be downloaded, use --allow-partial to \
+                         ignore them",
+                        failed,
+                        downloads.len()
+                    ),
+                ));
+            }
         }
-        ApiCommands::SearchPresentaciones {
+        ApiCommands::Fotos {
             nregistro,
-            vmp,
-            comercializados,
-            limit,
+            tipo,
+            out,
         } => {
-            let params = SearchPresentationsParams {
-                registration_number: nregistro,
-                vmp,
-                commercialized: if comercializados { Some(1) } else { None },
-                ..Default::default()
-            };
+            fs::create_dir_all(&out).with_context(|| format!("Failed to create {:?}", out))?;
+            let downloads = client
+                .download_photos(&nregistro, tipo.map(PhotoType::from), &out)
+                .await?;
+            let (saved, failed): (Vec<_>, Vec<_>) = downloads
+                .iter()
+                .partition(|download| download.status.is_success());
+            if json {
+                print_json(&serde_json::json!({ "saved": saved, "errors": failed }))?;
+            } else if downloads.is_empty() {
+                let tipo = tipo
+                    .and_then(|tipo| tipo.to_possible_value())
+                    .map(|value| format!(" de tipo {}", value.get_name()))
+                    .unwrap_or_default();
+                println!("El medicamento {} no tiene fotos{}", nregistro, tipo);
+            } else {
+                print_photo_table(&downloads);
+            }
+            if !failed.is_empty() {
+                return Err(exit_error(
+                    EXIT_PARTIAL,
+                    format!(
+                        "{} of {} photos could not be downloaded",
+                        failed.len(),
+                        downloads.len()
+                    ),
+                ));
+            }
+        }
+    }
 
-            let response = client.search_presentations(&params).await?;
+    Ok(())
+}
 
-            tracing::info!(
-                "Found {} total presentations (page {} of {}, showing {} results)",
-                response.total_rows,
-                response.page,
-                response.total_rows.div_ceil(response.page_size),
-                response.results.len()
-            );
+/// Prints a change of the change log, numbered `n`.
+fn print_change(n: usize, cambio: &ChangeRecord) {
+    println!("{}. Nº Registro: {}", n, cambio.nregistro);
+    if let Some(date) = cambio.datetime() {
+        println!("   Fecha: {}", date.to_rfc3339());
+    }
+    let tipo = cambio.kind().map_or("desconocido", ChangeType::label);
+    println!("   Tipo: {}", tipo);
+    if !cambio.changes.is_empty() {
+        let labels: Vec<&str> = cambio
+            .changes
+            .iter()
+            .map(|code| {
+                ChangeAspect::from_code(code).map_or(code.as_str(), |aspect| aspect.label())
+            })
+            .collect();
+        println!("   Cambios: {}", labels.join(", "));
+    }
+    println!();
+}
 
-            for (i, p) in response.results.iter().enumerate().take(limit) {
-                println!("{}. CN: {} - {}", i + 1, p.cn, p.name);
-                if p.commercialized {
-                    println!("   ✓ Comercializada");
-                }
-                println!();
-            }
+/// Runs `command` in the shell with `cambio` as JSON on its standard input.
+async fn run_change_command(command: &str, cambio: &ChangeRecord) -> anyhow::Result<()> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut child = tokio::process::Command::new(shell)
+        .args([flag, command])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {:?}", command))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let input = serde_json::to_vec(cambio)?;
+        tokio::io::AsyncWriteExt::write_all(&mut stdin, &input)
+            .await
+            .with_context(|| format!("Failed to write the change to {:?}", command))?;
+    }
+    let status = child
+        .wait()
+        .await
+        .with_context(|| format!("Failed to run {:?}", command))?;
+    if !status.success() {
+        anyhow::bail!("{:?} failed for {}: {}", command, cambio.nregistro, status);
+    }
+    Ok(())
+}
 
-            if response.results.len() > limit {
-                tracing::info!(
-                    "Showing {} of {} results from page",
-                    limit,
-                    response.results.len()
-                );
+/// Parses the `--interval` of `api watch`: a number followed by `s`, `m` or `h`.
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    let (number, unit) = value.split_at(value.len() - value.ends_with(['s', 'm', 'h']) as usize);
+    let number: u64 = number.parse().map_err(|_| {
+        format!("invalid interval {value:?}, expected a value such as 90s, 15m or 1h")
+    })?;
+    let seconds = match unit {
+        "m" => number.saturating_mul(60),
+        "h" => number.saturating_mul(3600),
+        _ => number,
+    };
+    if seconds == 0 {
+        return Err("the interval must be greater than zero".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Date given as dd/mm/yyyy, as the API takes them, or yyyy-mm-dd.
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%d/%m/%Y")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d"))
+        .map_err(|_| format!("invalid date {value:?}, expected dd/mm/yyyy or yyyy-mm-dd"))
+}
+
+/// Identifiers of `path`, one per line, skipping blank lines and `#` comments.
+fn read_id_list(path: &Path) -> anyhow::Result<Vec<String>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.split_once('#').map_or(line, |(id, _)| id).trim())
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// `report.json` of `api download-docs`, with the downloads grouped by outcome.
+fn download_report(downloads: &[DocumentDownload]) -> serde_json::Value {
+    let with = |keep: fn(&DocumentDownloadStatus) -> bool| {
+        downloads
+            .iter()
+            .filter(|download| keep(&download.status))
+            .collect::<Vec<_>>()
+    };
+    serde_json::json!({
+        "successes": with(DocumentDownloadStatus::is_success),
+        "not_found": with(|status| matches!(status, DocumentDownloadStatus::NotFound { .. })),
+        "errors": with(|status| matches!(status, DocumentDownloadStatus::Failed { .. })),
+    })
+}
+
+/// One line per download with its registration number, outcome and file or reason.
+fn print_download_table(downloads: &[DocumentDownload]) {
+    println!("{:<12} {:<11} DETALLE", "NREGISTRO", "ESTADO");
+    for download in downloads {
+        let (status, detail) = match &download.status {
+            DocumentDownloadStatus::Downloaded { path, bytes } => (
+                "descargado",
+                format!("{} ({} bytes)", path.display(), bytes),
+            ),
+            DocumentDownloadStatus::Skipped { path } => ("existente", path.display().to_string()),
+            DocumentDownloadStatus::NotFound { reason } => ("no existe", reason.clone()),
+            DocumentDownloadStatus::Failed { error } => ("error", error.clone()),
+        };
+        println!("{:<12} {:<11} {}", download.nregistro, status, detail);
+    }
+}
+
+/// One line per photo with its type, size and file, or why it was not saved.
+fn print_photo_table(downloads: &[PhotoDownload]) {
+    println!("{:<7} {:>10}  ARCHIVO", "TIPO", "TAMAÑO");
+    for download in downloads {
+        let tipo = match download.photo.kind() {
+            Some(PhotoType::Packaging) => "envase",
+            Some(PhotoType::PharmaceuticalForm) => "forma",
+            None => download.photo.photo_type.as_str(),
+        };
+        let (size, detail) = match &download.status {
+            DocumentDownloadStatus::Downloaded { path, bytes } => {
+                (HumanBytes(*bytes).to_string(), path.display().to_string())
+            }
+            DocumentDownloadStatus::Skipped { path } => {
+                ("-".to_string(), path.display().to_string())
             }
+            DocumentDownloadStatus::NotFound { reason } => {
+                ("-".to_string(), format!("no existe: {}", reason))
+            }
+            DocumentDownloadStatus::Failed { error } => {
+                ("-".to_string(), format!("error: {}", error))
+            }
+        };
+        println!("{:<7} {:>10}  {}", tipo, size, detail);
+    }
+}
+
+/// Where a downloaded file was saved, or why it was not.
+fn render_download_status(status: &DocumentDownloadStatus) -> String {
+    match status {
+        DocumentDownloadStatus::Downloaded { path, bytes } => {
+            format!("Guardada: {} ({})", path.display(), HumanBytes(*bytes))
         }
-        ApiCommands::SupplyProblems { cn } => {
-            if let Some(codigo) = cn {
-                let response = client.get_supply_problems(&codigo).await?;
-                tracing::info!(
-                    "Found {} supply problems for CN {} (page {} of {})",
-                    response.total_rows,
-                    codigo,
-                    response.page,
-                    response.total_rows.div_ceil(response.page_size)
-                );
+        DocumentDownloadStatus::Skipped { path } => format!("Existente: {}", path.display()),
+        DocumentDownloadStatus::NotFound { reason } => format!("No existe: {}", reason),
+        DocumentDownloadStatus::Failed { error } => format!("Error: {}", error),
+    }
+}
 
-                for (i, prob) in response.results.iter().enumerate() {
-                    println!("{}. CN: {} - {}", i + 1, prob.cn, prob.name);
-                    println!("   Activo: {}", if prob.active { "Sí" } else { "No" });
-                    if let Some(obs) = &prob.observations {
-                        println!("   Observaciones: {}", obs);
-                    }
-                    println!();
-                }
-            } else {
-                let response = client.get_all_supply_problems().await?;
-                tracing::info!(
-                    "Found {} total supply problems (page {} of {})",
-                    response.total_rows,
-                    response.page,
-                    response.total_rows.div_ceil(response.page_size)
-                );
+/// Index of a segmented document, one section per line indented by its level.
+fn render_section_index(sections: &[Section]) -> String {
+    let mut index = String::new();
+    for section in sections {
+        let level = section.section.matches('.').count();
+        index.push_str(&format!(
+            "{}{} {}\n",
+            "  ".repeat(level),
+            section.section,
+            section.title
+        ));
+    }
+    index
+}
 
-                for (i, prob) in response.results.iter().enumerate() {
-                    println!("{}. CN: {} - {}", i + 1, prob.cn, prob.name);
-                    println!("   Activo: {}", if prob.active { "Sí" } else { "No" });
-                    if let Some(obs) = &prob.observations {
-                        println!("   Observaciones: {}", obs);
-                    }
-                    println!();
-                }
+/// `fields` of `med` as `name: value` lines, or just the value of a single field.
+fn render_medication_fields(med: &Medication, fields: &[MedicamentoField]) -> String {
+    let text = |value: serde_json::Value| match value {
+        serde_json::Value::Null => "-".to_string(),
+        serde_json::Value::Bool(value) => if value { "Sí" } else { "No" }.to_string(),
+        serde_json::Value::String(value) => value,
+        serde_json::Value::Array(values) => values
+            .iter()
+            .filter_map(|value| value.as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
+        value => value.to_string(),
+    };
+    if let [field] = fields {
+        return format!("{}\n", text(field.value(med)));
+    }
+    fields
+        .iter()
+        .map(|field| format!("{}: {}\n", field.name(), text(field.value(med))))
+        .collect()
+}
+
+/// `med` as JSON, only with `fields` if there are any.
+fn medication_json(
+    med: &Medication,
+    fields: &[MedicamentoField],
+) -> anyhow::Result<serde_json::Value> {
+    if fields.is_empty() {
+        return Ok(serde_json::to_value(med)?);
+    }
+    Ok(fields
+        .iter()
+        .map(|field| (field.name(), field.value(med)))
+        .collect::<serde_json::Map<_, _>>()
+        .into())
+}
+
+/// Details of `med`, with its presentations and active ingredients when asked.
+fn render_medication_details(med: &Medication, presentaciones: bool, activos: bool) -> String {
+    let mut out = String::new();
+    out.push_str("=== Medicamento ===\n");
+    out.push_str(&format!("Nº Registro: {}\n", med.nregistro));
+    out.push_str(&format!("Nombre: {}\n", med.name));
+    out.push_str(&format!("Laboratorio: {}\n", med.labtitular));
+    out.push_str(&format!("Principios Activos: {}\n", med.pactivos));
+    out.push_str(&format!("Condiciones de prescripción: {}\n", med.cpresc));
+
+    if let Some(comerc) = med.commercialized {
+        out.push_str(&format!(
+            "Comercializado: {}\n",
+            if comerc { "Sí" } else { "No" }
+        ));
+    }
+
+    if let Some(triangulo) = med.black_triangle
+        && triangulo
+    {
+        out.push_str("⚠️  Triángulo negro (medicamento bajo vigilancia adicional)\n");
+    }
+
+    if let Some(huerfano) = med.orphan
+        && huerfano
+    {
+        out.push_str("💊 Medicamento huérfano\n");
+    }
+
+    if activos && !med.active_ingredients.is_empty() {
+        out.push_str("\n=== Principios Activos ===\n");
+        for pa in &med.active_ingredients {
+            out.push_str(&format!("- {}", pa.name));
+            if let (Some(cantidad), Some(unidad)) = (&pa.amount, &pa.unit) {
+                out.push_str(&format!(": {} {}", cantidad, unidad));
             }
+            out.push('\n');
         }
-        ApiCommands::SafetyNotes { nregistro } => {
-            let notas = client.get_safety_notes(&nregistro).await?;
-
-            println!("Notas de Seguridad: {}\n", notas.len());
+    }
 
-            for (i, nota) in notas.iter().enumerate() {
-                println!("{}. {} - {}", i + 1, nota.num, nota.subject);
-                println!("   URL: {}", nota.url);
-                println!();
+    if presentaciones && !med.presentations.is_empty() {
+        out.push_str("\n=== Presentaciones ===\n");
+        for pres in &med.presentations {
+            out.push_str(&format!("- CN: {} - {}\n", pres.cn, pres.name));
+            if pres.commercialized {
+                out.push_str("  ✓ Comercializada\n");
             }
         }
-        ApiCommands::Changes { desde, nregistro } => {
-            let nregs: Vec<&str> = nregistro.iter().map(|s| s.as_str()).collect();
-            let nregs_opt = if nregs.is_empty() {
-                None
-            } else {
-                Some(nregs.as_slice())
-            };
+    }
 
-            let response = client.get_change_log(&desde, nregs_opt).await?;
+    if !med.docs.is_empty() {
+        out.push_str("\n=== Documentos Disponibles ===\n");
+        for doc in &med.docs {
+            let tipo = match doc.doc_type {
+                1 => "Ficha Técnica",
+                2 => "Prospecto",
+                3 => "Informe Público Evaluación",
+                4 => "Plan de gestión de riesgos",
+                _ => "Otro",
+            };
+            out.push_str(&format!("- {}: {}\n", tipo, doc.url));
+        }
+    }
+    out
+}
 
+/// Prints the rows of the paginated subcommands, `--limit` results at most, and writes
+/// them to the `--export` file.
+///
+/// Without `--all` the requested page is printed as the API response in JSON; with
+/// `--all` each page is printed as it arrives, as a single JSON array.
+async fn print_pages<T: Serialize + CsvRecord>(
+    results: impl Stream<Item = anyhow::Result<PaginatedResponse<T>>>,
+    what: &str,
+    pages: &PageArgs,
+    limit: usize,
+    export: &ExportArgs,
+    json: bool,
+    mut print_row: impl FnMut(usize, &T),
+) -> anyhow::Result<()> {
+    let mut csv = export.create::<T>()?;
+    let mut results = std::pin::pin!(results);
+    let mut total = 0;
+    let mut shown = 0;
+
+    while shown < limit
+        && let Some(mut response) = results.try_next().await?
+    {
+        if response.page <= 1 || !pages.all {
+            total = response.total_rows;
             tracing::info!(
-                "Found {} total changes since {} (page {} of {})",
+                "Found {} total {} (page {} of {})",
                 response.total_rows,
-                desde,
+                what,
                 response.page,
-                response.total_rows.div_ceil(response.page_size)
+                response.total_rows.div_ceil(response.page_size.max(1))
             );
-
-            for (i, cambio) in response.results.iter().enumerate() {
-                println!("{}. Nº Registro: {}", i + 1, cambio.nregistro);
-                let tipo = match cambio.change_type {
-                    1 => "Nuevo",
-                    2 => "Baja",
-                    3 => "Modificado",
-                    _ => "Desconocido",
-                };
-                println!("   Tipo: {}", tipo);
-                if !cambio.changes.is_empty() {
-                    println!("   Cambios: {}", cambio.changes.join(", "));
-                }
-                println!();
+        }
+        response.results.truncate(limit - shown);
+        if let Some(csv) = &mut csv {
+            for row in &response.results {
+                csv.write(row)?;
             }
         }
-        ApiCommands::Maestra {
-            tipo,
-            nombre,
-            limit,
-        } => {
-            // Validate that at least one filter parameter is provided (API requires this)
-            if nombre.is_none() {
-                tracing::warn!(
-                    "No filter parameters provided. The CIMA API requires at least one filter parameter (nombre, id, codigo, etc.)"
-                );
-                tracing::warn!(
-                    "The maestra CLI currently only supports --nombre. Other parameters can be used via the library API."
-                );
-                eprintln!("Error: The --nombre parameter is required for this command");
-                eprintln!(
-                    "(The API supports id, codigo, estupefaciente, etc., but the CLI currently only exposes --nombre)"
-                );
-                eprintln!("Example: nomenclator api maestra --tipo pa --nombre 'paracetamol'");
-                std::process::exit(1);
-            }
-
-            let tipo_maestra = match tipo.as_str() {
-                "pa" => MasterDataType::ActiveIngredients,
-                "ff" => MasterDataType::PharmaceuticalForms,
-                "va" => MasterDataType::AdministrationRoutes,
-                "lab" => MasterDataType::Laboratories,
-                "atc" => MasterDataType::AtcCodes,
-                _ => anyhow::bail!(
-                    "Tipo de maestra desconocido: {}. Use: pa, ff, va, lab, atc",
-                    tipo
-                ),
-            };
 
-            let params = MasterDataParams {
-                name: nombre,
-                ..Default::default()
-            };
+        if json && !pages.all {
+            shown = response.results.len();
+            print_json(&response)?;
+            break;
+        }
+        let mut stdout = std::io::stdout().lock();
+        for row in &response.results {
+            if json {
+                let separator = if shown == 0 { "[\n  " } else { ",\n  " };
+                write!(stdout, "{separator}").context("Failed to write JSON")?;
+                serde_json::to_writer(&mut stdout, row).context("Failed to write JSON")?;
+            } else {
+                print_row(shown + 1, row);
+            }
+            shown += 1;
+        }
+        stdout.flush().context("Failed to write results")?;
+    }
+    if json && pages.all {
+        println!("{}", if shown == 0 { "[]" } else { "\n]" });
+    }
 
-            let response = client.get_master_data(tipo_maestra, &params).await?;
+    tracing::info!("Showing {} of {} {}", shown, total, what);
+    if let (Some(csv), Some(path)) = (csv, &export.export) {
+        let rows = csv.finish()?;
+        print_status(json, &format!("✓ Exported {} {} to {:?}", rows, what, path));
+    }
+    Ok(())
+}
 
-            tracing::info!(
-                "Found {} total items (page {} of {})",
-                response.total_rows,
-                response.page,
-                response.total_rows.div_ceil(response.page_size)
-            );
+fn print_medication(n: usize, med: &MedicationSummary) {
+    println!("{}. {} ({})", n, med.name, med.nregistro);
+    println!("   Laboratorio: {}", med.labtitular);
+    if let Some(comerc) = med.commercialized {
+        println!("   Comercializado: {}", if comerc { "Sí" } else { "No" });
+    }
+    println!();
+}
 
-            for (i, item) in response.results.iter().enumerate().take(limit) {
-                print!("{}. {}", i + 1, item.name);
-                if let Some(codigo) = &item.code {
-                    print!(" ({})", codigo);
-                } else if let Some(id) = item.id {
-                    print!(" (ID: {})", id);
-                }
-                println!();
-            }
+/// Pairs the `--seccion` and `--texto` values of `buscar-ft` in order, marking a pair as
+/// "must not contain" when `--no-contiene` appears between it and the previous pair.
+fn technical_sheet_queries(
+    matches: &ArgMatches,
+    sections: Vec<String>,
+    texts: Vec<String>,
+) -> anyhow::Result<Vec<TechnicalSheetQuery>> {
+    if sections.len() != texts.len() {
+        return Err(exit_error(
+            EXIT_USAGE,
+            format!(
+                "Each --seccion needs a --texto ({} sections, {} texts)",
+                sections.len(),
+                texts.len()
+            ),
+        ));
+    }
+    for section in &sections {
+        validate_technical_sheet_section(section)?;
+    }
 
-            if response.results.len() > limit {
-                tracing::info!(
-                    "Showing {} of {} results from page",
-                    limit,
-                    response.results.len()
-                );
-            }
+    let indices = |id: &str| -> Vec<usize> {
+        match matches.value_source(id) {
+            Some(ValueSource::CommandLine) => matches
+                .indices_of(id)
+                .map(|indices| indices.collect())
+                .unwrap_or_default(),
+            _ => Vec::new(),
         }
+    };
+    let section_indices = indices("seccion");
+    let text_indices = indices("texto");
+    let negations = indices("no_contiene");
+
+    let mut previous_end = 0;
+    let mut queries = Vec::with_capacity(sections.len());
+    for (i, (section, text)) in sections.into_iter().zip(texts).enumerate() {
+        let start = section_indices[i].min(text_indices[i]);
+        let negated = negations.iter().any(|&n| n > previous_end && n < start);
+        previous_end = section_indices[i].max(text_indices[i]);
+        queries.push(TechnicalSheetQuery {
+            section,
+            text,
+            contains: if negated { 0 } else { 1 },
+        });
     }
+    if negations.iter().any(|&n| n > previous_end) {
+        return Err(exit_error(
+            EXIT_USAGE,
+            "--no-contiene must be followed by a --seccion and --texto pair",
+        ));
+    }
+
+    Ok(queries)
+}
 
+/// Checks that `section` is a section of the technical data sheet, 1 to 10 with optional
+/// sublevels such as 4.1 or 6.6.
+fn validate_technical_sheet_section(section: &str) -> anyhow::Result<()> {
+    let mut levels = section.split('.');
+    let valid = levels
+        .next()
+        .and_then(|top| top.parse::<u8>().ok())
+        .is_some_and(|top| (1..=10).contains(&top))
+        && levels.all(|level| !level.is_empty() && level.bytes().all(|b| b.is_ascii_digit()));
+
+    if !valid {
+        return Err(exit_error(
+            EXIT_USAGE,
+            format!(
+                "Invalid section '{}': use a section of the technical data sheet from 1 to 10, \
+                 optionally with sublevels such as 4.1 or 4.3",
+                section
+            ),
+        ));
+    }
     Ok(())
 }
+
+/// Numbered list of clinical descriptions with their VMP and commercialized presentations.
+fn render_clinical_descriptions(descriptions: &[ClinicalDescription]) -> String {
+    let mut list = String::new();
+    for (i, desc) in descriptions.iter().enumerate() {
+        list.push_str(&format!(
+            "{}. VMPP: {} - {}\n",
+            i + 1,
+            desc.vmpp,
+            desc.vmpp_desc
+        ));
+        list.push_str(&format!("   VMP: {} - {}\n", desc.vmp, desc.vmp_desc));
+        list.push_str(&format!(
+            "   Presentaciones comercializadas: {}\n\n",
+            desc.commercialized_presentations
+        ));
+    }
+    list
+}
+
+/// Clinical descriptions grouped by VMP, each VMPP indented under its VMP.
+fn render_clinical_description_tree(descriptions: &[ClinicalDescription]) -> String {
+    let mut vmps: Vec<(&ClinicalDescription, Vec<&ClinicalDescription>)> = Vec::new();
+    for desc in descriptions {
+        match vmps.iter_mut().find(|(vmp, _)| vmp.vmp == desc.vmp) {
+            Some((_, vmpps)) => vmpps.push(desc),
+            None => vmps.push((desc, vec![desc])),
+        }
+    }
+
+    let mut tree = String::new();
+    for (vmp, vmpps) in vmps {
+        tree.push_str(&format!("VMP: {} - {}\n", vmp.vmp, vmp.vmp_desc));
+        for desc in vmpps {
+            tree.push_str(&format!(
+                "  VMPP: {} - {} ({} comercializadas)\n",
+                desc.vmpp, desc.vmpp_desc, desc.commercialized_presentations
+            ));
+        }
+    }
+    tree
+}
+
+/// Content of the sections of a segmented document, as plain text or HTML.
+fn render_sections(sections: &[Section], html: bool) -> String {
+    let mut content = String::new();
+    for section in sections {
+        if html {
+            content.push_str(section.content.as_deref().unwrap_or_default());
+            content.push('\n');
+        } else {
+            content.push_str(&format!("=== {} {} ===\n", section.section, section.title));
+            if let Some(text) = section.text() {
+                content.push_str(&text);
+                content.push('\n');
+            }
+            content.push('\n');
+        }
+    }
+    content
+}
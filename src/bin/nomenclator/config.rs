@@ -0,0 +1,212 @@
+//! Configuration file of the `nomenclator` tool, giving the defaults of its options.
+//!
+//! Each section holds the options of a subcommand, named like the flags with `_`
+//! instead of `-`:
+//!
+//! ```toml
+//! [csv]
+//! work_dir = "/var/lib/cima/nomenclator_data"
+//! output_dir = "/var/lib/cima/csv_output"
+//! concurrency = 4
+//!
+//! [api]
+//! base_url = "https://cima.aemps.es/cima/rest"
+//! ```
+//!
+//! The values only replace the built-in defaults, so environment variables and flags
+//! given on the command line take precedence over them.
+
+use anyhow::Context;
+use clap::Command;
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the configuration file, like `--config`
+pub const CONFIG_ENV: &str = "CIMA_CONFIG";
+
+/// Option of a subcommand that can be set in the configuration file
+struct ConfigKey {
+    /// Section of the file, the name of the subcommand
+    section: &'static str,
+    /// Key in the section
+    key: &'static str,
+    /// Id of the argument of the subcommand
+    arg: &'static str,
+}
+
+const KEYS: &[ConfigKey] = &[
+    ConfigKey {
+        section: "csv",
+        key: "output_dir",
+        arg: "output_dir",
+    },
+    ConfigKey {
+        section: "csv",
+        key: "work_dir",
+        arg: "work_dir",
+    },
+    ConfigKey {
+        section: "csv",
+        key: "concurrency",
+        arg: "concurrency",
+    },
+    ConfigKey {
+        section: "csv",
+        key: "output_format",
+        arg: "format",
+    },
+    ConfigKey {
+        section: "csv",
+        key: "download_url",
+        arg: "download_url",
+    },
+    ConfigKey {
+        section: "csv",
+        key: "mirrors",
+        arg: "mirrors",
+    },
+    ConfigKey {
+        section: "csv",
+        key: "zip_cache",
+        arg: "zip_cache",
+    },
+    ConfigKey {
+        section: "csv",
+        key: "base_url",
+        arg: "base_url",
+    },
+    ConfigKey {
+        section: "csv",
+        key: "timeout",
+        arg: "timeout",
+    },
+    ConfigKey {
+        section: "csv",
+        key: "rate_limit",
+        arg: "rate_limit",
+    },
+    ConfigKey {
+        section: "csv",
+        key: "retries",
+        arg: "retries",
+    },
+    ConfigKey {
+        section: "api",
+        key: "base_url",
+        arg: "base_url",
+    },
+    ConfigKey {
+        section: "api",
+        key: "format",
+        arg: "format",
+    },
+    ConfigKey {
+        section: "api",
+        key: "timeout",
+        arg: "timeout",
+    },
+    ConfigKey {
+        section: "api",
+        key: "rate_limit",
+        arg: "rate_limit",
+    },
+    ConfigKey {
+        section: "api",
+        key: "retries",
+        arg: "retries",
+    },
+];
+
+/// Parsed configuration file
+#[derive(Debug)]
+pub struct Config {
+    /// File the configuration was read from
+    pub path: PathBuf,
+    table: toml::Table,
+}
+
+/// `cima-rs/config.toml` in `$XDG_CONFIG_HOME`, or in `~/.config` when it is not set
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(std::env::home_dir()?.join(".config")))?;
+    Some(config_home.join("cima-rs").join("config.toml"))
+}
+
+/// Reads the configuration file at `path`, or at [`default_path`] if `None`.
+///
+/// A missing default file is not an error: there is just no configuration.
+pub fn load(path: Option<&Path>) -> anyhow::Result<Option<Config>> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match default_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(None),
+        },
+    };
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file {:?}", path))?;
+    let table = contents
+        .parse::<toml::Table>()
+        .with_context(|| format!("Failed to parse config file {:?}", path))?;
+    Ok(Some(Config { path, table }))
+}
+
+impl Config {
+    /// Sets the values of the file as the defaults of the arguments of `command`,
+    /// returning it with a warning for each unknown section or key.
+    pub fn apply(&self, mut command: Command) -> anyhow::Result<(Command, Vec<String>)> {
+        let mut warnings = Vec::new();
+        for (section, values) in &self.table {
+            let Some(values) = values.as_table() else {
+                warnings.push(format!("Unknown key '{}' in {:?}", section, self.path));
+                continue;
+            };
+            if !KEYS.iter().any(|key| key.section == section) {
+                warnings.push(format!("Unknown section [{}] in {:?}", section, self.path));
+                continue;
+            }
+            for (name, value) in values {
+                let Some(key) = KEYS
+                    .iter()
+                    .find(|key| key.section == section && key.key == name)
+                else {
+                    warnings.push(format!(
+                        "Unknown key '{}.{}' in {:?}",
+                        section, name, self.path
+                    ));
+                    continue;
+                };
+                let defaults = values_of(value).with_context(|| {
+                    format!("Invalid value of '{}.{}' in {:?}", section, name, self.path)
+                })?;
+                command = command.mut_subcommand(key.section, |subcommand| {
+                    subcommand.mut_arg(key.arg, |arg| arg.default_values(defaults))
+                });
+            }
+        }
+        Ok((command, warnings))
+    }
+}
+
+/// Command line values of a configuration value, several for an array
+fn values_of(value: &toml::Value) -> anyhow::Result<Vec<String>> {
+    match value {
+        toml::Value::String(value) => Ok(vec![value.clone()]),
+        toml::Value::Integer(value) => Ok(vec![value.to_string()]),
+        toml::Value::Float(value) => Ok(vec![value.to_string()]),
+        toml::Value::Boolean(value) => Ok(vec![value.to_string()]),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                toml::Value::Array(_) | toml::Value::Table(_) => {
+                    anyhow::bail!("expected a list of plain values")
+                }
+                value => Ok(values_of(value)?.remove(0)),
+            })
+            .collect(),
+        toml::Value::Datetime(_) | toml::Value::Table(_) => {
+            anyhow::bail!("expected a string, number, boolean or list")
+        }
+    }
+}
@@ -0,0 +1,450 @@
+//! Recording of the requests of a [`CimaClient`](crate::CimaClient) to a cassette file,
+//! and their replay without the network.
+//!
+//! A cassette is a JSON file listing every request sent, with its method, URL and body,
+//! and the status and body of its response. Requests are matched on the three, with the
+//! query parameters in any order.
+
+use anyhow::{Context, Result};
+use reqwest::{RequestBuilder, Response, ResponseBuilderExt, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Text replacing the values scrubbed by [`Scrub::query_param`] and [`Scrub::json_field`]
+pub const SCRUBBED: &str = "[scrubbed]";
+
+/// What a [`Cassette`] does with the requests of the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordMode {
+    /// Send the requests and write them with their responses to the cassette,
+    /// replacing what it held
+    Record,
+    /// Answer the requests from the cassette, failing on those it does not hold
+    Replay,
+    /// Replay when the cassette file exists, record it otherwise
+    Auto,
+}
+
+/// Request of an [`Interaction`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    /// HTTP method, e.g. `GET`
+    pub method: String,
+    /// URL with the query parameters sorted
+    pub url: String,
+    /// Body of POST requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+/// Response of an [`Interaction`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    /// HTTP status code
+    pub status: u16,
+    /// Body, when it is UTF-8 text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Body, when it is not text, such as a PDF document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<Vec<u8>>,
+}
+
+impl RecordedResponse {
+    fn new(status: u16, bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(body) => Self {
+                status,
+                body: Some(body),
+                bytes: None,
+            },
+            Err(e) => Self {
+                status,
+                body: None,
+                bytes: Some(e.into_bytes()),
+            },
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.body
+            .map(String::into_bytes)
+            .or(self.bytes)
+            .unwrap_or_default()
+    }
+}
+
+/// A request of the client and the response it got
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interaction {
+    /// Request sent
+    pub request: RecordedRequest,
+    /// Response received
+    pub response: RecordedResponse,
+}
+
+/// Content of a cassette file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+/// Replacement of a volatile or private value, applied to the interactions before they
+/// are written and to the requests before they are matched
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scrub {
+    /// Value of a query parameter of the request URLs
+    QueryParam {
+        /// Parameter name
+        name: String,
+        /// Value written instead
+        replacement: String,
+    },
+    /// Value of the fields of the JSON response bodies with this name, at any depth
+    JsonField {
+        /// Field name
+        name: String,
+        /// Value written instead
+        replacement: Value,
+    },
+    /// Every occurrence of a text in the URLs and bodies, e.g. the address of a local
+    /// server
+    Text {
+        /// Text to replace
+        text: String,
+        /// Text written instead
+        replacement: String,
+    },
+}
+
+impl Scrub {
+    /// Replaces the value of the query parameter `name` with [`SCRUBBED`]
+    pub fn query_param(name: &str) -> Self {
+        Scrub::QueryParam {
+            name: name.to_string(),
+            replacement: SCRUBBED.to_string(),
+        }
+    }
+
+    /// Replaces the value of the JSON fields `name` of the responses with [`SCRUBBED`]
+    pub fn json_field(name: &str) -> Self {
+        Scrub::JsonField {
+            name: name.to_string(),
+            replacement: Value::from(SCRUBBED),
+        }
+    }
+
+    /// Replaces `text` with `replacement` everywhere
+    pub fn text(text: &str, replacement: &str) -> Self {
+        Scrub::Text {
+            text: text.to_string(),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    fn request(&self, request: &mut RecordedRequest) {
+        match self {
+            Scrub::QueryParam { name, replacement } => {
+                let Ok(mut url) = Url::parse(&request.url) else {
+                    return;
+                };
+                let pairs: Vec<(String, String)> = url
+                    .query_pairs()
+                    .map(|(key, value)| {
+                        let value = if key == name.as_str() {
+                            replacement.clone()
+                        } else {
+                            value.into_owned()
+                        };
+                        (key.into_owned(), value)
+                    })
+                    .collect();
+                if !pairs.is_empty() {
+                    url.query_pairs_mut().clear().extend_pairs(pairs);
+                    request.url = url.to_string();
+                }
+            }
+            Scrub::JsonField { .. } => {}
+            Scrub::Text { text, replacement } => {
+                request.url = request.url.replace(text.as_str(), replacement);
+                if let Some(body) = &mut request.body {
+                    *body = body.replace(text.as_str(), replacement);
+                }
+            }
+        }
+    }
+
+    fn response(&self, response: &mut RecordedResponse) {
+        let Some(body) = &mut response.body else {
+            return;
+        };
+        match self {
+            Scrub::QueryParam { .. } => {}
+            Scrub::JsonField { name, replacement } => {
+                if let Ok(mut json) = serde_json::from_str::<Value>(body) {
+                    replace_fields(&mut json, name, replacement);
+                    *body = json.to_string();
+                }
+            }
+            Scrub::Text { text, replacement } => *body = body.replace(text.as_str(), replacement),
+        }
+    }
+}
+
+/// Sets every field `name` in `json` to `replacement`
+fn replace_fields(json: &mut Value, name: &str, replacement: &Value) {
+    match json {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                if key == name {
+                    *value = replacement.clone();
+                } else {
+                    replace_fields(value, name, replacement);
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                replace_fields(value, name, replacement);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `url` with its query parameters sorted by name, then value
+fn normalize_url(url: &Url) -> String {
+    let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    if pairs.is_empty() {
+        return url.to_string();
+    }
+    pairs.sort();
+    let mut url = url.clone();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    url.to_string()
+}
+
+/// Interactions of a cassette, with whether each was replayed already
+#[derive(Debug, Default)]
+struct Tape {
+    interactions: Vec<Interaction>,
+    played: Vec<bool>,
+}
+
+/// Cassette recording the requests of a [`CimaClient`](crate::CimaClient) or replaying
+/// them, set in [`CimaClientOptions::cassette`](crate::CimaClientOptions::cassette)
+///
+/// ```no_run
+/// use cima_rs::{Cassette, CimaClient, CimaClientOptions, RecordMode, Scrub};
+/// use std::sync::Arc;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let cassette = Cassette::open("tests/cassettes/paracetamol.json", RecordMode::Auto)?
+///     .with_scrub(Scrub::json_field("fecha"));
+/// let client = CimaClient::with_options(CimaClientOptions {
+///     cassette: Some(Arc::new(cassette)),
+///     ..Default::default()
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    replaying: bool,
+    scrubs: Vec<Scrub>,
+    tape: Mutex<Tape>,
+}
+
+impl Cassette {
+    /// Opens the cassette at `path`, reading it unless `mode` records it
+    pub fn open(path: impl AsRef<Path>, mode: RecordMode) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let replaying = match mode {
+            RecordMode::Record => false,
+            RecordMode::Replay => true,
+            RecordMode::Auto => path.exists(),
+        };
+        let mut interactions = if replaying {
+            let text = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read cassette {}", path.display()))?;
+            serde_json::from_str::<CassetteFile>(&text)
+                .with_context(|| format!("Failed to parse cassette {}", path.display()))?
+                .interactions
+        } else {
+            Vec::new()
+        };
+        // Cassettes edited by hand may list the query parameters in any order
+        for interaction in &mut interactions {
+            if let Ok(url) = Url::parse(&interaction.request.url) {
+                interaction.request.url = normalize_url(&url);
+            }
+        }
+        Ok(Self {
+            path,
+            replaying,
+            scrubs: Vec::new(),
+            tape: Mutex::new(Tape {
+                played: vec![false; interactions.len()],
+                interactions,
+            }),
+        })
+    }
+
+    /// Adds `scrub` to the replacements of the cassette
+    pub fn with_scrub(mut self, scrub: Scrub) -> Self {
+        self.scrubs.push(scrub);
+        self
+    }
+
+    /// Whether requests are answered from the cassette rather than sent
+    pub fn is_replaying(&self) -> bool {
+        self.replaying
+    }
+
+    /// Interactions recorded, or read from the file when replaying
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.tape().interactions.clone()
+    }
+
+    fn tape(&self) -> std::sync::MutexGuard<'_, Tape> {
+        self.tape.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Answers `request` from the cassette, or sends it with `send` and records it
+    pub(crate) async fn send<F, Fut>(&self, request: RequestBuilder, send: F) -> Result<Response>
+    where
+        F: FnOnce(RequestBuilder) -> Fut,
+        Fut: Future<Output = reqwest::Result<Response>>,
+    {
+        let (client, request) = request.build_split();
+        let request = request.context("Failed to build request")?;
+        let url = request.url().clone();
+        let mut recorded = RecordedRequest {
+            method: request.method().to_string(),
+            url: normalize_url(&url),
+            body: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(|bytes| String::from_utf8_lossy(bytes).into_owned()),
+        };
+        for scrub in &self.scrubs {
+            scrub.request(&mut recorded);
+        }
+
+        let response = if self.replaying {
+            self.replay(&recorded)?
+        } else {
+            let response = send(RequestBuilder::from_parts(client, request)).await?;
+            let status = response.status().as_u16();
+            let bytes = response.bytes().await?.to_vec();
+            let mut stored = RecordedResponse::new(status, bytes.clone());
+            for scrub in &self.scrubs {
+                scrub.response(&mut stored);
+            }
+            self.record(Interaction {
+                request: recorded,
+                response: stored,
+            })?;
+            RecordedResponse::new(status, bytes)
+        };
+
+        let status = response.status;
+        let response = http::Response::builder()
+            .status(status)
+            .url(url)
+            .body(response.into_bytes())
+            .context("Failed to rebuild the response")?;
+        Ok(Response::from(response))
+    }
+
+    /// Response of the first interaction matching `request` not replayed yet, or of the
+    /// last one matching it once all were
+    fn replay(&self, request: &RecordedRequest) -> Result<RecordedResponse> {
+        let mut tape = self.tape();
+        let matching: Vec<usize> = (0..tape.interactions.len())
+            .filter(|&i| tape.interactions[i].request == *request)
+            .collect();
+        let Some(&index) = matching
+            .iter()
+            .find(|&&i| !tape.played[i])
+            .or(matching.last())
+        else {
+            anyhow::bail!(
+                "No interaction of cassette {} matches {} {}, record it again",
+                self.path.display(),
+                request.method,
+                request.url
+            );
+        };
+        tape.played[index] = true;
+        Ok(tape.interactions[index].response.clone())
+    }
+
+    /// Adds `interaction` to the cassette and writes it
+    fn record(&self, interaction: Interaction) -> Result<()> {
+        let mut tape = self.tape();
+        tape.interactions.push(interaction);
+        tape.played.push(true);
+        let file = CassetteFile {
+            interactions: tape.interactions.clone(),
+        };
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&file)?)
+            .with_context(|| format!("Failed to write cassette {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_url() {
+        let url = Url::parse("http://localhost/medicamentos?nombre=a&comerc=1&atc=N02").unwrap();
+        assert_eq!(
+            normalize_url(&url),
+            "http://localhost/medicamentos?atc=N02&comerc=1&nombre=a"
+        );
+        let url = Url::parse("http://localhost/psuministro").unwrap();
+        assert_eq!(normalize_url(&url), "http://localhost/psuministro");
+    }
+
+    #[test]
+    fn test_scrub() {
+        let mut request = RecordedRequest {
+            method: "GET".to_string(),
+            url: "http://127.0.0.1:4000/registroCambios?fecha=01%2F01%2F2024&pagina=2".to_string(),
+            body: None,
+        };
+        let mut response = RecordedResponse::new(
+            200,
+            br#"{"resultados":[{"nregistro":"1","fecha":1704236400000}]}"#.to_vec(),
+        );
+        let scrubs = [
+            Scrub::query_param("fecha"),
+            Scrub::json_field("fecha"),
+            Scrub::text("http://127.0.0.1:4000", "http://cima"),
+        ];
+        for scrub in &scrubs {
+            scrub.request(&mut request);
+            scrub.response(&mut response);
+        }
+
+        assert_eq!(
+            request.url,
+            "http://cima/registroCambios?fecha=%5Bscrubbed%5D&pagina=2"
+        );
+        assert_eq!(
+            response.body.as_deref(),
+            Some(r#"{"resultados":[{"fecha":"[scrubbed]","nregistro":"1"}]}"#)
+        );
+    }
+}
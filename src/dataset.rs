@@ -0,0 +1,458 @@
+//! Local SQLite mirror of the nomenclator and of the CIMA REST API.
+//!
+//! [`Dataset::build`] loads the nomenclator dump with
+//! [`load_nomenclator_into_sqlite`] and adds, for every commercialized prescription,
+//! its registration number and the medication the API has for it. [`Dataset::sync`]
+//! then keeps those medications current from the change log (`registroCambios`),
+//! requesting only the ones that changed.
+//!
+//! Besides the nomenclator tables, the database has:
+//!
+//! | Table | Contents |
+//! |-------|----------|
+//! | `medications` | One row per `nregistro`, with the [`Medication`] as JSON |
+//! | `prescription_nregistro` | `nregistro` of each national code (`cod_nacion`) |
+//! | `medication_atc` | ATC codes of each medication |
+//! | `dataset_state` | `last_sync`, the day `sync` reads the change log from |
+
+use crate::api_client::{CimaClient, EndpointError};
+use crate::downloader::{DownloadOptions, download_and_extract_nomenclator_with_options};
+use crate::endpoints::Endpoint;
+use crate::models::{ChangeRecord, ChangeType, Medication, api_datetime};
+use crate::parser::load_nomenclator_into_sqlite;
+use anyhow::{Context, Result};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Medications requested between two commits of the database
+const BATCH_SIZE: usize = 500;
+
+/// Requests in flight at once by default, and during [`Dataset::sync`]
+const CONCURRENCY: usize = 4;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS medications (
+    nregistro TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    labtitular TEXT NOT NULL,
+    commercialized INTEGER,
+    json TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS prescription_nregistro (
+    cod_nacion TEXT PRIMARY KEY,
+    nregistro TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_prescription_nregistro_nregistro
+    ON prescription_nregistro (nregistro);
+CREATE TABLE IF NOT EXISTS medication_atc (
+    nregistro TEXT NOT NULL,
+    atc TEXT NOT NULL,
+    PRIMARY KEY (nregistro, atc)
+);
+CREATE INDEX IF NOT EXISTS idx_medication_atc_atc ON medication_atc (atc);
+CREATE TABLE IF NOT EXISTS dataset_state (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+";
+
+const DROP_SCHEMA: &str = "
+DROP TABLE IF EXISTS medications;
+DROP TABLE IF EXISTS prescription_nregistro;
+DROP TABLE IF EXISTS medication_atc;
+DROP TABLE IF EXISTS dataset_state;
+";
+
+/// Options of [`Dataset::build`]
+#[derive(Debug, Clone)]
+pub struct DatasetOptions {
+    /// Download and extract the nomenclator into the work directory first; when
+    /// false, its XML files must already be there
+    pub download: bool,
+    /// Options of the download
+    pub download_options: DownloadOptions,
+    /// Add the medications of the API to the nomenclator tables
+    pub enrich: bool,
+    /// Requests in flight at once
+    pub concurrency: usize,
+}
+
+impl Default for DatasetOptions {
+    fn default() -> Self {
+        DatasetOptions {
+            download: true,
+            download_options: DownloadOptions::default(),
+            enrich: true,
+            concurrency: CONCURRENCY,
+        }
+    }
+}
+
+/// Outcome of [`Dataset::sync`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Day the change log was read from, as "dd/mm/yyyy"
+    pub since: String,
+    /// Changes listed by the API
+    pub changes: usize,
+    /// Medications requested again and stored
+    pub updated: usize,
+    /// Medications deleted from the mirror
+    pub removed: usize,
+    /// Changed medications left out, as they are neither mirrored nor commercialized
+    pub skipped: usize,
+}
+
+/// SQLite mirror of the nomenclator and of the medications of the API
+#[derive(Debug)]
+pub struct Dataset {
+    conn: Connection,
+    path: PathBuf,
+}
+
+impl Dataset {
+    /// Builds the mirror at `db_path` from the nomenclator in `work_dir`, replacing
+    /// the tables of an earlier build.
+    ///
+    /// Each commercialized national code is requested from `medicamento?cn=`, at most
+    /// `options.concurrency` at a time; codes the API has no medication for are left
+    /// out of `prescription_nregistro`. The change log is read from the day of the
+    /// build on the first [`Dataset::sync`].
+    pub async fn build<P: AsRef<Path>, Q: AsRef<Path>>(
+        work_dir: P,
+        db_path: Q,
+        client: &CimaClient,
+        options: &DatasetOptions,
+    ) -> Result<Self> {
+        let work_dir = work_dir.as_ref().to_path_buf();
+        let path = db_path.as_ref().to_path_buf();
+        let started = today();
+
+        if options.download {
+            download_and_extract_nomenclator_with_options(&work_dir, &options.download_options)
+                .await
+                .context("Failed to download the nomenclator")?;
+        }
+
+        let mut conn = open(&path)?;
+        let mut conn = tokio::task::spawn_blocking(move || {
+            load_nomenclator_into_sqlite(&work_dir, &mut conn)
+                .with_context(|| format!("Failed to load {}", work_dir.display()))?;
+            conn.execute_batch(DROP_SCHEMA)?;
+            conn.execute_batch(SCHEMA)?;
+            Ok::<_, anyhow::Error>(conn)
+        })
+        .await??;
+
+        if options.enrich {
+            enrich(&mut conn, client, options.concurrency).await?;
+        }
+        set_state(&conn, "last_sync", &started)?;
+        Ok(Dataset { conn, path })
+    }
+
+    /// Opens the mirror built at `db_path`
+    pub fn open<P: AsRef<Path>>(db_path: P) -> Result<Self> {
+        let path = db_path.as_ref().to_path_buf();
+        let conn = open(&path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Dataset { conn, path })
+    }
+
+    /// Path of the database
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Connection to the database, for queries the helpers do not cover
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Day, as "dd/mm/yyyy", the next [`Dataset::sync`] reads the change log from
+    pub fn last_sync(&self) -> Result<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT value FROM dataset_state WHERE key = 'last_sync'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Applies the changes listed since [`Dataset::last_sync`].
+    ///
+    /// Each changed medication is requested once. It is stored when already in the
+    /// mirror or commercialized, and removed, with its national codes and ATC codes,
+    /// when deleted or no longer served by the API. `last_sync` then moves to the day
+    /// of the newest change, so changes of that day are read again next time.
+    pub async fn sync(&mut self, client: &CimaClient) -> Result<SyncReport> {
+        let since = self
+            .last_sync()?
+            .context("The dataset has no sync date, build it with Dataset::build")?;
+        let changes: Result<Vec<ChangeRecord>> = client
+            .get_change_log_pages(&since, None)
+            .map_ok(|page| stream::iter(page.results.into_iter().map(Ok)))
+            .try_flatten()
+            .try_collect()
+            .await;
+        let changes = changes.context("Failed to read the change log")?;
+        let newest = changes.iter().filter_map(ChangeRecord::datetime).max();
+
+        // Latest change of each medication
+        let mut latest: BTreeMap<&str, &ChangeRecord> = BTreeMap::new();
+        for change in &changes {
+            let entry = latest.entry(&change.nregistro).or_insert(change);
+            if change.date >= entry.date {
+                *entry = change;
+            }
+        }
+
+        let mut report = SyncReport {
+            since,
+            changes: changes.len(),
+            ..Default::default()
+        };
+        let (deleted, changed): (Vec<_>, Vec<_>) = latest
+            .into_iter()
+            .partition(|(_, change)| change.kind() == Some(ChangeType::Deleted));
+        tracing::info!(
+            changes = report.changes,
+            medications = changed.len(),
+            deleted = deleted.len(),
+            "Syncing dataset"
+        );
+
+        for batch in changed.chunks(BATCH_SIZE) {
+            let results: Vec<(&str, Option<Medication>)> = stream::iter(batch)
+                .map(|(nregistro, _)| async move {
+                    let medication = fetch_medication(client, "nregistro", nregistro).await?;
+                    Ok::<_, anyhow::Error>((*nregistro, medication))
+                })
+                .buffered(CONCURRENCY)
+                .try_collect()
+                .await?;
+            let tx = self.conn.transaction()?;
+            for (nregistro, medication) in results {
+                match medication {
+                    Some(medication)
+                        if medication.commercialized == Some(true)
+                            || is_mirrored(&tx, nregistro)? =>
+                    {
+                        store_medication(&tx, &medication)?;
+                        report.updated += 1;
+                    }
+                    Some(_) => report.skipped += 1,
+                    None => report.removed += remove_medication(&tx, nregistro)?,
+                }
+            }
+            tx.commit()?;
+        }
+
+        let tx = self.conn.transaction()?;
+        for (nregistro, _) in deleted {
+            report.removed += remove_medication(&tx, nregistro)?;
+        }
+        if let Some(newest) = newest {
+            set_state(&tx, "last_sync", &newest.format("%d/%m/%Y").to_string())?;
+        }
+        tx.commit()?;
+
+        tracing::info!(
+            updated = report.updated,
+            removed = report.removed,
+            skipped = report.skipped,
+            "Dataset synced"
+        );
+        Ok(report)
+    }
+
+    /// Medication with registration number `nregistro`
+    pub fn medication_by_nregistro(&self, nregistro: &str) -> Result<Option<Medication>> {
+        self.conn
+            .query_row(
+                "SELECT json FROM medications WHERE nregistro = ?1",
+                [nregistro],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?
+            .map(|json| parse_medication(&json))
+            .transpose()
+    }
+
+    /// Medication of the national code `cod_nacion`
+    pub fn medication_by_national_code(&self, cod_nacion: &str) -> Result<Option<Medication>> {
+        self.conn
+            .query_row(
+                "SELECT m.json FROM prescription_nregistro p
+                 JOIN medications m ON m.nregistro = p.nregistro
+                 WHERE p.cod_nacion = ?1",
+                [cod_nacion],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?
+            .map(|json| parse_medication(&json))
+            .transpose()
+    }
+
+    /// Medications with an ATC code starting with `prefix`, by registration number
+    pub fn medications_by_atc_prefix(&self, prefix: &str) -> Result<Vec<Medication>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT json FROM medications WHERE nregistro IN (
+                 SELECT nregistro FROM medication_atc
+                 WHERE substr(atc, 1, length(?1)) = ?1
+             )
+             ORDER BY nregistro",
+        )?;
+        let rows = stmt.query_map([prefix], |row| row.get::<_, String>(0))?;
+        rows.map(|json| parse_medication(&json?)).collect()
+    }
+}
+
+/// Opens the database at `path`, creating it if missing
+fn open(path: &Path) -> Result<Connection> {
+    Connection::open(path).with_context(|| format!("Failed to open {}", path.display()))
+}
+
+/// Today in the time zone of the API, as "dd/mm/yyyy"
+fn today() -> String {
+    let millis = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64);
+    api_datetime(millis)
+        .unwrap_or_default()
+        .format("%d/%m/%Y")
+        .to_string()
+}
+
+/// Requests the medication of every commercialized prescription and stores it
+async fn enrich(conn: &mut Connection, client: &CimaClient, concurrency: usize) -> Result<()> {
+    let codes: Vec<String> = conn
+        .prepare("SELECT cod_nacion FROM prescriptions WHERE sw_comercializado = 1 ORDER BY 1")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    tracing::info!(codes = codes.len(), "Requesting medications");
+
+    let mut unresolved = 0;
+    for batch in codes.chunks(BATCH_SIZE) {
+        let results: Vec<(&String, Option<Medication>)> = stream::iter(batch)
+            .map(|code| async move {
+                Ok::<_, anyhow::Error>((code, fetch_medication(client, "cn", code).await?))
+            })
+            .buffered(concurrency.max(1))
+            .try_collect()
+            .await?;
+        let tx = conn.transaction()?;
+        for (code, medication) in results {
+            match medication {
+                Some(medication) => {
+                    store_medication(&tx, &medication)?;
+                    tx.execute(
+                        "INSERT OR REPLACE INTO prescription_nregistro (cod_nacion, nregistro)
+                         VALUES (?1, ?2)",
+                        params![code, medication.nregistro],
+                    )?;
+                }
+                None => unresolved += 1,
+            }
+        }
+        tx.commit()?;
+    }
+    tracing::info!(
+        resolved = codes.len() - unresolved,
+        unresolved,
+        "Medications stored"
+    );
+    Ok(())
+}
+
+/// Medication identified by the `medicamento` parameter `key`, `None` if the API has
+/// none
+async fn fetch_medication(
+    client: &CimaClient,
+    key: &'static str,
+    value: &str,
+) -> Result<Option<Medication>> {
+    let params = [(key, value.to_string())];
+    client
+        .request_optional(&Endpoint::Medication, &params[..])
+        .await
+        .with_context(|| EndpointError::new("get_medication", params.to_vec()))
+}
+
+fn parse_medication(json: &str) -> Result<Medication> {
+    serde_json::from_str(json).context("Failed to parse a stored medication")
+}
+
+fn is_mirrored(conn: &Connection, nregistro: &str) -> Result<bool> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM medications WHERE nregistro = ?1",
+            [nregistro],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+/// Inserts or replaces `medication`, its ATC codes and the national codes of its
+/// presentations
+fn store_medication(conn: &Connection, medication: &Medication) -> Result<()> {
+    let nregistro = &medication.nregistro;
+    conn.execute(
+        "INSERT OR REPLACE INTO medications (nregistro, name, labtitular, commercialized, json)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            nregistro,
+            medication.name,
+            medication.labtitular,
+            medication.commercialized,
+            serde_json::to_string(medication)?,
+        ],
+    )
+    .with_context(|| format!("Failed to store medication {}", nregistro))?;
+    conn.execute(
+        "DELETE FROM medication_atc WHERE nregistro = ?1",
+        [nregistro],
+    )?;
+    for atc in &medication.atcs {
+        conn.execute(
+            "INSERT OR IGNORE INTO medication_atc (nregistro, atc) VALUES (?1, ?2)",
+            params![nregistro, atc.code],
+        )?;
+    }
+    for presentation in &medication.presentations {
+        conn.execute(
+            "INSERT OR REPLACE INTO prescription_nregistro (cod_nacion, nregistro)
+             VALUES (?1, ?2)",
+            params![presentation.cn, nregistro],
+        )?;
+    }
+    Ok(())
+}
+
+/// Deletes the medication `nregistro` and its rows, returning 1 if it was mirrored
+fn remove_medication(conn: &Connection, nregistro: &str) -> Result<usize> {
+    let removed = conn.execute("DELETE FROM medications WHERE nregistro = ?1", [nregistro])?;
+    conn.execute(
+        "DELETE FROM medication_atc WHERE nregistro = ?1",
+        [nregistro],
+    )?;
+    conn.execute(
+        "DELETE FROM prescription_nregistro WHERE nregistro = ?1",
+        [nregistro],
+    )?;
+    Ok(removed)
+}
+
+fn set_state(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO dataset_state (key, value) VALUES (?1, ?2)",
+        params![key, value],
+    )?;
+    Ok(())
+}
@@ -1,42 +1,1461 @@
+use crate::USER_AGENT;
+use crate::parser::{DictionaryKind, PRESCRIPTION_XML};
 use anyhow::Context;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
-use std::io::{self, Cursor};
-use std::path::PathBuf;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tracing::instrument;
 use zip::ZipArchive;
 
-const NOMENCLATOR_DUMP_URL: &str = "https://listadomedicamentos.aemps.gob.es/prescripcion.zip";
+/// AEMPS Nomenclator dump, the default [`DownloadOptions::url`]
+pub const NOMENCLATOR_DUMP_URL: &str = "https://listadomedicamentos.aemps.gob.es/prescripcion.zip";
+
+/// Incremental prescription files, `{date}` being the publication date as `YYYYMMDD`
+const NOMENCLATOR_DELTA_URL: &str =
+    "https://listadomedicamentos.aemps.gob.es/prescripcion_incremental_{date}.zip";
+
+/// URL of the incremental prescription file published on `date` (`YYYYMMDD`).
+pub fn nomenclator_delta_url(date: &str) -> anyhow::Result<String> {
+    if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+        anyhow::bail!("Expected a YYYYMMDD date, got '{}'", date);
+    }
+    Ok(NOMENCLATOR_DELTA_URL.replace("{date}", date))
+}
+
+/// File of the target directory recording the archive extracted into it
+pub const DOWNLOAD_STATE_JSON: &str = "download_state.json";
+
+/// File of the target directory listing the size and SHA-256 of every extracted file
+pub const DOWNLOAD_MANIFEST_JSON: &str = "download_manifest.json";
+
+/// Files the Nomenclator dump must hold for an existing extraction to be reused.
+const NOMENCLATOR_KEY_FILES: &[&str] = &[PRESCRIPTION_XML];
+
+/// Progress of a running download, passed to [`DownloadOptions::progress`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadProgress {
+    /// Part of the archive was received
+    Downloading {
+        /// Bytes received so far
+        bytes: u64,
+        /// Size of the archive, from `Content-Length` when sent
+        total: Option<u64>,
+    },
+    /// A file of the archive was extracted
+    Extracting {
+        /// Files extracted so far, `total` in the final update
+        files: usize,
+        /// Files in the archive
+        total: usize,
+        /// Name of the extracted file
+        name: String,
+    },
+}
+
+/// Callback receiving [`DownloadProgress`] updates
+pub type DownloadProgressCallback = Arc<dyn Fn(DownloadProgress) + Send + Sync>;
+
+/// Names of the XML files parsed by the parsers of `dictionaries`, and of
+/// Prescripcion.xml if `prescriptions` is set, for [`DownloadOptions::include`].
+pub fn nomenclator_xml_files(dictionaries: &[DictionaryKind], prescriptions: bool) -> Vec<String> {
+    let prescription = prescriptions.then_some(PRESCRIPTION_XML);
+    dictionaries
+        .iter()
+        .map(|kind| kind.default_xml_filename())
+        .chain(prescription)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Time allowed to connect to the server
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time allowed without receiving anything before a download is considered stalled
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time allowed for a whole request, body included
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Builder of the default [`DownloadOptions::client`], with the crate's user agent and
+/// timeouts suited to downloading archives of hundreds of MB. Add a proxy or other
+/// settings and pass the result in [`DownloadOptions::client`].
+pub fn download_client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .read_timeout(READ_TIMEOUT)
+        .timeout(DOWNLOAD_TIMEOUT)
+}
+
+/// Options of [`download_and_extract_nomenclator_with_options`].
+#[derive(Clone)]
+pub struct DownloadOptions {
+    /// Download and extract again even if the target directory already holds the dump
+    /// and the remote archive is unchanged. Existing files are overwritten, other files
+    /// in the directory are left alone.
+    pub force: bool,
+    /// ZIP archive to download, [`NOMENCLATOR_DUMP_URL`] by default
+    pub url: String,
+    /// Copies of the archive tried in order when downloading from `url` fails
+    pub mirrors: Vec<String>,
+    /// Names or `*`/`?` patterns of the archive entries to extract, every entry by
+    /// default. See [`nomenclator_xml_files`] to select files by parser.
+    pub include: Option<Vec<String>>,
+    /// Keep the downloaded archive in the target directory after extracting it
+    pub keep_zip: bool,
+    /// Called as chunks of the archive arrive and after each extracted file
+    pub progress: Option<DownloadProgressCallback>,
+    /// Times a download failing with a connection error, a server error or a cut body is
+    /// retried
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for every further one
+    pub backoff: Duration,
+    /// Continue an interrupted download with a range request, in a retry or a later
+    /// run, instead of starting over
+    pub resume: bool,
+    /// Directory keeping the downloaded archives across runs, named after their `ETag`
+    /// or `Last-Modified` header. An edition found there is extracted without
+    /// downloading it again. See [`prune_zip_cache`].
+    pub zip_cache_dir: Option<PathBuf>,
+    /// Client the archive is downloaded with, for instance one going through a proxy.
+    /// A client built from [`download_client_builder`] by default.
+    pub client: Option<reqwest::Client>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            force: false,
+            url: NOMENCLATOR_DUMP_URL.to_string(),
+            mirrors: Vec::new(),
+            include: None,
+            keep_zip: false,
+            progress: None,
+            max_retries: 3,
+            backoff: Duration::from_secs(1),
+            resume: true,
+            zip_cache_dir: None,
+            client: None,
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// [`DownloadOptions::client`], or the default one built on first use.
+    fn client(&self) -> anyhow::Result<reqwest::Client> {
+        if let Some(client) = &self.client {
+            return Ok(client.clone());
+        }
+        static DEFAULT: OnceLock<reqwest::Client> = OnceLock::new();
+        if let Some(client) = DEFAULT.get() {
+            return Ok(client.clone());
+        }
+        let client = download_client_builder()
+            .build()
+            .context("Failed to create HTTP client")?;
+        Ok(DEFAULT.get_or_init(|| client).clone())
+    }
+
+    /// `url` followed by the mirrors.
+    fn urls<'a>(&'a self, url: &'a str) -> impl Iterator<Item = &'a str> {
+        std::iter::once(url).chain(self.mirrors.iter().map(String::as_str))
+    }
+}
+
+impl fmt::Debug for DownloadOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DownloadOptions")
+            .field("force", &self.force)
+            .field("url", &self.url)
+            .field("mirrors", &self.mirrors)
+            .field("include", &self.include)
+            .field("keep_zip", &self.keep_zip)
+            .field(
+                "progress",
+                &self.progress.as_ref().map(|_| "Fn(DownloadProgress)"),
+            )
+            .field("max_retries", &self.max_retries)
+            .field("backoff", &self.backoff)
+            .field("resume", &self.resume)
+            .field("zip_cache_dir", &self.zip_cache_dir)
+            .field("client", &self.client)
+            .finish()
+    }
+}
+
+/// Files in the target directory after
+/// [`download_and_extract_nomenclator_with_options`], and how they got there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractionReport {
+    /// Directory holding the extracted files
+    pub target_dir: PathBuf,
+    /// Extracted files sorted by name, as listed in [`DOWNLOAD_MANIFEST_JSON`]
+    pub files: Vec<ExtractedFile>,
+    /// Whether the archive was downloaded
+    pub outcome: DownloadOutcome,
+}
+
+impl ExtractionReport {
+    /// Report of the files of `manifest` in `target_dir`.
+    fn new(target_dir: &Path, manifest: &DownloadManifest, outcome: DownloadOutcome) -> Self {
+        let files = manifest
+            .files
+            .iter()
+            .map(|(name, entry)| ExtractedFile {
+                name: name.clone(),
+                size: entry.size,
+                modified: fs::metadata(target_dir.join(name))
+                    .and_then(|metadata| metadata.modified())
+                    .ok(),
+            })
+            .collect();
+        Self {
+            target_dir: target_dir.to_path_buf(),
+            files,
+            outcome,
+        }
+    }
+
+    /// Whether the download was skipped because the files were up to date.
+    pub fn skipped(&self) -> bool {
+        self.outcome == DownloadOutcome::UpToDate
+    }
+
+    /// Size of the downloaded archive, 0 when the download was skipped.
+    pub fn downloaded_bytes(&self) -> u64 {
+        match self.outcome {
+            DownloadOutcome::UpToDate => 0,
+            DownloadOutcome::Downloaded { bytes, .. } => bytes,
+        }
+    }
+
+    /// Total size of the extracted files.
+    pub fn extracted_bytes(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+}
+
+/// One file of an [`ExtractionReport`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedFile {
+    /// Name in the archive, relative to the target directory
+    pub name: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Modification time of the extracted file, when the file system records it
+    pub modified: Option<SystemTime>,
+}
+
+/// What [`download_and_extract_nomenclator_with_options`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadOutcome {
+    /// The extracted dump matches the remote archive, nothing was downloaded
+    UpToDate,
+    /// The archive was downloaded and extracted
+    Downloaded {
+        /// URL that served the archive, [`DownloadOptions::url`] or one of the mirrors
+        url: String,
+        /// Size of the archive
+        bytes: u64,
+        /// `Last-Modified` header of the archive, if sent
+        last_modified: Option<String>,
+        /// Attempts that failed before the download succeeded
+        retries: u32,
+        /// Whether the archive was taken from [`DownloadOptions::zip_cache_dir`] instead
+        /// of downloaded
+        cached: bool,
+    },
+}
+
+/// Validators of the archive last extracted into a directory, to tell whether the
+/// remote one changed since.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct DownloadState {
+    url: String,
+    last_modified: Option<String>,
+    #[serde(default)]
+    etag: Option<String>,
+    content_length: Option<u64>,
+}
+
+impl DownloadState {
+    /// Reads the state of `target_dir`, `None` if there is none yet.
+    fn load(target_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = target_dir.join(DOWNLOAD_STATE_JSON);
+        match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .map(Some)
+                .with_context(|| format!("Failed to parse {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Writes the state to `target_dir`.
+    fn save(&self, target_dir: &Path) -> anyhow::Result<()> {
+        let path = target_dir.join(DOWNLOAD_STATE_JSON);
+        let content = serde_json::to_vec_pretty(self)?;
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Validators sent with `response`.
+    fn from_response(url: &str, response: &reqwest::Response) -> Self {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+        };
+        Self {
+            url: url.to_string(),
+            last_modified: header(reqwest::header::LAST_MODIFIED).map(str::to_string),
+            etag: header(reqwest::header::ETAG).map(str::to_string),
+            content_length: header(reqwest::header::CONTENT_LENGTH)
+                .and_then(|length| length.parse().ok()),
+        }
+    }
+
+    /// Whether `remote` is known to be the archive this state was saved for. Without a
+    /// `Last-Modified` header it never is.
+    fn matches(&self, remote: &DownloadState) -> bool {
+        remote.last_modified.is_some()
+            && self.url == remote.url
+            && self.last_modified == remote.last_modified
+            && (remote.etag.is_none() || self.etag == remote.etag)
+            && (remote.content_length.is_none() || self.content_length == remote.content_length)
+    }
+}
+
+/// Files extracted into a directory, to tell whether they were modified since
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct DownloadManifest {
+    /// [`DownloadOptions::include`] of the extraction
+    #[serde(default)]
+    include: Option<Vec<String>>,
+    /// Entries by file name in the archive
+    files: BTreeMap<String, ManifestEntry>,
+}
+
+/// One extracted file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ManifestEntry {
+    size: u64,
+    sha256: String,
+}
+
+impl DownloadManifest {
+    /// Reads the manifest of `target_dir`, `None` if there is none yet.
+    fn load(target_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = target_dir.join(DOWNLOAD_MANIFEST_JSON);
+        match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .map(Some)
+                .with_context(|| format!("Failed to parse {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Writes the manifest to `target_dir`.
+    fn save(&self, target_dir: &Path) -> anyhow::Result<()> {
+        let path = target_dir.join(DOWNLOAD_MANIFEST_JSON);
+        let content = serde_json::to_vec_pretty(self)?;
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Whether every file is still in `target_dir` with its recorded size and checksum.
+    fn verify(&self, target_dir: &Path) -> bool {
+        self.files.iter().all(|(name, entry)| {
+            let path = target_dir.join(name);
+            let unchanged = fs::metadata(&path).is_ok_and(|metadata| metadata.len() == entry.size)
+                && sha256(&path).is_ok_and(|actual| actual == entry.sha256);
+            if !unchanged {
+                tracing::warn!(file = %name, "Extracted file missing or modified");
+            }
+            unchanged
+        })
+    }
+}
+
+/// Writer computing the SHA-256 of what goes through it
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Hex encoded SHA-256 of everything written.
+    fn finish(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hex encoded SHA-256 of the file at `path`.
+fn sha256(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
 /// Downloads and extracts the Nomenclator dump into the specified directory.
-pub async fn download_and_extract_nomenclator<P: AsRef<std::path::Path>>(
+///
+/// If the directory already holds Prescripcion.xml, a HEAD request compares the
+/// `Last-Modified` and `Content-Length` headers of the remote archive with those of the
+/// one extracted last, recorded in [`DOWNLOAD_STATE_JSON`], and the download is skipped
+/// when they match.
+pub async fn download_and_extract_nomenclator<P: AsRef<Path>>(
     target_dir: P,
 ) -> anyhow::Result<PathBuf> {
-    let target_dir = target_dir.as_ref().to_path_buf();
+    let report =
+        download_and_extract_nomenclator_with_options(target_dir, &DownloadOptions::default())
+            .await?;
+    Ok(report.target_dir)
+}
 
-    if target_dir.exists()
-        && fs::read_dir(&target_dir)
-            .map(|mut d| d.next().is_some())
-            .unwrap_or(false)
+/// Same as [`download_and_extract_nomenclator`] with the given options, reporting the
+/// extracted files and whether they were downloaded.
+pub async fn download_and_extract_nomenclator_with_options<P: AsRef<Path>>(
+    target_dir: P,
+    options: &DownloadOptions,
+) -> anyhow::Result<ExtractionReport> {
+    download_archive(
+        &options.url,
+        target_dir.as_ref(),
+        options,
+        NOMENCLATOR_KEY_FILES,
+    )
+    .await
+}
+
+/// Downloads the ZIP archive at `url`, or from one of [`DownloadOptions::mirrors`], and
+/// extracts it into `target_dir` with the same checks, retries and reporting as
+/// [`download_and_extract_nomenclator_with_options`]. [`DownloadOptions::url`] is not
+/// used.
+///
+/// The download is skipped while the files extracted last still match
+/// [`DOWNLOAD_MANIFEST_JSON`] and the remote archive is unchanged.
+pub async fn download_and_extract<P: AsRef<Path>>(
+    url: &str,
+    target_dir: P,
+    options: &DownloadOptions,
+) -> anyhow::Result<ExtractionReport> {
+    download_archive(url, target_dir.as_ref(), options, &[]).await
+}
+
+/// Downloads and extracts the archive at `url`, skipping the download when `target_dir`
+/// holds `key_files` and everything else extracted last is unchanged.
+#[instrument(name = "download", skip_all, fields(url = url, target_dir = %target_dir.display()))]
+async fn download_archive(
+    url: &str,
+    target_dir: &Path,
+    options: &DownloadOptions,
+    key_files: &'static [&'static str],
+) -> anyhow::Result<ExtractionReport> {
+    remove_stale_staging_dirs(target_dir)?;
+    if !options.force
+        && is_extracted(target_dir, key_files, options.include.as_deref()).await?
+        && let Some(state) = DownloadState::load(target_dir)?
     {
-        return Ok(target_dir);
+        // Validators differ between hosts, so ask the one that served the archive
+        let url = options
+            .urls(url)
+            .find(|url| *url == state.url)
+            .unwrap_or(url);
+        match remote_state(&options.client()?, url).await {
+            Ok(remote) if state.matches(&remote) => {
+                tracing::info!(target_dir = ?target_dir, url, "Archive up to date, skipping download");
+                let manifest = DownloadManifest::load(target_dir)?.unwrap_or_default();
+                return Ok(ExtractionReport::new(
+                    target_dir,
+                    &manifest,
+                    DownloadOutcome::UpToDate,
+                ));
+            }
+            Ok(remote) => tracing::info!(
+                previous = ?state.last_modified,
+                remote = ?remote.last_modified,
+                "Remote archive changed"
+            ),
+            Err(error) => tracing::warn!("{:#}, downloading again", error),
+        }
     }
 
-    fs::create_dir_all(&target_dir).context("Failed to create target directory")?;
+    let mut failures = Vec::new();
+    for url in options.urls(url) {
+        match fetch_and_extract(url, target_dir, options).await {
+            Ok(extracted) => {
+                extracted.manifest.save(target_dir)?;
+                extracted.state.save(target_dir)?;
+                let outcome = DownloadOutcome::Downloaded {
+                    url: extracted.state.url,
+                    bytes: extracted.bytes,
+                    last_modified: extracted.state.last_modified,
+                    retries: extracted.retries,
+                    cached: extracted.cached,
+                };
+                return Ok(ExtractionReport::new(
+                    target_dir,
+                    &extracted.manifest,
+                    outcome,
+                ));
+            }
+            Err(error) => {
+                tracing::warn!(url, "Download failed: {:#}", error);
+                failures.push(error);
+            }
+        }
+    }
+    let last = failures.pop().context("No download URL")?;
+    Err(last.context(format!(
+        "Failed to download {} from {} URL(s)",
+        archive_file_name(url),
+        failures.len() + 1
+    )))
+}
 
-    let response = reqwest::get(NOMENCLATOR_DUMP_URL)
+/// Validators of the archive at `url`, from a HEAD request.
+async fn remote_state(client: &reqwest::Client, url: &str) -> anyhow::Result<DownloadState> {
+    let response = client
+        .head(url)
+        .send()
         .await
-        .context("Failed to download nomenclator dump")?;
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to check {}", url))?;
+    Ok(DownloadState::from_response(url, &response))
+}
+
+/// Whether `dir` holds every one of `key_files` selected by `include`, was extracted
+/// with the same `include`, and every extracted file still matches its
+/// [`DOWNLOAD_MANIFEST_JSON`] entry.
+#[instrument(name = "verify", skip_all, fields(dir = %dir.display()))]
+async fn is_extracted(
+    dir: &Path,
+    key_files: &'static [&'static str],
+    include: Option<&[String]>,
+) -> anyhow::Result<bool> {
+    let dir = dir.to_path_buf();
+    let include = include.map(<[String]>::to_vec);
+    tokio::task::spawn_blocking(move || {
+        let has_key_files = key_files
+            .iter()
+            .filter(|name| is_included(include.as_deref(), name))
+            .all(|name| dir.join(name).is_file());
+        if !has_key_files {
+            return Ok(false);
+        }
+        Ok(DownloadManifest::load(&dir)?
+            .is_some_and(|manifest| manifest.include == include && manifest.verify(&dir)))
+    })
+    .await
+    .map_err(|error| task_error("Verification", error))?
+}
+
+/// Error of a blocking task that did not complete, with the message it panicked with.
+fn task_error(task: &str, error: tokio::task::JoinError) -> anyhow::Error {
+    match error.try_into_panic() {
+        Ok(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("no message");
+            anyhow::anyhow!("{} task panicked: {}", task, message)
+        }
+        Err(error) => anyhow::Error::new(error).context(format!("{} task failed", task)),
+    }
+}
+
+/// Downloads and extracts the incremental prescription file published on `date`
+/// (`YYYYMMDD`) into the specified directory.
+///
+/// The extracted XML is read with
+/// [`parse_prescription_delta_xml`](crate::parser::parse_prescription_delta_xml).
+pub async fn download_and_extract_nomenclator_delta<P: AsRef<Path>>(
+    date: &str,
+    target_dir: P,
+) -> anyhow::Result<PathBuf> {
+    let url = nomenclator_delta_url(date)?;
+    let target_dir = target_dir.as_ref().to_path_buf();
+    fetch_and_extract(&url, &target_dir, &DownloadOptions::default()).await?;
+    Ok(target_dir)
+}
+
+/// Archive downloaded and extracted by [`fetch_and_extract`]
+struct Extracted {
+    state: DownloadState,
+    bytes: u64,
+    retries: u32,
+    cached: bool,
+    manifest: DownloadManifest,
+}
+
+/// Archive ready to be extracted
+struct Archive {
+    path: PathBuf,
+    state: DownloadState,
+    bytes: u64,
+    retries: u32,
+    /// Whether it was found in the ZIP cache
+    cached: bool,
+    /// Whether it is left in place after the extraction
+    keep: bool,
+}
+
+/// Downloads the ZIP archive at `url` into `target_dir` and extracts it there, with
+/// the retries, progress, `keep_zip` and `zip_cache_dir` of `options`.
+///
+/// The archive is streamed to a file named after the last segment of `url`, removed
+/// after a successful extraction unless `keep_zip` is set, or taken from and stored in
+/// the ZIP cache when there is one.
+async fn fetch_and_extract(
+    url: &str,
+    target_dir: &Path,
+    options: &DownloadOptions,
+) -> anyhow::Result<Extracted> {
+    fs::create_dir_all(target_dir).context("Failed to create target directory")?;
+
+    let archive = match &options.zip_cache_dir {
+        Some(cache_dir) => cached_archive(url, cache_dir, options).await?,
+        None => {
+            let path = target_dir.join(archive_file_name(url));
+            let (state, bytes, retries) = download_with_retries(url, &path, options).await?;
+            Archive {
+                path,
+                state,
+                bytes,
+                retries,
+                cached: false,
+                keep: options.keep_zip,
+            }
+        }
+    };
+
+    let target = target_dir.to_path_buf();
+    let zip_path = archive.path.clone();
+    let include = options.include.clone();
+    let progress = options.progress.clone();
+    // The blocking task outlives this future when it is dropped, so tell it to stop
+    let cancel = CancelOnDrop::default();
+    let cancelled = Arc::clone(&cancel.0);
+    let span = tracing::info_span!(
+        "extract",
+        zip = %archive.path.display(),
+        files = tracing::field::Empty
+    );
+    let extract_span = span.clone();
+    let extracted = tokio::task::spawn_blocking(move || {
+        let _entered = extract_span.enter();
+        extract_staged(
+            &zip_path,
+            &target,
+            include.as_deref(),
+            progress.as_ref(),
+            &cancelled,
+        )
+    })
+    .await
+    .map_err(|error| task_error("Extraction", error))?;
+    let manifest = match extracted {
+        Ok(manifest) => {
+            span.record("files", manifest.files.len());
+            manifest
+        }
+        Err(error) => {
+            if options.zip_cache_dir.is_some() && archive.keep {
+                // Do not extract the same broken archive in every run
+                tracing::warn!(path = ?archive.path, "Removing the archive from the ZIP cache");
+                fs::remove_file(&archive.path).ok();
+            }
+            return Err(error);
+        }
+    };
+
+    if !archive.keep {
+        fs::remove_file(&archive.path)
+            .with_context(|| format!("Failed to remove {}", archive.path.display()))?;
+    }
+    Ok(Extracted {
+        state: archive.state,
+        bytes: archive.bytes,
+        retries: archive.retries,
+        cached: archive.cached,
+        manifest,
+    })
+}
+
+/// Archive at `url` from `cache_dir`, downloaded there unless a HEAD request finds that
+/// the cache already holds the edition the server has.
+async fn cached_archive(
+    url: &str,
+    cache_dir: &Path,
+    options: &DownloadOptions,
+) -> anyhow::Result<Archive> {
+    fs::create_dir_all(cache_dir)
+        .with_context(|| format!("Failed to create {}", cache_dir.display()))?;
+
+    match remote_state(&options.client()?, url).await {
+        Ok(remote) => {
+            if let Some(name) = cached_file_name(url, &remote) {
+                let path = cache_dir.join(name);
+                if let Ok(metadata) = fs::metadata(&path)
+                    && metadata.is_file()
+                    && remote
+                        .content_length
+                        .is_none_or(|length| length == metadata.len())
+                {
+                    tracing::info!(path = ?path, "Using the cached archive");
+                    let bytes = metadata.len();
+                    return Ok(Archive {
+                        path,
+                        state: DownloadState {
+                            content_length: Some(bytes),
+                            ..remote
+                        },
+                        bytes,
+                        retries: 0,
+                        cached: true,
+                        keep: true,
+                    });
+                }
+            }
+        }
+        Err(error) => tracing::warn!("{:#}, not looking up the ZIP cache", error),
+    }
+
+    let path = cache_dir.join(archive_file_name(url));
+    let (state, bytes, retries) = download_with_retries(url, &path, options).await?;
+    let Some(name) = cached_file_name(url, &state) else {
+        // Without validators the archive could never be reused
+        return Ok(Archive {
+            path,
+            state,
+            bytes,
+            retries,
+            cached: false,
+            keep: options.keep_zip,
+        });
+    };
+    let cached = cache_dir.join(name);
+    fs::rename(&path, &cached).with_context(|| format!("Failed to rename {}", path.display()))?;
+    Ok(Archive {
+        path: cached,
+        state,
+        bytes,
+        retries,
+        cached: false,
+        keep: true,
+    })
+}
+
+/// Name of the archive at `url` in the ZIP cache: the name of the downloaded file
+/// followed by a hash of its `ETag`, or of its `Last-Modified` header without one.
+/// `None` if the server sent neither.
+fn cached_file_name(url: &str, state: &DownloadState) -> Option<String> {
+    let validator = state.etag.as_deref().or(state.last_modified.as_deref())?;
+    let name = archive_file_name(url);
+    let stem = name.strip_suffix(".zip").unwrap_or(name);
+    let hash = format!("{:x}", Sha256::digest(validator.as_bytes()));
+    Some(format!("{stem}-{}.zip", &hash[..CACHE_TAG_LEN]))
+}
+
+/// Hex digits of the hash naming an edition in the ZIP cache
+const CACHE_TAG_LEN: usize = 16;
+
+/// Name of the downloaded file a file of the ZIP cache is an edition of, without its
+/// extension. `None` for files that are not cached archives.
+fn cached_archive_stem(file_name: &str) -> Option<&str> {
+    let (stem, tag) = file_name.strip_suffix(".zip")?.rsplit_once('-')?;
+    (tag.len() == CACHE_TAG_LEN && tag.bytes().all(|byte| byte.is_ascii_hexdigit())).then_some(stem)
+}
+
+/// Deletes all but the `keep` most recently downloaded editions of every archive in
+/// `dir`, a [`DownloadOptions::zip_cache_dir`]. Returns the deleted files.
+pub fn prune_zip_cache(dir: impl AsRef<Path>, keep: usize) -> anyhow::Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    let mut editions: BTreeMap<String, Vec<(SystemTime, PathBuf)>> = BTreeMap::new();
+    let entries = fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {}", dir.display()))?;
+        let file_name = entry.file_name();
+        let Some(stem) = file_name.to_str().and_then(cached_archive_stem) else {
+            continue;
+        };
+        let path = entry.path();
+        let modified = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        editions
+            .entry(stem.to_string())
+            .or_default()
+            .push((modified, path));
+    }
+
+    let mut removed = Vec::new();
+    for mut files in editions.into_values() {
+        files.sort_by(|a, b| b.cmp(a));
+        for (_, path) in files.into_iter().skip(keep) {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            tracing::info!(path = ?path, "Pruned cached archive");
+            removed.push(path);
+        }
+    }
+    removed.sort();
+    Ok(removed)
+}
 
-    let content = response
-        .bytes()
+/// Name of the file the archive at `url` is downloaded to.
+fn archive_file_name(url: &str) -> &str {
+    url.split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("download.zip")
+}
+
+/// Body shorter than its `Content-Length`
+#[derive(Debug)]
+struct Truncated {
+    received: u64,
+    expected: u64,
+}
+
+impl fmt::Display for Truncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Truncated download: received {} of {} bytes",
+            self.received, self.expected
+        )
+    }
+}
+
+impl std::error::Error for Truncated {}
+
+/// Whether `error` may go away on a retry: a connection failure, a server error, a body
+/// cut short or rate limiting. Client errors and local I/O errors are not.
+fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            match error.status() {
+                Some(status) => {
+                    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                }
+                None => {
+                    error.is_connect()
+                        || error.is_timeout()
+                        || error.is_request()
+                        || error.is_body()
+                        // Bodies cut short surface as decode errors of the byte stream
+                        || error.is_decode()
+                }
+            }
+        } else {
+            cause.is::<Truncated>()
+        }
+    })
+}
+
+/// Validators of a partly downloaded archive, saved next to it so that a later run can
+/// resume the transfer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PartialDownload {
+    state: DownloadState,
+    /// Whether the server advertised `Accept-Ranges: bytes`
+    accept_ranges: bool,
+}
+
+impl PartialDownload {
+    /// Reads the validators saved at `path`, `None` if there are none or they are
+    /// unreadable.
+    fn load(path: &Path) -> Option<Self> {
+        let content = fs::read(path).ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    /// Writes the validators to `path`.
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Value of the `If-Range` header guarding a resumed transfer, `None` if the
+    /// archive cannot be resumed.
+    fn if_range(&self) -> Option<&str> {
+        if !self.accept_ranges {
+            return None;
+        }
+        self.state
+            .etag
+            .as_deref()
+            .or(self.state.last_modified.as_deref())
+    }
+}
+
+/// Progress of the download of an archive across attempts and runs
+struct Transfer {
+    /// Partly downloaded archive
+    part_path: PathBuf,
+    /// File holding the [`PartialDownload`] of `part_path`
+    state_path: PathBuf,
+    /// Validators of the full archive, from the last complete response
+    partial: Option<PartialDownload>,
+    /// Bytes written to `part_path` so far
+    written: u64,
+    /// Whether `part_path` holds bytes of an earlier attempt or run
+    resumed: bool,
+}
+
+impl Transfer {
+    /// Transfer of `url` to `path`, picking up the part left by a previous run when
+    /// `resume` is set and it was downloaded from the same URL.
+    fn new(url: &str, path: &Path, resume: bool) -> Self {
+        let mut part_path = path.as_os_str().to_owned();
+        part_path.push(".part");
+        let part_path = PathBuf::from(part_path);
+        let mut state_path = part_path.as_os_str().to_owned();
+        state_path.push(".json");
+        let state_path = PathBuf::from(state_path);
+
+        let partial =
+            PartialDownload::load(&state_path).filter(|partial| resume && partial.state.url == url);
+        let written = match &partial {
+            Some(_) => fs::metadata(&part_path).map_or(0, |metadata| metadata.len()),
+            None => 0,
+        };
+        Self {
+            part_path,
+            state_path,
+            partial,
+            written,
+            resumed: false,
+        }
+    }
+
+    /// Discards the part downloaded so far, so that the next attempt starts over.
+    fn restart(&mut self) -> anyhow::Result<()> {
+        for path in [&self.part_path, &self.state_path] {
+            if let Err(e) = fs::remove_file(path)
+                && e.kind() != io::ErrorKind::NotFound
+            {
+                return Err(e).with_context(|| format!("Failed to remove {}", path.display()));
+            }
+        }
+        self.partial = None;
+        self.written = 0;
+        self.resumed = false;
+        Ok(())
+    }
+}
+
+/// Downloads `url` to `path`, retrying transient failures up to
+/// [`DownloadOptions::max_retries`] times. Returns the validators and size of the
+/// archive and the number of retries.
+///
+/// The archive is written to `path` with a `.part` suffix until complete. With
+/// [`DownloadOptions::resume`] set, a retry or a later run continues it with a range
+/// request when the server accepts ranges and the archive has an `ETag` or
+/// `Last-Modified` header. An archive completed from an earlier attempt or run is
+/// checked against the CRC-32 of its entries and downloaded again if any differs.
+#[instrument(
+    name = "fetch",
+    skip_all,
+    fields(url = url, bytes = tracing::field::Empty, retries = tracing::field::Empty)
+)]
+async fn download_with_retries(
+    url: &str,
+    path: &Path,
+    options: &DownloadOptions,
+) -> anyhow::Result<(DownloadState, u64, u32)> {
+    let client = options.client()?;
+    let mut transfer = Transfer::new(url, path, options.resume);
+    let mut retries = 0;
+    let state = loop {
+        match download_attempt(&client, url, &mut transfer, options).await {
+            Ok(_) if transfer.resumed && !is_valid_zip(&transfer.part_path).await => {
+                tracing::warn!("Resumed archive is corrupt, downloading it again");
+                transfer.restart()?;
+            }
+            Ok(state) => break state,
+            Err(error) if retries < options.max_retries && is_transient(&error) => {
+                let delay = options.backoff.saturating_mul(2u32.saturating_pow(retries));
+                retries += 1;
+                tracing::warn!(
+                    attempt = retries,
+                    max_retries = options.max_retries,
+                    written = transfer.written,
+                    ?delay,
+                    "Download failed, retrying: {:#}",
+                    error
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(error) => return Err(error),
+        }
+    };
+
+    fs::rename(&transfer.part_path, path)
+        .with_context(|| format!("Failed to rename {}", transfer.part_path.display()))?;
+    if let Err(e) = fs::remove_file(&transfer.state_path)
+        && e.kind() != io::ErrorKind::NotFound
+    {
+        return Err(e)
+            .with_context(|| format!("Failed to remove {}", transfer.state_path.display()));
+    }
+    let span = tracing::Span::current();
+    span.record("bytes", transfer.written);
+    span.record("retries", retries);
+    crate::telemetry::record_download(transfer.written);
+    Ok((state, transfer.written, retries))
+}
+
+/// Downloads `url` once, resuming after the bytes already written when possible and
+/// starting over otherwise.
+///
+/// A part already as long as the archive is used as is. A resumed transfer starts over
+/// when the server answers the range request with `416 Range Not Satisfiable` or with a
+/// `Content-Range` that does not start where the part ends.
+async fn download_attempt(
+    client: &reqwest::Client,
+    url: &str,
+    transfer: &mut Transfer,
+    options: &DownloadOptions,
+) -> anyhow::Result<DownloadState> {
+    let resume = transfer
+        .partial
+        .as_ref()
+        .and_then(PartialDownload::if_range)
+        .filter(|_| options.resume && transfer.written > 0)
+        .map(str::to_string);
+    if let Some(partial) = &transfer.partial
+        && resume.is_some()
+        && partial.state.content_length == Some(transfer.written)
+    {
+        tracing::info!(bytes = transfer.written, "Download already complete");
+        transfer.resumed = true;
+        return Ok(partial.state.clone());
+    }
+
+    let mut response = send_get(
+        client,
+        url,
+        resume
+            .as_deref()
+            .map(|if_range| (transfer.written, if_range)),
+    )
+    .await
+    .with_context(|| format!("Failed to download {}", url))?;
+    if resume.is_some() {
+        let status = response.status();
+        let restart = status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE
+            || (status == reqwest::StatusCode::PARTIAL_CONTENT
+                && content_range_start(response.headers()) != Some(transfer.written));
+        if restart {
+            tracing::warn!(%status, from = transfer.written, "Server rejected the resumed range, restarting download");
+            transfer.restart()?;
+            response = send_get(client, url, None)
+                .await
+                .with_context(|| format!("Failed to download {}", url))?;
+        }
+    }
+    let response = response
+        .error_for_status()
+        .with_context(|| format!("Failed to download {}", url))?;
+
+    let path = &transfer.part_path;
+    let mut file = if resume.is_some() && response.status() == reqwest::StatusCode::PARTIAL_CONTENT
+    {
+        tracing::info!(from = transfer.written, "Resuming download");
+        transfer.resumed = true;
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?
+    } else {
+        if transfer.written > 0 {
+            tracing::info!("Restarting download from the beginning");
+        }
+        let partial = PartialDownload {
+            state: DownloadState::from_response(url, &response),
+            accept_ranges: response
+                .headers()
+                .get(reqwest::header::ACCEPT_RANGES)
+                .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"bytes")),
+        };
+        if options.resume {
+            partial.save(&transfer.state_path)?;
+        }
+        transfer.partial = Some(partial);
+        transfer.written = 0;
+        transfer.resumed = false;
+        tokio::fs::File::create(path)
+            .await
+            .with_context(|| format!("Failed to create {}", path.display()))?
+    };
+    let total = transfer
+        .partial
+        .as_ref()
+        .and_then(|partial| partial.state.content_length);
+
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.with_context(|| format!("Failed to read {}", url))?;
+        file.write_all(&chunk)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        transfer.written += chunk.len() as u64;
+        if let Some(callback) = &options.progress {
+            callback(DownloadProgress::Downloading {
+                bytes: transfer.written,
+                total,
+            });
+        }
+    }
+    file.flush()
         .await
-        .context("Failed to read response bytes")?;
-    let reader = Cursor::new(content);
-    let mut archive = ZipArchive::new(reader).context("Failed to open zip archive")?;
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    if let Some(expected) = total
+        && transfer.written != expected
+    {
+        return Err(anyhow::Error::new(Truncated {
+            received: transfer.written,
+            expected,
+        })
+        .context(format!("Failed to download {}", url)));
+    }
+    let mut state = transfer
+        .partial
+        .as_ref()
+        .map(|partial| partial.state.clone())
+        .unwrap_or_default();
+    state.content_length = Some(transfer.written);
+    Ok(state)
+}
+
+/// Sends a GET request for `url`, asking for the bytes after `range.0` when `range`
+/// holds them and the `If-Range` validator of the archive.
+async fn send_get(
+    client: &reqwest::Client,
+    url: &str,
+    range: Option<(u64, &str)>,
+) -> reqwest::Result<reqwest::Response> {
+    let mut request = client.get(url);
+    if let Some((from, if_range)) = range {
+        request = request
+            .header(reqwest::header::RANGE, format!("bytes={}-", from))
+            .header(reqwest::header::IF_RANGE, if_range);
+    }
+    request.send().await
+}
+
+/// First byte of a `Content-Range: bytes <first>-<last>/<length>` header
+fn content_range_start(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    let value = headers.get(reqwest::header::CONTENT_RANGE)?.to_str().ok()?;
+    let (first, _) = value.trim().strip_prefix("bytes ")?.split_once('-')?;
+    first.trim().parse().ok()
+}
+
+/// Whether the ZIP archive at `path` opens and the CRC-32 of every entry matches,
+/// reading all of it.
+async fn is_valid_zip(path: &Path) -> bool {
+    let path = path.to_path_buf();
+    let checked = tokio::task::spawn_blocking(move || {
+        let file = fs::File::open(&path)?;
+        let mut archive = ZipArchive::new(io::BufReader::new(file))?;
+        for i in 0..archive.len() {
+            io::copy(&mut archive.by_index(i)?, &mut io::sink())?;
+        }
+        Ok::<_, anyhow::Error>(())
+    })
+    .await;
+    match checked {
+        Ok(Ok(())) => true,
+        Ok(Err(error)) => {
+            tracing::warn!("{:#}", error);
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+/// Flag set when dropped, telling a blocking task that its result is no longer awaited
+#[derive(Default)]
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Prefix of the directory inside the target directory the archive is extracted to
+/// before its files are moved into place, followed by the process ID.
+///
+/// The staging directory lives inside the target directory rather than next to it, so
+/// that moving its files into place is a rename within one file system and nothing is
+/// written outside the directory the caller chose.
+const STAGING_DIR_PREFIX: &str = ".extracting-";
+
+/// Suffix of the file next to a staging directory that its run holds locked
+const STAGING_LOCK_SUFFIX: &str = ".lock";
+
+/// Staging directory of this process in `target_dir`.
+fn staging_dir(target_dir: &Path) -> PathBuf {
+    target_dir.join(format!("{}{}", STAGING_DIR_PREFIX, std::process::id()))
+}
+
+/// Lock file of the staging directory at `dir`.
+fn staging_lock_path(dir: &Path) -> PathBuf {
+    let mut path = dir.as_os_str().to_owned();
+    path.push(STAGING_LOCK_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Removes the staging directories left in `target_dir` by runs that crashed, and their
+/// lock files. Directories whose lock file another run still holds are left alone.
+fn remove_stale_staging_dirs(target_dir: &Path) -> anyhow::Result<()> {
+    let entries = match fs::read_dir(target_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", target_dir.display()));
+        }
+    };
+    let own = staging_dir(target_dir);
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {}", target_dir.display()))?;
+        let name = entry.file_name();
+        let Some(name) = name
+            .to_str()
+            .filter(|name| name.starts_with(STAGING_DIR_PREFIX))
+        else {
+            continue;
+        };
+        let dir = match name.strip_suffix(STAGING_LOCK_SUFFIX) {
+            // Lock files are removed with their directory, or alone once it is gone
+            Some(dir) if target_dir.join(dir).exists() => continue,
+            Some(dir) => target_dir.join(dir),
+            None if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) => entry.path(),
+            None => continue,
+        };
+        if dir == own {
+            continue;
+        }
+        let lock_path = staging_lock_path(&dir);
+        let lock = match fs::OpenOptions::new().write(true).open(&lock_path) {
+            Ok(lock) => Some(lock),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to open {}", lock_path.display()));
+            }
+        };
+        match lock.as_ref().map(fs::File::try_lock) {
+            Some(Err(fs::TryLockError::WouldBlock)) => {
+                tracing::debug!(path = ?dir, "Staging directory in use by another run");
+                continue;
+            }
+            Some(Err(fs::TryLockError::Error(e))) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", lock_path.display()));
+            }
+            Some(Ok(())) | None => {}
+        }
+        if dir.exists() {
+            tracing::warn!(path = ?dir, "Removing staging directory of an interrupted run");
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to remove {}", dir.display()))?;
+        }
+        if lock.is_some() {
+            fs::remove_file(&lock_path)
+                .with_context(|| format!("Failed to remove {}", lock_path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Directory removed with everything in it when dropped, along with the lock file
+/// that tells other runs it is in use
+struct StagingDir {
+    path: PathBuf,
+    lock: fs::File,
+}
+
+impl StagingDir {
+    /// Empty directory at `path`, removing what a previous extraction left there.
+    fn create(path: PathBuf) -> anyhow::Result<Self> {
+        // Lock first, so that other runs never find the directory unlocked
+        let lock_path = staging_lock_path(&path);
+        let lock = loop {
+            let lock = fs::File::create(&lock_path)
+                .with_context(|| format!("Failed to create {}", lock_path.display()))?;
+            lock.lock()
+                .with_context(|| format!("Failed to lock {}", lock_path.display()))?;
+            // Another run cleaning up may have taken the lock between creating and locking
+            // the file, and removed it; a lock on a removed file protects nothing
+            if is_same_file(&lock, &lock_path)
+                .with_context(|| format!("Failed to read {}", lock_path.display()))?
+            {
+                break lock;
+            }
+        };
+        if path.exists() {
+            fs::remove_dir_all(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        fs::create_dir_all(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Self { path, lock })
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if let Err(error) = fs::remove_dir_all(&self.path) {
+            tracing::warn!(path = ?self.path, "Failed to remove staging directory: {}", error);
+        }
+        self.lock.unlock().ok();
+        let lock_path = staging_lock_path(&self.path);
+        if let Err(error) = fs::remove_file(&lock_path) {
+            tracing::warn!(path = ?lock_path, "Failed to remove staging lock: {}", error);
+        }
+    }
+}
 
-    for i in 0..archive.len() {
+/// Whether `path` still names the open `file`, rather than nothing or a file created
+/// after `file` was removed
+fn is_same_file(file: &fs::File, path: &Path) -> io::Result<bool> {
+    let on_disk = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let open = file.metadata()?;
+        Ok(open.dev() == on_disk.dev() && open.ino() == on_disk.ino())
+    }
+    // Elsewhere only whether the name still exists is checked
+    #[cfg(not(unix))]
+    {
+        let _ = (file, on_disk);
+        Ok(true)
+    }
+}
+
+/// Extracts the archive like [`extract`] into a staging directory inside `target_dir`,
+/// moving the files into `target_dir` only once all of them were extracted, so that a
+/// failed or cancelled extraction leaves the files there untouched.
+fn extract_staged(
+    zip_path: &Path,
+    target_dir: &Path,
+    include: Option<&[String]>,
+    progress: Option<&DownloadProgressCallback>,
+    cancelled: &AtomicBool,
+) -> anyhow::Result<DownloadManifest> {
+    let staging = StagingDir::create(staging_dir(target_dir))?;
+    let manifest = extract(zip_path, &staging.path, include, progress, cancelled)?;
+    anyhow::ensure!(!cancelled.load(Ordering::Relaxed), "Extraction cancelled");
+    move_files(&staging.path, target_dir)?;
+    Ok(manifest)
+}
+
+/// Moves the files in `from` and its subdirectories to the same paths in `to`,
+/// replacing existing files.
+fn move_files(from: &Path, to: &Path) -> anyhow::Result<()> {
+    let entries =
+        fs::read_dir(from).with_context(|| format!("Failed to read {}", from.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {}", from.display()))?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            fs::create_dir_all(&target)
+                .with_context(|| format!("Failed to create {}", target.display()))?;
+            move_files(&source, &target)?;
+        } else {
+            fs::rename(&source, &target)
+                .with_context(|| format!("Failed to move {}", target.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Reader failing once `cancelled` is set
+struct CancellableReader<'a, R> {
+    inner: R,
+    cancelled: &'a AtomicBool,
+}
+
+impl<R: Read> Read for CancellableReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::other("Extraction cancelled"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Extracts the entries of the ZIP archive at `zip_path` selected by `include` into
+/// `target_dir`, checking the CRC of every file and stopping once `cancelled` is set.
+fn extract(
+    zip_path: &Path,
+    target_dir: &Path,
+    include: Option<&[String]>,
+    progress: Option<&DownloadProgressCallback>,
+    cancelled: &AtomicBool,
+) -> anyhow::Result<DownloadManifest> {
+    let reader = fs::File::open(zip_path)
+        .with_context(|| format!("Failed to open {}", zip_path.display()))?;
+    let mut archive =
+        ZipArchive::new(io::BufReader::new(reader)).context("Failed to open zip archive")?;
+
+    let mut manifest = DownloadManifest {
+        include: include.map(<[String]>::to_vec),
+        ..Default::default()
+    };
+    let selected: Vec<usize> = (0..archive.len())
+        .filter(|&i| {
+            archive.name_for_index(i).is_some_and(|name| {
+                include.is_none() || (!name.ends_with('/') && is_included(include, name))
+            })
+        })
+        .collect();
+    for (done, &i) in selected.iter().enumerate() {
+        anyhow::ensure!(!cancelled.load(Ordering::Relaxed), "Extraction cancelled");
         let mut file = archive
             .by_index(i)
-            .context("Failed to access file in zip")?;
+            .with_context(|| format!("Failed to access file {} in zip", i))?;
         let outpath = target_dir.join(file.mangled_name());
 
         if file.name().ends_with('/') {
@@ -47,26 +1466,109 @@ pub async fn download_and_extract_nomenclator<P: AsRef<std::path::Path>>(
             {
                 fs::create_dir_all(p).context("Failed to create parent directory")?;
             }
-            let mut outfile = fs::File::create(&outpath).context("Failed to create output file")?;
-            io::copy(&mut file, &mut outfile).context("Failed to copy file content")?;
+            let outfile = fs::File::create(&outpath).context("Failed to create output file")?;
+            let mut outfile = HashingWriter::new(outfile);
+            // The zip reader fails on a CRC mismatch once the entry is read to the end
+            let mut reader = CancellableReader {
+                inner: &mut file,
+                cancelled,
+            };
+            let size = io::copy(&mut reader, &mut outfile)
+                .with_context(|| format!("Failed to extract {}", file.name()))?;
+            manifest.files.insert(
+                file.name().to_string(),
+                ManifestEntry {
+                    size,
+                    sha256: outfile.finish(),
+                },
+            );
+        }
+        if let Some(callback) = progress {
+            callback(DownloadProgress::Extracting {
+                files: done + 1,
+                total: selected.len(),
+                name: file.name().to_string(),
+            });
         }
     }
 
-    Ok(target_dir)
+    Ok(manifest)
+}
+
+/// Whether the archive entry `name` matches one of the `include` patterns, or
+/// `include` is `None`.
+fn is_included(include: Option<&[String]>, name: &str) -> bool {
+    include.is_none_or(|patterns| patterns.iter().any(|pattern| glob_match(pattern, name)))
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any run of characters and `?`
+/// for a single one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it was tried at
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((after_star, tried)) => {
+                    p = after_star;
+                    n = tried + 1;
+                    backtrack = Some((after_star, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[tokio::test]
-    #[ignore] // Requires network access to external AEMPS server
-    async fn test_download_and_extract() {
-        let temp_dir = tempfile::tempdir().unwrap();
-        let target_dir = temp_dir.path().join("nomenclator");
-        fs::create_dir_all(&target_dir).unwrap();
-        let result = download_and_extract_nomenclator(&target_dir).await;
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), target_dir);
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("Prescripcion.xml", "Prescripcion.xml"));
+        assert!(!glob_match("Prescripcion.xml", "Prescripcion.xsd"));
+        assert!(glob_match("DICCIONARIO_*.xml", "DICCIONARIO_ATC.xml"));
+        assert!(!glob_match("DICCIONARIO_*.xml", "DICCIONARIO_ATC.xsd"));
+        assert!(glob_match("*.x?d", "Prescripcion.xsd"));
+        assert!(glob_match("*_*_*", "A_B_C"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("?", ""));
+        assert!(glob_match("*ATC*", "DICCIONARIO_ATC.xml"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_is_same_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".extracting-1.lock");
+        let file = fs::File::create(&path).unwrap();
+        assert!(is_same_file(&file, &path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+        assert!(!is_same_file(&file, &path).unwrap());
+
+        fs::File::create(&path).unwrap();
+        assert!(!is_same_file(&file, &path).unwrap());
+    }
+
+    #[test]
+    fn test_nomenclator_xml_files() {
+        assert_eq!(
+            nomenclator_xml_files(&[DictionaryKind::Atc], true),
+            ["DICCIONARIO_ATC.xml", "Prescripcion.xml"]
+        );
     }
 }
@@ -1,6 +1,13 @@
-use crate::api_client::CimaClient;
-use crate::models::ChangeRecord;
+use super::endpoint::Endpoint;
+use crate::api_client::{CimaClient, EndpointError};
+use crate::models::{ChangeRecord, PaginatedResponse};
+use crate::pagination::paginate;
 use anyhow::{Context, Result};
+use chrono::NaiveTime;
+use futures::{Stream, StreamExt, TryStreamExt, stream};
+use std::collections::HashSet;
+use std::time::Duration;
+use tracing::instrument;
 
 impl CimaClient {
     /// Get change log from a specific date
@@ -10,11 +17,24 @@ impl CimaClient {
     /// # Arguments
     /// * `date` - Date in format "dd/mm/yyyy"
     /// * `registration_numbers` - Optional list of registration numbers to filter
+    #[instrument(skip_all, fields(fecha = date))]
     pub async fn get_change_log(
         &self,
         date: &str,
         registration_numbers: Option<&[&str]>,
-    ) -> Result<crate::models::PaginatedResponse<ChangeRecord>> {
+    ) -> Result<PaginatedResponse<ChangeRecord>> {
+        self.get_change_log_page(date, registration_numbers, None)
+            .await
+    }
+
+    /// Get one page of the change log from a specific date, the first one if `page` is `None`
+    #[instrument(skip_all, fields(fecha = date, pagina = page))]
+    pub async fn get_change_log_page(
+        &self,
+        date: &str,
+        registration_numbers: Option<&[&str]>,
+        page: Option<u32>,
+    ) -> Result<PaginatedResponse<ChangeRecord>> {
         let mut params = vec![("fecha", date.to_string())];
 
         if let Some(regs) = registration_numbers {
@@ -22,9 +42,155 @@ impl CimaClient {
                 params.push(("nregistro", reg.to_string()));
             }
         }
+        if let Some(page) = page {
+            params.push(("pagina", page.to_string()));
+        }
 
-        self.get_with_params("registroCambios", &params)
+        self.request(&Endpoint::ChangeLog, &params)
             .await
-            .context("Failed to get change log")
+            .with_context(|| EndpointError::new("get_change_log_page", params.clone()))
+    }
+
+    /// Get change log from a specific date yielding every page as it arrives
+    pub fn get_change_log_pages<'a>(
+        &'a self,
+        date: &'a str,
+        registration_numbers: Option<&'a [&'a str]>,
+    ) -> impl Stream<Item = Result<PaginatedResponse<ChangeRecord>>> + 'a {
+        paginate(move |page| self.get_change_log_page(date, registration_numbers, Some(page)))
+    }
+
+    /// Poll the change log every `interval`, yielding each change once as it appears
+    ///
+    /// The first poll, from `date` ("dd/mm/yyyy"), is immediate. Later polls start from
+    /// the day of the newest change seen, so memory stays bounded. A failed poll yields
+    /// its error and polling goes on, so the stream never ends.
+    pub fn watch_changes(
+        &self,
+        date: &str,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<ChangeRecord>> + '_ {
+        watch(date.to_string(), interval, move |date| async move {
+            self.get_change_log_pages(&date, None)
+                .map_ok(|page| stream::iter(page.results.into_iter().map(Ok)))
+                .try_flatten()
+                .try_collect()
+                .await
+        })
+    }
+}
+
+/// Identity of a change, to tell apart the ones already yielded
+type ChangeKey = (String, i64, u8, Vec<String>);
+
+/// Calls `fetch` with the date to poll from every `interval`, yielding the changes not
+/// seen before
+fn watch<F, Fut>(
+    date: String,
+    interval: Duration,
+    fetch: F,
+) -> impl Stream<Item = Result<ChangeRecord>>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<Vec<ChangeRecord>>>,
+{
+    let seen: HashSet<ChangeKey> = HashSet::new();
+    stream::unfold(
+        (fetch, date, seen, true),
+        move |(mut fetch, mut date, mut seen, first)| async move {
+            if !first {
+                tokio::time::sleep(interval).await;
+            }
+            let records = match fetch(date.clone()).await {
+                Ok(records) => records,
+                Err(e) => return Some((vec![Err(e)], (fetch, date, seen, false))),
+            };
+
+            let newest = records.iter().filter_map(ChangeRecord::datetime).max();
+            let new: Vec<_> = records
+                .into_iter()
+                .filter(|record| {
+                    seen.insert((
+                        record.nregistro.clone(),
+                        record.date,
+                        record.change_type,
+                        record.changes.clone(),
+                    ))
+                })
+                .map(Ok)
+                .collect();
+
+            if let Some(newest) = newest
+                && let Some(day_start) = newest.with_time(NaiveTime::MIN).single()
+            {
+                date = newest.format("%d/%m/%Y").to_string();
+                let day_start = day_start.timestamp_millis();
+                seen.retain(|(_, millis, _, _)| *millis >= day_start);
+            }
+            Some((new, (fetch, date, seen, false)))
+        },
+    )
+    .flat_map(stream::iter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn change(nregistro: &str, date: i64) -> ChangeRecord {
+        ChangeRecord {
+            nregistro: nregistro.to_string(),
+            date,
+            change_type: 3,
+            changes: vec!["ft".to_string()],
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watch_yields_new_changes_once() {
+        // 2024-06-01T10:00+02:00 and 2024-06-03T10:00+02:00
+        let (june_1, june_3) = (1_717_228_800_000, 1_717_401_600_000);
+        let polls = Arc::new(Mutex::new(Vec::new()));
+        let requested = polls.clone();
+        let start = tokio::time::Instant::now();
+        let changes = watch(
+            "01/06/2024".to_string(),
+            Duration::from_secs(900),
+            move |date| {
+                let mut polls = requested.lock().unwrap();
+                polls.push((date, start.elapsed().as_secs()));
+                let records = match polls.len() {
+                    1 => Ok(vec![change("60000", june_1)]),
+                    2 => Err(anyhow::anyhow!("API returned error status 503")),
+                    3 => Ok(vec![change("60000", june_1), change("60001", june_3)]),
+                    _ => Ok(vec![change("60001", june_3), change("60002", june_3)]),
+                };
+                async move { records }
+            },
+        );
+
+        let results: Vec<_> = changes.take(4).collect().await;
+
+        let outcome: Vec<_> = results
+            .iter()
+            .map(|result| match result {
+                Ok(record) => record.nregistro.clone(),
+                Err(e) => e.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            outcome,
+            ["60000", "API returned error status 503", "60001", "60002"]
+        );
+        assert_eq!(
+            *polls.lock().unwrap(),
+            [
+                ("01/06/2024".to_string(), 0),
+                ("01/06/2024".to_string(), 900),
+                ("01/06/2024".to_string(), 1800),
+                ("03/06/2024".to_string(), 2700),
+            ]
+        );
     }
 }
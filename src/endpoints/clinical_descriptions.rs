@@ -1,6 +1,11 @@
-use crate::api_client::CimaClient;
-use crate::models::ClinicalDescription;
+use super::endpoint::Endpoint;
+use super::query::{QueryParams, query_params};
+use crate::api_client::{CimaClient, EndpointError};
+use crate::models::{ClinicalDescription, PaginatedResponse};
+use crate::pagination::paginate;
 use anyhow::{Context, Result};
+use futures::{Stream, TryStreamExt};
+use tracing::instrument;
 
 /// VMP/VMPP search parameters
 #[derive(Debug, Default, Clone)]
@@ -17,46 +22,76 @@ pub struct SearchClinicalDescriptionParams {
     pub atc: Option<String>,
     /// Medication name
     pub name: Option<String>,
+    /// VMP code ID
+    pub vmp: Option<String>,
+    /// VMPP code ID
+    pub vmpp: Option<String>,
     /// If included, returns results in hierarchical mode
     pub tree_mode: bool,
     /// Page number
     pub page: Option<u32>,
 }
 
+query_params!(SearchClinicalDescriptionParams {
+    active_ingredient => "practiv1",
+    active_ingredient_id => "idpractiv1",
+    dose => "dosis",
+    pharmaceutical_form => "forma",
+    atc => "atc",
+    name => "nombre",
+    vmp => "vmp",
+    vmpp => "vmpp",
+    tree_mode => "modoArbol",
+    page => "pagina",
+});
+
 impl SearchClinicalDescriptionParams {
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub(crate) fn to_query_params(&self) -> Vec<(&str, String)> {
-        let mut params = Vec::new();
+    /// Build query parameters as vector of tuples, see [`QueryParams::query`]
+    pub(crate) fn to_query_params(&self) -> Vec<(&'static str, String)> {
+        self.query()
+    }
+}
 
-        if let Some(ref v) = self.active_ingredient {
-            params.push(("practiv1", v.clone()));
-        }
-        if let Some(v) = self.active_ingredient_id {
-            params.push(("idpractiv1", v.to_string()));
-        }
-        if let Some(ref v) = self.dose {
-            params.push(("dosis", v.clone()));
-        }
-        if let Some(ref v) = self.pharmaceutical_form {
-            params.push(("forma", v.clone()));
-        }
-        if let Some(ref v) = self.atc {
-            params.push(("atc", v.clone()));
-        }
-        if let Some(ref v) = self.name {
-            params.push(("nombre", v.clone()));
-        }
-        if self.tree_mode {
-            params.push(("modoArbol", "true".to_string()));
-        }
-        if let Some(v) = self.page {
-            params.push(("pagina", v.to_string()));
-        }
+/// Client-side options applied while fetching every page of clinical descriptions
+///
+/// The CIMA `vmpp` endpoint has no server-side filter on the number of
+/// commercialized presentations, so these filters run as each page arrives and
+/// only matching items are kept in memory.
+#[derive(Debug, Default, Clone)]
+pub struct ClinicalDescriptionFetchOpts {
+    /// Keep only descriptions with at least this many commercialized presentations
+    pub min_commercialized: Option<i32>,
+}
+
+impl ClinicalDescriptionFetchOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `description` passes the filters
+    pub fn keep(&self, description: &ClinicalDescription) -> bool {
+        self.min_commercialized
+            .is_none_or(|min| description.commercialized_presentations >= min)
+    }
+}
+
+/// Result of fetching every page of clinical descriptions with client-side filters
+#[derive(Debug, Clone)]
+pub struct ClinicalDescriptionFetch {
+    /// Descriptions that passed the filters
+    pub results: Vec<ClinicalDescription>,
+    /// Number of descriptions received from the API before filtering
+    pub scanned: usize,
+}
 
-        params
+impl ClinicalDescriptionFetch {
+    /// Number of descriptions kept after filtering
+    pub fn kept(&self) -> usize {
+        self.results.len()
     }
 }
 
@@ -64,14 +99,163 @@ impl CimaClient {
     /// Search clinical descriptions (VMP/VMPP)
     ///
     /// Returns a paginated response with clinical descriptions
+    #[instrument(skip_all, fields(query = ?params.to_query_params()))]
     pub async fn search_clinical_descriptions(
         &self,
         params: &SearchClinicalDescriptionParams,
-    ) -> Result<crate::models::PaginatedResponse<ClinicalDescription>> {
-        let query_params = params.to_query_params();
+    ) -> Result<PaginatedResponse<ClinicalDescription>> {
+        self.request(&Endpoint::ClinicalDescriptions, params)
+            .await
+            .with_context(|| EndpointError::new("search_clinical_descriptions", params.query()))
+    }
+
+    /// Get the clinical description for a specific VMPP code
+    ///
+    /// Returns `None` if the API has no description with exactly that VMPP code.
+    #[instrument(skip_all, fields(vmpp = vmpp_code))]
+    pub async fn get_clinical_description(
+        &self,
+        vmpp_code: &str,
+    ) -> Result<Option<ClinicalDescription>> {
+        let params = SearchClinicalDescriptionParams {
+            vmpp: Some(vmpp_code.to_string()),
+            ..Default::default()
+        };
 
-        self.get_with_params("vmpp", &query_params)
+        let response = self
+            .search_clinical_descriptions(&params)
             .await
-            .context("Failed to search clinical descriptions")
+            .with_context(|| {
+                EndpointError::new(
+                    "get_clinical_description",
+                    vec![("vmpp", vmpp_code.to_string())],
+                )
+            })?;
+
+        Ok(response
+            .results
+            .into_iter()
+            .find(|description| description.vmpp == vmpp_code))
+    }
+
+    /// Search clinical descriptions (VMP/VMPP) yielding every result page as it arrives
+    ///
+    /// The `page` field of `params` is ignored; pages are requested from 1.
+    pub fn search_clinical_descriptions_pages(
+        &self,
+        params: &SearchClinicalDescriptionParams,
+    ) -> impl Stream<Item = Result<PaginatedResponse<ClinicalDescription>>> + '_ {
+        let params = params.clone();
+        paginate(move |page| {
+            let params = SearchClinicalDescriptionParams {
+                page: Some(page),
+                ..params.clone()
+            };
+            async move { self.search_clinical_descriptions(&params).await }
+        })
+    }
+
+    /// Search clinical descriptions (VMP/VMPP) fetching every result page
+    ///
+    /// The `page` field of `params` is ignored; pages are requested from 1
+    /// until all rows reported by the API have been received.
+    #[instrument(skip_all, fields(query = ?params.to_query_params()))]
+    pub async fn search_all_clinical_descriptions(
+        &self,
+        params: &SearchClinicalDescriptionParams,
+    ) -> Result<Vec<ClinicalDescription>> {
+        let fetch = self
+            .search_all_clinical_descriptions_with_opts(params, &Default::default())
+            .await?;
+
+        Ok(fetch.results)
+    }
+
+    /// Search clinical descriptions (VMP/VMPP) fetching every result page and
+    /// applying client-side filters as each page is received
+    ///
+    /// Returns the kept descriptions together with the number of scanned ones.
+    #[instrument(skip_all, fields(query = ?params.to_query_params(), min_commercialized = opts.min_commercialized))]
+    pub async fn search_all_clinical_descriptions_with_opts(
+        &self,
+        params: &SearchClinicalDescriptionParams,
+        opts: &ClinicalDescriptionFetchOpts,
+    ) -> Result<ClinicalDescriptionFetch> {
+        let mut pages = std::pin::pin!(self.search_clinical_descriptions_pages(params));
+        let mut results = Vec::new();
+        let mut scanned = 0;
+
+        while let Some(response) = pages.try_next().await? {
+            scanned += response.results.len();
+            results.extend(response.results.into_iter().filter(|d| opts.keep(d)));
+        }
+
+        tracing::debug!(
+            scanned,
+            kept = results.len(),
+            "Fetched clinical descriptions"
+        );
+
+        Ok(ClinicalDescriptionFetch { results, scanned })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_params_to_query() {
+        let params = SearchClinicalDescriptionParams {
+            active_ingredient: Some("paracetamol".to_string()),
+            atc: Some("N02BE01".to_string()),
+            tree_mode: true,
+            ..Default::default()
+        };
+
+        let query = params.to_query_params();
+        assert_eq!(query.len(), 3);
+        assert!(
+            query
+                .iter()
+                .any(|(k, v)| k == &"practiv1" && v == "paracetamol")
+        );
+        assert!(query.iter().any(|(k, v)| k == &"atc" && v == "N02BE01"));
+        assert!(query.iter().any(|(k, v)| k == &"modoArbol" && v == "true"));
+    }
+
+    #[test]
+    fn test_search_params_vmp_vmpp_to_query() {
+        let params = SearchClinicalDescriptionParams {
+            vmp: Some("3456789".to_string()),
+            vmpp: Some("1234567".to_string()),
+            ..Default::default()
+        };
+
+        let query = params.to_query_params();
+        assert_eq!(query.len(), 2);
+        assert!(query.iter().any(|(k, v)| k == &"vmp" && v == "3456789"));
+        assert!(query.iter().any(|(k, v)| k == &"vmpp" && v == "1234567"));
+    }
+
+    #[test]
+    fn test_fetch_opts_min_commercialized() {
+        let description = |count| ClinicalDescription {
+            vmp: "1".to_string(),
+            vmp_desc: "VMP".to_string(),
+            vmpp: "2".to_string(),
+            vmpp_desc: "VMPP".to_string(),
+            commercialized_presentations: count,
+        };
+
+        let opts = ClinicalDescriptionFetchOpts::default();
+        assert!(opts.keep(&description(0)));
+
+        let opts = ClinicalDescriptionFetchOpts {
+            min_commercialized: Some(1),
+        };
+        assert!(!opts.keep(&description(0)));
+        assert!(opts.keep(&description(1)));
+        assert!(opts.keep(&description(5)));
     }
 }
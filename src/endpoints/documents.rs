@@ -1,114 +1,305 @@
-use crate::api_client::CimaClient;
-use crate::models::{DocumentType, Section};
+use super::endpoint::Endpoint;
+use crate::api_client::{CimaClient, EndpointError};
+use crate::models::{DocumentType, Medication, Section};
 use anyhow::{Context, Result};
+use futures::{StreamExt, stream};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tracing::instrument;
+
+/// Options of [`CimaClient::download_documents`]
+#[derive(Debug, Clone)]
+pub struct DocumentDownloadOptions {
+    /// Documents downloaded at the same time
+    pub concurrency: usize,
+    /// Replace the files already in the output directory instead of skipping them
+    pub overwrite: bool,
+    /// Download the HTML version of the document instead of the PDF
+    pub html: bool,
+}
+
+impl Default for DocumentDownloadOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            overwrite: false,
+            html: false,
+        }
+    }
+}
+
+/// Outcome of downloading the document of one registration number
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentDownload {
+    /// Registration number of the medication
+    pub nregistro: String,
+    /// What happened to its document
+    #[serde(flatten)]
+    pub status: DocumentDownloadStatus,
+}
+
+/// Status of a [`DocumentDownload`]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DocumentDownloadStatus {
+    /// Written to `path`
+    Downloaded { path: PathBuf, bytes: u64 },
+    /// `path` already existed and was kept
+    Skipped { path: PathBuf },
+    /// The medication or its document does not exist
+    NotFound { reason: String },
+    /// The download failed
+    Failed { error: String },
+}
+
+impl DocumentDownloadStatus {
+    /// Whether the document is in the output directory
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Downloaded { .. } | Self::Skipped { .. })
+    }
+}
 
 impl CimaClient {
     /// Get document sections list (without content)
+    ///
+    /// Empty when the medication has no segmented document of that type.
+    #[instrument(skip_all, fields(?doc_type, nregistro = registration_number))]
     pub async fn get_document_sections(
         &self,
         doc_type: DocumentType,
         registration_number: &str,
     ) -> Result<Vec<Section>> {
-        let endpoint = format!("docSegmentado/secciones/{}", doc_type as u8);
         let params = vec![("nregistro", registration_number.to_string())];
 
-        self.get_with_params(&endpoint, &params)
+        self.request_optional(&Endpoint::DocumentSections(doc_type), &params)
             .await
-            .context("Failed to get document sections")
+            .map(Option::unwrap_or_default)
+            .with_context(|| {
+                EndpointError::new("get_document_sections", document_params(doc_type, &params))
+            })
     }
 
     /// Get document section content
+    ///
+    /// Empty when the medication has no segmented document of that type, or it has no
+    /// such section.
+    #[instrument(skip_all, fields(?doc_type, nregistro = registration_number, seccion = section))]
     pub async fn get_document_content(
         &self,
         doc_type: DocumentType,
         registration_number: &str,
         section: Option<&str>,
     ) -> Result<Vec<Section>> {
-        let endpoint = format!("docSegmentado/contenido/{}", doc_type as u8);
         let mut params = vec![("nregistro", registration_number.to_string())];
 
         if let Some(sec) = section {
             params.push(("seccion", sec.to_string()));
         }
 
-        self.get_with_params(&endpoint, &params)
+        self.request_optional(&Endpoint::DocumentContent(doc_type), &params)
             .await
-            .context("Failed to get document content")
+            .map(Option::unwrap_or_default)
+            .with_context(|| {
+                EndpointError::new("get_document_content", document_params(doc_type, &params))
+            })
     }
 
     /// Get complete technical data sheet in HTML
+    #[instrument(skip_all, fields(nregistro = registration_number))]
     pub async fn get_technical_sheet_html(&self, registration_number: &str) -> Result<String> {
-        let url = format!(
-            "https://cima.aemps.es/cima/dochtml/ft/{}/FichaTecnica.html",
-            registration_number
-        );
-
-        self.client
-            .get(&url)
-            .send()
-            .await
-            .with_context(|| "Failed to fetch technical sheet HTML".to_string())?
-            .text()
-            .await
-            .context("Failed to read technical sheet HTML")
+        let endpoint = Endpoint::TechnicalSheetHtml {
+            registration_number: registration_number.to_string(),
+            section: None,
+        };
+
+        self.request_text(&endpoint, &()).await.with_context(|| {
+            EndpointError::new(
+                "get_technical_sheet_html",
+                vec![("nregistro", registration_number.to_string())],
+            )
+        })
     }
 
     /// Get a specific section of the technical data sheet in HTML
+    #[instrument(skip_all, fields(nregistro = registration_number, seccion = section))]
     pub async fn get_technical_sheet_section_html(
         &self,
         registration_number: &str,
         section: &str,
     ) -> Result<String> {
-        let url = format!(
-            "https://cima.aemps.es/cima/dochtml/ft/{}/{}/FichaTecnica.html",
-            registration_number, section
-        );
-
-        self.client
-            .get(&url)
-            .send()
-            .await
-            .with_context(|| "Failed to fetch technical sheet section HTML".to_string())?
-            .text()
-            .await
-            .context("Failed to read technical sheet section HTML")
+        let endpoint = Endpoint::TechnicalSheetHtml {
+            registration_number: registration_number.to_string(),
+            section: Some(section.to_string()),
+        };
+
+        self.request_text(&endpoint, &()).await.with_context(|| {
+            EndpointError::new(
+                "get_technical_sheet_section_html",
+                vec![
+                    ("nregistro", registration_number.to_string()),
+                    ("seccion", section.to_string()),
+                ],
+            )
+        })
     }
 
     /// Get complete package leaflet in HTML
+    #[instrument(skip_all, fields(nregistro = registration_number))]
     pub async fn get_package_leaflet_html(&self, registration_number: &str) -> Result<String> {
-        let url = format!(
-            "https://cima.aemps.es/cima/dochtml/p/{}/Prospecto.html",
-            registration_number
-        );
-
-        self.client
-            .get(&url)
-            .send()
-            .await
-            .with_context(|| "Failed to fetch package leaflet HTML".to_string())?
-            .text()
-            .await
-            .context("Failed to read package leaflet HTML")
+        let endpoint = Endpoint::PackageLeafletHtml {
+            registration_number: registration_number.to_string(),
+            section: None,
+        };
+
+        self.request_text(&endpoint, &()).await.with_context(|| {
+            EndpointError::new(
+                "get_package_leaflet_html",
+                vec![("nregistro", registration_number.to_string())],
+            )
+        })
     }
 
     /// Get a specific section of the package leaflet in HTML
+    #[instrument(skip_all, fields(nregistro = registration_number, seccion = section))]
     pub async fn get_package_leaflet_section_html(
         &self,
         registration_number: &str,
         section: &str,
     ) -> Result<String> {
-        let url = format!(
-            "https://cima.aemps.es/cima/dochtml/p/{}/{}/Prospecto.html",
-            registration_number, section
-        );
-
-        self.client
-            .get(&url)
-            .send()
+        let endpoint = Endpoint::PackageLeafletHtml {
+            registration_number: registration_number.to_string(),
+            section: Some(section.to_string()),
+        };
+
+        self.request_text(&endpoint, &()).await.with_context(|| {
+            EndpointError::new(
+                "get_package_leaflet_section_html",
+                vec![
+                    ("nregistro", registration_number.to_string()),
+                    ("seccion", section.to_string()),
+                ],
+            )
+        })
+    }
+
+    /// Download the `doc_type` document of every registration number into `out_dir`
+    ///
+    /// The files are named like the ones of the CIMA website, such as `FT_51347.pdf`.
+    /// Results are in the order of `nregistros`, each with its own outcome, so a
+    /// missing document does not stop the others. The requests follow the rate limit
+    /// and retries of the client.
+    #[instrument(skip_all, fields(?doc_type, count = nregistros.len(), out_dir = %out_dir.display()))]
+    pub async fn download_documents(
+        &self,
+        doc_type: DocumentType,
+        nregistros: &[String],
+        out_dir: &Path,
+        options: &DocumentDownloadOptions,
+    ) -> Vec<DocumentDownload> {
+        stream::iter(nregistros)
+            .map(|nregistro| async move {
+                let status = self
+                    .download_document(doc_type, nregistro, out_dir, options)
+                    .await
+                    .unwrap_or_else(|e| DocumentDownloadStatus::Failed {
+                        error: format!("{e:#}"),
+                    });
+                DocumentDownload {
+                    nregistro: nregistro.clone(),
+                    status,
+                }
+            })
+            .buffered(options.concurrency.max(1))
+            .collect()
             .await
-            .with_context(|| "Failed to fetch package leaflet section HTML".to_string())?
-            .text()
+    }
+
+    async fn download_document(
+        &self,
+        doc_type: DocumentType,
+        nregistro: &str,
+        out_dir: &Path,
+        options: &DocumentDownloadOptions,
+    ) -> Result<DocumentDownloadStatus> {
+        let extension = if options.html { "html" } else { "pdf" };
+        let path = out_dir.join(format!(
+            "{}_{}.{}",
+            doc_type.code().to_uppercase(),
+            nregistro,
+            extension
+        ));
+        if !options.overwrite && tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(DocumentDownloadStatus::Skipped { path });
+        }
+
+        let params = vec![("nregistro", nregistro.to_string())];
+        let Some(medication) = self
+            .request_optional::<Medication, _>(&Endpoint::Medication, &params)
+            .await
+            .with_context(|| EndpointError::new("download_documents", params.clone()))?
+        else {
+            return Ok(DocumentDownloadStatus::NotFound {
+                reason: "medication not found".to_string(),
+            });
+        };
+        let document = medication
+            .docs
+            .iter()
+            .find(|doc| doc.doc_type == doc_type as u8);
+        let url = match document {
+            Some(doc) if options.html => doc.url_html.as_deref(),
+            Some(doc) => Some(doc.url.as_str()),
+            None => None,
+        };
+        let Some(url) = url else {
+            return Ok(DocumentDownloadStatus::NotFound {
+                reason: format!("no {} {} document", doc_type.code(), extension),
+            });
+        };
+        self.download_file(url, &path).await
+    }
+
+    /// Download `url` to `path`
+    ///
+    /// A 404 Not Found response is a [`DocumentDownloadStatus::NotFound`], other
+    /// failures are errors.
+    pub(crate) async fn download_file(
+        &self,
+        url: &str,
+        path: &Path,
+    ) -> Result<DocumentDownloadStatus> {
+        let response = self
+            .send(self.client.get(url))
             .await
-            .context("Failed to read package leaflet section HTML")
+            .with_context(|| format!("Failed to fetch {}", url))?;
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(DocumentDownloadStatus::NotFound {
+                reason: format!("{} returned 404 Not Found", url),
+            });
+        }
+        if !status.is_success() {
+            anyhow::bail!("Server returned error status {}: {}", status, url);
+        }
+        let body = response
+            .bytes()
+            .await
+            .with_context(|| format!("Failed to read {}", url))?;
+        tokio::fs::write(path, &body)
+            .await
+            .with_context(|| format!("Failed to write {:?}", path))?;
+        Ok(DocumentDownloadStatus::Downloaded {
+            path: path.to_path_buf(),
+            bytes: body.len() as u64,
+        })
     }
 }
+/// `doc_type` followed by `params`, identifying a request for a segmented document
+fn document_params(
+    doc_type: DocumentType,
+    params: &[(&'static str, String)],
+) -> Vec<(&'static str, String)> {
+    let mut identifying = vec![("doc_type", format!("{doc_type:?}"))];
+    identifying.extend_from_slice(params);
+    identifying
+}
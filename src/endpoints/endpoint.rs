@@ -0,0 +1,236 @@
+//! Paths of the CIMA REST API.
+//!
+//! [`Endpoint`] names every path wrapped by [`CimaClient`](crate::CimaClient), and
+//! [`RawEndpoint`] any other one, for [`CimaClient::request`](crate::CimaClient::request).
+
+use crate::models::DocumentType;
+use anyhow::{Result, bail};
+use std::fmt;
+
+/// Base URL of the HTML documents, which are not served by the REST API
+const DOCUMENTS_URL: &str = "https://cima.aemps.es/cima/dochtml";
+
+/// Path of the API, below the base URL of the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// `medicamento`
+    Medication,
+    /// `medicamentos`
+    Medications,
+    /// `buscarEnFichaTecnica`, sent as POST
+    TechnicalSheetSearch,
+    /// `presentacion/{cn}`
+    Presentation(String),
+    /// `presentaciones`
+    Presentations,
+    /// `vmpp`
+    ClinicalDescriptions,
+    /// `maestras`
+    MasterData,
+    /// `registroCambios`
+    ChangeLog,
+    /// `psuministro`, or `psuministro/{cn}` for one presentation
+    SupplyProblems(Option<String>),
+    /// `notas`
+    SafetyNotes,
+    /// `materiales`
+    Materials,
+    /// `docSegmentado/secciones/{tipo}`
+    DocumentSections(DocumentType),
+    /// `docSegmentado/contenido/{tipo}`
+    DocumentContent(DocumentType),
+    /// HTML technical sheet of a registration number, or of one of its sections, from
+    /// the documents server
+    TechnicalSheetHtml {
+        registration_number: String,
+        section: Option<String>,
+    },
+    /// HTML package leaflet of a registration number, or of one of its sections, from
+    /// the documents server
+    PackageLeafletHtml {
+        registration_number: String,
+        section: Option<String>,
+    },
+    /// Path not wrapped by the crate
+    Raw(RawEndpoint),
+}
+
+impl Endpoint {
+    /// Endpoint of any `path` of the API, see [`RawEndpoint::new`]
+    pub fn raw(path: &str) -> Result<Self> {
+        RawEndpoint::new(path).map(Self::Raw)
+    }
+
+    /// Path below the base URL, after checking the segments taken from arguments
+    pub fn path(&self) -> Result<String> {
+        let path = match self {
+            Self::Medication => "medicamento".to_string(),
+            Self::Medications => "medicamentos".to_string(),
+            Self::TechnicalSheetSearch => "buscarEnFichaTecnica".to_string(),
+            Self::Presentation(cn) => format!("presentacion/{}", segment(cn)?),
+            Self::Presentations => "presentaciones".to_string(),
+            Self::ClinicalDescriptions => "vmpp".to_string(),
+            Self::MasterData => "maestras".to_string(),
+            Self::ChangeLog => "registroCambios".to_string(),
+            Self::SupplyProblems(None) => "psuministro".to_string(),
+            Self::SupplyProblems(Some(cn)) => format!("psuministro/{}", segment(cn)?),
+            Self::SafetyNotes => "notas".to_string(),
+            Self::Materials => "materiales".to_string(),
+            Self::DocumentSections(doc_type) => {
+                format!("docSegmentado/secciones/{}", *doc_type as u8)
+            }
+            Self::DocumentContent(doc_type) => {
+                format!("docSegmentado/contenido/{}", *doc_type as u8)
+            }
+            Self::TechnicalSheetHtml {
+                registration_number,
+                section,
+            } => document_path(
+                "ft",
+                registration_number,
+                section.as_deref(),
+                "FichaTecnica",
+            )?,
+            Self::PackageLeafletHtml {
+                registration_number,
+                section,
+            } => document_path("p", registration_number, section.as_deref(), "Prospecto")?,
+            Self::Raw(raw) => raw.0.clone(),
+        };
+        Ok(path)
+    }
+
+    /// URL of the endpoint for a client whose API is at `base_url`
+    pub fn url(&self, base_url: &str) -> Result<String> {
+        let base_url = match self {
+            Self::TechnicalSheetHtml { .. } | Self::PackageLeafletHtml { .. } => DOCUMENTS_URL,
+            _ => base_url,
+        };
+        Ok(format!("{}/{}", base_url, self.path()?))
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.path() {
+            Ok(path) => f.write_str(&path),
+            Err(_) => write!(f, "{self:?}"),
+        }
+    }
+}
+
+/// Path of the API not wrapped by the crate, such as a newly published endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawEndpoint(String);
+
+impl RawEndpoint {
+    /// Endpoint at `path`, below the base URL of the client, like
+    /// `docSegmentado/secciones/1`
+    ///
+    /// Segments may only have letters, digits and `-._~`, and not be `.` or `..`; the
+    /// query string goes in the parameters of the request.
+    pub fn new(path: &str) -> Result<Self> {
+        let path = path.strip_prefix('/').unwrap_or(path);
+        for part in path.split('/') {
+            segment(part)?;
+        }
+        Ok(Self(path.to_string()))
+    }
+
+    /// Path below the base URL
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// `value` if it can be a path segment as is, see [`RawEndpoint::new`]
+fn segment(value: &str) -> Result<&str> {
+    let valid = !value.is_empty()
+        && value != "."
+        && value != ".."
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~'));
+    if !valid {
+        bail!("Invalid path segment {value:?}");
+    }
+    Ok(value)
+}
+
+/// Path of an HTML document on the documents server
+fn document_path(
+    kind: &str,
+    registration_number: &str,
+    section: Option<&str>,
+    file: &str,
+) -> Result<String> {
+    let registration_number = segment(registration_number)?;
+    Ok(match section {
+        Some(section) => format!(
+            "{kind}/{registration_number}/{}/{file}.html",
+            segment(section)?
+        ),
+        None => format!("{kind}/{registration_number}/{file}.html"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(Endpoint::Medication.path().unwrap(), "medicamento");
+        assert_eq!(
+            Endpoint::SupplyProblems(Some("712729".to_string()))
+                .path()
+                .unwrap(),
+            "psuministro/712729"
+        );
+        assert_eq!(
+            Endpoint::DocumentContent(DocumentType::PackageLeaflet)
+                .path()
+                .unwrap(),
+            "docSegmentado/contenido/2"
+        );
+        assert_eq!(
+            Endpoint::TechnicalSheetHtml {
+                registration_number: "51347".to_string(),
+                section: Some("4.2".to_string()),
+            }
+            .url("http://localhost")
+            .unwrap(),
+            "https://cima.aemps.es/cima/dochtml/ft/51347/4.2/FichaTecnica.html"
+        );
+    }
+
+    #[test]
+    fn test_invalid_segments() {
+        for cn in [
+            "",
+            "..",
+            "712729/../maestras",
+            "7127 29",
+            "712729?x=1",
+            "71%2F",
+        ] {
+            assert!(
+                Endpoint::Presentation(cn.to_string()).path().is_err(),
+                "{cn}"
+            );
+        }
+        assert!(Endpoint::raw("medicamento//fotos").is_err());
+        assert!(Endpoint::raw("medicamento?nregistro=1").is_err());
+        assert!(Endpoint::raw("https://example.com").is_err());
+    }
+
+    #[test]
+    fn test_raw() {
+        let raw = Endpoint::raw("/docSegmentado/secciones/1").unwrap();
+        assert_eq!(raw.path().unwrap(), "docSegmentado/secciones/1");
+        assert_eq!(
+            raw.url("http://localhost").unwrap(),
+            "http://localhost/docSegmentado/secciones/1"
+        );
+    }
+}
@@ -1,6 +1,11 @@
-use crate::api_client::CimaClient;
-use crate::models::{MasterDataType, MasterItem};
+use super::endpoint::Endpoint;
+use super::query::{QueryParams, query_params};
+use crate::api_client::{CimaClient, EndpointError};
+use crate::models::{MasterDataType, MasterItem, PaginatedResponse};
+use crate::pagination::paginate;
 use anyhow::{Context, Result};
+use futures::Stream;
+use tracing::instrument;
 
 /// Master data search parameters
 #[derive(Debug, Default, Clone)]
@@ -23,39 +28,60 @@ pub struct MasterDataParams {
     pub page: Option<u32>,
 }
 
+query_params!(MasterDataParams {
+    name => "nombre",
+    id => "id",
+    code => "codigo",
+    narcotic => "estupefaciente",
+    psychotropic => "psicotropo",
+    narcotic_or_psychotropic => "estuopsico",
+    in_use => "enuso",
+    page => "pagina",
+});
+
 impl MasterDataParams {
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub(crate) fn to_query_params(&self, data_type: MasterDataType) -> Vec<(&str, String)> {
-        let mut params = vec![("maestra", data_type.as_u8().to_string())];
+    /// Checks the filters against the catalog before querying it
+    ///
+    /// The API needs at least one filter, and the narcotic and psychotropic filters
+    /// only apply to active ingredients.
+    pub fn validate(&self, data_type: MasterDataType) -> Result<()> {
+        let narcotic_filters = self.narcotic.is_some()
+            || self.psychotropic.is_some()
+            || self.narcotic_or_psychotropic.is_some();
 
-        if let Some(ref v) = self.name {
-            params.push(("nombre", v.clone()));
-        }
-        if let Some(v) = self.id {
-            params.push(("id", v.to_string()));
-        }
-        if let Some(ref v) = self.code {
-            params.push(("codigo", v.clone()));
-        }
-        if let Some(v) = self.narcotic {
-            params.push(("estupefaciente", v.to_string()));
+        if !narcotic_filters
+            && self.name.is_none()
+            && self.id.is_none()
+            && self.code.is_none()
+            && self.in_use.is_none()
+        {
+            anyhow::bail!(
+                "Master data queries need at least one filter (name, id, code, narcotic, \
+                 psychotropic or in use)"
+            );
         }
-        if let Some(v) = self.psychotropic {
-            params.push(("psicotropo", v.to_string()));
-        }
-        if let Some(v) = self.narcotic_or_psychotropic {
-            params.push(("estuopsico", v.to_string()));
-        }
-        if let Some(v) = self.in_use {
-            params.push(("enuso", v.to_string()));
-        }
-        if let Some(v) = self.page {
-            params.push(("pagina", v.to_string()));
+        if narcotic_filters
+            && !matches!(
+                data_type,
+                MasterDataType::ActiveIngredients | MasterDataType::ActiveIngredientsSNOMED
+            )
+        {
+            anyhow::bail!(
+                "The narcotic and psychotropic filters only apply to active ingredients, not {:?}",
+                data_type
+            );
         }
+        Ok(())
+    }
 
+    /// Build query parameters of the `data_type` catalog, see [`QueryParams::query`]
+    pub(crate) fn to_query_params(&self, data_type: MasterDataType) -> Vec<(&'static str, String)> {
+        let mut params = vec![("maestra", data_type.as_u8().to_string())];
+        params.extend(self.query());
         params
     }
 }
@@ -65,18 +91,71 @@ impl CimaClient {
     ///
     /// **Important**: The CIMA API requires at least one filter parameter to be set
     /// (name, id, code, narcotic, psychotropic, narcotic_or_psychotropic, or in_use).
-    /// Without any filter, the API returns 204 No Content.
+    /// Without any filter, the API returns 204 No Content, so `params` are checked
+    /// with [`MasterDataParams::validate`] before sending the request.
     ///
     /// Returns a paginated response with master data items.
+    #[instrument(skip_all, fields(maestra = data_type.as_u8(), query = ?params.to_query_params(data_type)))]
     pub async fn get_master_data(
         &self,
         data_type: MasterDataType,
         params: &MasterDataParams,
-    ) -> Result<crate::models::PaginatedResponse<MasterItem>> {
+    ) -> Result<PaginatedResponse<MasterItem>> {
+        params.validate(data_type)?;
         let query_params = params.to_query_params(data_type);
 
-        self.get_with_params("maestras", &query_params)
+        self.request(&Endpoint::MasterData, &query_params)
             .await
-            .context("Failed to get master data")
+            .with_context(|| EndpointError::new("get_master_data", query_params.clone()))
+    }
+
+    /// Get elements from a master data catalog yielding every result page as it arrives
+    ///
+    /// The `page` field of `params` is ignored; pages are requested from 1.
+    pub fn get_master_data_pages(
+        &self,
+        data_type: MasterDataType,
+        params: &MasterDataParams,
+    ) -> impl Stream<Item = Result<PaginatedResponse<MasterItem>>> + '_ {
+        let params = params.clone();
+        paginate(move |page| {
+            let params = MasterDataParams {
+                page: Some(page),
+                ..params.clone()
+            };
+            async move { self.get_master_data(data_type, &params).await }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_requires_a_filter() {
+        let params = MasterDataParams::default();
+        assert!(params.validate(MasterDataType::Laboratories).is_err());
+
+        let params = MasterDataParams {
+            id: Some(1),
+            ..Default::default()
+        };
+        assert!(params.validate(MasterDataType::Laboratories).is_ok());
+    }
+
+    #[test]
+    fn test_validate_narcotic_filters_only_for_active_ingredients() {
+        let params = MasterDataParams {
+            psychotropic: Some(1),
+            ..Default::default()
+        };
+        assert!(params.validate(MasterDataType::ActiveIngredients).is_ok());
+        assert!(
+            params
+                .validate(MasterDataType::ActiveIngredientsSNOMED)
+                .is_ok()
+        );
+        assert!(params.validate(MasterDataType::AtcCodes).is_err());
     }
 }
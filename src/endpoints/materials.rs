@@ -1,19 +1,22 @@
-use crate::api_client::CimaClient;
+use super::endpoint::Endpoint;
+use crate::api_client::{CimaClient, EndpointError};
 use crate::models::SafetyMaterial;
 use anyhow::{Context, Result};
+use tracing::instrument;
 
 impl CimaClient {
     /// Get informative materials associated with a medication
     ///
     /// Returns a single SafetyMaterial object (not an array)
+    #[instrument(skip_all, fields(nregistro = registration_number))]
     pub async fn get_informative_materials(
         &self,
         registration_number: &str,
     ) -> Result<SafetyMaterial> {
         let params = vec![("nregistro", registration_number.to_string())];
 
-        self.get_with_params("materiales", &params)
+        self.request(&Endpoint::Materials, &params)
             .await
-            .context("Failed to get informative materials")
+            .with_context(|| EndpointError::new("get_informative_materials", params.clone()))
     }
 }
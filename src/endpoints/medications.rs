@@ -1,7 +1,13 @@
-use crate::api_client::CimaClient;
-use crate::models::{Medication, MedicationSummary};
+use super::endpoint::Endpoint;
+use super::query::{QueryParams, query_params};
+use crate::api_client::{CimaClient, EndpointError};
+use crate::models::{Medication, MedicationSummary, PaginatedResponse};
+use crate::pagination::paginate;
 use anyhow::{Context, Result};
+use futures::{Stream, StreamExt, stream};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use tracing::instrument;
 
 /// Medication search parameters
 #[derive(Debug, Default, Clone)]
@@ -52,83 +58,39 @@ pub struct SearchMedicationsParams {
     pub page: Option<u32>,
 }
 
+query_params!(SearchMedicationsParams {
+    name => "nombre",
+    laboratory => "laboratorio",
+    active_ingredient_1 => "practiv1",
+    active_ingredient_2 => "practiv2",
+    active_ingredient_1_id => "idpractiv1",
+    active_ingredient_2_id => "idpractiv2",
+    national_code => "cn",
+    atc => "atc",
+    registration_number => "nregistro",
+    active_ingredient_count => "npactiv",
+    black_triangle => "triangulo",
+    orphan => "huerfano",
+    biosimilar => "biosimilar",
+    substitutable_type => "sust",
+    vmp => "vmp",
+    commercialized => "comerc",
+    authorized => "autorizados",
+    prescription => "receta",
+    narcotic => "estupefaciente",
+    psychotropic => "psicotropo",
+    narcotic_or_psychotropic => "estuopsico",
+    page => "pagina",
+});
+
 impl SearchMedicationsParams {
     pub fn new() -> Self {
         Self::default()
     }
 
-    /// Build query parameters as vector of tuples
-    pub(crate) fn to_query_params(&self) -> Vec<(&str, String)> {
-        let mut params = Vec::new();
-
-        if let Some(ref v) = self.name {
-            params.push(("nombre", v.clone()));
-        }
-        if let Some(ref v) = self.laboratory {
-            params.push(("laboratorio", v.clone()));
-        }
-        if let Some(ref v) = self.active_ingredient_1 {
-            params.push(("practiv1", v.clone()));
-        }
-        if let Some(ref v) = self.active_ingredient_2 {
-            params.push(("practiv2", v.clone()));
-        }
-        if let Some(v) = self.active_ingredient_1_id {
-            params.push(("idpractiv1", v.to_string()));
-        }
-        if let Some(v) = self.active_ingredient_2_id {
-            params.push(("idpractiv2", v.to_string()));
-        }
-        if let Some(ref v) = self.national_code {
-            params.push(("cn", v.clone()));
-        }
-        if let Some(ref v) = self.atc {
-            params.push(("atc", v.clone()));
-        }
-        if let Some(ref v) = self.registration_number {
-            params.push(("nregistro", v.clone()));
-        }
-        if let Some(v) = self.active_ingredient_count {
-            params.push(("npactiv", v.to_string()));
-        }
-        if let Some(v) = self.black_triangle {
-            params.push(("triangulo", v.to_string()));
-        }
-        if let Some(v) = self.orphan {
-            params.push(("huerfano", v.to_string()));
-        }
-        if let Some(v) = self.biosimilar {
-            params.push(("biosimilar", v.to_string()));
-        }
-        if let Some(v) = self.substitutable_type {
-            params.push(("sust", v.to_string()));
-        }
-        if let Some(ref v) = self.vmp {
-            params.push(("vmp", v.clone()));
-        }
-        if let Some(v) = self.commercialized {
-            params.push(("comerc", v.to_string()));
-        }
-        if let Some(v) = self.authorized {
-            params.push(("autorizados", v.to_string()));
-        }
-        if let Some(v) = self.prescription {
-            params.push(("receta", v.to_string()));
-        }
-        if let Some(v) = self.narcotic {
-            params.push(("estupefaciente", v.to_string()));
-        }
-        if let Some(v) = self.psychotropic {
-            params.push(("psicotropo", v.to_string()));
-        }
-        if let Some(v) = self.narcotic_or_psychotropic {
-            params.push(("estuopsico", v.to_string()));
-        }
-        if let Some(v) = self.page {
-            params.push(("pagina", v.to_string()));
-        }
-
-        params
+    /// Build query parameters as vector of tuples, see [`QueryParams::query`]
+    pub(crate) fn to_query_params(&self) -> Vec<(&'static str, String)> {
+        self.query()
     }
 }
 
@@ -146,8 +108,27 @@ pub struct TechnicalSheetQuery {
     pub contains: u8,
 }
 
+/// Medication requested by [`CimaClient::get_medications`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MedicationId {
+    /// Registration number (`nregistro`)
+    RegistrationNumber(String),
+    /// National code of one of its presentations (`cn`)
+    NationalCode(String),
+}
+
+impl fmt::Display for MedicationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MedicationId::RegistrationNumber(nregistro) => write!(f, "nregistro {}", nregistro),
+            MedicationId::NationalCode(cn) => write!(f, "cn {}", cn),
+        }
+    }
+}
+
 impl CimaClient {
     /// Get medication information by registration number or national code
+    #[instrument(skip_all, fields(nregistro = registration_number, cn = national_code))]
     pub async fn get_medication(
         &self,
         registration_number: Option<&str>,
@@ -166,33 +147,77 @@ impl CimaClient {
             anyhow::bail!("Must provide either registration_number or national_code");
         }
 
-        self.get_with_params("medicamento", &params)
+        self.request(&Endpoint::Medication, &params)
+            .await
+            .with_context(|| EndpointError::new("get_medication", params.clone()))
+    }
+
+    /// Get several medications, at most `concurrency` requests at a time
+    ///
+    /// Results are in the order of `ids`, each with its own error, so a missing
+    /// medication does not stop the others.
+    #[instrument(skip_all, fields(count = ids.len(), concurrency = concurrency))]
+    pub async fn get_medications(
+        &self,
+        ids: &[MedicationId],
+        concurrency: usize,
+    ) -> Vec<Result<Medication>> {
+        stream::iter(ids)
+            .map(|id| match id {
+                MedicationId::RegistrationNumber(nregistro) => {
+                    self.get_medication(Some(nregistro), None)
+                }
+                MedicationId::NationalCode(cn) => self.get_medication(None, Some(cn)),
+            })
+            .buffered(concurrency.max(1))
+            .collect()
             .await
-            .context("Failed to get medication")
     }
 
     /// Search medications according to specified parameters
     ///
     /// Returns a paginated response with medication search results.
+    #[instrument(skip_all, fields(query = ?params.to_query_params()))]
     pub async fn search_medications(
         &self,
         params: &SearchMedicationsParams,
-    ) -> Result<crate::models::PaginatedResponse<MedicationSummary>> {
-        let query_params = params.to_query_params();
-
-        self.get_with_params("medicamentos", &query_params)
+    ) -> Result<PaginatedResponse<MedicationSummary>> {
+        self.request(&Endpoint::Medications, params)
             .await
-            .context("Failed to search medications")
+            .with_context(|| EndpointError::new("search_medications", params.query()))
+    }
+
+    /// Search medications yielding every result page as it arrives
+    ///
+    /// The `page` field of `params` is ignored; pages are requested from 1.
+    pub fn search_medications_pages(
+        &self,
+        params: &SearchMedicationsParams,
+    ) -> impl Stream<Item = Result<PaginatedResponse<MedicationSummary>>> + '_ {
+        let params = params.clone();
+        paginate(move |page| {
+            let params = SearchMedicationsParams {
+                page: Some(page),
+                ..params.clone()
+            };
+            async move { self.search_medications(&params).await }
+        })
     }
 
     /// Search medications by content in technical data sheet
+    #[instrument(skip_all, fields(count = queries.len()))]
     pub async fn search_in_technical_sheet(
         &self,
         queries: &[TechnicalSheetQuery],
     ) -> Result<Vec<MedicationSummary>> {
-        self.post("buscarEnFichaTecnica", queries)
+        self.post(&Endpoint::TechnicalSheetSearch, queries)
             .await
-            .context("Failed to search in technical sheet")
+            .with_context(|| {
+                EndpointError::new(
+                    "search_in_technical_sheet",
+                    vec![("count", queries.len().to_string())],
+                )
+            })
     }
 }
 
@@ -1,15 +1,25 @@
 pub mod changes;
 pub mod clinical_descriptions;
 pub mod documents;
+pub mod endpoint;
 pub mod master_data;
 pub mod materials;
 pub mod medications;
+pub mod photos;
 pub mod presentations;
+pub mod query;
 pub mod safety_notes;
 pub mod supply_problems;
 
 // Re-export commonly used types
-pub use clinical_descriptions::SearchClinicalDescriptionParams;
+pub use clinical_descriptions::{
+    ClinicalDescriptionFetch, ClinicalDescriptionFetchOpts, SearchClinicalDescriptionParams,
+};
+pub use documents::{DocumentDownload, DocumentDownloadOptions, DocumentDownloadStatus};
+pub use endpoint::{Endpoint, RawEndpoint};
 pub use master_data::MasterDataParams;
-pub use medications::{SearchMedicationsParams, TechnicalSheetQuery};
+pub use medications::{MedicationId, SearchMedicationsParams, TechnicalSheetQuery};
+pub use photos::PhotoDownload;
 pub use presentations::SearchPresentationsParams;
+pub use query::QueryParams;
+pub use safety_notes::SafetyNoteDownload;
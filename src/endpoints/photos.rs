@@ -0,0 +1,107 @@
+use crate::api_client::CimaClient;
+use crate::endpoints::DocumentDownloadStatus;
+use crate::models::{Photo, PhotoType};
+use anyhow::Result;
+use serde::Serialize;
+use std::path::Path;
+use tracing::instrument;
+
+/// Outcome of downloading one photo of a medication
+#[derive(Debug, Clone, Serialize)]
+pub struct PhotoDownload {
+    /// Downloaded photo
+    pub photo: Photo,
+    /// What happened to it; photos are always downloaded again, so never `Skipped`
+    #[serde(flatten)]
+    pub status: DocumentDownloadStatus,
+}
+
+impl CimaClient {
+    /// Download the image of `photo` to `path`
+    ///
+    /// A 404 Not Found response is a [`DocumentDownloadStatus::NotFound`], other
+    /// failures are errors.
+    #[instrument(skip_all, fields(url = %photo.url, path = %path.display()))]
+    pub async fn download_photo(
+        &self,
+        photo: &Photo,
+        path: &Path,
+    ) -> Result<DocumentDownloadStatus> {
+        self.download_file(&photo.url, path).await
+    }
+
+    /// Download the photos of a medication into `out_dir`, only those of `kind` if given
+    ///
+    /// The files are named after the registration number, the photo type and the
+    /// position of the photo among those of its type, keeping the extension of the
+    /// image, such as `60000_materialas_1.jpg`. Each photo has its own outcome, so a
+    /// failed download does not stop the others.
+    #[instrument(skip_all, fields(nregistro = registration_number, ?kind, out_dir = %out_dir.display()))]
+    pub async fn download_photos(
+        &self,
+        registration_number: &str,
+        kind: Option<PhotoType>,
+        out_dir: &Path,
+    ) -> Result<Vec<PhotoDownload>> {
+        let medication = self.get_medication(Some(registration_number), None).await?;
+        let photos = medication
+            .photos
+            .into_iter()
+            .filter(|photo| kind.is_none_or(|kind| photo.kind() == Some(kind)));
+
+        let mut downloads: Vec<PhotoDownload> = Vec::new();
+        for photo in photos {
+            let position = downloads
+                .iter()
+                .filter(|download| download.photo.photo_type == photo.photo_type)
+                .count()
+                + 1;
+            let path = out_dir.join(format!(
+                "{}_{}_{}.{}",
+                registration_number,
+                photo.photo_type,
+                position,
+                image_extension(&photo.url)
+            ));
+            let status = self
+                .download_photo(&photo, &path)
+                .await
+                .unwrap_or_else(|e| DocumentDownloadStatus::Failed {
+                    error: format!("{e:#}"),
+                });
+            downloads.push(PhotoDownload { photo, status });
+        }
+        Ok(downloads)
+    }
+}
+
+/// Extension of the image at `url`, `jpg` if it has none.
+fn image_extension(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    match name.rsplit_once('.') {
+        Some((_, extension))
+            if !extension.is_empty() && extension.chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            extension
+        }
+        _ => "jpg",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_extension() {
+        assert_eq!(
+            image_extension(
+                "https://cima.aemps.es/cima/fotos/full/materialas/60000/60000_materialas.png?v=2"
+            ),
+            "png"
+        );
+        assert_eq!(image_extension("https://example.com/fotos/60000"), "jpg");
+        assert_eq!(image_extension("https://example.com/v1.2/foto"), "jpg");
+    }
+}
@@ -1,6 +1,11 @@
-use crate::api_client::CimaClient;
-use crate::models::{Presentation, PresentationSummary};
+use super::endpoint::Endpoint;
+use super::query::{QueryParams, query_params};
+use crate::api_client::{CimaClient, EndpointError};
+use crate::models::{PaginatedResponse, Presentation, PresentationSummary};
+use crate::pagination::paginate;
 use anyhow::{Context, Result};
+use futures::Stream;
+use tracing::instrument;
 
 /// Presentation search parameters
 #[derive(Debug, Default, Clone)]
@@ -9,6 +14,10 @@ pub struct SearchPresentationsParams {
     pub national_code: Option<String>,
     /// Registration number
     pub registration_number: Option<String>,
+    /// Medication name
+    pub name: Option<String>,
+    /// ATC code or description
+    pub atc: Option<String>,
     /// VMP code ID
     pub vmp: Option<String>,
     /// VMPP code ID
@@ -27,69 +36,145 @@ pub struct SearchPresentationsParams {
     pub page: Option<u32>,
 }
 
+query_params!(SearchPresentationsParams {
+    national_code => "cn",
+    registration_number => "nregistro",
+    name => "nombre",
+    atc => "atc",
+    vmp => "vmp",
+    vmpp => "vmpp",
+    active_ingredient_id => "idpractiv1",
+    commercialized => "comerc",
+    narcotic => "estupefaciente",
+    psychotropic => "psicotropo",
+    narcotic_or_psychotropic => "estuopsico",
+    page => "pagina",
+});
+
 impl SearchPresentationsParams {
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub(crate) fn to_query_params(&self) -> Vec<(&str, String)> {
-        let mut params = Vec::new();
-
-        if let Some(ref v) = self.national_code {
-            params.push(("cn", v.clone()));
-        }
-        if let Some(ref v) = self.registration_number {
-            params.push(("nregistro", v.clone()));
-        }
-        if let Some(ref v) = self.vmp {
-            params.push(("vmp", v.clone()));
-        }
-        if let Some(ref v) = self.vmpp {
-            params.push(("vmpp", v.clone()));
-        }
-        if let Some(v) = self.active_ingredient_id {
-            params.push(("idpractiv1", v.to_string()));
-        }
-        if let Some(v) = self.commercialized {
-            params.push(("comerc", v.to_string()));
-        }
-        if let Some(v) = self.narcotic {
-            params.push(("estupefaciente", v.to_string()));
-        }
-        if let Some(v) = self.psychotropic {
-            params.push(("psicotropo", v.to_string()));
-        }
-        if let Some(v) = self.narcotic_or_psychotropic {
-            params.push(("estuopsico", v.to_string()));
+    /// Checks the filters before querying the API
+    ///
+    /// The flags only take 0 or 1, and at most one of the narcotic, psychotropic and
+    /// narcotic or psychotropic filters can be set.
+    pub fn validate(&self) -> Result<()> {
+        let flags = [
+            ("commercialized", self.commercialized),
+            ("narcotic", self.narcotic),
+            ("psychotropic", self.psychotropic),
+            ("narcotic_or_psychotropic", self.narcotic_or_psychotropic),
+        ];
+        if let Some((name, Some(value))) =
+            flags.iter().find(|(_, value)| value.is_some_and(|v| v > 1))
+        {
+            anyhow::bail!("The {} filter takes 0 or 1, not {}", name, value);
         }
-        if let Some(v) = self.page {
-            params.push(("pagina", v.to_string()));
+        let narcotic_filters = [
+            self.narcotic,
+            self.psychotropic,
+            self.narcotic_or_psychotropic,
+        ];
+        if narcotic_filters
+            .iter()
+            .filter(|filter| filter.is_some())
+            .count()
+            > 1
+        {
+            anyhow::bail!(
+                "Only one of the narcotic, psychotropic and narcotic or psychotropic filters \
+                 can be set"
+            );
         }
+        Ok(())
+    }
 
-        params
+    /// Build query parameters as vector of tuples, see [`QueryParams::query`]
+    pub(crate) fn to_query_params(&self) -> Vec<(&'static str, String)> {
+        self.query()
     }
 }
 
 impl CimaClient {
     /// Get presentation information by national code
+    #[instrument(skip_all, fields(cn = national_code))]
     pub async fn get_presentation(&self, national_code: &str) -> Result<Presentation> {
-        let endpoint = format!("presentacion/{}", national_code);
-        self.get(&endpoint)
-            .await
-            .context("Failed to get presentation")
+        let endpoint = Endpoint::Presentation(national_code.to_string());
+        self.request(&endpoint, &()).await.with_context(|| {
+            EndpointError::new("get_presentation", vec![("cn", national_code.to_string())])
+        })
     }
 
     /// Search presentations according to specified parameters
     ///
-    /// Returns a paginated response with presentation search results.
+    /// `params` are checked with [`SearchPresentationsParams::validate`] before sending
+    /// the request. Returns a paginated response with presentation search results.
+    #[instrument(skip_all, fields(query = ?params.to_query_params()))]
     pub async fn search_presentations(
         &self,
         params: &SearchPresentationsParams,
-    ) -> Result<crate::models::PaginatedResponse<PresentationSummary>> {
-        let query_params = params.to_query_params();
-
-        self.get_with_params("presentaciones", &query_params)
+    ) -> Result<PaginatedResponse<PresentationSummary>> {
+        params.validate()?;
+        self.request(&Endpoint::Presentations, params)
             .await
-            .context("Failed to search presentations")
+            .with_context(|| EndpointError::new("search_presentations", params.query()))
+    }
+
+    /// Search presentations yielding every result page as it arrives
+    ///
+    /// The `page` field of `params` is ignored; pages are requested from 1.
+    pub fn search_presentations_pages(
+        &self,
+        params: &SearchPresentationsParams,
+    ) -> impl Stream<Item = Result<PaginatedResponse<PresentationSummary>>> + '_ {
+        let params = params.clone();
+        paginate(move |page| {
+            let params = SearchPresentationsParams {
+                page: Some(page),
+                ..params.clone()
+            };
+            async move { self.search_presentations(&params).await }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_flags() {
+        assert!(SearchPresentationsParams::default().validate().is_ok());
+
+        let params = SearchPresentationsParams {
+            commercialized: Some(0),
+            psychotropic: Some(1),
+            ..Default::default()
+        };
+        assert!(params.validate().is_ok());
+
+        let params = SearchPresentationsParams {
+            commercialized: Some(2),
+            ..Default::default()
+        };
+        assert!(params.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_one_narcotic_filter() {
+        let params = SearchPresentationsParams {
+            narcotic: Some(1),
+            psychotropic: Some(1),
+            ..Default::default()
+        };
+        assert!(params.validate().is_err());
+
+        let params = SearchPresentationsParams {
+            narcotic_or_psychotropic: Some(1),
+            ..Default::default()
+        };
+        assert!(params.validate().is_ok());
     }
 }
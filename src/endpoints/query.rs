@@ -0,0 +1,237 @@
+//! Query string of the search parameter structs.
+//!
+//! Each struct lists its fields with their wire names once, in a [`query_params!`]
+//! table, which builds both the query and [`QueryParams::WIRE_NAMES`].
+
+/// Search parameters sent as a query string
+pub trait QueryParams {
+    /// Field name and wire name of every parameter, in query order
+    const WIRE_NAMES: &'static [(&'static str, &'static str)];
+
+    /// Wire name and value of every parameter that is set
+    fn query(&self) -> Vec<(&'static str, String)>;
+
+    /// Wire name of `field`, if it is a parameter
+    fn wire_name(field: &str) -> Option<&'static str> {
+        Self::WIRE_NAMES
+            .iter()
+            .find(|(name, _)| *name == field)
+            .map(|(_, wire)| *wire)
+    }
+}
+
+/// Pairs sent as they are, for [`CimaClient::request`](crate::CimaClient::request)
+impl QueryParams for [(&'static str, String)] {
+    const WIRE_NAMES: &'static [(&'static str, &'static str)] = &[];
+
+    fn query(&self) -> Vec<(&'static str, String)> {
+        self.to_vec()
+    }
+}
+
+impl QueryParams for Vec<(&'static str, String)> {
+    const WIRE_NAMES: &'static [(&'static str, &'static str)] = &[];
+
+    fn query(&self) -> Vec<(&'static str, String)> {
+        self.clone()
+    }
+}
+
+/// No parameters
+impl QueryParams for () {
+    const WIRE_NAMES: &'static [(&'static str, &'static str)] = &[];
+
+    fn query(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+}
+
+/// Value of a parameter field, `None` when the parameter is not sent
+pub trait QueryValue {
+    fn query_value(&self) -> Option<String>;
+}
+
+impl QueryValue for Option<String> {
+    /// Empty strings are not sent
+    fn query_value(&self) -> Option<String> {
+        self.as_ref().filter(|v| !v.is_empty()).cloned()
+    }
+}
+
+macro_rules! impl_query_value_for_numbers {
+    ($($t:ty),*) => {
+        $(impl QueryValue for Option<$t> {
+            fn query_value(&self) -> Option<String> {
+                self.map(|v| v.to_string())
+            }
+        })*
+    };
+}
+
+impl_query_value_for_numbers!(u8, i32, u32);
+
+impl QueryValue for bool {
+    /// Flags are only sent when set, as `true`
+    fn query_value(&self) -> Option<String> {
+        self.then(|| "true".to_string())
+    }
+}
+
+/// Implements [`QueryParams`] for a struct from its `field => "wire name"` table
+macro_rules! query_params {
+    ($params:ty { $($field:ident => $wire:literal),* $(,)? }) => {
+        impl $crate::endpoints::query::QueryParams for $params {
+            const WIRE_NAMES: &'static [(&'static str, &'static str)] =
+                &[$((stringify!($field), $wire)),*];
+
+            fn query(&self) -> Vec<(&'static str, String)> {
+                let mut params = Vec::new();
+                $(
+                    if let Some(value) =
+                        $crate::endpoints::query::QueryValue::query_value(&self.$field)
+                    {
+                        params.push(($wire, value));
+                    }
+                )*
+                params
+            }
+        }
+    };
+}
+
+pub(crate) use query_params;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::{
+        MasterDataParams, SearchClinicalDescriptionParams, SearchMedicationsParams,
+        SearchPresentationsParams,
+    };
+    use std::collections::HashSet;
+
+    /// Checks that `params` emits non-empty, unique keys from its table and no empty value
+    fn assert_well_formed<P: QueryParams>(params: &P) {
+        let wire_names: HashSet<_> = P::WIRE_NAMES.iter().map(|(_, wire)| *wire).collect();
+        assert_eq!(
+            wire_names.len(),
+            P::WIRE_NAMES.len(),
+            "duplicated wire name"
+        );
+        let query = params.query();
+        let keys: HashSet<_> = query.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys.len(), query.len(), "duplicated key in {query:?}");
+        for (key, value) in &query {
+            assert!(!key.is_empty(), "empty key in {query:?}");
+            assert!(!value.is_empty(), "empty value for {key} in {query:?}");
+            assert!(wire_names.contains(key), "{key} is not in the table");
+        }
+    }
+
+    /// Strings tried for every text parameter
+    const TEXTS: &[&str] = &["", "a", "paracetamol 1 g", "ñ&="];
+    /// Numbers tried for every numeric parameter
+    const NUMBERS: &[i32] = &[0, 1, 5, i32::MAX];
+
+    fn text(i: usize) -> Option<String> {
+        (!i.is_multiple_of(5)).then(|| TEXTS[i % TEXTS.len()].to_string())
+    }
+
+    fn number(i: usize) -> Option<i32> {
+        (!i.is_multiple_of(3)).then(|| NUMBERS[i % NUMBERS.len()])
+    }
+
+    fn flag(i: usize) -> Option<u8> {
+        number(i).map(|v| v as u8)
+    }
+
+    #[test]
+    fn test_query_params_well_formed() {
+        for i in 0..60 {
+            let (a, b, c) = (i, i / 2 + 1, i / 3 + 2);
+            assert_well_formed(&SearchMedicationsParams {
+                name: text(a),
+                laboratory: text(b),
+                active_ingredient_1: text(c),
+                active_ingredient_2: text(a + 1),
+                active_ingredient_1_id: number(b),
+                active_ingredient_2_id: number(c),
+                national_code: text(b + 1),
+                atc: text(c + 1),
+                registration_number: text(a + 2),
+                active_ingredient_count: number(a),
+                black_triangle: flag(a),
+                orphan: flag(b),
+                biosimilar: flag(c),
+                substitutable_type: flag(a + 1),
+                vmp: text(b + 2),
+                commercialized: flag(b + 1),
+                authorized: flag(c + 1),
+                prescription: flag(a + 2),
+                narcotic: flag(b + 2),
+                psychotropic: flag(c + 2),
+                narcotic_or_psychotropic: flag(a + 3),
+                page: number(c).map(|v| v as u32),
+            });
+            assert_well_formed(&SearchPresentationsParams {
+                national_code: text(a),
+                registration_number: text(b),
+                name: text(c),
+                atc: text(a + 1),
+                vmp: text(b + 1),
+                vmpp: text(c + 1),
+                active_ingredient_id: number(a),
+                commercialized: flag(b),
+                narcotic: flag(c),
+                psychotropic: flag(a + 1),
+                narcotic_or_psychotropic: flag(b + 1),
+                page: number(c).map(|v| v as u32),
+            });
+            assert_well_formed(&SearchClinicalDescriptionParams {
+                active_ingredient: text(a),
+                active_ingredient_id: number(b),
+                dose: text(c),
+                pharmaceutical_form: text(a + 1),
+                atc: text(b + 1),
+                name: text(c + 1),
+                vmp: text(a + 2),
+                vmpp: text(b + 2),
+                tree_mode: i.is_multiple_of(2),
+                page: number(c).map(|v| v as u32),
+            });
+            assert_well_formed(&MasterDataParams {
+                name: text(a),
+                id: number(b),
+                code: text(c),
+                narcotic: flag(a),
+                psychotropic: flag(b),
+                narcotic_or_psychotropic: flag(c),
+                in_use: flag(a + 1),
+                page: number(b).map(|v| v as u32),
+            });
+        }
+    }
+
+    #[test]
+    fn test_wire_name() {
+        assert_eq!(
+            SearchMedicationsParams::wire_name("commercialized"),
+            Some("comerc")
+        );
+        assert_eq!(
+            SearchClinicalDescriptionParams::wire_name("tree_mode"),
+            Some("modoArbol")
+        );
+        assert_eq!(MasterDataParams::wire_name("maestra"), None);
+    }
+
+    #[test]
+    fn test_empty_text_not_sent() {
+        let params = SearchMedicationsParams {
+            name: Some(String::new()),
+            atc: Some("N02BE01".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(params.query(), vec![("atc", "N02BE01".to_string())]);
+    }
+}
@@ -1,14 +1,109 @@
-use crate::api_client::CimaClient;
+use super::endpoint::Endpoint;
+use crate::api_client::{CimaClient, EndpointError};
+use crate::endpoints::DocumentDownloadStatus;
 use crate::models::SafetyNote;
 use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use tracing::instrument;
+
+/// Outcome of downloading one safety note
+#[derive(Debug, Clone, Serialize)]
+pub struct SafetyNoteDownload {
+    /// Downloaded note
+    pub note: SafetyNote,
+    /// What happened to it; notes are always downloaded again, so never `Skipped`
+    #[serde(flatten)]
+    pub status: DocumentDownloadStatus,
+}
 
 impl CimaClient {
     /// Get safety notes associated with a medication
+    #[instrument(skip_all, fields(nregistro = registration_number))]
     pub async fn get_safety_notes(&self, registration_number: &str) -> Result<Vec<SafetyNote>> {
         let params = vec![("nregistro", registration_number.to_string())];
 
-        self.get_with_params("notas", &params)
+        self.request(&Endpoint::SafetyNotes, &params)
             .await
-            .context("Failed to get safety notes")
+            .with_context(|| EndpointError::new("get_safety_notes", params.clone()))
+    }
+
+    /// Download the document of every note in `notes` into `out_dir`
+    ///
+    /// The files keep the name of the document in its URL, or are named after the note
+    /// number when the URL has none. Each note has its own outcome, so a failed
+    /// download does not stop the others.
+    #[instrument(skip_all, fields(count = notes.len(), out_dir = %out_dir.display()))]
+    pub async fn download_safety_notes(
+        &self,
+        notes: &[SafetyNote],
+        out_dir: &Path,
+    ) -> Vec<SafetyNoteDownload> {
+        let mut downloads = Vec::with_capacity(notes.len());
+        for note in notes {
+            let path = out_dir.join(note_file_name(note));
+            let status = self
+                .download_file(&note.url, &path)
+                .await
+                .unwrap_or_else(|e| DocumentDownloadStatus::Failed {
+                    error: format!("{e:#}"),
+                });
+            downloads.push(SafetyNoteDownload {
+                note: note.clone(),
+                status,
+            });
+        }
+        downloads
+    }
+}
+
+/// Last segment of the URL of `note`, or `nota_{num}.pdf` with the characters of the
+/// number that are not letters or digits replaced by `_`.
+fn note_file_name(note: &SafetyNote) -> String {
+    let path = note.url.split(['?', '#']).next().unwrap_or_default();
+    match path.rsplit('/').next() {
+        Some(name) if name.contains('.') => name.to_string(),
+        _ => {
+            let num: String = note
+                .num
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            format!("nota_{}.pdf", num)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(num: &str, url: &str) -> SafetyNote {
+        SafetyNote {
+            note_type: 1,
+            num: num.to_string(),
+            r#ref: None,
+            subject: "Asunto".to_string(),
+            date: 0,
+            url: url.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_note_file_name() {
+        assert_eq!(
+            note_file_name(&note(
+                "MUH (FV), 3/2024",
+                "https://www.aemps.gob.es/informa/MUH_FV_03-2024.pdf?x=1"
+            )),
+            "MUH_FV_03-2024.pdf"
+        );
+        assert_eq!(
+            note_file_name(&note(
+                "MUH (FV), 3/2024",
+                "https://www.aemps.gob.es/informa/"
+            )),
+            "nota_MUH__FV___3_2024.pdf"
+        );
     }
 }
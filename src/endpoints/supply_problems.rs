@@ -1,29 +1,67 @@
-use crate::api_client::CimaClient;
-use crate::models::SupplyProblem;
+use super::endpoint::Endpoint;
+use crate::api_client::{CimaClient, EndpointError};
+use crate::models::{PaginatedResponse, SupplyProblem};
+use crate::pagination::paginate;
 use anyhow::{Context, Result};
+use futures::Stream;
+use tracing::instrument;
 
 impl CimaClient {
     /// Get all current supply problems
     ///
     /// Returns a paginated response with all active supply problems.
-    pub async fn get_all_supply_problems(
-        &self,
-    ) -> Result<crate::models::PaginatedResponse<SupplyProblem>> {
-        self.get("psuministro")
+    #[instrument(skip_all)]
+    pub async fn get_all_supply_problems(&self) -> Result<PaginatedResponse<SupplyProblem>> {
+        self.get_supply_problems_page(None, None)
             .await
-            .context("Failed to get all supply problems")
+            .context(EndpointError::new("get_all_supply_problems", Vec::new()))
     }
 
     /// Get supply problems for a specific presentation by national code
     ///
     /// Returns a paginated response with supply problems for the specified CN
+    #[instrument(skip_all, fields(cn = national_code))]
     pub async fn get_supply_problems(
         &self,
         national_code: &str,
-    ) -> Result<crate::models::PaginatedResponse<SupplyProblem>> {
-        let endpoint = format!("psuministro/{}", national_code);
-        self.get(&endpoint)
+    ) -> Result<PaginatedResponse<SupplyProblem>> {
+        self.get_supply_problems_page(Some(national_code), None)
             .await
-            .context("Failed to get supply problems for national code")
+            .with_context(|| {
+                EndpointError::new(
+                    "get_supply_problems",
+                    vec![("cn", national_code.to_string())],
+                )
+            })
+    }
+
+    /// Get one page of the supply problems, of every presentation if `national_code` is
+    /// `None`, the first one if `page` is `None`
+    #[instrument(skip_all, fields(cn = national_code, pagina = page))]
+    pub async fn get_supply_problems_page(
+        &self,
+        national_code: Option<&str>,
+        page: Option<u32>,
+    ) -> Result<PaginatedResponse<SupplyProblem>> {
+        let endpoint = Endpoint::SupplyProblems(national_code.map(str::to_string));
+        let params: Vec<_> = page
+            .map(|p| ("pagina", p.to_string()))
+            .into_iter()
+            .collect();
+
+        self.request(&endpoint, &params).await.with_context(|| {
+            let cn = national_code.map(|cn| ("cn", cn.to_string()));
+            let params = cn.into_iter().chain(params.iter().cloned()).collect();
+            EndpointError::new("get_supply_problems_page", params)
+        })
+    }
+
+    /// Get supply problems yielding every page as it arrives, of every presentation if
+    /// `national_code` is `None`
+    pub fn get_supply_problems_pages<'a>(
+        &'a self,
+        national_code: Option<&'a str>,
+    ) -> impl Stream<Item = Result<PaginatedResponse<SupplyProblem>>> + 'a {
+        paginate(move |page| self.get_supply_problems_page(national_code, Some(page)))
     }
 }
@@ -0,0 +1,262 @@
+//! Enrichment of the generated CSV files with data from the CIMA REST API.
+
+use crate::api_client::CimaClient;
+use crate::endpoints::SearchMedicationsParams;
+use crate::parser::schema::Columns;
+use crate::parser::{PRESCRIPTIONS_CSV, ParserOptions, PrescriptionRecord, part_file_name};
+use anyhow::{Context, Result};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// File mapping the national codes of prescriptions.csv to CIMA registration numbers
+pub const PRESCRIPTION_NREGISTRO_CSV: &str = "prescription_nregistro.csv";
+
+/// Requests per second made by [`enrich_prescriptions_with_nregistro`]
+pub const DEFAULT_REQUESTS_PER_SECOND: u32 = 10;
+
+/// Codes looked up between two writes of the mapping file
+pub const DEFAULT_BATCH_SIZE: usize = 500;
+
+/// Options of [`enrich_prescriptions_with_nregistro_with_options`]
+#[derive(Debug, Clone)]
+pub struct NregistroOptions {
+    /// Requests in flight at once
+    pub concurrency: usize,
+    /// Most requests started per second, unlimited if `None`
+    pub requests_per_second: Option<u32>,
+    /// Codes looked up before the mapping file is written, so an interrupted run
+    /// keeps what it resolved
+    pub batch_size: usize,
+    /// Delimiter and header style of prescriptions.csv, also used for the mapping file
+    pub parser: ParserOptions,
+}
+
+impl Default for NregistroOptions {
+    fn default() -> Self {
+        NregistroOptions {
+            concurrency: 4,
+            requests_per_second: Some(DEFAULT_REQUESTS_PER_SECOND),
+            batch_size: DEFAULT_BATCH_SIZE,
+            parser: ParserOptions::default(),
+        }
+    }
+}
+
+/// Outcome of [`enrich_prescriptions_with_nregistro`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NregistroReport {
+    /// National codes with a registration number, cached ones included
+    pub resolved: usize,
+    /// National codes found in the mapping file of an earlier run
+    pub cached: usize,
+    /// National codes the API has no medication for, in order
+    pub unresolved: Vec<String>,
+}
+
+/// Looks up the CIMA registration number (`nregistro`) of every national code of
+/// prescriptions.csv in `output_dir` and writes them to
+/// [`PRESCRIPTION_NREGISTRO_CSV`].
+///
+/// Each distinct code is requested once from `medicamentos?cn=`, at most `concurrency`
+/// at a time and [`DEFAULT_REQUESTS_PER_SECOND`] per second. Codes already in the
+/// mapping file are not requested again, so a failed run can be resumed.
+pub async fn enrich_prescriptions_with_nregistro<P: AsRef<Path>>(
+    output_dir: P,
+    client: &CimaClient,
+    concurrency: usize,
+) -> Result<NregistroReport> {
+    let options = NregistroOptions {
+        concurrency,
+        ..Default::default()
+    };
+    enrich_prescriptions_with_nregistro_with_options(output_dir, client, &options).await
+}
+
+/// Like [`enrich_prescriptions_with_nregistro`], for CSV files written with
+/// `options.parser` and with the given request limits.
+///
+/// The mapping file has the `cod_nacion` column named as in prescriptions.csv and a
+/// `nregistro` column. Codes that do not resolve are left out of it and listed in the
+/// report.
+pub async fn enrich_prescriptions_with_nregistro_with_options<P: AsRef<Path>>(
+    output_dir: P,
+    client: &CimaClient,
+    options: &NregistroOptions,
+) -> Result<NregistroReport> {
+    let output_dir = output_dir.as_ref();
+    let codes = read_national_codes(output_dir, &options.parser)?;
+    let path = output_dir.join(PRESCRIPTION_NREGISTRO_CSV);
+    let mut mapping = read_mapping(&path, &options.parser)?;
+    mapping.retain(|code, _| codes.contains(code));
+
+    let mut report = NregistroReport {
+        cached: mapping.len(),
+        ..Default::default()
+    };
+    let pending: Vec<&String> = codes
+        .iter()
+        .filter(|code| !mapping.contains_key(*code))
+        .collect();
+    tracing::info!(
+        codes = codes.len(),
+        cached = report.cached,
+        pending = pending.len(),
+        "Looking up registration numbers"
+    );
+
+    let limiter = RateLimiter::new(options.requests_per_second);
+    for batch in pending.chunks(options.batch_size.max(1)) {
+        let results: Vec<(&String, Option<String>)> = stream::iter(batch)
+            .map(|code| async {
+                limiter.wait().await;
+                Ok::<_, anyhow::Error>((*code, lookup_nregistro(client, code).await?))
+            })
+            .buffered(options.concurrency.max(1))
+            .try_collect()
+            .await?;
+        for (code, nregistro) in results {
+            match nregistro {
+                Some(nregistro) => {
+                    mapping.insert(code.clone(), nregistro);
+                }
+                None => report.unresolved.push(code.clone()),
+            }
+        }
+        write_mapping(&path, &mapping, &options.parser)?;
+    }
+    // Also written when nothing was requested, dropping codes no longer listed
+    write_mapping(&path, &mapping, &options.parser)?;
+
+    report.resolved = mapping.len();
+    tracing::info!(
+        resolved = report.resolved,
+        unresolved = report.unresolved.len(),
+        "Registration numbers written"
+    );
+    Ok(report)
+}
+
+/// Registration number of the medication with national code `code`, if any.
+async fn lookup_nregistro(client: &CimaClient, code: &str) -> Result<Option<String>> {
+    let params = SearchMedicationsParams {
+        national_code: Some(code.to_string()),
+        ..Default::default()
+    };
+    let response = client
+        .search_medications(&params)
+        .await
+        .with_context(|| format!("Failed to look up national code {}", code))?;
+    Ok(response
+        .results
+        .into_iter()
+        .next()
+        .map(|medication| medication.nregistro))
+}
+
+/// Spaces the start of requests to at most `requests_per_second`.
+struct RateLimiter(Option<Mutex<Interval>>);
+
+impl RateLimiter {
+    fn new(requests_per_second: Option<u32>) -> Self {
+        RateLimiter(requests_per_second.filter(|rate| *rate > 0).map(|rate| {
+            let mut interval = tokio::time::interval(Duration::from_secs(1) / rate);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            Mutex::new(interval)
+        }))
+    }
+
+    async fn wait(&self) {
+        if let Some(interval) = &self.0 {
+            interval.lock().await.tick().await;
+        }
+    }
+}
+
+/// Header of the `cod_nacion` column of prescriptions.csv.
+fn national_code_header(options: &ParserOptions) -> &'static str {
+    PrescriptionRecord::COLUMNS
+        .iter()
+        .find(|column| column.name == "cod_nacion")
+        .map_or("cod_nacion", |column| column.header(options.header_style))
+}
+
+/// prescriptions.csv in `output_dir`, or its parts when split.
+fn prescription_files(output_dir: &Path) -> Result<Vec<PathBuf>> {
+    let path = output_dir.join(PRESCRIPTIONS_CSV);
+    if path.exists() {
+        return Ok(vec![path]);
+    }
+    let parts: Vec<PathBuf> = (1..)
+        .map(|part| output_dir.join(part_file_name(PRESCRIPTIONS_CSV, part)))
+        .take_while(|path| path.exists())
+        .collect();
+    if parts.is_empty() {
+        anyhow::bail!("No {} in {}", PRESCRIPTIONS_CSV, output_dir.display());
+    }
+    Ok(parts)
+}
+
+/// Distinct national codes of prescriptions.csv, in order.
+fn read_national_codes(output_dir: &Path, options: &ParserOptions) -> Result<BTreeSet<String>> {
+    let header = national_code_header(options);
+    let mut codes = BTreeSet::new();
+    for path in prescription_files(output_dir)? {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(options.delimiter)
+            .from_path(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let column = reader
+            .headers()
+            .with_context(|| format!("Failed to read the header of {}", path.display()))?
+            .iter()
+            .position(|name| name == header)
+            .with_context(|| format!("{} has no {} column", path.display(), header))?;
+        for row in reader.records() {
+            let row = row.with_context(|| format!("Failed to read {}", path.display()))?;
+            if let Some(code) = row.get(column).filter(|code| !code.is_empty()) {
+                codes.insert(code.to_string());
+            }
+        }
+    }
+    Ok(codes)
+}
+
+/// Codes and registration numbers of the mapping file at `path`, empty if missing.
+fn read_mapping(path: &Path, options: &ParserOptions) -> Result<BTreeMap<String, String>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .from_path(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut mapping = BTreeMap::new();
+    for row in reader.records() {
+        let row = row.with_context(|| format!("Failed to read {}", path.display()))?;
+        if let (Some(code), Some(nregistro)) = (row.get(0), row.get(1)) {
+            mapping.insert(code.to_string(), nregistro.to_string());
+        }
+    }
+    Ok(mapping)
+}
+
+fn write_mapping(
+    path: &Path,
+    mapping: &BTreeMap<String, String>,
+    options: &ParserOptions,
+) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut wtr = options.csv_writer(file);
+    wtr.write_record([national_code_header(options), "nregistro"])?;
+    for (code, nregistro) in mapping {
+        wtr.write_record([code, nregistro])?;
+    }
+    wtr.flush()
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,349 @@
+use crate::models::{
+    AuthorizationStatus, ChangeRecord, ClinicalDescription, MasterItem, MedicationSummary,
+    PresentationSummary, SupplyProblem, api_datetime,
+};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// API results that can be exported as CSV rows
+pub trait CsvRecord {
+    /// Column names, in the order of [`CsvRecord::record`]
+    const HEADERS: &'static [&'static str];
+
+    /// Values of the columns for this item
+    fn record(&self) -> Vec<String>;
+}
+
+/// CSV file written one row at a time, so that results can be exported as they arrive
+pub struct CsvExport<T> {
+    wtr: csv::Writer<File>,
+    rows: usize,
+    item: PhantomData<fn(&T)>,
+}
+
+impl<T: CsvRecord> CsvExport<T> {
+    /// Creates the file at `path`, replacing any existing one, and writes the header
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut wtr = csv::Writer::from_path(path).context("Failed to create CSV file")?;
+        wtr.write_record(T::HEADERS)?;
+        Ok(Self {
+            wtr,
+            rows: 0,
+            item: PhantomData,
+        })
+    }
+
+    /// Writes the row of `item`
+    pub fn write(&mut self, item: &T) -> Result<()> {
+        self.wtr.write_record(item.record())?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Flushes the file, returning the number of rows written
+    pub fn finish(mut self) -> Result<usize> {
+        self.wtr.flush().context("Failed to write CSV file")?;
+        Ok(self.rows)
+    }
+}
+
+/// Writes `items` to a CSV file with the columns of [`CsvRecord::HEADERS`]
+fn write_csv<T: CsvRecord, P: AsRef<Path>>(items: &[T], path: P) -> Result<()> {
+    let mut export = CsvExport::create(path)?;
+    for item in items {
+        export.write(item)?;
+    }
+    export.finish()?;
+    Ok(())
+}
+
+/// Date of a CIMA timestamp (milliseconds since the Unix epoch, GMT+2:00) as `YYYY-MM-DD`
+fn iso_date(millis: i64) -> String {
+    api_datetime(millis)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+fn optional_date(millis: Option<i64>) -> String {
+    millis.map(iso_date).unwrap_or_default()
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn status_dates(status: &AuthorizationStatus) -> [String; 3] {
+    [
+        optional_date(status.aut),
+        optional_date(status.susp),
+        optional_date(status.rev),
+    ]
+}
+
+impl CsvRecord for ClinicalDescription {
+    const HEADERS: &'static [&'static str] = &[
+        "vmp",
+        "vmp_desc",
+        "vmpp",
+        "vmpp_desc",
+        "commercialized_presentations",
+    ];
+
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.vmp.clone(),
+            self.vmp_desc.clone(),
+            self.vmpp.clone(),
+            self.vmpp_desc.clone(),
+            self.commercialized_presentations.to_string(),
+        ]
+    }
+}
+
+impl CsvRecord for MasterItem {
+    const HEADERS: &'static [&'static str] = &["id", "code", "name"];
+
+    fn record(&self) -> Vec<String> {
+        vec![
+            optional(self.id),
+            self.code.clone().unwrap_or_default(),
+            self.name.clone(),
+        ]
+    }
+}
+
+impl CsvRecord for MedicationSummary {
+    const HEADERS: &'static [&'static str] = &[
+        "nregistro",
+        "name",
+        "labtitular",
+        "cpresc",
+        "commercialized",
+        "prescription_required",
+        "black_triangle",
+        "orphan",
+        "psum",
+        "authorization_date",
+        "suspension_date",
+        "revocation_date",
+    ];
+
+    fn record(&self) -> Vec<String> {
+        let mut record = vec![
+            self.nregistro.clone(),
+            self.name.clone(),
+            self.labtitular.clone(),
+            self.cpresc.clone(),
+            optional(self.commercialized),
+            optional(self.prescription_required),
+            optional(self.black_triangle),
+            optional(self.orphan),
+            optional(self.psum),
+        ];
+        record.extend(status_dates(&self.status));
+        record
+    }
+}
+
+impl CsvRecord for PresentationSummary {
+    const HEADERS: &'static [&'static str] = &[
+        "cn",
+        "name",
+        "commercialized",
+        "psum",
+        "authorization_date",
+        "suspension_date",
+        "revocation_date",
+    ];
+
+    fn record(&self) -> Vec<String> {
+        let mut record = vec![
+            self.cn.clone(),
+            self.name.clone(),
+            self.commercialized.to_string(),
+            optional(self.psum),
+        ];
+        record.extend(status_dates(&self.status));
+        record
+    }
+}
+
+impl CsvRecord for SupplyProblem {
+    const HEADERS: &'static [&'static str] = &[
+        "cn",
+        "name",
+        "start_date",
+        "end_date",
+        "active",
+        "observations",
+    ];
+
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.cn.clone(),
+            self.name.clone(),
+            iso_date(self.fini),
+            optional_date(self.ffin),
+            self.active.to_string(),
+            self.observations.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl CsvRecord for ChangeRecord {
+    const HEADERS: &'static [&'static str] = &["nregistro", "date", "change_type", "changes"];
+
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.nregistro.clone(),
+            iso_date(self.date),
+            self.change_type.to_string(),
+            self.changes.join(";"),
+        ]
+    }
+}
+
+/// Writes clinical descriptions (VMP/VMPP) to a CSV file.
+///
+/// Columns: `vmp`, `vmp_desc`, `vmpp`, `vmpp_desc`, `commercialized_presentations`.
+pub fn write_clinical_descriptions_csv<P: AsRef<Path>>(
+    items: &[ClinicalDescription],
+    path: P,
+) -> Result<()> {
+    write_csv(items, path)
+}
+
+/// Writes medication search results to a CSV file.
+///
+/// Columns: `nregistro`, `name`, `labtitular`, `cpresc`, the commercialized, prescription,
+/// black triangle, orphan and supply problem flags, and the authorization, suspension and
+/// revocation dates as `YYYY-MM-DD`.
+pub fn write_medications_csv<P: AsRef<Path>>(items: &[MedicationSummary], path: P) -> Result<()> {
+    write_csv(items, path)
+}
+
+/// Writes presentation search results to a CSV file.
+///
+/// Columns: `cn`, `name`, `commercialized`, `psum` and the authorization, suspension and
+/// revocation dates as `YYYY-MM-DD`.
+pub fn write_presentations_csv<P: AsRef<Path>>(
+    items: &[PresentationSummary],
+    path: P,
+) -> Result<()> {
+    write_csv(items, path)
+}
+
+/// Writes supply problems to a CSV file.
+///
+/// Columns: `cn`, `name`, `start_date`, `end_date`, `active`, `observations`.
+pub fn write_supply_problems_csv<P: AsRef<Path>>(items: &[SupplyProblem], path: P) -> Result<()> {
+    write_csv(items, path)
+}
+
+/// Writes change log records to a CSV file.
+///
+/// Columns: `nregistro`, `date`, `change_type` (1 new, 2 deleted, 3 modified) and
+/// `changes`, separated by `;`.
+pub fn write_changes_csv<P: AsRef<Path>>(items: &[ChangeRecord], path: P) -> Result<()> {
+    write_csv(items, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn description(vmpp: &str, vmpp_desc: &str, count: i32) -> ClinicalDescription {
+        ClinicalDescription {
+            vmp: "3456789".to_string(),
+            vmp_desc: "Paracetamol 1 g comprimido".to_string(),
+            vmpp: vmpp.to_string(),
+            vmpp_desc: vmpp_desc.to_string(),
+            commercialized_presentations: count,
+        }
+    }
+
+    #[test]
+    fn test_write_clinical_descriptions_csv() {
+        let items = vec![
+            description("1234567", "Paracetamol 1 g 40 comprimidos", 12),
+            description("1234568", "Paracetamol 1 g, 20 comprimidos", 3),
+            description("1234569", "Paracetamol \"EFG\" 1 g", 0),
+        ];
+
+        let csv_file = NamedTempFile::new().unwrap();
+        let result = write_clinical_descriptions_csv(&items, csv_file.path());
+        assert!(result.is_ok());
+
+        let mut csv_reader = csv::Reader::from_path(csv_file.path()).unwrap();
+
+        let headers = csv_reader.headers().unwrap();
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            vec![
+                "vmp",
+                "vmp_desc",
+                "vmpp",
+                "vmpp_desc",
+                "commercialized_presentations"
+            ]
+        );
+
+        let records: Vec<csv::StringRecord> = csv_reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].get(4).unwrap(), "12");
+        assert_eq!(
+            records[1].get(3).unwrap(),
+            "Paracetamol 1 g, 20 comprimidos"
+        );
+        assert_eq!(records[2].get(3).unwrap(), "Paracetamol \"EFG\" 1 g");
+    }
+
+    #[test]
+    fn test_write_empty_clinical_descriptions_csv() {
+        let csv_file = NamedTempFile::new().unwrap();
+        write_clinical_descriptions_csv(&[], csv_file.path()).unwrap();
+
+        let mut csv_reader = csv::Reader::from_path(csv_file.path()).unwrap();
+        assert_eq!(csv_reader.headers().unwrap().len(), 5);
+        assert_eq!(csv_reader.records().count(), 0);
+    }
+
+    #[test]
+    fn test_iso_date() {
+        // 2024-03-01 00:00 in GMT+2:00
+        assert_eq!(iso_date(1_709_244_000_000), "2024-03-01");
+        assert_eq!(optional_date(None), "");
+    }
+
+    #[test]
+    fn test_write_medications_csv() {
+        let medication: MedicationSummary = serde_json::from_value(serde_json::json!({
+            "nregistro": "60000",
+            "nombre": "PARACETAMOL EJEMPLO 500 MG COMPRIMIDOS",
+            "labtitular": "Laboratorio Ejemplo",
+            "estado": { "aut": 1_709_244_000_000_i64 },
+            "cpresc": "Sin receta",
+            "comerc": true
+        }))
+        .unwrap();
+
+        let csv_file = NamedTempFile::new().unwrap();
+        write_medications_csv(&[medication], csv_file.path()).unwrap();
+
+        let mut csv_reader = csv::Reader::from_path(csv_file.path()).unwrap();
+        assert_eq!(
+            csv_reader.headers().unwrap(),
+            MedicationSummary::HEADERS.to_vec()
+        );
+        let records: Vec<csv::StringRecord> = csv_reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(&records[0][0], "60000");
+        assert_eq!(&records[0][4], "true");
+        assert_eq!(&records[0][5], "");
+        assert_eq!(&records[0][9], "2024-03-01");
+        assert_eq!(&records[0][10], "");
+    }
+}
@@ -1,20 +1,54 @@
-#![doc = include_str!("../README.md")]
+#![cfg_attr(
+    all(feature = "api", feature = "parser", feature = "downloader"),
+    doc = include_str!("../README.md")
+)]
 
+#[cfg(feature = "api")]
 pub mod api_client;
+#[cfg(feature = "record-replay")]
+pub mod cassette;
+#[cfg(feature = "dataset")]
+pub mod dataset;
+#[cfg(feature = "downloader")]
 pub mod downloader;
+#[cfg(feature = "api")]
 pub mod endpoints;
+#[cfg(all(feature = "api", feature = "parser"))]
+pub mod enrich;
+#[cfg(feature = "api")]
+pub mod export;
+#[cfg(feature = "api")]
 pub mod models;
+#[cfg(feature = "api")]
+pub mod pagination;
+#[cfg(feature = "parser")]
 pub mod parser;
+pub mod prelude;
+pub mod telemetry;
+#[cfg(feature = "test-util")]
+pub mod testing;
+
+/// `User-Agent` header of the requests sent by the crate
+pub const USER_AGENT: &str = concat!("cima-rs/", env!("CARGO_PKG_VERSION"));
 
 // Re-export main types for convenience
-pub use api_client::CimaClient;
+#[cfg(feature = "api")]
+pub use api_client::{
+    ApiStatusError, CimaClient, CimaClientBuilder, CimaClientOptions, EndpointError, RetryPolicy,
+};
+#[cfg(feature = "record-replay")]
+pub use cassette::{Cassette, RecordMode, Scrub};
+#[cfg(feature = "api")]
 pub use endpoints::{
-    MasterDataParams, SearchClinicalDescriptionParams, SearchMedicationsParams,
-    SearchPresentationsParams, TechnicalSheetQuery,
+    ClinicalDescriptionFetch, ClinicalDescriptionFetchOpts, DocumentDownload,
+    DocumentDownloadOptions, DocumentDownloadStatus, Endpoint, MasterDataParams, MedicationId,
+    PhotoDownload, QueryParams, RawEndpoint, SafetyNoteDownload, SearchClinicalDescriptionParams,
+    SearchMedicationsParams, SearchPresentationsParams, TechnicalSheetQuery,
 };
+#[cfg(feature = "api")]
 pub use models::{
-    ActiveIngredient, AtcCode, AuthorizationStatus, ChangeRecord, ClinicalDescription, Document,
-    DocumentType, Excipient, MasterDataType, MasterItem, MaterialDocument, Medication,
-    MedicationSummary, PaginatedResponse, Photo, Presentation, PresentationSummary, SafetyMaterial,
-    SafetyNote, Section, SupplyProblem,
+    ActiveIngredient, AtcCode, AuthorizationStatus, ChangeAspect, ChangeRecord, ChangeType,
+    ClinicalDescription, Document, DocumentType, Excipient, MasterDataType, MasterItem,
+    MaterialDocument, Medication, MedicationSummary, PaginatedResponse, Photo, PhotoType,
+    Presentation, PresentationSummary, SafetyMaterial, SafetyNote, Section, SupplyProblem,
 };
@@ -1,3 +1,4 @@
+use chrono::{DateTime, FixedOffset, TimeDelta};
 use serde::{Deserialize, Serialize};
 
 /// Wrapper for paginated API responses
@@ -62,6 +63,23 @@ pub struct SupplyProblem {
     pub active: bool,
 }
 
+impl SupplyProblem {
+    /// Start date in the GMT+2:00 time zone of the API
+    pub fn start_date(&self) -> Option<DateTime<FixedOffset>> {
+        api_datetime(self.fini)
+    }
+
+    /// Expected end or resolution date in the GMT+2:00 time zone of the API
+    pub fn end_date(&self) -> Option<DateTime<FixedOffset>> {
+        api_datetime(self.ffin?)
+    }
+
+    /// Time between [`fini`](Self::fini) and [`ffin`](Self::ffin), `None` without an end date
+    pub fn duration(&self) -> Option<TimeDelta> {
+        Some(TimeDelta::milliseconds(self.ffin? - self.fini))
+    }
+}
+
 /// Document section
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Section {
@@ -79,6 +97,114 @@ pub struct Section {
     pub content: Option<String>,
 }
 
+impl Section {
+    /// Content as plain text, with one line per paragraph, list item or table row
+    pub fn text(&self) -> Option<String> {
+        self.content.as_deref().map(html_to_text)
+    }
+}
+
+/// Plain text of an HTML fragment: tags are dropped, block elements and `<br>` end a
+/// line, list items start with a dash and character references are decoded.
+fn html_to_text(html: &str) -> String {
+    let mut raw = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        raw.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match name.as_str() {
+            "li" if !tag.starts_with('/') => raw.push_str("\n- "),
+            "br" | "p" | "div" | "li" | "tr" | "ul" | "ol" | "table" | "h1" | "h2" | "h3"
+            | "h4" | "h5" | "h6" => raw.push('\n'),
+            "td" | "th" => raw.push(' '),
+            _ => {}
+        }
+        rest = &rest[start + end + 1..];
+    }
+    raw.push_str(rest);
+
+    decode_entities(&raw)
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty() && line != "-")
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `text` with its numeric and usual named character references decoded.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 8)
+            .map(|end| &rest[1..end + 1]);
+        let character = reference.and_then(|name| match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            "aacute" => Some('á'),
+            "eacute" => Some('é'),
+            "iacute" => Some('í'),
+            "oacute" => Some('ó'),
+            "uacute" => Some('ú'),
+            "Aacute" => Some('Á'),
+            "Eacute" => Some('É'),
+            "Iacute" => Some('Í'),
+            "Oacute" => Some('Ó'),
+            "Uacute" => Some('Ú'),
+            "ntilde" => Some('ñ'),
+            "Ntilde" => Some('Ñ'),
+            "uuml" => Some('ü'),
+            "Uuml" => Some('Ü'),
+            "iquest" => Some('¿'),
+            "iexcl" => Some('¡'),
+            "ordm" => Some('º'),
+            "ordf" => Some('ª'),
+            "deg" => Some('°'),
+            "micro" => Some('µ'),
+            "middot" => Some('·'),
+            "plusmn" => Some('±'),
+            _ => {
+                let number = name.strip_prefix('#')?;
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (reference, character) {
+            (Some(name), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[name.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
 /// Document type
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(u8)]
@@ -97,6 +223,18 @@ pub enum DocumentType {
     RiskManagementPlan = 4,
 }
 
+impl DocumentType {
+    /// Short name the CIMA website uses in the document URLs, such as `ft`
+    pub fn code(self) -> &'static str {
+        match self {
+            DocumentType::TechnicalSheet => "ft",
+            DocumentType::PackageLeaflet => "p",
+            DocumentType::PublicReport => "ipe",
+            DocumentType::RiskManagementPlan => "pgr",
+        }
+    }
+}
+
 /// Document associated with a medication
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Document {
@@ -137,6 +275,13 @@ pub struct SafetyNote {
     pub url: String,
 }
 
+impl SafetyNote {
+    /// Publication date in the GMT+2:00 time zone of the API
+    pub fn datetime(&self) -> Option<DateTime<FixedOffset>> {
+        api_datetime(self.date)
+    }
+}
+
 /// Informative material document
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MaterialDocument {
@@ -246,6 +391,34 @@ pub struct Photo {
     pub date: Option<i64>,
 }
 
+impl Photo {
+    /// Kind of photo, `None` for a type the API did not use so far
+    pub fn kind(&self) -> Option<PhotoType> {
+        [PhotoType::Packaging, PhotoType::PharmaceuticalForm]
+            .into_iter()
+            .find(|kind| kind.code() == self.photo_type)
+    }
+}
+
+/// Kind of a [`Photo`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhotoType {
+    /// Packaging material
+    Packaging,
+    /// Pharmaceutical form, such as the tablets
+    PharmaceuticalForm,
+}
+
+impl PhotoType {
+    /// Value of [`Photo::photo_type`] for this kind
+    pub fn code(self) -> &'static str {
+        match self {
+            PhotoType::Packaging => "materialas",
+            PhotoType::PharmaceuticalForm => "formafarmac",
+        }
+    }
+}
+
 /// Presentation of a medication (simplified view for listings)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PresentationSummary {
@@ -456,6 +629,131 @@ pub struct ChangeRecord {
     pub changes: Vec<String>,
 }
 
+impl ChangeRecord {
+    /// Typed [`change_type`](Self::change_type), `None` for an unknown code
+    pub fn kind(&self) -> Option<ChangeType> {
+        ChangeType::from_code(self.change_type)
+    }
+
+    /// Typed [`changes`](Self::changes), leaving out unknown ones
+    pub fn aspects(&self) -> impl Iterator<Item = ChangeAspect> + '_ {
+        self.changes
+            .iter()
+            .filter_map(|change| ChangeAspect::from_code(change))
+    }
+
+    /// Date of the change in the GMT+2:00 time zone of the API
+    pub fn datetime(&self) -> Option<DateTime<FixedOffset>> {
+        api_datetime(self.date)
+    }
+}
+
+/// `millis` since the Unix Epoch in the GMT+2:00 time zone the API uses
+pub(crate) fn api_datetime(millis: i64) -> Option<DateTime<FixedOffset>> {
+    let offset = FixedOffset::east_opt(2 * 3600)?;
+    Some(DateTime::from_timestamp_millis(millis)?.with_timezone(&offset))
+}
+
+/// Type of a [`ChangeRecord`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ChangeType {
+    New = 1,
+    Deleted = 2,
+    Modified = 3,
+}
+
+impl ChangeType {
+    /// Type with the `tipoCambio` code `code`
+    pub fn from_code(code: u8) -> Option<ChangeType> {
+        match code {
+            1 => Some(ChangeType::New),
+            2 => Some(ChangeType::Deleted),
+            3 => Some(ChangeType::Modified),
+            _ => None,
+        }
+    }
+
+    /// Spanish label, such as `baja`
+    pub fn label(self) -> &'static str {
+        match self {
+            ChangeType::New => "nuevo",
+            ChangeType::Deleted => "baja",
+            ChangeType::Modified => "modificado",
+        }
+    }
+}
+
+/// What changed in a medication, listed in [`ChangeRecord::changes`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeAspect {
+    /// Authorization status (`estado`)
+    Status,
+    /// Commercialization (`comerc`)
+    Commercialization,
+    /// Patient leaflet (`prosp`)
+    Leaflet,
+    /// Technical data sheet (`ft`)
+    TechnicalSheet,
+    /// Supply problems (`psum`)
+    SupplyProblems,
+    /// Safety notes (`notasSeguridad`)
+    SafetyNotes,
+    /// Informative materials (`matinf`)
+    InformativeMaterials,
+    /// Anything else (`otros`)
+    Other,
+}
+
+impl ChangeAspect {
+    /// Every aspect, in the order the API documents them
+    pub const ALL: [ChangeAspect; 8] = [
+        ChangeAspect::Status,
+        ChangeAspect::Commercialization,
+        ChangeAspect::Leaflet,
+        ChangeAspect::TechnicalSheet,
+        ChangeAspect::SupplyProblems,
+        ChangeAspect::SafetyNotes,
+        ChangeAspect::InformativeMaterials,
+        ChangeAspect::Other,
+    ];
+
+    /// Code used by the API
+    pub fn code(self) -> &'static str {
+        match self {
+            ChangeAspect::Status => "estado",
+            ChangeAspect::Commercialization => "comerc",
+            ChangeAspect::Leaflet => "prosp",
+            ChangeAspect::TechnicalSheet => "ft",
+            ChangeAspect::SupplyProblems => "psum",
+            ChangeAspect::SafetyNotes => "notasSeguridad",
+            ChangeAspect::InformativeMaterials => "matinf",
+            ChangeAspect::Other => "otros",
+        }
+    }
+
+    /// Aspect with the API code `code`
+    pub fn from_code(code: &str) -> Option<ChangeAspect> {
+        ChangeAspect::ALL
+            .into_iter()
+            .find(|aspect| aspect.code() == code)
+    }
+
+    /// Spanish label, such as `ficha técnica actualizada`
+    pub fn label(self) -> &'static str {
+        match self {
+            ChangeAspect::Status => "estado de autorización modificado",
+            ChangeAspect::Commercialization => "comercialización modificada",
+            ChangeAspect::Leaflet => "prospecto actualizado",
+            ChangeAspect::TechnicalSheet => "ficha técnica actualizada",
+            ChangeAspect::SupplyProblems => "problema de suministro modificado",
+            ChangeAspect::SafetyNotes => "nota de seguridad publicada",
+            ChangeAspect::InformativeMaterials => "materiales informativos actualizados",
+            ChangeAspect::Other => "otros cambios",
+        }
+    }
+}
+
 /// Master data type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -477,3 +775,87 @@ impl MasterDataType {
         self as u8
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section_text() {
+        let section = Section {
+            section: "4.3".to_string(),
+            title: "Contraindicaciones".to_string(),
+            order: 7,
+            content: Some(
+                "<h2 class=\"t\">4.3. Contraindicaciones</h2>\n<p>Hipersensibilidad al \
+                 principio activo o a alguno de los excipientes incluidos en la \
+                 secci&oacute;n&nbsp;6.1.</p><ul><li>Insuficiencia hep&aacute;tica \
+                 grave</li><li>Ni&ntilde;os &lt; 6 a&#241;os &amp; &#x2265;70&nbsp;kg</li></ul>\
+                 <table><tr><td>Dosis</td><td>500 mg</td></tr></table>AT&T &unknown;"
+                    .to_string(),
+            ),
+        };
+
+        assert_eq!(
+            section.text().unwrap(),
+            "4.3. Contraindicaciones\n\
+             Hipersensibilidad al principio activo o a alguno de los excipientes incluidos \
+             en la sección 6.1.\n\
+             - Insuficiencia hepática grave\n\
+             - Niños < 6 años & ≥70 kg\n\
+             Dosis 500 mg\n\
+             AT&T &unknown;"
+        );
+    }
+
+    #[test]
+    fn test_change_record_typed_fields() {
+        let record: ChangeRecord = serde_json::from_value(serde_json::json!({
+            "nregistro": "60000",
+            "fecha": 1_705_273_200_000_i64,
+            "tipoCambio": 3,
+            "cambios": ["ft", "desconocido", "notasSeguridad"]
+        }))
+        .unwrap();
+
+        assert_eq!(record.kind(), Some(ChangeType::Modified));
+        assert_eq!(
+            record.aspects().collect::<Vec<_>>(),
+            [ChangeAspect::TechnicalSheet, ChangeAspect::SafetyNotes]
+        );
+        assert_eq!(
+            record.datetime().unwrap().to_rfc3339(),
+            "2024-01-15T01:00:00+02:00"
+        );
+        assert_eq!(ChangeType::from_code(4), None);
+        for aspect in ChangeAspect::ALL {
+            assert_eq!(ChangeAspect::from_code(aspect.code()), Some(aspect));
+        }
+    }
+
+    #[test]
+    fn test_supply_problem_dates() {
+        let mut problem: SupplyProblem = serde_json::from_value(serde_json::json!({
+            "cn": "600000",
+            "nombre": "PARACETAMOL EJEMPLO 500 MG 20 COMPRIMIDOS",
+            "fini": 1_709_244_000_000_i64,
+            "ffin": 1_711_922_400_000_i64,
+            "activo": false
+        }))
+        .unwrap();
+
+        assert_eq!(
+            problem.start_date().unwrap().to_rfc3339(),
+            "2024-03-01T00:00:00+02:00"
+        );
+        assert_eq!(
+            problem.end_date().unwrap().to_rfc3339(),
+            "2024-04-01T00:00:00+02:00"
+        );
+        assert_eq!(problem.duration().unwrap().num_days(), 31);
+
+        problem.ffin = None;
+        assert!(problem.end_date().is_none());
+        assert!(problem.duration().is_none());
+    }
+}
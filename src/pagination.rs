@@ -0,0 +1,181 @@
+//! Iteration over the pages of the paginated CIMA endpoints
+
+use crate::models::PaginatedResponse;
+use anyhow::Result;
+use futures::{Stream, TryStreamExt};
+
+/// Requests pages from 1 with `fetch` until all rows reported by the API have been
+/// received, yielding each page as it arrives
+///
+/// Only one page is held at a time. The stream ends after the first empty page, or
+/// the first one with fewer rows than its page size.
+pub fn paginate<T, F, Fut>(fetch: F) -> impl Stream<Item = Result<PaginatedResponse<T>>>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<PaginatedResponse<T>>>,
+{
+    futures::stream::try_unfold(
+        (fetch, Some(1), 0),
+        |(mut fetch, page, scanned)| async move {
+            let Some(page) = page else {
+                return Ok(None);
+            };
+            let response = fetch(page).await?;
+            let received = response.results.len();
+            let scanned = scanned + received;
+            let short = response.page_size > 0 && received < response.page_size as usize;
+            let next = (received > 0 && !short && scanned < response.total_rows as usize)
+                .then_some(page + 1);
+
+            Ok(Some((response, (fetch, next, scanned))))
+        },
+    )
+}
+
+/// Requests every page with `fetch`, as [`paginate`] does, and returns the rows of all
+/// of them in order
+///
+/// ```no_run
+/// # async fn example(client: &cima_rs::CimaClient) -> anyhow::Result<()> {
+/// use cima_rs::SearchMedicationsParams;
+/// use cima_rs::pagination::collect_all_pages;
+///
+/// let params = SearchMedicationsParams {
+///     name: Some("paracetamol".to_string()),
+///     ..Default::default()
+/// };
+/// let medications = collect_all_pages(|page| {
+///     let params = SearchMedicationsParams {
+///         page: Some(page),
+///         ..params.clone()
+///     };
+///     async move { client.search_medications(&params).await }
+/// })
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn collect_all_pages<T, F, Fut>(fetch: F) -> Result<Vec<T>>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<PaginatedResponse<T>>>,
+{
+    paginate(fetch)
+        .try_fold(Vec::new(), |mut rows, page| async move {
+            rows.extend(page.results);
+            Ok(rows)
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(page: u32, total_rows: u32, results: Vec<u32>) -> PaginatedResponse<u32> {
+        PaginatedResponse {
+            total_rows,
+            page,
+            page_size: 2,
+            results,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_paginate_until_total_rows() {
+        let pages: Vec<_> = paginate(|n| async move {
+            Ok(match n {
+                1 => page(1, 3, vec![1, 2]),
+                2 => page(2, 3, vec![3]),
+                _ => panic!("page {n} requested"),
+            })
+        })
+        .try_collect()
+        .await
+        .unwrap();
+
+        let rows: Vec<u32> = pages.into_iter().flat_map(|p| p.results).collect();
+        assert_eq!(rows, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_paginate_stops_at_empty_page() {
+        let pages: Vec<_> = paginate(|n| async move {
+            Ok(match n {
+                1 => page(1, 10, vec![1, 2]),
+                2 => page(2, 10, vec![]),
+                _ => panic!("page {n} requested"),
+            })
+        })
+        .try_collect()
+        .await
+        .unwrap();
+
+        assert_eq!(pages.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_paginate_stops_at_short_page() {
+        let pages: Vec<_> = paginate(|n| async move {
+            Ok(match n {
+                1 => page(1, 10, vec![1, 2]),
+                2 => page(2, 10, vec![3]),
+                _ => panic!("page {n} requested"),
+            })
+        })
+        .try_collect()
+        .await
+        .unwrap();
+
+        assert_eq!(pages.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_collect_all_pages() {
+        let rows = collect_all_pages(|n| async move {
+            Ok(match n {
+                1 => page(1, 5, vec![1, 2]),
+                2 => page(2, 5, vec![3, 4]),
+                3 => page(3, 5, vec![5]),
+                _ => panic!("page {n} requested"),
+            })
+        })
+        .await
+        .unwrap();
+        assert_eq!(rows, [1, 2, 3, 4, 5]);
+
+        let single = collect_all_pages(|n| async move { Ok(page(n, 2, vec![1, 2])) })
+            .await
+            .unwrap();
+        assert_eq!(single, [1, 2]);
+
+        let mut requested = 0;
+        let none = collect_all_pages(|n| {
+            requested += 1;
+            async move { Ok(page(n, 0, vec![])) }
+        })
+        .await
+        .unwrap();
+        assert!(none.is_empty());
+        assert_eq!(requested, 1);
+    }
+
+    #[tokio::test]
+    async fn test_paginate_stops_on_error() {
+        let mut requested = Vec::new();
+        let result: Result<Vec<_>> = paginate(|n| {
+            requested.push(n);
+            async move {
+                match n {
+                    1 => Ok(page(1, 10, vec![1, 2])),
+                    _ => anyhow::bail!("page {n} failed"),
+                }
+            }
+        })
+        .try_collect()
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(requested, [1, 2]);
+    }
+}
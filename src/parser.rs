@@ -1,31 +1,314 @@
+use self::dedup::NaturalKey;
 use anyhow::{Context, Result};
+use encoding_rs::{Encoding, UTF_8};
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+use quick_xml::Reader;
 use quick_xml::de::from_reader;
+use quick_xml::errors::IllFormedError;
+use quick_xml::events::{BytesEnd, Event};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::path::Path;
+use std::time::Instant;
+
+#[cfg(feature = "arrow")]
+mod arrow;
+mod codegen;
+mod csv_row;
+mod dates;
+mod dedup;
+mod delta;
+mod dictionary;
+mod diff;
+mod drift;
+mod generic;
+mod load;
+mod manifest;
+mod nomenclator;
+mod numbers;
+mod options;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod parts;
+mod pipeline;
+mod postgres;
+mod report;
+#[cfg(any(feature = "parquet", feature = "sqlite"))]
+mod rows;
+pub mod schema;
+mod sinks;
+mod sorting;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stats;
+mod validate;
+mod xml_de;
+#[cfg(feature = "validate-xml")]
+mod xsd;
+
+#[cfg(feature = "arrow")]
+pub use self::arrow::{
+    PrescriptionBatches, arrow_schema, parse_atc_xml_to_record_batch,
+    parse_dcp_xml_to_record_batch, parse_dcpf_xml_to_record_batch, parse_dcsa_xml_to_record_batch,
+    parse_envases_xml_to_record_batch, parse_excipientes_xml_to_record_batch,
+    parse_forma_farmaceutica_simplificada_xml_to_record_batch,
+    parse_forma_farmaceutica_xml_to_record_batch, parse_laboratorio_xml_to_record_batch,
+    parse_principio_activo_xml_to_record_batch, parse_situacion_registro_xml_to_record_batch,
+    parse_unidad_contenido_xml_to_record_batch, parse_via_administracion_xml_to_record_batch,
+    prescriptions_to_record_batches, records_to_record_batch,
+};
+pub use self::codegen::{
+    ENUM_DICTIONARIES, dictionary_codes, generate_dictionary_enum, generate_dictionary_enums,
+};
+pub use self::dates::DATE_COLUMNS;
+pub use self::delta::{
+    PrescriptionDelta, apply_delta, apply_delta_with_options, parse_prescription_delta_xml,
+    parse_prescription_delta_xml_from_reader,
+};
+pub use self::dictionary::DictionaryKind;
+pub use self::diff::{
+    DiffOptions, RowChange, RowChangeKind, TableDiff, diff_csv_dirs, diff_csv_dirs_with_options,
+    natural_key,
+};
+pub use self::generic::{
+    generic_dictionary_to_csv, generic_dictionary_to_csv_from_reader_with_options,
+    generic_dictionary_to_csv_with_options, parse_generic_dictionary_xml,
+    parse_generic_dictionary_xml_from_reader,
+};
+pub use self::load::{
+    load_atc_csv, load_atc_csv_with_options, load_dcp_csv, load_dcp_csv_with_options,
+    load_dcpf_csv, load_dcpf_csv_with_options, load_dcsa_csv, load_dcsa_csv_with_options,
+    load_envases_csv, load_envases_csv_with_options, load_excipientes_csv,
+    load_excipientes_csv_with_options, load_forma_farmaceutica_csv,
+    load_forma_farmaceutica_csv_with_options, load_forma_farmaceutica_simplificada_csv,
+    load_forma_farmaceutica_simplificada_csv_with_options, load_laboratorio_csv,
+    load_laboratorio_csv_with_options, load_prescriptions_csv, load_prescriptions_csv_with_options,
+    load_principio_activo_csv, load_principio_activo_csv_with_options, load_situacion_registro_csv,
+    load_situacion_registro_csv_with_options, load_unidad_contenido_csv,
+    load_unidad_contenido_csv_with_options, load_via_administracion_csv,
+    load_via_administracion_csv_with_options,
+};
+pub use self::manifest::{MANIFEST_JSON, Manifest, SourceEntry};
+pub use self::nomenclator::{
+    FileOutcome, FileProgressCallback, FileStatus, NomenclatorFile, NomenclatorOptions,
+    NomenclatorReport, PRESCRIPTIONS_NDJSON, parse_all_nomenclator, render_summary,
+};
+pub use self::numbers::NUMERIC_COLUMNS;
+#[cfg(feature = "validate-xml")]
+pub use self::options::OnViolation;
+pub use self::options::{
+    BoolParsing, BoolRepr, HeaderStyle, OnDuplicate, OnError, ParserOptions, PrescriptionKey,
+    ProgressCallback, QuoteStyle,
+};
+#[cfg(feature = "parquet")]
+pub use self::parquet::*;
+pub use self::parts::part_file_name;
+pub use self::postgres::{
+    POSTGRES_IMPORT_SQL, POSTGRES_SCHEMA_SQL, generate_postgres_schema,
+    generate_postgres_schema_with_options, postgres_import_sql, postgres_import_sql_with_options,
+    postgres_schema_sql, postgres_schema_sql_with_options,
+};
+pub use self::report::{ParseProgress, ParseReport, ParseStats, RecordError};
+use self::report::{parse_span, record_parse};
+pub use self::sinks::{
+    PrescriptionSinks, parse_prescription_xml_to_sinks,
+    parse_prescription_xml_to_sinks_with_options,
+};
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::{
+    load_csv_dir_into_sqlite, load_csv_dir_into_sqlite_with_options, load_nomenclator_into_sqlite,
+    parse_nomenclator_to_sqlite,
+};
+pub use self::stats::{
+    Anomaly, CsvDirStats, FileStats, LaboratoryCount, PrescriptionStats, StatsOptions,
+    csv_dir_stats, csv_dir_stats_with_options, previous_row_counts,
+};
+pub use self::validate::{
+    RelationshipReport, ValidationReport, validate_nomenclator_output,
+    validate_nomenclator_output_with_options,
+};
+#[cfg(feature = "validate-xml")]
+pub use self::xsd::{XsdViolation, validate_against_xsd, validate_against_xsd_from_reader};
+pub use chrono::NaiveDate;
 
 // Helper module for deserializing "0"/"1" strings as booleans
 mod bool_from_string {
-    use serde::{Deserialize, Deserializer};
+    use super::options::BoolParsing;
+    use serde::Deserializer;
+    use serde::de::{self, Visitor};
+    use std::cell::Cell;
+    use std::fmt;
+
+    thread_local! {
+        // Serde gives deserialize_with functions no context, so the parsing mode of
+        // the current record is kept per thread
+        static PARSING: Cell<BoolParsing> = Cell::new(BoolParsing::default());
+        static EMPTY_VALUES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Runs `f` with the given parsing mode, returning its result and the number of
+    /// empty flags read.
+    pub(super) fn with_parsing<T>(parsing: BoolParsing, f: impl FnOnce() -> T) -> (T, usize) {
+        let previous = PARSING.replace(parsing);
+        let previous_empty = EMPTY_VALUES.replace(0);
+        let result = f();
+        PARSING.set(previous);
+        let empty = EMPTY_VALUES.replace(previous_empty);
+        (result, empty)
+    }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<bool, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?;
-        match s.as_str() {
-            "1" => Ok(true),
-            "0" => Ok(false),
-            _ => Err(serde::de::Error::custom(format!(
-                "expected '0' or '1', got '{}'",
-                s
-            ))),
+        deserializer.deserialize_str(FlagVisitor)
+    }
+
+    /// Parses the flag text without copying it
+    struct FlagVisitor;
+
+    impl Visitor<'_> for FlagVisitor {
+        type Value = bool;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a boolean flag")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<bool, E> {
+            let parsing = PARSING.get();
+            match parsing.parse(value).map_err(E::custom)? {
+                Some(value) => Ok(value),
+                None => {
+                    EMPTY_VALUES.set(EMPTY_VALUES.get() + 1);
+                    match parsing {
+                        BoolParsing::Lenient { empty_value } => Ok(empty_value),
+                        BoolParsing::Strict => unreachable!("strict parsing rejects empty values"),
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Helper module for optional text elements: absent, self-closing, empty and
+// whitespace-only elements all read as `None`
+mod optional_text {
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Option::<String>::deserialize(deserializer)?;
+        Ok(value.filter(|value| !value.trim().is_empty()))
+    }
+}
+
+/// Buffered XML reader that always yields UTF-8 bytes.
+///
+/// Created by [`decode_xml`] or [`open_xml`]; sources declaring a non UTF-8
+/// encoding are transcoded on the fly.
+pub enum XmlReader<R: BufRead> {
+    /// Source already encoded as UTF-8
+    Utf8(R),
+    /// Source transcoded from another encoding
+    Transcoded(BufReader<DecodeReaderBytes<R, Vec<u8>>>),
+}
+
+impl<R: BufRead> Read for XmlReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            XmlReader::Utf8(r) => r.read(buf),
+            XmlReader::Transcoded(r) => r.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for XmlReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            XmlReader::Utf8(r) => r.fill_buf(),
+            XmlReader::Transcoded(r) => r.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match self {
+            XmlReader::Utf8(r) => r.consume(amt),
+            XmlReader::Transcoded(r) => r.consume(amt),
+        }
+    }
+}
+
+/// XML reader over a file on disk.
+pub type XmlSource = XmlReader<BufReader<File>>;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Capacity of the buffer XML files are read through
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Extracts the `encoding` pseudo-attribute from an XML declaration, if any.
+fn declared_encoding(prolog: &[u8]) -> Option<&'static Encoding> {
+    let prolog = prolog.strip_prefix(b"<?xml")?;
+    let end = prolog.windows(2).position(|w| w == b"?>")?;
+    let declaration = std::str::from_utf8(&prolog[..end]).ok()?;
+
+    let value = declaration.split("encoding").nth(1)?;
+    let value = value.trim_start().strip_prefix('=')?.trim_start();
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let label = value[1..].split(quote).next()?;
+
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+/// Wraps a buffered XML source, transcoding it to UTF-8 if needed.
+///
+/// A UTF-8 byte order mark is stripped, and non UTF-8 encodings declared in the
+/// XML declaration (e.g. `ISO-8859-1` or `windows-1252`) are decoded on the fly.
+pub fn decode_xml<R: BufRead>(mut reader: R) -> Result<XmlReader<R>> {
+    let prolog = reader.fill_buf()?;
+    if prolog.starts_with(UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+        return Ok(XmlReader::Utf8(reader));
+    }
+
+    match declared_encoding(prolog) {
+        Some(encoding) if encoding != UTF_8 => {
+            tracing::debug!(encoding = encoding.name(), "Transcoding XML to UTF-8");
+            let decoder = DecodeReaderBytesBuilder::new()
+                .encoding(Some(encoding))
+                .build(reader);
+            Ok(XmlReader::Transcoded(BufReader::new(decoder)))
         }
+        _ => Ok(XmlReader::Utf8(reader)),
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Opens an XML file, transcoding it to UTF-8 if needed (see [`decode_xml`]).
+pub fn open_xml<P: AsRef<Path>>(xml_path: P) -> Result<XmlSource> {
+    let file = File::open(xml_path.as_ref())
+        .with_context(|| format!("Failed to open {}", xml_path.as_ref().display()))?;
+
+    decode_xml(BufReader::with_capacity(READ_BUFFER_SIZE, file))
+}
+
+#[cfg(feature = "validate-xml")]
+use self::xsd::validated;
+
+/// Runs `parse`; XSD validation needs the `validate-xml` feature.
+#[cfg(not(feature = "validate-xml"))]
+fn validated(
+    _xml_path: &Path,
+    _options: &ParserOptions,
+    parse: impl FnOnce() -> Result<ParseReport>,
+) -> Result<ParseReport> {
+    parse()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AtcRecord {
     #[serde(rename(deserialize = "nroatc"))]
     pub number: i32,
@@ -35,14 +318,14 @@ pub struct AtcRecord {
     pub description: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_atc")]
-struct AtcList {
+pub struct AtcList {
     #[serde(rename = "atc")]
-    records: Vec<AtcRecord>,
+    pub records: Vec<AtcRecord>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DcpRecord {
     #[serde(rename(deserialize = "codigodcp"))]
     pub code: String,
@@ -52,13 +335,13 @@ pub struct DcpRecord {
     pub dcsa_code: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_dcp")]
-struct DcpList {
+pub struct DcpList {
     #[serde(rename = "dcp")]
-    records: Vec<DcpRecord>,
+    pub records: Vec<DcpRecord>,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DcpfRecord {
     #[serde(rename(deserialize = "codigodcpf"))]
     pub code: String,
@@ -68,14 +351,14 @@ pub struct DcpfRecord {
     pub dcp_code: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_dcpf")]
-struct DcpfList {
+pub struct DcpfList {
     #[serde(rename = "dcpf")]
-    records: Vec<DcpfRecord>,
+    pub records: Vec<DcpfRecord>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DcsaRecord {
     #[serde(rename(deserialize = "codigodcsa"))]
     pub code: String,
@@ -83,14 +366,14 @@ pub struct DcsaRecord {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_dcsa")]
-struct DcsaList {
+pub struct DcsaList {
     #[serde(rename = "dcsa")]
-    records: Vec<DcsaRecord>,
+    pub records: Vec<DcsaRecord>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ContainerRecord {
     #[serde(rename(deserialize = "codigoenvase"))]
     pub code: String,
@@ -98,13 +381,13 @@ pub struct ContainerRecord {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_envases")]
-struct ContainerList {
+pub struct ContainerList {
     #[serde(rename = "envases")]
-    records: Vec<ContainerRecord>,
+    pub records: Vec<ContainerRecord>,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ExcipientRecord {
     #[serde(rename(deserialize = "codigoedo"))]
     pub code: String,
@@ -112,31 +395,35 @@ pub struct ExcipientRecord {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_excipientes")]
-struct ExcipientList {
+pub struct ExcipientList {
     #[serde(rename = "excipientes")]
-    records: Vec<ExcipientRecord>,
+    pub records: Vec<ExcipientRecord>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PharmaceuticalFormRecord {
     #[serde(rename(deserialize = "codigoformafarmaceutica"))]
     pub code: String,
     #[serde(rename(deserialize = "formafarmaceutica"))]
     pub name: String,
-    #[serde(rename(deserialize = "codigoformafarmaceuticasimplificada"), default)]
+    #[serde(
+        rename(deserialize = "codigoformafarmaceuticasimplificada"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub simplified_code: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_formas_farmaceuticas")]
-struct PharmaceuticalFormList {
+pub struct PharmaceuticalFormList {
     #[serde(rename = "formasfarmaceuticas")]
-    records: Vec<PharmaceuticalFormRecord>,
+    pub records: Vec<PharmaceuticalFormRecord>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SimplifiedPharmaceuticalFormRecord {
     #[serde(rename(deserialize = "codigoformafarmaceuticasimplificada"))]
     pub code: String,
@@ -144,37 +431,53 @@ pub struct SimplifiedPharmaceuticalFormRecord {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_formas_farmaceuticas_simplificadas")]
-struct SimplifiedPharmaceuticalFormList {
+pub struct SimplifiedPharmaceuticalFormList {
     #[serde(rename = "formasfarmaceuticassimplificadas")]
-    records: Vec<SimplifiedPharmaceuticalFormRecord>,
+    pub records: Vec<SimplifiedPharmaceuticalFormRecord>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LaboratoryRecord {
     #[serde(rename(deserialize = "codigolaboratorio"))]
     pub code: String,
     #[serde(rename(deserialize = "laboratorio"))]
     pub name: String,
-    #[serde(rename(deserialize = "direccion"))]
+    #[serde(
+        rename(deserialize = "direccion"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub address: Option<String>,
-    #[serde(rename(deserialize = "codigopostal"))]
+    #[serde(
+        rename(deserialize = "codigopostal"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub zip: Option<String>,
-    #[serde(rename(deserialize = "localidad"))]
+    #[serde(
+        rename(deserialize = "localidad"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub city: Option<String>,
-    #[serde(rename(deserialize = "cif"))]
+    #[serde(
+        rename(deserialize = "cif"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub vat: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_laboratorios")]
-struct LaboratoryList {
+pub struct LaboratoryList {
     #[serde(rename = "laboratorios")]
-    records: Vec<LaboratoryRecord>,
+    pub records: Vec<LaboratoryRecord>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ActiveIngridientRecord {
     #[serde(rename(deserialize = "nroprincipioactivo"))]
     pub number: String,
@@ -184,14 +487,14 @@ pub struct ActiveIngridientRecord {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_principios_activos")]
-struct ActiveIngredientList {
+pub struct ActiveIngredientList {
     #[serde(rename = "principiosactivos")]
-    records: Vec<ActiveIngridientRecord>,
+    pub records: Vec<ActiveIngridientRecord>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RegistrationStatusRecord {
     #[serde(rename(deserialize = "codigosituacionregistro"))]
     pub code: String,
@@ -199,14 +502,14 @@ pub struct RegistrationStatusRecord {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_situacion_registro")]
-struct RegistrationStatusList {
+pub struct RegistrationStatusList {
     #[serde(rename = "situacionesregistro")]
-    records: Vec<RegistrationStatusRecord>,
+    pub records: Vec<RegistrationStatusRecord>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ContainerUnitRecord {
     #[serde(rename(deserialize = "codigounidadcontenido"))]
     pub code: String,
@@ -214,14 +517,14 @@ pub struct ContainerUnitRecord {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_unidad_contenido")]
-struct ContainerUnitList {
+pub struct ContainerUnitList {
     #[serde(rename = "unidadescontenido")]
-    records: Vec<ContainerUnitRecord>,
+    pub records: Vec<ContainerUnitRecord>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AdministrationRouteRecord {
     #[serde(rename(deserialize = "codigoviaadministracion"))]
     pub code: String,
@@ -229,11 +532,11 @@ pub struct AdministrationRouteRecord {
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion_vias_administracion")]
-struct AdministrationRouteList {
+pub struct AdministrationRouteList {
     #[serde(rename = "viasadministracion")]
-    records: Vec<AdministrationRouteRecord>,
+    pub records: Vec<AdministrationRouteRecord>,
 }
 
 // ============================================================================
@@ -241,45 +544,93 @@ struct AdministrationRouteList {
 // ============================================================================
 
 /// Active ingredient composition for a prescription
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ActiveIngredient {
-    #[serde(rename(deserialize = "cod_principio_activo"), default)]
+    #[serde(
+        rename(deserialize = "cod_principio_activo"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub active_ingredient_code: Option<String>,
-    #[serde(rename(deserialize = "orden_colacion"))]
+    #[serde(
+        rename(deserialize = "orden_colacion"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub order: Option<String>,
-    #[serde(rename(deserialize = "dosis_pa"))]
+    #[serde(
+        rename(deserialize = "dosis_pa"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub dose: Option<String>,
-    #[serde(rename(deserialize = "unidad_dosis_pa"))]
+    #[serde(
+        rename(deserialize = "unidad_dosis_pa"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub dose_unit: Option<String>,
-    #[serde(rename(deserialize = "dosis_composicion"))]
+    #[serde(
+        rename(deserialize = "dosis_composicion"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub composition_dose: Option<String>,
-    #[serde(rename(deserialize = "unidad_composicion"))]
+    #[serde(
+        rename(deserialize = "unidad_composicion"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub composition_unit: Option<String>,
-    #[serde(rename(deserialize = "dosis_administracion"))]
+    #[serde(
+        rename(deserialize = "dosis_administracion"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub administration_dose: Option<String>,
-    #[serde(rename(deserialize = "unidad_administracion"))]
+    #[serde(
+        rename(deserialize = "unidad_administracion"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub administration_unit: Option<String>,
-    #[serde(rename(deserialize = "dosis_prescripcion"))]
+    #[serde(
+        rename(deserialize = "dosis_prescripcion"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub prescription_dose: Option<String>,
-    #[serde(rename(deserialize = "unidad_prescripcion"))]
+    #[serde(
+        rename(deserialize = "unidad_prescripcion"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub prescription_unit: Option<String>,
 }
 
 /// Administration route for a prescription
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct AdminRoute {
     #[serde(rename(deserialize = "cod_via_admin"))]
     pub route_code: String,
 }
 
 /// Pharmaceutical form for a prescription
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PrescriptionForm {
     #[serde(rename(deserialize = "cod_forfar"))]
     pub form_code: String,
-    #[serde(rename(deserialize = "cod_forfar_simplificada"))]
+    #[serde(
+        rename(deserialize = "cod_forfar_simplificada"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub simplified_form_code: Option<String>,
-    #[serde(rename(deserialize = "nro_pactiv"))]
+    #[serde(
+        rename(deserialize = "nro_pactiv"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub num_active_ingredients: Option<String>,
     #[serde(rename(deserialize = "composicion_pa"), default)]
     pub active_ingredients: Vec<ActiveIngredient>,
@@ -288,20 +639,32 @@ pub struct PrescriptionForm {
 }
 
 /// ATC duplicate information
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct AtcDuplicate {
     #[serde(rename(deserialize = "atc_duplicidad"))]
     pub duplicate_atc: String,
-    #[serde(rename(deserialize = "descripcion_atc_duplicidad"))]
+    #[serde(
+        rename(deserialize = "descripcion_atc_duplicidad"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub description: Option<String>,
-    #[serde(rename(deserialize = "efecto_duplicidad"))]
+    #[serde(
+        rename(deserialize = "efecto_duplicidad"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub effect: Option<String>,
-    #[serde(rename(deserialize = "recomendacion_duplicidad"))]
+    #[serde(
+        rename(deserialize = "recomendacion_duplicidad"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub recommendation: Option<String>,
 }
 
 /// ATC code for a prescription
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PrescriptionAtc {
     #[serde(rename(deserialize = "cod_atc"))]
     pub atc_code: String,
@@ -310,31 +673,107 @@ pub struct PrescriptionAtc {
 }
 
 /// Supply problem for a prescription
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct SupplyProblem {
-    #[serde(rename(deserialize = "fecha_inicio"))]
+    #[serde(
+        rename(deserialize = "fecha_inicio"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub start_date: Option<String>,
-    #[serde(rename(deserialize = "observaciones"))]
+    #[serde(
+        rename(deserialize = "observaciones"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
     pub observations: Option<String>,
 }
 
+/// Excipient with obligatory declaration for a prescription
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PrescriptionExcipient {
+    #[serde(rename(deserialize = "cod_excipiente"))]
+    pub excipient_code: String,
+    #[serde(
+        rename(deserialize = "cantidad"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
+    pub quantity: Option<String>,
+    #[serde(
+        rename(deserialize = "unidad"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
+    pub unit: Option<String>,
+}
+
+/// Informational note referenced by a prescription
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PrescriptionNote {
+    #[serde(
+        rename(deserialize = "tipo_nota"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
+    pub note_type: Option<String>,
+    #[serde(
+        rename(deserialize = "num_nota"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
+    pub number: Option<String>,
+    #[serde(
+        rename(deserialize = "referencia_nota"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
+    pub reference: Option<String>,
+    #[serde(
+        rename(deserialize = "asunto_nota"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
+    pub subject: Option<String>,
+    #[serde(
+        rename(deserialize = "fecha_nota"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
+    pub date: Option<String>,
+    #[serde(
+        rename(deserialize = "url_nota"),
+        default,
+        deserialize_with = "optional_text::deserialize"
+    )]
+    pub url: Option<String>,
+}
+
 // ============================================================================
 // Main Prescription Record
 // ============================================================================
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PrescriptionRecord {
     pub cod_nacion: String,
     pub nro_definitivo: String,
     pub des_nomco: String,
     pub des_prese: String,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub cod_dcsa: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub cod_dcp: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub cod_dcpf: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub des_dosific: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub cod_envase: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub contenido: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub unid_contenido: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub nro_conte: Option<String>,
     #[serde(deserialize_with = "bool_from_string::deserialize")]
     pub sw_psicotropo: bool,
@@ -344,7 +783,9 @@ pub struct PrescriptionRecord {
     pub sw_afecta_conduccion: bool,
     #[serde(deserialize_with = "bool_from_string::deserialize")]
     pub sw_triangulo_negro: bool,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub url_fictec: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub url_prosp: Option<String>,
     #[serde(deserialize_with = "bool_from_string::deserialize")]
     pub sw_receta: bool,
@@ -366,15 +807,23 @@ pub struct PrescriptionRecord {
     pub sw_huerfano: bool,
     #[serde(deserialize_with = "bool_from_string::deserialize")]
     pub sw_base_a_plantas: bool,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub laboratorio_titular: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub laboratorio_comercializador: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub fecha_autorizacion: Option<String>,
     #[serde(deserialize_with = "bool_from_string::deserialize")]
     pub sw_comercializado: bool,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub fec_comer: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub cod_sitreg: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub cod_sitreg_presen: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub fecha_situacion_registro: Option<String>,
+    #[serde(default, deserialize_with = "optional_text::deserialize")]
     pub fec_sitreg_presen: Option<String>,
     #[serde(deserialize_with = "bool_from_string::deserialize")]
     pub sw_tiene_excipientes_decl_obligatoria: bool,
@@ -396,14 +845,20 @@ pub struct PrescriptionRecord {
 
     #[serde(rename(deserialize = "problemassuministro"), default, skip_serializing)]
     pub supply_problems: Vec<SupplyProblem>,
+
+    #[serde(rename(deserialize = "excipientes"), default, skip_serializing)]
+    pub excipients: Vec<PrescriptionExcipient>,
+
+    #[serde(rename(deserialize = "notas"), default, skip_serializing)]
+    pub notes: Vec<PrescriptionNote>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Header {
     pub listprescriptiondate: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename = "aemps_prescripcion")]
 pub struct PrescriptionList {
     pub header: Option<Header>,
@@ -411,47 +866,591 @@ pub struct PrescriptionList {
     pub records: Vec<PrescriptionRecord>,
 }
 
+/// Streaming reader over the `<prescription>` elements of a Prescripcion.xml file.
+///
+/// Each record is deserialized on its own, so memory usage stays proportional to a
+/// single prescription instead of the whole file.
+pub struct PrescriptionReader<R: BufRead> {
+    elements: XmlElements<R>,
+    bool_parsing: BoolParsing,
+    on_error: OnError,
+    empty_flags: usize,
+    /// Elements read so far
+    index: usize,
+    errors: Vec<RecordError>,
+    census: Option<drift::ElementCensus>,
+}
+
+impl PrescriptionReader<XmlSource> {
+    /// Opens a Prescripcion.xml file for streaming.
+    pub fn from_path<P: AsRef<Path>>(xml_path: P) -> Result<Self> {
+        Ok(Self::new(open_xml(xml_path)?))
+    }
+}
+
+impl<R: BufRead> PrescriptionReader<R> {
+    /// Creates a streaming reader over any buffered XML source.
+    pub fn new(source: R) -> Self {
+        Self {
+            elements: XmlElements::new(source, "prescription", "Prescription"),
+            bool_parsing: BoolParsing::default(),
+            on_error: OnError::default(),
+            empty_flags: 0,
+            index: 0,
+            errors: Vec::new(),
+            census: None,
+        }
+    }
+
+    /// Creates a streaming reader parsing the flags as configured in `options`.
+    pub fn with_options(source: R, options: &ParserOptions) -> Self {
+        Self {
+            bool_parsing: options.bool_parsing,
+            on_error: options.on_error,
+            census: options
+                .detect_unknown_elements
+                .then(drift::ElementCensus::prescriptions),
+            ..Self::new(source)
+        }
+    }
+
+    /// Position in the decoded XML input, i.e. the number of bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.elements.bytes_read()
+    }
+
+    /// Number of empty `sw_*` flags read so far and replaced by the lenient default.
+    pub fn empty_flags(&self) -> usize {
+        self.empty_flags
+    }
+
+    /// Records skipped so far with [`OnError::SkipAndReport`].
+    pub fn errors(&self) -> &[RecordError] {
+        &self.errors
+    }
+
+    /// Returns the raw bytes of the next `<prescription>` element, or `None` at end of file.
+    fn next_element(&mut self) -> Result<Option<Vec<u8>>> {
+        let element = self.elements.next_element()?;
+        if let (Some(census), Some(bytes)) = (&mut self.census, &element) {
+            census.scan(bytes);
+        }
+        Ok(element)
+    }
+
+    /// Reads and deserializes the next prescription record.
+    ///
+    /// With [`OnError::SkipAndReport`] records failing to deserialize are added to
+    /// [`errors`](Self::errors) and the following one is returned instead.
+    pub fn next_record(&mut self) -> Result<Option<PrescriptionRecord>> {
+        while let Some(bytes) = self.next_element()? {
+            let index = self.index;
+            self.index += 1;
+            let (record, empty_flags) = deserialize_prescription(&bytes, self.bool_parsing);
+            match record {
+                Ok(record) => {
+                    self.empty_flags += empty_flags;
+                    return Ok(Some(record));
+                }
+                Err(e) if self.on_error == OnError::SkipAndReport => {
+                    let key = element_key(&bytes, b"cod_nacion");
+                    self.errors.push(RecordError::new(index, key, &e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Streams the raw bytes of every `<tag>` element of an XML source.
+///
+/// The event buffer is reused between elements, and each element is allocated with
+/// the size of the largest one so far, so reading does not reallocate as it goes.
+struct XmlElements<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    /// Size of the largest element so far, reserved for the next one
+    element_capacity: usize,
+    tag: &'static str,
+    /// Name of the XML file used in error messages
+    name: &'static str,
+}
+
+impl<R: BufRead> XmlElements<R> {
+    fn new(source: R, tag: &'static str, name: &'static str) -> Self {
+        XmlElements {
+            reader: Reader::from_reader(source),
+            buf: Vec::new(),
+            element_capacity: 0,
+            tag,
+            name,
+        }
+    }
+
+    fn bytes_read(&self) -> u64 {
+        self.reader.buffer_position()
+    }
+
+    /// Returns the raw bytes of the next element, or `None` at end of file.
+    fn next_element(&mut self) -> Result<Option<Vec<u8>>> {
+        // Skip everything (declaration, root, header) until the next element starts
+        let mut element = loop {
+            self.buf.clear();
+            let event = self
+                .reader
+                .read_event_into(&mut self.buf)
+                .with_context(|| format!("Failed to read {} XML", self.name))?;
+            match event {
+                Event::Start(e) if e.name().as_ref() == self.tag.as_bytes() => {
+                    let mut element = Vec::with_capacity(self.element_capacity);
+                    element.push(b'<');
+                    element.extend_from_slice(&e);
+                    element.push(b'>');
+                    break element;
+                }
+                Event::Eof => return Ok(None),
+                _ => {}
+            }
+        };
+
+        // Copy the content as written until the matching end tag, which the buffer
+        // also holds after the returned text
+        let end = BytesEnd::new(self.tag);
+        let start = element.len();
+        match self.reader.read_text_into(end.name(), &mut element) {
+            Ok(content) => {
+                let length = content.len();
+                element.truncate(start + length);
+            }
+            Err(quick_xml::Error::IllFormed(IllFormedError::MissingEndTag(_))) => {
+                anyhow::bail!("Unexpected end of file inside <{}>", self.tag)
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {} XML", self.name));
+            }
+        }
+        element.extend_from_slice(b"</");
+        element.extend_from_slice(self.tag.as_bytes());
+        element.push(b'>');
+        self.element_capacity = self.element_capacity.max(element.len());
+        Ok(Some(element))
+    }
+}
+
+/// Text of the first `<field>` child of an element, if it can be read.
+fn element_key(element: &[u8], field: &[u8]) -> Option<String> {
+    let mut reader = Reader::from_reader(element);
+    let mut buf = Vec::new();
+    let mut depth = 0usize;
+    let mut in_field = false;
+    loop {
+        match reader.read_event_into(&mut buf).ok()? {
+            Event::Start(e) => {
+                depth += 1;
+                in_field = depth == 2 && e.name().as_ref() == field;
+            }
+            Event::Text(text) if in_field => {
+                let key = text.decode().ok()?.trim().to_string();
+                return (!key.is_empty()).then_some(key);
+            }
+            Event::End(_) => {
+                if in_field {
+                    return None;
+                }
+                depth = depth.saturating_sub(1);
+            }
+            Event::Eof => return None,
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Deserializes the bytes of one `<prescription>` element, also returning the number of
+/// empty flags read as the lenient default.
+fn deserialize_prescription(
+    bytes: &[u8],
+    bool_parsing: BoolParsing,
+) -> (Result<PrescriptionRecord>, usize) {
+    let xml = match std::str::from_utf8(bytes) {
+        Ok(xml) => xml,
+        Err(e) => {
+            return (Err(e).context("Failed to deserialize Prescription XML"), 0);
+        }
+    };
+    // Records the simple deserializer cannot read, or that fail, go through quick-xml,
+    // which reads the others the same way
+    if let (Ok(record), empty_flags) =
+        bool_from_string::with_parsing(bool_parsing, || xml_de::from_str(xml))
+    {
+        return (Ok(record), empty_flags);
+    }
+    let (record, empty_flags) = bool_from_string::with_parsing(bool_parsing, || {
+        quick_xml::de::from_str::<PrescriptionRecord>(xml)
+    });
+    (
+        record.context("Failed to deserialize Prescription XML"),
+        empty_flags,
+    )
+}
+
+impl<R: BufRead> Iterator for PrescriptionReader<R> {
+    type Item = Result<PrescriptionRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Prescriptions left after dropping duplicated keys
+///
+/// Records are streamed except in [`OnDuplicate::KeepLast`] mode, which has to read
+/// the whole file before knowing which record of each key is the last one.
+struct UniquePrescriptions<R: BufRead> {
+    reader: PrescriptionReader<R>,
+    duplicates: dedup::Duplicates,
+    /// Records read ahead in [`OnDuplicate::KeepLast`] mode
+    buffered: Option<std::vec::IntoIter<PrescriptionRecord>>,
+}
+
+impl<R: BufRead> UniquePrescriptions<R> {
+    fn new(reader: PrescriptionReader<R>, on_duplicate: OnDuplicate) -> Self {
+        UniquePrescriptions {
+            reader,
+            duplicates: dedup::Duplicates::new(on_duplicate),
+            buffered: None,
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<PrescriptionRecord>> {
+        if self.duplicates.on_duplicate() == OnDuplicate::KeepLast {
+            if self.buffered.is_none() {
+                let records = self.reader.by_ref().collect::<Result<Vec<_>>>()?;
+                self.buffered = Some(self.duplicates.retain_last(records).into_iter());
+            }
+            return Ok(self.buffered.as_mut().and_then(Iterator::next));
+        }
+        while let Some(record) = self.reader.next_record()? {
+            if self.duplicates.admit(record.natural_key()) {
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    }
+
+    fn finish(self, report: &mut ParseReport) -> Result<()> {
+        finish_prescriptions(
+            self.reader.empty_flags,
+            self.reader.errors,
+            self.reader.census,
+            self.duplicates,
+            report,
+        )
+    }
+}
+
+/// Adds the duplicates, empty flags, skipped records and unknown elements to `report`,
+/// logging the empty flags replaced by the lenient default.
+fn finish_prescriptions(
+    empty_flags: usize,
+    errors: Vec<RecordError>,
+    census: Option<drift::ElementCensus>,
+    duplicates: dedup::Duplicates,
+    report: &mut ParseReport,
+) -> Result<()> {
+    report.empty_flags = empty_flags;
+    if empty_flags > 0 {
+        tracing::warn!(
+            count = empty_flags,
+            "Empty prescription flags read as the lenient default"
+        );
+    }
+    report_errors("Prescription", errors, report);
+    if let Some(census) = census {
+        census.finish("Prescription", report);
+    }
+    duplicates.finish("Prescription", report)
+}
+
+/// Adds the records skipped with [`OnError::SkipAndReport`] to `report`, logging them.
+fn report_errors(name: &str, errors: Vec<RecordError>, report: &mut ParseReport) {
+    if !errors.is_empty() {
+        tracing::warn!(
+            count = errors.len(),
+            file = name,
+            "Skipped records failing to deserialize"
+        );
+    }
+    report.errors = errors;
+}
+
+/// Output format produced by the nomenclator parsers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Comma separated values with a header row; nested prescription entities are
+    /// written to separate files
+    #[default]
+    Csv,
+    /// Newline-delimited JSON, one object per record with nested entities inlined
+    NdJson,
+}
+
+impl OutputFormat {
+    /// File extension used for files in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::NdJson => "ndjson",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "ndjson" | "jsonl" => Ok(OutputFormat::NdJson),
+            _ => anyhow::bail!("Unknown output format '{}', expected csv or ndjson", s),
+        }
+    }
+}
+
+/// Writes `record` as a single JSON line.
+fn write_ndjson_line<W: Write, T: Serialize>(writer: &mut W, record: &T) -> Result<()> {
+    serde_json::to_writer(&mut *writer, record).context("Failed to serialize record to JSON")?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Deserializes the `<tag>` elements of a dictionary one at a time, passing each one
+/// to `census` if any. With [`OnError::SkipAndReport`] the records that fail are
+/// returned as errors instead of stopping.
+fn parse_records_by_element<R: BufRead, T: DeserializeOwned + schema::Columns>(
+    reader: R,
+    tag: &'static str,
+    name: &'static str,
+    on_error: OnError,
+    mut census: Option<&mut drift::ElementCensus>,
+) -> Result<(Vec<T>, Vec<RecordError>)> {
+    let key_field = T::COLUMNS
+        .iter()
+        .find(|column| column.name == "code")
+        .map(|column| column.wire_name.as_bytes());
+    let mut elements = XmlElements::new(decode_xml(reader)?, tag, name);
+    let (mut records, mut errors) = (Vec::new(), Vec::new());
+    let mut index = 0;
+    while let Some(bytes) = elements.next_element()? {
+        if let Some(census) = census.as_deref_mut() {
+            census.scan(&bytes);
+        }
+        match from_reader(bytes.as_slice())
+            .with_context(|| format!("Failed to deserialize {} XML", name))
+        {
+            Ok(record) => records.push(record),
+            Err(e) if on_error == OnError::Fail => return Err(e),
+            Err(e) => {
+                let key = key_field.and_then(|field| element_key(&bytes, field));
+                errors.push(RecordError::new(index, key, &e));
+            }
+        }
+        index += 1;
+    }
+    Ok((records, errors))
+}
+
 macro_rules! impl_xml_parser {
-    ($(#[$attr:meta])* $fn_name:ident, $list_type:ty, $error_ctx:expr) => {
-        $(#[$attr])*
-        pub fn $fn_name<P: AsRef<Path>>(xml_path: P, csv_path: P) -> Result<()> {
-            let file = File::open(xml_path)?;
-            let reader = BufReader::new(file);
-            let list: $list_type = from_reader(reader).context($error_ctx)?;
-
-            let mut wtr = csv::Writer::from_path(csv_path)?;
-            for record in list.records {
-                wtr.serialize(record)?;
+    (
+        $name:literal,
+        $element:literal,
+        $parse_fn:ident,
+        $parse_reader_fn:ident,
+        $csv_fn:ident,
+        $csv_reader_fn:ident,
+        $csv_options_fn:ident,
+        $csv_reader_options_fn:ident,
+        $ndjson_fn:ident,
+        $ndjson_reader_fn:ident,
+        $list_type:ty,
+        $record_type:ty
+        $(, $mut_record:ident, $transform:block)?
+    ) => {
+        #[doc = concat!("Parses ", $name, " XML from a buffered reader and returns its records.")]
+        pub fn $parse_reader_fn<R: BufRead>(reader: R) -> Result<Vec<$record_type>> {
+            #[allow(unused_mut)]
+            let mut list: $list_type = from_reader(decode_xml(reader)?)
+                .context(concat!("Failed to deserialize ", $name, " XML"))?;
+
+            $(
+                for $mut_record in list.records.iter_mut() {
+                    $transform
+                }
+            )?
+
+            Ok(list.records)
+        }
+
+        #[doc = concat!("Parses the ", $name, " XML file and returns its records.")]
+        pub fn $parse_fn<P: AsRef<Path>>(xml_path: P) -> Result<Vec<$record_type>> {
+            let file = File::open(xml_path.as_ref())
+                .with_context(|| format!("Failed to open {}", xml_path.as_ref().display()))?;
+            $parse_reader_fn(BufReader::new(file))
+        }
+
+        #[doc = concat!("Parses ", $name, " XML from a buffered reader and writes CSV to `writer`.")]
+        pub fn $csv_reader_fn<R: BufRead, W: Write>(reader: R, writer: W) -> Result<()> {
+            $csv_reader_options_fn(reader, writer, &ParserOptions::default())?;
+            Ok(())
+        }
+
+        #[doc = concat!("Parses ", $name, " XML from a buffered reader and writes CSV formatted after `options` to `writer`.")]
+        pub fn $csv_reader_options_fn<R: BufRead, W: Write>(
+            reader: R,
+            writer: W,
+            options: &ParserOptions,
+        ) -> Result<ParseReport> {
+            let span = parse_span($name, "csv");
+            let _entered = span.enter();
+            let started = Instant::now();
+            let mut report = ParseReport::default();
+            let records = if options.on_error == OnError::Fail
+                && !options.detect_unknown_elements
+            {
+                $parse_reader_fn(reader)?
+            } else {
+                let mut census = options.detect_unknown_elements.then(|| {
+                    drift::ElementCensus::dictionary(
+                        $element,
+                        <$record_type as schema::Columns>::COLUMNS,
+                    )
+                });
+                #[allow(unused_mut)]
+                let (mut records, errors) = parse_records_by_element::<_, $record_type>(
+                    reader,
+                    $element,
+                    $name,
+                    options.on_error,
+                    census.as_mut(),
+                )?;
+                $(
+                    for $mut_record in records.iter_mut() {
+                        $transform
+                    }
+                )?
+                report_errors($name, errors, &mut report);
+                if let Some(census) = census {
+                    census.finish($name, &mut report);
+                }
+                records
+            };
+            let records = dedup::deduplicate(
+                records,
+                options.on_duplicate,
+                $name,
+                &mut report,
+            )?;
+            let columns = <$record_type as schema::Columns>::COLUMNS;
+
+            let progress = |written, done| ParseProgress {
+                records: records.len(),
+                rows: vec![($name, written)],
+                bytes_read: None,
+                done,
+            };
+
+            let mut wtr = options.csv_writer(writer);
+            options.write_header(&mut wtr, columns)?;
+            for (index, record) in records.iter().enumerate() {
+                options.write_row(&mut wtr, columns, record)?;
+                if options.progress_due(index + 1) {
+                    options.report_progress(progress(index + 1, false));
+                }
             }
             wtr.flush()?;
+            options.report_progress(progress(records.len(), true));
+            report.records = records.len();
+            report.elapsed = started.elapsed();
+            record_parse(&span, $name, &report);
+
+            Ok(report)
+        }
 
+        #[doc = concat!("Parses the ", $name, " XML file and writes its content to a CSV file.")]
+        pub fn $csv_fn<P: AsRef<Path>>(xml_path: P, csv_path: P) -> Result<()> {
+            $csv_options_fn(xml_path, csv_path, &ParserOptions::default())?;
             Ok(())
         }
-    };
-    ($(#[$attr:meta])* $fn_name:ident, $list_type:ty, $error_ctx:expr, $mut_record:ident, $transform:block) => {
-        $(#[$attr])*
-        pub fn $fn_name<P: AsRef<Path>>(xml_path: P, csv_path: P) -> Result<()> {
-            let file = File::open(xml_path)?;
-            let reader = BufReader::new(file);
-            let list: $list_type = from_reader(reader).context($error_ctx)?;
-
-            let mut wtr = csv::Writer::from_path(csv_path)?;
-            for mut $mut_record in list.records {
-                $transform
-                wtr.serialize($mut_record)?;
+
+        #[doc = concat!("Parses the ", $name, " XML file and writes its content to a CSV file formatted after `options`.")]
+        pub fn $csv_options_fn<P: AsRef<Path>>(
+            xml_path: P,
+            csv_path: P,
+            options: &ParserOptions,
+        ) -> Result<ParseReport> {
+            let (xml_path, csv_path) = (xml_path.as_ref(), csv_path.as_ref());
+            manifest::parse_file_unless_unchanged(xml_path, csv_path, options, || {
+                validated(xml_path, options, || {
+                    let file = File::open(xml_path)
+                        .with_context(|| format!("Failed to open {}", xml_path.display()))?;
+                    let sort_columns = sorting::key_columns(
+                        <$record_type as schema::Columns>::COLUMNS,
+                        &["code"],
+                    );
+                    parts::write_csv_file(csv_path, options, &sort_columns, |output| {
+                        $csv_reader_options_fn(BufReader::new(file), output, options)
+                    })
+                })
+            })
+        }
+
+        #[doc = concat!("Parses ", $name, " XML from a buffered reader and writes NDJSON to `writer`.")]
+        pub fn $ndjson_reader_fn<R: BufRead, W: Write>(reader: R, writer: W) -> Result<()> {
+            let span = parse_span($name, "ndjson");
+            let _entered = span.enter();
+            let started = Instant::now();
+            let records = $parse_reader_fn(reader)?;
+
+            let mut wtr = io::BufWriter::new(writer);
+            for record in &records {
+                write_ndjson_line(&mut wtr, record)?;
             }
             wtr.flush()?;
+            let report = ParseReport {
+                records: records.len(),
+                elapsed: started.elapsed(),
+                ..Default::default()
+            };
+            record_parse(&span, $name, &report);
 
             Ok(())
         }
+
+        #[doc = concat!("Parses the ", $name, " XML file and writes one JSON object per line to `out_path`.")]
+        pub fn $ndjson_fn<P: AsRef<Path>>(xml_path: P, out_path: P) -> Result<()> {
+            let file = File::open(xml_path.as_ref())
+                .with_context(|| format!("Failed to open {}", xml_path.as_ref().display()))?;
+            let output = File::create(out_path.as_ref())
+                .with_context(|| format!("Failed to create {}", out_path.as_ref().display()))?;
+            $ndjson_reader_fn(BufReader::new(file), output)
+        }
     };
 }
 
 impl_xml_parser!(
-    /// Parses the ATC XML file and writes its content to a CSV file.
+    "ATC",
+    "atc",
+    parse_atc_xml,
+    parse_atc_xml_from_reader,
     parse_atc_xml_to_csv,
+    parse_atc_xml_to_csv_from_reader,
+    parse_atc_xml_to_csv_with_options,
+    parse_atc_xml_to_csv_from_reader_with_options,
+    parse_atc_xml_to_ndjson,
+    parse_atc_xml_to_ndjson_from_reader,
     AtcList,
-    "Failed to deserialize ATC XML",
+    AtcRecord,
     record,
     {
         // Clean description by removing "CODE - " prefix if it exists
@@ -463,100 +1462,419 @@ impl_xml_parser!(
 );
 
 impl_xml_parser!(
-    /// Parses the DCP XML file and writes its content to a CSV file.
+    "DCP",
+    "dcp",
+    parse_dcp_xml,
+    parse_dcp_xml_from_reader,
     parse_dcp_xml_to_csv,
+    parse_dcp_xml_to_csv_from_reader,
+    parse_dcp_xml_to_csv_with_options,
+    parse_dcp_xml_to_csv_from_reader_with_options,
+    parse_dcp_xml_to_ndjson,
+    parse_dcp_xml_to_ndjson_from_reader,
     DcpList,
-    "Failed to deserialize DCP XML"
+    DcpRecord
 );
 
 impl_xml_parser!(
-    /// Parses the DCPF XML file and writes its content to a CSV file.
+    "DCPF",
+    "dcpf",
+    parse_dcpf_xml,
+    parse_dcpf_xml_from_reader,
     parse_dcpf_xml_to_csv,
+    parse_dcpf_xml_to_csv_from_reader,
+    parse_dcpf_xml_to_csv_with_options,
+    parse_dcpf_xml_to_csv_from_reader_with_options,
+    parse_dcpf_xml_to_ndjson,
+    parse_dcpf_xml_to_ndjson_from_reader,
     DcpfList,
-    "Failed to deserialize DCPF XML"
+    DcpfRecord
 );
 
 impl_xml_parser!(
-    /// Parses the DCSA XML file and writes its content to a CSV file.
+    "DCSA",
+    "dcsa",
+    parse_dcsa_xml,
+    parse_dcsa_xml_from_reader,
     parse_dcsa_xml_to_csv,
+    parse_dcsa_xml_to_csv_from_reader,
+    parse_dcsa_xml_to_csv_with_options,
+    parse_dcsa_xml_to_csv_from_reader_with_options,
+    parse_dcsa_xml_to_ndjson,
+    parse_dcsa_xml_to_ndjson_from_reader,
     DcsaList,
-    "Failed to deserialize DCSA XML"
+    DcsaRecord
 );
 
 impl_xml_parser!(
-    /// Parses the Envases XML file and writes its content to a CSV file.
+    "Envases",
+    "envases",
+    parse_envases_xml,
+    parse_envases_xml_from_reader,
     parse_envases_xml_to_csv,
+    parse_envases_xml_to_csv_from_reader,
+    parse_envases_xml_to_csv_with_options,
+    parse_envases_xml_to_csv_from_reader_with_options,
+    parse_envases_xml_to_ndjson,
+    parse_envases_xml_to_ndjson_from_reader,
     ContainerList,
-    "Failed to deserialize Envases XML"
+    ContainerRecord
 );
 
 impl_xml_parser!(
-    /// Parses the Excipientes XML file and writes its content to a CSV file.
+    "Excipientes",
+    "excipientes",
+    parse_excipientes_xml,
+    parse_excipientes_xml_from_reader,
     parse_excipientes_xml_to_csv,
+    parse_excipientes_xml_to_csv_from_reader,
+    parse_excipientes_xml_to_csv_with_options,
+    parse_excipientes_xml_to_csv_from_reader_with_options,
+    parse_excipientes_xml_to_ndjson,
+    parse_excipientes_xml_to_ndjson_from_reader,
     ExcipientList,
-    "Failed to deserialize Excipientes XML"
+    ExcipientRecord
 );
 
 impl_xml_parser!(
-    /// Parses the Forma Farmaceutica XML file and writes its content to a CSV file.
+    "Forma Farmaceutica",
+    "formasfarmaceuticas",
+    parse_forma_farmaceutica_xml,
+    parse_forma_farmaceutica_xml_from_reader,
     parse_forma_farmaceutica_xml_to_csv,
+    parse_forma_farmaceutica_xml_to_csv_from_reader,
+    parse_forma_farmaceutica_xml_to_csv_with_options,
+    parse_forma_farmaceutica_xml_to_csv_from_reader_with_options,
+    parse_forma_farmaceutica_xml_to_ndjson,
+    parse_forma_farmaceutica_xml_to_ndjson_from_reader,
     PharmaceuticalFormList,
-    "Failed to deserialize Forma Farmaceutica XML"
+    PharmaceuticalFormRecord
 );
 
 impl_xml_parser!(
-    /// Parses the Forma Farmaceutica Simplificada XML file and writes its content to a CSV file.
+    "Forma Farmaceutica Simplificada",
+    "formasfarmaceuticassimplificadas",
+    parse_forma_farmaceutica_simplificada_xml,
+    parse_forma_farmaceutica_simplificada_xml_from_reader,
     parse_forma_farmaceutica_simplificada_xml_to_csv,
+    parse_forma_farmaceutica_simplificada_xml_to_csv_from_reader,
+    parse_forma_farmaceutica_simplificada_xml_to_csv_with_options,
+    parse_forma_farmaceutica_simplificada_xml_to_csv_from_reader_with_options,
+    parse_forma_farmaceutica_simplificada_xml_to_ndjson,
+    parse_forma_farmaceutica_simplificada_xml_to_ndjson_from_reader,
     SimplifiedPharmaceuticalFormList,
-    "Failed to deserialize Forma Farmaceutica Simplificada XML"
+    SimplifiedPharmaceuticalFormRecord
 );
 
 impl_xml_parser!(
-    /// Parses the Laboratorio XML file and writes its content to a CSV file.
+    "Laboratorio",
+    "laboratorios",
+    parse_laboratorio_xml,
+    parse_laboratorio_xml_from_reader,
     parse_laboratorio_xml_to_csv,
+    parse_laboratorio_xml_to_csv_from_reader,
+    parse_laboratorio_xml_to_csv_with_options,
+    parse_laboratorio_xml_to_csv_from_reader_with_options,
+    parse_laboratorio_xml_to_ndjson,
+    parse_laboratorio_xml_to_ndjson_from_reader,
     LaboratoryList,
-    "Failed to deserialize Laboratorio XML"
+    LaboratoryRecord
 );
 
 impl_xml_parser!(
-    /// Parses the Principio Activo XML file and writes its content to a CSV file.
+    "Principio Activo",
+    "principiosactivos",
+    parse_principio_activo_xml,
+    parse_principio_activo_xml_from_reader,
     parse_principio_activo_xml_to_csv,
+    parse_principio_activo_xml_to_csv_from_reader,
+    parse_principio_activo_xml_to_csv_with_options,
+    parse_principio_activo_xml_to_csv_from_reader_with_options,
+    parse_principio_activo_xml_to_ndjson,
+    parse_principio_activo_xml_to_ndjson_from_reader,
     ActiveIngredientList,
-    "Failed to deserialize Principio Activo XML"
+    ActiveIngridientRecord
 );
 
 impl_xml_parser!(
-    /// Parses the Situacion Registro XML file and writes its content to a CSV file.
+    "Situacion Registro",
+    "situacionesregistro",
+    parse_situacion_registro_xml,
+    parse_situacion_registro_xml_from_reader,
     parse_situacion_registro_xml_to_csv,
+    parse_situacion_registro_xml_to_csv_from_reader,
+    parse_situacion_registro_xml_to_csv_with_options,
+    parse_situacion_registro_xml_to_csv_from_reader_with_options,
+    parse_situacion_registro_xml_to_ndjson,
+    parse_situacion_registro_xml_to_ndjson_from_reader,
     RegistrationStatusList,
-    "Failed to deserialize Situacion Registro XML"
+    RegistrationStatusRecord
 );
 
 impl_xml_parser!(
-    /// Parses the Unidad Contenido XML file and writes its content to a CSV file.
+    "Unidad Contenido",
+    "unidadescontenido",
+    parse_unidad_contenido_xml,
+    parse_unidad_contenido_xml_from_reader,
     parse_unidad_contenido_xml_to_csv,
+    parse_unidad_contenido_xml_to_csv_from_reader,
+    parse_unidad_contenido_xml_to_csv_with_options,
+    parse_unidad_contenido_xml_to_csv_from_reader_with_options,
+    parse_unidad_contenido_xml_to_ndjson,
+    parse_unidad_contenido_xml_to_ndjson_from_reader,
     ContainerUnitList,
-    "Failed to deserialize Unidad Contenido XML"
+    ContainerUnitRecord
 );
 
 impl_xml_parser!(
-    /// Parses the Via Administracion XML file and writes its content to a CSV file.
+    "Via Administracion",
+    "viasadministracion",
+    parse_via_administracion_xml,
+    parse_via_administracion_xml_from_reader,
     parse_via_administracion_xml_to_csv,
+    parse_via_administracion_xml_to_csv_from_reader,
+    parse_via_administracion_xml_to_csv_with_options,
+    parse_via_administracion_xml_to_csv_from_reader_with_options,
+    parse_via_administracion_xml_to_ndjson,
+    parse_via_administracion_xml_to_ndjson_from_reader,
     AdministrationRouteList,
-    "Failed to deserialize Via Administracion XML"
+    AdministrationRouteRecord
 );
 
-impl_xml_parser!(
-    /// Parses the Prescription XML file and writes its content to a CSV file.
-    parse_prescription_xml_to_csv,
-    PrescriptionList,
-    "Failed to deserialize Prescription XML"
-);
+/// Parses the Prescription XML file and returns the complete list in memory.
+///
+/// For large files prefer [`PrescriptionReader`], which streams one record at a time.
+pub fn parse_prescription_xml<P: AsRef<Path>>(xml_path: P) -> Result<PrescriptionList> {
+    let reader = open_xml(xml_path)?;
+    from_reader(reader).context("Failed to deserialize Prescription XML")
+}
+
+/// Parses Prescription XML from a buffered reader and returns the complete list in memory.
+pub fn parse_prescription_xml_from_reader<R: BufRead>(reader: R) -> Result<PrescriptionList> {
+    from_reader(decode_xml(reader)?).context("Failed to deserialize Prescription XML")
+}
+
+/// Parses Prescription XML from a buffered reader and writes CSV to `writer`.
+///
+/// Records are streamed one `<prescription>` element at a time.
+pub fn parse_prescription_xml_to_csv_from_reader<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+) -> Result<()> {
+    parse_prescription_xml_to_csv_from_reader_with_options(
+        reader,
+        writer,
+        &ParserOptions::default(),
+    )?;
+    Ok(())
+}
+
+/// Parses Prescription XML from a buffered reader and writes CSV formatted after
+/// `options` to `writer`.
+pub fn parse_prescription_xml_to_csv_from_reader_with_options<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    options: &ParserOptions,
+) -> Result<ParseReport> {
+    let span = parse_span(PRESCRIPTION_XML, "csv");
+    let _entered = span.enter();
+    let started = Instant::now();
+    let reader = PrescriptionReader::with_options(decode_xml(reader)?, options);
+    let mut records = UniquePrescriptions::new(reader, options.on_duplicate);
+    let columns = &options.prescription_record_columns()?;
+    let mut report = ParseReport::default();
+    let progress = |records: &UniquePrescriptions<_>, written, done| ParseProgress {
+        records: written,
+        rows: vec![(PRESCRIPTIONS_CSV, written)],
+        bytes_read: Some(records.reader.bytes_read()),
+        done,
+    };
+
+    let mut wtr = options.csv_writer(writer);
+    options.write_header(&mut wtr, columns)?;
+    while let Some(record) = records.next_record()? {
+        options.write_row(&mut wtr, columns, &record)?;
+        report.records += 1;
+        if options.progress_due(report.records) {
+            options.report_progress(progress(&records, report.records, false));
+        }
+    }
+    wtr.flush()?;
+    options.report_progress(progress(&records, report.records, true));
+    records.finish(&mut report)?;
+    report.elapsed = started.elapsed();
+    record_parse(&span, PRESCRIPTION_XML, &report);
+
+    Ok(report)
+}
+
+/// Parses the Prescription XML file and writes its content to a CSV file.
+///
+/// Records are streamed one `<prescription>` element at a time.
+pub fn parse_prescription_xml_to_csv<P: AsRef<Path>>(xml_path: P, csv_path: P) -> Result<()> {
+    parse_prescription_xml_to_csv_with_options(xml_path, csv_path, &ParserOptions::default())?;
+    Ok(())
+}
+
+/// Parses the Prescription XML file and writes its content to a CSV file formatted
+/// after `options`.
+pub fn parse_prescription_xml_to_csv_with_options<P: AsRef<Path>>(
+    xml_path: P,
+    csv_path: P,
+    options: &ParserOptions,
+) -> Result<ParseReport> {
+    let (xml_path, csv_path) = (xml_path.as_ref(), csv_path.as_ref());
+    manifest::parse_file_unless_unchanged(xml_path, csv_path, options, || {
+        validated(xml_path, options, || {
+            let reader = open_xml(xml_path)?;
+            let sort_columns =
+                sorting::key_columns(&options.prescription_record_columns()?, &["cod_nacion"]);
+            parts::write_csv_file(csv_path, options, &sort_columns, |output| {
+                parse_prescription_xml_to_csv_from_reader_with_options(reader, output, options)
+            })
+        })
+    })
+}
+
+/// Prescription record with its nested collections, as written to NDJSON
+#[derive(Serialize)]
+struct PrescriptionJson<'a> {
+    #[serde(flatten)]
+    record: &'a PrescriptionRecord,
+    forms: &'a Option<PrescriptionForm>,
+    atc_codes: &'a [PrescriptionAtc],
+    supply_problems: &'a [SupplyProblem],
+    excipients: &'a [PrescriptionExcipient],
+    notes: &'a [PrescriptionNote],
+}
+
+impl<'a> From<&'a PrescriptionRecord> for PrescriptionJson<'a> {
+    fn from(record: &'a PrescriptionRecord) -> Self {
+        PrescriptionJson {
+            record,
+            forms: &record.forms,
+            atc_codes: &record.atc_codes,
+            supply_problems: &record.supply_problems,
+            excipients: &record.excipients,
+            notes: &record.notes,
+        }
+    }
+}
+
+/// Parses Prescription XML from a buffered reader and writes NDJSON to `writer`.
+///
+/// Each line holds one prescription including its nested `forms`, `atc_codes`,
+/// `supply_problems`, `excipients` and `notes`. Records are streamed one `<prescription>` element at a time.
+pub fn parse_prescription_xml_to_ndjson_from_reader<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+) -> Result<()> {
+    let span = parse_span(PRESCRIPTION_XML, "ndjson");
+    let _entered = span.enter();
+    let started = Instant::now();
+    let records = PrescriptionReader::new(decode_xml(reader)?);
+
+    let mut wtr = io::BufWriter::new(writer);
+    let mut report = ParseReport::default();
+    for record in records {
+        let record = record?;
+        write_ndjson_line(&mut wtr, &PrescriptionJson::from(&record))?;
+        report.records += 1;
+    }
+    wtr.flush()?;
+    report.elapsed = started.elapsed();
+    record_parse(&span, PRESCRIPTION_XML, &report);
+
+    Ok(())
+}
+
+/// Parses the Prescription XML file and writes one JSON object per prescription to `out_path`.
+///
+/// See [`parse_prescription_xml_to_ndjson_from_reader`].
+pub fn parse_prescription_xml_to_ndjson<P: AsRef<Path>>(xml_path: P, out_path: P) -> Result<()> {
+    let output = File::create(out_path.as_ref())
+        .with_context(|| format!("Failed to create {}", out_path.as_ref().display()))?;
+    parse_prescription_xml_to_ndjson_from_reader(open_xml(xml_path)?, output)
+}
+
+/// Parses the Prescription XML file into a single output file in the given format.
+///
+/// CSV output contains only the flat prescription fields, NDJSON output also
+/// includes the nested collections.
+pub fn parse_prescription_xml_to_file<P: AsRef<Path>>(
+    xml_path: P,
+    out_path: P,
+    format: OutputFormat,
+) -> Result<()> {
+    match format {
+        OutputFormat::Csv => parse_prescription_xml_to_csv(xml_path, out_path),
+        OutputFormat::NdJson => parse_prescription_xml_to_ndjson(xml_path, out_path),
+    }
+}
+
+/// Prescription file in the nomenclator archive
+pub const PRESCRIPTION_XML: &str = "Prescripcion.xml";
+
+/// Main prescription records file
+pub const PRESCRIPTIONS_CSV: &str = "prescriptions.csv";
+/// Pharmaceutical forms file (1:1 with prescriptions)
+pub const PRESCRIPTION_FORMS_CSV: &str = "prescription_forms.csv";
+/// Active ingredients file (1:N)
+pub const PRESCRIPTION_ACTIVE_INGREDIENTS_CSV: &str = "prescription_active_ingredients.csv";
+/// Administration routes file (1:N)
+pub const PRESCRIPTION_ADMIN_ROUTES_CSV: &str = "prescription_admin_routes.csv";
+/// ATC codes file (1:N)
+pub const PRESCRIPTION_ATC_CSV: &str = "prescription_atc.csv";
+/// ATC duplicates file (nested 1:N)
+pub const PRESCRIPTION_ATC_DUPLICATES_CSV: &str = "prescription_atc_duplicates.csv";
+/// Supply problems file (1:N)
+pub const PRESCRIPTION_SUPPLY_PROBLEMS_CSV: &str = "prescription_supply_problems.csv";
+/// Excipients with obligatory declaration file (1:N)
+pub const PRESCRIPTION_EXCIPIENTS_CSV: &str = "prescription_excipients.csv";
+/// Informational notes file (1:N)
+pub const PRESCRIPTION_NOTES_CSV: &str = "prescription_notes.csv";
+
+/// Every file generated by [`parse_prescription_xml_to_csvs`], in write order
+pub const PRESCRIPTION_CSV_FILES: [&str; 9] = [
+    PRESCRIPTIONS_CSV,
+    PRESCRIPTION_FORMS_CSV,
+    PRESCRIPTION_ACTIVE_INGREDIENTS_CSV,
+    PRESCRIPTION_ADMIN_ROUTES_CSV,
+    PRESCRIPTION_ATC_CSV,
+    PRESCRIPTION_ATC_DUPLICATES_CSV,
+    PRESCRIPTION_SUPPLY_PROBLEMS_CSV,
+    PRESCRIPTION_EXCIPIENTS_CSV,
+    PRESCRIPTION_NOTES_CSV,
+];
+
+/// Number of rows a prescription adds to each file of [`PRESCRIPTION_CSV_FILES`].
+fn prescription_row_counts(record: &PrescriptionRecord) -> [usize; 9] {
+    let form = record.forms.as_ref();
+    [
+        1,
+        usize::from(form.is_some()),
+        form.map_or(0, |form| form.active_ingredients.len()),
+        form.map_or(0, |form| form.admin_routes.len()),
+        record.atc_codes.len(),
+        record
+            .atc_codes
+            .iter()
+            .map(|atc| atc.duplicates.len())
+            .sum(),
+        record.supply_problems.len(),
+        record.excipients.len(),
+        record.notes.len(),
+    ]
+}
 
 /// Parses the Prescription XML file and writes content to multiple CSV files for normalized data.
 ///
-/// This function extracts nested entities (forms, active ingredients, admin routes, ATC codes, supply problems)
-/// into separate CSV files with proper relationships via prescription_id.
+/// This function extracts nested entities (forms, active ingredients, admin routes, ATC codes,
+/// supply problems, excipients, notes) into separate CSV files with proper relationships via
+/// prescription_id.
+///
+/// Records are streamed one `<prescription>` element at a time and written to the
+/// CSV files immediately, so memory usage does not grow with the size of the input.
 ///
 /// # Output Files
 /// - `prescriptions.csv` - Main prescription records
@@ -566,108 +1884,85 @@ impl_xml_parser!(
 /// - `prescription_atc.csv` - ATC codes (1:N)
 /// - `prescription_atc_duplicates.csv` - ATC duplicates (nested 1:N)
 /// - `prescription_supply_problems.csv` - Supply problems (1:N)
+/// - `prescription_excipients.csv` - Excipients with obligatory declaration (1:N)
+/// - `prescription_notes.csv` - Informational notes (1:N)
 pub fn parse_prescription_xml_to_csvs<P: AsRef<Path>>(xml_path: P, output_dir: P) -> Result<()> {
-    let file = File::open(xml_path)?;
-    let reader = BufReader::new(file);
-    let list: PrescriptionList =
-        from_reader(reader).context("Failed to deserialize Prescription XML")?;
-
-    // Create CSV writers for each output file
-    let mut wtr_main = csv::Writer::from_path(output_dir.as_ref().join("prescriptions.csv"))?;
-    let mut wtr_forms = csv::Writer::from_path(output_dir.as_ref().join("prescription_forms.csv"))?;
-    let mut wtr_ingredients = csv::Writer::from_path(
-        output_dir
-            .as_ref()
-            .join("prescription_active_ingredients.csv"),
-    )?;
-    let mut wtr_routes =
-        csv::Writer::from_path(output_dir.as_ref().join("prescription_admin_routes.csv"))?;
-    let mut wtr_atc = csv::Writer::from_path(output_dir.as_ref().join("prescription_atc.csv"))?;
-    let mut wtr_atc_duplicates =
-        csv::Writer::from_path(output_dir.as_ref().join("prescription_atc_duplicates.csv"))?;
-    let mut wtr_supply =
-        csv::Writer::from_path(output_dir.as_ref().join("prescription_supply_problems.csv"))?;
-
-    // Process each prescription record
-    for record in list.records {
-        // Use cod_nacion as prescription ID (matches DB primary key)
-        let prescription_id = record.cod_nacion.clone();
-
-        // Write main prescription record (nested collections are skipped via serde)
-        wtr_main.serialize(&record)?;
-
-        // Write pharmaceutical form and its nested entities
-        if let Some(form) = &record.forms {
-            // Write form record
-            wtr_forms.write_record([
-                &prescription_id,
-                &form.form_code,
-                form.simplified_form_code.as_deref().unwrap_or(""),
-                form.num_active_ingredients.as_deref().unwrap_or(""),
-            ])?;
-
-            // Write active ingredients
-            for ingredient in &form.active_ingredients {
-                wtr_ingredients.write_record([
-                    &prescription_id,
-                    ingredient.active_ingredient_code.as_deref().unwrap_or(""),
-                    ingredient.order.as_deref().unwrap_or(""),
-                    ingredient.dose.as_deref().unwrap_or(""),
-                    ingredient.dose_unit.as_deref().unwrap_or(""),
-                    ingredient.composition_dose.as_deref().unwrap_or(""),
-                    ingredient.composition_unit.as_deref().unwrap_or(""),
-                    ingredient.administration_dose.as_deref().unwrap_or(""),
-                    ingredient.administration_unit.as_deref().unwrap_or(""),
-                    ingredient.prescription_dose.as_deref().unwrap_or(""),
-                    ingredient.prescription_unit.as_deref().unwrap_or(""),
-                ])?;
-            }
-
-            // Write administration routes
-            for route in &form.admin_routes {
-                wtr_routes.write_record([&prescription_id, &route.route_code])?;
-            }
-        }
-
-        // Write ATC codes and their duplicates
-        for atc in &record.atc_codes {
-            wtr_atc.write_record([&prescription_id, &atc.atc_code])?;
-
-            // Write ATC duplicates
-            for duplicate in &atc.duplicates {
-                wtr_atc_duplicates.write_record([
-                    &prescription_id,
-                    &atc.atc_code,
-                    &duplicate.duplicate_atc,
-                    duplicate.description.as_deref().unwrap_or(""),
-                    duplicate.effect.as_deref().unwrap_or(""),
-                    duplicate.recommendation.as_deref().unwrap_or(""),
-                ])?;
-            }
-        }
-
-        // Write supply problems
-        for problem in &record.supply_problems {
-            wtr_supply.write_record([
-                &prescription_id,
-                problem.start_date.as_deref().unwrap_or(""),
-                problem.observations.as_deref().unwrap_or(""),
-            ])?;
-        }
-    }
+    parse_prescription_xml_to_csvs_with_options(xml_path, output_dir, &ParserOptions::default())?;
+    Ok(())
+}
 
-    // Flush all writers
-    wtr_main.flush()?;
-    wtr_forms.flush()?;
-    wtr_ingredients.flush()?;
-    wtr_routes.flush()?;
-    wtr_atc.flush()?;
-    wtr_atc_duplicates.flush()?;
-    wtr_supply.flush()?;
+/// Parses the Prescription XML file into the normalized CSV files formatted after `options`.
+///
+/// See [`parse_prescription_xml_to_csvs`].
+pub fn parse_prescription_xml_to_csvs_with_options<P: AsRef<Path>>(
+    xml_path: P,
+    output_dir: P,
+    options: &ParserOptions,
+) -> Result<ParseReport> {
+    let (xml_path, output_dir) = (xml_path.as_ref(), output_dir.as_ref());
+    manifest::parse_unless_unchanged(
+        xml_path,
+        output_dir,
+        &PRESCRIPTION_CSV_FILES,
+        options,
+        || {
+            validated(xml_path, options, || {
+                parse_prescription_xml_to_csvs_from_reader_with_options(
+                    open_xml(xml_path)?,
+                    |name| {
+                        let path = output_dir.join(name);
+                        File::create(&path)
+                            .with_context(|| format!("Failed to create {}", path.display()))
+                    },
+                    options,
+                )
+            })
+        },
+    )
+}
 
+/// Parses Prescription XML from a buffered reader and writes the normalized CSV files
+/// to writers created by `make_writer`.
+///
+/// `make_writer` is called once for each file name in [`PRESCRIPTION_CSV_FILES`]
+/// before any record is parsed, or, with [`ParserOptions::max_rows_per_file`], with the
+/// name of each part (see [`part_file_name`]) as the previous one fills up.
+pub fn parse_prescription_xml_to_csvs_from_reader<R, W, F>(reader: R, make_writer: F) -> Result<()>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(&str) -> Result<W>,
+{
+    parse_prescription_xml_to_csvs_from_reader_with_options(
+        reader,
+        make_writer,
+        &ParserOptions::default(),
+    )?;
     Ok(())
 }
 
+/// Parses Prescription XML from a buffered reader and writes the normalized CSV files,
+/// formatted after `options`, to writers created by `make_writer`.
+pub fn parse_prescription_xml_to_csvs_from_reader_with_options<R, W, F>(
+    reader: R,
+    make_writer: F,
+    options: &ParserOptions,
+) -> Result<ParseReport>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(&str) -> Result<W>,
+{
+    let span = parse_span(PRESCRIPTION_XML, "csvs");
+    let _entered = span.enter();
+    let reader = PrescriptionReader::with_options(decode_xml(reader)?, options);
+    let report = parts::write_prescription_files(make_writer, options, |writers| {
+        pipeline::write_prescription_csvs(reader, writers, options)
+    })?;
+    record_parse(&span, PRESCRIPTION_XML, &report);
+    Ok(report)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -720,27 +2015,19 @@ mod tests {
 
     #[test]
     fn test_parse_dcp_xml() {
-        let mut xml_file = NamedTempFile::new().unwrap();
-        writeln!(
-            xml_file,
-            r#"<aemps_prescripcion_dcp>
+        let xml = r#"<aemps_prescripcion_dcp>
                 <dcp>
                     <codigodcp>D01</codigodcp>
                     <nombredcp>DCP NAME</nombredcp>
                     <codigodcsa>S01</codigodcsa>
                 </dcp>
-            </aemps_prescripcion_dcp>"#
-        )
-        .unwrap();
-
-        let csv_file = NamedTempFile::new().unwrap();
-        let xml_path = xml_file.path();
-        let csv_path = csv_file.path();
+            </aemps_prescripcion_dcp>"#;
 
-        let result = parse_dcp_xml_to_csv(xml_path, csv_path);
+        let mut csv_output = Vec::new();
+        let result = parse_dcp_xml_to_csv_from_reader(xml.as_bytes(), &mut csv_output);
         assert!(result.is_ok());
 
-        let mut csv_reader = csv::Reader::from_path(csv_path).unwrap();
+        let mut csv_reader = csv::Reader::from_reader(csv_output.as_slice());
 
         // Verify CSV headers use Rust field names
         let headers = csv_reader.headers().unwrap();
@@ -794,26 +2081,18 @@ mod tests {
 
     #[test]
     fn test_parse_dcsa_xml() {
-        let mut xml_file = NamedTempFile::new().unwrap();
-        writeln!(
-            xml_file,
-            r#"<aemps_prescripcion_dcsa>
+        let xml = r#"<aemps_prescripcion_dcsa>
                 <dcsa>
                     <codigodcsa>S01</codigodcsa>
                     <nombredcsa>DCSA NAME</nombredcsa>
                 </dcsa>
-            </aemps_prescripcion_dcsa>"#
-        )
-        .unwrap();
+            </aemps_prescripcion_dcsa>"#;
 
-        let csv_file = NamedTempFile::new().unwrap();
-        let xml_path = xml_file.path();
-        let csv_path = csv_file.path();
-
-        let result = parse_dcsa_xml_to_csv(xml_path, csv_path);
+        let mut csv_output = Vec::new();
+        let result = parse_dcsa_xml_to_csv_from_reader(xml.as_bytes(), &mut csv_output);
         assert!(result.is_ok());
 
-        let mut csv_reader = csv::Reader::from_path(csv_path).unwrap();
+        let mut csv_reader = csv::Reader::from_reader(csv_output.as_slice());
 
         // Verify CSV headers use Rust field names
         let headers = csv_reader.headers().unwrap();
@@ -1022,13 +2301,642 @@ mod tests {
 
         assert_eq!(records[1].get(0).unwrap(), "L02");
         assert_eq!(records[1].get(1).unwrap(), "LAB NAME 2");
-        // Second record has empty strings for optional fields
+        // Missing optional fields are written as `null_repr`, empty by default
         assert_eq!(records[1].get(2).unwrap(), "");
         assert_eq!(records[1].get(3).unwrap(), "");
         assert_eq!(records[1].get(4).unwrap(), "");
         assert_eq!(records[1].get(5).unwrap(), "");
     }
 
+    #[test]
+    fn test_parse_laboratorio_xml_with_options() {
+        let xml = r#"<aemps_prescripcion_laboratorios>
+                <laboratorios>
+                    <codigolaboratorio>L01</codigolaboratorio>
+                    <laboratorio>LAB; NAME</laboratorio>
+                    <direccion>ADDR</direccion>
+                </laboratorios>
+                <laboratorios>
+                    <codigolaboratorio>L02</codigolaboratorio>
+                    <laboratorio>LAB NAME 2</laboratorio>
+                </laboratorios>
+            </aemps_prescripcion_laboratorios>"#;
+        let options = ParserOptions {
+            delimiter: b';',
+            null_repr: "\\N".to_string(),
+            ..Default::default()
+        };
+
+        let mut output = Vec::new();
+        parse_laboratorio_xml_to_csv_from_reader_with_options(
+            xml.as_bytes(),
+            &mut output,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "code;name;address;zip;city;vat\n\
+             L01;\"LAB; NAME\";ADDR;\\N;\\N;\\N\n\
+             L02;LAB NAME 2;\\N;\\N;\\N;\\N\n"
+        );
+    }
+
+    /// An optional element written absent, self-closing, empty and whitespace-only
+    fn empty_variants(element: &str) -> [String; 4] {
+        [
+            String::new(),
+            format!("<{element}/>"),
+            format!("<{element}></{element}>"),
+            format!("<{element}> \n\t</{element}>"),
+        ]
+    }
+
+    #[test]
+    fn test_empty_laboratory_elements_read_as_none() {
+        let elements = ["direccion", "codigopostal", "localidad", "cif"];
+        let options = ParserOptions {
+            null_repr: "\\N".to_string(),
+            ..Default::default()
+        };
+        for (index, element) in elements.iter().enumerate() {
+            for variant in empty_variants(element) {
+                let others: String = elements
+                    .iter()
+                    .filter(|other| *other != element)
+                    .map(|other| format!("<{other}>X</{other}>"))
+                    .collect();
+                let xml = format!(
+                    "<aemps_prescripcion_laboratorios><laboratorios>\
+                     <codigolaboratorio>L01</codigolaboratorio><laboratorio>LAB</laboratorio>\
+                     {variant}{others}</laboratorios></aemps_prescripcion_laboratorios>"
+                );
+                let records = parse_laboratorio_xml_from_reader(xml.as_bytes()).unwrap();
+                let record = &records[0];
+                let fields = [&record.address, &record.zip, &record.city, &record.vat];
+                assert_eq!(*fields[index], None, "{variant:?}");
+                assert!(fields.iter().filter(|field| field.is_none()).count() == 1);
+
+                let mut output = Vec::new();
+                parse_laboratorio_xml_to_csv_from_reader_with_options(
+                    xml.as_bytes(),
+                    &mut output,
+                    &options,
+                )
+                .unwrap();
+                let output = String::from_utf8(output).unwrap();
+                let row: Vec<&str> = output.lines().nth(1).unwrap().split(',').collect();
+                assert_eq!(row[index + 2], "\\N", "{variant:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_prescription_elements_read_as_none() {
+        let base = prescription_xml("600000", "{}")
+            .replace("<laboratorio_titular>LAB</laboratorio_titular>", "");
+        let mut cases: Vec<(String, &str)> = <PrescriptionRecord as schema::Columns>::COLUMNS
+            .iter()
+            .filter(|column| column.nullable)
+            .map(|column| (base.clone(), column.wire_name))
+            .collect();
+        let nested: [(&str, &[&str]); 6] = [
+            (
+                "<formasfarmaceuticas><cod_forfar>1</cod_forfar>{}</formasfarmaceuticas>",
+                &["cod_forfar_simplificada", "nro_pactiv"],
+            ),
+            (
+                "<formasfarmaceuticas><cod_forfar>1</cod_forfar>\
+                 <composicion_pa>{}</composicion_pa></formasfarmaceuticas>",
+                &[
+                    "cod_principio_activo",
+                    "orden_colacion",
+                    "dosis_pa",
+                    "unidad_dosis_pa",
+                    "dosis_composicion",
+                    "unidad_composicion",
+                    "dosis_administracion",
+                    "unidad_administracion",
+                    "dosis_prescripcion",
+                    "unidad_prescripcion",
+                ],
+            ),
+            (
+                "<atc><cod_atc>A</cod_atc><duplicidades>\
+                 <atc_duplicidad>B</atc_duplicidad>{}</duplicidades></atc>",
+                &[
+                    "descripcion_atc_duplicidad",
+                    "efecto_duplicidad",
+                    "recomendacion_duplicidad",
+                ],
+            ),
+            (
+                "<problemassuministro>{}</problemassuministro>",
+                &["fecha_inicio", "observaciones"],
+            ),
+            (
+                "<excipientes><cod_excipiente>1</cod_excipiente>{}</excipientes>",
+                &["cantidad", "unidad"],
+            ),
+            (
+                "<notas>{}</notas>",
+                &[
+                    "tipo_nota",
+                    "num_nota",
+                    "referencia_nota",
+                    "asunto_nota",
+                    "fecha_nota",
+                    "url_nota",
+                ],
+            ),
+        ];
+        for (container, elements) in nested {
+            for element in elements {
+                cases.push((base.replace("{}", container), element));
+            }
+        }
+
+        for (xml, element) in cases {
+            let records: Vec<PrescriptionRecord> = empty_variants(element)
+                .iter()
+                .map(|variant| {
+                    let xml = xml.replace("{}", variant);
+                    let (record, _) = deserialize_prescription(xml.as_bytes(), Default::default());
+                    record.unwrap_or_else(|e| panic!("{variant}: {e:#}"))
+                })
+                .collect();
+            for record in &records[1..] {
+                assert_eq!(*record, records[0], "{element}");
+            }
+        }
+
+        // None is written as the null representation
+        let xml = format!(
+            "<aemps_prescripcion>{}</aemps_prescripcion>",
+            base.replace("{}", "<cod_dcsa> </cod_dcsa><notas><tipo_nota/></notas>")
+        );
+        let options = ParserOptions {
+            null_repr: "\\N".to_string(),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        parse_prescription_xml_to_csv_from_reader_with_options(
+            xml.as_bytes(),
+            &mut output,
+            &options,
+        )
+        .unwrap();
+        let mut reader = csv::Reader::from_reader(output.as_slice());
+        let cod_dcsa = reader
+            .headers()
+            .unwrap()
+            .iter()
+            .position(|name| name == "cod_dcsa")
+            .unwrap();
+        let row = reader.records().next().unwrap().unwrap();
+        assert_eq!(&row[cod_dcsa], "\\N");
+    }
+
+    #[test]
+    fn test_on_duplicate() {
+        let xml = r#"<aemps_prescripcion_atc>
+                <atc><nroatc>1</nroatc><codigoatc>A01</codigoatc><descatc>FIRST</descatc></atc>
+                <atc><nroatc>2</nroatc><codigoatc>B01</codigoatc><descatc>BLOOD</descatc></atc>
+                <atc><nroatc>3</nroatc><codigoatc>A01</codigoatc><descatc>LAST</descatc></atc>
+            </aemps_prescripcion_atc>"#;
+        let parse = |on_duplicate| {
+            let options = ParserOptions {
+                on_duplicate,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            parse_atc_xml_to_csv_from_reader_with_options(xml.as_bytes(), &mut output, &options)
+                .map(|report| (report, String::from_utf8(output).unwrap()))
+        };
+
+        let (report, csv) = parse(OnDuplicate::KeepFirst).unwrap();
+        assert_eq!(csv, "number,code,description\n1,A01,FIRST\n2,B01,BLOOD\n");
+        assert_eq!(report.records, 2);
+        assert_eq!(report.duplicates, 1);
+        assert_eq!(report.duplicate_keys, vec!["A01"]);
+
+        let (report, csv) = parse(OnDuplicate::KeepLast).unwrap();
+        assert_eq!(csv, "number,code,description\n2,B01,BLOOD\n3,A01,LAST\n");
+        assert_eq!(report.duplicates, 1);
+        assert_eq!(report.duplicate_keys, vec!["A01"]);
+
+        let err = parse(OnDuplicate::Error).unwrap_err();
+        assert_eq!(err.to_string(), "Duplicate keys in ATC XML: A01");
+    }
+
+    #[test]
+    fn test_on_duplicate_prescriptions() {
+        let xml = format!(
+            "<aemps_prescripcion>{}{}{}</aemps_prescripcion>",
+            prescription_xml("600000", "<atc><cod_atc>FIRST</cod_atc></atc>"),
+            prescription_xml("600001", ""),
+            prescription_xml("600000", "<atc><cod_atc>LAST</cod_atc></atc>"),
+        );
+        let parse = |on_duplicate| {
+            let options = ParserOptions {
+                on_duplicate,
+                ..Default::default()
+            };
+            let mut outputs = std::collections::HashMap::new();
+            parse_prescription_xml_to_csvs_from_reader_with_options(
+                xml.as_bytes(),
+                |name| {
+                    let buffer = SharedBuffer::default();
+                    outputs.insert(name.to_string(), buffer.clone());
+                    Ok(buffer)
+                },
+                &options,
+            )
+            .map(|report| (report, outputs[PRESCRIPTION_ATC_CSV].to_string()))
+        };
+
+        let (report, atc) = parse(OnDuplicate::KeepFirst).unwrap();
+        assert_eq!(atc, "600000,FIRST\n");
+        assert_eq!((report.records, report.duplicates), (2, 1));
+        assert_eq!(report.duplicate_keys, vec!["600000"]);
+
+        let (report, atc) = parse(OnDuplicate::KeepLast).unwrap();
+        assert_eq!(atc, "600000,LAST\n");
+        assert_eq!((report.records, report.duplicates), (2, 1));
+
+        let err = parse(OnDuplicate::Error).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Duplicate keys in Prescription XML: 600000"
+        );
+    }
+
+    #[test]
+    fn test_bool_repr_prescriptions() {
+        let xml_path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/delta/Prescripcion.xml");
+        let columns = <PrescriptionRecord as schema::Columns>::COLUMNS;
+        let parse = |bool_repr| {
+            let output_dir = tempfile::tempdir().unwrap();
+            let options = ParserOptions {
+                bool_repr,
+                ..Default::default()
+            };
+            parse_prescription_xml_to_csvs_with_options(
+                xml_path.as_path(),
+                output_dir.path(),
+                &options,
+            )
+            .unwrap();
+            let mut reader =
+                csv::Reader::from_path(output_dir.path().join(PRESCRIPTIONS_CSV)).unwrap();
+            reader
+                .records()
+                .map(|row| row.unwrap().iter().map(str::to_string).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        let expected = parse(BoolRepr::TrueFalse);
+        assert_eq!(expected.len(), 3);
+        for (bool_repr, (true_text, false_text)) in [
+            (BoolRepr::TrueFalse, ("true", "false")),
+            (BoolRepr::OneZero, ("1", "0")),
+            (BoolRepr::TF, ("T", "F")),
+        ] {
+            let rows = parse(bool_repr);
+            assert_eq!(rows.len(), expected.len());
+            for (row, expected_row) in rows.iter().zip(&expected) {
+                for ((column, field), expected_field) in columns.iter().zip(row).zip(expected_row) {
+                    if column.column_type != schema::ColumnType::Boolean {
+                        assert_eq!(field, expected_field, "{}", column.name);
+                    } else if expected_field == "true" {
+                        assert_eq!(field, true_text, "{}", column.name);
+                    } else {
+                        assert_eq!(field, false_text, "{}", column.name);
+                    }
+                }
+            }
+        }
+        // The fixture has both values
+        let flags: Vec<&String> = expected
+            .iter()
+            .flat_map(|row| columns.iter().zip(row))
+            .filter(|(column, _)| column.column_type == schema::ColumnType::Boolean)
+            .map(|(_, flag)| flag)
+            .collect();
+        assert!(
+            flags.iter().any(|flag| *flag == "true") && flags.iter().any(|flag| *flag == "false")
+        );
+    }
+
+    fn errors_fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/errors")
+            .join(name)
+    }
+
+    #[test]
+    fn test_on_error_prescriptions() {
+        let xml_path = errors_fixture("Prescripcion.xml");
+        let parse = |on_error, workers| {
+            let output_dir = tempfile::tempdir().unwrap();
+            let options = ParserOptions {
+                on_error,
+                workers,
+                ..Default::default()
+            };
+            parse_prescription_xml_to_csvs_with_options(
+                xml_path.as_path(),
+                output_dir.path(),
+                &options,
+            )
+            .map(|report| {
+                let read = |name| std::fs::read_to_string(output_dir.path().join(name)).unwrap();
+                (report, read(PRESCRIPTIONS_CSV), read(PRESCRIPTION_ATC_CSV))
+            })
+        };
+
+        for workers in [1, 4] {
+            let (report, prescriptions, atc) = parse(OnError::SkipAndReport, workers).unwrap();
+            assert_eq!(report.records, 2);
+            assert_eq!(report.errors.len(), 1);
+            let error = &report.errors[0];
+            assert_eq!((error.index, error.key.as_deref()), (1, Some("600001")));
+            assert!(error.message.contains("got 'X'"), "{}", error.message);
+
+            let codes: Vec<_> = prescriptions
+                .lines()
+                .skip(1)
+                .map(|line| line.split(',').next().unwrap())
+                .collect();
+            assert_eq!(codes, ["600000", "600002"], "workers {workers}");
+            assert_eq!(atc, "600000,N02BE01\n600002,N02BE01\n");
+
+            let err = parse(OnError::Fail, workers).unwrap_err();
+            assert!(format!("{err:#}").contains("got 'X'"), "{err:#}");
+        }
+
+        // The streaming reader skips the record as well
+        let options = ParserOptions {
+            on_error: OnError::SkipAndReport,
+            ..Default::default()
+        };
+        let mut reader = PrescriptionReader::with_options(open_xml(&xml_path).unwrap(), &options);
+        let records: Vec<_> = reader.by_ref().map(|record| record.unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(reader.errors()[0].key.as_deref(), Some("600001"));
+    }
+
+    #[test]
+    fn test_on_error_dictionary() {
+        let xml_path = errors_fixture("DICCIONARIO_ATC.xml");
+        let output_dir = tempfile::tempdir().unwrap();
+        let csv_path = output_dir.path().join("atc.csv");
+        let options = ParserOptions {
+            on_error: OnError::SkipAndReport,
+            ..Default::default()
+        };
+
+        let report =
+            parse_atc_xml_to_csv_with_options(xml_path.as_path(), &csv_path, &options).unwrap();
+        assert_eq!(report.records, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].index, 1);
+        assert_eq!(report.errors[0].key.as_deref(), Some("A01"));
+        assert_eq!(
+            std::fs::read_to_string(&csv_path).unwrap(),
+            "number,code,description\n1,A,TRACTO ALIMENTARIO Y METABOLISMO\n3,A01A,PREPARADOS ESTOMATOLÓGICOS\n"
+        );
+
+        let err = parse_atc_xml_to_csv_with_options(
+            xml_path.as_path(),
+            &csv_path,
+            &ParserOptions::default(),
+        )
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("Failed to deserialize ATC XML"));
+    }
+
+    #[test]
+    fn test_progress_callback() {
+        let xml = format!(
+            "<aemps_prescripcion>{}</aemps_prescripcion>",
+            (0..1000)
+                .map(|i| prescription_xml(
+                    &format!("{:06}", 600000 + i),
+                    "<atc><cod_atc>N02BE01</cod_atc></atc>"
+                ))
+                .collect::<String>()
+        );
+        let updates = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = updates.clone();
+        let options = ParserOptions {
+            progress: Some(std::sync::Arc::new(move |progress| {
+                received.lock().unwrap().push(progress)
+            })),
+            progress_interval: 100,
+            ..Default::default()
+        };
+
+        parse_prescription_xml_to_csvs_from_reader_with_options(
+            xml.as_bytes(),
+            |_| Ok(io::sink()),
+            &options,
+        )
+        .unwrap();
+
+        let updates = updates.lock().unwrap();
+        assert_eq!(updates.len(), 11);
+        assert!(updates[..10].iter().all(|progress| !progress.done));
+        assert_eq!(updates[0].records, 100);
+        assert!(
+            updates
+                .windows(2)
+                .all(|pair| pair[0].bytes_read < pair[1].bytes_read)
+        );
+
+        let last = updates.last().unwrap();
+        assert!(last.done);
+        assert_eq!(last.records, 1000);
+        assert_eq!(last.bytes_read, Some(xml.len() as u64));
+        assert_eq!(last.rows.len(), PRESCRIPTION_CSV_FILES.len());
+        assert!(last.rows.contains(&(PRESCRIPTION_ATC_CSV, 1000)));
+        assert!(last.rows.contains(&(PRESCRIPTION_FORMS_CSV, 0)));
+    }
+
+    #[test]
+    fn test_header_style() {
+        let atc_xml = r#"<aemps_prescripcion_atc>
+                <atc>
+                    <nroatc>1</nroatc>
+                    <codigoatc>A</codigoatc>
+                    <descatc>A - ALIMENTARY TRACT AND METABOLISM</descatc>
+                </atc>
+            </aemps_prescripcion_atc>"#;
+        let laboratory_xml = r#"<aemps_prescripcion_laboratorios>
+                <laboratorios>
+                    <codigolaboratorio>L01</codigolaboratorio>
+                    <laboratorio>LAB NAME</laboratorio>
+                </laboratorios>
+            </aemps_prescripcion_laboratorios>"#;
+        let header = |output: Vec<u8>| {
+            let output = String::from_utf8(output).unwrap();
+            output.lines().next().unwrap().to_string()
+        };
+
+        for (style, atc_header, laboratory_header) in [
+            (
+                HeaderStyle::English,
+                "number,code,description",
+                "code,name,address,zip,city,vat",
+            ),
+            (
+                HeaderStyle::Spanish,
+                "nroatc,codigoatc,descatc",
+                "codigolaboratorio,laboratorio,direccion,codigopostal,localidad,cif",
+            ),
+        ] {
+            let options = ParserOptions {
+                header_style: style,
+                ..Default::default()
+            };
+
+            let mut atc = Vec::new();
+            parse_atc_xml_to_csv_from_reader_with_options(atc_xml.as_bytes(), &mut atc, &options)
+                .unwrap();
+            assert_eq!(header(atc), atc_header);
+
+            let mut laboratories = Vec::new();
+            parse_laboratorio_xml_to_csv_from_reader_with_options(
+                laboratory_xml.as_bytes(),
+                &mut laboratories,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(header(laboratories), laboratory_header);
+        }
+    }
+
+    #[test]
+    fn test_parse_prescription_to_multi_csv_with_options() {
+        let xml = format!(
+            "<aemps_prescripcion>{}</aemps_prescripcion>",
+            prescription_xml(
+                "600000",
+                r#"<formasfarmaceuticas>
+                    <cod_forfar>288</cod_forfar>
+                    <composicion_pa>
+                        <cod_principio_activo>160</cod_principio_activo>
+                        <dosis_pa>500</dosis_pa>
+                    </composicion_pa>
+                </formasfarmaceuticas>"#
+            )
+        );
+        let options = ParserOptions {
+            delimiter: b';',
+            null_repr: "\\N".to_string(),
+            bool_repr: BoolRepr::OneZero,
+            ..Default::default()
+        };
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let xml_file = output_dir.path().join("Prescripcion.xml");
+        std::fs::write(&xml_file, xml).unwrap();
+        parse_prescription_xml_to_csvs_with_options(
+            xml_file.as_path(),
+            output_dir.path(),
+            &options,
+        )
+        .unwrap();
+
+        let read = |name| std::fs::read_to_string(output_dir.path().join(name)).unwrap();
+        assert_eq!(read(PRESCRIPTION_FORMS_CSV), "600000;288;\\N;\\N\n");
+        assert_eq!(
+            read(PRESCRIPTION_ACTIVE_INGREDIENTS_CSV),
+            "600000;160;\\N;500;\\N;\\N;\\N;\\N;\\N;\\N;\\N\n"
+        );
+
+        let main = read(PRESCRIPTIONS_CSV);
+        let mut lines = main.lines();
+        let header: Vec<&str> = lines.next().unwrap().split(';').collect();
+        let row: Vec<&str> = lines.next().unwrap().split(';').collect();
+        let field = |name| row[header.iter().position(|column| *column == name).unwrap()];
+        assert_eq!(field("cod_nacion"), "600000");
+        assert_eq!(field("sw_receta"), "1");
+        assert_eq!(field("sw_psicotropo"), "0");
+        assert_eq!(field("url_fictec"), "\\N");
+    }
+
+    #[test]
+    fn test_prescription_columns() {
+        let xml = format!(
+            "<aemps_prescripcion>{}</aemps_prescripcion>",
+            prescription_xml("600000", "<atc><cod_atc>N02BE01</cod_atc></atc>")
+        );
+        let options = ParserOptions {
+            prescription_columns: Some(vec![
+                "laboratorio_titular".to_string(),
+                "des_nomco".to_string(),
+                "sw_receta".to_string(),
+            ]),
+            ..Default::default()
+        };
+        let output_dir = tempfile::tempdir().unwrap();
+        let xml_file = output_dir.path().join("Prescripcion.xml");
+        std::fs::write(&xml_file, xml).unwrap();
+        parse_prescription_xml_to_csvs_with_options(
+            xml_file.as_path(),
+            output_dir.path(),
+            &options,
+        )
+        .unwrap();
+
+        let read = |name| std::fs::read_to_string(output_dir.path().join(name)).unwrap();
+        assert_eq!(
+            read(PRESCRIPTIONS_CSV),
+            "cod_nacion,des_nomco,sw_receta,laboratorio_titular\n\
+             600000,\"TEST & \"\"CO\"\"\",true,LAB\n"
+        );
+        assert_eq!(read(PRESCRIPTION_ATC_CSV), "600000,N02BE01\n");
+
+        let schema = postgres_schema_sql_with_options(&options).unwrap();
+        assert!(schema.contains(
+            "CREATE TABLE prescriptions (\n    \"cod_nacion\" text NOT NULL,\n    \
+             \"des_nomco\" text NOT NULL,\n    \"sw_receta\" boolean NOT NULL,\n    \
+             \"laboratorio_titular\" text,\n    PRIMARY KEY (\"cod_nacion\"),\n    \
+             FOREIGN KEY (\"laboratorio_titular\")"
+        ));
+        assert!(!schema.contains("\"cod_dcsa\""));
+
+        // The key is kept and unknown names are rejected
+        let options = ParserOptions {
+            prescription_columns: Some(vec!["cod_nacionn".to_string()]),
+            prescription_key: PrescriptionKey::Both,
+            ..Default::default()
+        };
+        assert_eq!(
+            options
+                .prescription_record_columns()
+                .unwrap_err()
+                .to_string()
+                .split(", expected")
+                .next(),
+            Some("Unknown prescription column \"cod_nacionn\"")
+        );
+        let options = ParserOptions {
+            prescription_columns: Some(Vec::new()),
+            ..options
+        };
+        let names: Vec<_> = options
+            .prescription_record_columns()
+            .unwrap()
+            .iter()
+            .map(|column| column.name)
+            .collect();
+        assert_eq!(names, ["cod_nacion", "nro_definitivo"]);
+    }
+
     #[test]
     fn test_parse_principio_activo_xml() {
         let mut xml_file = NamedTempFile::new().unwrap();
@@ -1282,6 +3190,22 @@ mod tests {
                     <atc>
                         <cod_atc>J01CR02</cod_atc>
                     </atc>
+                    <excipientes>
+                        <cod_excipiente>1234</cod_excipiente>
+                        <cantidad>12,5</cantidad>
+                        <unidad>mg</unidad>
+                    </excipientes>
+                    <excipientes>
+                        <cod_excipiente>5678</cod_excipiente>
+                    </excipientes>
+                    <notas>
+                        <tipo_nota>1</tipo_nota>
+                        <num_nota>2024/01</num_nota>
+                        <referencia_nota>MUH (FV), 1/2024</referencia_nota>
+                        <asunto_nota>Nota informativa</asunto_nota>
+                        <fecha_nota>15/01/2024</fecha_nota>
+                        <url_nota>https://www.aemps.gob.es/nota</url_nota>
+                    </notas>
                 </prescription>
             </aemps_prescripcion>"#
         )
@@ -1296,7 +3220,7 @@ mod tests {
             result.err()
         );
 
-        // Verify all 7 CSV files were created
+        // Verify all CSV files were created
         assert!(output_dir.path().join("prescriptions.csv").exists());
         assert!(output_dir.path().join("prescription_forms.csv").exists());
         assert!(
@@ -1312,7 +3236,514 @@ mod tests {
                 .exists()
         );
         assert!(output_dir.path().join("prescription_atc.csv").exists());
+        for file in PRESCRIPTION_CSV_FILES {
+            assert!(output_dir.path().join(file).exists(), "{file}");
+        }
+
+        let read = |name| std::fs::read_to_string(output_dir.path().join(name)).unwrap();
+        assert_eq!(
+            read(PRESCRIPTION_EXCIPIENTS_CSV),
+            "600000,1234,\"12,5\",mg\n600000,5678,,\n"
+        );
+        assert_eq!(
+            read(PRESCRIPTION_NOTES_CSV),
+            "600000,1,2024/01,\"MUH (FV), 1/2024\",Nota informativa,15/01/2024,https://www.aemps.gob.es/nota\n"
+        );
+
+        println!("Multi-CSV test passed! All 9 files created successfully");
+    }
+
+    pub(super) fn prescription_xml(cod_nacion: &str, extra: &str) -> String {
+        format!(
+            r#"<prescription>
+                    <cod_nacion>{cod_nacion}</cod_nacion>
+                    <nro_definitivo>66337</nro_definitivo>
+                    <des_nomco>TEST &amp; "CO"</des_nomco>
+                    <des_prese>TEST, 20 comprimidos</des_prese>
+                    <sw_psicotropo>0</sw_psicotropo>
+                    <sw_estupefaciente>0</sw_estupefaciente>
+                    <sw_afecta_conduccion>0</sw_afecta_conduccion>
+                    <sw_triangulo_negro>0</sw_triangulo_negro>
+                    <sw_receta>1</sw_receta>
+                    <sw_generico>1</sw_generico>
+                    <sw_sustituible>1</sw_sustituible>
+                    <sw_envase_clinico>1</sw_envase_clinico>
+                    <sw_uso_hospitalario>1</sw_uso_hospitalario>
+                    <sw_diagnostico_hospitalario>0</sw_diagnostico_hospitalario>
+                    <sw_tld>0</sw_tld>
+                    <sw_especial_control_medico>0</sw_especial_control_medico>
+                    <sw_huerfano>0</sw_huerfano>
+                    <sw_base_a_plantas>0</sw_base_a_plantas>
+                    <laboratorio_titular>LAB</laboratorio_titular>
+                    <sw_comercializado>0</sw_comercializado>
+                    <sw_tiene_excipientes_decl_obligatoria>0</sw_tiene_excipientes_decl_obligatoria>
+                    <biosimilar>0</biosimilar>
+                    <importacion_paralela>0</importacion_paralela>
+                    <radiofarmaco>0</radiofarmaco>
+                    <serializacion>1</serializacion>
+                    {extra}
+                </prescription>"#
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "test-util")]
+    fn test_streaming_prescription_csv_matches_in_memory() {
+        let xml_file = NamedTempFile::new().unwrap();
+        crate::testing::fixtures::generate_prescription_xml(300, 1, xml_file.as_file()).unwrap();
+
+        // Reference output produced from a fully deserialized list
+        let file = File::open(xml_file.path()).unwrap();
+        let list: PrescriptionList = from_reader(BufReader::new(file)).unwrap();
+        let mut expected = csv::Writer::from_writer(Vec::new());
+        for record in list.records {
+            expected.serialize(record).unwrap();
+        }
+        let expected = expected.into_inner().unwrap();
+
+        let csv_file = NamedTempFile::new().unwrap();
+        parse_prescription_xml_to_csv(xml_file.path(), csv_file.path()).unwrap();
+
+        assert_eq!(std::fs::read(csv_file.path()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_prescription_reader_iterates_records() {
+        let xml = format!(
+            "<aemps_prescripcion>{}{}</aemps_prescripcion>",
+            prescription_xml("600000", ""),
+            prescription_xml("600001", "")
+        );
+
+        let records: Vec<PrescriptionRecord> = PrescriptionReader::new(xml.as_bytes())
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].cod_nacion, "600000");
+        assert_eq!(records[0].des_nomco, "TEST & \"CO\"");
+        assert_eq!(records[1].cod_nacion, "600001");
+    }
+
+    #[test]
+    fn test_prescription_reader_truncated_file() {
+        let xml = "<aemps_prescripcion><prescription><cod_nacion>600000</cod_nacion>";
+
+        let result: Result<Vec<PrescriptionRecord>> =
+            PrescriptionReader::new(xml.as_bytes()).collect();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_prescription_reader_elements_cut_by_buffer() {
+        let xml = format!(
+            "<?xml version=\"1.0\"?>\n<aemps_prescripcion>\n<header><date>1</date></header>\n  {}\
+             <!-- <prescription> -->{}text{}\n</aemps_prescripcion>\n",
+            prescription_xml("600000", ""),
+            prescription_xml("600001", "").replace("</prescription>", "</prescription\n>"),
+            prescription_xml("600002", "<notas><tipo_nota>1</tipo_nota></notas>"),
+        );
+        fn read<R: BufRead>(
+            mut reader: PrescriptionReader<R>,
+            length: usize,
+        ) -> Vec<PrescriptionRecord> {
+            let records = reader.by_ref().collect::<Result<_>>().unwrap();
+            assert_eq!(reader.bytes_read(), length as u64);
+            records
+        }
+
+        let records = read(PrescriptionReader::new(xml.as_bytes()), xml.len());
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].notes.len(), 1);
+        for capacity in 1..64 {
+            let source = BufReader::with_capacity(capacity, xml.as_bytes());
+            assert_eq!(read(PrescriptionReader::new(source), xml.len()), records);
+        }
+    }
+
+    #[test]
+    fn test_prescription_reader_markup_inside_elements() {
+        let xml = format!(
+            "<aemps_prescripcion><prescriptions>2</prescriptions>{}\
+             <prescription_count>2</prescription_count>{}</aemps_prescripcion>",
+            prescription_xml("600000", "<!-- </prescription> --><?pi </prescription>?>").replace(
+                "<des_nomco>TEST &amp; \"CO\"</des_nomco>",
+                "<des_nomco><![CDATA[A </prescription> B]]></des_nomco>"
+            ),
+            prescription_xml("600001", "<prescription_note>1</prescription_note>"),
+        );
+
+        for capacity in [1, 7, 64, 8192] {
+            let source = BufReader::with_capacity(capacity, xml.as_bytes());
+            let records: Vec<PrescriptionRecord> = PrescriptionReader::new(source)
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[0].cod_nacion, "600000");
+            assert_eq!(records[0].des_nomco, "A </prescription> B");
+            assert_eq!(records[1].cod_nacion, "600001");
+        }
+    }
 
-        println!("Multi-CSV test passed! All 7 files created successfully");
+    #[test]
+    fn test_prescription_reader_lenient_flags() {
+        let xml = format!(
+            "<aemps_prescripcion>{}</aemps_prescripcion>",
+            prescription_xml("600000", "")
+                .replace("<sw_receta>1</sw_receta>", "<sw_receta> S </sw_receta>")
+                .replace("<sw_generico>1</sw_generico>", "<sw_generico/>")
+                .replace("<sw_tld>0</sw_tld>", "<sw_tld></sw_tld>")
+                .replace(
+                    "<biosimilar>0</biosimilar>",
+                    "<biosimilar>true</biosimilar>"
+                )
+                .replace(
+                    "<sw_huerfano>0</sw_huerfano>",
+                    "<sw_huerfano>n</sw_huerfano>"
+                )
+        );
+
+        let mut reader = PrescriptionReader::new(xml.as_bytes());
+        let record = reader.next_record().unwrap().unwrap();
+        assert!(record.sw_receta);
+        assert!(!record.sw_generico);
+        assert!(!record.sw_tld);
+        assert!(record.biosimilar);
+        assert!(!record.sw_huerfano);
+        assert_eq!(reader.empty_flags(), 2);
+
+        let options = ParserOptions {
+            bool_parsing: BoolParsing::Lenient { empty_value: true },
+            ..Default::default()
+        };
+        let mut reader = PrescriptionReader::with_options(xml.as_bytes(), &options);
+        let record = reader.next_record().unwrap().unwrap();
+        assert!(record.sw_generico);
+        assert!(record.sw_tld);
+        assert_eq!(reader.empty_flags(), 2);
+
+        let options = ParserOptions {
+            bool_parsing: BoolParsing::Strict,
+            ..Default::default()
+        };
+        let result = PrescriptionReader::with_options(xml.as_bytes(), &options).next_record();
+        assert!(result.is_err());
+
+        let unknown = xml.replace("<sw_receta> S </sw_receta>", "<sw_receta>X</sw_receta>");
+        let result = PrescriptionReader::new(unknown.as_bytes()).next_record();
+        assert!(format!("{:#}", result.unwrap_err()).contains("got 'X'"));
+    }
+
+    /// In-memory writer whose contents stay readable after the CSV writer is dropped
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl std::fmt::Display for SharedBuffer {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(&String::from_utf8_lossy(&self.0.borrow()))
+        }
+    }
+
+    fn encoding_fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/encoding")
+            .join(name)
+    }
+
+    #[test]
+    fn test_declared_encoding() {
+        let latin1 = declared_encoding(br#"<?xml version="1.0" encoding="ISO-8859-1"?><a/>"#);
+        assert_eq!(latin1, Some(encoding_rs::WINDOWS_1252));
+
+        let single_quotes = declared_encoding(b"<?xml version='1.0' encoding='utf-8'?>");
+        assert_eq!(single_quotes, Some(UTF_8));
+
+        assert_eq!(declared_encoding(br#"<?xml version="1.0"?>"#), None);
+        assert_eq!(declared_encoding(b"<root/>"), None);
+    }
+
+    #[test]
+    fn test_parse_laboratorio_xml_encodings() {
+        for name in [
+            "DICCIONARIO_LABORATORIOS_utf8_bom.xml",
+            "DICCIONARIO_LABORATORIOS_latin1.xml",
+        ] {
+            let csv_file = NamedTempFile::new().unwrap();
+            parse_laboratorio_xml_to_csv(encoding_fixture(name).as_path(), csv_file.path())
+                .unwrap_or_else(|e| panic!("{}: {:#}", name, e));
+
+            let mut csv_reader = csv::Reader::from_path(csv_file.path()).unwrap();
+            assert_eq!(csv_reader.headers().unwrap().get(0).unwrap(), "code");
+
+            let records: Vec<csv::StringRecord> =
+                csv_reader.records().map(|r| r.unwrap()).collect();
+            assert_eq!(records.len(), 1, "{}", name);
+            assert_eq!(records[0].get(1).unwrap(), "LABORATORIOS ESPAÑOLES, S.A.");
+            assert_eq!(records[0].get(2).unwrap(), "Calle Alcalá, 15");
+            assert_eq!(records[0].get(4).unwrap(), "Cádiz");
+        }
+    }
+
+    #[test]
+    fn test_parse_via_administracion_xml_encodings() {
+        for (name, second) in [
+            (
+                "DICCIONARIO_VIAS_ADMINISTRACION_utf8.xml",
+                "INTRAPERITONEAL – USO CRÓNICO",
+            ),
+            (
+                "DICCIONARIO_VIAS_ADMINISTRACION_windows1252.xml",
+                "INTRAPERITONEAL – USO CRÓNICO",
+            ),
+            (
+                "DICCIONARIO_VIAS_ADMINISTRACION_latin1.xml",
+                "INTRAPERITONEAL USO CRÓNICO",
+            ),
+        ] {
+            let csv_file = NamedTempFile::new().unwrap();
+            parse_via_administracion_xml_to_csv(encoding_fixture(name).as_path(), csv_file.path())
+                .unwrap_or_else(|e| panic!("{}: {:#}", name, e));
+
+            let content = std::fs::read_to_string(csv_file.path()).unwrap();
+            assert!(content.contains("HEMODIÁLISIS"), "{}: {}", name, content);
+            assert!(content.contains(second), "{}: {}", name, content);
+        }
+    }
+
+    #[test]
+    fn test_parse_atc_xml_records() {
+        let mut xml_file = NamedTempFile::new().unwrap();
+        writeln!(
+            xml_file,
+            r#"<aemps_prescripcion_atc>
+                <atc>
+                    <nroatc>1</nroatc>
+                    <codigoatc>A01</codigoatc>
+                    <descatc>A01 - DIGESTIVE</descatc>
+                </atc>
+                <atc>
+                    <nroatc>2</nroatc>
+                    <codigoatc>B01</codigoatc>
+                    <descatc>BLOOD</descatc>
+                </atc>
+            </aemps_prescripcion_atc>"#
+        )
+        .unwrap();
+
+        let records = parse_atc_xml(xml_file.path()).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].number, 1);
+        assert_eq!(records[0].code, "A01");
+        assert_eq!(records[0].description, "DIGESTIVE");
+        assert_eq!(records[1].code, "B01");
+        assert_eq!(records[1].description, "BLOOD");
+    }
+
+    #[test]
+    fn test_parse_laboratorio_xml_records() {
+        let records =
+            parse_laboratorio_xml(encoding_fixture("DICCIONARIO_LABORATORIOS_latin1.xml")).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].code, "1");
+        assert_eq!(records[0].zip.as_deref(), Some("28014"));
+        assert_eq!(records[0].city.as_deref(), Some("Cádiz"));
+    }
+
+    #[test]
+    fn test_parse_prescription_xml_records() {
+        let mut xml_file = NamedTempFile::new().unwrap();
+        writeln!(
+            xml_file,
+            r#"<aemps_prescripcion>
+                <header><listprescriptiondate>01/01/2024</listprescriptiondate></header>
+                {}
+                {}
+            </aemps_prescripcion>"#,
+            prescription_xml("600000", "<atc><cod_atc>J01CR02</cod_atc></atc>"),
+            prescription_xml("600001", ""),
+        )
+        .unwrap();
+
+        let list = parse_prescription_xml(xml_file.path()).unwrap();
+
+        assert_eq!(
+            list.header.unwrap().listprescriptiondate,
+            "01/01/2024".to_string()
+        );
+        assert_eq!(list.records.len(), 2);
+        assert_eq!(list.records[0].cod_nacion, "600000");
+        assert_eq!(list.records[0].atc_codes[0].atc_code, "J01CR02");
+        assert!(list.records[0].sw_receta);
+        assert!(list.records[1].atc_codes.is_empty());
+    }
+
+    #[test]
+    fn test_parse_atc_xml_from_zip_entry() {
+        let mut archive = Vec::new();
+        {
+            let mut zip = zip::ZipWriter::new(io::Cursor::new(&mut archive));
+            zip.start_file(
+                "DICCIONARIO_ATC.xml",
+                zip::write::SimpleFileOptions::default(),
+            )
+            .unwrap();
+            zip.write_all(
+                br#"<aemps_prescripcion_atc>
+                    <atc><nroatc>1</nroatc><codigoatc>A01</codigoatc><descatc>A01 - DIGESTIVE</descatc></atc>
+                </aemps_prescripcion_atc>"#,
+            )
+            .unwrap();
+            zip.finish().unwrap();
+        }
+
+        let mut archive = zip::ZipArchive::new(io::Cursor::new(archive)).unwrap();
+        let entry = archive.by_name("DICCIONARIO_ATC.xml").unwrap();
+        let records = parse_atc_xml_from_reader(BufReader::new(entry)).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].code, "A01");
+        assert_eq!(records[0].description, "DIGESTIVE");
+    }
+
+    #[test]
+    fn test_parse_prescription_to_multi_csv_from_reader() {
+        let xml = format!(
+            "<aemps_prescripcion>{}{}</aemps_prescripcion>",
+            prescription_xml("600000", "<atc><cod_atc>J01CR02</cod_atc></atc>"),
+            prescription_xml("600001", "<atc><cod_atc>N02BE01</cod_atc></atc>")
+        );
+
+        let mut outputs = std::collections::HashMap::new();
+        parse_prescription_xml_to_csvs_from_reader(xml.as_bytes(), |name| {
+            let buffer = SharedBuffer::default();
+            outputs.insert(name.to_string(), buffer.clone());
+            Ok(buffer)
+        })
+        .unwrap();
+
+        assert_eq!(outputs.len(), PRESCRIPTION_CSV_FILES.len());
+        let atc = outputs[PRESCRIPTION_ATC_CSV].to_string();
+        assert_eq!(atc, "600000,J01CR02\n600001,N02BE01\n");
+        let main = outputs[PRESCRIPTIONS_CSV].to_string();
+        assert_eq!(main.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_parse_prescription_to_ndjson() {
+        let xml = format!(
+            r#"<aemps_prescripcion>
+                {}
+                {}
+            </aemps_prescripcion>"#,
+            prescription_xml(
+                "600000",
+                r#"<formasfarmaceuticas>
+                    <cod_forfar>10</cod_forfar>
+                    <composicion_pa><cod_principio_activo>123</cod_principio_activo></composicion_pa>
+                    <viasadministracion><cod_via_admin>48</cod_via_admin></viasadministracion>
+                </formasfarmaceuticas>
+                <atc>
+                    <cod_atc>J01CR02</cod_atc>
+                    <duplicidades><atc_duplicidad>J01CA04</atc_duplicidad></duplicidades>
+                </atc>
+                <problemassuministro><fecha_inicio>01/01/2024</fecha_inicio></problemassuministro>"#
+            ),
+            prescription_xml("600001", ""),
+        );
+
+        let mut output = Vec::new();
+        parse_prescription_xml_to_ndjson_from_reader(xml.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+
+        let first = &lines[0];
+        assert_eq!(first["cod_nacion"], "600000");
+        assert_eq!(first["des_nomco"], r#"TEST & "CO""#);
+        assert_eq!(first["sw_receta"], true);
+        assert_eq!(first["forms"]["form_code"], "10");
+        assert_eq!(
+            first["forms"]["active_ingredients"][0]["active_ingredient_code"],
+            "123"
+        );
+        assert_eq!(first["forms"]["admin_routes"][0]["route_code"], "48");
+        assert_eq!(first["atc_codes"][0]["atc_code"], "J01CR02");
+        assert_eq!(
+            first["atc_codes"][0]["duplicates"][0]["duplicate_atc"],
+            "J01CA04"
+        );
+        assert_eq!(first["supply_problems"][0]["start_date"], "01/01/2024");
+
+        let second = &lines[1];
+        assert_eq!(second["cod_nacion"], "600001");
+        assert!(second["forms"].is_null());
+        assert_eq!(second["atc_codes"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_parse_dictionary_to_ndjson() {
+        let mut output = Vec::new();
+        parse_laboratorio_xml_to_ndjson_from_reader(
+            BufReader::new(
+                File::open(encoding_fixture("DICCIONARIO_LABORATORIOS_latin1.xml")).unwrap(),
+            ),
+            &mut output,
+        )
+        .unwrap();
+        let expected =
+            parse_laboratorio_xml(encoding_fixture("DICCIONARIO_LABORATORIOS_latin1.xml")).unwrap();
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), expected.len());
+        for (line, record) in lines.iter().zip(&expected) {
+            assert_eq!(line, &serde_json::to_value(record).unwrap());
+        }
+
+        let xml_file = NamedTempFile::new().unwrap();
+        std::fs::write(
+            xml_file.path(),
+            r#"<aemps_prescripcion_atc>
+                <atc><nroatc>1</nroatc><codigoatc>A01</codigoatc><descatc>A01 - DIGESTIVE</descatc></atc>
+            </aemps_prescripcion_atc>"#,
+        )
+        .unwrap();
+        let out_file = NamedTempFile::new().unwrap();
+        parse_atc_xml_to_ndjson(xml_file.path(), out_file.path()).unwrap();
+        let line = std::fs::read_to_string(out_file.path()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(value["code"], "A01");
+        assert_eq!(value["description"], "DIGESTIVE");
+    }
+
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!("csv".parse::<OutputFormat>().unwrap(), OutputFormat::Csv);
+        assert_eq!(
+            "NDJSON".parse::<OutputFormat>().unwrap(),
+            OutputFormat::NdJson
+        );
+        assert_eq!(OutputFormat::NdJson.extension(), "ndjson");
+        assert!("xml".parse::<OutputFormat>().is_err());
     }
 }
@@ -0,0 +1,391 @@
+//! Arrow record batches from the nomenclator parsers (requires the `arrow` feature).
+
+use super::dates;
+use super::schema::{Column, ColumnType, Columns};
+use super::{PrescriptionReader, PrescriptionRecord, XmlSource};
+use anyhow::{Context, Result};
+use arrow_array::builder::{BooleanBuilder, Date32Builder, Int32Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use chrono::NaiveDate;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Builds the Arrow schema for a column list.
+///
+/// Booleans map to `Boolean`, integers to `Int32`, dates to `Date32` and any other
+/// column to `Utf8`.
+pub fn arrow_schema(columns: &[Column]) -> SchemaRef {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|column| {
+            let data_type = match column.column_type {
+                ColumnType::Boolean => DataType::Boolean,
+                ColumnType::Int32 => DataType::Int32,
+                ColumnType::Text => DataType::Utf8,
+                ColumnType::Date => DataType::Date32,
+            };
+            Field::new(column.name, data_type, column.nullable)
+        })
+        .collect();
+    Arc::new(Schema::new(fields))
+}
+
+/// Parses a date as [`dates::parse_date`] into days since the Unix epoch.
+fn parse_date(value: &str) -> Option<i32> {
+    let date = dates::parse_date(value)?;
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1)?;
+    Some((date - epoch).num_days() as i32)
+}
+
+enum ColumnBuilder {
+    Boolean(BooleanBuilder),
+    Int32(Int32Builder),
+    Utf8(StringBuilder),
+    Date32(Date32Builder),
+}
+
+impl ColumnBuilder {
+    fn new(column_type: ColumnType) -> Self {
+        match column_type {
+            ColumnType::Boolean => ColumnBuilder::Boolean(BooleanBuilder::new()),
+            ColumnType::Int32 => ColumnBuilder::Int32(Int32Builder::new()),
+            ColumnType::Text => ColumnBuilder::Utf8(StringBuilder::new()),
+            ColumnType::Date => ColumnBuilder::Date32(Date32Builder::new()),
+        }
+    }
+
+    fn append(&mut self, column: &Column, value: Option<&Value>) -> Result<()> {
+        let value = value.filter(|value| !value.is_null());
+        match (self, value) {
+            (ColumnBuilder::Boolean(builder), value) => {
+                builder.append_option(value.and_then(Value::as_bool))
+            }
+            (ColumnBuilder::Int32(builder), value) => builder.append_option(
+                value
+                    .and_then(Value::as_i64)
+                    .and_then(|v| i32::try_from(v).ok()),
+            ),
+            (ColumnBuilder::Utf8(builder), value) => {
+                builder.append_option(value.and_then(Value::as_str))
+            }
+            (ColumnBuilder::Date32(builder), None) => builder.append_null(),
+            (ColumnBuilder::Date32(builder), Some(value)) => {
+                let text = value.as_str().unwrap_or_default();
+                let days = parse_date(text).with_context(|| {
+                    format!("Invalid date '{}' in column {}", text, column.name)
+                })?;
+                builder.append_value(days);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Boolean(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Int32(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Utf8(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Date32(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+/// Accumulates serialized rows into a [`RecordBatch`] typed after a column list
+pub(crate) struct RecordBatchBuilder {
+    columns: &'static [Column],
+    schema: SchemaRef,
+    builders: Vec<ColumnBuilder>,
+    rows: usize,
+}
+
+impl RecordBatchBuilder {
+    pub(crate) fn new(columns: &'static [Column]) -> Self {
+        RecordBatchBuilder {
+            columns,
+            schema: arrow_schema(columns),
+            builders: columns
+                .iter()
+                .map(|column| ColumnBuilder::new(column.column_type))
+                .collect(),
+            rows: 0,
+        }
+    }
+
+    pub(crate) fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.rows
+    }
+
+    /// Appends a row, matching its serialized fields to the columns by name.
+    pub(crate) fn append<T: Serialize>(&mut self, row: &T) -> Result<()> {
+        let fields = match serde_json::to_value(row)? {
+            Value::Object(fields) => fields,
+            _ => anyhow::bail!("Expected a record serializing to an object"),
+        };
+        for (column, builder) in self.columns.iter().zip(self.builders.iter_mut()) {
+            builder.append(column, fields.get(column.name))?;
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Returns the accumulated rows as a batch and starts a new one.
+    pub(crate) fn finish(&mut self) -> Result<RecordBatch> {
+        let arrays = self
+            .builders
+            .iter_mut()
+            .map(ColumnBuilder::finish)
+            .collect();
+        self.rows = 0;
+        RecordBatch::try_new(self.schema.clone(), arrays).context("Failed to build record batch")
+    }
+}
+
+/// Converts dictionary records into a single [`RecordBatch`] typed after [`Columns::COLUMNS`].
+pub fn records_to_record_batch<T: Serialize + Columns>(records: &[T]) -> Result<RecordBatch> {
+    let mut builder = RecordBatchBuilder::new(T::COLUMNS);
+    for record in records {
+        builder.append(record)?;
+    }
+    builder.finish()
+}
+
+macro_rules! impl_record_batch {
+    ($name:literal, $batch_fn:ident, $parse_fn:ident) => {
+        #[doc = concat!("Parses the ", $name, " XML file into a single Arrow record batch.")]
+        pub fn $batch_fn<P: AsRef<Path>>(xml_path: P) -> Result<RecordBatch> {
+            records_to_record_batch(&super::$parse_fn(xml_path)?)
+        }
+    };
+}
+
+impl_record_batch!("ATC", parse_atc_xml_to_record_batch, parse_atc_xml);
+impl_record_batch!("DCP", parse_dcp_xml_to_record_batch, parse_dcp_xml);
+impl_record_batch!("DCPF", parse_dcpf_xml_to_record_batch, parse_dcpf_xml);
+impl_record_batch!("DCSA", parse_dcsa_xml_to_record_batch, parse_dcsa_xml);
+impl_record_batch!(
+    "Envases",
+    parse_envases_xml_to_record_batch,
+    parse_envases_xml
+);
+impl_record_batch!(
+    "Excipientes",
+    parse_excipientes_xml_to_record_batch,
+    parse_excipientes_xml
+);
+impl_record_batch!(
+    "Forma Farmaceutica",
+    parse_forma_farmaceutica_xml_to_record_batch,
+    parse_forma_farmaceutica_xml
+);
+impl_record_batch!(
+    "Forma Farmaceutica Simplificada",
+    parse_forma_farmaceutica_simplificada_xml_to_record_batch,
+    parse_forma_farmaceutica_simplificada_xml
+);
+impl_record_batch!(
+    "Laboratorio",
+    parse_laboratorio_xml_to_record_batch,
+    parse_laboratorio_xml
+);
+impl_record_batch!(
+    "Principio Activo",
+    parse_principio_activo_xml_to_record_batch,
+    parse_principio_activo_xml
+);
+impl_record_batch!(
+    "Situacion Registro",
+    parse_situacion_registro_xml_to_record_batch,
+    parse_situacion_registro_xml
+);
+impl_record_batch!(
+    "Unidad Contenido",
+    parse_unidad_contenido_xml_to_record_batch,
+    parse_unidad_contenido_xml
+);
+impl_record_batch!(
+    "Via Administracion",
+    parse_via_administracion_xml_to_record_batch,
+    parse_via_administracion_xml
+);
+
+/// Iterator over record batches of the main prescription table
+///
+/// Created by [`prescriptions_to_record_batches`].
+pub struct PrescriptionBatches<R: BufRead> {
+    records: PrescriptionReader<R>,
+    builder: RecordBatchBuilder,
+    batch_size: usize,
+    done: bool,
+}
+
+impl<R: BufRead> PrescriptionBatches<R> {
+    /// Batches the records of `records`, `batch_size` rows at a time.
+    pub fn new(records: PrescriptionReader<R>, batch_size: usize) -> Self {
+        PrescriptionBatches {
+            records,
+            builder: RecordBatchBuilder::new(PrescriptionRecord::COLUMNS),
+            batch_size: batch_size.max(1),
+            done: false,
+        }
+    }
+
+    /// Schema of the produced batches
+    pub fn schema(&self) -> SchemaRef {
+        self.builder.schema()
+    }
+
+    fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        while !self.done && self.builder.len() < self.batch_size {
+            match self.records.next_record()? {
+                Some(record) => self.builder.append(&record)?,
+                None => self.done = true,
+            }
+        }
+        if self.builder.len() == 0 {
+            return Ok(None);
+        }
+        self.builder.finish().map(Some)
+    }
+}
+
+impl<R: BufRead> Iterator for PrescriptionBatches<R> {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = self.next_batch();
+        if batch.is_err() {
+            self.done = true;
+        }
+        batch.transpose()
+    }
+}
+
+/// Streams the main prescription table of a Prescription XML file as Arrow record batches.
+///
+/// Records are parsed one `<prescription>` element at a time, so memory usage is
+/// proportional to `batch_size` rather than to the size of the file.
+pub fn prescriptions_to_record_batches<P: AsRef<Path>>(
+    xml_path: P,
+    batch_size: usize,
+) -> Result<PrescriptionBatches<XmlSource>> {
+    Ok(PrescriptionBatches::new(
+        PrescriptionReader::from_path(xml_path)?,
+        batch_size,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::prescription_xml;
+    use super::super::{AtcRecord, PrescriptionReader};
+    use super::*;
+    use arrow_array::Array;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Date32Type;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("01/01/1970"), Some(0));
+        assert_eq!(parse_date("1970-01-02"), Some(1));
+        assert_eq!(parse_date("29/02/2024"), Some(19782));
+        assert_eq!(parse_date("31/12/1969"), Some(-1));
+        assert_eq!(parse_date("2024"), None);
+        assert_eq!(parse_date("32/01/2024"), None);
+    }
+
+    #[test]
+    fn test_prescription_record_batches() {
+        let xml = format!(
+            "<aemps_prescripcion>{}{}{}</aemps_prescripcion>",
+            prescription_xml(
+                "600000",
+                "<fecha_autorizacion>29/02/2024</fecha_autorizacion>"
+            ),
+            prescription_xml("600001", ""),
+            prescription_xml("600002", ""),
+        );
+        let records = PrescriptionReader::new(xml.as_bytes());
+        let batches: Vec<RecordBatch> = PrescriptionBatches::new(records, 2)
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].num_rows(), 2);
+        assert_eq!(batches[1].num_rows(), 1);
+
+        let schema = batches[0].schema();
+        assert_eq!(schema.fields().len(), PrescriptionRecord::COLUMNS.len());
+        assert_eq!(
+            schema.field_with_name("sw_receta").unwrap().data_type(),
+            &DataType::Boolean
+        );
+        assert_eq!(
+            schema.field_with_name("des_nomco").unwrap().data_type(),
+            &DataType::Utf8
+        );
+        assert_eq!(
+            schema
+                .field_with_name("fecha_autorizacion")
+                .unwrap()
+                .data_type(),
+            &DataType::Date32
+        );
+
+        let dates = batches[0]
+            .column_by_name("fecha_autorizacion")
+            .unwrap()
+            .as_primitive::<Date32Type>();
+        assert_eq!(dates.value(0), 19782);
+        assert!(dates.is_null(1));
+
+        let codes = batches[1]
+            .column_by_name("cod_nacion")
+            .unwrap()
+            .as_string::<i32>();
+        assert_eq!(codes.value(0), "600002");
+    }
+
+    #[test]
+    fn test_prescription_record_batches_invalid_date() {
+        let xml = format!(
+            "<aemps_prescripcion>{}</aemps_prescripcion>",
+            prescription_xml("600000", "<fec_comer>not a date</fec_comer>"),
+        );
+        let mut batches = PrescriptionBatches::new(PrescriptionReader::new(xml.as_bytes()), 10);
+
+        let err = batches.next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("fec_comer"));
+        assert!(batches.next().is_none());
+    }
+
+    #[test]
+    fn test_records_to_record_batch() {
+        let records = vec![
+            AtcRecord {
+                number: 1,
+                code: "A01".to_string(),
+                description: "DIGESTIVE".to_string(),
+            },
+            AtcRecord {
+                number: 2,
+                code: "B01".to_string(),
+                description: "BLOOD".to_string(),
+            },
+        ];
+
+        let batch = records_to_record_batch(&records).unwrap();
+
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.schema().field(0).data_type(), &DataType::Int32);
+        let codes = batch.column_by_name("code").unwrap().as_string::<i32>();
+        assert_eq!(codes.value(1), "B01");
+    }
+}
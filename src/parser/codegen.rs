@@ -0,0 +1,278 @@
+//! Generation of Rust enums from the small nomenclator dictionaries.
+//!
+//! Registration statuses, container units, administration routes and the like are
+//! small and rarely change, so code handling them can match on enum variants instead
+//! of code strings. The generated source only depends on the dictionary contents:
+//! entries are sorted by code, so regenerating an unchanged dictionary gives the same
+//! file and a changed one gives a reviewable diff.
+
+use super::dictionary::DictionaryKind;
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Dictionaries turned into enums by [`generate_dictionary_enums`], with the enum names
+pub const ENUM_DICTIONARIES: [(DictionaryKind, &str); 5] = [
+    (DictionaryKind::Containers, "Container"),
+    (DictionaryKind::SimplifiedForms, "SimplifiedForm"),
+    (DictionaryKind::RegistrationStatuses, "RegistrationStatus"),
+    (DictionaryKind::ContainerUnits, "ContainerUnit"),
+    (DictionaryKind::AdministrationRoutes, "AdministrationRoute"),
+];
+
+/// Reads the `(code, name)` pairs of a dictionary from its XML file, or from the CSV
+/// file written by its parser when `path` has a `.csv` extension.
+///
+/// Only the dictionaries of [`ENUM_DICTIONARIES`] are supported.
+pub fn dictionary_codes<P: AsRef<Path>>(
+    kind: DictionaryKind,
+    path: P,
+) -> Result<Vec<(String, String)>> {
+    let path = path.as_ref();
+    let csv = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    macro_rules! codes {
+        ($parse_fn:ident, $load_fn:ident) => {
+            if csv {
+                super::$load_fn(path)?
+            } else {
+                super::$parse_fn(path)?
+            }
+            .into_iter()
+            .map(|record| (record.code, record.name))
+            .collect()
+        };
+    }
+    Ok(match kind {
+        DictionaryKind::Containers => codes!(parse_envases_xml, load_envases_csv),
+        DictionaryKind::SimplifiedForms => codes!(
+            parse_forma_farmaceutica_simplificada_xml,
+            load_forma_farmaceutica_simplificada_csv
+        ),
+        DictionaryKind::RegistrationStatuses => {
+            codes!(parse_situacion_registro_xml, load_situacion_registro_csv)
+        }
+        DictionaryKind::ContainerUnits => {
+            codes!(parse_unidad_contenido_xml, load_unidad_contenido_csv)
+        }
+        DictionaryKind::AdministrationRoutes => {
+            codes!(parse_via_administracion_xml, load_via_administracion_csv)
+        }
+        _ => anyhow::bail!("No enum is generated for {kind}"),
+    })
+}
+
+/// Rust source of an enum named `enum_name` with one variant per entry of `codes`.
+///
+/// Variants are named after the Spanish name in UpperCamelCase without accents
+/// (`VÍA ORAL` becomes `ViaOral`), followed by the code when two names clash, and
+/// documented with the name as written. Names without letters give `Code` followed by
+/// the code. The enum has `from_code` and `as_code` methods and an `Unknown(String)`
+/// variant for codes added to the dictionary later. Repeated codes keep their first
+/// name.
+pub fn generate_dictionary_enum(
+    enum_name: &str,
+    kind: DictionaryKind,
+    codes: &[(String, String)],
+) -> String {
+    let mut seen = HashSet::new();
+    let mut entries: Vec<(&str, &str)> = codes
+        .iter()
+        .map(|(code, name)| (code.trim(), name.trim()))
+        .filter(|(code, _)| seen.insert(*code))
+        .collect();
+    entries.sort_by_key(|(code, _)| match code.parse::<u64>() {
+        Ok(number) => (0, number, *code),
+        Err(_) => (1, 0, *code),
+    });
+
+    let mut variants: Vec<String> = entries
+        .iter()
+        .map(|(code, name)| variant_name(name, code))
+        .collect();
+    let mut uses: HashMap<String, usize> = HashMap::new();
+    for variant in &variants {
+        *uses.entry(variant.clone()).or_default() += 1;
+    }
+    for (variant, (code, _)) in variants.iter_mut().zip(&entries) {
+        if uses[variant.as_str()] > 1 || matches!(variant.as_str(), "Unknown" | "Self") {
+            variant.push_str(&code_suffix(code));
+        }
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "/// Codes of {}", kind.default_xml_filename());
+    let _ = writeln!(out, "#[derive(Debug, Clone, PartialEq, Eq, Hash)]");
+    let _ = writeln!(out, "pub enum {enum_name} {{");
+    for ((_, name), variant) in entries.iter().zip(&variants) {
+        let _ = writeln!(out, "    /// {name}");
+        let _ = writeln!(out, "    {variant},");
+    }
+    let _ = writeln!(
+        out,
+        "    /// Code missing from the dictionary the enum was generated from"
+    );
+    let _ = writeln!(out, "    Unknown(String),");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "impl {enum_name} {{");
+    let _ = writeln!(out, "    /// Variant of an AEMPS code");
+    let _ = writeln!(out, "    pub fn from_code(code: &str) -> Self {{");
+    let _ = writeln!(out, "        match code {{");
+    for ((code, _), variant) in entries.iter().zip(&variants) {
+        let _ = writeln!(out, "            {code:?} => Self::{variant},");
+    }
+    let _ = writeln!(out, "            _ => Self::Unknown(code.to_string()),");
+    let _ = writeln!(out, "        }}");
+    let _ = writeln!(out, "    }}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "    /// AEMPS code of the variant");
+    let _ = writeln!(out, "    pub fn as_code(&self) -> &str {{");
+    let _ = writeln!(out, "        match self {{");
+    for ((code, _), variant) in entries.iter().zip(&variants) {
+        let _ = writeln!(out, "            Self::{variant} => {code:?},");
+    }
+    let _ = writeln!(out, "            Self::Unknown(code) => code,");
+    let _ = writeln!(out, "        }}");
+    let _ = writeln!(out, "    }}");
+    let _ = writeln!(out, "}}");
+    out
+}
+
+/// Rust source of the enums of [`ENUM_DICTIONARIES`], read from `dir`.
+///
+/// Each dictionary is read from its XML file in `dir` or, when missing, from its CSV
+/// file, so both a work directory and an output directory of
+/// [`parse_all_nomenclator`](super::parse_all_nomenclator) can be used.
+pub fn generate_dictionary_enums<P: AsRef<Path>>(dir: P) -> Result<String> {
+    let dir = dir.as_ref();
+    let mut out = String::from(
+        "// @generated by `nomenclator codegen` from the AEMPS nomenclator dictionaries.\n\
+         // Do not edit: regenerate it instead.\n",
+    );
+    for (kind, enum_name) in ENUM_DICTIONARIES {
+        let xml_path = dir.join(kind.default_xml_filename());
+        let path = if xml_path.exists() {
+            xml_path
+        } else {
+            dir.join(kind.default_csv_filename())
+        };
+        anyhow::ensure!(
+            path.exists(),
+            "Neither {} nor {} found in {}",
+            kind.default_xml_filename(),
+            kind.default_csv_filename(),
+            dir.display()
+        );
+        let codes = dictionary_codes(kind, &path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        tracing::debug!(%kind, codes = codes.len(), "Generating {enum_name}");
+        out.push('\n');
+        out.push_str(&generate_dictionary_enum(enum_name, kind, &codes));
+    }
+    Ok(out)
+}
+
+/// UpperCamelCase identifier for a Spanish dictionary name.
+fn variant_name(name: &str, code: &str) -> String {
+    let mut variant = String::new();
+    for word in name
+        .chars()
+        .map(unaccent)
+        .collect::<String>()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            variant.push(first.to_ascii_uppercase());
+            variant.extend(chars.map(|c| c.to_ascii_lowercase()));
+        }
+    }
+    if variant.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        variant
+    } else {
+        format!("Code{}", code_suffix(code))
+    }
+}
+
+/// Code as it can follow a variant name, non alphanumeric characters replaced by `_`.
+fn code_suffix(code: &str) -> String {
+    code.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// ASCII letter of a Spanish accented letter, other characters unchanged.
+fn unaccent(c: char) -> char {
+    match c {
+        'á' | 'à' | 'ä' | 'â' => 'a',
+        'Á' | 'À' | 'Ä' | 'Â' => 'A',
+        'é' | 'è' | 'ë' | 'ê' => 'e',
+        'É' | 'È' | 'Ë' | 'Ê' => 'E',
+        'í' | 'ì' | 'ï' | 'î' => 'i',
+        'Í' | 'Ì' | 'Ï' | 'Î' => 'I',
+        'ó' | 'ò' | 'ö' | 'ô' => 'o',
+        'Ó' | 'Ò' | 'Ö' | 'Ô' => 'O',
+        'ú' | 'ù' | 'ü' | 'û' => 'u',
+        'Ú' | 'Ù' | 'Ü' | 'Û' => 'U',
+        'ñ' => 'n',
+        'Ñ' => 'N',
+        'ç' => 'c',
+        'Ç' => 'C',
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variant_names() {
+        assert_eq!(variant_name("VÍA ORAL", "48"), "ViaOral");
+        assert_eq!(
+            variant_name("INTRAPERITONEAL – USO CRÓNICO", "12"),
+            "IntraperitonealUsoCronico"
+        );
+        assert_eq!(
+            variant_name("Solución/suspensión", "7"),
+            "SolucionSuspension"
+        );
+        assert_eq!(variant_name("1/2 COMPRIMIDO", "3"), "Code3");
+        assert_eq!(variant_name(" - ", "A.1"), "CodeA_1");
+    }
+
+    #[test]
+    fn test_enum_is_sorted_and_unambiguous() {
+        let codes = [
+            ("10".to_string(), "ML".to_string()),
+            ("2".to_string(), "ml".to_string()),
+            ("1".to_string(), "Unknown".to_string()),
+            ("2".to_string(), "repeated".to_string()),
+        ];
+        let source = generate_dictionary_enum("Unit", DictionaryKind::ContainerUnits, &codes);
+        let variants: Vec<&str> = source
+            .lines()
+            .filter(|line| line.starts_with("            \""))
+            .collect();
+        assert_eq!(
+            variants,
+            [
+                "            \"1\" => Self::Unknown1,",
+                "            \"2\" => Self::Ml2,",
+                "            \"10\" => Self::Ml10,",
+            ]
+        );
+
+        let mut reversed = codes.to_vec();
+        reversed.reverse();
+        reversed.retain(|(_, name)| name != "repeated");
+        assert_eq!(
+            generate_dictionary_enum("Unit", DictionaryKind::ContainerUnits, &reversed),
+            source
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Serialization of records straight into CSV fields, see
+//! [`ParserOptions::write_row`](super::ParserOptions).
+
+use super::options::ParserOptions;
+use super::schema::Column;
+use serde::Serialize;
+use serde::ser::{self, Error as _, Impossible};
+use std::io::Write;
+
+/// Serializer writing the fields of a record named by `columns` to a CSV writer
+///
+/// Records serialize their fields in the order of their column list, which may leave
+/// some of them out, so each field is matched against the columns not written yet and
+/// skipped when absent. Columns without a field are written as nulls. Nothing is
+/// buffered: each value goes to the writer as it is serialized.
+pub(crate) struct RowSerializer<'a, W: Write> {
+    wtr: &'a mut csv::Writer<W>,
+    options: &'a ParserOptions,
+    columns: &'a [Column],
+    /// Index of the next column to write
+    next: usize,
+    in_row: bool,
+}
+
+impl<'a, W: Write> RowSerializer<'a, W> {
+    pub(crate) fn new(
+        wtr: &'a mut csv::Writer<W>,
+        options: &'a ParserOptions,
+        columns: &'a [Column],
+    ) -> Self {
+        RowSerializer {
+            wtr,
+            options,
+            columns,
+            next: 0,
+            in_row: false,
+        }
+    }
+
+    fn write_field(&mut self, value: impl AsRef<[u8]>) -> csv::Result<()> {
+        if !self.in_row {
+            return Err(csv::Error::custom(
+                "Expected a record serializing to a struct",
+            ));
+        }
+        self.wtr.write_field(value)
+    }
+
+    fn write_null(&mut self) -> csv::Result<()> {
+        let options = self.options;
+        self.write_field(&options.null_repr)
+    }
+}
+
+macro_rules! serialize_display {
+    ($($method:ident: $type:ty),*) => {
+        $(
+            fn $method(self, value: $type) -> csv::Result<()> {
+                self.write_field(value.to_string())
+            }
+        )*
+    };
+}
+
+impl<W: Write> ser::Serializer for &mut RowSerializer<'_, W> {
+    type Ok = ();
+    type Error = csv::Error;
+    type SerializeSeq = Impossible<(), csv::Error>;
+    type SerializeTuple = Impossible<(), csv::Error>;
+    type SerializeTupleStruct = Impossible<(), csv::Error>;
+    type SerializeTupleVariant = Impossible<(), csv::Error>;
+    type SerializeMap = Impossible<(), csv::Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), csv::Error>;
+
+    fn serialize_bool(self, value: bool) -> csv::Result<()> {
+        let text = self.options.bool_repr.format(value);
+        self.write_field(text)
+    }
+
+    serialize_display!(
+        serialize_i8: i8, serialize_i16: i16, serialize_i32: i32, serialize_i64: i64,
+        serialize_u8: u8, serialize_u16: u16, serialize_u32: u32, serialize_u64: u64,
+        serialize_f32: f32, serialize_f64: f64, serialize_char: char
+    );
+
+    fn serialize_str(self, value: &str) -> csv::Result<()> {
+        self.write_field(value)
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> csv::Result<()> {
+        self.write_field(value)
+    }
+
+    fn serialize_none(self) -> csv::Result<()> {
+        self.write_null()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> csv::Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> csv::Result<()> {
+        self.write_null()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> csv::Result<()> {
+        self.write_null()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> csv::Result<()> {
+        self.write_field(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> csv::Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _value: &T,
+    ) -> csv::Result<()> {
+        Err(csv::Error::custom(format!(
+            "Cannot write {name}::{variant} to a CSV field"
+        )))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> csv::Result<Self::SerializeSeq> {
+        Err(csv::Error::custom("Cannot write a sequence to a CSV field"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> csv::Result<Self::SerializeTuple> {
+        Err(csv::Error::custom("Cannot write a tuple to a CSV field"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> csv::Result<Self::SerializeTupleStruct> {
+        Err(csv::Error::custom(format!(
+            "Cannot write {name} to a CSV field"
+        )))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> csv::Result<Self::SerializeTupleVariant> {
+        Err(csv::Error::custom(format!(
+            "Cannot write {name}::{variant} to a CSV field"
+        )))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> csv::Result<Self::SerializeMap> {
+        Err(csv::Error::custom("Cannot write a map to a CSV field"))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> csv::Result<Self::SerializeStruct> {
+        if self.in_row {
+            return Err(csv::Error::custom(format!(
+                "Cannot write {name} to a CSV field"
+            )));
+        }
+        self.in_row = true;
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> csv::Result<Self::SerializeStructVariant> {
+        Err(csv::Error::custom(format!(
+            "Cannot write {name}::{variant} to a CSV field"
+        )))
+    }
+}
+
+impl<W: Write> ser::SerializeStruct for &mut RowSerializer<'_, W> {
+    type Ok = ();
+    type Error = csv::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> csv::Result<()> {
+        let Some(offset) = self.columns[self.next..]
+            .iter()
+            .position(|column| column.name == key)
+        else {
+            return Ok(());
+        };
+        for _ in 0..offset {
+            self.write_null()?;
+        }
+        self.next += offset + 1;
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> csv::Result<()> {
+        for _ in self.next..self.columns.len() {
+            self.write_null()?;
+        }
+        self.wtr.write_record(None::<&[u8]>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::options::BoolRepr;
+    use super::super::schema::{Column, ColumnType};
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Row {
+        code: &'static str,
+        skipped: u8,
+        number: i32,
+        flag: bool,
+        missing: Option<&'static str>,
+    }
+
+    const fn column(name: &'static str) -> Column {
+        Column {
+            name,
+            wire_name: name,
+            column_type: ColumnType::Text,
+            nullable: true,
+        }
+    }
+
+    #[test]
+    fn test_fields_written_by_column() {
+        let options = ParserOptions {
+            null_repr: "NULL".to_string(),
+            bool_repr: BoolRepr::OneZero,
+            ..Default::default()
+        };
+        let row = Row {
+            code: "A, B",
+            skipped: 7,
+            number: -3,
+            flag: true,
+            missing: None,
+        };
+        let columns = [
+            column("unknown"),
+            column("code"),
+            column("number"),
+            column("between"),
+            column("flag"),
+            column("missing"),
+            column("last"),
+        ];
+        let mut wtr = options.csv_writer(Vec::new());
+        row.serialize(&mut RowSerializer::new(&mut wtr, &options, &columns))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(wtr.into_inner().unwrap()).unwrap(),
+            "NULL,\"A, B\",-3,NULL,1,NULL,NULL\n"
+        );
+
+        let mut wtr = options.csv_writer(Vec::new());
+        let error = "A".serialize(&mut RowSerializer::new(&mut wtr, &options, &columns));
+        assert!(error.is_err());
+    }
+}
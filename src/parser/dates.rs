@@ -0,0 +1,158 @@
+//! Normalization of the prescription date columns to ISO 8601.
+//!
+//! Prescripcion.xml writes dates as `dd/mm/yyyy` and, in some dumps, `yyyymmdd`. With
+//! [`ParserOptions::normalize_dates`](super::ParserOptions::normalize_dates) they are
+//! rewritten as `yyyy-mm-dd`, which Postgres and SQLite read without a date style.
+
+use super::PrescriptionRecord;
+use chrono::NaiveDate;
+
+/// Columns rewritten by [`ParserOptions::normalize_dates`](super::ParserOptions::normalize_dates),
+/// named as in [`NUMERIC_COLUMNS`](super::NUMERIC_COLUMNS)
+pub const DATE_COLUMNS: [&str; 5] = [
+    "prescriptions.fecha_autorizacion",
+    "prescriptions.fec_comer",
+    "prescriptions.fecha_situacion_registro",
+    "prescriptions.fec_sitreg_presen",
+    "prescription_supply_problems.start_date",
+];
+
+/// Values per column of [`DATE_COLUMNS`] that could not be parsed
+pub(crate) type UnparsedDates = [usize; DATE_COLUMNS.len()];
+
+/// Parses a `dd/mm/yyyy`, `yyyymmdd` or `yyyy-mm-dd` date.
+pub(crate) fn parse_date(value: &str) -> Option<NaiveDate> {
+    let value = value.trim();
+    if value.len() == 8 && value.bytes().all(|b| b.is_ascii_digit()) {
+        return NaiveDate::from_ymd_opt(
+            value[..4].parse().ok()?,
+            value[4..6].parse().ok()?,
+            value[6..].parse().ok()?,
+        );
+    }
+    let format = if value.contains('/') {
+        "%d/%m/%Y"
+    } else {
+        "%Y-%m-%d"
+    };
+    NaiveDate::parse_from_str(value, format).ok()
+}
+
+/// Rewrites the [`DATE_COLUMNS`] of `record` as `yyyy-mm-dd` in place, leaving empty
+/// and unparseable values untouched, and returns the number of unparseable values per
+/// column.
+pub(crate) fn normalize_prescription_dates(record: &mut PrescriptionRecord) -> UnparsedDates {
+    let mut unparsed = UnparsedDates::default();
+    let mut normalize = |value: &mut Option<String>, column: usize| {
+        let Some(text) = value.as_mut() else {
+            return;
+        };
+        if text.trim().is_empty() {
+            return;
+        }
+        match parse_date(text) {
+            Some(date) => *text = date.format("%Y-%m-%d").to_string(),
+            None => unparsed[column] += 1,
+        }
+    };
+
+    normalize(&mut record.fecha_autorizacion, 0);
+    normalize(&mut record.fec_comer, 1);
+    normalize(&mut record.fecha_situacion_registro, 2);
+    normalize(&mut record.fec_sitreg_presen, 3);
+    for problem in &mut record.supply_problems {
+        normalize(&mut problem.start_date, 4);
+    }
+    unparsed
+}
+
+impl PrescriptionRecord {
+    /// [`fecha_autorizacion`](Self::fecha_autorizacion) as a date, `None` when missing
+    /// or not a date
+    pub fn authorization_date(&self) -> Option<NaiveDate> {
+        self.fecha_autorizacion.as_deref().and_then(parse_date)
+    }
+
+    /// [`fec_comer`](Self::fec_comer) as a date, `None` when missing or not a date
+    pub fn commercialization_date(&self) -> Option<NaiveDate> {
+        self.fec_comer.as_deref().and_then(parse_date)
+    }
+
+    /// [`fecha_situacion_registro`](Self::fecha_situacion_registro) as a date, `None`
+    /// when missing or not a date
+    pub fn registration_status_date(&self) -> Option<NaiveDate> {
+        self.fecha_situacion_registro
+            .as_deref()
+            .and_then(parse_date)
+    }
+
+    /// [`fec_sitreg_presen`](Self::fec_sitreg_presen) as a date, `None` when missing or
+    /// not a date
+    pub fn presentation_status_date(&self) -> Option<NaiveDate> {
+        self.fec_sitreg_presen.as_deref().and_then(parse_date)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{BoolParsing, deserialize_prescription};
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        let cases = [
+            ("15/03/2024", Some((2024, 3, 15))),
+            ("5/3/2024", Some((2024, 3, 5))),
+            ("20240315", Some((2024, 3, 15))),
+            ("2024-03-15", Some((2024, 3, 15))),
+            (" 29/02/2024 ", Some((2024, 2, 29))),
+            ("29/02/2023", None),
+            ("20241315", None),
+            ("15/03/24 10:00", None),
+            ("2024", None),
+            ("", None),
+        ];
+        for (input, expected) in cases {
+            let expected = expected.map(|(y, m, d)| NaiveDate::from_ymd_opt(y, m, d).unwrap());
+            assert_eq!(parse_date(input), expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn test_normalize_prescription_dates() {
+        let xml = super::super::tests::prescription_xml(
+            "600000",
+            "<fecha_autorizacion>15/03/2024</fecha_autorizacion>\
+             <fec_comer>20240401</fec_comer>\
+             <fecha_situacion_registro>sin fecha</fecha_situacion_registro>\
+             <fec_sitreg_presen></fec_sitreg_presen>\
+             <problemassuministro><fecha_inicio>01/12/2025</fecha_inicio></problemassuministro>",
+        );
+        let (record, _) = deserialize_prescription(xml.as_bytes(), BoolParsing::default());
+        let mut record = record.unwrap();
+        assert_eq!(
+            record.authorization_date(),
+            NaiveDate::from_ymd_opt(2024, 3, 15)
+        );
+        assert_eq!(record.registration_status_date(), None);
+
+        let unparsed = normalize_prescription_dates(&mut record);
+        assert_eq!(record.fecha_autorizacion.as_deref(), Some("2024-03-15"));
+        assert_eq!(record.fec_comer.as_deref(), Some("2024-04-01"));
+        assert_eq!(
+            record.fecha_situacion_registro.as_deref(),
+            Some("sin fecha")
+        );
+        assert_eq!(record.fec_sitreg_presen, None);
+        assert_eq!(
+            record.supply_problems[0].start_date.as_deref(),
+            Some("2025-12-01")
+        );
+        assert_eq!(unparsed, [0, 0, 1, 0, 0]);
+        // Normalized values still read as dates
+        assert_eq!(
+            record.commercialization_date(),
+            NaiveDate::from_ymd_opt(2024, 4, 1)
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Duplicate detection on the natural key of the parsed records.
+
+use super::options::OnDuplicate;
+use super::report::ParseReport;
+use super::{
+    ActiveIngridientRecord, AdministrationRouteRecord, AtcRecord, ContainerRecord,
+    ContainerUnitRecord, DcpRecord, DcpfRecord, DcsaRecord, ExcipientRecord, LaboratoryRecord,
+    PharmaceuticalFormRecord, PrescriptionRecord, RegistrationStatusRecord,
+    SimplifiedPharmaceuticalFormRecord,
+};
+use anyhow::Result;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Records identified by a single key column
+pub(crate) trait NaturalKey {
+    fn natural_key(&self) -> &str;
+}
+
+macro_rules! impl_natural_key {
+    ($field:ident: $($record:ty),+) => {
+        $(
+            impl NaturalKey for $record {
+                fn natural_key(&self) -> &str {
+                    &self.$field
+                }
+            }
+        )+
+    };
+}
+
+impl_natural_key!(
+    code: AtcRecord,
+    DcpRecord,
+    DcpfRecord,
+    DcsaRecord,
+    ContainerRecord,
+    ExcipientRecord,
+    PharmaceuticalFormRecord,
+    SimplifiedPharmaceuticalFormRecord,
+    LaboratoryRecord,
+    ActiveIngridientRecord,
+    RegistrationStatusRecord,
+    ContainerUnitRecord,
+    AdministrationRouteRecord
+);
+impl_natural_key!(cod_nacion: PrescriptionRecord);
+
+/// Tracks the keys seen while records are written
+pub(crate) struct Duplicates {
+    on_duplicate: OnDuplicate,
+    seen: HashSet<Box<str>>,
+    count: usize,
+    keys: BTreeSet<String>,
+}
+
+impl Duplicates {
+    pub(crate) fn new(on_duplicate: OnDuplicate) -> Self {
+        Duplicates {
+            on_duplicate,
+            seen: HashSet::new(),
+            count: 0,
+            keys: BTreeSet::new(),
+        }
+    }
+
+    pub(crate) fn on_duplicate(&self) -> OnDuplicate {
+        self.on_duplicate
+    }
+
+    /// Whether a record with `key` is the first of its key and should be written.
+    pub(crate) fn admit(&mut self, key: &str) -> bool {
+        if self.seen.contains(key) {
+            self.count += 1;
+            self.keys.insert(key.to_string());
+            false
+        } else {
+            self.seen.insert(key.into());
+            true
+        }
+    }
+
+    /// Drops every record followed by another one with the same key.
+    pub(crate) fn retain_last<T: NaturalKey>(&mut self, records: Vec<T>) -> Vec<T> {
+        let mut last: HashMap<&str, usize> = HashMap::new();
+        for (index, record) in records.iter().enumerate() {
+            if last.insert(record.natural_key(), index).is_some() {
+                self.count += 1;
+                self.keys.insert(record.natural_key().to_string());
+            }
+        }
+        let keep: HashSet<usize> = last.into_values().collect();
+        records
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, record)| keep.contains(&index).then_some(record))
+            .collect()
+    }
+
+    /// Adds the duplicates to `report`, failing in [`OnDuplicate::Error`] mode if any
+    /// were found.
+    pub(crate) fn finish(self, name: &str, report: &mut ParseReport) -> Result<()> {
+        if self.count > 0 {
+            tracing::warn!(file = name, duplicates = self.count, "Duplicate keys");
+        }
+        report.duplicates += self.count;
+        report.duplicate_keys.extend(self.keys);
+        if self.on_duplicate == OnDuplicate::Error && report.duplicates > 0 {
+            anyhow::bail!(
+                "Duplicate keys in {} XML: {}",
+                name,
+                report.duplicate_keys.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Applies `on_duplicate` to parsed records.
+pub(crate) fn deduplicate<T: NaturalKey>(
+    records: Vec<T>,
+    on_duplicate: OnDuplicate,
+    name: &str,
+    report: &mut ParseReport,
+) -> Result<Vec<T>> {
+    let mut duplicates = Duplicates::new(on_duplicate);
+    let records = match on_duplicate {
+        OnDuplicate::KeepLast => duplicates.retain_last(records),
+        OnDuplicate::KeepFirst | OnDuplicate::Error => records
+            .into_iter()
+            .filter(|record| duplicates.admit(record.natural_key()))
+            .collect(),
+    };
+    duplicates.finish(name, report)?;
+    Ok(records)
+}
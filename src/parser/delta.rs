@@ -0,0 +1,227 @@
+//! Incremental prescription files and their application to a generated CSV set.
+//!
+//! Besides the full Prescripcion.xml, AEMPS publishes incremental files listing the
+//! prescriptions added (`altas`), removed (`bajas`) and modified (`modificaciones`)
+//! since the previous one. Added and modified prescriptions use the same
+//! `<prescription>` element as the full file; removed ones only carry their
+//! `cod_nacion`.
+
+use super::load::load_prescriptions_csv_with_options;
+use super::options::ParserOptions;
+use super::report::ParseReport;
+use super::{PrescriptionRecord, decode_xml, open_xml};
+use super::{parts, pipeline};
+use anyhow::{Context, Result};
+use quick_xml::de::from_reader;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::BufRead;
+use std::path::Path;
+
+/// Changes listed by an incremental prescription file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PrescriptionDelta {
+    /// Prescriptions added since the previous file
+    pub added: Vec<PrescriptionRecord>,
+    /// `cod_nacion` of the prescriptions removed since the previous file
+    pub removed: Vec<String>,
+    /// New content of the prescriptions modified since the previous file
+    pub modified: Vec<PrescriptionRecord>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PrescriptionGroup {
+    #[serde(rename = "prescription", default)]
+    records: Vec<PrescriptionRecord>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RemovedGroup {
+    #[serde(rename = "cod_nacion", default)]
+    codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename = "aemps_prescripcion_incremental")]
+struct DeltaList {
+    #[serde(rename = "altas", default)]
+    added: PrescriptionGroup,
+    #[serde(rename = "bajas", default)]
+    removed: RemovedGroup,
+    #[serde(rename = "modificaciones", default)]
+    modified: PrescriptionGroup,
+}
+
+/// Parses an incremental prescription XML file from a buffered reader.
+pub fn parse_prescription_delta_xml_from_reader<R: BufRead>(
+    reader: R,
+) -> Result<PrescriptionDelta> {
+    let list: DeltaList = from_reader(decode_xml(reader)?)
+        .context("Failed to deserialize incremental Prescription XML")?;
+    Ok(PrescriptionDelta {
+        added: list.added.records,
+        removed: list
+            .removed
+            .codes
+            .into_iter()
+            .map(|code| code.trim().to_string())
+            .collect(),
+        modified: list.modified.records,
+    })
+}
+
+/// Parses an incremental prescription XML file.
+pub fn parse_prescription_delta_xml<P: AsRef<Path>>(xml_path: P) -> Result<PrescriptionDelta> {
+    parse_prescription_delta_xml_from_reader(open_xml(xml_path)?)
+}
+
+/// Applies `delta` to the prescription CSV files in `base_csv_dir`, writing the patched
+/// set to `out_dir`.
+pub fn apply_delta<P: AsRef<Path>>(
+    base_csv_dir: P,
+    delta: &PrescriptionDelta,
+    out_dir: P,
+) -> Result<ParseReport> {
+    apply_delta_with_options(base_csv_dir, delta, out_dir, &ParserOptions::default())
+}
+
+/// Applies `delta` to the prescription CSV files written with `options` in
+/// `base_csv_dir`, writing the patched set to `out_dir` with the same options.
+///
+/// Removed prescriptions are deleted by `cod_nacion` and modified ones replaced in
+/// place. Added prescriptions are appended, or replace the base record when it already
+/// exists, so applying the same file twice gives the same result. A modification of an
+/// unknown prescription is appended as well. `out_dir` may be `base_csv_dir`: the
+/// base files are read completely before being rewritten.
+pub fn apply_delta_with_options<P: AsRef<Path>>(
+    base_csv_dir: P,
+    delta: &PrescriptionDelta,
+    out_dir: P,
+    options: &ParserOptions,
+) -> Result<ParseReport> {
+    let (base_csv_dir, out_dir) = (base_csv_dir.as_ref(), out_dir.as_ref());
+    let base = load_prescriptions_csv_with_options(base_csv_dir, options)?;
+
+    let removed: HashSet<&str> = delta.removed.iter().map(String::as_str).collect();
+    let base_len = base.len();
+    let mut records: Vec<PrescriptionRecord> = base
+        .into_iter()
+        .filter(|record| !removed.contains(record.cod_nacion.as_str()))
+        .collect();
+    let missing = removed.len().saturating_sub(base_len - records.len());
+    if missing > 0 {
+        tracing::warn!(
+            count = missing,
+            "Removed prescriptions not found in the base files"
+        );
+    }
+
+    let mut index: HashMap<String, usize> = records
+        .iter()
+        .enumerate()
+        .map(|(position, record)| (record.cod_nacion.clone(), position))
+        .collect();
+    for record in delta.modified.iter().chain(&delta.added) {
+        match index.get(&record.cod_nacion) {
+            Some(&position) => records[position] = record.clone(),
+            None => {
+                index.insert(record.cod_nacion.clone(), records.len());
+                records.push(record.clone());
+            }
+        }
+    }
+
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+    let make_writer = |name: &str| {
+        let path = out_dir.join(name);
+        File::create(&path).with_context(|| format!("Failed to create {}", path.display()))
+    };
+    parts::write_prescription_files(make_writer, options, |writers| {
+        pipeline::write_records_csvs(&records, writers, options)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        PRESCRIPTION_ATC_CSV, PRESCRIPTION_CSV_FILES, load_prescriptions_csv,
+        parse_prescription_xml_to_csvs,
+    };
+    use super::*;
+    use tempfile::TempDir;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/delta")
+            .join(name)
+    }
+
+    /// Base CSV set generated from the full fixture
+    fn base_csvs() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        parse_prescription_xml_to_csvs(fixture("Prescripcion.xml").as_path(), dir.path()).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_prescription_delta_xml() {
+        let delta = parse_prescription_delta_xml(fixture("Prescripcion_incremental.xml")).unwrap();
+
+        assert_eq!(delta.added.len(), 1);
+        assert_eq!(delta.added[0].cod_nacion, "600003");
+        assert_eq!(delta.added[0].atc_codes[0].atc_code, "A02BC01");
+        assert_eq!(delta.removed, vec!["600001"]);
+        assert_eq!(delta.modified.len(), 1);
+        assert_eq!(delta.modified[0].cod_nacion, "600002");
+        assert!(!delta.modified[0].sw_comercializado);
+    }
+
+    #[test]
+    fn test_parse_empty_delta() {
+        let xml = "<aemps_prescripcion_incremental></aemps_prescripcion_incremental>";
+        let delta = parse_prescription_delta_xml_from_reader(xml.as_bytes()).unwrap();
+        assert_eq!(delta, PrescriptionDelta::default());
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        let base = base_csvs();
+        let out = TempDir::new().unwrap();
+        let delta = parse_prescription_delta_xml(fixture("Prescripcion_incremental.xml")).unwrap();
+
+        let report = apply_delta(base.path(), &delta, out.path()).unwrap();
+        assert_eq!(report.records, 3);
+
+        let records = load_prescriptions_csv(out.path()).unwrap();
+        let codes: Vec<_> = records.iter().map(|r| r.cod_nacion.as_str()).collect();
+        // Removed: 600001, modified in place: 600002, added at the end: 600003
+        assert_eq!(codes, ["600000", "600002", "600003"]);
+        assert_eq!(records[1], delta.modified[0]);
+        assert_eq!(records[1].supply_problems.len(), 1);
+        assert_eq!(records[2], delta.added[0]);
+
+        // Child rows of the removed prescription are gone as well
+        let atc = fs::read_to_string(out.path().join(PRESCRIPTION_ATC_CSV)).unwrap();
+        assert_eq!(atc, "600000,N02BE01\n600002,M01AE01\n600003,A02BC01\n");
+    }
+
+    #[test]
+    fn test_apply_delta_in_place_is_idempotent() {
+        let dir = base_csvs();
+        let delta = parse_prescription_delta_xml(fixture("Prescripcion_incremental.xml")).unwrap();
+        let other = TempDir::new().unwrap();
+        apply_delta(dir.path(), &delta, other.path()).unwrap();
+
+        apply_delta(dir.path(), &delta, dir.path()).unwrap();
+        apply_delta(dir.path(), &delta, dir.path()).unwrap();
+        for name in PRESCRIPTION_CSV_FILES {
+            assert_eq!(
+                fs::read_to_string(dir.path().join(name)).unwrap(),
+                fs::read_to_string(other.path().join(name)).unwrap(),
+                "{name}"
+            );
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! Enumeration of the nomenclator dictionaries and their default file names.
+
+use super::OutputFormat;
+use super::options::ParserOptions;
+use super::report::ParseReport;
+use anyhow::Result;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// One of the dictionary XML files of the nomenclator.
+///
+/// Prescripcion.xml is not a dictionary: it produces several CSV files and has its
+/// own functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DictionaryKind {
+    Atc,
+    Dcp,
+    Dcpf,
+    Dcsa,
+    Containers,
+    Excipients,
+    PharmaceuticalForms,
+    SimplifiedForms,
+    Laboratories,
+    ActiveIngredients,
+    RegistrationStatuses,
+    ContainerUnits,
+    AdministrationRoutes,
+}
+
+impl DictionaryKind {
+    /// Every dictionary, in the order the nomenclator lists them
+    pub const ALL: [DictionaryKind; 13] = [
+        DictionaryKind::Atc,
+        DictionaryKind::Dcp,
+        DictionaryKind::Dcpf,
+        DictionaryKind::Dcsa,
+        DictionaryKind::Containers,
+        DictionaryKind::Excipients,
+        DictionaryKind::PharmaceuticalForms,
+        DictionaryKind::SimplifiedForms,
+        DictionaryKind::Laboratories,
+        DictionaryKind::ActiveIngredients,
+        DictionaryKind::RegistrationStatuses,
+        DictionaryKind::ContainerUnits,
+        DictionaryKind::AdministrationRoutes,
+    ];
+
+    /// Name of the XML file in the nomenclator archive.
+    pub const fn default_xml_filename(self) -> &'static str {
+        match self {
+            DictionaryKind::Atc => "DICCIONARIO_ATC.xml",
+            DictionaryKind::Dcp => "DICCIONARIO_DCP.xml",
+            DictionaryKind::Dcpf => "DICCIONARIO_DCPF.xml",
+            DictionaryKind::Dcsa => "DICCIONARIO_DCSA.xml",
+            DictionaryKind::Containers => "DICCIONARIO_ENVASES.xml",
+            DictionaryKind::Excipients => "DICCIONARIO_EXCIPIENTES_DECL_OBLIGATORIA.xml",
+            DictionaryKind::PharmaceuticalForms => "DICCIONARIO_FORMA_FARMACEUTICA.xml",
+            DictionaryKind::SimplifiedForms => "DICCIONARIO_FORMA_FARMACEUTICA_SIMPLIFICADAS.xml",
+            DictionaryKind::Laboratories => "DICCIONARIO_LABORATORIOS.xml",
+            DictionaryKind::ActiveIngredients => "DICCIONARIO_PRINCIPIOS_ACTIVOS.xml",
+            DictionaryKind::RegistrationStatuses => "DICCIONARIO_SITUACION_REGISTRO.xml",
+            DictionaryKind::ContainerUnits => "DICCIONARIO_UNIDAD_CONTENIDO.xml",
+            DictionaryKind::AdministrationRoutes => "DICCIONARIO_VIAS_ADMINISTRACION.xml",
+        }
+    }
+
+    /// Name of the generated CSV file, as listed in [`DICTIONARY_TABLES`](super::schema::DICTIONARY_TABLES).
+    pub const fn default_csv_filename(self) -> &'static str {
+        match self {
+            DictionaryKind::Atc => "atc.csv",
+            DictionaryKind::Dcp => "dcp.csv",
+            DictionaryKind::Dcpf => "dcpf.csv",
+            DictionaryKind::Dcsa => "dcsa.csv",
+            DictionaryKind::Containers => "envases.csv",
+            DictionaryKind::Excipients => "excipientes.csv",
+            DictionaryKind::PharmaceuticalForms => "forma_farmaceutica.csv",
+            DictionaryKind::SimplifiedForms => "forma_farmaceutica_simplificada.csv",
+            DictionaryKind::Laboratories => "laboratorios.csv",
+            DictionaryKind::ActiveIngredients => "principios_activos.csv",
+            DictionaryKind::RegistrationStatuses => "situacion_registro.csv",
+            DictionaryKind::ContainerUnits => "unidad_contenido.csv",
+            DictionaryKind::AdministrationRoutes => "vias_administracion.csv",
+        }
+    }
+
+    /// Short name selecting the dictionary on the command line, its CSV file name
+    /// without the extension, such as `atc` or `laboratorios`.
+    pub fn short_name(self) -> &'static str {
+        let csv = self.default_csv_filename();
+        csv.strip_suffix(".csv").unwrap_or(csv)
+    }
+
+    /// Name of the file generated in `format`, such as `atc.ndjson`
+    pub fn output_filename(self, format: OutputFormat) -> String {
+        format!("{}.{}", self.short_name(), format.extension())
+    }
+
+    /// Dictionary named by its [`short_name`](Self::short_name) or XML file name,
+    /// ignoring case
+    pub fn from_name(name: &str) -> Option<DictionaryKind> {
+        DictionaryKind::ALL.into_iter().find(|kind| {
+            kind.short_name().eq_ignore_ascii_case(name)
+                || kind.default_xml_filename().eq_ignore_ascii_case(name)
+        })
+    }
+
+    /// Parses the dictionary XML file and writes its content to a CSV file formatted
+    /// after `options`.
+    pub fn parse<P: AsRef<Path>>(
+        self,
+        xml_path: P,
+        csv_path: P,
+        options: &ParserOptions,
+    ) -> Result<ParseReport> {
+        let (xml_path, csv_path) = (xml_path.as_ref(), csv_path.as_ref());
+        match self {
+            DictionaryKind::Atc => {
+                super::parse_atc_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+            DictionaryKind::Dcp => {
+                super::parse_dcp_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+            DictionaryKind::Dcpf => {
+                super::parse_dcpf_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+            DictionaryKind::Dcsa => {
+                super::parse_dcsa_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+            DictionaryKind::Containers => {
+                super::parse_envases_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+            DictionaryKind::Excipients => {
+                super::parse_excipientes_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+            DictionaryKind::PharmaceuticalForms => {
+                super::parse_forma_farmaceutica_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+            DictionaryKind::SimplifiedForms => {
+                super::parse_forma_farmaceutica_simplificada_xml_to_csv_with_options(
+                    xml_path, csv_path, options,
+                )
+            }
+            DictionaryKind::Laboratories => {
+                super::parse_laboratorio_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+            DictionaryKind::ActiveIngredients => {
+                super::parse_principio_activo_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+            DictionaryKind::RegistrationStatuses => {
+                super::parse_situacion_registro_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+            DictionaryKind::ContainerUnits => {
+                super::parse_unidad_contenido_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+            DictionaryKind::AdministrationRoutes => {
+                super::parse_via_administracion_xml_to_csv_with_options(xml_path, csv_path, options)
+            }
+        }
+    }
+
+    /// Parses dictionary XML from a buffered reader and writes CSV formatted after
+    /// `options` to `writer`.
+    pub fn parse_to_writer<R: BufRead, W: Write>(
+        self,
+        reader: R,
+        writer: W,
+        options: &ParserOptions,
+    ) -> Result<ParseReport> {
+        match self {
+            DictionaryKind::Atc => {
+                super::parse_atc_xml_to_csv_from_reader_with_options(reader, writer, options)
+            }
+            DictionaryKind::Dcp => {
+                super::parse_dcp_xml_to_csv_from_reader_with_options(reader, writer, options)
+            }
+            DictionaryKind::Dcpf => {
+                super::parse_dcpf_xml_to_csv_from_reader_with_options(reader, writer, options)
+            }
+            DictionaryKind::Dcsa => {
+                super::parse_dcsa_xml_to_csv_from_reader_with_options(reader, writer, options)
+            }
+            DictionaryKind::Containers => {
+                super::parse_envases_xml_to_csv_from_reader_with_options(reader, writer, options)
+            }
+            DictionaryKind::Excipients => {
+                super::parse_excipientes_xml_to_csv_from_reader_with_options(
+                    reader, writer, options,
+                )
+            }
+            DictionaryKind::PharmaceuticalForms => {
+                super::parse_forma_farmaceutica_xml_to_csv_from_reader_with_options(
+                    reader, writer, options,
+                )
+            }
+            DictionaryKind::SimplifiedForms => {
+                super::parse_forma_farmaceutica_simplificada_xml_to_csv_from_reader_with_options(
+                    reader, writer, options,
+                )
+            }
+            DictionaryKind::Laboratories => {
+                super::parse_laboratorio_xml_to_csv_from_reader_with_options(
+                    reader, writer, options,
+                )
+            }
+            DictionaryKind::ActiveIngredients => {
+                super::parse_principio_activo_xml_to_csv_from_reader_with_options(
+                    reader, writer, options,
+                )
+            }
+            DictionaryKind::RegistrationStatuses => {
+                super::parse_situacion_registro_xml_to_csv_from_reader_with_options(
+                    reader, writer, options,
+                )
+            }
+            DictionaryKind::ContainerUnits => {
+                super::parse_unidad_contenido_xml_to_csv_from_reader_with_options(
+                    reader, writer, options,
+                )
+            }
+            DictionaryKind::AdministrationRoutes => {
+                super::parse_via_administracion_xml_to_csv_from_reader_with_options(
+                    reader, writer, options,
+                )
+            }
+        }
+    }
+
+    /// Parses dictionary XML from a buffered reader and writes one JSON object per
+    /// record to `writer`.
+    pub fn parse_ndjson_to_writer<R: BufRead, W: Write>(self, reader: R, writer: W) -> Result<()> {
+        match self {
+            DictionaryKind::Atc => super::parse_atc_xml_to_ndjson_from_reader(reader, writer),
+            DictionaryKind::Dcp => super::parse_dcp_xml_to_ndjson_from_reader(reader, writer),
+            DictionaryKind::Dcpf => super::parse_dcpf_xml_to_ndjson_from_reader(reader, writer),
+            DictionaryKind::Dcsa => super::parse_dcsa_xml_to_ndjson_from_reader(reader, writer),
+            DictionaryKind::Containers => {
+                super::parse_envases_xml_to_ndjson_from_reader(reader, writer)
+            }
+            DictionaryKind::Excipients => {
+                super::parse_excipientes_xml_to_ndjson_from_reader(reader, writer)
+            }
+            DictionaryKind::PharmaceuticalForms => {
+                super::parse_forma_farmaceutica_xml_to_ndjson_from_reader(reader, writer)
+            }
+            DictionaryKind::SimplifiedForms => {
+                super::parse_forma_farmaceutica_simplificada_xml_to_ndjson_from_reader(
+                    reader, writer,
+                )
+            }
+            DictionaryKind::Laboratories => {
+                super::parse_laboratorio_xml_to_ndjson_from_reader(reader, writer)
+            }
+            DictionaryKind::ActiveIngredients => {
+                super::parse_principio_activo_xml_to_ndjson_from_reader(reader, writer)
+            }
+            DictionaryKind::RegistrationStatuses => {
+                super::parse_situacion_registro_xml_to_ndjson_from_reader(reader, writer)
+            }
+            DictionaryKind::ContainerUnits => {
+                super::parse_unidad_contenido_xml_to_ndjson_from_reader(reader, writer)
+            }
+            DictionaryKind::AdministrationRoutes => {
+                super::parse_via_administracion_xml_to_ndjson_from_reader(reader, writer)
+            }
+        }
+    }
+
+    /// Parses the dictionary XML file and writes its content to a Parquet file.
+    #[cfg(feature = "parquet")]
+    pub fn parse_parquet<P: AsRef<Path>>(self, xml_path: P, parquet_path: P) -> Result<()> {
+        match self {
+            DictionaryKind::Atc => super::parse_atc_xml_to_parquet(xml_path, parquet_path),
+            DictionaryKind::Dcp => super::parse_dcp_xml_to_parquet(xml_path, parquet_path),
+            DictionaryKind::Dcpf => super::parse_dcpf_xml_to_parquet(xml_path, parquet_path),
+            DictionaryKind::Dcsa => super::parse_dcsa_xml_to_parquet(xml_path, parquet_path),
+            DictionaryKind::Containers => {
+                super::parse_envases_xml_to_parquet(xml_path, parquet_path)
+            }
+            DictionaryKind::Excipients => {
+                super::parse_excipientes_xml_to_parquet(xml_path, parquet_path)
+            }
+            DictionaryKind::PharmaceuticalForms => {
+                super::parse_forma_farmaceutica_xml_to_parquet(xml_path, parquet_path)
+            }
+            DictionaryKind::SimplifiedForms => {
+                super::parse_forma_farmaceutica_simplificada_xml_to_parquet(xml_path, parquet_path)
+            }
+            DictionaryKind::Laboratories => {
+                super::parse_laboratorio_xml_to_parquet(xml_path, parquet_path)
+            }
+            DictionaryKind::ActiveIngredients => {
+                super::parse_principio_activo_xml_to_parquet(xml_path, parquet_path)
+            }
+            DictionaryKind::RegistrationStatuses => {
+                super::parse_situacion_registro_xml_to_parquet(xml_path, parquet_path)
+            }
+            DictionaryKind::ContainerUnits => {
+                super::parse_unidad_contenido_xml_to_parquet(xml_path, parquet_path)
+            }
+            DictionaryKind::AdministrationRoutes => {
+                super::parse_via_administracion_xml_to_parquet(xml_path, parquet_path)
+            }
+        }
+    }
+}
+
+impl fmt::Display for DictionaryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.default_xml_filename())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::schema::DICTIONARY_TABLES;
+    use std::fs;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/nomenclator")
+            .join(name)
+    }
+
+    #[test]
+    fn test_parse_all_fixtures() {
+        let output_dir = tempfile::tempdir().unwrap();
+
+        for kind in DictionaryKind::ALL {
+            let csv_path = output_dir.path().join(kind.default_csv_filename());
+            let report = kind
+                .parse(
+                    fixture(kind.default_xml_filename()),
+                    csv_path.clone(),
+                    &ParserOptions::default(),
+                )
+                .unwrap_or_else(|e| panic!("{kind}: {e:#}"));
+            assert_eq!(report.records, 2, "{kind}");
+
+            let csv = fs::read_to_string(&csv_path).unwrap();
+            assert_eq!(csv.lines().count(), 3, "{kind}");
+        }
+    }
+
+    #[test]
+    fn test_from_name() {
+        for kind in DictionaryKind::ALL {
+            assert_eq!(DictionaryKind::from_name(kind.short_name()), Some(kind));
+            assert_eq!(
+                DictionaryKind::from_name(kind.default_xml_filename()),
+                Some(kind)
+            );
+        }
+        assert_eq!(
+            DictionaryKind::from_name("diccionario_atc.xml"),
+            Some(DictionaryKind::Atc)
+        );
+        assert_eq!(
+            DictionaryKind::from_name("LABORATORIOS"),
+            Some(DictionaryKind::Laboratories)
+        );
+        assert_eq!(DictionaryKind::from_name("atc.csv"), None);
+    }
+
+    #[test]
+    fn test_filenames_match_schema() {
+        let mut csv_files: Vec<_> = DictionaryKind::ALL
+            .iter()
+            .map(|kind| kind.default_csv_filename())
+            .collect();
+        let mut tables: Vec<_> = DICTIONARY_TABLES.iter().map(|t| t.file_name).collect();
+        csv_files.sort_unstable();
+        tables.sort_unstable();
+        assert_eq!(csv_files, tables);
+
+        let xml_files: std::collections::HashSet<_> = DictionaryKind::ALL
+            .iter()
+            .map(|kind| kind.default_xml_filename())
+            .collect();
+        assert_eq!(xml_files.len(), DictionaryKind::ALL.len());
+    }
+}
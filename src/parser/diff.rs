@@ -0,0 +1,428 @@
+//! Comparison of two directories of generated CSV files.
+//!
+//! Rows are matched by the natural key of their table, see [`natural_key`]. Both files
+//! of a table are sorted on that key in chunks of [`DiffOptions::chunk_rows`] rows,
+//! spilled to temporary files and merged, so the memory used does not grow with the
+//! size of the tables.
+
+use super::load::CsvRows;
+use super::options::{ParserOptions, PrescriptionKey};
+use super::schema::{DICTIONARY_TABLES, PRESCRIPTION_TABLES, Table, prescription_key_columns};
+use super::sorting::{compare_fields, key_columns};
+use super::{
+    PRESCRIPTION_ACTIVE_INGREDIENTS_CSV, PRESCRIPTION_ADMIN_ROUTES_CSV, PRESCRIPTION_ATC_CSV,
+    PRESCRIPTION_ATC_DUPLICATES_CSV, PRESCRIPTION_EXCIPIENTS_CSV, PRESCRIPTION_NOTES_CSV,
+    PRESCRIPTION_SUPPLY_PROBLEMS_CSV,
+};
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+
+/// Settings of [`diff_csv_dirs_with_options`]
+#[derive(Clone)]
+pub struct DiffOptions {
+    /// Options both directories were written with
+    pub parser: ParserOptions,
+    /// Tables compared, every dictionary and prescription table by default
+    pub tables: Vec<&'static Table>,
+    /// Rows of a file sorted in memory at once
+    pub chunk_rows: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            parser: ParserOptions::default(),
+            tables: DICTIONARY_TABLES
+                .iter()
+                .chain(PRESCRIPTION_TABLES.iter())
+                .collect(),
+            chunk_rows: 100_000,
+        }
+    }
+}
+
+/// How a row differs between the two directories
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RowChangeKind {
+    /// Only in the new directory
+    Added,
+    /// Only in the old directory
+    Removed,
+    /// In both, with different values
+    Changed,
+}
+
+/// A row added, removed or changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RowChange {
+    /// Table name, e.g. `prescriptions`
+    pub table: &'static str,
+    #[serde(rename = "change")]
+    pub kind: RowChangeKind,
+    /// Values of the natural key columns
+    pub key: Vec<String>,
+    /// Columns whose value changed, empty unless the row was changed
+    pub columns: Vec<&'static str>,
+}
+
+/// Number of rows of each kind in one table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TableDiff {
+    pub table: &'static str,
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+}
+
+impl TableDiff {
+    /// Whether both files hold the same rows
+    pub fn is_empty(&self) -> bool {
+        self.added == 0 && self.removed == 0 && self.changed == 0
+    }
+}
+
+/// Columns identifying a row of `table` written with `key`: the primary key of the
+/// dictionaries and `prescriptions`, and the prescription plus the code of the entry in
+/// the child tables.
+pub fn natural_key(table: &Table, key: PrescriptionKey) -> Vec<&'static str> {
+    if let Some(primary_key) = table.primary_key {
+        return vec![primary_key];
+    }
+    let entry: &[&'static str] = match table.file_name {
+        PRESCRIPTION_ACTIVE_INGREDIENTS_CSV => &["active_ingredient_code"],
+        PRESCRIPTION_ADMIN_ROUTES_CSV => &["route_code"],
+        PRESCRIPTION_ATC_CSV => &["atc_code"],
+        PRESCRIPTION_ATC_DUPLICATES_CSV => &["atc_code", "duplicate_atc"],
+        PRESCRIPTION_SUPPLY_PROBLEMS_CSV => &["start_date"],
+        PRESCRIPTION_EXCIPIENTS_CSV => &["excipient_code"],
+        PRESCRIPTION_NOTES_CSV => &["note_type", "number"],
+        // One form per prescription
+        _ => &[],
+    };
+    prescription_key_columns(key)
+        .iter()
+        .map(|column| column.name)
+        .chain(entry.iter().copied())
+        .collect()
+}
+
+/// Compares the CSV files written by the nomenclator parsers in `old_dir` and
+/// `new_dir`, passing every differing row to `on_change`.
+pub fn diff_csv_dirs<P: AsRef<Path>, Q: AsRef<Path>>(
+    old_dir: P,
+    new_dir: Q,
+    on_change: impl FnMut(RowChange) -> Result<()>,
+) -> Result<Vec<TableDiff>> {
+    diff_csv_dirs_with_options(old_dir, new_dir, &DiffOptions::default(), on_change)
+}
+
+/// Compares the CSV files written with `options` in `old_dir` and `new_dir`.
+///
+/// A file missing from one directory counts as empty, so all its rows are added or
+/// removed; tables missing from both are left out of the result. Changes are reported
+/// table by table, in key order.
+pub fn diff_csv_dirs_with_options<P: AsRef<Path>, Q: AsRef<Path>>(
+    old_dir: P,
+    new_dir: Q,
+    options: &DiffOptions,
+    mut on_change: impl FnMut(RowChange) -> Result<()>,
+) -> Result<Vec<TableDiff>> {
+    let mut diffs = Vec::new();
+    for table in &options.tables {
+        let old_path = old_dir.as_ref().join(table.file_name);
+        let new_path = new_dir.as_ref().join(table.file_name);
+        if !old_path.exists() && !new_path.exists() {
+            continue;
+        }
+        diffs.push(diff_table(
+            table,
+            &old_path,
+            &new_path,
+            options,
+            &mut on_change,
+        )?);
+    }
+    Ok(diffs)
+}
+
+fn diff_table(
+    table: &Table,
+    old_path: &Path,
+    new_path: &Path,
+    options: &DiffOptions,
+    on_change: &mut impl FnMut(RowChange) -> Result<()>,
+) -> Result<TableDiff> {
+    let columns = options.parser.table_columns(table)?;
+    let key = key_columns(
+        &columns,
+        &natural_key(table, options.parser.prescription_key),
+    );
+    let mut old = SortedRows::open(old_path, table, &key, options)?;
+    let mut new = SortedRows::open(new_path, table, &key, options)?;
+    let key_values = |row: &StringRecord| -> Vec<String> {
+        key.iter()
+            .map(|&column| row.get(column).unwrap_or_default().to_string())
+            .collect()
+    };
+
+    let mut diff = TableDiff {
+        table: table.name(),
+        ..Default::default()
+    };
+    let (mut old_row, mut new_row) = (old.next()?, new.next()?);
+    loop {
+        let ordering = match (&old_row, &new_row) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(old_value), Some(new_value)) => compare_keys(old_value, new_value, &key),
+        };
+        let (kind, row, changed) = match (ordering, old_row.take(), new_row.take()) {
+            (Ordering::Less, Some(row), next) => {
+                diff.removed += 1;
+                (old_row, new_row) = (old.next()?, next);
+                (RowChangeKind::Removed, row, Vec::new())
+            }
+            (Ordering::Greater, next, Some(row)) => {
+                diff.added += 1;
+                (old_row, new_row) = (next, new.next()?);
+                (RowChangeKind::Added, row, Vec::new())
+            }
+            (_, Some(old_value), Some(new_value)) => {
+                (old_row, new_row) = (old.next()?, new.next()?);
+                let changed: Vec<&'static str> = columns
+                    .iter()
+                    .enumerate()
+                    .filter(|(position, _)| old_value.get(*position) != new_value.get(*position))
+                    .map(|(_, column)| column.name)
+                    .collect();
+                if changed.is_empty() {
+                    diff.unchanged += 1;
+                    continue;
+                }
+                diff.changed += 1;
+                (RowChangeKind::Changed, new_value, changed)
+            }
+            _ => unreachable!("a missing row never compares equal"),
+        };
+        on_change(RowChange {
+            table: diff.table,
+            kind,
+            key: key_values(&row),
+            columns: changed,
+        })?;
+    }
+    Ok(diff)
+}
+
+/// Compares the key columns of two rows, numbers by value.
+fn compare_keys(row: &StringRecord, other: &StringRecord, key: &[usize]) -> Ordering {
+    key.iter()
+        .map(|&column| {
+            compare_fields(
+                row.get(column).unwrap_or_default().as_bytes(),
+                other.get(column).unwrap_or_default().as_bytes(),
+            )
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Compares two rows by their key, then by their whole content, so that rows sharing a
+/// key are matched in the same order in both files.
+fn compare_rows(row: &StringRecord, other: &StringRecord, key: &[usize]) -> Ordering {
+    compare_keys(row, other, key).then_with(|| row.iter().cmp(other.iter()))
+}
+
+/// Sorted chunk of the rows of a file
+enum Run {
+    Memory(std::vec::IntoIter<StringRecord>),
+    File(csv::Reader<File>),
+}
+
+impl Run {
+    fn next(&mut self) -> Result<Option<StringRecord>> {
+        match self {
+            Run::Memory(rows) => Ok(rows.next()),
+            Run::File(reader) => {
+                let mut row = StringRecord::new();
+                let more = reader
+                    .read_record(&mut row)
+                    .context("Failed to read a temporary sort file")?;
+                Ok(more.then_some(row))
+            }
+        }
+    }
+}
+
+/// Rows of a CSV file in key order, merged from its sorted runs
+struct SortedRows<'a> {
+    key: &'a [usize],
+    runs: Vec<Run>,
+    /// Next row of every run
+    heads: Vec<Option<StringRecord>>,
+}
+
+impl<'a> SortedRows<'a> {
+    /// Sorts the rows of the file of `table` at `path`, none if it does not exist.
+    fn open(path: &Path, table: &Table, key: &'a [usize], options: &DiffOptions) -> Result<Self> {
+        let mut runs = Vec::new();
+        if path.exists() {
+            let columns = options.parser.table_columns(table)?;
+            let mut rows = CsvRows::open(path, columns, table.has_header, &options.parser)?;
+            let mut chunk = Vec::new();
+            while let Some(row) = rows.next_row(path)? {
+                chunk.push(row);
+                if chunk.len() >= options.chunk_rows.max(1) {
+                    runs.push(spill(&mut chunk, key)?);
+                }
+            }
+            chunk.sort_by(|row, other| compare_rows(row, other, key));
+            runs.push(Run::Memory(chunk.into_iter()));
+        }
+        let heads = runs.iter_mut().map(Run::next).collect::<Result<_>>()?;
+        Ok(SortedRows { key, runs, heads })
+    }
+
+    /// Smallest of the next rows of the runs.
+    fn next(&mut self) -> Result<Option<StringRecord>> {
+        let smallest = self
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(run, head)| Some((run, head.as_ref()?)))
+            .min_by(|(_, row), (_, other)| compare_rows(row, other, self.key))
+            .map(|(run, _)| run);
+        let Some(run) = smallest else {
+            return Ok(None);
+        };
+        let next = self.runs[run].next()?;
+        Ok(std::mem::replace(&mut self.heads[run], next))
+    }
+}
+
+/// Sorts `chunk` into a temporary file, emptying it.
+fn spill(chunk: &mut Vec<StringRecord>, key: &[usize]) -> Result<Run> {
+    chunk.sort_by(|row, other| compare_rows(row, other, key));
+    let file = tempfile::tempfile().context("Failed to create a temporary sort file")?;
+    let mut writer = csv::Writer::from_writer(file);
+    for row in chunk.drain(..) {
+        writer
+            .write_record(&row)
+            .context("Failed to write a temporary sort file")?;
+    }
+    let mut file = writer
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .context("Failed to write a temporary sort file")?;
+    file.seek(SeekFrom::Start(0))
+        .context("Failed to read a temporary sort file")?;
+    Ok(Run::File(
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(file),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, file_name: &str, content: &str) {
+        std::fs::write(dir.join(file_name), content).unwrap();
+    }
+
+    fn diff(old: &Path, new: &Path, chunk_rows: usize) -> (Vec<TableDiff>, Vec<RowChange>) {
+        let options = DiffOptions {
+            tables: vec![&DICTIONARY_TABLES[0]],
+            chunk_rows,
+            ..Default::default()
+        };
+        let mut changes = Vec::new();
+        let diffs = diff_csv_dirs_with_options(old, new, &options, |change| {
+            changes.push(change);
+            Ok(())
+        })
+        .unwrap();
+        (diffs, changes)
+    }
+
+    #[test]
+    fn test_diff_matches_rows_by_key_across_runs() {
+        let (old, new) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        write(
+            old.path(),
+            "atc.csv",
+            "number,code,description\n3,C,c\n1,A,a\n2,B,b\n4,D,d\n",
+        );
+        write(
+            new.path(),
+            "atc.csv",
+            "number,code,description\n5,E,e\n4,D,d\n1,A,a2\n3,C,c\n",
+        );
+
+        // Chunks of one row exercise the merge of the spilled runs
+        for chunk_rows in [1, 100] {
+            let (diffs, changes) = diff(old.path(), new.path(), chunk_rows);
+            assert_eq!(
+                diffs,
+                [TableDiff {
+                    table: "atc",
+                    added: 1,
+                    removed: 1,
+                    changed: 1,
+                    unchanged: 2,
+                }]
+            );
+            let summary: Vec<_> = changes
+                .iter()
+                .map(|change| (change.kind, change.key.join(","), change.columns.clone()))
+                .collect();
+            assert_eq!(
+                summary,
+                [
+                    (RowChangeKind::Changed, "A".to_string(), vec!["description"]),
+                    (RowChangeKind::Removed, "B".to_string(), vec![]),
+                    (RowChangeKind::Added, "E".to_string(), vec![]),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_diff_missing_file_counts_as_empty() {
+        let (old, new) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        write(new.path(), "atc.csv", "number,code,description\n1,A,a\n");
+        let (diffs, changes) = diff(old.path(), new.path(), 100);
+        assert_eq!(diffs[0].added, 1);
+        assert_eq!(changes[0].kind, RowChangeKind::Added);
+
+        // Tables in neither directory are left out
+        let (diffs, _) = diff(old.path(), old.path(), 100);
+        assert!(diffs.is_empty());
+    }
+
+    #[test]
+    fn test_natural_key_of_child_tables() {
+        let notes = PRESCRIPTION_TABLES
+            .iter()
+            .find(|table| table.file_name == PRESCRIPTION_NOTES_CSV)
+            .unwrap();
+        assert_eq!(
+            natural_key(notes, PrescriptionKey::Both),
+            ["prescription_id", "nro_definitivo", "note_type", "number"]
+        );
+        assert_eq!(
+            natural_key(&PRESCRIPTION_TABLES[0], PrescriptionKey::CodNacion),
+            ["cod_nacion"]
+        );
+    }
+}
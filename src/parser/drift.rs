@@ -0,0 +1,260 @@
+//! Detection of XML elements the record structs do not map, see
+//! [`ParserOptions::detect_unknown_elements`](super::ParserOptions::detect_unknown_elements).
+
+use super::PrescriptionRecord;
+use super::report::ParseReport;
+use super::schema::{
+    Column, Columns, PRESCRIPTION_ACTIVE_INGREDIENT_COLUMNS, PRESCRIPTION_ADMIN_ROUTE_COLUMNS,
+    PRESCRIPTION_ATC_COLUMNS, PRESCRIPTION_ATC_DUPLICATE_COLUMNS, PRESCRIPTION_EXCIPIENT_COLUMNS,
+    PRESCRIPTION_FORM_COLUMNS, PRESCRIPTION_NOTE_COLUMNS, PRESCRIPTION_SUPPLY_PROBLEM_COLUMNS,
+};
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Counts the child elements of known parents that are not among their mapped children
+pub(crate) struct ElementCensus {
+    /// Mapped child element names per parent element name
+    known: HashMap<&'static str, HashSet<&'static str>>,
+    /// Occurrences of each unmapped element, as `parent/child`
+    unknown: BTreeMap<String, usize>,
+}
+
+fn wire_names(columns: &[Column]) -> impl Iterator<Item = &'static str> + '_ {
+    columns.iter().map(|column| column.wire_name)
+}
+
+impl ElementCensus {
+    /// Census of the `<tag>` records of a dictionary, whose children are `columns`.
+    pub(crate) fn dictionary(tag: &'static str, columns: &[Column]) -> Self {
+        Self::new([(tag, wire_names(columns).collect())])
+    }
+
+    /// Census of the `<prescription>` elements and their nested entities.
+    pub(crate) fn prescriptions() -> Self {
+        // Child tables start with the prescription key, which is not an element
+        let nested = |columns: &[Column], skip: usize, extra: &[&'static str]| -> Vec<_> {
+            wire_names(&columns[skip..])
+                .chain(extra.iter().copied())
+                .collect()
+        };
+        Self::new([
+            (
+                "prescription",
+                nested(
+                    PrescriptionRecord::COLUMNS,
+                    0,
+                    &[
+                        "formasfarmaceuticas",
+                        "atc",
+                        "problemassuministro",
+                        "excipientes",
+                        "notas",
+                    ],
+                ),
+            ),
+            (
+                "formasfarmaceuticas",
+                nested(
+                    PRESCRIPTION_FORM_COLUMNS,
+                    1,
+                    &["composicion_pa", "viasadministracion"],
+                ),
+            ),
+            (
+                "composicion_pa",
+                nested(PRESCRIPTION_ACTIVE_INGREDIENT_COLUMNS, 1, &[]),
+            ),
+            (
+                "viasadministracion",
+                nested(PRESCRIPTION_ADMIN_ROUTE_COLUMNS, 1, &[]),
+            ),
+            (
+                "atc",
+                nested(PRESCRIPTION_ATC_COLUMNS, 1, &["duplicidades"]),
+            ),
+            // Duplicates also carry the ATC code of their parent in the CSV
+            (
+                "duplicidades",
+                nested(PRESCRIPTION_ATC_DUPLICATE_COLUMNS, 2, &[]),
+            ),
+            (
+                "problemassuministro",
+                nested(PRESCRIPTION_SUPPLY_PROBLEM_COLUMNS, 1, &[]),
+            ),
+            (
+                "excipientes",
+                nested(PRESCRIPTION_EXCIPIENT_COLUMNS, 1, &[]),
+            ),
+            ("notas", nested(PRESCRIPTION_NOTE_COLUMNS, 1, &[])),
+        ])
+    }
+
+    fn new<const N: usize>(parents: [(&'static str, Vec<&'static str>); N]) -> Self {
+        ElementCensus {
+            known: parents
+                .into_iter()
+                .map(|(parent, children)| (parent, children.into_iter().collect()))
+                .collect(),
+            unknown: BTreeMap::new(),
+        }
+    }
+
+    /// Counts the unmapped elements of one record, given as raw XML.
+    ///
+    /// Only children of known parents are checked, so the content of an unknown
+    /// element is counted once as that element.
+    pub(crate) fn scan(&mut self, element: &[u8]) {
+        let mut reader = Reader::from_reader(element);
+        let mut buf = Vec::new();
+        let mut parents: Vec<Vec<u8>> = Vec::new();
+        loop {
+            match reader.read_event_into(&mut buf) {
+                Ok(Event::Start(e)) => {
+                    self.check(parents.last(), &e);
+                    parents.push(e.name().as_ref().to_vec());
+                }
+                Ok(Event::Empty(e)) => self.check(parents.last(), &e),
+                Ok(Event::End(_)) => {
+                    parents.pop();
+                }
+                // Malformed records are reported by the deserializer
+                Ok(Event::Eof) | Err(_) => break,
+                _ => {}
+            }
+            buf.clear();
+        }
+    }
+
+    fn check(&mut self, parent: Option<&Vec<u8>>, element: &BytesStart) {
+        let Some(parent) = parent.and_then(|parent| std::str::from_utf8(parent).ok()) else {
+            return;
+        };
+        let Some(children) = self.known.get(parent) else {
+            return;
+        };
+        let name = String::from_utf8_lossy(element.name().as_ref()).into_owned();
+        if !children.contains(name.as_str()) {
+            *self.unknown.entry(format!("{parent}/{name}")).or_default() += 1;
+        }
+    }
+
+    /// Adds the unmapped elements to `report`, logging a warning for each.
+    pub(crate) fn finish(self, name: &str, report: &mut ParseReport) {
+        for (element, count) in &self.unknown {
+            tracing::warn!(file = name, element = %element, count, "Unknown XML element");
+        }
+        report.unknown_elements = self.unknown.into_iter().collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        DictionaryKind, ParserOptions, parse_atc_xml_to_csv_with_options,
+        parse_prescription_xml_to_csvs_with_options,
+    };
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_scan_counts_unknown_children_of_known_parents() {
+        let mut census = ElementCensus::prescriptions();
+        census.scan(
+            b"<prescription><cod_nacion>1</cod_nacion><nuevocampo>x</nuevocampo>\
+              <atc><cod_atc>A</cod_atc><nuevocampo/></atc>\
+              <nuevogrupo><hijo>1</hijo></nuevogrupo></prescription>",
+        );
+        census.scan(b"<prescription><nuevocampo>y</nuevocampo></prescription>");
+
+        let mut report = ParseReport::default();
+        census.finish("Prescription", &mut report);
+        assert_eq!(
+            report.unknown_elements,
+            [
+                ("atc/nuevocampo".to_string(), 1),
+                ("prescription/nuevocampo".to_string(), 2),
+                ("prescription/nuevogrupo".to_string(), 1),
+            ]
+        );
+    }
+
+    fn fixture(dir: &str, name: &str) -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(dir)
+            .join(name)
+    }
+
+    fn detecting() -> ParserOptions {
+        ParserOptions {
+            detect_unknown_elements: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_dictionary_unknown_elements() {
+        let dir = TempDir::new().unwrap();
+        let csv_path = dir.path().join("atc.csv");
+        let xml_path = fixture("drift", "DICCIONARIO_ATC.xml");
+
+        let report = parse_atc_xml_to_csv_with_options(&xml_path, &csv_path, &detecting()).unwrap();
+        assert_eq!(report.records, 3);
+        assert_eq!(report.unknown_elements, [("atc/nuevocampo".to_string(), 2)]);
+
+        let report =
+            parse_atc_xml_to_csv_with_options(&xml_path, &csv_path, &ParserOptions::default())
+                .unwrap();
+        assert!(report.unknown_elements.is_empty());
+    }
+
+    #[test]
+    fn test_prescription_unknown_elements() {
+        let dir = TempDir::new().unwrap();
+        for workers in [1, 4] {
+            let options = ParserOptions {
+                workers,
+                ..detecting()
+            };
+            let report = parse_prescription_xml_to_csvs_with_options(
+                fixture("drift", "Prescripcion.xml").as_path(),
+                dir.path(),
+                &options,
+            )
+            .unwrap();
+            assert_eq!(report.records, 3);
+            assert_eq!(
+                report.unknown_elements,
+                [
+                    ("atc/nuevocampo".to_string(), 1),
+                    ("prescription/nuevocampo".to_string(), 2),
+                ],
+                "{workers}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_known_fixtures_have_no_unknown_elements() {
+        let dir = TempDir::new().unwrap();
+        for kind in DictionaryKind::ALL {
+            let report = kind
+                .parse(
+                    fixture("nomenclator", kind.default_xml_filename()),
+                    dir.path().join(kind.default_csv_filename()),
+                    &detecting(),
+                )
+                .unwrap();
+            assert!(report.unknown_elements.is_empty(), "{kind}: {report:?}");
+        }
+
+        let report = parse_prescription_xml_to_csvs_with_options(
+            fixture("delta", "Prescripcion.xml").as_path(),
+            dir.path(),
+            &detecting(),
+        )
+        .unwrap();
+        assert!(report.unknown_elements.is_empty(), "{report:?}");
+    }
+}
@@ -0,0 +1,320 @@
+//! Schema-less parsing of dictionary XML files.
+//!
+//! Every nomenclator dictionary is a `<root>` holding one element per record, whose
+//! children are the record fields. These functions read any file of that shape into
+//! JSON maps, so dictionaries without a typed parser (new ones, or ones whose layout
+//! changed) can still be inspected or converted to CSV. The typed parsers are preferred
+//! when they exist: they check the expected fields, name the columns, parse flags and
+//! numbers and clean values such as the ATC descriptions.
+
+use super::options::ParserOptions;
+use super::parts;
+use super::report::{ParseReport, parse_span, record_parse};
+use super::{decode_xml, open_xml, validated};
+use anyhow::{Context, Result};
+use quick_xml::Reader;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use serde_json::{Map, Value};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// Content of a dictionary read without a schema
+struct GenericDictionary {
+    root: String,
+    records: Vec<Map<String, Value>>,
+    /// Field names in order of first appearance, as maps are sorted by key
+    fields: Vec<String>,
+}
+
+fn read_dictionary<R: BufRead>(reader: R) -> Result<GenericDictionary> {
+    let mut reader = Reader::from_reader(decode_xml(reader)?);
+    let mut buf = Vec::new();
+    let mut root = None;
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut record = Map::new();
+    // Name and text of the field being read
+    let mut field: Option<(String, String)> = None;
+    let mut depth = 0usize;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .context("Failed to read dictionary XML")?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                let empty = matches!(event, Event::Empty(_));
+                match depth {
+                    0 => root = Some(name),
+                    1 => {}
+                    2 if empty => insert_field(&mut record, &mut fields, name, Value::Null),
+                    2 => field = Some((name, String::new())),
+                    _ => {
+                        let (parent, _) = field.as_ref().context("Unexpected XML nesting")?;
+                        anyhow::bail!("Unexpected element <{name}> inside field <{parent}>");
+                    }
+                }
+                if empty {
+                    if depth == 1 {
+                        records.push(Map::new());
+                    }
+                } else {
+                    depth += 1;
+                }
+            }
+            Event::End(_) => {
+                depth = depth.saturating_sub(1);
+                match depth {
+                    1 => records.push(std::mem::take(&mut record)),
+                    2 => {
+                        if let Some((name, text)) = field.take() {
+                            let text = text.trim();
+                            let value = if text.is_empty() {
+                                Value::Null
+                            } else {
+                                Value::String(text.to_string())
+                            };
+                            insert_field(&mut record, &mut fields, name, value);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Text(ref text) => {
+                if let Some((_, value)) = &mut field {
+                    value.push_str(&text.decode().context("Invalid text in dictionary XML")?);
+                }
+            }
+            Event::CData(ref text) => {
+                if let Some((_, value)) = &mut field {
+                    value.push_str(&text.decode().context("Invalid text in dictionary XML")?);
+                }
+            }
+            Event::GeneralRef(ref reference) => {
+                if let Some((_, value)) = &mut field {
+                    match reference.resolve_char_ref()? {
+                        Some(c) => value.push(c),
+                        None => {
+                            let entity = reference.decode()?;
+                            let resolved = resolve_predefined_entity(&entity)
+                                .with_context(|| format!("Unknown entity &{entity};"))?;
+                            value.push_str(resolved);
+                        }
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(GenericDictionary {
+        root: root.context("Dictionary XML has no root element")?,
+        records,
+        fields,
+    })
+}
+
+/// Adds `value` to `record`, collecting the values of a repeated field in an array.
+fn insert_field(
+    record: &mut Map<String, Value>,
+    fields: &mut Vec<String>,
+    name: String,
+    value: Value,
+) {
+    match record.get_mut(&name) {
+        Some(Value::Array(values)) => values.push(value),
+        Some(previous) => *previous = Value::Array(vec![previous.take(), value]),
+        None => {
+            if !fields.contains(&name) {
+                fields.push(name.clone());
+            }
+            record.insert(name, value);
+        }
+    }
+}
+
+/// Reads a two-level dictionary XML from a buffered reader, returning the name of the
+/// root element and one map per record.
+///
+/// Field values are kept as text, trimmed, and empty fields (`<campo/>`) are `null`.
+/// A field repeated within a record becomes an array of its values. Fields with
+/// child elements are rejected, as they cannot be represented as one value.
+pub fn parse_generic_dictionary_xml_from_reader<R: BufRead>(
+    reader: R,
+) -> Result<(String, Vec<Map<String, Value>>)> {
+    let dictionary = read_dictionary(reader)?;
+    Ok((dictionary.root, dictionary.records))
+}
+
+/// Reads a two-level dictionary XML file, see [`parse_generic_dictionary_xml_from_reader`].
+pub fn parse_generic_dictionary_xml<P: AsRef<Path>>(
+    xml_path: P,
+) -> Result<(String, Vec<Map<String, Value>>)> {
+    parse_generic_dictionary_xml_from_reader(open_xml(xml_path)?)
+}
+
+/// Converts a two-level dictionary XML from a buffered reader to CSV formatted after
+/// `options`, with one column per field name in order of first appearance.
+///
+/// Headers are the XML element names whatever the
+/// [`header_style`](ParserOptions::header_style). Missing and empty fields are written
+/// as [`null_repr`](ParserOptions::null_repr), and repeated fields as a JSON array.
+pub fn generic_dictionary_to_csv_from_reader_with_options<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    options: &ParserOptions,
+) -> Result<ParseReport> {
+    let started = Instant::now();
+    let dictionary = read_dictionary(reader)?;
+    let span = parse_span(&dictionary.root, "csv");
+    let _entered = span.enter();
+    tracing::debug!(
+        root = %dictionary.root,
+        records = dictionary.records.len(),
+        "Parsed generic dictionary"
+    );
+
+    let mut wtr = options.csv_writer(writer);
+    wtr.write_record(&dictionary.fields)?;
+    for record in &dictionary.records {
+        for name in &dictionary.fields {
+            match record.get(name) {
+                None | Some(Value::Null) => wtr.write_field(&options.null_repr)?,
+                Some(Value::String(value)) => wtr.write_field(value)?,
+                Some(value) => wtr.write_field(value.to_string())?,
+            }
+        }
+        wtr.write_record(None::<&[u8]>)?;
+    }
+    wtr.flush()?;
+    let report = ParseReport {
+        records: dictionary.records.len(),
+        elapsed: started.elapsed(),
+        ..Default::default()
+    };
+    record_parse(&span, &dictionary.root, &report);
+    Ok(report)
+}
+
+/// Converts any two-level dictionary XML file to CSV.
+pub fn generic_dictionary_to_csv<P: AsRef<Path>>(xml_path: P, csv_path: P) -> Result<()> {
+    generic_dictionary_to_csv_with_options(xml_path, csv_path, &ParserOptions::default())?;
+    Ok(())
+}
+
+/// Converts any two-level dictionary XML file to CSV formatted after `options`, see
+/// [`generic_dictionary_to_csv_from_reader_with_options`].
+pub fn generic_dictionary_to_csv_with_options<P: AsRef<Path>>(
+    xml_path: P,
+    csv_path: P,
+    options: &ParserOptions,
+) -> Result<ParseReport> {
+    validated(xml_path.as_ref(), options, || {
+        let reader = open_xml(xml_path.as_ref())?;
+        parts::write_csv_file(csv_path.as_ref(), options, &[0], |output| {
+            generic_dictionary_to_csv_from_reader_with_options(reader, output, options)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::options::HeaderStyle;
+    use super::super::{parse_atc_xml, parse_laboratorio_xml_to_csv_with_options};
+    use super::*;
+    use tempfile::TempDir;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/nomenclator")
+            .join(name)
+    }
+
+    #[test]
+    fn test_generic_matches_typed_atc() {
+        let (root, records) = parse_generic_dictionary_xml(fixture("DICCIONARIO_ATC.xml")).unwrap();
+        assert_eq!(root, "aemps_prescripcion_atc");
+
+        let typed = parse_atc_xml(fixture("DICCIONARIO_ATC.xml")).unwrap();
+        assert_eq!(records.len(), typed.len());
+        for (record, typed) in records.iter().zip(&typed) {
+            assert_eq!(record["nroatc"], typed.number.to_string());
+            assert_eq!(record["codigoatc"], typed.code.as_str());
+            // The typed parser also strips the code prefix of the description
+            assert_eq!(
+                record["descatc"],
+                format!("{} - {}", typed.code, typed.description)
+            );
+        }
+    }
+
+    #[test]
+    fn test_generic_csv_matches_typed_csv() {
+        let dir = TempDir::new().unwrap();
+        let (generic, typed) = (dir.path().join("generic.csv"), dir.path().join("typed.csv"));
+        let xml_path = fixture("DICCIONARIO_LABORATORIOS.xml");
+        generic_dictionary_to_csv(xml_path.clone(), generic.clone()).unwrap();
+        let options = ParserOptions {
+            header_style: HeaderStyle::Spanish,
+            ..Default::default()
+        };
+        parse_laboratorio_xml_to_csv_with_options(xml_path, typed.clone(), &options).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(generic).unwrap(),
+            std::fs::read_to_string(typed).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invented_dictionary() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<aemps_prescripcion_colores>
+  <color>
+    <codigo>1</codigo>
+    <nombre> Rojo &amp; granate </nombre>
+  </color>
+  <color>
+    <codigo>2</codigo>
+    <tono>claro</tono>
+    <tono>&#233;mbar</tono>
+    <nombre/>
+  </color>
+  <color/>
+</aemps_prescripcion_colores>"#;
+        let (root, records) = parse_generic_dictionary_xml_from_reader(xml.as_bytes()).unwrap();
+        assert_eq!(root, "aemps_prescripcion_colores");
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["nombre"], "Rojo & granate");
+        assert_eq!(records[1]["tono"], serde_json::json!(["claro", "émbar"]));
+        assert_eq!(records[1]["nombre"], Value::Null);
+        assert!(records[2].is_empty());
+
+        let mut csv = Vec::new();
+        let report = generic_dictionary_to_csv_from_reader_with_options(
+            xml.as_bytes(),
+            &mut csv,
+            &ParserOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.records, 3);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "codigo,nombre,tono\n1,Rojo & granate,\n2,,\"[\"\"claro\"\",\"\"émbar\"\"]\"\n,,\n"
+        );
+    }
+
+    #[test]
+    fn test_nested_field_is_rejected() {
+        let xml = "<raiz><item><campo><sub>1</sub></campo></item></raiz>";
+        let err = parse_generic_dictionary_xml_from_reader(xml.as_bytes()).unwrap_err();
+        assert!(
+            err.to_string().contains("<sub> inside field <campo>"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,635 @@
+//! Loading of the generated CSV files back into the record structs.
+//!
+//! The record structs deserialize from the AEMPS element names, so the rows are read
+//! against the `wire_name` of each [`Column`] whatever [`HeaderStyle`](super::HeaderStyle)
+//! the header was written in.
+
+use super::options::{ParserOptions, PrescriptionKey};
+use super::schema::{
+    Column, Columns, PRESCRIPTION_ACTIVE_INGREDIENT_COLUMNS, PRESCRIPTION_ADMIN_ROUTE_COLUMNS,
+    PRESCRIPTION_ATC_COLUMNS, PRESCRIPTION_ATC_DUPLICATE_COLUMNS, PRESCRIPTION_EXCIPIENT_COLUMNS,
+    PRESCRIPTION_FORM_COLUMNS, PRESCRIPTION_NOTE_COLUMNS, PRESCRIPTION_SUPPLY_PROBLEM_COLUMNS,
+    prescription_child_columns, prescription_key_columns,
+};
+use super::{
+    ActiveIngredient, ActiveIngridientRecord, AdminRoute, AdministrationRouteRecord, AtcDuplicate,
+    AtcRecord, ContainerRecord, ContainerUnitRecord, DcpRecord, DcpfRecord, DcsaRecord,
+    ExcipientRecord, LaboratoryRecord, PRESCRIPTION_ACTIVE_INGREDIENTS_CSV,
+    PRESCRIPTION_ADMIN_ROUTES_CSV, PRESCRIPTION_ATC_CSV, PRESCRIPTION_ATC_DUPLICATES_CSV,
+    PRESCRIPTION_EXCIPIENTS_CSV, PRESCRIPTION_FORMS_CSV, PRESCRIPTION_NOTES_CSV,
+    PRESCRIPTION_SUPPLY_PROBLEMS_CSV, PRESCRIPTIONS_CSV, PharmaceuticalFormRecord, PrescriptionAtc,
+    PrescriptionExcipient, PrescriptionForm, PrescriptionNote, PrescriptionRecord,
+    RegistrationStatusRecord, SimplifiedPharmaceuticalFormRecord, SupplyProblem,
+};
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+/// Rows of a CSV file written after `options`, ready to be deserialized
+pub(super) struct CsvRows {
+    reader: csv::Reader<File>,
+    columns: Cow<'static, [Column]>,
+    /// Element names the record structs deserialize from
+    wire_names: StringRecord,
+    null_repr: String,
+}
+
+impl CsvRows {
+    /// Opens `path`, checking the header row against `columns` when `has_header` is set.
+    pub(super) fn open(
+        path: &Path,
+        columns: Cow<'static, [Column]>,
+        has_header: bool,
+        options: &ParserOptions,
+    ) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(options.delimiter)
+            .has_headers(has_header)
+            .from_reader(file);
+        if has_header {
+            let header = reader
+                .headers()
+                .with_context(|| format!("Failed to read the header of {}", path.display()))?;
+            let expected: Vec<_> = columns
+                .iter()
+                .map(|column| column.header(options.header_style))
+                .collect();
+            if !header.iter().eq(expected.iter().copied()) {
+                anyhow::bail!(
+                    "Unexpected header in {}: expected {}, found {}",
+                    path.display(),
+                    expected.join(","),
+                    header.iter().collect::<Vec<_>>().join(",")
+                );
+            }
+        }
+        Ok(CsvRows {
+            reader,
+            wire_names: columns.iter().map(|column| column.wire_name).collect(),
+            columns,
+            null_repr: options.null_repr.clone(),
+        })
+    }
+
+    /// Reads every row as a `T`, passing the raw row along to `f`.
+    fn for_each<T: DeserializeOwned>(
+        mut self,
+        path: &Path,
+        mut f: impl FnMut(&StringRecord, T) -> Result<()>,
+    ) -> Result<()> {
+        let mut row = StringRecord::new();
+        while self
+            .reader
+            .read_record(&mut row)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+        {
+            let line = row.position().map_or(0, |position| position.line());
+            let row = self.nulls_to_empty(&row);
+            let record = row.deserialize(Some(&self.wire_names)).with_context(|| {
+                format!("Failed to deserialize {} line {}", path.display(), line)
+            })?;
+            f(&row, record).with_context(|| format!("{} line {}", path.display(), line))?;
+        }
+        Ok(())
+    }
+
+    /// Reads the next row, with nulls as empty fields, `None` at the end of the file.
+    pub(super) fn next_row(&mut self, path: &Path) -> Result<Option<StringRecord>> {
+        let mut row = StringRecord::new();
+        if !self
+            .reader
+            .read_record(&mut row)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+        {
+            return Ok(None);
+        }
+        Ok(Some(self.nulls_to_empty(&row)))
+    }
+
+    /// Replaces [`null_repr`](ParserOptions::null_repr) by an empty field in nullable
+    /// columns, which deserializes as `None`.
+    fn nulls_to_empty(&self, row: &StringRecord) -> StringRecord {
+        if self.null_repr.is_empty() {
+            return row.clone();
+        }
+        row.iter()
+            .zip(self.columns.iter())
+            .map(|(field, column)| {
+                if column.nullable && field == self.null_repr {
+                    ""
+                } else {
+                    field
+                }
+            })
+            .collect()
+    }
+}
+
+/// Loads a CSV file with a header row written for records of type `T`.
+fn load_records<T: Columns + DeserializeOwned>(
+    csv_path: &Path,
+    options: &ParserOptions,
+) -> Result<Vec<T>> {
+    let mut records = Vec::new();
+    CsvRows::open(csv_path, Cow::Borrowed(T::COLUMNS), true, options)?.for_each(
+        csv_path,
+        |_, record| {
+            records.push(record);
+            Ok(())
+        },
+    )?;
+    Ok(records)
+}
+
+macro_rules! impl_csv_loader {
+    ($name:literal, $load_fn:ident, $load_options_fn:ident, $record_type:ty) => {
+        #[doc = concat!("Loads a CSV file written by the ", $name, " parser.")]
+        pub fn $load_fn<P: AsRef<Path>>(csv_path: P) -> Result<Vec<$record_type>> {
+            $load_options_fn(csv_path, &ParserOptions::default())
+        }
+
+        #[doc = concat!("Loads a CSV file written by the ", $name, " parser with `options`.")]
+        pub fn $load_options_fn<P: AsRef<Path>>(
+            csv_path: P,
+            options: &ParserOptions,
+        ) -> Result<Vec<$record_type>> {
+            load_records(csv_path.as_ref(), options)
+        }
+    };
+}
+
+impl_csv_loader!("ATC", load_atc_csv, load_atc_csv_with_options, AtcRecord);
+impl_csv_loader!("DCP", load_dcp_csv, load_dcp_csv_with_options, DcpRecord);
+impl_csv_loader!(
+    "DCPF",
+    load_dcpf_csv,
+    load_dcpf_csv_with_options,
+    DcpfRecord
+);
+impl_csv_loader!(
+    "DCSA",
+    load_dcsa_csv,
+    load_dcsa_csv_with_options,
+    DcsaRecord
+);
+impl_csv_loader!(
+    "Envases",
+    load_envases_csv,
+    load_envases_csv_with_options,
+    ContainerRecord
+);
+impl_csv_loader!(
+    "Excipientes",
+    load_excipientes_csv,
+    load_excipientes_csv_with_options,
+    ExcipientRecord
+);
+impl_csv_loader!(
+    "Forma Farmaceutica",
+    load_forma_farmaceutica_csv,
+    load_forma_farmaceutica_csv_with_options,
+    PharmaceuticalFormRecord
+);
+impl_csv_loader!(
+    "Forma Farmaceutica Simplificada",
+    load_forma_farmaceutica_simplificada_csv,
+    load_forma_farmaceutica_simplificada_csv_with_options,
+    SimplifiedPharmaceuticalFormRecord
+);
+impl_csv_loader!(
+    "Laboratorio",
+    load_laboratorio_csv,
+    load_laboratorio_csv_with_options,
+    LaboratoryRecord
+);
+impl_csv_loader!(
+    "Principio Activo",
+    load_principio_activo_csv,
+    load_principio_activo_csv_with_options,
+    ActiveIngridientRecord
+);
+impl_csv_loader!(
+    "Situacion Registro",
+    load_situacion_registro_csv,
+    load_situacion_registro_csv_with_options,
+    RegistrationStatusRecord
+);
+impl_csv_loader!(
+    "Unidad Contenido",
+    load_unidad_contenido_csv,
+    load_unidad_contenido_csv_with_options,
+    ContainerUnitRecord
+);
+impl_csv_loader!(
+    "Via Administracion",
+    load_via_administracion_csv,
+    load_via_administracion_csv_with_options,
+    AdministrationRouteRecord
+);
+
+/// Loads the CSV files written by
+/// [`parse_prescription_xml_to_csvs`](super::parse_prescription_xml_to_csvs) from
+/// `dir`, rebuilding the nested collections of each prescription from the child files.
+pub fn load_prescriptions_csv<P: AsRef<Path>>(dir: P) -> Result<Vec<PrescriptionRecord>> {
+    load_prescriptions_csv_with_options(dir, &ParserOptions::default())
+}
+
+/// Loads the prescription CSV files written with `options` from `dir`.
+///
+/// Child rows are attached by `cod_nacion`, in file order; a row referencing a missing
+/// prescription, form or ATC code is an error. Files keyed by `nro_definitivo` alone
+/// cannot be loaded, as several prescriptions may share one.
+pub fn load_prescriptions_csv_with_options<P: AsRef<Path>>(
+    dir: P,
+    options: &ParserOptions,
+) -> Result<Vec<PrescriptionRecord>> {
+    if options.prescription_key == PrescriptionKey::NroDefinitivo {
+        anyhow::bail!(
+            "Prescription CSV files keyed by nro_definitivo alone cannot be loaded, \
+             several prescriptions may share one"
+        );
+    }
+    let dir = dir.as_ref();
+    let mut records: Vec<PrescriptionRecord> = load_records(&dir.join(PRESCRIPTIONS_CSV), options)?;
+    let index: HashMap<String, usize> = records
+        .iter()
+        .enumerate()
+        .map(|(position, record)| (record.cod_nacion.clone(), position))
+        .collect();
+
+    let children = |file_name: &str| dir.join(file_name);
+    let key_columns = prescription_key_columns(options.prescription_key).len();
+
+    attach_children(
+        &mut records,
+        &index,
+        &children(PRESCRIPTION_FORMS_CSV),
+        PRESCRIPTION_FORM_COLUMNS,
+        options,
+        |record, _, form: PrescriptionForm| {
+            record.forms = Some(form);
+            Ok(())
+        },
+    )?;
+    attach_children(
+        &mut records,
+        &index,
+        &children(PRESCRIPTION_ACTIVE_INGREDIENTS_CSV),
+        PRESCRIPTION_ACTIVE_INGREDIENT_COLUMNS,
+        options,
+        |record, _, ingredient: ActiveIngredient| {
+            form_of(record)?.active_ingredients.push(ingredient);
+            Ok(())
+        },
+    )?;
+    attach_children(
+        &mut records,
+        &index,
+        &children(PRESCRIPTION_ADMIN_ROUTES_CSV),
+        PRESCRIPTION_ADMIN_ROUTE_COLUMNS,
+        options,
+        |record, _, route: AdminRoute| {
+            form_of(record)?.admin_routes.push(route);
+            Ok(())
+        },
+    )?;
+    attach_children(
+        &mut records,
+        &index,
+        &children(PRESCRIPTION_ATC_CSV),
+        PRESCRIPTION_ATC_COLUMNS,
+        options,
+        |record, _, atc: PrescriptionAtc| {
+            record.atc_codes.push(atc);
+            Ok(())
+        },
+    )?;
+    attach_children(
+        &mut records,
+        &index,
+        &children(PRESCRIPTION_ATC_DUPLICATES_CSV),
+        PRESCRIPTION_ATC_DUPLICATE_COLUMNS,
+        options,
+        |record, row, duplicate: AtcDuplicate| {
+            let atc_code = row.get(key_columns).unwrap_or_default();
+            let atc = record
+                .atc_codes
+                .iter_mut()
+                .find(|atc| atc.atc_code == atc_code)
+                .with_context(|| {
+                    format!(
+                        "Prescription {} has no ATC code {}",
+                        record.cod_nacion, atc_code
+                    )
+                })?;
+            atc.duplicates.push(duplicate);
+            Ok(())
+        },
+    )?;
+    attach_children(
+        &mut records,
+        &index,
+        &children(PRESCRIPTION_SUPPLY_PROBLEMS_CSV),
+        PRESCRIPTION_SUPPLY_PROBLEM_COLUMNS,
+        options,
+        |record, _, problem: SupplyProblem| {
+            record.supply_problems.push(problem);
+            Ok(())
+        },
+    )?;
+    attach_children(
+        &mut records,
+        &index,
+        &children(PRESCRIPTION_EXCIPIENTS_CSV),
+        PRESCRIPTION_EXCIPIENT_COLUMNS,
+        options,
+        |record, _, excipient: PrescriptionExcipient| {
+            record.excipients.push(excipient);
+            Ok(())
+        },
+    )?;
+    attach_children(
+        &mut records,
+        &index,
+        &children(PRESCRIPTION_NOTES_CSV),
+        PRESCRIPTION_NOTE_COLUMNS,
+        options,
+        |record, _, note: PrescriptionNote| {
+            record.notes.push(note);
+            Ok(())
+        },
+    )?;
+
+    Ok(records)
+}
+
+/// Reads the rows of a child file as `T` and passes each one to `attach` along with
+/// the prescription its first column refers to.
+fn attach_children<T: DeserializeOwned>(
+    records: &mut [PrescriptionRecord],
+    index: &HashMap<String, usize>,
+    path: &Path,
+    columns: &'static [Column],
+    options: &ParserOptions,
+    mut attach: impl FnMut(&mut PrescriptionRecord, &StringRecord, T) -> Result<()>,
+) -> Result<()> {
+    let columns = prescription_child_columns(columns, options.prescription_key);
+    CsvRows::open(path, columns, false, options)?.for_each(path, |row, child| {
+        let key = row.get(0).unwrap_or_default();
+        let position = *index
+            .get(key)
+            .with_context(|| format!("Unknown prescription {}", key))?;
+        attach(&mut records[position], row, child)
+    })
+}
+
+/// Pharmaceutical form of a prescription, which its ingredients and routes belong to
+fn form_of(record: &mut PrescriptionRecord) -> Result<&mut PrescriptionForm> {
+    let cod_nacion = &record.cod_nacion;
+    record
+        .forms
+        .as_mut()
+        .with_context(|| format!("Prescription {} has no pharmaceutical form", cod_nacion))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::options::{BoolRepr, HeaderStyle};
+    use super::super::tests::prescription_xml;
+    use super::super::*;
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/nomenclator")
+            .join(name)
+    }
+
+    macro_rules! assert_round_trip {
+        ($kind:expr, $parse_fn:ident, $csv_fn:ident, $load_fn:ident) => {{
+            let dir = TempDir::new().unwrap();
+            let (xml_path, csv_path) = (
+                fixture($kind.default_xml_filename()),
+                dir.path().join($kind.default_csv_filename()),
+            );
+            let records = $parse_fn(&xml_path).unwrap();
+            $csv_fn(xml_path, csv_path.clone()).unwrap();
+            assert_eq!($load_fn(&csv_path).unwrap(), records, "{}", $kind);
+        }};
+    }
+
+    #[test]
+    fn test_load_dictionary_csvs() {
+        use DictionaryKind::*;
+        assert_round_trip!(Atc, parse_atc_xml, parse_atc_xml_to_csv, load_atc_csv);
+        assert_round_trip!(Dcp, parse_dcp_xml, parse_dcp_xml_to_csv, load_dcp_csv);
+        assert_round_trip!(Dcpf, parse_dcpf_xml, parse_dcpf_xml_to_csv, load_dcpf_csv);
+        assert_round_trip!(Dcsa, parse_dcsa_xml, parse_dcsa_xml_to_csv, load_dcsa_csv);
+        assert_round_trip!(
+            Containers,
+            parse_envases_xml,
+            parse_envases_xml_to_csv,
+            load_envases_csv
+        );
+        assert_round_trip!(
+            Excipients,
+            parse_excipientes_xml,
+            parse_excipientes_xml_to_csv,
+            load_excipientes_csv
+        );
+        assert_round_trip!(
+            PharmaceuticalForms,
+            parse_forma_farmaceutica_xml,
+            parse_forma_farmaceutica_xml_to_csv,
+            load_forma_farmaceutica_csv
+        );
+        assert_round_trip!(
+            SimplifiedForms,
+            parse_forma_farmaceutica_simplificada_xml,
+            parse_forma_farmaceutica_simplificada_xml_to_csv,
+            load_forma_farmaceutica_simplificada_csv
+        );
+        assert_round_trip!(
+            Laboratories,
+            parse_laboratorio_xml,
+            parse_laboratorio_xml_to_csv,
+            load_laboratorio_csv
+        );
+        assert_round_trip!(
+            ActiveIngredients,
+            parse_principio_activo_xml,
+            parse_principio_activo_xml_to_csv,
+            load_principio_activo_csv
+        );
+        assert_round_trip!(
+            RegistrationStatuses,
+            parse_situacion_registro_xml,
+            parse_situacion_registro_xml_to_csv,
+            load_situacion_registro_csv
+        );
+        assert_round_trip!(
+            ContainerUnits,
+            parse_unidad_contenido_xml,
+            parse_unidad_contenido_xml_to_csv,
+            load_unidad_contenido_csv
+        );
+        assert_round_trip!(
+            AdministrationRoutes,
+            parse_via_administracion_xml,
+            parse_via_administracion_xml_to_csv,
+            load_via_administracion_csv
+        );
+    }
+
+    fn options() -> ParserOptions {
+        ParserOptions {
+            delimiter: b';',
+            null_repr: "\\N".to_string(),
+            bool_repr: BoolRepr::OneZero,
+            header_style: HeaderStyle::Spanish,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_load_dictionary_csv_with_options() {
+        let dir = TempDir::new().unwrap();
+        let xml_path = fixture("DICCIONARIO_LABORATORIOS.xml");
+        let csv_path = dir.path().join("laboratorios.csv");
+        parse_laboratorio_xml_to_csv_with_options(&xml_path, &csv_path, &options()).unwrap();
+
+        let records = load_laboratorio_csv_with_options(&csv_path, &options()).unwrap();
+        assert_eq!(records, parse_laboratorio_xml(&xml_path).unwrap());
+        assert_eq!(records[1].address, None);
+
+        // The header written with other options is rejected
+        let err = load_laboratorio_csv(&csv_path).unwrap_err();
+        assert!(format!("{err:#}").contains("Unexpected header"), "{err:#}");
+    }
+
+    fn write_prescriptions(dir: &Path) -> std::path::PathBuf {
+        let xml_path = dir.join("Prescripcion.xml");
+        fs::write(
+            &xml_path,
+            format!(
+                "<aemps_prescripcion>{}{}</aemps_prescripcion>",
+                prescription_xml(
+                    "600000",
+                    r#"<cod_dcsa>1001</cod_dcsa>
+                    <fecha_autorizacion>01/02/2003</fecha_autorizacion>
+                    <formasfarmaceuticas>
+                        <cod_forfar>288</cod_forfar>
+                        <cod_forfar_simplificada>34</cod_forfar_simplificada>
+                        <nro_pactiv>2</nro_pactiv>
+                        <composicion_pa>
+                            <cod_principio_activo>160</cod_principio_activo>
+                            <orden_colacion>1</orden_colacion>
+                            <dosis_pa>875</dosis_pa>
+                            <unidad_dosis_pa>mg</unidad_dosis_pa>
+                        </composicion_pa>
+                        <composicion_pa>
+                            <cod_principio_activo>161</cod_principio_activo>
+                            <orden_colacion>2</orden_colacion>
+                        </composicion_pa>
+                        <viasadministracion><cod_via_admin>48</cod_via_admin></viasadministracion>
+                    </formasfarmaceuticas>
+                    <atc>
+                        <cod_atc>J01CR02</cod_atc>
+                        <duplicidades>
+                            <atc_duplicidad>J01CA04</atc_duplicidad>
+                            <descripcion_atc_duplicidad>AMOXICILINA</descripcion_atc_duplicidad>
+                        </duplicidades>
+                    </atc>
+                    <atc><cod_atc>J01CA04</cod_atc></atc>
+                    <problemassuministro>
+                        <fecha_inicio>01/01/2024</fecha_inicio>
+                        <observaciones>Sin stock; revisar</observaciones>
+                    </problemassuministro>
+                    <excipientes>
+                        <cod_excipiente>1234</cod_excipiente>
+                        <cantidad>12,5</cantidad>
+                        <unidad>mg</unidad>
+                    </excipientes>
+                    <notas>
+                        <tipo_nota>1</tipo_nota>
+                        <num_nota>2024/01</num_nota>
+                        <url_nota>https://www.aemps.gob.es/nota</url_nota>
+                    </notas>"#
+                ),
+                prescription_xml("600001", ""),
+            ),
+        )
+        .unwrap();
+        xml_path
+    }
+
+    #[test]
+    fn test_load_prescriptions_csv() {
+        let dir = TempDir::new().unwrap();
+        let xml_path = write_prescriptions(dir.path());
+        let expected = parse_prescription_xml(&xml_path).unwrap().records;
+
+        let csv_dir = dir.path().join("csv");
+        fs::create_dir(&csv_dir).unwrap();
+        parse_prescription_xml_to_csvs(&xml_path, &csv_dir).unwrap();
+        let records = load_prescriptions_csv(&csv_dir).unwrap();
+        assert_eq!(records, expected);
+        assert_eq!(records[0].atc_codes[0].duplicates.len(), 1);
+
+        let options_dir = dir.path().join("options");
+        fs::create_dir(&options_dir).unwrap();
+        parse_prescription_xml_to_csvs_with_options(&xml_path, &options_dir, &options()).unwrap();
+        assert_eq!(
+            load_prescriptions_csv_with_options(&options_dir, &options()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_load_prescriptions_csv_unknown_prescription() {
+        let dir = TempDir::new().unwrap();
+        let xml_path = write_prescriptions(dir.path());
+        parse_prescription_xml_to_csvs(xml_path.as_path(), dir.path()).unwrap();
+        let notes = dir.path().join(PRESCRIPTION_NOTES_CSV);
+        fs::write(&notes, "699999,1,,,,,\n").unwrap();
+
+        let err = load_prescriptions_csv(dir.path()).unwrap_err();
+        let message = format!("{err:#}");
+        assert!(message.contains("Unknown prescription 699999"), "{message}");
+        assert!(message.contains("line 1"), "{message}");
+    }
+
+    #[test]
+    fn test_load_prescriptions_csv_prescription_key() {
+        let dir = TempDir::new().unwrap();
+        let xml_path = write_prescriptions(dir.path());
+        let expected = parse_prescription_xml(&xml_path).unwrap().records;
+
+        let both = ParserOptions {
+            prescription_key: PrescriptionKey::Both,
+            ..Default::default()
+        };
+        let csv_dir = dir.path().join("both");
+        fs::create_dir(&csv_dir).unwrap();
+        parse_prescription_xml_to_csvs_with_options(&xml_path, &csv_dir, &both).unwrap();
+        assert_eq!(
+            load_prescriptions_csv_with_options(&csv_dir, &both).unwrap(),
+            expected
+        );
+
+        let nro_definitivo = ParserOptions {
+            prescription_key: PrescriptionKey::NroDefinitivo,
+            ..Default::default()
+        };
+        let err = load_prescriptions_csv_with_options(&csv_dir, &nro_definitivo).unwrap_err();
+        assert!(err.to_string().contains("nro_definitivo"), "{err:#}");
+    }
+}
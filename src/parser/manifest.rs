@@ -0,0 +1,390 @@
+//! Checksum manifest used to skip parsing unchanged XML files.
+
+use super::options::ParserOptions;
+use super::parts::part_number;
+use super::report::ParseReport;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// File name of the manifest written to the output directory
+pub const MANIFEST_JSON: &str = "manifest.json";
+
+/// Serializes updates of the manifest by parsers running in parallel
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Source XML files parsed into an output directory and the files generated from them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Entries by source file name
+    pub sources: BTreeMap<String, SourceEntry>,
+}
+
+/// One parsed source file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceEntry {
+    pub size: u64,
+    pub sha256: String,
+    /// SHA-256 of every generated file by file name
+    pub outputs: BTreeMap<String, String>,
+    /// SHA-256 of the parser options shaping the generated files, such as the delimiter
+    /// or the header style; empty in manifests written before it was recorded
+    #[serde(default)]
+    pub options_sha256: String,
+}
+
+/// Hex encoded SHA-256 of the options in `options` that change what the parsers write,
+/// so that outputs written with other options are not taken as current.
+fn options_sha256(options: &ParserOptions) -> String {
+    let shaping = format!(
+        "{:?} {:?}",
+        (
+            options.delimiter,
+            &options.quote_style,
+            &options.null_repr,
+            options.bool_repr,
+            options.header_style,
+            options.bool_parsing,
+            options.on_duplicate,
+            options.on_error,
+        ),
+        (
+            options.normalize_numbers,
+            options.normalize_dates,
+            options.prescription_key,
+            options.max_rows_per_file,
+            options.sort_output,
+            &options.prescription_columns,
+        ),
+    );
+    format!("{:x}", Sha256::digest(shaping.as_bytes()))
+}
+
+/// Hex encoded SHA-256 of the file at `path`.
+fn sha256(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl Manifest {
+    /// Reads the manifest of `output_dir`, empty if there is none yet.
+    pub fn load<P: AsRef<Path>>(output_dir: P) -> Result<Self> {
+        let path = output_dir.as_ref().join(MANIFEST_JSON);
+        match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .with_context(|| format!("Failed to parse {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Writes the manifest to `output_dir`.
+    pub fn save<P: AsRef<Path>>(&self, output_dir: P) -> Result<()> {
+        let path = output_dir.as_ref().join(MANIFEST_JSON);
+        let content = serde_json::to_vec_pretty(self)?;
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Whether `source` was parsed from identical content with the same output options,
+    /// and every output in `outputs` still exists with its recorded checksum.
+    ///
+    /// With `split` set the outputs were written as parts, and every recorded part of
+    /// them must still match instead.
+    fn is_current(
+        &self,
+        name: &str,
+        source: &SourceEntry,
+        output_dir: &Path,
+        outputs: &[&str],
+        split: bool,
+    ) -> bool {
+        let Some(entry) = self.sources.get(name) else {
+            return false;
+        };
+        let unchanged = |output: &str, recorded: &String| {
+            sha256(&output_dir.join(output)).is_ok_and(|actual| &actual == recorded)
+        };
+        let outputs_current = if split {
+            let is_part_of = |recorded: &str, output: &str| part_number(recorded, output).is_some();
+            outputs.iter().all(|output| {
+                entry
+                    .outputs
+                    .keys()
+                    .any(|recorded| is_part_of(recorded, output))
+            }) && entry.outputs.iter().all(|(recorded, checksum)| {
+                outputs.iter().any(|output| is_part_of(recorded, output))
+                    && unchanged(recorded, checksum)
+            })
+        } else {
+            outputs.iter().all(|output| {
+                entry
+                    .outputs
+                    .get(*output)
+                    .is_some_and(|recorded| unchanged(output, recorded))
+            })
+        };
+        entry.size == source.size
+            && entry.sha256 == source.sha256
+            && entry.options_sha256 == source.options_sha256
+            && outputs_current
+    }
+}
+
+/// Runs `parse` writing `outputs` to `output_dir`, unless
+/// [`ParserOptions::skip_unchanged`] is set and the manifest shows `xml_path` was
+/// already parsed into the current outputs.
+pub(crate) fn parse_unless_unchanged(
+    xml_path: &Path,
+    output_dir: &Path,
+    outputs: &[&str],
+    options: &ParserOptions,
+    parse: impl FnOnce() -> Result<ParseReport>,
+) -> Result<ParseReport> {
+    if !options.skip_unchanged {
+        return parse();
+    }
+
+    let name = xml_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut source = SourceEntry {
+        size: fs::metadata(xml_path)
+            .with_context(|| format!("Failed to open {}", xml_path.display()))?
+            .len(),
+        sha256: sha256(xml_path)?,
+        outputs: BTreeMap::new(),
+        options_sha256: options_sha256(options),
+    };
+
+    {
+        let _lock = MANIFEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        if Manifest::load(output_dir)?.is_current(
+            &name,
+            &source,
+            output_dir,
+            outputs,
+            options.max_rows_per_file.is_some(),
+        ) {
+            tracing::info!(file = %name, "Unchanged, skipping");
+            return Ok(ParseReport {
+                unchanged: true,
+                ..Default::default()
+            });
+        }
+    }
+
+    let report = parse()?;
+
+    let written: Vec<&str> = if report.parts.is_empty() {
+        outputs.to_vec()
+    } else {
+        report.parts.iter().map(|(part, _)| part.as_str()).collect()
+    };
+    for output in written {
+        source
+            .outputs
+            .insert(output.to_string(), sha256(&output_dir.join(output))?);
+    }
+    let _lock = MANIFEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut manifest = Manifest::load(output_dir)?;
+    manifest.sources.insert(name, source);
+    manifest.save(output_dir)?;
+
+    Ok(report)
+}
+
+/// [`parse_unless_unchanged`] for a parser writing the single file `out_path`.
+pub(crate) fn parse_file_unless_unchanged(
+    xml_path: &Path,
+    out_path: &Path,
+    options: &ParserOptions,
+    parse: impl FnOnce() -> Result<ParseReport>,
+) -> Result<ParseReport> {
+    let output_dir = match out_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = out_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    parse_unless_unchanged(xml_path, output_dir, &[&name], options, parse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::options::HeaderStyle;
+    use super::super::tests::prescription_xml;
+    use super::super::{
+        PRESCRIPTION_ATC_CSV, PRESCRIPTION_CSV_FILES, parse_atc_xml_to_csv_with_options,
+        parse_prescription_xml_to_csvs_with_options,
+    };
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+    use tempfile::TempDir;
+
+    struct Fixture {
+        _dir: TempDir,
+        atc_xml: PathBuf,
+        prescriptions_xml: PathBuf,
+        output_dir: PathBuf,
+    }
+
+    fn fixture() -> Fixture {
+        let dir = TempDir::new().unwrap();
+        let atc_xml = dir.path().join("DICCIONARIO_ATC.xml");
+        fs::write(
+            &atc_xml,
+            "<aemps_prescripcion_atc><atc><nroatc>1</nroatc><codigoatc>A01</codigoatc>\
+             <descatc>A01 - DIGESTIVE</descatc></atc></aemps_prescripcion_atc>",
+        )
+        .unwrap();
+        let prescriptions_xml = dir.path().join("Prescripcion.xml");
+        fs::write(
+            &prescriptions_xml,
+            format!(
+                "<aemps_prescripcion>{}</aemps_prescripcion>",
+                prescription_xml("600000", "<atc><cod_atc>A01</cod_atc></atc>")
+            ),
+        )
+        .unwrap();
+        let output_dir = dir.path().join("output");
+        fs::create_dir(&output_dir).unwrap();
+        Fixture {
+            _dir: dir,
+            atc_xml,
+            prescriptions_xml,
+            output_dir,
+        }
+    }
+
+    fn run(fixture: &Fixture) -> (ParseReport, ParseReport) {
+        run_with(
+            fixture,
+            ParserOptions {
+                skip_unchanged: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn run_with(fixture: &Fixture, options: ParserOptions) -> (ParseReport, ParseReport) {
+        let atc = parse_atc_xml_to_csv_with_options(
+            &fixture.atc_xml,
+            &fixture.output_dir.join("atc.csv"),
+            &options,
+        )
+        .unwrap();
+        let prescriptions = parse_prescription_xml_to_csvs_with_options(
+            &fixture.prescriptions_xml,
+            &fixture.output_dir,
+            &options,
+        )
+        .unwrap();
+        (atc, prescriptions)
+    }
+
+    fn modification_times(dir: &Path) -> BTreeMap<PathBuf, SystemTime> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.path(), entry.metadata().unwrap().modified().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_second_run_writes_nothing() {
+        let fixture = fixture();
+        let (atc, prescriptions) = run(&fixture);
+        assert!(!atc.unchanged && !prescriptions.unchanged);
+
+        let manifest = Manifest::load(&fixture.output_dir).unwrap();
+        assert_eq!(manifest.sources.len(), 2);
+        let entry = &manifest.sources["Prescripcion.xml"];
+        assert_eq!(entry.outputs.len(), PRESCRIPTION_CSV_FILES.len());
+        assert_eq!(entry.sha256, sha256(&fixture.prescriptions_xml).unwrap());
+
+        let before = modification_times(&fixture.output_dir);
+        let (atc, prescriptions) = run(&fixture);
+        assert!(atc.unchanged && prescriptions.unchanged);
+        assert_eq!(modification_times(&fixture.output_dir), before);
+    }
+
+    #[test]
+    fn test_changed_source_is_parsed_again() {
+        let fixture = fixture();
+        run(&fixture);
+
+        fs::write(
+            &fixture.atc_xml,
+            "<aemps_prescripcion_atc><atc><nroatc>2</nroatc><codigoatc>B01</codigoatc>\
+             <descatc>BLOOD</descatc></atc></aemps_prescripcion_atc>",
+        )
+        .unwrap();
+        let (atc, prescriptions) = run(&fixture);
+
+        assert!(!atc.unchanged && prescriptions.unchanged);
+        let csv = fs::read_to_string(fixture.output_dir.join("atc.csv")).unwrap();
+        assert!(csv.contains("B01"));
+    }
+
+    #[test]
+    fn test_corrupted_or_missing_outputs_are_regenerated() {
+        let fixture = fixture();
+        run(&fixture);
+        let atc_csv = fixture.output_dir.join("atc.csv");
+        let expected = fs::read_to_string(&atc_csv).unwrap();
+
+        fs::write(&atc_csv, "truncated").unwrap();
+        fs::remove_file(fixture.output_dir.join(PRESCRIPTION_ATC_CSV)).unwrap();
+        let (atc, prescriptions) = run(&fixture);
+
+        assert!(!atc.unchanged && !prescriptions.unchanged);
+        assert_eq!(fs::read_to_string(&atc_csv).unwrap(), expected);
+        assert!(fixture.output_dir.join(PRESCRIPTION_ATC_CSV).exists());
+    }
+
+    #[test]
+    fn test_changed_output_options_are_parsed_again() {
+        let fixture = fixture();
+        run(&fixture);
+        let atc_csv = fixture.output_dir.join("atc.csv");
+        let before = fs::read_to_string(&atc_csv).unwrap();
+
+        let (atc, prescriptions) = run_with(
+            &fixture,
+            ParserOptions {
+                skip_unchanged: true,
+                header_style: HeaderStyle::Spanish,
+                ..Default::default()
+            },
+        );
+
+        assert!(!atc.unchanged && !prescriptions.unchanged);
+        let after = fs::read_to_string(&atc_csv).unwrap();
+        assert_ne!(after.lines().next(), before.lines().next());
+        let (atc, prescriptions) = run_with(
+            &fixture,
+            ParserOptions {
+                skip_unchanged: true,
+                header_style: HeaderStyle::Spanish,
+                workers: 2,
+                ..Default::default()
+            },
+        );
+        assert!(atc.unchanged && prescriptions.unchanged);
+    }
+}
@@ -0,0 +1,851 @@
+//! Conversion of a whole extracted nomenclator directory to CSV.
+
+use super::dictionary::DictionaryKind;
+use super::options::{ParserOptions, ProgressCallback};
+use super::report::{ParseProgress, ParseReport, ParseStats};
+use super::{
+    OutputFormat, PRESCRIPTION_CSV_FILES, PRESCRIPTION_XML, open_xml,
+    parse_prescription_xml_to_csvs_with_options, parse_prescription_xml_to_ndjson_from_reader,
+};
+use anyhow::{Context, Result};
+use futures::channel::oneshot;
+use futures::stream::{self, StreamExt};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// One of the XML files converted by [`parse_all_nomenclator`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NomenclatorFile {
+    /// A dictionary and its CSV file
+    Dictionary(DictionaryKind),
+    /// Prescripcion.xml and its [`PRESCRIPTION_CSV_FILES`]
+    Prescriptions,
+}
+
+/// File of Prescripcion.xml in [`OutputFormat::NdJson`], one prescription per line
+/// with its nested entities
+pub const PRESCRIPTIONS_NDJSON: &str = "prescriptions.ndjson";
+
+impl NomenclatorFile {
+    /// Short name of [`NomenclatorFile::Prescriptions`]
+    pub const PRESCRIPTIONS: &str = "prescripciones";
+
+    /// Every file, dictionaries first
+    pub fn all() -> Vec<NomenclatorFile> {
+        DictionaryKind::ALL
+            .into_iter()
+            .map(NomenclatorFile::Dictionary)
+            .chain([NomenclatorFile::Prescriptions])
+            .collect()
+    }
+
+    /// Name of the XML file in the work directory
+    pub fn xml_filename(self) -> &'static str {
+        match self {
+            NomenclatorFile::Dictionary(kind) => kind.default_xml_filename(),
+            NomenclatorFile::Prescriptions => PRESCRIPTION_XML,
+        }
+    }
+
+    /// Short name selecting the file on the command line
+    pub fn short_name(self) -> &'static str {
+        match self {
+            NomenclatorFile::Dictionary(kind) => kind.short_name(),
+            NomenclatorFile::Prescriptions => Self::PRESCRIPTIONS,
+        }
+    }
+}
+
+impl FromStr for NomenclatorFile {
+    type Err = anyhow::Error;
+
+    /// Reads a short name, such as `atc` or `prescripciones`, or an XML file name
+    fn from_str(name: &str) -> Result<Self> {
+        if name.eq_ignore_ascii_case(Self::PRESCRIPTIONS)
+            || name.eq_ignore_ascii_case(PRESCRIPTION_XML)
+        {
+            return Ok(NomenclatorFile::Prescriptions);
+        }
+        DictionaryKind::from_name(name)
+            .map(NomenclatorFile::Dictionary)
+            .with_context(|| {
+                let names: Vec<_> = NomenclatorFile::all()
+                    .into_iter()
+                    .map(NomenclatorFile::short_name)
+                    .collect();
+                format!(
+                    "Unknown nomenclator file '{}', use one of {} or an XML file name",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Callback receiving the [`ParseProgress`] updates of a dictionary with its XML file
+/// name
+pub type FileProgressCallback = Arc<dyn Fn(&'static str, ParseProgress) + Send + Sync>;
+
+/// Settings of [`parse_all_nomenclator`]
+#[derive(Clone)]
+pub struct NomenclatorOptions {
+    /// Options used for every file
+    pub parser: ParserOptions,
+    /// Number of dictionary files parsed at the same time
+    pub concurrency: usize,
+    /// Progress callback of the prescription step only, replacing
+    /// [`ParserOptions::progress`] there
+    pub prescription_progress: Option<ProgressCallback>,
+    /// Progress callback of the dictionaries, replacing [`ParserOptions::progress`]
+    /// there. It also receives an empty update when a file starts and a final one when
+    /// it ends, even if it fails.
+    pub dictionary_progress: Option<FileProgressCallback>,
+    /// Files to convert, every one by default; the others are reported as
+    /// [`FileStatus::Excluded`]
+    pub files: Vec<NomenclatorFile>,
+    /// Format of the generated files. [`OutputFormat::NdJson`] writes one file per
+    /// dictionary and [`PRESCRIPTIONS_NDJSON`], and ignores the CSV settings of
+    /// `parser`.
+    pub format: OutputFormat,
+}
+
+impl fmt::Debug for NomenclatorOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NomenclatorOptions")
+            .field("parser", &self.parser)
+            .field("concurrency", &self.concurrency)
+            .field(
+                "prescription_progress",
+                &self
+                    .prescription_progress
+                    .as_ref()
+                    .map(|_| "Fn(ParseProgress)"),
+            )
+            .field(
+                "dictionary_progress",
+                &self
+                    .dictionary_progress
+                    .as_ref()
+                    .map(|_| "Fn(&str, ParseProgress)"),
+            )
+            .field("files", &self.files)
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl Default for NomenclatorOptions {
+    fn default() -> Self {
+        NomenclatorOptions {
+            parser: ParserOptions::default(),
+            concurrency: num_cpus::get(),
+            prescription_progress: None,
+            dictionary_progress: None,
+            files: NomenclatorFile::all(),
+            format: OutputFormat::Csv,
+        }
+    }
+}
+
+/// Result of one XML file of [`parse_all_nomenclator`]
+// One per XML file, so boxing the report would only make matching on it harder
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum FileStatus {
+    /// Parsed, or skipped as unchanged when [`ParseReport::unchanged`] is set
+    Parsed(ParseReport),
+    /// Not found in the work directory
+    Skipped,
+    /// Left out of [`NomenclatorOptions::files`]
+    Excluded,
+    /// Failed to parse
+    Failed(anyhow::Error),
+}
+
+/// Outcome of one XML file of [`parse_all_nomenclator`]
+#[derive(Debug)]
+pub struct FileOutcome {
+    /// Name of the XML file in the work directory
+    pub xml_file: &'static str,
+    /// Names of the files it produces in the output directory, before any split
+    /// into the [`ParseReport::parts`] listed in its report
+    pub output_files: Vec<String>,
+    /// Whether the file was parsed, missing or failed
+    pub status: FileStatus,
+}
+
+impl FileOutcome {
+    /// Report of the file when it was parsed
+    pub fn report(&self) -> Option<&ParseReport> {
+        match &self.status {
+            FileStatus::Parsed(report) => Some(report),
+            _ => None,
+        }
+    }
+
+    /// Row counts of the file when it was parsed and not skipped as unchanged. The
+    /// rows of a single output file are listed under its name.
+    pub fn stats(&self) -> Option<ParseStats> {
+        let report = self.report().filter(|report| !report.unchanged)?;
+        Some(report.stats(&self.output_files[0]))
+    }
+}
+
+/// Per-file outcomes of [`parse_all_nomenclator`]
+#[derive(Debug, Default)]
+pub struct NomenclatorReport {
+    /// Dictionaries in [`DictionaryKind::ALL`] order, then Prescripcion.xml
+    pub files: Vec<FileOutcome>,
+}
+
+impl NomenclatorReport {
+    /// Outcome of the XML file named `xml_file`
+    pub fn file(&self, xml_file: &str) -> Option<&FileOutcome> {
+        self.files.iter().find(|file| file.xml_file == xml_file)
+    }
+
+    /// Files parsed, including those skipped as unchanged
+    pub fn parsed(&self) -> impl Iterator<Item = &FileOutcome> {
+        self.files.iter().filter(|file| file.report().is_some())
+    }
+
+    /// Files not found in the work directory
+    pub fn skipped(&self) -> impl Iterator<Item = &FileOutcome> {
+        self.files
+            .iter()
+            .filter(|file| matches!(file.status, FileStatus::Skipped))
+    }
+
+    /// Files left out of [`NomenclatorOptions::files`]
+    pub fn excluded(&self) -> impl Iterator<Item = &FileOutcome> {
+        self.files
+            .iter()
+            .filter(|file| matches!(file.status, FileStatus::Excluded))
+    }
+
+    /// Files that failed to parse
+    pub fn failed(&self) -> impl Iterator<Item = &FileOutcome> {
+        self.files
+            .iter()
+            .filter(|file| matches!(file.status, FileStatus::Failed(_)))
+    }
+
+    /// Whether no file failed to parse
+    pub fn is_success(&self) -> bool {
+        self.failed().next().is_none()
+    }
+
+    /// Row counts of the files parsed and not skipped as unchanged, by XML file name
+    pub fn stats(&self) -> BTreeMap<&'static str, ParseStats> {
+        self.files
+            .iter()
+            .filter_map(|file| Some((file.xml_file, file.stats()?)))
+            .collect()
+    }
+}
+
+/// Summary of `report` as printed by `nomenclator csv`, one indented line per count:
+/// dictionaries parsed, failed, missing or excluded, then the prescriptions outcome.
+pub fn render_summary(report: &NomenclatorReport) -> String {
+    let is_dictionary = |outcome: &&FileOutcome| outcome.xml_file != PRESCRIPTION_XML;
+    let failed = report.failed().filter(is_dictionary).count();
+    let skipped = report.skipped().count();
+    let excluded = report.excluded().count();
+
+    let mut summary = format!(
+        "  ✓ Dictionary files successful: {}\n",
+        report.parsed().filter(is_dictionary).count()
+    );
+    if failed > 0 {
+        summary.push_str(&format!("  ✗ Dictionary files failed: {}\n", failed));
+    }
+    if skipped > 0 {
+        summary.push_str(&format!("  - XML files not found: {}\n", skipped));
+    }
+    if excluded > 0 {
+        summary.push_str(&format!("  - XML files excluded: {}\n", excluded));
+    }
+    match report.file(PRESCRIPTION_XML) {
+        Some(FileOutcome {
+            status: FileStatus::Excluded,
+            ..
+        }) => summary.push_str("  - Prescription parsing: Excluded\n"),
+        Some(FileOutcome {
+            status: FileStatus::Failed(_),
+            ..
+        }) => summary.push_str("  ✗ Prescription parsing: Failed\n"),
+        outcome => {
+            let files = outcome.map_or(&[][..], |outcome| &outcome.output_files);
+            // Compressed outputs count as their format, prescriptions.csv.gz as csv
+            let extension = files
+                .first()
+                .map(|name| name.strip_suffix(".gz").unwrap_or(name))
+                .and_then(|name| Path::new(name).extension()?.to_str())
+                .unwrap_or("csv");
+            summary.push_str(&format!(
+                "  ✓ Prescription parsing: Success ({} {} files)\n",
+                files.len(),
+                extension
+            ));
+        }
+    }
+    summary
+}
+
+/// Parses every dictionary and Prescripcion.xml found in `work_dir` to CSV files in
+/// `output_dir`, with the default file names, or to the
+/// [`format`](NomenclatorOptions::format) of `options`.
+///
+/// Only the [`files`](NomenclatorOptions::files) selected in `options` are read, the
+/// others are reported as [`FileStatus::Excluded`].
+///
+/// Files are parsed on blocking threads, at most
+/// [`concurrency`](NomenclatorOptions::concurrency) at a time: Prescripcion.xml takes
+/// one of them while the dictionaries share the others, or is parsed after them with a
+/// concurrency of 1. Missing XML files are logged and reported as
+/// [`FileStatus::Skipped`]. A file failing to parse does not stop the others: check
+/// [`NomenclatorReport::is_success`]. Only an unusable `output_dir` is an error.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        work_dir = %work_dir.as_ref().display(),
+        format = options.format.extension(),
+        concurrency = options.concurrency
+    )
+)]
+pub async fn parse_all_nomenclator<P: AsRef<Path>>(
+    work_dir: P,
+    output_dir: P,
+    options: NomenclatorOptions,
+) -> Result<NomenclatorReport> {
+    let (work_dir, output_dir) = (work_dir.as_ref(), output_dir.as_ref());
+    fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create {}", output_dir.display()))?;
+
+    let parallel =
+        options.concurrency > 1 && options.files.contains(&NomenclatorFile::Prescriptions);
+    let slots = if parallel {
+        options.concurrency - 1
+    } else {
+        options.concurrency.max(1)
+    };
+    let dictionaries = parse_dictionaries(work_dir, output_dir, &options, slots);
+    let prescriptions = parse_prescriptions(work_dir, output_dir, &options);
+    let (mut files, prescriptions) = if parallel {
+        futures::join!(dictionaries, prescriptions)
+    } else {
+        (dictionaries.await, prescriptions.await)
+    };
+    files.push(prescriptions);
+
+    Ok(NomenclatorReport { files })
+}
+
+/// Parses the dictionaries of [`parse_all_nomenclator`], `slots` at a time.
+async fn parse_dictionaries(
+    work_dir: &Path,
+    output_dir: &Path,
+    options: &NomenclatorOptions,
+    slots: usize,
+) -> Vec<FileOutcome> {
+    tracing::info!(
+        file_count = DictionaryKind::ALL.len(),
+        concurrency = slots,
+        "Parsing dictionary files"
+    );
+    let format = options.format;
+    stream::iter(DictionaryKind::ALL)
+        .map(|kind| {
+            let xml_file = kind.default_xml_filename();
+            let xml_path = work_dir.join(xml_file);
+            let output_file = kind.output_filename(format);
+            let output_path = output_dir.join(&output_file);
+            let selected = options.files.contains(&NomenclatorFile::Dictionary(kind));
+            let callback = options.dictionary_progress.clone();
+            let mut parser = options.parser.clone();
+            if let Some(callback) = &callback {
+                let callback = callback.clone();
+                parser.progress = Some(Arc::new(move |progress| callback(xml_file, progress)));
+            }
+            async move {
+                let report = |records, done| {
+                    if let Some(callback) = &callback {
+                        callback(
+                            xml_file,
+                            ParseProgress {
+                                records,
+                                rows: Vec::new(),
+                                bytes_read: None,
+                                done,
+                            },
+                        );
+                    }
+                };
+                let status = if !selected {
+                    FileStatus::Excluded
+                } else {
+                    report(0, false);
+                    let status = if format == OutputFormat::NdJson {
+                        run_blocking(xml_file, xml_path, move |xml| {
+                            write_ndjson(&output_path, |writer| {
+                                kind.parse_ndjson_to_writer(
+                                    BufReader::new(File::open(&xml)?),
+                                    writer,
+                                )
+                            })
+                        })
+                        .await
+                    } else {
+                        run_blocking(xml_file, xml_path, move |xml| {
+                            kind.parse(xml, output_path, &parser)
+                        })
+                        .await
+                    };
+                    let records = match &status {
+                        FileStatus::Parsed(parsed) => parsed.records,
+                        _ => 0,
+                    };
+                    report(records, true);
+                    status
+                };
+                FileOutcome {
+                    xml_file,
+                    output_files: vec![output_file],
+                    status,
+                }
+            }
+        })
+        .buffered(slots)
+        .collect()
+        .await
+}
+
+/// Parses Prescripcion.xml for [`parse_all_nomenclator`].
+async fn parse_prescriptions(
+    work_dir: &Path,
+    output_dir: &Path,
+    options: &NomenclatorOptions,
+) -> FileOutcome {
+    let format = options.format;
+    let output_files = match format {
+        OutputFormat::Csv => PRESCRIPTION_CSV_FILES.map(String::from).to_vec(),
+        OutputFormat::NdJson => vec![PRESCRIPTIONS_NDJSON.to_string()],
+    };
+    let parser = ParserOptions {
+        progress: options.prescription_progress.clone(),
+        ..options.parser.clone()
+    };
+    let out_dir = output_dir.to_path_buf();
+    let xml_path = work_dir.join(PRESCRIPTION_XML);
+    let status = if !options.files.contains(&NomenclatorFile::Prescriptions) {
+        FileStatus::Excluded
+    } else {
+        tracing::info!(
+            "Parsing {} to {} {} files",
+            PRESCRIPTION_XML,
+            output_files.len(),
+            format.extension()
+        );
+        match format {
+            OutputFormat::Csv => {
+                run_blocking(PRESCRIPTION_XML, xml_path, move |xml| {
+                    parse_prescription_xml_to_csvs_with_options(&xml, &out_dir, &parser)
+                })
+                .await
+            }
+            OutputFormat::NdJson => {
+                run_blocking(PRESCRIPTION_XML, xml_path, move |xml| {
+                    write_ndjson(&out_dir.join(PRESCRIPTIONS_NDJSON), |writer| {
+                        parse_prescription_xml_to_ndjson_from_reader(open_xml(&xml)?, writer)
+                    })
+                })
+                .await
+            }
+        }
+    };
+    FileOutcome {
+        xml_file: PRESCRIPTION_XML,
+        output_files,
+        status,
+    }
+}
+
+/// Creates `path` and writes NDJSON to it with `write`, counting the lines as records.
+fn write_ndjson<F>(path: &Path, write: F) -> Result<ParseReport>
+where
+    F: FnOnce(&mut LineCounter<File>) -> Result<()>,
+{
+    let start = Instant::now();
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut counter = LineCounter {
+        inner: file,
+        lines: 0,
+    };
+    write(&mut counter)?;
+    Ok(ParseReport {
+        records: counter.lines,
+        elapsed: start.elapsed(),
+        ..Default::default()
+    })
+}
+
+/// Writer counting the newlines going through it. JSON strings escape their
+/// newlines, so NDJSON has one per record.
+struct LineCounter<W> {
+    inner: W,
+    lines: usize,
+}
+
+impl<W: Write> Write for LineCounter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.lines += buf[..written].iter().filter(|&&byte| byte == b'\n').count();
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Runs `parse` over `xml_path` on a thread of its own, unless the file is missing.
+///
+/// The result comes back through a channel rather than a runtime's blocking pool, so
+/// that [`parse_all_nomenclator`] runs on any executor.
+async fn run_blocking<F>(xml_file: &'static str, xml_path: PathBuf, parse: F) -> FileStatus
+where
+    F: FnOnce(PathBuf) -> Result<ParseReport> + Send + 'static,
+{
+    if !xml_path.exists() {
+        tracing::warn!(file = %xml_file, "File not found, skipping");
+        return FileStatus::Skipped;
+    }
+
+    tracing::debug!(xml = %xml_file, "Starting parse task");
+    let span = tracing::info_span!(
+        "parse_file",
+        xml = xml_file,
+        records = tracing::field::Empty
+    );
+    let thread_span = span.clone();
+    let (sender, receiver) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _entered = thread_span.enter();
+        // The receiver only goes away with the parse_all_nomenclator future
+        let _ = sender.send(parse(xml_path));
+    });
+    match receiver.await {
+        Ok(Ok(report)) => {
+            span.record("records", report.records);
+            tracing::info!(xml = %xml_file, "Completed parse");
+            FileStatus::Parsed(report)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(xml = %xml_file, error = %e, "Parse failed");
+            FileStatus::Failed(e)
+        }
+        Err(_) => {
+            tracing::error!(xml = %xml_file, "Parse thread panicked");
+            FileStatus::Failed(anyhow::anyhow!("Parse thread of {} panicked", xml_file))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Work directory holding a copy of the `names` fixtures
+    fn work_dir(names: &[&str]) -> TempDir {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let dir = TempDir::new().unwrap();
+        for name in names {
+            let source = if *name == PRESCRIPTION_XML {
+                fixtures.join("delta").join(name)
+            } else {
+                fixtures.join("nomenclator").join(name)
+            };
+            fs::copy(source, dir.path().join(name)).unwrap();
+        }
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_parse_all_nomenclator_skips_missing_files() {
+        let work = work_dir(&[
+            "DICCIONARIO_ATC.xml",
+            "DICCIONARIO_LABORATORIOS.xml",
+            PRESCRIPTION_XML,
+        ]);
+        let out = TempDir::new().unwrap();
+        let options = NomenclatorOptions {
+            concurrency: 2,
+            ..Default::default()
+        };
+
+        let report = parse_all_nomenclator(work.path(), out.path(), options)
+            .await
+            .unwrap();
+        assert_eq!(report.files.len(), DictionaryKind::ALL.len() + 1);
+        assert!(report.is_success());
+
+        let parsed: Vec<_> = report.parsed().map(|file| file.xml_file).collect();
+        assert_eq!(
+            parsed,
+            [
+                "DICCIONARIO_ATC.xml",
+                "DICCIONARIO_LABORATORIOS.xml",
+                PRESCRIPTION_XML
+            ]
+        );
+        assert_eq!(report.skipped().count(), DictionaryKind::ALL.len() - 2);
+        assert!(matches!(
+            report.file("DICCIONARIO_DCP.xml").unwrap().status,
+            FileStatus::Skipped
+        ));
+        assert_eq!(
+            report
+                .file(PRESCRIPTION_XML)
+                .unwrap()
+                .report()
+                .unwrap()
+                .records,
+            3
+        );
+
+        let stats = report.stats();
+        assert_eq!(
+            stats.keys().copied().collect::<Vec<_>>(),
+            [
+                "DICCIONARIO_ATC.xml",
+                "DICCIONARIO_LABORATORIOS.xml",
+                PRESCRIPTION_XML
+            ]
+        );
+        let atc = &stats["DICCIONARIO_ATC.xml"];
+        assert_eq!((atc.rows_read, atc.duplicates, atc.errors), (2, 0, 0));
+        assert_eq!(
+            atc.rows_written,
+            BTreeMap::from([("atc.csv".to_string(), 2)])
+        );
+        let prescriptions = &stats[PRESCRIPTION_XML];
+        assert_eq!(prescriptions.rows_read, 3);
+        assert_eq!(
+            prescriptions.rows_written.len(),
+            PRESCRIPTION_CSV_FILES.len()
+        );
+        assert_eq!(prescriptions.rows_written["prescriptions.csv"], 3);
+        assert_eq!(prescriptions.rows_written["prescription_atc.csv"], 3);
+        // The fixture has no child rows other than ATC codes
+        assert_eq!(prescriptions.total_rows_written(), 6);
+
+        assert!(out.path().join("atc.csv").exists());
+        assert!(!out.path().join("dcp.csv").exists());
+        for name in PRESCRIPTION_CSV_FILES {
+            assert!(out.path().join(name).exists(), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_all_nomenclator_reports_failures() {
+        let work = work_dir(&["DICCIONARIO_ATC.xml"]);
+        fs::write(
+            work.path().join("DICCIONARIO_DCP.xml"),
+            "<aemps_prescripcion_dcp><dcp>",
+        )
+        .unwrap();
+        let out = TempDir::new().unwrap();
+
+        let report = parse_all_nomenclator(work.path(), out.path(), NomenclatorOptions::default())
+            .await
+            .unwrap();
+        assert!(!report.is_success());
+        let failed: Vec<_> = report.failed().map(|file| file.xml_file).collect();
+        assert_eq!(failed, ["DICCIONARIO_DCP.xml"]);
+        assert!(
+            report
+                .file("DICCIONARIO_ATC.xml")
+                .unwrap()
+                .report()
+                .is_some()
+        );
+        assert!(matches!(
+            report.file(PRESCRIPTION_XML).unwrap().status,
+            FileStatus::Skipped
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_parse_all_nomenclator_selected_files() {
+        let work = work_dir(&[
+            "DICCIONARIO_ATC.xml",
+            "DICCIONARIO_LABORATORIOS.xml",
+            PRESCRIPTION_XML,
+        ]);
+        let out = TempDir::new().unwrap();
+        let options = NomenclatorOptions {
+            files: vec!["atc".parse().unwrap()],
+            ..Default::default()
+        };
+
+        let report = parse_all_nomenclator(work.path(), out.path(), options)
+            .await
+            .unwrap();
+        let parsed: Vec<_> = report.parsed().map(|file| file.xml_file).collect();
+        assert_eq!(parsed, ["DICCIONARIO_ATC.xml"]);
+        assert_eq!(report.excluded().count(), DictionaryKind::ALL.len());
+        assert_eq!(report.skipped().count(), 0);
+        assert!(matches!(
+            report.file(PRESCRIPTION_XML).unwrap().status,
+            FileStatus::Excluded
+        ));
+        assert!(!out.path().join("laboratorios.csv").exists());
+        assert!(!out.path().join("prescriptions.csv").exists());
+    }
+
+    #[tokio::test]
+    async fn test_parse_all_nomenclator_progress() {
+        let work = work_dir(&[
+            "DICCIONARIO_ATC.xml",
+            "DICCIONARIO_LABORATORIOS.xml",
+            PRESCRIPTION_XML,
+        ]);
+        let out = TempDir::new().unwrap();
+        let updates = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let prescriptions_done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let (dictionary_updates, done) = (updates.clone(), prescriptions_done.clone());
+        let options = NomenclatorOptions {
+            files: ["atc", "laboratorios", "prescripciones"]
+                .map(|name| name.parse().unwrap())
+                .to_vec(),
+            concurrency: 2,
+            dictionary_progress: Some(Arc::new(move |file, progress| {
+                dictionary_updates.lock().unwrap().push((file, progress));
+            })),
+            prescription_progress: Some(Arc::new(move |progress| {
+                if progress.done {
+                    done.store(true, std::sync::atomic::Ordering::SeqCst);
+                }
+            })),
+            ..Default::default()
+        };
+
+        let report = parse_all_nomenclator(work.path(), out.path(), options)
+            .await
+            .unwrap();
+        assert!(report.is_success());
+        assert!(prescriptions_done.load(std::sync::atomic::Ordering::SeqCst));
+        let updates = updates.lock().unwrap();
+        for file in ["DICCIONARIO_ATC.xml", "DICCIONARIO_LABORATORIOS.xml"] {
+            let file_updates: Vec<_> = updates.iter().filter(|(name, _)| *name == file).collect();
+            let (first, last) = (&file_updates[0].1, &file_updates.last().unwrap().1);
+            assert_eq!((first.records, first.done), (0, false), "{file}");
+            assert!(last.done, "{file}");
+            assert_eq!(
+                last.records,
+                report.file(file).unwrap().report().unwrap().records
+            );
+        }
+        assert!(
+            updates
+                .iter()
+                .all(|(name, _)| name.starts_with("DICCIONARIO_ATC")
+                    || name.starts_with("DICCIONARIO_LABORATORIOS"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_render_summary() {
+        let work = work_dir(&["DICCIONARIO_ATC.xml", PRESCRIPTION_XML]);
+        let out = TempDir::new().unwrap();
+        let options = NomenclatorOptions {
+            files: vec![
+                NomenclatorFile::Dictionary(DictionaryKind::Atc),
+                NomenclatorFile::Dictionary(DictionaryKind::Dcp),
+                NomenclatorFile::Prescriptions,
+            ],
+            ..Default::default()
+        };
+
+        let report = parse_all_nomenclator(work.path(), out.path(), options)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            render_summary(&report),
+            format!(
+                "  ✓ Dictionary files successful: 1\n\
+                 \x20 - XML files not found: 1\n\
+                 \x20 - XML files excluded: {}\n\
+                 \x20 ✓ Prescription parsing: Success ({} csv files)\n",
+                DictionaryKind::ALL.len() - 2,
+                PRESCRIPTION_CSV_FILES.len()
+            )
+        );
+    }
+
+    #[test]
+    fn test_nomenclator_file_from_str() {
+        assert_eq!(
+            "laboratorios".parse::<NomenclatorFile>().unwrap(),
+            NomenclatorFile::Dictionary(DictionaryKind::Laboratories)
+        );
+        assert_eq!(
+            "DICCIONARIO_DCP.xml".parse::<NomenclatorFile>().unwrap(),
+            NomenclatorFile::Dictionary(DictionaryKind::Dcp)
+        );
+        assert_eq!(
+            "prescripciones".parse::<NomenclatorFile>().unwrap(),
+            NomenclatorFile::Prescriptions
+        );
+        assert_eq!(
+            PRESCRIPTION_XML.parse::<NomenclatorFile>().unwrap(),
+            NomenclatorFile::Prescriptions
+        );
+
+        let error = "labs".parse::<NomenclatorFile>().unwrap_err().to_string();
+        assert!(error.contains("'labs'"), "{error}");
+        assert!(error.contains("atc, dcp"), "{error}");
+        assert!(error.contains("prescripciones"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_parse_all_nomenclator_ndjson() {
+        let work = work_dir(&["DICCIONARIO_ATC.xml", PRESCRIPTION_XML]);
+        let out = TempDir::new().unwrap();
+        let options = NomenclatorOptions {
+            format: OutputFormat::NdJson,
+            ..Default::default()
+        };
+
+        let report = parse_all_nomenclator(work.path(), out.path(), options)
+            .await
+            .unwrap();
+        assert!(report.is_success());
+        let prescriptions = report.file(PRESCRIPTION_XML).unwrap();
+        assert_eq!(prescriptions.output_files, [PRESCRIPTIONS_NDJSON]);
+        assert_eq!(prescriptions.report().unwrap().records, 3);
+        assert_eq!(
+            report.stats()["DICCIONARIO_ATC.xml"].rows_written,
+            BTreeMap::from([("atc.ndjson".to_string(), 2)])
+        );
+
+        let ndjson = fs::read_to_string(out.path().join(PRESCRIPTIONS_NDJSON)).unwrap();
+        assert_eq!(ndjson.lines().count(), 3);
+        for line in ndjson.lines() {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert!(value.is_object(), "{line}");
+        }
+        assert!(out.path().join("atc.ndjson").exists());
+        assert!(!out.path().join("atc.csv").exists());
+        assert!(!out.path().join("prescriptions.csv").exists());
+    }
+}
@@ -0,0 +1,171 @@
+//! Normalization of the numeric prescription columns written with Spanish formatting.
+//!
+//! AEMPS writes quantities such as `contenido` or `dosis_pa` as text with a comma
+//! decimal separator and, sometimes, dots grouping thousands (`2,5`, `1.000,75`). With
+//! [`ParserOptions::normalize_numbers`](super::ParserOptions::normalize_numbers) they are
+//! rewritten with a dot decimal separator and no grouping.
+
+use super::PrescriptionRecord;
+
+/// Columns rewritten by [`ParserOptions::normalize_numbers`](super::ParserOptions::normalize_numbers),
+/// as `file.column` with the file name of [`PRESCRIPTION_CSV_FILES`](super::PRESCRIPTION_CSV_FILES)
+/// without extension and the English column name
+pub const NUMERIC_COLUMNS: [&str; 6] = [
+    "prescriptions.contenido",
+    "prescription_active_ingredients.dose",
+    "prescription_active_ingredients.composition_dose",
+    "prescription_active_ingredients.administration_dose",
+    "prescription_active_ingredients.prescription_dose",
+    "prescription_excipients.quantity",
+];
+
+/// Values per column of [`NUMERIC_COLUMNS`] that could not be parsed
+pub(crate) type UnparsedCounts = [usize; NUMERIC_COLUMNS.len()];
+
+/// Rewrites a Spanish formatted number with a dot decimal separator and no grouping.
+///
+/// A comma is the decimal separator and dots before it group thousands. Without a
+/// comma, dots are taken as grouping when every group after the first has three
+/// digits (`1.000`), and as the decimal separator otherwise (`0.5`). Returns `None`
+/// for anything else, including empty values.
+pub(crate) fn normalize_number(value: &str) -> Option<String> {
+    let value = value.trim();
+    let (sign, digits) = match value.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", value),
+    };
+    let (integer, fraction) = match digits.split_once(',') {
+        Some((integer, fraction)) => (ungroup(integer)?, Some(fraction)),
+        None => match ungroup(digits) {
+            Some(integer) => (integer, None),
+            None => {
+                let (integer, fraction) = digits.split_once('.')?;
+                (integer.to_string(), Some(fraction))
+            }
+        },
+    };
+
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if !is_digits(&integer) || (integer.is_empty() && fraction.is_none()) {
+        return None;
+    }
+    match fraction {
+        None => Some(format!("{sign}{integer}")),
+        Some(fraction) if !fraction.is_empty() && is_digits(fraction) => {
+            let integer = if integer.is_empty() { "0" } else { &integer };
+            Some(format!("{sign}{integer}.{fraction}"))
+        }
+        Some(_) => None,
+    }
+}
+
+/// Removes the dots grouping the thousands of `integer`, `None` if they are misplaced.
+fn ungroup(integer: &str) -> Option<String> {
+    let mut groups = integer.split('.');
+    let first = groups.next().unwrap_or_default();
+    let mut result = first.to_string();
+    for group in groups {
+        if first.is_empty() || first.len() > 3 || group.len() != 3 {
+            return None;
+        }
+        result.push_str(group);
+    }
+    Some(result)
+}
+
+/// Normalizes the [`NUMERIC_COLUMNS`] of `record` in place, leaving empty and
+/// unparseable values untouched, and returns the number of unparseable values per column.
+pub(crate) fn normalize_prescription(record: &mut PrescriptionRecord) -> UnparsedCounts {
+    let mut unparsed = UnparsedCounts::default();
+    let mut normalize = |value: &mut Option<String>, column: usize| {
+        let Some(text) = value.as_mut() else {
+            return;
+        };
+        if text.trim().is_empty() {
+            return;
+        }
+        match normalize_number(text) {
+            Some(number) => *text = number,
+            None => unparsed[column] += 1,
+        }
+    };
+
+    normalize(&mut record.contenido, 0);
+    if let Some(form) = &mut record.forms {
+        for ingredient in &mut form.active_ingredients {
+            normalize(&mut ingredient.dose, 1);
+            normalize(&mut ingredient.composition_dose, 2);
+            normalize(&mut ingredient.administration_dose, 3);
+            normalize(&mut ingredient.prescription_dose, 4);
+        }
+    }
+    for excipient in &mut record.excipients {
+        normalize(&mut excipient.quantity, 5);
+    }
+    unparsed
+}
+
+/// Non-zero counts of `unparsed` paired with the names in `columns`, in order.
+pub(crate) fn unparsed_by_column(
+    columns: &[&'static str],
+    unparsed: &[usize],
+) -> Vec<(&'static str, usize)> {
+    columns
+        .iter()
+        .copied()
+        .zip(unparsed.iter().copied())
+        .filter(|&(_, count)| count > 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{BoolParsing, deserialize_prescription};
+    use super::*;
+
+    #[test]
+    fn test_normalize_number() {
+        assert_eq!(normalize_number("2,5").as_deref(), Some("2.5"));
+        assert_eq!(normalize_number("1.000,75").as_deref(), Some("1000.75"));
+        assert_eq!(normalize_number("1.000").as_deref(), Some("1000"));
+        assert_eq!(normalize_number("12.345.678").as_deref(), Some("12345678"));
+        assert_eq!(normalize_number(" 500 ").as_deref(), Some("500"));
+        assert_eq!(normalize_number("0.5").as_deref(), Some("0.5"));
+        assert_eq!(normalize_number(",5").as_deref(), Some("0.5"));
+        assert_eq!(normalize_number("-2,25").as_deref(), Some("-2.25"));
+
+        for value in [
+            "",
+            "c.s.",
+            "c.s.p. 100 ml",
+            "1,2,3",
+            "1.00,5",
+            "2,",
+            "-",
+            "1 000",
+        ] {
+            assert_eq!(normalize_number(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn test_normalize_prescription() {
+        let xml = super::super::tests::prescription_xml(
+            "600000",
+            "<contenido>1.000,75</contenido>\
+             <excipientes><cod_excipiente>1</cod_excipiente><cantidad>c.s.</cantidad></excipientes>\
+             <excipientes><cod_excipiente>2</cod_excipiente><cantidad></cantidad></excipientes>",
+        );
+        let (record, _) = deserialize_prescription(xml.as_bytes(), BoolParsing::default());
+        let mut record = record.unwrap();
+
+        let unparsed = normalize_prescription(&mut record);
+        assert_eq!(record.contenido.as_deref(), Some("1000.75"));
+        assert_eq!(record.excipients[0].quantity.as_deref(), Some("c.s."));
+        assert_eq!(record.excipients[1].quantity, None);
+        assert_eq!(
+            unparsed_by_column(&NUMERIC_COLUMNS, &unparsed),
+            [("prescription_excipients.quantity", 1)]
+        );
+    }
+}
@@ -0,0 +1,471 @@
+//! CSV formatting options accepted by the `*_with_options` parser functions.
+
+use super::csv_row::RowSerializer;
+use super::report::ParseProgress;
+use super::schema::{Column, Columns, Table};
+use super::{PRESCRIPTIONS_CSV, PrescriptionRecord};
+use anyhow::{Context, Result};
+pub use csv::QuoteStyle;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::Write;
+#[cfg(feature = "validate-xml")]
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// How boolean columns are written to CSV
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BoolRepr {
+    /// `true` / `false`
+    #[default]
+    TrueFalse,
+    /// `1` / `0`
+    OneZero,
+    /// `T` / `F`
+    TF,
+}
+
+impl BoolRepr {
+    /// Text written for `value`
+    pub fn format(&self, value: bool) -> &'static str {
+        match (self, value) {
+            (BoolRepr::TrueFalse, true) => "true",
+            (BoolRepr::TrueFalse, false) => "false",
+            (BoolRepr::OneZero, true) => "1",
+            (BoolRepr::OneZero, false) => "0",
+            (BoolRepr::TF, true) => "T",
+            (BoolRepr::TF, false) => "F",
+        }
+    }
+}
+
+/// How the `0`/`1` flags of Prescripcion.xml are parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoolParsing {
+    /// Only exactly `0` or `1` is accepted
+    Strict,
+    /// Surrounding whitespace is ignored and `0`/`1`, `S`/`N` and `true`/`false` are
+    /// accepted in any case. Empty elements are read as `empty_value` and counted.
+    Lenient { empty_value: bool },
+}
+
+impl Default for BoolParsing {
+    fn default() -> Self {
+        BoolParsing::Lenient { empty_value: false }
+    }
+}
+
+impl BoolParsing {
+    /// Parses a flag, returning `Ok(None)` for an empty value in lenient mode.
+    pub(crate) fn parse(&self, value: &str) -> std::result::Result<Option<bool>, String> {
+        match self {
+            BoolParsing::Strict => match value {
+                "1" => Ok(Some(true)),
+                "0" => Ok(Some(false)),
+                _ => Err(format!("expected '0' or '1', got '{}'", value)),
+            },
+            BoolParsing::Lenient { .. } => {
+                let token = value.trim();
+                if token.is_empty() {
+                    Ok(None)
+                } else if ["1", "s", "true"]
+                    .iter()
+                    .any(|t| token.eq_ignore_ascii_case(t))
+                {
+                    Ok(Some(true))
+                } else if ["0", "n", "false"]
+                    .iter()
+                    .any(|t| token.eq_ignore_ascii_case(t))
+                {
+                    Ok(Some(false))
+                } else {
+                    Err(format!("expected 0/1, S/N or true/false, got '{}'", value))
+                }
+            }
+        }
+    }
+}
+
+/// What happens to records sharing the natural key of an earlier record
+///
+/// Dictionaries are keyed by `code`, prescriptions by `cod_nacion`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnDuplicate {
+    /// The first record is written, later ones are dropped
+    #[default]
+    KeepFirst,
+    /// The last record is written, earlier ones are dropped. Prescriptions are held in
+    /// memory until the whole file has been read.
+    KeepLast,
+    /// Parsing fails listing every duplicated key
+    Error,
+}
+
+/// What happens to records that fail to deserialize
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Parsing stops at the first malformed record
+    #[default]
+    Fail,
+    /// Malformed records are skipped and listed in
+    /// [`ParseReport::errors`](super::ParseReport::errors). Errors in the XML structure
+    /// itself, such as a truncated file, still stop parsing.
+    SkipAndReport,
+}
+
+/// What happens when the XML does not match the schema of
+/// [`ParserOptions::validate_first`]
+#[cfg(feature = "validate-xml")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnViolation {
+    /// Parsing fails listing the first violations
+    #[default]
+    Abort,
+    /// Violations are logged and listed in
+    /// [`ParseReport::xsd_violations`](super::ParseReport::xsd_violations), and the file
+    /// is parsed anyway
+    Warn,
+}
+
+/// Naming of the columns in CSV headers and generated DDL
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HeaderStyle {
+    /// Field names of the record structs (`number`, `code`, `description`)
+    #[default]
+    English,
+    /// Original AEMPS element names (`nroatc`, `codigoatc`, `descatc`)
+    Spanish,
+}
+
+/// Identifier of the prescription written first in the child CSV files
+///
+/// `cod_nacion` identifies a presentation, `nro_definitivo` the registered medicine
+/// and may be shared by several presentations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrescriptionKey {
+    /// `prescription_id` column holding the `cod_nacion`
+    #[default]
+    CodNacion,
+    /// `nro_definitivo` column
+    NroDefinitivo,
+    /// `prescription_id` followed by `nro_definitivo`
+    Both,
+}
+
+/// Callback receiving [`ParseProgress`] updates
+pub type ProgressCallback = Arc<dyn Fn(ParseProgress) + Send + Sync>;
+
+/// Formatting of the generated CSV files
+///
+/// The default matches the plain functions: comma delimited, fields quoted only when
+/// necessary, empty fields for missing values, `true`/`false` booleans, English
+/// header names, only the first record of each key, no malformed records, and
+/// numbers and dates kept as written by AEMPS.
+///
+/// ```
+/// use cima_rs::parser::ParserOptions;
+///
+/// // Postgres `COPY ... (FORMAT text)` style output
+/// let options = ParserOptions {
+///     delimiter: b';',
+///     null_repr: "\\N".to_string(),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone)]
+pub struct ParserOptions {
+    /// Field delimiter
+    pub delimiter: u8,
+    /// When fields are quoted
+    pub quote_style: QuoteStyle,
+    /// Text written for missing optional values
+    pub null_repr: String,
+    /// Text written for boolean values
+    pub bool_repr: BoolRepr,
+    /// Column names written in header rows
+    pub header_style: HeaderStyle,
+    /// How the prescription flags are read from the XML
+    pub bool_parsing: BoolParsing,
+    /// Handling of records with an already seen key
+    pub on_duplicate: OnDuplicate,
+    /// Handling of records that fail to deserialize
+    pub on_error: OnError,
+    /// Called every [`progress_interval`](Self::progress_interval) records and once
+    /// more when the parse completes
+    pub progress: Option<ProgressCallback>,
+    /// Number of records between two progress updates
+    pub progress_interval: usize,
+    /// Skip files whose source XML and outputs match the `manifest.json` of the output
+    /// directory, and record every parse in it
+    pub skip_unchanged: bool,
+    /// Threads deserializing and rendering prescriptions in
+    /// [`parse_prescription_xml_to_csvs`](super::parse_prescription_xml_to_csvs); `1`
+    /// parses on the calling thread. Output order is the same either way.
+    pub workers: usize,
+    /// Rewrite the Spanish formatted quantities listed in
+    /// [`NUMERIC_COLUMNS`](super::NUMERIC_COLUMNS) of the prescription CSVs with a dot
+    /// decimal separator and no grouping. Values that do not parse are kept as read
+    /// and counted in [`ParseReport::unparsed_numbers`](super::ParseReport::unparsed_numbers).
+    pub normalize_numbers: bool,
+    /// Rewrite the prescription dates listed in [`DATE_COLUMNS`](super::DATE_COLUMNS)
+    /// as `yyyy-mm-dd`. Values that do not parse are kept as read and counted in
+    /// [`ParseReport::unparsed_dates`](super::ParseReport::unparsed_dates).
+    pub normalize_dates: bool,
+    /// Prescription identifier(s) written first in the child CSV files. Parsing fails
+    /// on a prescription whose chosen identifier is empty.
+    pub prescription_key: PrescriptionKey,
+    /// Count the XML elements that no record field maps, such as elements added by
+    /// AEMPS after this version, into [`ParseReport::unknown_elements`](super::ParseReport::unknown_elements)
+    pub detect_unknown_elements: bool,
+    /// Split every CSV file written to a directory into parts of at most this many
+    /// rows, named after the file as `prescriptions.part0001.csv`, `part0002`, ...
+    /// Each part repeats the header of its file, if any, and the parts are listed in
+    /// [`ParseReport::parts`](super::ParseReport::parts). Functions writing to a
+    /// caller-provided writer and the `load_*` functions ignore it.
+    pub max_rows_per_file: Option<usize>,
+    /// Write the rows of every CSV file written to a directory in key order:
+    /// dictionaries by code, prescriptions by `cod_nacion` and the child files by
+    /// prescription key, then by their other columns. Numeric keys compare by value.
+    /// The rendered rows of one file are held in memory until it is complete.
+    /// Functions writing to a single caller-provided writer ignore it.
+    pub sort_output: bool,
+    /// Columns of `prescriptions.csv` to write, by their English name, in the order of
+    /// the full file. `cod_nacion`, and `nro_definitivo` when it is part of
+    /// [`prescription_key`](Self::prescription_key), are always written. Parsing fails
+    /// on an unknown name. The child files are unaffected, and the `load_*` functions
+    /// need every column.
+    pub prescription_columns: Option<Vec<String>>,
+    /// XSD schema the XML file is validated against before the path based
+    /// `*_to_csv*_with_options` functions parse it, see
+    /// [`validate_against_xsd`](super::validate_against_xsd)
+    #[cfg(feature = "validate-xml")]
+    pub validate_first: Option<PathBuf>,
+    /// Handling of XML files not matching [`validate_first`](Self::validate_first)
+    #[cfg(feature = "validate-xml")]
+    pub on_violation: OnViolation,
+}
+
+impl fmt::Debug for ParserOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("ParserOptions");
+        debug
+            .field("delimiter", &self.delimiter)
+            .field("quote_style", &self.quote_style)
+            .field("null_repr", &self.null_repr)
+            .field("bool_repr", &self.bool_repr)
+            .field("header_style", &self.header_style)
+            .field("bool_parsing", &self.bool_parsing)
+            .field("on_duplicate", &self.on_duplicate)
+            .field("on_error", &self.on_error)
+            .field(
+                "progress",
+                &self.progress.as_ref().map(|_| "Fn(ParseProgress)"),
+            )
+            .field("progress_interval", &self.progress_interval)
+            .field("skip_unchanged", &self.skip_unchanged)
+            .field("workers", &self.workers)
+            .field("normalize_numbers", &self.normalize_numbers)
+            .field("normalize_dates", &self.normalize_dates)
+            .field("prescription_key", &self.prescription_key)
+            .field("detect_unknown_elements", &self.detect_unknown_elements)
+            .field("max_rows_per_file", &self.max_rows_per_file)
+            .field("sort_output", &self.sort_output)
+            .field("prescription_columns", &self.prescription_columns);
+        #[cfg(feature = "validate-xml")]
+        debug
+            .field("validate_first", &self.validate_first)
+            .field("on_violation", &self.on_violation);
+        debug.finish()
+    }
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        ParserOptions {
+            delimiter: b',',
+            quote_style: QuoteStyle::Necessary,
+            null_repr: String::new(),
+            bool_repr: BoolRepr::TrueFalse,
+            header_style: HeaderStyle::English,
+            bool_parsing: BoolParsing::default(),
+            on_duplicate: OnDuplicate::KeepFirst,
+            on_error: OnError::Fail,
+            progress: None,
+            progress_interval: 1000,
+            skip_unchanged: false,
+            workers: num_cpus::get(),
+            normalize_numbers: false,
+            normalize_dates: false,
+            prescription_key: PrescriptionKey::CodNacion,
+            detect_unknown_elements: false,
+            max_rows_per_file: None,
+            sort_output: false,
+            prescription_columns: None,
+            #[cfg(feature = "validate-xml")]
+            validate_first: None,
+            #[cfg(feature = "validate-xml")]
+            on_violation: OnViolation::Abort,
+        }
+    }
+}
+
+impl ParserOptions {
+    /// Columns written to `prescriptions.csv`, see
+    /// [`prescription_columns`](Self::prescription_columns).
+    pub(crate) fn prescription_record_columns(&self) -> Result<Cow<'static, [Column]>> {
+        let columns = PrescriptionRecord::COLUMNS;
+        let Some(selected) = &self.prescription_columns else {
+            return Ok(Cow::Borrowed(columns));
+        };
+        for name in selected {
+            anyhow::ensure!(
+                columns.iter().any(|column| column.name == name),
+                "Unknown prescription column {name:?}, expected one of: {}",
+                columns
+                    .iter()
+                    .map(|column| column.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        let key: &[&str] = match self.prescription_key {
+            PrescriptionKey::CodNacion => &["cod_nacion"],
+            PrescriptionKey::NroDefinitivo | PrescriptionKey::Both => {
+                &["cod_nacion", "nro_definitivo"]
+            }
+        };
+        Ok(Cow::Owned(
+            columns
+                .iter()
+                .filter(|column| {
+                    key.contains(&column.name) || selected.iter().any(|name| name == column.name)
+                })
+                .copied()
+                .collect(),
+        ))
+    }
+
+    /// Columns written to the file of `table` with these options.
+    pub(crate) fn table_columns(&self, table: &Table) -> Result<Cow<'static, [Column]>> {
+        if table.file_name == PRESCRIPTIONS_CSV {
+            self.prescription_record_columns()
+        } else {
+            Ok(table.columns_with_key(self.prescription_key))
+        }
+    }
+
+    /// Creates a CSV writer using the configured delimiter and quoting.
+    pub fn csv_writer<W: Write>(&self, writer: W) -> csv::Writer<W> {
+        csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .quote_style(self.quote_style)
+            .from_writer(writer)
+    }
+
+    /// Whether a progress update is due after `records` records.
+    pub(crate) fn progress_due(&self, records: usize) -> bool {
+        self.progress.is_some() && records.is_multiple_of(self.progress_interval.max(1))
+    }
+
+    /// Passes `progress` to the callback, if any.
+    pub(crate) fn report_progress(&self, progress: ParseProgress) {
+        if let Some(callback) = &self.progress {
+            callback(progress);
+        }
+    }
+
+    /// Text for an optional value, [`null_repr`](Self::null_repr) when missing.
+    pub(crate) fn field<'a>(&'a self, value: Option<&'a str>) -> &'a str {
+        value.unwrap_or(&self.null_repr)
+    }
+
+    /// Writes the names of `columns` in the configured [`HeaderStyle`] as a header row.
+    pub(crate) fn write_header<W: Write>(
+        &self,
+        wtr: &mut csv::Writer<W>,
+        columns: &[Column],
+    ) -> Result<()> {
+        wtr.write_record(
+            columns
+                .iter()
+                .map(|column| column.header(self.header_style)),
+        )?;
+        Ok(())
+    }
+
+    /// Writes `row` with one field per column, rendering nulls and booleans as configured.
+    pub(crate) fn write_row<W: Write, T: Serialize>(
+        &self,
+        wtr: &mut csv::Writer<W>,
+        columns: &[Column],
+        row: &T,
+    ) -> Result<()> {
+        row.serialize(&mut RowSerializer::new(wtr, self, columns))
+            .context("Failed to serialize record")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{LaboratoryRecord, schema::Columns};
+    use super::*;
+
+    #[test]
+    fn test_write_row_null_and_bool_repr() {
+        let options = ParserOptions {
+            delimiter: b';',
+            null_repr: "\\N".to_string(),
+            bool_repr: BoolRepr::OneZero,
+            ..Default::default()
+        };
+        let record = LaboratoryRecord {
+            code: "L02".to_string(),
+            name: "LAB; NAME".to_string(),
+            address: None,
+            zip: Some(String::new()),
+            city: None,
+            vat: None,
+        };
+
+        let mut wtr = options.csv_writer(Vec::new());
+        options
+            .write_header(&mut wtr, LaboratoryRecord::COLUMNS)
+            .unwrap();
+        options
+            .write_row(&mut wtr, LaboratoryRecord::COLUMNS, &record)
+            .unwrap();
+        let output = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+
+        assert_eq!(
+            output,
+            "code;name;address;zip;city;vat\nL02;\"LAB; NAME\";\\N;;\\N;\\N\n"
+        );
+        assert_eq!(options.bool_repr.format(true), "1");
+    }
+
+    #[test]
+    fn test_lenient_bool_parsing() {
+        let lenient = BoolParsing::default();
+        for token in ["1", "S", "s", "true", "TRUE", " 1 ", "\n\tS\n"] {
+            assert_eq!(lenient.parse(token), Ok(Some(true)), "{token:?}");
+        }
+        for token in ["0", "N", "n", "false", "False", " 0"] {
+            assert_eq!(lenient.parse(token), Ok(Some(false)), "{token:?}");
+        }
+        assert_eq!(lenient.parse(""), Ok(None));
+        assert_eq!(lenient.parse("   "), Ok(None));
+        assert!(lenient.parse("2").is_err());
+        assert!(lenient.parse("yes").is_err());
+    }
+
+    #[test]
+    fn test_strict_bool_parsing() {
+        let strict = BoolParsing::Strict;
+        assert_eq!(strict.parse("1"), Ok(Some(true)));
+        assert_eq!(strict.parse("0"), Ok(Some(false)));
+        for token in ["", " 1", "S", "N", "true", "false"] {
+            assert!(strict.parse(token).is_err(), "{token:?}");
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! Parquet output for the nomenclator parsers (requires the `parquet` feature).
+
+use super::arrow::RecordBatchBuilder;
+use super::rows::PrescriptionRows;
+use super::schema::{
+    Column, Columns, PRESCRIPTION_ACTIVE_INGREDIENT_COLUMNS, PRESCRIPTION_ADMIN_ROUTE_COLUMNS,
+    PRESCRIPTION_ATC_COLUMNS, PRESCRIPTION_ATC_DUPLICATE_COLUMNS, PRESCRIPTION_EXCIPIENT_COLUMNS,
+    PRESCRIPTION_FORM_COLUMNS, PRESCRIPTION_NOTE_COLUMNS, PRESCRIPTION_SUPPLY_PROBLEM_COLUMNS,
+};
+use super::{
+    PRESCRIPTION_ACTIVE_INGREDIENTS_CSV, PRESCRIPTION_ADMIN_ROUTES_CSV, PRESCRIPTION_ATC_CSV,
+    PRESCRIPTION_ATC_DUPLICATES_CSV, PRESCRIPTION_EXCIPIENTS_CSV, PRESCRIPTION_FORMS_CSV,
+    PRESCRIPTION_NOTES_CSV, PRESCRIPTION_SUPPLY_PROBLEMS_CSV, PRESCRIPTIONS_CSV,
+    PrescriptionReader, PrescriptionRecord, open_xml,
+};
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Number of rows buffered before a record batch is written
+const BATCH_SIZE: usize = 8192;
+
+/// Parquet file name for one of the prescription CSV file names.
+fn parquet_file_name(csv_name: &str) -> PathBuf {
+    Path::new(csv_name).with_extension("parquet")
+}
+
+/// Buffers serialized rows and writes them to a Parquet file in batches
+struct TableWriter {
+    path: PathBuf,
+    writer: ArrowWriter<File>,
+    batch: RecordBatchBuilder,
+}
+
+impl TableWriter {
+    fn create(path: &Path, columns: &'static [Column]) -> Result<Self> {
+        let batch = RecordBatchBuilder::new(columns);
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer = ArrowWriter::try_new(file, batch.schema(), Some(props))?;
+
+        Ok(TableWriter {
+            path: path.to_path_buf(),
+            writer,
+            batch,
+        })
+    }
+
+    fn write<S: Serialize>(&mut self, row: &S) -> Result<()> {
+        self.batch
+            .append(row)
+            .with_context(|| format!("Failed to encode row for {}", self.path.display()))?;
+        if self.batch.len() >= BATCH_SIZE {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    fn flush_batch(&mut self) -> Result<()> {
+        if self.batch.len() > 0 {
+            self.writer.write(&self.batch.finish()?)?;
+        }
+        Ok(())
+    }
+
+    fn close(mut self) -> Result<()> {
+        self.flush_batch()?;
+        self.writer
+            .close()
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// Writes dictionary records to a Parquet file typed after [`Columns::COLUMNS`].
+pub fn write_records_to_parquet<T, P>(records: &[T], parquet_path: P) -> Result<()>
+where
+    T: Serialize + Columns,
+    P: AsRef<Path>,
+{
+    let mut writer = TableWriter::create(parquet_path.as_ref(), T::COLUMNS)?;
+    for record in records {
+        writer.write(record)?;
+    }
+    writer.close()
+}
+
+macro_rules! impl_parquet_writer {
+    ($name:literal, $parquet_fn:ident, $parse_fn:ident) => {
+        #[doc = concat!("Parses the ", $name, " XML file and writes its content to a Parquet file.")]
+        pub fn $parquet_fn<P: AsRef<Path>>(xml_path: P, parquet_path: P) -> Result<()> {
+            let records = super::$parse_fn(xml_path)?;
+            write_records_to_parquet(&records, parquet_path)
+        }
+    };
+}
+
+impl_parquet_writer!("ATC", parse_atc_xml_to_parquet, parse_atc_xml);
+impl_parquet_writer!("DCP", parse_dcp_xml_to_parquet, parse_dcp_xml);
+impl_parquet_writer!("DCPF", parse_dcpf_xml_to_parquet, parse_dcpf_xml);
+impl_parquet_writer!("DCSA", parse_dcsa_xml_to_parquet, parse_dcsa_xml);
+impl_parquet_writer!("Envases", parse_envases_xml_to_parquet, parse_envases_xml);
+impl_parquet_writer!(
+    "Excipientes",
+    parse_excipientes_xml_to_parquet,
+    parse_excipientes_xml
+);
+impl_parquet_writer!(
+    "Forma Farmaceutica",
+    parse_forma_farmaceutica_xml_to_parquet,
+    parse_forma_farmaceutica_xml
+);
+impl_parquet_writer!(
+    "Forma Farmaceutica Simplificada",
+    parse_forma_farmaceutica_simplificada_xml_to_parquet,
+    parse_forma_farmaceutica_simplificada_xml
+);
+impl_parquet_writer!(
+    "Laboratorio",
+    parse_laboratorio_xml_to_parquet,
+    parse_laboratorio_xml
+);
+impl_parquet_writer!(
+    "Principio Activo",
+    parse_principio_activo_xml_to_parquet,
+    parse_principio_activo_xml
+);
+impl_parquet_writer!(
+    "Situacion Registro",
+    parse_situacion_registro_xml_to_parquet,
+    parse_situacion_registro_xml
+);
+impl_parquet_writer!(
+    "Unidad Contenido",
+    parse_unidad_contenido_xml_to_parquet,
+    parse_unidad_contenido_xml
+);
+impl_parquet_writer!(
+    "Via Administracion",
+    parse_via_administracion_xml_to_parquet,
+    parse_via_administracion_xml
+);
+
+/// Parses the Prescription XML file and writes the normalized tables as Parquet files.
+///
+/// Produces the same nine tables as
+/// [`parse_prescription_xml_to_csvs`](super::parse_prescription_xml_to_csvs), named
+/// after the CSV files with a `.parquet` extension. Boolean flags are stored as
+/// booleans and missing optional values as nulls.
+pub fn parse_prescription_xml_to_parquet<P: AsRef<Path>>(xml_path: P, output_dir: P) -> Result<()> {
+    let output_dir = output_dir.as_ref();
+    let records = PrescriptionReader::new(open_xml(xml_path)?);
+    let create = |name: &str, columns: &'static [Column]| {
+        TableWriter::create(&output_dir.join(parquet_file_name(name)), columns)
+    };
+
+    let mut wtr_main = create(PRESCRIPTIONS_CSV, PrescriptionRecord::COLUMNS)?;
+    let mut wtr_forms = create(PRESCRIPTION_FORMS_CSV, PRESCRIPTION_FORM_COLUMNS)?;
+    let mut wtr_ingredients = create(
+        PRESCRIPTION_ACTIVE_INGREDIENTS_CSV,
+        PRESCRIPTION_ACTIVE_INGREDIENT_COLUMNS,
+    )?;
+    let mut wtr_routes = create(
+        PRESCRIPTION_ADMIN_ROUTES_CSV,
+        PRESCRIPTION_ADMIN_ROUTE_COLUMNS,
+    )?;
+    let mut wtr_atc = create(PRESCRIPTION_ATC_CSV, PRESCRIPTION_ATC_COLUMNS)?;
+    let mut wtr_atc_duplicates = create(
+        PRESCRIPTION_ATC_DUPLICATES_CSV,
+        PRESCRIPTION_ATC_DUPLICATE_COLUMNS,
+    )?;
+    let mut wtr_supply = create(
+        PRESCRIPTION_SUPPLY_PROBLEMS_CSV,
+        PRESCRIPTION_SUPPLY_PROBLEM_COLUMNS,
+    )?;
+    let mut wtr_excipients = create(PRESCRIPTION_EXCIPIENTS_CSV, PRESCRIPTION_EXCIPIENT_COLUMNS)?;
+    let mut wtr_notes = create(PRESCRIPTION_NOTES_CSV, PRESCRIPTION_NOTE_COLUMNS)?;
+
+    for record in records {
+        let record = record?;
+        wtr_main.write(&record)?;
+
+        let rows = PrescriptionRows::from(&record);
+        if let Some(form) = &rows.forms {
+            wtr_forms.write(form)?;
+        }
+        for row in &rows.active_ingredients {
+            wtr_ingredients.write(row)?;
+        }
+        for row in &rows.admin_routes {
+            wtr_routes.write(row)?;
+        }
+        for row in &rows.atc_codes {
+            wtr_atc.write(row)?;
+        }
+        for row in &rows.atc_duplicates {
+            wtr_atc_duplicates.write(row)?;
+        }
+        for row in &rows.supply_problems {
+            wtr_supply.write(row)?;
+        }
+        for row in &rows.excipients {
+            wtr_excipients.write(row)?;
+        }
+        for row in &rows.notes {
+            wtr_notes.write(row)?;
+        }
+    }
+
+    wtr_main.close()?;
+    wtr_forms.close()?;
+    wtr_ingredients.close()?;
+    wtr_routes.close()?;
+    wtr_atc.close()?;
+    wtr_atc_duplicates.close()?;
+    wtr_supply.close()?;
+    wtr_excipients.close()?;
+    wtr_notes.close()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::prescription_xml;
+    use super::super::{PRESCRIPTION_CSV_FILES, parse_prescription_xml_to_csvs};
+    use super::*;
+    use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use arrow_array::cast::AsArray;
+    use arrow_array::{Array, RecordBatch};
+    use arrow_schema::DataType;
+    use tempfile::TempDir;
+
+    fn read_parquet(path: &Path) -> RecordBatch {
+        let file = File::open(path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        // Test fixtures always fit in a single batch
+        assert!(batches.len() <= 1);
+        batches
+            .pop()
+            .unwrap_or_else(|| RecordBatch::new_empty(super::super::arrow::arrow_schema(&[])))
+    }
+
+    fn read_csv(path: &Path, has_headers: bool) -> Vec<csv::StringRecord> {
+        csv::ReaderBuilder::new()
+            .has_headers(has_headers)
+            .from_path(path)
+            .unwrap()
+            .records()
+            .map(|record| record.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_prescription_to_parquet_matches_csv() {
+        let dir = TempDir::new().unwrap();
+        let xml_path = dir.path().join("Prescripcion.xml");
+        std::fs::write(
+            &xml_path,
+            format!(
+                "<aemps_prescripcion>{}{}</aemps_prescripcion>",
+                prescription_xml(
+                    "600000",
+                    r#"<formasfarmaceuticas>
+                        <cod_forfar>10</cod_forfar>
+                        <viasadministracion><cod_via_admin>48</cod_via_admin></viasadministracion>
+                    </formasfarmaceuticas>
+                    <atc>
+                        <cod_atc>J01CR02</cod_atc>
+                        <duplicidades><atc_duplicidad>J01CA04</atc_duplicidad></duplicidades>
+                    </atc>"#
+                ),
+                prescription_xml("600001", "<atc><cod_atc>N02BE01</cod_atc></atc>"),
+            ),
+        )
+        .unwrap();
+
+        let csv_dir = dir.path().join("csv");
+        let parquet_dir = dir.path().join("parquet");
+        std::fs::create_dir_all(&csv_dir).unwrap();
+        std::fs::create_dir_all(&parquet_dir).unwrap();
+        parse_prescription_xml_to_csvs(&xml_path, &csv_dir).unwrap();
+        parse_prescription_xml_to_parquet(&xml_path, &parquet_dir).unwrap();
+
+        for name in PRESCRIPTION_CSV_FILES {
+            assert!(parquet_dir.join(parquet_file_name(name)).exists(), "{name}");
+        }
+
+        // Main table: typed columns with the same values as the CSV
+        let batch = read_parquet(&parquet_dir.join("prescriptions.parquet"));
+        let csv_rows = read_csv(&csv_dir.join(PRESCRIPTIONS_CSV), true);
+        assert_eq!(batch.num_rows(), csv_rows.len());
+        assert_eq!(
+            batch
+                .schema()
+                .field_with_name("sw_receta")
+                .unwrap()
+                .data_type(),
+            &DataType::Boolean
+        );
+        assert_eq!(
+            batch
+                .schema()
+                .field_with_name("cod_nacion")
+                .unwrap()
+                .data_type(),
+            &DataType::Utf8
+        );
+
+        let cod_nacion = batch
+            .column_by_name("cod_nacion")
+            .unwrap()
+            .as_string::<i32>();
+        let sw_receta = batch.column_by_name("sw_receta").unwrap().as_boolean();
+        let url_fictec = batch.column_by_name("url_fictec").unwrap();
+        for (i, row) in csv_rows.iter().enumerate() {
+            assert_eq!(cod_nacion.value(i), &row[0]);
+            assert_eq!(sw_receta.value(i).to_string(), row[18]);
+            assert!(url_fictec.is_null(i));
+        }
+
+        // Child table rows match the header-less CSV
+        let batch = read_parquet(&parquet_dir.join("prescription_atc.parquet"));
+        let csv_rows = read_csv(&csv_dir.join(PRESCRIPTION_ATC_CSV), false);
+        assert_eq!(batch.num_rows(), 2);
+        let atc_codes = batch.column_by_name("atc_code").unwrap().as_string::<i32>();
+        for (i, row) in csv_rows.iter().enumerate() {
+            assert_eq!(atc_codes.value(i), &row[1]);
+        }
+
+        let batch = read_parquet(&parquet_dir.join("prescription_forms.parquet"));
+        assert_eq!(batch.num_rows(), 1);
+        assert!(
+            batch
+                .column_by_name("simplified_form_code")
+                .unwrap()
+                .is_null(0)
+        );
+
+        let batch = read_parquet(&parquet_dir.join("prescription_supply_problems.parquet"));
+        assert_eq!(batch.num_rows(), 0);
+    }
+
+    #[test]
+    fn test_parse_atc_to_parquet() {
+        let dir = TempDir::new().unwrap();
+        let xml_path = dir.path().join("DICCIONARIO_ATC.xml");
+        std::fs::write(
+            &xml_path,
+            r#"<aemps_prescripcion_atc>
+                <atc><nroatc>1</nroatc><codigoatc>A01</codigoatc><descatc>A01 - DIGESTIVE</descatc></atc>
+                <atc><nroatc>2</nroatc><codigoatc>B01</codigoatc><descatc>B01 - BLOOD</descatc></atc>
+            </aemps_prescripcion_atc>"#,
+        )
+        .unwrap();
+        let parquet_path = dir.path().join("atc.parquet");
+
+        parse_atc_xml_to_parquet(&xml_path, &parquet_path).unwrap();
+
+        let batch = read_parquet(&parquet_path);
+        assert_eq!(batch.num_rows(), 2);
+        let numbers = batch
+            .column_by_name("number")
+            .unwrap()
+            .as_primitive::<arrow_array::types::Int32Type>();
+        assert_eq!(numbers.value(1), 2);
+        let descriptions = batch
+            .column_by_name("description")
+            .unwrap()
+            .as_string::<i32>();
+        assert_eq!(descriptions.value(0), "DIGESTIVE");
+    }
+}
@@ -0,0 +1,519 @@
+//! Splitting of CSV output into numbered parts, see
+//! [`ParserOptions::max_rows_per_file`](super::ParserOptions::max_rows_per_file), and
+//! sorting of its rows, see [`ParserOptions::sort_output`](super::ParserOptions::sort_output).
+
+use super::options::ParserOptions;
+use super::report::ParseReport;
+use super::schema::{self, PRESCRIPTION_TABLES};
+use super::sorting::{self, SortedRows};
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Capacity of the buffer of each output file, large as rows are written in many
+/// small pieces
+const WRITE_BUFFER_SIZE: usize = 32 * 1024;
+
+/// Name of part `part`, starting at 1, of the file `name`: `prescriptions.part0001.csv`
+pub fn part_file_name(name: &str, part: usize) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{stem}.part{part:04}.{extension}"),
+        None => format!("{name}.part{part:04}"),
+    }
+}
+
+/// Whether `candidate` is a part of the file `name`, returning its number.
+pub(crate) fn part_number(candidate: &str, name: &str) -> Option<usize> {
+    let (prefix, suffix) = match name.rsplit_once('.') {
+        Some((stem, extension)) => (format!("{stem}.part"), format!(".{extension}")),
+        None => (format!("{name}.part"), String::new()),
+    };
+    let number = candidate.strip_prefix(&prefix)?.strip_suffix(&suffix)?;
+    (number.len() >= 4 && number.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| number.parse().ok())
+        .flatten()
+}
+
+/// Parts of the file `name` present in `dir`, in order.
+pub(crate) fn existing_parts(dir: &Path, name: &str) -> Result<Vec<String>> {
+    let mut parts = Vec::new();
+    let entries = fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    for entry in entries {
+        let file_name = entry?.file_name().to_string_lossy().into_owned();
+        if let Some(number) = part_number(&file_name, name) {
+            parts.push((number, file_name));
+        }
+    }
+    parts.sort();
+    Ok(parts.into_iter().map(|(_, file_name)| file_name).collect())
+}
+
+/// CSV output written to one file, or rolled over to numbered parts of at most
+/// [`ParserOptions::max_rows_per_file`] rows, each starting with the header of the file.
+/// With [`ParserOptions::sort_output`], rows are held until [`finish`](Self::finish)
+/// and written in key order.
+///
+/// Row boundaries are found by scanning the written bytes for line feeds outside
+/// double quotes, which is how the CSV writers quote fields.
+pub(crate) struct PartWriter<'a, W: Write, F> {
+    name: &'a str,
+    make_writer: &'a RefCell<F>,
+    writer: BufWriter<W>,
+    max_rows: Option<usize>,
+    /// Whether the first row is a header, until it is complete
+    reading_header: bool,
+    header: Vec<u8>,
+    in_quotes: bool,
+    at_row_start: bool,
+    /// Rows of the current part, excluding the header
+    rows: usize,
+    /// Finished parts with their rows
+    parts: Vec<(String, usize)>,
+    /// Rows held for sorting
+    sorted: Option<SortedRows>,
+}
+
+impl<'a, W, F> PartWriter<'a, W, F>
+where
+    W: Write,
+    F: FnMut(&str) -> Result<W>,
+{
+    /// Creates the file `name` with `make_writer`, or its first part when splitting.
+    /// Rows are sorted on the `sort_columns` when sorting.
+    pub(crate) fn new(
+        name: &'a str,
+        has_header: bool,
+        sort_columns: &[usize],
+        make_writer: &'a RefCell<F>,
+        options: &ParserOptions,
+    ) -> Result<Self> {
+        let max_rows = options.max_rows_per_file.map(|max| max.max(1));
+        let first = match max_rows {
+            Some(_) => part_file_name(name, 1),
+            None => name.to_string(),
+        };
+        let writer = (make_writer.borrow_mut())(&first)?;
+        Ok(PartWriter {
+            name,
+            make_writer,
+            writer: BufWriter::with_capacity(WRITE_BUFFER_SIZE, writer),
+            max_rows,
+            reading_header: has_header,
+            header: Vec::new(),
+            in_quotes: false,
+            at_row_start: true,
+            rows: 0,
+            parts: Vec::new(),
+            sorted: options
+                .sort_output
+                .then(|| SortedRows::new(sort_columns, options.delimiter)),
+        })
+    }
+
+    fn next_part(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let part = self.parts.len() + 1;
+        self.parts.push((
+            part_file_name(self.name, part),
+            std::mem::take(&mut self.rows),
+        ));
+        let writer = (self.make_writer.borrow_mut())(&part_file_name(self.name, part + 1))
+            .map_err(|e| io::Error::other(format!("{e:#}")))?;
+        self.writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, writer);
+        self.writer.write_all(&self.header)
+    }
+
+    /// Writes `bytes`, which end a row when `ends_row` is set.
+    fn write_segment(&mut self, bytes: &[u8], ends_row: bool, max_rows: usize) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        if !self.reading_header
+            && let Some(sorted) = &mut self.sorted
+        {
+            sorted.push(bytes, ends_row);
+            return Ok(());
+        }
+        if self.at_row_start && !self.reading_header && self.rows == max_rows {
+            self.next_part()?;
+        }
+        self.at_row_start = ends_row;
+        if self.reading_header {
+            self.header.extend_from_slice(bytes);
+        }
+        self.writer.write_all(bytes)?;
+        if ends_row {
+            if self.reading_header {
+                self.reading_header = false;
+            } else {
+                self.rows += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the output and returns the parts written with their rows, empty when
+    /// not splitting.
+    pub(crate) fn finish(mut self) -> Result<Vec<(String, usize)>> {
+        if let Some(sorted) = self.sorted.take() {
+            let (bytes, rows) = sorted.into_sorted();
+            let max_rows = self.max_rows.unwrap_or(usize::MAX);
+            for row in rows {
+                self.write_segment(&bytes[row], true, max_rows)
+                    .with_context(|| format!("Failed to write {}", self.name))?;
+            }
+        }
+        self.writer
+            .flush()
+            .with_context(|| format!("Failed to write {}", self.name))?;
+        if self.max_rows.is_some() {
+            let part = self.parts.len() + 1;
+            self.parts
+                .push((part_file_name(self.name, part), self.rows));
+        }
+        Ok(self.parts)
+    }
+}
+
+impl<W, F> Write for PartWriter<'_, W, F>
+where
+    W: Write,
+    F: FnMut(&str) -> Result<W>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let max_rows = match (self.max_rows, &self.sorted) {
+            (Some(max_rows), _) => Some(max_rows),
+            (None, Some(_)) => Some(usize::MAX),
+            (None, None) => None,
+        };
+        let Some(max_rows) = max_rows else {
+            return self.writer.write(buf);
+        };
+        let mut start = 0;
+        for (position, byte) in buf.iter().enumerate() {
+            match byte {
+                b'"' => self.in_quotes = !self.in_quotes,
+                b'\n' if !self.in_quotes => {
+                    self.write_segment(&buf[start..=position], true, max_rows)?;
+                    start = position + 1;
+                }
+                _ => {}
+            }
+        }
+        self.write_segment(&buf[start..], false, max_rows)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Runs `write` over the CSV file at `path`, split as configured in `options`, and
+/// adds the parts written to the report.
+pub(crate) fn write_csv_file(
+    path: &Path,
+    options: &ParserOptions,
+    sort_columns: &[usize],
+    write: impl FnOnce(&mut dyn Write) -> Result<ParseReport>,
+) -> Result<ParseReport> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file path", path.display()))?
+        .to_string_lossy();
+    let make_writer = RefCell::new(|name: &str| {
+        let path = dir.join(name);
+        File::create(&path).with_context(|| format!("Failed to create {}", path.display()))
+    });
+    let mut writer = PartWriter::new(&name, true, sort_columns, &make_writer, options)?;
+    let mut report = write(&mut writer)?;
+    report.parts = writer.finish()?;
+    Ok(report)
+}
+
+/// Runs `write` over writers for the prescription CSV files created by `make_writer`,
+/// split and sorted as configured in `options`, and adds the parts written to the report.
+pub(crate) fn write_prescription_files<W, F>(
+    make_writer: F,
+    options: &ParserOptions,
+    write: impl FnOnce(&mut [PartWriter<'_, W, F>]) -> Result<ParseReport>,
+) -> Result<ParseReport>
+where
+    W: Write,
+    F: FnMut(&str) -> Result<W>,
+{
+    let make_writer = RefCell::new(make_writer);
+    let mut writers = Vec::with_capacity(PRESCRIPTION_TABLES.len());
+    let key_columns = schema::prescription_key_columns(options.prescription_key).len();
+    for table in &PRESCRIPTION_TABLES {
+        let sort_columns = match table.primary_key {
+            Some(key) => sorting::key_columns(&options.table_columns(table)?, &[key]),
+            None => (0..key_columns).collect(),
+        };
+        writers.push(PartWriter::new(
+            table.file_name,
+            table.has_header,
+            &sort_columns,
+            &make_writer,
+            options,
+        )?);
+    }
+    let mut report = write(&mut writers)?;
+    for writer in writers {
+        report.parts.extend(writer.finish()?);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        PRESCRIPTION_ATC_CSV, PRESCRIPTION_CSV_FILES, PRESCRIPTIONS_CSV,
+        generate_postgres_schema_with_options, parse_atc_xml_to_csv_with_options,
+        parse_prescription_xml_to_csvs_with_options,
+    };
+    use super::*;
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    fn split(
+        name: &str,
+        has_header: bool,
+        max_rows: Option<usize>,
+        chunks: &[&str],
+    ) -> (BTreeMap<String, String>, Vec<(String, usize)>) {
+        let files = RefCell::new(BTreeMap::new());
+        let make_writer = RefCell::new(|name: &str| {
+            files.borrow_mut().insert(name.to_string(), Vec::new());
+            Ok(SharedFile(name.to_string(), &files))
+        });
+        let options = ParserOptions {
+            max_rows_per_file: max_rows,
+            ..Default::default()
+        };
+        let mut writer = PartWriter::new(name, has_header, &[0], &make_writer, &options).unwrap();
+        for chunk in chunks {
+            writer.write_all(chunk.as_bytes()).unwrap();
+        }
+        let parts = writer.finish().unwrap();
+        let files = files
+            .into_inner()
+            .into_iter()
+            .map(|(name, bytes)| (name, String::from_utf8(bytes).unwrap()))
+            .collect();
+        (files, parts)
+    }
+
+    /// Writer appending to an entry of a shared map of files
+    struct SharedFile<'a>(String, &'a RefCell<BTreeMap<String, Vec<u8>>>);
+
+    impl Write for SharedFile<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.borrow_mut().get_mut(&self.0).unwrap().extend(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_part_file_name() {
+        assert_eq!(
+            part_file_name("prescriptions.csv", 1),
+            "prescriptions.part0001.csv"
+        );
+        assert_eq!(part_file_name("atc", 12), "atc.part0012");
+        assert_eq!(part_number("atc.part0012.csv", "atc.csv"), Some(12));
+        assert_eq!(part_number("atc.part12.csv", "atc.csv"), None);
+        assert_eq!(part_number("atc_x.part0001.csv", "atc.csv"), None);
+    }
+
+    #[test]
+    fn test_split_rows_across_writes() {
+        // Rows cut across writes, and a quoted field holding a line feed
+        let (files, parts) = split(
+            "t.csv",
+            true,
+            Some(2),
+            &["a,b\n1,x", "\n2,\"y\nz\"\n3", ",w\n4,v\n5,u\n"],
+        );
+        assert_eq!(
+            files.into_iter().collect::<Vec<_>>(),
+            [
+                (
+                    "t.part0001.csv".to_string(),
+                    "a,b\n1,x\n2,\"y\nz\"\n".to_string()
+                ),
+                ("t.part0002.csv".to_string(), "a,b\n3,w\n4,v\n".to_string()),
+                ("t.part0003.csv".to_string(), "a,b\n5,u\n".to_string()),
+            ]
+        );
+        assert_eq!(
+            parts,
+            [
+                ("t.part0001.csv".to_string(), 2),
+                ("t.part0002.csv".to_string(), 2),
+                ("t.part0003.csv".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_split() {
+        let (files, parts) = split("t.csv", false, None, &["1\n2\n3\n"]);
+        assert_eq!(files["t.csv"], "1\n2\n3\n");
+        assert!(parts.is_empty());
+
+        // Empty output still has a first part, with the header when there is one
+        let (files, parts) = split("t.csv", false, Some(2), &[]);
+        assert_eq!(files["t.part0001.csv"], "");
+        assert_eq!(parts, [("t.part0001.csv".to_string(), 0)]);
+    }
+
+    fn fixture(dir: &str, name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(dir)
+            .join(name)
+    }
+
+    fn splitting(max_rows: usize) -> ParserOptions {
+        ParserOptions {
+            max_rows_per_file: Some(max_rows),
+            ..Default::default()
+        }
+    }
+
+    fn read(dir: &Path, name: &str) -> String {
+        fs::read_to_string(dir.join(name)).unwrap()
+    }
+
+    #[test]
+    fn test_split_prescription_csvs() {
+        let xml_path = fixture("delta", "Prescripcion.xml");
+        for workers in [1, 4] {
+            let dir = TempDir::new().unwrap();
+            let options = ParserOptions {
+                workers,
+                ..splitting(2)
+            };
+            let report = parse_prescription_xml_to_csvs_with_options(
+                xml_path.as_path(),
+                dir.path(),
+                &options,
+            )
+            .unwrap();
+            assert_eq!(report.records, 3);
+
+            let prescriptions = part_file_name(PRESCRIPTIONS_CSV, 1);
+            let first = read(dir.path(), &prescriptions);
+            let header = first.lines().next().unwrap();
+            assert!(header.starts_with("cod_nacion,"), "{header}");
+            assert_eq!(first.lines().count(), 3);
+            assert!(first.lines().nth(2).unwrap().starts_with("600001,"));
+            let second = read(dir.path(), &part_file_name(PRESCRIPTIONS_CSV, 2));
+            assert_eq!(second.lines().collect::<Vec<_>>()[0], header);
+            assert!(second.lines().nth(1).unwrap().starts_with("600002,"));
+            assert!(!dir.path().join(PRESCRIPTIONS_CSV).exists());
+
+            // Child files have no header and roll over on their own rows
+            assert_eq!(
+                read(dir.path(), &part_file_name(PRESCRIPTION_ATC_CSV, 1)),
+                "600000,N02BE01\n600001,J01CA04\n"
+            );
+            assert_eq!(
+                read(dir.path(), &part_file_name(PRESCRIPTION_ATC_CSV, 2)),
+                "600002,M01AE01\n"
+            );
+
+            // Every part of every file is listed, in order
+            let names: Vec<_> = report.parts.iter().map(|(name, _)| name.as_str()).collect();
+            assert_eq!(names[0], "prescriptions.part0001.csv");
+            assert_eq!(names[1], "prescriptions.part0002.csv");
+            for name in PRESCRIPTION_CSV_FILES {
+                assert!(names.contains(&part_file_name(name, 1).as_str()), "{name}");
+            }
+            for (name, rows) in &report.parts {
+                let content = read(dir.path(), name);
+                let header = usize::from(name.starts_with("prescriptions."));
+                assert_eq!(content.lines().count(), rows + header, "{name}");
+            }
+            assert!(
+                report
+                    .parts
+                    .contains(&("prescription_atc.part0002.csv".to_string(), 1))
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_dictionary_csv_and_import_script() {
+        let dir = TempDir::new().unwrap();
+        let options = splitting(1);
+        let report = parse_atc_xml_to_csv_with_options(
+            fixture("nomenclator", "DICCIONARIO_ATC.xml"),
+            dir.path().join("atc.csv"),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            report.parts,
+            [
+                ("atc.part0001.csv".to_string(), 1),
+                ("atc.part0002.csv".to_string(), 1),
+            ]
+        );
+        assert_eq!(
+            read(dir.path(), "atc.part0001.csv"),
+            "number,code,description\n1,A,TRACTO ALIMENTARIO Y METABOLISMO\n"
+        );
+        assert_eq!(
+            read(dir.path(), "atc.part0002.csv"),
+            "number,code,description\n2,A01,PREPARADOS ESTOMATOLÓGICOS\n"
+        );
+
+        generate_postgres_schema_with_options(dir.path(), &options).unwrap();
+        let import = read(dir.path(), super::super::POSTGRES_IMPORT_SQL);
+        let first = import.find("FROM 'atc.part0001.csv'").unwrap();
+        let second = import.find("FROM 'atc.part0002.csv'").unwrap();
+        assert!(first < second);
+        assert!(!import.contains("FROM 'atc.csv'"));
+        // Files not written keep their unsplit name
+        assert!(import.contains("FROM 'dcp.csv'"));
+    }
+
+    #[test]
+    fn test_split_outputs_are_recorded_in_manifest() {
+        let dir = TempDir::new().unwrap();
+        let xml_path = fixture("nomenclator", "DICCIONARIO_ATC.xml");
+        let csv_path = dir.path().join("atc.csv");
+        let options = ParserOptions {
+            skip_unchanged: true,
+            ..splitting(1)
+        };
+        let parse = |options: &ParserOptions| {
+            parse_atc_xml_to_csv_with_options(xml_path.clone(), csv_path.clone(), options).unwrap()
+        };
+        assert!(!parse(&options).unchanged);
+        assert!(parse(&options).unchanged);
+
+        // Unsplit output is not current, nor are tampered parts
+        let unsplit = ParserOptions {
+            max_rows_per_file: None,
+            ..options.clone()
+        };
+        assert!(!parse(&unsplit).unchanged);
+        assert!(!parse(&options).unchanged);
+        fs::write(dir.path().join("atc.part0002.csv"), "changed").unwrap();
+        assert!(!parse(&options).unchanged);
+        assert!(parse(&options).unchanged);
+    }
+}
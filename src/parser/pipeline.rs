@@ -0,0 +1,730 @@
+//! Rendering of prescriptions into the normalized CSV files, optionally on worker threads.
+
+use super::dates::{DATE_COLUMNS, UnparsedDates, normalize_prescription_dates};
+use super::dedup::Duplicates;
+use super::numbers::{NUMERIC_COLUMNS, UnparsedCounts, normalize_prescription, unparsed_by_column};
+use super::options::{OnDuplicate, OnError, ParserOptions, PrescriptionKey};
+use super::report::{ParseProgress, ParseReport, RecordError};
+use super::schema::Column;
+use super::{
+    PRESCRIPTION_CSV_FILES, PrescriptionReader, PrescriptionRecord, UniquePrescriptions,
+    deserialize_prescription, element_key, finish_prescriptions, prescription_row_counts,
+};
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use std::time::Instant;
+
+/// Number of files in [`PRESCRIPTION_CSV_FILES`]
+const FILES: usize = PRESCRIPTION_CSV_FILES.len();
+
+/// Prescriptions handed to a worker at once
+const CHUNK_SIZE: usize = 64;
+
+/// Chunks handed to the workers but not written yet, per worker
+const CHUNKS_PER_WORKER: usize = 2;
+
+/// Byte buffer shared between a CSV writer and its [`Renderer`]
+#[derive(Clone, Default)]
+struct RowBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl RowBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+impl Write for RowBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// CSV rows of one prescription for every file of [`PRESCRIPTION_CSV_FILES`]
+struct Rendered {
+    key: String,
+    rows: [Vec<u8>; FILES],
+    counts: [usize; FILES],
+    unparsed: UnparsedCounts,
+    unparsed_dates: UnparsedDates,
+}
+
+/// Renders prescriptions into CSV rows formatted after the parser options
+struct Renderer<'a> {
+    options: &'a ParserOptions,
+    /// Columns of `prescriptions.csv`
+    columns: &'a [Column],
+    buffers: [RowBuffer; FILES],
+    writers: [csv::Writer<RowBuffer>; FILES],
+}
+
+impl<'a> Renderer<'a> {
+    fn new(options: &'a ParserOptions, columns: &'a [Column]) -> Self {
+        let buffers: [RowBuffer; FILES] = Default::default();
+        let writers = buffers.clone().map(|buffer| options.csv_writer(buffer));
+        Renderer {
+            options,
+            columns,
+            buffers,
+            writers,
+        }
+    }
+
+    /// Header row of `prescriptions.csv`; the other files have none.
+    fn header(&mut self) -> Result<Vec<u8>> {
+        self.options
+            .write_header(&mut self.writers[0], self.columns)?;
+        self.writers[0].flush()?;
+        Ok(self.buffers[0].take())
+    }
+
+    fn render(&mut self, mut record: PrescriptionRecord) -> Result<Rendered> {
+        let unparsed = if self.options.normalize_numbers {
+            normalize_prescription(&mut record)
+        } else {
+            UnparsedCounts::default()
+        };
+        let unparsed_dates = if self.options.normalize_dates {
+            normalize_prescription_dates(&mut record)
+        } else {
+            UnparsedDates::default()
+        };
+        let record = &record;
+        let columns = self.columns;
+        let key = self.options.prescription_key;
+        check_key(record, key)?;
+        let [
+            main,
+            forms,
+            ingredients,
+            routes,
+            atc_codes,
+            atc_duplicates,
+            supply,
+            excipients,
+            notes,
+        ] = &mut self.writers;
+
+        // Write main prescription record (nested collections are skipped via serde)
+        self.options.write_row(main, columns, record)?;
+
+        // Write pharmaceutical form and its nested entities
+        if let Some(form) = &record.forms {
+            // Write form record
+            write_key(forms, key, record)?;
+            forms.write_record([
+                &form.form_code,
+                self.options.field(form.simplified_form_code.as_deref()),
+                self.options.field(form.num_active_ingredients.as_deref()),
+            ])?;
+
+            // Write active ingredients
+            for ingredient in &form.active_ingredients {
+                write_key(ingredients, key, record)?;
+                ingredients.write_record([
+                    self.options
+                        .field(ingredient.active_ingredient_code.as_deref()),
+                    self.options.field(ingredient.order.as_deref()),
+                    self.options.field(ingredient.dose.as_deref()),
+                    self.options.field(ingredient.dose_unit.as_deref()),
+                    self.options.field(ingredient.composition_dose.as_deref()),
+                    self.options.field(ingredient.composition_unit.as_deref()),
+                    self.options
+                        .field(ingredient.administration_dose.as_deref()),
+                    self.options
+                        .field(ingredient.administration_unit.as_deref()),
+                    self.options.field(ingredient.prescription_dose.as_deref()),
+                    self.options.field(ingredient.prescription_unit.as_deref()),
+                ])?;
+            }
+
+            // Write administration routes
+            for route in &form.admin_routes {
+                write_key(routes, key, record)?;
+                routes.write_record([&route.route_code])?;
+            }
+        }
+
+        // Write ATC codes and their duplicates
+        for atc in &record.atc_codes {
+            write_key(atc_codes, key, record)?;
+            atc_codes.write_record([&atc.atc_code])?;
+
+            // Write ATC duplicates
+            for duplicate in &atc.duplicates {
+                write_key(atc_duplicates, key, record)?;
+                atc_duplicates.write_record([
+                    &atc.atc_code,
+                    &duplicate.duplicate_atc,
+                    self.options.field(duplicate.description.as_deref()),
+                    self.options.field(duplicate.effect.as_deref()),
+                    self.options.field(duplicate.recommendation.as_deref()),
+                ])?;
+            }
+        }
+
+        // Write supply problems
+        for problem in &record.supply_problems {
+            write_key(supply, key, record)?;
+            supply.write_record([
+                self.options.field(problem.start_date.as_deref()),
+                self.options.field(problem.observations.as_deref()),
+            ])?;
+        }
+
+        // Write excipients with obligatory declaration
+        for excipient in &record.excipients {
+            write_key(excipients, key, record)?;
+            excipients.write_record([
+                &excipient.excipient_code,
+                self.options.field(excipient.quantity.as_deref()),
+                self.options.field(excipient.unit.as_deref()),
+            ])?;
+        }
+
+        // Write informational notes
+        for note in &record.notes {
+            write_key(notes, key, record)?;
+            notes.write_record([
+                self.options.field(note.note_type.as_deref()),
+                self.options.field(note.number.as_deref()),
+                self.options.field(note.reference.as_deref()),
+                self.options.field(note.subject.as_deref()),
+                self.options.field(note.date.as_deref()),
+                self.options.field(note.url.as_deref()),
+            ])?;
+        }
+
+        let mut rows: [Vec<u8>; FILES] = Default::default();
+        for ((writer, buffer), row) in self.writers.iter_mut().zip(&self.buffers).zip(&mut rows) {
+            writer.flush()?;
+            *row = buffer.take();
+        }
+        Ok(Rendered {
+            key: record.cod_nacion.clone(),
+            rows,
+            counts: prescription_row_counts(record),
+            unparsed,
+            unparsed_dates,
+        })
+    }
+}
+
+/// Fails when an identifier written by `key` is empty.
+fn check_key(record: &PrescriptionRecord, key: PrescriptionKey) -> Result<()> {
+    let (cod_nacion, nro_definitivo) = match key {
+        PrescriptionKey::CodNacion => (true, false),
+        PrescriptionKey::NroDefinitivo => (false, true),
+        PrescriptionKey::Both => (true, true),
+    };
+    if cod_nacion && record.cod_nacion.trim().is_empty() {
+        anyhow::bail!(
+            "Prescription with nro_definitivo {} has an empty cod_nacion",
+            record.nro_definitivo
+        );
+    }
+    if nro_definitivo && record.nro_definitivo.trim().is_empty() {
+        anyhow::bail!(
+            "Prescription {} has an empty nro_definitivo",
+            record.cod_nacion
+        );
+    }
+    Ok(())
+}
+
+/// Writes the identifiers of `record` selected by `key`, starting a child row.
+fn write_key<W: Write>(
+    wtr: &mut csv::Writer<W>,
+    key: PrescriptionKey,
+    record: &PrescriptionRecord,
+) -> csv::Result<()> {
+    if key != PrescriptionKey::NroDefinitivo {
+        wtr.write_field(&record.cod_nacion)?;
+    }
+    if key != PrescriptionKey::CodNacion {
+        wtr.write_field(&record.nro_definitivo)?;
+    }
+    Ok(())
+}
+
+/// Writes rendered prescriptions in order and reports progress
+struct Output<'a, W: Write> {
+    writers: &'a mut [W],
+    options: &'a ParserOptions,
+    rows: [(&'static str, usize); FILES],
+    unparsed: UnparsedCounts,
+    unparsed_dates: UnparsedDates,
+}
+
+impl<'a, W: Write> Output<'a, W> {
+    fn new(writers: &'a mut [W], options: &'a ParserOptions, header: &[u8]) -> Result<Self> {
+        writers[0].write_all(header)?;
+        Ok(Output {
+            writers,
+            options,
+            rows: PRESCRIPTION_CSV_FILES.map(|name| (name, 0)),
+            unparsed: UnparsedCounts::default(),
+            unparsed_dates: UnparsedDates::default(),
+        })
+    }
+
+    fn progress(&self, bytes_read: Option<u64>, done: bool) -> ParseProgress {
+        ParseProgress {
+            records: self.rows[0].1,
+            rows: self.rows.to_vec(),
+            bytes_read,
+            done,
+        }
+    }
+
+    fn write(&mut self, rendered: Rendered, bytes_read: Option<u64>) -> Result<()> {
+        for (writer, row) in self.writers.iter_mut().zip(&rendered.rows) {
+            writer.write_all(row)?;
+        }
+        for ((_, count), added) in self.rows.iter_mut().zip(rendered.counts) {
+            *count += added;
+        }
+        for (count, added) in self.unparsed.iter_mut().zip(rendered.unparsed) {
+            *count += added;
+        }
+        for (count, added) in self.unparsed_dates.iter_mut().zip(rendered.unparsed_dates) {
+            *count += added;
+        }
+        if self.options.progress_due(self.rows[0].1) {
+            self.options
+                .report_progress(self.progress(bytes_read, false));
+        }
+        Ok(())
+    }
+
+    /// Flushes every file and returns a report of the prescriptions written.
+    fn finish(self, bytes_read: Option<u64>) -> Result<ParseReport> {
+        for writer in self.writers.iter_mut() {
+            writer.flush()?;
+        }
+        self.options
+            .report_progress(self.progress(bytes_read, true));
+        Ok(ParseReport {
+            records: self.rows[0].1,
+            rows: self.rows.to_vec(),
+            unparsed_numbers: unparsed_by_column(&NUMERIC_COLUMNS, &self.unparsed),
+            unparsed_dates: unparsed_by_column(&DATE_COLUMNS, &self.unparsed_dates),
+            ..Default::default()
+        })
+    }
+}
+
+/// Writes the prescriptions of `reader` to `writers`, one per file of
+/// [`PRESCRIPTION_CSV_FILES`].
+///
+/// With more than one [`ParserOptions::workers`] the `<prescription>` elements are
+/// deserialized and rendered on worker threads while this thread reads the input and
+/// writes the rendered rows in input order. [`OnDuplicate::KeepLast`] always runs on a
+/// single thread.
+pub(super) fn write_prescription_csvs<R: BufRead, W: Write>(
+    reader: PrescriptionReader<R>,
+    writers: &mut [W],
+    options: &ParserOptions,
+) -> Result<ParseReport> {
+    let started = Instant::now();
+    let mut report = if options.workers > 1 && options.on_duplicate != OnDuplicate::KeepLast {
+        write_parallel(reader, writers, options)?
+    } else {
+        write_sequential(reader, writers, options)?
+    };
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+fn write_sequential<R: BufRead, W: Write>(
+    reader: PrescriptionReader<R>,
+    writers: &mut [W],
+    options: &ParserOptions,
+) -> Result<ParseReport> {
+    let mut records = UniquePrescriptions::new(reader, options.on_duplicate);
+    let columns = options.prescription_record_columns()?;
+    let mut renderer = Renderer::new(options, &columns);
+    let mut output = Output::new(writers, options, &renderer.header()?)?;
+
+    while let Some(record) = records.next_record()? {
+        output.write(renderer.render(record)?, Some(records.reader.bytes_read()))?;
+    }
+
+    let mut report = output.finish(Some(records.reader.bytes_read()))?;
+    records.finish(&mut report)?;
+    Ok(report)
+}
+
+/// Writes prescriptions already in memory to `writers`, one per file of
+/// [`PRESCRIPTION_CSV_FILES`].
+pub(super) fn write_records_csvs<W: Write>(
+    records: &[PrescriptionRecord],
+    writers: &mut [W],
+    options: &ParserOptions,
+) -> Result<ParseReport> {
+    let started = Instant::now();
+    let columns = options.prescription_record_columns()?;
+    let mut renderer = Renderer::new(options, &columns);
+    let mut output = Output::new(writers, options, &renderer.header()?)?;
+    for record in records {
+        output.write(renderer.render(record.clone())?, None)?;
+    }
+    let mut report = output.finish(None)?;
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+/// Outcome of one `<prescription>` element on a worker
+// Almost every element is rendered, so boxing the rows would only add an allocation
+#[allow(clippy::large_enum_variant)]
+enum WorkerResult {
+    /// Rendered rows and the number of empty flags read
+    Rendered(Rendered, usize),
+    /// Deserialization error, with the `cod_nacion` of the element when readable
+    Invalid(anyhow::Error, Option<String>),
+    /// Error rendering a record, which always stops parsing
+    Failed(anyhow::Error),
+}
+
+fn write_parallel<R: BufRead, W: Write>(
+    mut reader: PrescriptionReader<R>,
+    writers: &mut [W],
+    options: &ParserOptions,
+) -> Result<ParseReport> {
+    let workers = options.workers;
+    let bool_parsing = reader.bool_parsing;
+    let columns = &options.prescription_record_columns()?;
+    let mut output = Output::new(writers, options, &Renderer::new(options, columns).header()?)?;
+    let mut duplicates = Duplicates::new(options.on_duplicate);
+    let mut empty_flags = 0;
+    let on_error = reader.on_error;
+    let mut errors = Vec::new();
+    // Elements read so far, including skipped and duplicated ones
+    let mut elements = 0;
+
+    let (job_tx, job_rx) = mpsc::channel::<(usize, Vec<Vec<u8>>)>();
+    let job_rx = Mutex::new(job_rx);
+    thread::scope(|scope| -> Result<()> {
+        // Owned by the scope so that returning early stops the workers
+        let job_tx = job_tx;
+        let (result_tx, result_rx) = mpsc::channel::<(usize, Vec<WorkerResult>)>();
+        for _ in 0..workers {
+            let job_rx = &job_rx;
+            let result_tx = result_tx.clone();
+            scope.spawn(move || {
+                let mut renderer = Renderer::new(options, columns);
+                loop {
+                    // The lock is only held while waiting for the next chunk
+                    let job = job_rx.lock().unwrap().recv();
+                    let Ok((index, chunk)) = job else {
+                        break;
+                    };
+                    let results = chunk
+                        .iter()
+                        .map(|bytes| {
+                            let (record, empty_flags) =
+                                deserialize_prescription(bytes, bool_parsing);
+                            match record.map(|record| renderer.render(record)) {
+                                Ok(Ok(rendered)) => WorkerResult::Rendered(rendered, empty_flags),
+                                Ok(Err(e)) => WorkerResult::Failed(e),
+                                Err(e) => {
+                                    WorkerResult::Invalid(e, element_key(bytes, b"cod_nacion"))
+                                }
+                            }
+                        })
+                        .collect();
+                    if result_tx.send((index, results)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(result_tx);
+
+        // Input position after each element of a chunk, used for progress updates
+        let mut positions = BTreeMap::new();
+        let mut pending = BTreeMap::new();
+        let (mut sent, mut written) = (0, 0);
+        let mut eof = false;
+        loop {
+            while !eof && sent - written < workers * CHUNKS_PER_WORKER {
+                let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+                let mut chunk_positions = Vec::with_capacity(CHUNK_SIZE);
+                while chunk.len() < CHUNK_SIZE {
+                    match reader.next_element()? {
+                        Some(bytes) => {
+                            chunk.push(bytes);
+                            chunk_positions.push(reader.bytes_read());
+                        }
+                        None => {
+                            eof = true;
+                            break;
+                        }
+                    }
+                }
+                if chunk.is_empty() {
+                    break;
+                }
+                positions.insert(sent, chunk_positions);
+                job_tx
+                    .send((sent, chunk))
+                    .context("Prescription workers stopped")?;
+                sent += 1;
+            }
+            if written == sent {
+                break;
+            }
+
+            let (index, results) = result_rx.recv().context("Prescription workers stopped")?;
+            pending.insert(index, results);
+            while let Some(results) = pending.remove(&written) {
+                let chunk_positions = positions.remove(&written).unwrap_or_default();
+                for (result, position) in results.into_iter().zip(chunk_positions) {
+                    let index = elements;
+                    elements += 1;
+                    match result {
+                        WorkerResult::Rendered(rendered, flags) => {
+                            empty_flags += flags;
+                            if duplicates.admit(&rendered.key) {
+                                output.write(rendered, Some(position))?;
+                            }
+                        }
+                        WorkerResult::Invalid(e, key) if on_error == OnError::SkipAndReport => {
+                            errors.push(RecordError::new(index, key, &e));
+                        }
+                        WorkerResult::Invalid(e, _) | WorkerResult::Failed(e) => return Err(e),
+                    }
+                }
+                written += 1;
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut report = output.finish(Some(reader.bytes_read()))?;
+    finish_prescriptions(empty_flags, errors, reader.census, duplicates, &mut report)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::prescription_xml;
+    use super::super::{
+        PRESCRIPTION_ACTIVE_INGREDIENTS_CSV, PRESCRIPTION_ATC_CSV,
+        PRESCRIPTION_SUPPLY_PROBLEMS_CSV, PRESCRIPTIONS_CSV,
+        parse_prescription_xml_to_csvs_from_reader_with_options,
+    };
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    fn parse(xml: &str, options: &ParserOptions) -> Result<(ParseReport, HashMap<String, String>)> {
+        let buffers: HashMap<String, RowBuffer> = PRESCRIPTION_CSV_FILES
+            .iter()
+            .map(|name| (name.to_string(), RowBuffer::default()))
+            .collect();
+        let report = parse_prescription_xml_to_csvs_from_reader_with_options(
+            xml.as_bytes(),
+            |name| Ok(buffers[name].clone()),
+            options,
+        )?;
+        let outputs = buffers
+            .iter()
+            .map(|(name, buffer)| (name.clone(), String::from_utf8(buffer.take()).unwrap()))
+            .collect();
+        Ok((report, outputs))
+    }
+
+    fn fixture(count: usize) -> String {
+        let records: String = (0..count)
+            .map(|i| {
+                prescription_xml(
+                    &format!("{:06}", 600000 + i % 150),
+                    &format!(
+                        "<formasfarmaceuticas><cod_forfar>{i}</cod_forfar>\
+                         <viasadministracion><cod_via_admin>48</cod_via_admin></viasadministracion>\
+                         </formasfarmaceuticas><atc><cod_atc>A{i}</cod_atc></atc>"
+                    ),
+                )
+            })
+            .collect();
+        format!("<aemps_prescripcion>{records}</aemps_prescripcion>")
+    }
+
+    #[test]
+    fn test_workers_preserve_input_order() {
+        let xml = fixture(200);
+        let sequential = ParserOptions {
+            workers: 1,
+            ..Default::default()
+        };
+        let parallel = ParserOptions {
+            workers: 4,
+            ..Default::default()
+        };
+
+        let (expected_report, expected) = parse(&xml, &sequential).unwrap();
+        let (report, outputs) = parse(&xml, &parallel).unwrap();
+
+        assert_eq!(
+            ParseReport {
+                elapsed: expected_report.elapsed,
+                ..report
+            },
+            expected_report
+        );
+        assert_eq!(report.records, 150);
+        assert_eq!(report.duplicates, 50);
+        assert_eq!(outputs, expected);
+        assert!(outputs[PRESCRIPTION_ATC_CSV].starts_with("600000,A0\n600001,A1\n"));
+    }
+
+    #[test]
+    fn test_workers_report_deserialization_errors() {
+        // Duplicated flag in the middle of the file
+        let xml = fixture(50).replace(
+            "<cod_nacion>600020</cod_nacion>",
+            "<cod_nacion>600020</cod_nacion><sw_receta>x</sw_receta>",
+        );
+        let options = ParserOptions {
+            workers: 4,
+            ..Default::default()
+        };
+
+        let err = parse(&xml, &options).unwrap_err();
+        assert!(err.to_string().contains("Failed to deserialize"), "{err:#}");
+    }
+
+    #[test]
+    fn test_normalize_numbers() {
+        let xml = format!(
+            "<aemps_prescripcion>{}{}</aemps_prescripcion>",
+            prescription_xml(
+                "600000",
+                "<contenido>2,5</contenido><formasfarmaceuticas><cod_forfar>10</cod_forfar>\
+                 <composicion_pa><cod_principio_activo>1</cod_principio_activo>\
+                 <dosis_pa>1.000,75</dosis_pa><dosis_composicion>c.s.</dosis_composicion>\
+                 </composicion_pa></formasfarmaceuticas>"
+            ),
+            prescription_xml("600001", "<contenido>c.s.</contenido>"),
+        );
+
+        for workers in [1, 4] {
+            let options = ParserOptions {
+                workers,
+                normalize_numbers: true,
+                ..Default::default()
+            };
+            let (report, outputs) = parse(&xml, &options).unwrap();
+            assert_eq!(
+                report.unparsed_numbers,
+                [
+                    ("prescriptions.contenido", 1),
+                    ("prescription_active_ingredients.composition_dose", 1)
+                ],
+                "{workers}"
+            );
+            assert!(outputs[PRESCRIPTIONS_CSV].contains(",2.5,"), "{workers}");
+            assert!(outputs[PRESCRIPTIONS_CSV].contains(",c.s.,"), "{workers}");
+            assert_eq!(
+                outputs[PRESCRIPTION_ACTIVE_INGREDIENTS_CSV], "600000,1,,1000.75,,c.s.,,,,,\n",
+                "{workers}"
+            );
+        }
+
+        let (report, outputs) = parse(&xml, &ParserOptions::default()).unwrap();
+        assert!(report.unparsed_numbers.is_empty());
+        assert!(outputs[PRESCRIPTIONS_CSV].contains(",\"2,5\","));
+    }
+
+    #[test]
+    fn test_normalize_dates() {
+        let xml = format!(
+            "<aemps_prescripcion>{}</aemps_prescripcion>",
+            prescription_xml(
+                "600000",
+                "<fecha_autorizacion>15/03/2024</fecha_autorizacion><fec_comer>?</fec_comer>\
+                 <problemassuministro><fecha_inicio>20251201</fecha_inicio></problemassuministro>"
+            ),
+        );
+        let options = ParserOptions {
+            normalize_dates: true,
+            ..Default::default()
+        };
+
+        let (report, outputs) = parse(&xml, &options).unwrap();
+        assert_eq!(report.unparsed_dates, [("prescriptions.fec_comer", 1)]);
+        assert!(outputs[PRESCRIPTIONS_CSV].contains(",2024-03-15,"));
+        assert_eq!(
+            outputs[PRESCRIPTION_SUPPLY_PROBLEMS_CSV],
+            "600000,2025-12-01,\n"
+        );
+    }
+
+    #[test]
+    fn test_prescription_key() {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/delta/Prescripcion.xml");
+        let xml = std::fs::read_to_string(path).unwrap();
+        let cases = [
+            (
+                PrescriptionKey::CodNacion,
+                "600000,N02BE01\n600001,J01CA04\n600002,M01AE01\n",
+            ),
+            (
+                PrescriptionKey::NroDefinitivo,
+                "60000,N02BE01\n60000,J01CA04\n60000,M01AE01\n",
+            ),
+            (
+                PrescriptionKey::Both,
+                "600000,60000,N02BE01\n600001,60000,J01CA04\n600002,60000,M01AE01\n",
+            ),
+        ];
+
+        for (key, expected) in cases {
+            for workers in [1, 4] {
+                let options = ParserOptions {
+                    prescription_key: key,
+                    workers,
+                    ..Default::default()
+                };
+                let (report, outputs) = parse(&xml, &options).unwrap();
+                assert_eq!(report.records, 3);
+                assert_eq!(outputs[PRESCRIPTION_ATC_CSV], expected, "{key:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_prescription_key() {
+        let xml = format!(
+            "<aemps_prescripcion>{}</aemps_prescripcion>",
+            prescription_xml("600000", "").replace(
+                "<nro_definitivo>66337</nro_definitivo>",
+                "<nro_definitivo> </nro_definitivo>"
+            )
+        );
+        assert!(parse(&xml, &ParserOptions::default()).is_ok());
+
+        for key in [PrescriptionKey::NroDefinitivo, PrescriptionKey::Both] {
+            let options = ParserOptions {
+                prescription_key: key,
+                ..Default::default()
+            };
+            let err = parse(&xml, &options).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("Prescription 600000 has an empty nro_definitivo"),
+                "{err:#}"
+            );
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! PostgreSQL DDL and import scripts for the generated CSV files.
+
+use super::options::{HeaderStyle, ParserOptions};
+use super::parts::existing_parts;
+use super::schema::{ColumnType, DICTIONARY_TABLES, PRESCRIPTION_TABLES, Table};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// File name of the generated `CREATE TABLE` script
+pub const POSTGRES_SCHEMA_SQL: &str = "schema.sql";
+/// File name of the generated `\copy` script
+pub const POSTGRES_IMPORT_SQL: &str = "import.sql";
+
+/// Every table in dependency order: referenced tables come before the tables using them.
+fn tables() -> impl Iterator<Item = &'static Table> {
+    DICTIONARY_TABLES.iter().chain(PRESCRIPTION_TABLES.iter())
+}
+
+/// Name of the column `name` of `table` in the given header style.
+fn column_name(table: &Table, name: &'static str, style: HeaderStyle) -> &'static str {
+    table
+        .columns
+        .iter()
+        .find(|column| column.name == name)
+        .map_or(name, |column| column.header(style))
+}
+
+/// Name of the column `name` of the table called `table_name` in the given header style.
+fn referenced_column_name(
+    table_name: &str,
+    name: &'static str,
+    style: HeaderStyle,
+) -> &'static str {
+    tables()
+        .find(|table| table.name() == table_name)
+        .map_or(name, |table| column_name(table, name, style))
+}
+
+fn create_table_sql(table: &Table, options: &ParserOptions) -> Result<String> {
+    let (style, key) = (options.header_style, options.prescription_key);
+    let name = table.name();
+    let columns = options.table_columns(table)?;
+    // Columns left out of `prescriptions.csv` have no foreign key
+    let foreign_keys: Vec<_> = table
+        .foreign_keys_with_key(key)
+        .iter()
+        .filter(|key| columns.iter().any(|column| column.name == key.column))
+        .collect();
+    let mut definitions: Vec<String> = columns
+        .iter()
+        .map(|column| {
+            let sql_type = match column.column_type {
+                ColumnType::Boolean => "boolean",
+                ColumnType::Int32 => "integer",
+                ColumnType::Text | ColumnType::Date => "text",
+            };
+            let mut definition = format!("\"{}\" {}", column.header(style), sql_type);
+            if !column.nullable {
+                definition.push_str(" NOT NULL");
+            }
+            definition
+        })
+        .collect();
+    if let Some(primary_key) = table.primary_key {
+        definitions.push(format!(
+            "PRIMARY KEY (\"{}\")",
+            column_name(table, primary_key, style)
+        ));
+    }
+    definitions.extend(foreign_keys.iter().map(|key| {
+        format!(
+            "FOREIGN KEY (\"{}\") REFERENCES {} (\"{}\")",
+            column_name(table, key.column, style),
+            key.table,
+            referenced_column_name(key.table, key.references, style)
+        )
+    }));
+
+    let mut sql = format!(
+        "CREATE TABLE {name} (\n    {}\n);\n",
+        definitions.join(",\n    ")
+    );
+    for key in &foreign_keys {
+        sql.push_str(&format!(
+            "CREATE INDEX idx_{name}_{} ON {name} (\"{}\");\n",
+            key.column,
+            column_name(table, key.column, style)
+        ));
+    }
+    Ok(sql)
+}
+
+/// Returns the `CREATE TABLE` statements for every generated CSV file.
+///
+/// Existing tables are dropped first. Columns are typed after the serialized
+/// records: `boolean` for the `sw_*` flags, `integer` for numeric fields and `text`
+/// elsewhere, with primary and foreign keys between the prescription and dictionary tables.
+pub fn postgres_schema_sql() -> String {
+    postgres_schema_sql_with_options(&ParserOptions::default())
+        .expect("Default options select every column")
+}
+
+/// Returns the `CREATE TABLE` statements for CSV files generated with `options`.
+///
+/// Column names follow [`ParserOptions::header_style`] and the prescription child
+/// tables start with the [`ParserOptions::prescription_key`] columns. Child tables keyed
+/// by `nro_definitivo` alone have no foreign key to `prescriptions`. `prescriptions`
+/// only has the columns selected by [`ParserOptions::prescription_columns`], which
+/// fails on unknown names.
+/// Boolean columns are `boolean` whatever the [`ParserOptions::bool_repr`], as `COPY`
+/// reads all of them.
+pub fn postgres_schema_sql_with_options(options: &ParserOptions) -> Result<String> {
+    let mut sql = String::from(
+        "-- Generated by cima-rs, do not edit\n\
+         -- boolean columns accept every BoolRepr: true/false, 1/0 and T/F\n\n",
+    );
+    let names: Vec<&str> = tables().map(Table::name).collect();
+    for name in names.iter().rev() {
+        sql.push_str(&format!("DROP TABLE IF EXISTS {name} CASCADE;\n"));
+    }
+    for table in tables() {
+        sql.push('\n');
+        sql.push_str(&create_table_sql(table, options)?);
+    }
+    Ok(sql)
+}
+
+/// Returns the psql `\copy` commands importing every generated CSV file.
+///
+/// Tables are loaded in dependency order. File paths are relative, so the script
+/// is meant to be run with psql from the CSV output directory.
+pub fn postgres_import_sql() -> String {
+    postgres_import_sql_with_options(&ParserOptions::default())
+        .expect("Default options select every column")
+}
+
+/// Returns the psql `\copy` commands importing CSV files generated with `options`.
+///
+/// Column names follow [`ParserOptions::header_style`], and non-default delimiters and
+/// null representations are passed on to `COPY`. Files are named as when not split by
+/// [`ParserOptions::max_rows_per_file`]; [`generate_postgres_schema_with_options`]
+/// lists the parts found instead.
+pub fn postgres_import_sql_with_options(options: &ParserOptions) -> Result<String> {
+    import_sql(options, |table| vec![table.file_name.to_string()])
+}
+
+/// `\copy` commands importing the files returned by `files` for each table, in order.
+fn import_sql(options: &ParserOptions, files: impl Fn(&Table) -> Vec<String>) -> Result<String> {
+    let mut copy_options = String::new();
+    if options.delimiter != b',' {
+        copy_options.push_str(&format!(
+            ", DELIMITER {}",
+            sql_literal(&char::from(options.delimiter).to_string())
+        ));
+    }
+    if !options.null_repr.is_empty() {
+        copy_options.push_str(&format!(", NULL {}", sql_literal(&options.null_repr)));
+    }
+
+    let mut sql = String::from("-- Generated by cima-rs, run with psql from the CSV directory\n\n");
+    for table in tables() {
+        let columns: Vec<String> = options
+            .table_columns(table)?
+            .iter()
+            .map(|column| format!("\"{}\"", column.header(options.header_style)))
+            .collect();
+        for file_name in files(table) {
+            sql.push_str(&format!(
+                "\\copy {} ({}) FROM '{}' WITH (FORMAT csv, HEADER {}{})\n",
+                table.name(),
+                columns.join(", "),
+                file_name,
+                table.has_header,
+                copy_options
+            ));
+        }
+    }
+    Ok(sql)
+}
+
+/// Quotes `value` as an SQL string literal.
+fn sql_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Writes `schema.sql` and `import.sql` for the generated CSV files to `output_dir`.
+///
+/// See [`postgres_schema_sql`] and [`postgres_import_sql`].
+pub fn generate_postgres_schema<P: AsRef<Path>>(output_dir: P) -> Result<()> {
+    generate_postgres_schema_with_options(output_dir, &ParserOptions::default())
+}
+
+/// Writes `schema.sql` and `import.sql` for CSV files generated with `options` to `output_dir`.
+///
+/// With [`ParserOptions::max_rows_per_file`], `import.sql` copies every part of each
+/// file found in `output_dir`, in order.
+pub fn generate_postgres_schema_with_options<P: AsRef<Path>>(
+    output_dir: P,
+    options: &ParserOptions,
+) -> Result<()> {
+    let output_dir = output_dir.as_ref();
+    let import = if options.max_rows_per_file.is_some() {
+        let mut parts = HashMap::new();
+        for table in tables() {
+            parts.insert(
+                table.file_name,
+                existing_parts(output_dir, table.file_name)?,
+            );
+        }
+        import_sql(options, |table| match &parts[table.file_name] {
+            found if found.is_empty() => vec![table.file_name.to_string()],
+            found => found.clone(),
+        })?
+    } else {
+        postgres_import_sql_with_options(options)?
+    };
+    for (file_name, content) in [
+        (
+            POSTGRES_SCHEMA_SQL,
+            postgres_schema_sql_with_options(options)?,
+        ),
+        (POSTGRES_IMPORT_SQL, import),
+    ] {
+        let path = output_dir.join(file_name);
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::PRESCRIPTION_CSV_FILES;
+    use super::super::options::PrescriptionKey;
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_schema_mentions_every_table_and_column() {
+        let sql = postgres_schema_sql();
+        for table in tables() {
+            let create = format!("CREATE TABLE {} (", table.name());
+            let start = sql.find(&create).unwrap_or_else(|| panic!("{create}"));
+            let body = &sql[start..start + sql[start..].find(");").unwrap()];
+            for column in table.columns {
+                assert!(
+                    body.contains(&format!("\"{}\"", column.name)),
+                    "{}.{}",
+                    table.name(),
+                    column.name
+                );
+            }
+        }
+
+        assert!(sql.contains("\"sw_receta\" boolean NOT NULL"));
+        assert!(sql.contains("-- boolean columns accept every BoolRepr"));
+        assert!(sql.contains("\"number\" integer NOT NULL"));
+        assert!(sql.contains("\"url_fictec\" text,"));
+        assert!(sql.contains("PRIMARY KEY (\"cod_nacion\")"));
+        assert!(sql.contains(
+            "FOREIGN KEY (\"prescription_id\") REFERENCES prescriptions (\"cod_nacion\")"
+        ));
+    }
+
+    #[test]
+    fn test_import_mentions_every_csv_file_in_order() {
+        let sql = postgres_import_sql();
+        let mut last = 0;
+        for table in tables() {
+            let position = sql
+                .find(&format!("FROM '{}'", table.file_name))
+                .unwrap_or_else(|| panic!("{}", table.file_name));
+            assert!(position > last, "{} out of order", table.file_name);
+            last = position;
+
+            for column in table.columns {
+                assert!(sql.contains(&format!("\"{}\"", column.name)));
+            }
+        }
+        for file in PRESCRIPTION_CSV_FILES {
+            assert!(sql.contains(file), "{file}");
+        }
+
+        assert!(sql.contains("FROM 'prescriptions.csv' WITH (FORMAT csv, HEADER true)"));
+        assert!(sql.contains("FROM 'prescription_atc.csv' WITH (FORMAT csv, HEADER false)"));
+    }
+
+    #[test]
+    fn test_spanish_schema_and_import() {
+        let options = ParserOptions {
+            delimiter: b';',
+            null_repr: "\\N".to_string(),
+            header_style: HeaderStyle::Spanish,
+            ..Default::default()
+        };
+
+        let schema = postgres_schema_sql_with_options(&options).unwrap();
+        assert!(schema.contains("\"codigoatc\" text NOT NULL"));
+        assert!(schema.contains("PRIMARY KEY (\"codigoatc\")"));
+        assert!(schema.contains("FOREIGN KEY (\"codigodcsa\") REFERENCES dcsa (\"codigodcsa\")"));
+        assert!(schema.contains("FOREIGN KEY (\"cod_atc\") REFERENCES atc (\"codigoatc\")"));
+        assert!(schema.contains(
+            "CREATE INDEX idx_prescription_atc_atc_code ON prescription_atc (\"cod_atc\");"
+        ));
+
+        let import = postgres_import_sql_with_options(&options).unwrap();
+        assert!(import.contains(
+            "\\copy atc (\"nroatc\", \"codigoatc\", \"descatc\") FROM 'atc.csv' \
+             WITH (FORMAT csv, HEADER true, DELIMITER ';', NULL '\\N')"
+        ));
+    }
+
+    #[test]
+    fn test_prescription_key_schema_and_import() {
+        let options = ParserOptions {
+            prescription_key: PrescriptionKey::NroDefinitivo,
+            ..Default::default()
+        };
+        let schema = postgres_schema_sql_with_options(&options).unwrap();
+        assert!(schema.contains(
+            "CREATE TABLE prescription_atc (\n    \"nro_definitivo\" text NOT NULL,\n    \"atc_code\""
+        ));
+        assert!(!schema.contains("REFERENCES prescriptions"));
+        let import = postgres_import_sql_with_options(&options).unwrap();
+        assert!(import.contains("\\copy prescription_atc (\"nro_definitivo\", \"atc_code\")"));
+
+        let options = ParserOptions {
+            prescription_key: PrescriptionKey::Both,
+            header_style: HeaderStyle::Spanish,
+            ..Default::default()
+        };
+        let schema = postgres_schema_sql_with_options(&options).unwrap();
+        assert!(schema.contains(
+            "CREATE TABLE prescription_atc (\n    \"cod_nacion\" text NOT NULL,\n    \"nro_definitivo\" text NOT NULL,"
+        ));
+        assert!(
+            schema
+                .contains("FOREIGN KEY (\"cod_nacion\") REFERENCES prescriptions (\"cod_nacion\")")
+        );
+    }
+
+    #[test]
+    fn test_generate_postgres_schema() {
+        let dir = TempDir::new().unwrap();
+        generate_postgres_schema(dir.path()).unwrap();
+
+        let schema = fs::read_to_string(dir.path().join(POSTGRES_SCHEMA_SQL)).unwrap();
+        let import = fs::read_to_string(dir.path().join(POSTGRES_IMPORT_SQL)).unwrap();
+        assert_eq!(schema, postgres_schema_sql());
+        assert_eq!(import, postgres_import_sql());
+    }
+}
@@ -0,0 +1,204 @@
+//! Summary and progress updates of the `*_with_options` parser functions.
+
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::Span;
+
+/// Outcome of parsing one XML file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseReport {
+    /// Records written
+    pub records: usize,
+    /// Records dropped because their key was already seen
+    pub duplicates: usize,
+    /// Distinct keys found more than once, in sorted order
+    pub duplicate_keys: Vec<String>,
+    /// Empty prescription flags replaced by the lenient default
+    pub empty_flags: usize,
+    /// Records skipped because they failed to deserialize, in file order. Only filled
+    /// with [`OnError::SkipAndReport`](super::OnError::SkipAndReport).
+    pub errors: Vec<RecordError>,
+    /// Values of each column of [`NUMERIC_COLUMNS`](super::NUMERIC_COLUMNS) left as read
+    /// because they are not numbers (e.g. `c.s.`), for columns with any. Only filled with
+    /// [`ParserOptions::normalize_numbers`](super::ParserOptions::normalize_numbers).
+    pub unparsed_numbers: Vec<(&'static str, usize)>,
+    /// Values of each column of [`DATE_COLUMNS`](super::DATE_COLUMNS) left as read
+    /// because they are not dates, for columns with any. Only filled with
+    /// [`ParserOptions::normalize_dates`](super::ParserOptions::normalize_dates).
+    pub unparsed_dates: Vec<(&'static str, usize)>,
+    /// Elements not mapped to any record field, as `parent/element`, with their number
+    /// of occurrences, in sorted order. Only filled with
+    /// [`ParserOptions::detect_unknown_elements`](super::ParserOptions::detect_unknown_elements).
+    pub unknown_elements: Vec<(String, usize)>,
+    /// Files written with their rows, excluding headers, when
+    /// [`ParserOptions::max_rows_per_file`](super::ParserOptions::max_rows_per_file)
+    /// splits the output, in write order
+    pub parts: Vec<(String, usize)>,
+    /// Places where the XML does not match the schema of
+    /// [`ParserOptions::validate_first`](super::ParserOptions::validate_first). Only
+    /// filled with [`OnViolation::Warn`](super::OnViolation::Warn).
+    #[cfg(feature = "validate-xml")]
+    pub xsd_violations: Vec<super::XsdViolation>,
+    /// Whether parsing was skipped because the source and its outputs match the
+    /// manifest, see [`ParserOptions::skip_unchanged`](super::ParserOptions::skip_unchanged)
+    pub unchanged: bool,
+    /// Rows written to each prescription CSV file, excluding headers, in
+    /// [`PRESCRIPTION_CSV_FILES`](super::PRESCRIPTION_CSV_FILES) order. Empty for the
+    /// functions writing a single file, whose rows are the `records`.
+    pub rows: Vec<(&'static str, usize)>,
+    /// Time spent parsing and writing
+    pub elapsed: Duration,
+}
+
+impl ParseReport {
+    /// Rows written to every output file, excluding headers
+    pub fn rows_written(&self) -> usize {
+        if self.rows.is_empty() {
+            self.records
+        } else {
+            self.rows.iter().map(|(_, rows)| rows).sum()
+        }
+    }
+
+    /// Row counts of the parse. The rows of a single-file parse are listed under
+    /// `output`.
+    pub fn stats(&self, output: &str) -> ParseStats {
+        let rows_written = if self.rows.is_empty() {
+            BTreeMap::from([(output.to_string(), self.records)])
+        } else {
+            self.rows
+                .iter()
+                .map(|(name, rows)| (name.to_string(), *rows))
+                .collect()
+        };
+        ParseStats {
+            rows_read: self.records + self.duplicates + self.errors.len(),
+            rows_written,
+            duplicates: self.duplicates,
+            errors: self.errors.len(),
+            elapsed: self.elapsed,
+        }
+    }
+}
+
+/// Span of a parser function reading `file` into `format`, closed with [`record_parse`]
+pub(crate) fn parse_span(file: &str, format: &'static str) -> Span {
+    tracing::info_span!(
+        "parse",
+        file,
+        format,
+        records = tracing::field::Empty,
+        rows = tracing::field::Empty
+    )
+}
+
+/// Records the counts of `report` on the [`parse_span`] of `file` and in the metrics
+pub(crate) fn record_parse(span: &Span, file: &str, report: &ParseReport) {
+    let rows = report.rows_written();
+    span.record("records", report.records);
+    span.record("rows", rows);
+    crate::telemetry::record_parse(file, rows, report.elapsed);
+}
+
+/// Row counts and duration of one parse, for monitoring the size of the outputs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ParseStats {
+    /// Records read from the XML file, including duplicates and skipped records
+    pub rows_read: usize,
+    /// Rows written per output file, excluding headers
+    pub rows_written: BTreeMap<String, usize>,
+    /// Records dropped as duplicates
+    pub duplicates: usize,
+    /// Records skipped because they failed to deserialize
+    pub errors: usize,
+    /// Time spent parsing and writing, serialized in seconds
+    #[serde(serialize_with = "serialize_seconds")]
+    pub elapsed: Duration,
+}
+
+impl ParseStats {
+    /// Rows written to every output file
+    pub fn total_rows_written(&self) -> usize {
+        self.rows_written.values().sum()
+    }
+}
+
+fn serialize_seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// A record skipped because it failed to deserialize
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RecordError {
+    /// Position of the record among the records of the file, starting at 0
+    pub index: usize,
+    /// Identifying field of the record (`cod_nacion` for prescriptions, the code of
+    /// dictionary entries), when it could be read
+    pub key: Option<String>,
+    /// Deserialization error
+    pub message: String,
+}
+
+impl RecordError {
+    pub(crate) fn new(index: usize, key: Option<String>, error: &anyhow::Error) -> Self {
+        RecordError {
+            index,
+            key,
+            message: format!("{:#}", error),
+        }
+    }
+}
+
+/// Progress of a running parse, passed to [`ParserOptions::progress`](super::ParserOptions::progress)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseProgress {
+    /// Records parsed so far
+    pub records: usize,
+    /// Rows written so far per output file. Prescription files are named as in
+    /// [`PRESCRIPTION_CSV_FILES`](super::PRESCRIPTION_CSV_FILES), dictionaries by their
+    /// XML name (e.g. `ATC`).
+    pub rows: Vec<(&'static str, usize)>,
+    /// Approximate position in the decoded XML input, when the parser streams it.
+    /// Dividing by the file size gives the fraction done.
+    pub bytes_read: Option<u64>,
+    /// Whether this is the final update
+    pub done: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let report = ParseReport {
+            records: 3,
+            duplicates: 1,
+            errors: vec![RecordError {
+                index: 2,
+                key: None,
+                message: "bad flag".to_string(),
+            }],
+            elapsed: Duration::from_millis(1500),
+            ..Default::default()
+        };
+        let stats = report.stats("atc.csv");
+        assert_eq!(stats.rows_read, 5);
+        assert_eq!(
+            stats.rows_written,
+            BTreeMap::from([("atc.csv".to_string(), 3)])
+        );
+        assert_eq!(
+            serde_json::to_string(&stats).unwrap(),
+            r#"{"rows_read":5,"rows_written":{"atc.csv":3},"duplicates":1,"errors":1,"elapsed":1.5}"#
+        );
+
+        let report = ParseReport {
+            records: 3,
+            rows: vec![("prescriptions.csv", 3), ("prescription_atc.csv", 4)],
+            ..Default::default()
+        };
+        assert_eq!(report.stats("ignored").total_rows_written(), 7);
+    }
+}
@@ -0,0 +1,189 @@
+//! Rows of the normalized prescription child tables, borrowed from a parsed record.
+//!
+//! Each row struct serializes in the order of the matching
+//! `schema::PRESCRIPTION_*_COLUMNS` list.
+
+use super::PrescriptionRecord;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub(crate) struct FormRow<'a> {
+    pub prescription_id: &'a str,
+    pub form_code: &'a str,
+    pub simplified_form_code: Option<&'a str>,
+    pub num_active_ingredients: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ActiveIngredientRow<'a> {
+    pub prescription_id: &'a str,
+    pub active_ingredient_code: Option<&'a str>,
+    pub order: Option<&'a str>,
+    pub dose: Option<&'a str>,
+    pub dose_unit: Option<&'a str>,
+    pub composition_dose: Option<&'a str>,
+    pub composition_unit: Option<&'a str>,
+    pub administration_dose: Option<&'a str>,
+    pub administration_unit: Option<&'a str>,
+    pub prescription_dose: Option<&'a str>,
+    pub prescription_unit: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct AdminRouteRow<'a> {
+    pub prescription_id: &'a str,
+    pub route_code: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct AtcRow<'a> {
+    pub prescription_id: &'a str,
+    pub atc_code: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct AtcDuplicateRow<'a> {
+    pub prescription_id: &'a str,
+    pub atc_code: &'a str,
+    pub duplicate_atc: &'a str,
+    pub description: Option<&'a str>,
+    pub effect: Option<&'a str>,
+    pub recommendation: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct SupplyProblemRow<'a> {
+    pub prescription_id: &'a str,
+    pub start_date: Option<&'a str>,
+    pub observations: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ExcipientRow<'a> {
+    pub prescription_id: &'a str,
+    pub excipient_code: &'a str,
+    pub quantity: Option<&'a str>,
+    pub unit: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct NoteRow<'a> {
+    pub prescription_id: &'a str,
+    pub note_type: Option<&'a str>,
+    pub number: Option<&'a str>,
+    pub reference: Option<&'a str>,
+    pub subject: Option<&'a str>,
+    pub date: Option<&'a str>,
+    pub url: Option<&'a str>,
+}
+
+/// Child table rows of a single prescription
+#[derive(Debug, Default)]
+pub(crate) struct PrescriptionRows<'a> {
+    pub forms: Option<FormRow<'a>>,
+    pub active_ingredients: Vec<ActiveIngredientRow<'a>>,
+    pub admin_routes: Vec<AdminRouteRow<'a>>,
+    pub atc_codes: Vec<AtcRow<'a>>,
+    pub atc_duplicates: Vec<AtcDuplicateRow<'a>>,
+    pub supply_problems: Vec<SupplyProblemRow<'a>>,
+    pub excipients: Vec<ExcipientRow<'a>>,
+    pub notes: Vec<NoteRow<'a>>,
+}
+
+impl<'a> From<&'a PrescriptionRecord> for PrescriptionRows<'a> {
+    fn from(record: &'a PrescriptionRecord) -> Self {
+        // cod_nacion is used as prescription ID (matches DB primary key)
+        let prescription_id = record.cod_nacion.as_str();
+        let mut rows = PrescriptionRows::default();
+
+        if let Some(form) = &record.forms {
+            rows.forms = Some(FormRow {
+                prescription_id,
+                form_code: &form.form_code,
+                simplified_form_code: form.simplified_form_code.as_deref(),
+                num_active_ingredients: form.num_active_ingredients.as_deref(),
+            });
+
+            rows.active_ingredients = form
+                .active_ingredients
+                .iter()
+                .map(|ingredient| ActiveIngredientRow {
+                    prescription_id,
+                    active_ingredient_code: ingredient.active_ingredient_code.as_deref(),
+                    order: ingredient.order.as_deref(),
+                    dose: ingredient.dose.as_deref(),
+                    dose_unit: ingredient.dose_unit.as_deref(),
+                    composition_dose: ingredient.composition_dose.as_deref(),
+                    composition_unit: ingredient.composition_unit.as_deref(),
+                    administration_dose: ingredient.administration_dose.as_deref(),
+                    administration_unit: ingredient.administration_unit.as_deref(),
+                    prescription_dose: ingredient.prescription_dose.as_deref(),
+                    prescription_unit: ingredient.prescription_unit.as_deref(),
+                })
+                .collect();
+
+            rows.admin_routes = form
+                .admin_routes
+                .iter()
+                .map(|route| AdminRouteRow {
+                    prescription_id,
+                    route_code: &route.route_code,
+                })
+                .collect();
+        }
+
+        for atc in &record.atc_codes {
+            rows.atc_codes.push(AtcRow {
+                prescription_id,
+                atc_code: &atc.atc_code,
+            });
+
+            rows.atc_duplicates
+                .extend(atc.duplicates.iter().map(|duplicate| AtcDuplicateRow {
+                    prescription_id,
+                    atc_code: &atc.atc_code,
+                    duplicate_atc: &duplicate.duplicate_atc,
+                    description: duplicate.description.as_deref(),
+                    effect: duplicate.effect.as_deref(),
+                    recommendation: duplicate.recommendation.as_deref(),
+                }));
+        }
+
+        rows.supply_problems = record
+            .supply_problems
+            .iter()
+            .map(|problem| SupplyProblemRow {
+                prescription_id,
+                start_date: problem.start_date.as_deref(),
+                observations: problem.observations.as_deref(),
+            })
+            .collect();
+
+        rows.excipients = record
+            .excipients
+            .iter()
+            .map(|excipient| ExcipientRow {
+                prescription_id,
+                excipient_code: &excipient.excipient_code,
+                quantity: excipient.quantity.as_deref(),
+                unit: excipient.unit.as_deref(),
+            })
+            .collect();
+
+        rows.notes = record
+            .notes
+            .iter()
+            .map(|note| NoteRow {
+                prescription_id,
+                note_type: note.note_type.as_deref(),
+                number: note.number.as_deref(),
+                reference: note.reference.as_deref(),
+                subject: note.subject.as_deref(),
+                date: note.date.as_deref(),
+                url: note.url.as_deref(),
+            })
+            .collect();
+
+        rows
+    }
+}
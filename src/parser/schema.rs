@@ -0,0 +1,662 @@
+//! Column layout of the tables produced by the nomenclator parsers.
+//!
+//! The column lists follow the order in which records are serialized, so they can be
+//! used to build typed schemas for the different output formats.
+
+use super::options::{HeaderStyle, PrescriptionKey};
+use super::{
+    ActiveIngridientRecord, AdministrationRouteRecord, AtcRecord, ContainerRecord,
+    ContainerUnitRecord, DcpRecord, DcpfRecord, DcsaRecord, ExcipientRecord, LaboratoryRecord,
+    PRESCRIPTION_ACTIVE_INGREDIENTS_CSV, PRESCRIPTION_ADMIN_ROUTES_CSV, PRESCRIPTION_ATC_CSV,
+    PRESCRIPTION_ATC_DUPLICATES_CSV, PRESCRIPTION_EXCIPIENTS_CSV, PRESCRIPTION_FORMS_CSV,
+    PRESCRIPTION_NOTES_CSV, PRESCRIPTION_SUPPLY_PROBLEMS_CSV, PRESCRIPTIONS_CSV,
+    PharmaceuticalFormRecord, PrescriptionRecord, RegistrationStatusRecord,
+    SimplifiedPharmaceuticalFormRecord,
+};
+use std::borrow::Cow;
+
+/// Logical type of a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Boolean,
+    Int32,
+    Text,
+    /// Calendar date, serialized as text in the source `dd/mm/yyyy` format
+    Date,
+}
+
+/// A single output column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    /// English name, as serialized from the record structs
+    pub name: &'static str,
+    /// Original AEMPS element name
+    pub wire_name: &'static str,
+    pub column_type: ColumnType,
+    pub nullable: bool,
+}
+
+impl Column {
+    /// Column name used in headers and DDL for the given style
+    pub fn header(&self, style: HeaderStyle) -> &'static str {
+        match style {
+            HeaderStyle::English => self.name,
+            HeaderStyle::Spanish => self.wire_name,
+        }
+    }
+
+    /// Sets the original AEMPS element name when it differs from the English one.
+    const fn wire(self, wire_name: &'static str) -> Self {
+        Column { wire_name, ..self }
+    }
+}
+
+const fn text(name: &'static str) -> Column {
+    Column {
+        name,
+        wire_name: name,
+        column_type: ColumnType::Text,
+        nullable: false,
+    }
+}
+
+const fn optional_text(name: &'static str) -> Column {
+    Column {
+        name,
+        wire_name: name,
+        column_type: ColumnType::Text,
+        nullable: true,
+    }
+}
+
+const fn optional_date(name: &'static str) -> Column {
+    Column {
+        name,
+        wire_name: name,
+        column_type: ColumnType::Date,
+        nullable: true,
+    }
+}
+
+const fn boolean(name: &'static str) -> Column {
+    Column {
+        name,
+        wire_name: name,
+        column_type: ColumnType::Boolean,
+        nullable: false,
+    }
+}
+
+const fn int32(name: &'static str) -> Column {
+    Column {
+        name,
+        wire_name: name,
+        column_type: ColumnType::Int32,
+        nullable: false,
+    }
+}
+
+/// Record types with a fixed column layout
+pub trait Columns {
+    /// Columns in serialization order
+    const COLUMNS: &'static [Column];
+}
+
+/// Column of one table referencing the primary key of another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForeignKey {
+    pub column: &'static str,
+    pub table: &'static str,
+    pub references: &'static str,
+}
+
+const fn fk(column: &'static str, table: &'static str, references: &'static str) -> ForeignKey {
+    ForeignKey {
+        column,
+        table,
+        references,
+    }
+}
+
+/// A named output table of the nomenclator parsers
+#[derive(Debug, Clone, Copy)]
+pub struct Table {
+    /// CSV file name of the table
+    pub file_name: &'static str,
+    pub columns: &'static [Column],
+    pub primary_key: Option<&'static str>,
+    pub foreign_keys: &'static [ForeignKey],
+    /// Whether the CSV file starts with a header row
+    pub has_header: bool,
+}
+
+impl Table {
+    /// Table name, i.e. the CSV file name without extension
+    pub fn name(&self) -> &'static str {
+        self.file_name.trim_end_matches(".csv")
+    }
+
+    /// Whether this is a prescription child table, keyed by the prescription in its
+    /// first column
+    fn is_prescription_child(&self) -> bool {
+        self.foreign_keys.first() == Some(&PRESCRIPTION_FK)
+    }
+
+    /// Columns as written with [`ParserOptions::prescription_key`](super::ParserOptions::prescription_key):
+    /// the prescription child tables start with the key columns of `key`.
+    pub fn columns_with_key(&self, key: PrescriptionKey) -> Cow<'static, [Column]> {
+        if self.is_prescription_child() {
+            prescription_child_columns(self.columns, key)
+        } else {
+            Cow::Borrowed(self.columns)
+        }
+    }
+
+    /// Foreign keys holding with `key`. `nro_definitivo` is not unique, so child tables
+    /// keyed by it alone do not reference `prescriptions`.
+    pub fn foreign_keys_with_key(&self, key: PrescriptionKey) -> &'static [ForeignKey] {
+        if key == PrescriptionKey::NroDefinitivo && self.is_prescription_child() {
+            &self.foreign_keys[1..]
+        } else {
+            self.foreign_keys
+        }
+    }
+}
+
+/// Columns of a prescription child table, such as [`PRESCRIPTION_FORM_COLUMNS`], with
+/// its leading `prescription_id` replaced by the key columns of `key`.
+pub fn prescription_child_columns(
+    columns: &'static [Column],
+    key: PrescriptionKey,
+) -> Cow<'static, [Column]> {
+    match key {
+        PrescriptionKey::CodNacion => Cow::Borrowed(columns),
+        _ => Cow::Owned(
+            prescription_key_columns(key)
+                .iter()
+                .chain(&columns[1..])
+                .copied()
+                .collect(),
+        ),
+    }
+}
+
+/// `cod_nacion` of the prescription in its child tables
+const PRESCRIPTION_ID: Column = text("prescription_id").wire("cod_nacion");
+/// `nro_definitivo` of the prescription in its child tables
+const NRO_DEFINITIVO: Column = text("nro_definitivo");
+
+/// Columns identifying the prescription at the start of every child table
+pub const fn prescription_key_columns(key: PrescriptionKey) -> &'static [Column] {
+    match key {
+        PrescriptionKey::CodNacion => &[PRESCRIPTION_ID],
+        PrescriptionKey::NroDefinitivo => &[NRO_DEFINITIVO],
+        PrescriptionKey::Both => &[PRESCRIPTION_ID, NRO_DEFINITIVO],
+    }
+}
+
+const fn dictionary(
+    file_name: &'static str,
+    columns: &'static [Column],
+    foreign_keys: &'static [ForeignKey],
+) -> Table {
+    Table {
+        file_name,
+        columns,
+        primary_key: Some("code"),
+        foreign_keys,
+        has_header: true,
+    }
+}
+
+const fn child(
+    file_name: &'static str,
+    columns: &'static [Column],
+    foreign_keys: &'static [ForeignKey],
+) -> Table {
+    Table {
+        file_name,
+        columns,
+        primary_key: None,
+        foreign_keys,
+        has_header: false,
+    }
+}
+
+impl Columns for AtcRecord {
+    const COLUMNS: &'static [Column] = &[
+        int32("number").wire("nroatc"),
+        text("code").wire("codigoatc"),
+        text("description").wire("descatc"),
+    ];
+}
+
+impl Columns for DcpRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("code").wire("codigodcp"),
+        text("name").wire("nombredcp"),
+        text("dcsa_code").wire("codigodcsa"),
+    ];
+}
+
+impl Columns for DcpfRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("code").wire("codigodcpf"),
+        text("name").wire("nombredcpf"),
+        text("dcp_code").wire("codigodcp"),
+    ];
+}
+
+impl Columns for DcsaRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("code").wire("codigodcsa"),
+        text("name").wire("nombredcsa"),
+    ];
+}
+
+impl Columns for ContainerRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("code").wire("codigoenvase"),
+        text("name").wire("envase"),
+    ];
+}
+
+impl Columns for ExcipientRecord {
+    const COLUMNS: &'static [Column] = &[text("code").wire("codigoedo"), text("name").wire("edo")];
+}
+
+impl Columns for PharmaceuticalFormRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("code").wire("codigoformafarmaceutica"),
+        text("name").wire("formafarmaceutica"),
+        optional_text("simplified_code").wire("codigoformafarmaceuticasimplificada"),
+    ];
+}
+
+impl Columns for SimplifiedPharmaceuticalFormRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("code").wire("codigoformafarmaceuticasimplificada"),
+        text("name").wire("formafarmaceuticasimplificada"),
+    ];
+}
+
+impl Columns for LaboratoryRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("code").wire("codigolaboratorio"),
+        text("name").wire("laboratorio"),
+        optional_text("address").wire("direccion"),
+        optional_text("zip").wire("codigopostal"),
+        optional_text("city").wire("localidad"),
+        optional_text("vat").wire("cif"),
+    ];
+}
+
+impl Columns for ActiveIngridientRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("number").wire("nroprincipioactivo"),
+        text("code").wire("codigoprincipioactivo"),
+        text("name").wire("principioactivo"),
+    ];
+}
+
+impl Columns for RegistrationStatusRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("code").wire("codigosituacionregistro"),
+        text("name").wire("situacionregistro"),
+    ];
+}
+
+impl Columns for ContainerUnitRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("code").wire("codigounidadcontenido"),
+        text("name").wire("unidadcontenido"),
+    ];
+}
+
+impl Columns for AdministrationRouteRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("code").wire("codigoviaadministracion"),
+        text("name").wire("viaadministracion"),
+    ];
+}
+
+impl Columns for PrescriptionRecord {
+    const COLUMNS: &'static [Column] = &[
+        text("cod_nacion"),
+        text("nro_definitivo"),
+        text("des_nomco"),
+        text("des_prese"),
+        optional_text("cod_dcsa"),
+        optional_text("cod_dcp"),
+        optional_text("cod_dcpf"),
+        optional_text("des_dosific"),
+        optional_text("cod_envase"),
+        optional_text("contenido"),
+        optional_text("unid_contenido"),
+        optional_text("nro_conte"),
+        boolean("sw_psicotropo"),
+        boolean("sw_estupefaciente"),
+        boolean("sw_afecta_conduccion"),
+        boolean("sw_triangulo_negro"),
+        optional_text("url_fictec"),
+        optional_text("url_prosp"),
+        boolean("sw_receta"),
+        boolean("sw_generico"),
+        boolean("sw_sustituible"),
+        boolean("sw_envase_clinico"),
+        boolean("sw_uso_hospitalario"),
+        boolean("sw_diagnostico_hospitalario"),
+        boolean("sw_tld"),
+        boolean("sw_especial_control_medico"),
+        boolean("sw_huerfano"),
+        boolean("sw_base_a_plantas"),
+        optional_text("laboratorio_titular"),
+        optional_text("laboratorio_comercializador"),
+        optional_date("fecha_autorizacion"),
+        boolean("sw_comercializado"),
+        optional_date("fec_comer"),
+        optional_text("cod_sitreg"),
+        optional_text("cod_sitreg_presen"),
+        optional_date("fecha_situacion_registro"),
+        optional_date("fec_sitreg_presen"),
+        boolean("sw_tiene_excipientes_decl_obligatoria"),
+        boolean("biosimilar"),
+        boolean("importacion_paralela"),
+        boolean("radiofarmaco"),
+        boolean("serializacion"),
+    ];
+}
+
+/// Columns of `prescription_forms.csv`
+pub const PRESCRIPTION_FORM_COLUMNS: &[Column] = &[
+    PRESCRIPTION_ID,
+    text("form_code").wire("cod_forfar"),
+    optional_text("simplified_form_code").wire("cod_forfar_simplificada"),
+    optional_text("num_active_ingredients").wire("nro_pactiv"),
+];
+
+/// Columns of `prescription_active_ingredients.csv`
+pub const PRESCRIPTION_ACTIVE_INGREDIENT_COLUMNS: &[Column] = &[
+    PRESCRIPTION_ID,
+    optional_text("active_ingredient_code").wire("cod_principio_activo"),
+    optional_text("order").wire("orden_colacion"),
+    optional_text("dose").wire("dosis_pa"),
+    optional_text("dose_unit").wire("unidad_dosis_pa"),
+    optional_text("composition_dose").wire("dosis_composicion"),
+    optional_text("composition_unit").wire("unidad_composicion"),
+    optional_text("administration_dose").wire("dosis_administracion"),
+    optional_text("administration_unit").wire("unidad_administracion"),
+    optional_text("prescription_dose").wire("dosis_prescripcion"),
+    optional_text("prescription_unit").wire("unidad_prescripcion"),
+];
+
+/// Columns of `prescription_admin_routes.csv`
+pub const PRESCRIPTION_ADMIN_ROUTE_COLUMNS: &[Column] =
+    &[PRESCRIPTION_ID, text("route_code").wire("cod_via_admin")];
+
+/// Columns of `prescription_atc.csv`
+pub const PRESCRIPTION_ATC_COLUMNS: &[Column] =
+    &[PRESCRIPTION_ID, text("atc_code").wire("cod_atc")];
+
+/// Columns of `prescription_atc_duplicates.csv`
+pub const PRESCRIPTION_ATC_DUPLICATE_COLUMNS: &[Column] = &[
+    PRESCRIPTION_ID,
+    text("atc_code").wire("cod_atc"),
+    text("duplicate_atc").wire("atc_duplicidad"),
+    optional_text("description").wire("descripcion_atc_duplicidad"),
+    optional_text("effect").wire("efecto_duplicidad"),
+    optional_text("recommendation").wire("recomendacion_duplicidad"),
+];
+
+/// Columns of `prescription_supply_problems.csv`
+pub const PRESCRIPTION_SUPPLY_PROBLEM_COLUMNS: &[Column] = &[
+    PRESCRIPTION_ID,
+    optional_date("start_date").wire("fecha_inicio"),
+    optional_text("observations").wire("observaciones"),
+];
+
+/// Columns of `prescription_excipients.csv`
+pub const PRESCRIPTION_EXCIPIENT_COLUMNS: &[Column] = &[
+    PRESCRIPTION_ID,
+    text("excipient_code").wire("cod_excipiente"),
+    optional_text("quantity").wire("cantidad"),
+    optional_text("unit").wire("unidad"),
+];
+
+/// Columns of `prescription_notes.csv`
+pub const PRESCRIPTION_NOTE_COLUMNS: &[Column] = &[
+    PRESCRIPTION_ID,
+    optional_text("note_type").wire("tipo_nota"),
+    optional_text("number").wire("num_nota"),
+    optional_text("reference").wire("referencia_nota"),
+    optional_text("subject").wire("asunto_nota"),
+    optional_text("date").wire("fecha_nota"),
+    optional_text("url").wire("url_nota"),
+];
+
+/// Every dictionary table, keyed by `code`, in dependency order
+pub const DICTIONARY_TABLES: [Table; 13] = [
+    dictionary("atc.csv", AtcRecord::COLUMNS, &[]),
+    dictionary("dcsa.csv", DcsaRecord::COLUMNS, &[]),
+    dictionary(
+        "dcp.csv",
+        DcpRecord::COLUMNS,
+        &[fk("dcsa_code", "dcsa", "code")],
+    ),
+    dictionary(
+        "dcpf.csv",
+        DcpfRecord::COLUMNS,
+        &[fk("dcp_code", "dcp", "code")],
+    ),
+    dictionary("envases.csv", ContainerRecord::COLUMNS, &[]),
+    dictionary("excipientes.csv", ExcipientRecord::COLUMNS, &[]),
+    dictionary(
+        "forma_farmaceutica_simplificada.csv",
+        SimplifiedPharmaceuticalFormRecord::COLUMNS,
+        &[],
+    ),
+    dictionary(
+        "forma_farmaceutica.csv",
+        PharmaceuticalFormRecord::COLUMNS,
+        &[fk(
+            "simplified_code",
+            "forma_farmaceutica_simplificada",
+            "code",
+        )],
+    ),
+    dictionary("laboratorios.csv", LaboratoryRecord::COLUMNS, &[]),
+    dictionary(
+        "principios_activos.csv",
+        ActiveIngridientRecord::COLUMNS,
+        &[],
+    ),
+    dictionary(
+        "situacion_registro.csv",
+        RegistrationStatusRecord::COLUMNS,
+        &[],
+    ),
+    dictionary("unidad_contenido.csv", ContainerUnitRecord::COLUMNS, &[]),
+    dictionary(
+        "vias_administracion.csv",
+        AdministrationRouteRecord::COLUMNS,
+        &[],
+    ),
+];
+
+const PRESCRIPTION_FK: ForeignKey = fk("prescription_id", "prescriptions", "cod_nacion");
+
+/// Every table generated from Prescripcion.xml, in the order of
+/// [`PRESCRIPTION_CSV_FILES`](super::PRESCRIPTION_CSV_FILES)
+pub const PRESCRIPTION_TABLES: [Table; 9] = [
+    Table {
+        file_name: PRESCRIPTIONS_CSV,
+        columns: PrescriptionRecord::COLUMNS,
+        primary_key: Some("cod_nacion"),
+        foreign_keys: &[
+            fk("cod_dcsa", "dcsa", "code"),
+            fk("cod_dcp", "dcp", "code"),
+            fk("cod_dcpf", "dcpf", "code"),
+            fk("cod_envase", "envases", "code"),
+            fk("unid_contenido", "unidad_contenido", "code"),
+            fk("laboratorio_titular", "laboratorios", "code"),
+            fk("laboratorio_comercializador", "laboratorios", "code"),
+            fk("cod_sitreg", "situacion_registro", "code"),
+            fk("cod_sitreg_presen", "situacion_registro", "code"),
+        ],
+        has_header: true,
+    },
+    child(
+        PRESCRIPTION_FORMS_CSV,
+        PRESCRIPTION_FORM_COLUMNS,
+        &[
+            PRESCRIPTION_FK,
+            fk("form_code", "forma_farmaceutica", "code"),
+            fk(
+                "simplified_form_code",
+                "forma_farmaceutica_simplificada",
+                "code",
+            ),
+        ],
+    ),
+    child(
+        PRESCRIPTION_ACTIVE_INGREDIENTS_CSV,
+        PRESCRIPTION_ACTIVE_INGREDIENT_COLUMNS,
+        &[PRESCRIPTION_FK],
+    ),
+    child(
+        PRESCRIPTION_ADMIN_ROUTES_CSV,
+        PRESCRIPTION_ADMIN_ROUTE_COLUMNS,
+        &[
+            PRESCRIPTION_FK,
+            fk("route_code", "vias_administracion", "code"),
+        ],
+    ),
+    child(
+        PRESCRIPTION_ATC_CSV,
+        PRESCRIPTION_ATC_COLUMNS,
+        &[PRESCRIPTION_FK, fk("atc_code", "atc", "code")],
+    ),
+    child(
+        PRESCRIPTION_ATC_DUPLICATES_CSV,
+        PRESCRIPTION_ATC_DUPLICATE_COLUMNS,
+        &[
+            PRESCRIPTION_FK,
+            fk("atc_code", "atc", "code"),
+            fk("duplicate_atc", "atc", "code"),
+        ],
+    ),
+    child(
+        PRESCRIPTION_SUPPLY_PROBLEMS_CSV,
+        PRESCRIPTION_SUPPLY_PROBLEM_COLUMNS,
+        &[PRESCRIPTION_FK],
+    ),
+    child(
+        PRESCRIPTION_EXCIPIENTS_CSV,
+        PRESCRIPTION_EXCIPIENT_COLUMNS,
+        &[PRESCRIPTION_FK, fk("excipient_code", "excipientes", "code")],
+    ),
+    child(
+        PRESCRIPTION_NOTES_CSV,
+        PRESCRIPTION_NOTE_COLUMNS,
+        &[PRESCRIPTION_FK],
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    fn csv_header<T: Serialize>(record: &T) -> Vec<String> {
+        let mut wtr = csv::Writer::from_writer(Vec::new());
+        wtr.serialize(record).unwrap();
+        let output = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        let header = output.lines().next().unwrap();
+        header.split(',').map(str::to_string).collect()
+    }
+
+    fn names(columns: &[Column]) -> Vec<String> {
+        columns.iter().map(|c| c.name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_columns_match_serialization_order() {
+        let atc = AtcRecord {
+            number: 1,
+            code: "A01".to_string(),
+            description: "DIGESTIVE".to_string(),
+        };
+        assert_eq!(csv_header(&atc), names(AtcRecord::COLUMNS));
+
+        let laboratory = LaboratoryRecord {
+            code: "1".to_string(),
+            name: "LAB".to_string(),
+            address: None,
+            zip: None,
+            city: None,
+            vat: None,
+        };
+        assert_eq!(csv_header(&laboratory), names(LaboratoryRecord::COLUMNS));
+
+        let form = PharmaceuticalFormRecord {
+            code: "10".to_string(),
+            name: "COMPRIMIDO".to_string(),
+            simplified_code: None,
+        };
+        assert_eq!(csv_header(&form), names(PharmaceuticalFormRecord::COLUMNS));
+    }
+
+    #[test]
+    fn test_prescription_columns_match_serialization_order() {
+        let xml = format!(
+            "<aemps_prescripcion>{}</aemps_prescripcion>",
+            super::super::tests::prescription_xml("600000", "")
+        );
+        let list = super::super::parse_prescription_xml_from_reader(xml.as_bytes()).unwrap();
+
+        assert_eq!(
+            csv_header(&list.records[0]),
+            names(PrescriptionRecord::COLUMNS)
+        );
+    }
+
+    #[test]
+    fn test_foreign_keys_reference_known_tables() {
+        let tables: Vec<&Table> = DICTIONARY_TABLES
+            .iter()
+            .chain(PRESCRIPTION_TABLES.iter())
+            .collect();
+        for (position, table) in tables.iter().enumerate() {
+            for key in table.foreign_keys {
+                assert!(
+                    table.columns.iter().any(|c| c.name == key.column),
+                    "{}.{}",
+                    table.name(),
+                    key.column
+                );
+                // Referenced tables come first so they can be loaded in this order
+                let referenced = tables[..position]
+                    .iter()
+                    .find(|t| t.name() == key.table)
+                    .unwrap_or_else(|| panic!("{} references {}", table.name(), key.table));
+                assert_eq!(referenced.primary_key, Some(key.references));
+            }
+        }
+    }
+
+    #[test]
+    fn test_wire_names_are_unique_per_table() {
+        for table in DICTIONARY_TABLES.iter().chain(PRESCRIPTION_TABLES.iter()) {
+            let mut wire_names: Vec<_> = table.columns.iter().map(|c| c.wire_name).collect();
+            wire_names.sort_unstable();
+            wire_names.dedup();
+            assert_eq!(wire_names.len(), table.columns.len(), "{}", table.name());
+        }
+    }
+
+    #[test]
+    fn test_prescription_tables_follow_csv_files() {
+        let files: Vec<_> = PRESCRIPTION_TABLES.iter().map(|t| t.file_name).collect();
+        assert_eq!(files, super::super::PRESCRIPTION_CSV_FILES);
+    }
+}
@@ -0,0 +1,116 @@
+//! Caller-supplied writers for the prescription CSV files.
+
+use super::options::ParserOptions;
+use super::report::ParseReport;
+use super::{PRESCRIPTION_CSV_FILES, parse_prescription_xml_to_csvs_from_reader_with_options};
+use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
+
+/// One writer per file of [`PRESCRIPTION_CSV_FILES`], to stream the prescription CSVs
+/// somewhere other than a directory, such as an upload or a compressor.
+///
+/// [`parse_prescription_xml_to_csvs_from_reader`](super::parse_prescription_xml_to_csvs_from_reader)
+/// takes a factory keyed by file name instead.
+pub struct PrescriptionSinks<W = Box<dyn Write>> {
+    /// `prescriptions.csv`
+    pub prescriptions: W,
+    /// `prescription_forms.csv`
+    pub forms: W,
+    /// `prescription_active_ingredients.csv`
+    pub active_ingredients: W,
+    /// `prescription_admin_routes.csv`
+    pub admin_routes: W,
+    /// `prescription_atc.csv`
+    pub atc: W,
+    /// `prescription_atc_duplicates.csv`
+    pub atc_duplicates: W,
+    /// `prescription_supply_problems.csv`
+    pub supply_problems: W,
+    /// `prescription_excipients.csv`
+    pub excipients: W,
+    /// `prescription_notes.csv`
+    pub notes: W,
+}
+
+impl<W> PrescriptionSinks<W> {
+    /// Creates every sink with `make_writer`, called with each name of
+    /// [`PRESCRIPTION_CSV_FILES`] in order.
+    pub fn from_fn(mut make_writer: impl FnMut(&str) -> Result<W>) -> Result<Self> {
+        let [
+            prescriptions,
+            forms,
+            active_ingredients,
+            admin_routes,
+            atc,
+            atc_duplicates,
+            supply_problems,
+            excipients,
+            notes,
+        ] = PRESCRIPTION_CSV_FILES;
+        Ok(PrescriptionSinks {
+            prescriptions: make_writer(prescriptions)?,
+            forms: make_writer(forms)?,
+            active_ingredients: make_writer(active_ingredients)?,
+            admin_routes: make_writer(admin_routes)?,
+            atc: make_writer(atc)?,
+            atc_duplicates: make_writer(atc_duplicates)?,
+            supply_problems: make_writer(supply_problems)?,
+            excipients: make_writer(excipients)?,
+            notes: make_writer(notes)?,
+        })
+    }
+
+    /// The sinks in [`PRESCRIPTION_CSV_FILES`] order
+    pub fn each_mut(&mut self) -> [&mut W; PRESCRIPTION_CSV_FILES.len()] {
+        [
+            &mut self.prescriptions,
+            &mut self.forms,
+            &mut self.active_ingredients,
+            &mut self.admin_routes,
+            &mut self.atc,
+            &mut self.atc_duplicates,
+            &mut self.supply_problems,
+            &mut self.excipients,
+            &mut self.notes,
+        ]
+    }
+}
+
+/// Parses Prescription XML from a buffered reader and writes the normalized CSV files
+/// to `sinks`.
+pub fn parse_prescription_xml_to_sinks<R: BufRead, W: Write>(
+    reader: R,
+    sinks: &mut PrescriptionSinks<W>,
+) -> Result<()> {
+    parse_prescription_xml_to_sinks_with_options(reader, sinks, &ParserOptions::default())?;
+    Ok(())
+}
+
+/// Parses Prescription XML from a buffered reader and writes the normalized CSV files,
+/// formatted after `options`, to `sinks`.
+///
+/// [`ParserOptions::max_rows_per_file`] is ignored, as every file has a single sink.
+pub fn parse_prescription_xml_to_sinks_with_options<R: BufRead, W: Write>(
+    reader: R,
+    sinks: &mut PrescriptionSinks<W>,
+    options: &ParserOptions,
+) -> Result<ParseReport> {
+    let options = ParserOptions {
+        max_rows_per_file: None,
+        ..options.clone()
+    };
+    let mut slots = sinks.each_mut().map(Some);
+    parse_prescription_xml_to_csvs_from_reader_with_options(
+        reader,
+        |name| {
+            let index = PRESCRIPTION_CSV_FILES
+                .iter()
+                .position(|file| *file == name)
+                .with_context(|| format!("No sink for {name}"))?;
+            slots[index]
+                .take()
+                .with_context(|| format!("Sink for {name} requested twice"))
+        },
+        &options,
+    )
+}
@@ -0,0 +1,190 @@
+//! Sorting of rendered CSV rows, see
+//! [`ParserOptions::sort_output`](super::ParserOptions::sort_output).
+
+use super::schema::Column;
+use std::cmp::Ordering;
+use std::ops::Range;
+
+/// Positions of the columns named `names` among `columns`, in order.
+pub(crate) fn key_columns(columns: &[Column], names: &[&str]) -> Vec<usize> {
+    names
+        .iter()
+        .filter_map(|name| columns.iter().position(|column| column.name == *name))
+        .collect()
+}
+
+/// Rows of a CSV file held until they can be written in key order
+///
+/// Only the rendered rows and their key fields are kept, not the parsed records, so
+/// the memory used is about the size of the file being written.
+pub(crate) struct SortedRows {
+    /// Columns compared first, in order
+    columns: Vec<usize>,
+    delimiter: u8,
+    bytes: Vec<u8>,
+    /// Ranges of the complete rows in `bytes`
+    rows: Vec<Range<usize>>,
+    /// Start of the row being written
+    row_start: usize,
+}
+
+impl SortedRows {
+    pub(crate) fn new(columns: &[usize], delimiter: u8) -> Self {
+        SortedRows {
+            columns: columns.to_vec(),
+            delimiter,
+            bytes: Vec::new(),
+            rows: Vec::new(),
+            row_start: 0,
+        }
+    }
+
+    /// Adds `bytes` to the current row, which they end when `ends_row` is set.
+    pub(crate) fn push(&mut self, bytes: &[u8], ends_row: bool) {
+        self.bytes.extend_from_slice(bytes);
+        if ends_row {
+            self.rows.push(self.row_start..self.bytes.len());
+            self.row_start = self.bytes.len();
+        }
+    }
+
+    /// Rows ordered by the key columns, then by their whole content, so the order does
+    /// not depend on the input order. Numbers compare by value.
+    pub(crate) fn into_sorted(mut self) -> (Vec<u8>, Vec<Range<usize>>) {
+        if self.row_start < self.bytes.len() {
+            self.rows.push(self.row_start..self.bytes.len());
+        }
+        let mut keyed: Vec<(Vec<Vec<u8>>, Range<usize>)> = self
+            .rows
+            .into_iter()
+            .map(|row| {
+                let key = key_fields(&self.bytes[row.clone()], self.delimiter, &self.columns);
+                (key, row)
+            })
+            .collect();
+        let bytes = self.bytes;
+        keyed.sort_by(|(key, row), (other_key, other_row)| {
+            key.iter()
+                .zip(other_key)
+                .map(|(field, other)| compare_fields(field, other))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| bytes[row.clone()].cmp(&bytes[other_row.clone()]))
+        });
+        let rows = keyed.into_iter().map(|(_, row)| row).collect();
+        (bytes, rows)
+    }
+}
+
+/// Unquoted values of the fields of `row` at `columns`, empty for missing fields.
+fn key_fields(row: &[u8], delimiter: u8, columns: &[usize]) -> Vec<Vec<u8>> {
+    let mut fields: Vec<Vec<u8>> = vec![Vec::new()];
+    let (mut in_quotes, mut previous_quote) = (false, false);
+    let last = columns.iter().max().copied().unwrap_or(0);
+    for &byte in row {
+        if in_quotes {
+            if byte == b'"' {
+                in_quotes = false;
+                previous_quote = true;
+            } else {
+                fields.last_mut().unwrap().push(byte);
+            }
+            continue;
+        }
+        match byte {
+            // A doubled quote inside a quoted field
+            b'"' if previous_quote => {
+                fields.last_mut().unwrap().push(b'"');
+                in_quotes = true;
+            }
+            b'"' => in_quotes = true,
+            b'\r' | b'\n' => break,
+            byte if byte == delimiter => {
+                if fields.len() > last {
+                    break;
+                }
+                fields.push(Vec::new());
+            }
+            byte => fields.last_mut().unwrap().push(byte),
+        }
+        previous_quote = false;
+    }
+    columns
+        .iter()
+        .map(|&column| fields.get(column).cloned().unwrap_or_default())
+        .collect()
+}
+
+/// Compares two fields as integers when both are, and as bytes otherwise.
+pub(crate) fn compare_fields(field: &[u8], other: &[u8]) -> Ordering {
+    let is_number = |value: &[u8]| !value.is_empty() && value.iter().all(u8::is_ascii_digit);
+    if is_number(field) && is_number(other) {
+        let trim = |value: &[u8]| {
+            let zeros = value.iter().take_while(|b| **b == b'0').count();
+            value[zeros..].to_vec()
+        };
+        let (field, other) = (trim(field), trim(other));
+        field
+            .len()
+            .cmp(&other.len())
+            .then_with(|| field.cmp(&other))
+    } else {
+        field.cmp(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(columns: &[usize], rows: &[&str]) -> Vec<String> {
+        let mut sorted_rows = SortedRows::new(columns, b',');
+        for row in rows {
+            sorted_rows.push(row.as_bytes(), row.ends_with('\n'));
+        }
+        let (bytes, rows) = sorted_rows.into_sorted();
+        rows.into_iter()
+            .map(|row| String::from_utf8(bytes[row].to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_key_fields() {
+        let row = b"1,\"a,\"\"b\"\"\",,x\r\n";
+        assert_eq!(
+            key_fields(row, b',', &[1, 0, 2, 3, 7]),
+            [
+                b"a,\"b\"".to_vec(),
+                b"1".to_vec(),
+                vec![],
+                b"x".to_vec(),
+                vec![]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rows_sorted_by_key_then_content() {
+        assert_eq!(
+            sorted(
+                &[1],
+                &[
+                    "b,10\n",
+                    "a,9\n",
+                    "\"multi\nline\",10\n",
+                    "c,2\n",
+                    "a,010\n"
+                ]
+            ),
+            [
+                "c,2\n",
+                "a,9\n",
+                "\"multi\nline\",10\n",
+                "a,010\n",
+                "b,10\n"
+            ]
+        );
+        // A last row without a line feed is kept
+        assert_eq!(sorted(&[0], &["2\n", "1"]), ["1", "2\n"]);
+    }
+}
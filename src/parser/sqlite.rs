@@ -0,0 +1,621 @@
+//! SQLite output for the nomenclator parsers (requires the `sqlite` feature).
+
+use super::load::CsvRows;
+use super::options::{ParserOptions, PrescriptionKey};
+use super::rows::PrescriptionRows;
+use super::schema::{
+    Column, ColumnType, Columns, DICTIONARY_TABLES, PRESCRIPTION_ACTIVE_INGREDIENT_COLUMNS,
+    PRESCRIPTION_ADMIN_ROUTE_COLUMNS, PRESCRIPTION_ATC_COLUMNS, PRESCRIPTION_ATC_DUPLICATE_COLUMNS,
+    PRESCRIPTION_EXCIPIENT_COLUMNS, PRESCRIPTION_FORM_COLUMNS, PRESCRIPTION_NOTE_COLUMNS,
+    PRESCRIPTION_SUPPLY_PROBLEM_COLUMNS, PRESCRIPTION_TABLES, Table,
+};
+use super::{DictionaryKind, PrescriptionReader, PrescriptionRecord, open_xml};
+use anyhow::{Context, Result};
+use rusqlite::types::Value;
+use rusqlite::{Connection, Statement, Transaction, params_from_iter};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::path::Path;
+
+/// Loads the records of a dictionary XML file into its table
+type DictionaryLoader = fn(&Transaction, &str, &Path) -> Result<usize>;
+
+/// Source XML file and loader of each table in [`DICTIONARY_TABLES`], in the same order
+const DICTIONARY_LOADERS: [(&str, DictionaryLoader); 13] = [
+    (
+        DictionaryKind::Atc.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_atc_xml(path)?),
+    ),
+    (
+        DictionaryKind::Dcsa.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_dcsa_xml(path)?),
+    ),
+    (
+        DictionaryKind::Dcp.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_dcp_xml(path)?),
+    ),
+    (
+        DictionaryKind::Dcpf.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_dcpf_xml(path)?),
+    ),
+    (
+        DictionaryKind::Containers.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_envases_xml(path)?),
+    ),
+    (
+        DictionaryKind::Excipients.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_excipientes_xml(path)?),
+    ),
+    (
+        DictionaryKind::SimplifiedForms.default_xml_filename(),
+        |tx, table, path| {
+            insert_records(
+                tx,
+                table,
+                &super::parse_forma_farmaceutica_simplificada_xml(path)?,
+            )
+        },
+    ),
+    (
+        DictionaryKind::PharmaceuticalForms.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_forma_farmaceutica_xml(path)?),
+    ),
+    (
+        DictionaryKind::Laboratories.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_laboratorio_xml(path)?),
+    ),
+    (
+        DictionaryKind::ActiveIngredients.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_principio_activo_xml(path)?),
+    ),
+    (
+        DictionaryKind::RegistrationStatuses.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_situacion_registro_xml(path)?),
+    ),
+    (
+        DictionaryKind::ContainerUnits.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_unidad_contenido_xml(path)?),
+    ),
+    (
+        DictionaryKind::AdministrationRoutes.default_xml_filename(),
+        |tx, table, path| insert_records(tx, table, &super::parse_via_administracion_xml(path)?),
+    ),
+];
+
+/// Builds the `CREATE TABLE` and `CREATE INDEX` statements of a table.
+///
+/// Every foreign key column is indexed, as those are the usual lookup and join columns.
+fn create_table_sql(table: &Table) -> String {
+    let name = table.name();
+    let mut definitions: Vec<String> = table
+        .columns
+        .iter()
+        .map(|column| {
+            let sql_type = match column.column_type {
+                ColumnType::Boolean | ColumnType::Int32 => "INTEGER",
+                ColumnType::Text | ColumnType::Date => "TEXT",
+            };
+            let mut definition = format!("\"{}\" {}", column.name, sql_type);
+            if !column.nullable {
+                definition.push_str(" NOT NULL");
+            }
+            if table.primary_key == Some(column.name) {
+                definition.push_str(" PRIMARY KEY");
+            }
+            definition
+        })
+        .collect();
+    definitions.extend(table.foreign_keys.iter().map(|key| {
+        format!(
+            "FOREIGN KEY (\"{}\") REFERENCES {} (\"{}\")",
+            key.column, key.table, key.references
+        )
+    }));
+
+    let mut sql = format!(
+        "DROP TABLE IF EXISTS {name};\nCREATE TABLE {name} (\n    {}\n);\n",
+        definitions.join(",\n    ")
+    );
+    for key in table.foreign_keys {
+        sql.push_str(&format!(
+            "CREATE INDEX idx_{name}_{column} ON {name} (\"{column}\");\n",
+            column = key.column
+        ));
+    }
+    sql
+}
+
+/// Prepares an `INSERT` statement for all the columns of a table.
+fn insert_statement<'a>(
+    tx: &'a Transaction,
+    table: &str,
+    columns: &[Column],
+) -> Result<Statement<'a>> {
+    let names: Vec<String> = columns.iter().map(|c| format!("\"{}\"", c.name)).collect();
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{i}")).collect();
+    let sql = format!(
+        "INSERT INTO {table} ({}) VALUES ({})",
+        names.join(", "),
+        placeholders.join(", ")
+    );
+    tx.prepare(&sql)
+        .with_context(|| format!("Failed to prepare insert into {table}"))
+}
+
+/// Inserts a serialized row, binding its fields in `columns` order.
+fn insert_row<T: Serialize>(statement: &mut Statement, columns: &[Column], row: &T) -> Result<()> {
+    let fields = match serde_json::to_value(row)? {
+        serde_json::Value::Object(fields) => fields,
+        _ => anyhow::bail!("Expected a record serializing to an object"),
+    };
+    let values = columns.iter().map(|column| match fields.get(column.name) {
+        Some(serde_json::Value::Bool(value)) => Value::Integer(i64::from(*value)),
+        Some(serde_json::Value::Number(value)) => {
+            value.as_i64().map(Value::Integer).unwrap_or(Value::Null)
+        }
+        Some(serde_json::Value::String(value)) => Value::Text(value.clone()),
+        _ => Value::Null,
+    });
+    statement.execute(params_from_iter(values))?;
+    Ok(())
+}
+
+/// Inserts dictionary records into `table`, returning the number of rows written.
+fn insert_records<T: Serialize + Columns>(
+    tx: &Transaction,
+    table: &str,
+    records: &[T],
+) -> Result<usize> {
+    let mut statement = insert_statement(tx, table, T::COLUMNS)?;
+    for record in records {
+        insert_row(&mut statement, T::COLUMNS, record)
+            .with_context(|| format!("Failed to insert into {table}"))?;
+    }
+    Ok(records.len())
+}
+
+/// Creates every nomenclator table in `conn`, dropping any previous version.
+fn create_tables(conn: &Connection) -> Result<()> {
+    let sql: String = DICTIONARY_TABLES
+        .iter()
+        .chain(PRESCRIPTION_TABLES.iter())
+        .map(create_table_sql)
+        .collect();
+    conn.execute_batch(&sql)
+        .context("Failed to create nomenclator tables")
+}
+
+/// Streams Prescripcion.xml into the nine prescription tables.
+fn load_prescriptions(tx: &Transaction, xml_path: &Path) -> Result<usize> {
+    let statement = |index: usize| {
+        let table = &PRESCRIPTION_TABLES[index];
+        insert_statement(tx, table.name(), table.columns)
+    };
+    let mut main = statement(0)?;
+    let mut forms = statement(1)?;
+    let mut ingredients = statement(2)?;
+    let mut routes = statement(3)?;
+    let mut atc = statement(4)?;
+    let mut duplicates = statement(5)?;
+    let mut supply = statement(6)?;
+    let mut excipients = statement(7)?;
+    let mut notes = statement(8)?;
+
+    let mut count = 0;
+    for record in PrescriptionReader::new(open_xml(xml_path)?) {
+        let record: PrescriptionRecord = record?;
+        insert_row(&mut main, PrescriptionRecord::COLUMNS, &record)
+            .with_context(|| format!("Failed to insert prescription {}", record.cod_nacion))?;
+
+        let rows = PrescriptionRows::from(&record);
+        if let Some(form) = &rows.forms {
+            insert_row(&mut forms, PRESCRIPTION_FORM_COLUMNS, form)?;
+        }
+        for row in &rows.active_ingredients {
+            insert_row(
+                &mut ingredients,
+                PRESCRIPTION_ACTIVE_INGREDIENT_COLUMNS,
+                row,
+            )?;
+        }
+        for row in &rows.admin_routes {
+            insert_row(&mut routes, PRESCRIPTION_ADMIN_ROUTE_COLUMNS, row)?;
+        }
+        for row in &rows.atc_codes {
+            insert_row(&mut atc, PRESCRIPTION_ATC_COLUMNS, row)?;
+        }
+        for row in &rows.atc_duplicates {
+            insert_row(&mut duplicates, PRESCRIPTION_ATC_DUPLICATE_COLUMNS, row)?;
+        }
+        for row in &rows.supply_problems {
+            insert_row(&mut supply, PRESCRIPTION_SUPPLY_PROBLEM_COLUMNS, row)?;
+        }
+        for row in &rows.excipients {
+            insert_row(&mut excipients, PRESCRIPTION_EXCIPIENT_COLUMNS, row)?;
+        }
+        for row in &rows.notes {
+            insert_row(&mut notes, PRESCRIPTION_NOTE_COLUMNS, row)?;
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Loads every nomenclator XML file found in `work_dir` into `conn`.
+///
+/// Creates one table per dictionary (keyed by `code`) and the nine prescription
+/// tables (`prescriptions` keyed by `cod_nacion`), declaring foreign keys from the
+/// prescription tables to the dictionaries and indexing them. Foreign key enforcement
+/// is switched off while loading, since the published files are not always
+/// consistent with each other, and restored afterwards.
+///
+/// Each dictionary is inserted in its own transaction, and the prescription tables
+/// share a single one. Missing XML files leave their tables empty.
+pub fn load_nomenclator_into_sqlite<P: AsRef<Path>>(
+    work_dir: P,
+    conn: &mut Connection,
+) -> Result<()> {
+    let work_dir = work_dir.as_ref();
+    without_foreign_keys(conn, |conn| load_tables(work_dir, conn))
+}
+
+/// Runs `load` with foreign key enforcement switched off, restoring it afterwards.
+fn without_foreign_keys<T>(
+    conn: &mut Connection,
+    load: impl FnOnce(&mut Connection) -> Result<T>,
+) -> Result<T> {
+    let enforce_foreign_keys: bool = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))?;
+    conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+    let result = load(conn);
+    if enforce_foreign_keys {
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+    }
+    result
+}
+
+/// Creates the tables and inserts every XML file found in `work_dir`.
+fn load_tables(work_dir: &Path, conn: &mut Connection) -> Result<()> {
+    create_tables(conn)?;
+
+    for (table, (xml_name, load)) in DICTIONARY_TABLES.iter().zip(DICTIONARY_LOADERS) {
+        let xml_path = work_dir.join(xml_name);
+        if !xml_path.exists() {
+            tracing::warn!(file = xml_name, "File not found, skipping");
+            continue;
+        }
+
+        let tx = conn.transaction()?;
+        let count = load(&tx, table.name(), &xml_path)
+            .with_context(|| format!("Failed to load {}", xml_name))?;
+        tx.commit()?;
+        tracing::info!(table = table.name(), rows = count, "Loaded dictionary");
+    }
+
+    let xml_path = work_dir.join("Prescripcion.xml");
+    if xml_path.exists() {
+        let tx = conn.transaction()?;
+        let count =
+            load_prescriptions(&tx, &xml_path).context("Failed to load Prescripcion.xml")?;
+        tx.commit()?;
+        tracing::info!(rows = count, "Loaded prescriptions");
+    } else {
+        tracing::warn!("Prescripcion.xml not found, skipping");
+    }
+
+    Ok(())
+}
+
+/// Parses every nomenclator XML file in `work_dir` into the SQLite database at `db_path`.
+///
+/// See [`load_nomenclator_into_sqlite`] for the created tables.
+pub fn parse_nomenclator_to_sqlite<P: AsRef<Path>>(work_dir: P, db_path: P) -> Result<()> {
+    let db_path = db_path.as_ref();
+    let mut conn = Connection::open(db_path)
+        .with_context(|| format!("Failed to open {}", db_path.display()))?;
+    load_nomenclator_into_sqlite(work_dir, &mut conn)
+}
+
+/// Loads the CSV files written by the nomenclator parsers in `csv_dir` into `conn`,
+/// returning the rows inserted into each table.
+///
+/// See [`load_csv_dir_into_sqlite_with_options`].
+pub fn load_csv_dir_into_sqlite<P: AsRef<Path>>(
+    csv_dir: P,
+    conn: &mut Connection,
+) -> Result<Vec<(&'static str, usize)>> {
+    load_csv_dir_into_sqlite_with_options(csv_dir, conn, &ParserOptions::default())
+}
+
+/// Loads the CSV files written with `options` in `csv_dir` into `conn`.
+///
+/// The tables are the ones of [`load_nomenclator_into_sqlite`], dropped and created
+/// again, and are filled in dependency order, each in its own transaction. Missing
+/// files leave their tables empty. A file whose header or number of columns does not
+/// match the current schema, such as one written by an older version of this crate, is
+/// an error. The child tables must be keyed by `cod_nacion`, the only key of the
+/// SQLite schema.
+pub fn load_csv_dir_into_sqlite_with_options<P: AsRef<Path>>(
+    csv_dir: P,
+    conn: &mut Connection,
+    options: &ParserOptions,
+) -> Result<Vec<(&'static str, usize)>> {
+    if options.prescription_key != PrescriptionKey::CodNacion {
+        anyhow::bail!("Only CSV files keyed by cod_nacion can be loaded into SQLite");
+    }
+    let csv_dir = csv_dir.as_ref();
+    without_foreign_keys(conn, |conn| {
+        create_tables(conn)?;
+        let mut counts = Vec::new();
+        for table in DICTIONARY_TABLES.iter().chain(PRESCRIPTION_TABLES.iter()) {
+            let path = csv_dir.join(table.file_name);
+            if !path.exists() {
+                tracing::warn!(file = table.file_name, "File not found, skipping");
+                counts.push((table.name(), 0));
+                continue;
+            }
+            let tx = conn.transaction()?;
+            let count = insert_csv(&tx, table, &path, options)
+                .with_context(|| format!("Failed to load {}", path.display()))?;
+            tx.commit()?;
+            tracing::info!(table = table.name(), rows = count, "Loaded CSV file");
+            counts.push((table.name(), count));
+        }
+        Ok(counts)
+    })
+}
+
+/// Inserts the rows of the CSV file of `table` at `path`, returning how many.
+fn insert_csv(
+    tx: &Transaction,
+    table: &Table,
+    path: &Path,
+    options: &ParserOptions,
+) -> Result<usize> {
+    let columns = options.table_columns(table)?;
+    let stale = || {
+        format!(
+            "{} does not match the {} table of this version of cima-rs, \
+             generate it again with `nomenclator csv`",
+            path.display(),
+            table.name()
+        )
+    };
+    let mut rows =
+        CsvRows::open(path, Cow::clone(&columns), table.has_header, options).with_context(stale)?;
+    let mut statement = insert_statement(tx, table.name(), &columns)?;
+    let mut count = 0;
+    while let Some(row) = rows.next_row(path)? {
+        if row.len() != columns.len() {
+            return Err(anyhow::anyhow!(
+                "Row {} has {} columns, expected {}",
+                count + 1,
+                row.len(),
+                columns.len()
+            ))
+            .with_context(stale);
+        }
+        let values = row
+            .iter()
+            .zip(columns.iter())
+            .map(|(field, column)| sql_value(field, column, options))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Row {}", count + 1))?;
+        statement
+            .execute(params_from_iter(values))
+            .with_context(|| format!("Failed to insert row {} into {}", count + 1, table.name()))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// SQLite value of a CSV field, whose nulls were already made empty.
+fn sql_value(field: &str, column: &Column, options: &ParserOptions) -> Result<Value> {
+    if field.is_empty() && column.nullable {
+        return Ok(Value::Null);
+    }
+    Ok(match column.column_type {
+        ColumnType::Boolean if field == options.bool_repr.format(true) => Value::Integer(1),
+        ColumnType::Boolean if field == options.bool_repr.format(false) => Value::Integer(0),
+        ColumnType::Boolean => anyhow::bail!("Invalid boolean {:?} in {}", field, column.name),
+        // Left as text when not a number, as the XML loader would not coerce it either
+        ColumnType::Int32 => field
+            .parse()
+            .map(Value::Integer)
+            .unwrap_or_else(|_| Value::Text(field.to_string())),
+        ColumnType::Text | ColumnType::Date => Value::Text(field.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::prescription_xml;
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_fixtures(dir: &Path) {
+        std::fs::write(
+            dir.join("DICCIONARIO_ATC.xml"),
+            r#"<aemps_prescripcion_atc>
+                <atc><nroatc>1</nroatc><codigoatc>J01CR02</codigoatc><descatc>J01CR02 - AMOXICILINA Y CLAVULANICO</descatc></atc>
+                <atc><nroatc>2</nroatc><codigoatc>N02BE01</codigoatc><descatc>N02BE01 - PARACETAMOL</descatc></atc>
+            </aemps_prescripcion_atc>"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("Prescripcion.xml"),
+            format!(
+                "<aemps_prescripcion>{}{}{}</aemps_prescripcion>",
+                prescription_xml("600000", "<atc><cod_atc>J01CR02</cod_atc></atc>"),
+                prescription_xml(
+                    "600001",
+                    r#"<atc><cod_atc>N02BE01</cod_atc></atc>
+                    <formasfarmaceuticas>
+                        <cod_forfar>10</cod_forfar>
+                        <viasadministracion><cod_via_admin>48</cod_via_admin></viasadministracion>
+                    </formasfarmaceuticas>"#
+                ),
+                prescription_xml("600002", ""),
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_load_nomenclator_into_sqlite() {
+        let dir = TempDir::new().unwrap();
+        write_fixtures(dir.path());
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        load_nomenclator_into_sqlite(dir.path(), &mut conn).unwrap();
+
+        let mut statement = conn
+            .prepare(
+                "SELECT p.cod_nacion, a.description FROM prescriptions p \
+                 JOIN prescription_atc pa ON pa.prescription_id = p.cod_nacion \
+                 JOIN atc a ON a.code = pa.atc_code \
+                 ORDER BY p.cod_nacion",
+            )
+            .unwrap();
+        let rows: Vec<(String, String)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    "600000".to_string(),
+                    "AMOXICILINA Y CLAVULANICO".to_string()
+                ),
+                ("600001".to_string(), "PARACETAMOL".to_string()),
+            ]
+        );
+
+        let (receta, url): (bool, Option<String>) = conn
+            .query_row(
+                "SELECT sw_receta, url_fictec FROM prescriptions WHERE cod_nacion = '600002'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert!(receta);
+        assert_eq!(url, None);
+
+        let routes: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM prescription_admin_routes",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(routes, 1);
+
+        // Missing dictionaries still get their (empty) tables
+        let labs: i64 = conn
+            .query_row("SELECT COUNT(*) FROM laboratorios", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(labs, 0);
+    }
+
+    #[test]
+    fn test_prescriptions_primary_key() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("Prescripcion.xml"),
+            format!(
+                "<aemps_prescripcion>{}{}</aemps_prescripcion>",
+                prescription_xml("600000", ""),
+                prescription_xml("600000", ""),
+            ),
+        )
+        .unwrap();
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        let err = load_nomenclator_into_sqlite(dir.path(), &mut conn).unwrap_err();
+        assert!(format!("{err:#}").contains("600000"));
+    }
+
+    #[test]
+    fn test_parse_nomenclator_to_sqlite_replaces_tables() {
+        let dir = TempDir::new().unwrap();
+        write_fixtures(dir.path());
+        let db_path = dir.path().join("nomenclator.sqlite");
+
+        parse_nomenclator_to_sqlite(dir.path(), &db_path).unwrap();
+        parse_nomenclator_to_sqlite(dir.path(), &db_path).unwrap();
+
+        let conn = Connection::open(&db_path).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM prescriptions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 3);
+        let indexes: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' \
+                 AND name = 'idx_prescription_atc_atc_code'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(indexes, 1);
+    }
+
+    #[test]
+    fn test_load_csv_dir_into_sqlite() {
+        let dir = TempDir::new().unwrap();
+        write_fixtures(dir.path());
+        let csv_dir = dir.path().join("csv");
+        std::fs::create_dir(&csv_dir).unwrap();
+        super::super::parse_atc_xml_to_csv(
+            dir.path().join("DICCIONARIO_ATC.xml"),
+            csv_dir.join("atc.csv"),
+        )
+        .unwrap();
+        super::super::parse_prescription_xml_to_csvs(
+            &dir.path().join("Prescripcion.xml"),
+            &csv_dir,
+        )
+        .unwrap();
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        let counts = load_csv_dir_into_sqlite(&csv_dir, &mut conn).unwrap();
+        assert_eq!(counts[0], ("atc", 2));
+        assert!(counts.contains(&("prescriptions", 3)));
+        assert!(counts.contains(&("laboratorios", 0)));
+
+        let (description, receta, url): (String, bool, Option<String>) = conn
+            .query_row(
+                "SELECT a.description, p.sw_receta, p.url_fictec FROM prescriptions p \
+                 JOIN prescription_atc pa ON pa.prescription_id = p.cod_nacion \
+                 JOIN atc a ON a.code = pa.atc_code \
+                 WHERE p.cod_nacion = '600001'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(description, "PARACETAMOL");
+        assert!(receta);
+        assert_eq!(url, None);
+    }
+
+    #[test]
+    fn test_load_csv_dir_rejects_stale_files() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("atc.csv"), "nroatc,codigoatc\n1,A\n").unwrap();
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        let err = load_csv_dir_into_sqlite(dir.path(), &mut conn).unwrap_err();
+        let message = format!("{err:#}");
+        assert!(
+            message.contains("does not match the atc table of this version of cima-rs"),
+            "{message}"
+        );
+
+        std::fs::remove_file(dir.path().join("atc.csv")).unwrap();
+        std::fs::write(dir.path().join("prescription_atc.csv"), "600000\n").unwrap();
+        let err = load_csv_dir_into_sqlite(dir.path(), &mut conn).unwrap_err();
+        assert!(format!("{err:#}").contains("Row 1 has 1 columns, expected 2"));
+    }
+}
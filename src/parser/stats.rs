@@ -0,0 +1,409 @@
+//! Summary of a directory of generated CSV files.
+//!
+//! Files are read row by row, so the memory used does not grow with their size, apart
+//! from the prescription count of every laboratory.
+
+use super::load::{CsvRows, load_laboratorio_csv_with_options};
+use super::options::ParserOptions;
+use super::schema::{DICTIONARY_TABLES, PRESCRIPTION_TABLES, Table};
+use super::{LaboratoryRecord, PRESCRIPTIONS_CSV};
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Settings of [`csv_dir_stats_with_options`]
+#[derive(Debug, Clone)]
+pub struct StatsOptions {
+    /// Options the directory was written with
+    pub parser: ParserOptions,
+    /// Row counts of a previous run by file name, see [`previous_row_counts`]
+    pub previous: Option<BTreeMap<String, usize>>,
+    /// Relative change from the previous row count reported as an anomaly, 0.2 by default
+    pub max_deviation: f64,
+    /// Laboratories listed by prescription count, 10 by default
+    pub top_laboratories: usize,
+}
+
+impl Default for StatsOptions {
+    fn default() -> Self {
+        StatsOptions {
+            parser: ParserOptions::default(),
+            previous: None,
+            max_deviation: 0.2,
+            top_laboratories: 10,
+        }
+    }
+}
+
+/// Summary of an output directory
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CsvDirStats {
+    /// Tables found in the directory, dictionaries first
+    pub files: Vec<FileStats>,
+    /// `None` when there is no prescriptions file
+    pub prescriptions: Option<PrescriptionStats>,
+    pub anomalies: Vec<Anomaly>,
+}
+
+/// Rows and size of one CSV file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileStats {
+    pub file: &'static str,
+    /// Rows excluding the header
+    pub rows: usize,
+    pub bytes: u64,
+}
+
+/// Counts over the prescriptions file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PrescriptionStats {
+    pub total: usize,
+    pub commercialized: usize,
+    pub not_commercialized: usize,
+    pub psychotropic: usize,
+    /// Laboratories holding the most prescriptions, most first
+    pub top_laboratories: Vec<LaboratoryCount>,
+}
+
+/// Prescriptions held by one laboratory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LaboratoryCount {
+    pub code: String,
+    /// Name from `laboratorios.csv`, `None` when the code is not there
+    pub name: Option<String>,
+    pub prescriptions: usize,
+}
+
+/// Something worth a look in the output directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Anomaly {
+    /// A file without rows
+    Empty { file: &'static str },
+    /// A row count too far from the one of the previous run
+    Deviation {
+        file: &'static str,
+        previous: usize,
+        current: usize,
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::Empty { file } => write!(f, "{} is empty", file),
+            Anomaly::Deviation {
+                file,
+                previous,
+                current,
+            } => write!(
+                f,
+                "{} has {} rows, {} in the previous run",
+                file, current, previous
+            ),
+        }
+    }
+}
+
+/// Entry of the `report.json` written by `nomenclator csv --report-json`
+#[derive(Deserialize)]
+struct ReportEntry {
+    rows_written: BTreeMap<String, usize>,
+}
+
+/// Rows written per output file according to the `report.json` at `path`.
+pub fn previous_row_counts<P: AsRef<Path>>(path: P) -> Result<BTreeMap<String, usize>> {
+    let path = path.as_ref();
+    let content = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let report: BTreeMap<String, ReportEntry> = serde_json::from_slice(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(report
+        .into_values()
+        .flat_map(|entry| entry.rows_written)
+        .collect())
+}
+
+/// Summarizes the CSV files written by the nomenclator parsers in `dir`.
+pub fn csv_dir_stats<P: AsRef<Path>>(dir: P) -> Result<CsvDirStats> {
+    csv_dir_stats_with_options(dir, &StatsOptions::default())
+}
+
+/// Summarizes the CSV files written with `options` in `dir`. Missing files are left out.
+pub fn csv_dir_stats_with_options<P: AsRef<Path>>(
+    dir: P,
+    options: &StatsOptions,
+) -> Result<CsvDirStats> {
+    let dir = dir.as_ref();
+    let mut files = Vec::new();
+    let mut prescriptions = None;
+    for table in DICTIONARY_TABLES.iter().chain(PRESCRIPTION_TABLES.iter()) {
+        let path = dir.join(table.file_name);
+        if !path.is_file() {
+            continue;
+        }
+        let bytes = fs::metadata(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .len();
+        let rows = if table.file_name == PRESCRIPTIONS_CSV {
+            let mut counts = PrescriptionCounts::new(table, &options.parser);
+            let rows = for_each_row(&path, table, &options.parser, |row| counts.add(row))?;
+            prescriptions = Some(counts.finish(dir, options)?);
+            rows
+        } else {
+            for_each_row(&path, table, &options.parser, |_| {})?
+        };
+        files.push(FileStats {
+            file: table.file_name,
+            rows,
+            bytes,
+        });
+    }
+
+    let mut anomalies = Vec::new();
+    for file in &files {
+        if file.rows == 0 {
+            anomalies.push(Anomaly::Empty { file: file.file });
+        }
+        let previous = options
+            .previous
+            .as_ref()
+            .and_then(|previous| previous.get(file.file));
+        if let Some(&previous) = previous
+            && previous > 0
+            && file.rows.abs_diff(previous) as f64 / previous as f64 > options.max_deviation
+        {
+            anomalies.push(Anomaly::Deviation {
+                file: file.file,
+                previous,
+                current: file.rows,
+            });
+        }
+    }
+
+    Ok(CsvDirStats {
+        files,
+        prescriptions,
+        anomalies,
+    })
+}
+
+/// Passes every row of the `table` file at `path` to `f`, returning the number of rows.
+fn for_each_row(
+    path: &Path,
+    table: &Table,
+    options: &ParserOptions,
+    mut f: impl FnMut(&StringRecord),
+) -> Result<usize> {
+    let columns = table.columns_with_key(options.prescription_key);
+    let mut rows = CsvRows::open(path, columns, table.has_header, options)?;
+    let mut count = 0;
+    while let Some(row) = rows.next_row(path)? {
+        f(&row);
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Counts of the prescriptions read so far
+struct PrescriptionCounts {
+    commercialized: Option<usize>,
+    psychotropic: Option<usize>,
+    laboratory: Option<usize>,
+    true_repr: &'static str,
+    stats: PrescriptionStats,
+    laboratories: HashMap<String, usize>,
+}
+
+impl PrescriptionCounts {
+    fn new(table: &Table, options: &ParserOptions) -> Self {
+        let position = |name: &str| table.columns.iter().position(|column| column.name == name);
+        PrescriptionCounts {
+            commercialized: position("sw_comercializado"),
+            psychotropic: position("sw_psicotropo"),
+            laboratory: position("laboratorio_titular"),
+            true_repr: options.bool_repr.format(true),
+            stats: PrescriptionStats {
+                total: 0,
+                commercialized: 0,
+                not_commercialized: 0,
+                psychotropic: 0,
+                top_laboratories: Vec::new(),
+            },
+            laboratories: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, row: &StringRecord) {
+        let field = |index: Option<usize>| index.and_then(|index| row.get(index));
+        self.stats.total += 1;
+        if field(self.commercialized) == Some(self.true_repr) {
+            self.stats.commercialized += 1;
+        } else {
+            self.stats.not_commercialized += 1;
+        }
+        if field(self.psychotropic) == Some(self.true_repr) {
+            self.stats.psychotropic += 1;
+        }
+        if let Some(code) = field(self.laboratory).filter(|code| !code.is_empty()) {
+            *self.laboratories.entry(code.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Final counts, with the names of the top laboratories from `laboratorios.csv` in
+    /// `dir` when it is there.
+    fn finish(mut self, dir: &Path, options: &StatsOptions) -> Result<PrescriptionStats> {
+        let mut laboratories: Vec<_> = self.laboratories.into_iter().collect();
+        laboratories.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        laboratories.truncate(options.top_laboratories);
+
+        let names_path = dir.join("laboratorios.csv");
+        let names: HashMap<String, String> = if names_path.is_file() {
+            load_laboratorio_csv_with_options(&names_path, &options.parser)?
+                .into_iter()
+                .map(|LaboratoryRecord { code, name, .. }| (code, name))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        self.stats.top_laboratories = laboratories
+            .into_iter()
+            .map(|(code, prescriptions)| LaboratoryCount {
+                name: names.get(&code).cloned(),
+                code,
+                prescriptions,
+            })
+            .collect();
+        Ok(self.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse_prescription_xml_to_csvs;
+    use super::super::tests::prescription_xml;
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Output directory with three prescriptions of LAB, one of LAB2, and the
+    /// laboratories dictionary naming LAB
+    fn output_dir() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let xml = dir.path().join("Prescripcion.xml");
+        let commercialized = prescription_xml("600001", "")
+            .replace(
+                "<sw_comercializado>0</sw_comercializado>",
+                "<sw_comercializado>1</sw_comercializado>",
+            )
+            .replace(
+                "<sw_psicotropo>0</sw_psicotropo>",
+                "<sw_psicotropo>1</sw_psicotropo>",
+            );
+        let other_lab = prescription_xml("600003", "").replace(
+            "<laboratorio_titular>LAB</laboratorio_titular>",
+            "<laboratorio_titular>LAB2</laboratorio_titular>",
+        );
+        fs::write(
+            &xml,
+            format!(
+                "<aemps_prescripcion>{}{}{}{}</aemps_prescripcion>",
+                prescription_xml("600000", ""),
+                commercialized,
+                prescription_xml("600002", ""),
+                other_lab
+            ),
+        )
+        .unwrap();
+        parse_prescription_xml_to_csvs(xml.as_path(), dir.path()).unwrap();
+        fs::write(
+            dir.path().join("laboratorios.csv"),
+            "code,name,address,zip,city,vat\nLAB,LABORATORIO EJEMPLO,,,,\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_csv_dir_stats() {
+        let dir = output_dir();
+
+        let stats = csv_dir_stats(dir.path()).unwrap();
+
+        let prescriptions = stats.prescriptions.unwrap();
+        assert_eq!(prescriptions.total, 4);
+        assert_eq!(prescriptions.commercialized, 1);
+        assert_eq!(prescriptions.not_commercialized, 3);
+        assert_eq!(prescriptions.psychotropic, 1);
+        assert_eq!(
+            prescriptions.top_laboratories,
+            vec![
+                LaboratoryCount {
+                    code: "LAB".to_string(),
+                    name: Some("LABORATORIO EJEMPLO".to_string()),
+                    prescriptions: 3,
+                },
+                LaboratoryCount {
+                    code: "LAB2".to_string(),
+                    name: None,
+                    prescriptions: 1,
+                },
+            ]
+        );
+        let file = |name: &str| stats.files.iter().find(|file| file.file == name).unwrap();
+        assert_eq!(file(PRESCRIPTIONS_CSV).rows, 4);
+        assert_eq!(
+            file(PRESCRIPTIONS_CSV).bytes,
+            fs::metadata(dir.path().join(PRESCRIPTIONS_CSV))
+                .unwrap()
+                .len()
+        );
+        assert_eq!(file("laboratorios.csv").rows, 1);
+        assert!(stats.files.iter().all(|file| file.file != "atc.csv"));
+        assert!(stats.anomalies.contains(&Anomaly::Empty {
+            file: "prescription_atc.csv"
+        }));
+        assert!(
+            !stats
+                .anomalies
+                .iter()
+                .any(|anomaly| matches!(anomaly, Anomaly::Deviation { .. }))
+        );
+    }
+
+    #[test]
+    fn test_csv_dir_stats_flags_deviations() {
+        let dir = output_dir();
+        let report = dir.path().join("report.json");
+        fs::write(
+            &report,
+            r#"{
+                "DICCIONARIO_LABORATORIOS.xml": {"rows_read": 1, "rows_written": {"laboratorios.csv": 1}},
+                "Prescripcion.xml": {"rows_read": 6, "rows_written": {"prescriptions.csv": 6}}
+            }"#,
+        )
+        .unwrap();
+
+        let options = StatsOptions {
+            previous: Some(previous_row_counts(&report).unwrap()),
+            ..Default::default()
+        };
+        let stats = csv_dir_stats_with_options(dir.path(), &options).unwrap();
+
+        let deviations: Vec<_> = stats
+            .anomalies
+            .iter()
+            .filter(|anomaly| matches!(anomaly, Anomaly::Deviation { .. }))
+            .collect();
+        assert_eq!(
+            deviations,
+            [&Anomaly::Deviation {
+                file: PRESCRIPTIONS_CSV,
+                previous: 6,
+                current: 4,
+            }]
+        );
+    }
+}
@@ -0,0 +1,373 @@
+//! Referential integrity checks across the generated CSV files.
+
+use super::options::{ParserOptions, PrescriptionKey};
+use super::schema::{DICTIONARY_TABLES, PRESCRIPTION_TABLES, Table};
+use anyhow::{Context, Result};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+/// Maximum number of missing codes kept per relationship
+const MAX_SAMPLES: usize = 5;
+
+/// Reference from a column of one generated file to a column of another
+#[derive(Debug, Clone, Copy)]
+struct Reference {
+    table: &'static Table,
+    column: &'static str,
+    referenced_table: &'static Table,
+    referenced_column: &'static str,
+}
+
+fn tables() -> impl Iterator<Item = &'static Table> {
+    DICTIONARY_TABLES.iter().chain(PRESCRIPTION_TABLES.iter())
+}
+
+fn table(name: &str) -> &'static Table {
+    tables()
+        .find(|table| table.name() == name)
+        .unwrap_or_else(|| panic!("unknown table {name}"))
+}
+
+/// Every checked reference: the declared foreign keys plus the active ingredient codes,
+/// which point to `principios_activos.number` rather than its primary key.
+fn references(prescription_key: PrescriptionKey) -> Vec<Reference> {
+    let mut references: Vec<Reference> = tables()
+        .flat_map(|table| {
+            let foreign_keys = table.foreign_keys_with_key(prescription_key);
+            foreign_keys.iter().map(move |key| Reference {
+                table,
+                column: key.column,
+                referenced_table: self::table(key.table),
+                referenced_column: key.references,
+            })
+        })
+        .collect();
+    references.push(Reference {
+        table: table("prescription_active_ingredients"),
+        column: "active_ingredient_code",
+        referenced_table: table("principios_activos"),
+        referenced_column: "number",
+    });
+    references
+}
+
+/// Position of the column `name` in the file of `table` written with `options`, `None`
+/// when left out by [`ParserOptions::prescription_columns`].
+fn column_index(table: &Table, name: &str, options: &ParserOptions) -> Result<Option<usize>> {
+    Ok(options
+        .table_columns(table)?
+        .iter()
+        .position(|column| column.name == name))
+}
+
+/// Outcome of checking one reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelationshipReport {
+    /// Referencing file and column, e.g. `prescription_forms.csv` / `form_code`
+    pub file: &'static str,
+    pub column: &'static str,
+    /// Referenced file and column, e.g. `forma_farmaceutica.csv` / `code`
+    pub referenced_file: &'static str,
+    pub referenced_column: &'static str,
+    /// Non-empty values checked
+    pub checked: usize,
+    /// Values not found in the referenced file
+    pub missing: usize,
+    /// Some of the distinct missing values
+    pub samples: Vec<String>,
+}
+
+impl RelationshipReport {
+    /// Whether every value was found
+    pub fn is_valid(&self) -> bool {
+        self.missing == 0
+    }
+}
+
+/// Result of [`validate_nomenclator_output`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Checked relationships
+    pub relationships: Vec<RelationshipReport>,
+    /// Relationships not checked because one of their files does not exist, or their
+    /// column was not written
+    pub skipped: Vec<String>,
+}
+
+impl ValidationReport {
+    /// Whether every checked reference was found
+    pub fn is_valid(&self) -> bool {
+        self.relationships.iter().all(RelationshipReport::is_valid)
+    }
+
+    /// Total number of dangling references
+    pub fn missing(&self) -> usize {
+        self.relationships.iter().map(|r| r.missing).sum()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for relationship in &self.relationships {
+            let mark = if relationship.is_valid() {
+                "✓"
+            } else {
+                "✗"
+            };
+            write!(
+                f,
+                "{} {}.{} -> {}.{}: {} checked, {} missing",
+                mark,
+                relationship.file,
+                relationship.column,
+                relationship.referenced_file,
+                relationship.referenced_column,
+                relationship.checked,
+                relationship.missing
+            )?;
+            if !relationship.samples.is_empty() {
+                write!(f, " (e.g. {})", relationship.samples.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        for skipped in &self.skipped {
+            writeln!(f, "- {} skipped, file or column not written", skipped)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the non-null values of one column of a generated CSV file.
+fn read_column(
+    output_dir: &Path,
+    table: &Table,
+    column: usize,
+    options: &ParserOptions,
+) -> Result<Vec<String>> {
+    let path = output_dir.join(table.file_name);
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(table.has_header)
+        .from_path(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut values = Vec::new();
+    for record in reader.records() {
+        let record = record.with_context(|| format!("Failed to read {}", path.display()))?;
+        match record.get(column) {
+            Some(value) if !value.is_empty() && value != options.null_repr => {
+                values.push(value.to_string())
+            }
+            _ => {}
+        }
+    }
+    Ok(values)
+}
+
+/// Checks that every code referenced by the CSV files in `output_dir` exists in the
+/// file it refers to.
+///
+/// Covers the references between dictionaries (e.g. `dcp.csv` to `dcsa.csv`) and from
+/// the prescription files to the dictionaries: pharmaceutical forms, simplified forms,
+/// active ingredients, administration routes, ATC codes, containers, laboratories,
+/// registration statuses and the other declared foreign keys. Relationships whose
+/// files are missing are listed as skipped.
+pub fn validate_nomenclator_output<P: AsRef<Path>>(output_dir: P) -> Result<ValidationReport> {
+    validate_nomenclator_output_with_options(output_dir, &ParserOptions::default())
+}
+
+/// Checks the references of CSV files generated with `options`.
+///
+/// See [`validate_nomenclator_output`].
+pub fn validate_nomenclator_output_with_options<P: AsRef<Path>>(
+    output_dir: P,
+    options: &ParserOptions,
+) -> Result<ValidationReport> {
+    let output_dir = output_dir.as_ref();
+    let mut known: HashMap<(&str, usize), HashSet<String>> = HashMap::new();
+    let mut report = ValidationReport::default();
+
+    let prescription_key = options.prescription_key;
+    for reference in references(prescription_key) {
+        let name = format!(
+            "{}.{} -> {}.{}",
+            reference.table.file_name,
+            reference.column,
+            reference.referenced_table.file_name,
+            reference.referenced_column
+        );
+        if !output_dir.join(reference.table.file_name).exists()
+            || !output_dir
+                .join(reference.referenced_table.file_name)
+                .exists()
+        {
+            report.skipped.push(name);
+            continue;
+        }
+
+        let (Some(index), Some(referenced_index)) = (
+            column_index(reference.table, reference.column, options)?,
+            column_index(
+                reference.referenced_table,
+                reference.referenced_column,
+                options,
+            )?,
+        ) else {
+            report.skipped.push(name);
+            continue;
+        };
+        let key = (reference.referenced_table.file_name, referenced_index);
+        let codes = match known.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let values = read_column(
+                    output_dir,
+                    reference.referenced_table,
+                    referenced_index,
+                    options,
+                )?;
+                entry.insert(values.into_iter().collect())
+            }
+        };
+
+        let values = read_column(output_dir, reference.table, index, options)?;
+        let mut missing = 0;
+        let mut samples = BTreeSet::new();
+        for value in &values {
+            if !codes.contains(value) {
+                missing += 1;
+                if samples.len() < MAX_SAMPLES {
+                    samples.insert(value.clone());
+                }
+            }
+        }
+        if missing > 0 {
+            tracing::warn!(relationship = %name, missing, "Dangling references");
+        }
+
+        report.relationships.push(RelationshipReport {
+            file: reference.table.file_name,
+            column: reference.column,
+            referenced_file: reference.referenced_table.file_name,
+            referenced_column: reference.referenced_column,
+            checked: values.len(),
+            missing,
+            samples: samples.into_iter().collect(),
+        });
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn write_output(dir: &Path) {
+        fs::write(
+            dir.join("forma_farmaceutica_simplificada.csv"),
+            "code,name\n34,COMPRIMIDO\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("forma_farmaceutica.csv"),
+            "code,name,simplified_code\n288,COMPRIMIDO RECUBIERTO,34\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("atc.csv"),
+            "number,code,description\n1,J01CR02,AMOXI\n",
+        )
+        .unwrap();
+        // Only cod_nacion is filled in
+        let columns = PRESCRIPTION_TABLES[0].columns;
+        let header: Vec<&str> = columns.iter().map(|c| c.name).collect();
+        let empty = ",".repeat(columns.len() - 1);
+        fs::write(
+            dir.join("prescriptions.csv"),
+            format!("{}\n600000{empty}\n600001{empty}\n", header.join(",")),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("prescription_forms.csv"),
+            "600000,288,34,1\n600001,999,,1\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("prescription_atc.csv"),
+            "600000,J01CR02\n600001,J01CR02\n",
+        )
+        .unwrap();
+    }
+
+    fn relationship<'a>(
+        report: &'a ValidationReport,
+        file: &str,
+        column: &str,
+    ) -> &'a RelationshipReport {
+        report
+            .relationships
+            .iter()
+            .find(|r| r.file == file && r.column == column)
+            .unwrap_or_else(|| panic!("{file}.{column}"))
+    }
+
+    #[test]
+    fn test_validate_dangling_reference() {
+        let dir = TempDir::new().unwrap();
+        write_output(dir.path());
+
+        let report = validate_nomenclator_output(dir.path()).unwrap();
+
+        let forms = relationship(&report, "prescription_forms.csv", "form_code");
+        assert_eq!(forms.referenced_file, "forma_farmaceutica.csv");
+        assert_eq!(forms.checked, 2);
+        assert_eq!(forms.missing, 1);
+        assert_eq!(forms.samples, vec!["999"]);
+
+        // Empty optional codes are not checked
+        let simplified = relationship(&report, "prescription_forms.csv", "simplified_form_code");
+        assert_eq!((simplified.checked, simplified.missing), (1, 0));
+
+        assert!(relationship(&report, "prescription_atc.csv", "atc_code").is_valid());
+        assert!(relationship(&report, "prescription_atc.csv", "prescription_id").is_valid());
+        assert!(relationship(&report, "forma_farmaceutica.csv", "simplified_code").is_valid());
+
+        assert!(!report.is_valid());
+        assert_eq!(report.missing(), 1);
+        assert!(report.skipped.contains(
+            &"prescription_admin_routes.csv.route_code -> vias_administracion.csv.code".to_string()
+        ));
+        assert!(report.to_string().contains(
+            "✗ prescription_forms.csv.form_code -> forma_farmaceutica.csv.code: 2 checked, 1 missing (e.g. 999)"
+        ));
+    }
+
+    #[test]
+    fn test_validate_every_reference_resolves() {
+        for key in [
+            PrescriptionKey::CodNacion,
+            PrescriptionKey::NroDefinitivo,
+            PrescriptionKey::Both,
+        ] {
+            let options = ParserOptions {
+                prescription_key: key,
+                ..Default::default()
+            };
+            for reference in references(key) {
+                let index = column_index(reference.table, reference.column, &options).unwrap();
+                let referenced_index = column_index(
+                    reference.referenced_table,
+                    reference.referenced_column,
+                    &options,
+                )
+                .unwrap();
+                assert!(index.is_some() && referenced_index.is_some());
+            }
+        }
+    }
+}
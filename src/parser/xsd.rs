@@ -0,0 +1,1316 @@
+//! Validation of XML files against an XSD schema.
+//!
+//! Covers the subset of XML Schema used by the AEMPS schemas: global and local element
+//! declarations, element references, named and anonymous complex types with
+//! `sequence`, `choice` and `all` groups and `minOccurs`/`maxOccurs`, and simple types
+//! restricting the built-in types with the `enumeration`, `length`, `minLength`,
+//! `maxLength`, `pattern` and `min`/`maxInclusive`/`Exclusive` facets. Attributes,
+//! namespaces and identity constraints are not checked, and schemas using `include`,
+//! `import` or `redefine` are rejected.
+//!
+//! The XML file is streamed, so a truncated download is reported as an unexpected end
+//! of file at its last line rather than failing to load.
+
+use super::options::{OnViolation, ParserOptions};
+use super::report::ParseReport;
+use super::{decode_xml, open_xml};
+use anyhow::{Context, Result};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+
+/// Violations kept before validation stops
+const MAX_VIOLATIONS: usize = 1000;
+
+/// Violations listed in the error of [`OnViolation::Abort`]
+const REPORTED_VIOLATIONS: usize = 10;
+
+/// Built-in types accepted as the type or base of a declaration
+const BUILTIN_TYPES: &[&str] = &[
+    "anyType",
+    "anySimpleType",
+    "string",
+    "normalizedString",
+    "token",
+    "language",
+    "Name",
+    "NCName",
+    "NMTOKEN",
+    "ID",
+    "IDREF",
+    "anyURI",
+    "QName",
+    "boolean",
+    "decimal",
+    "float",
+    "double",
+    "integer",
+    "long",
+    "int",
+    "short",
+    "byte",
+    "nonNegativeInteger",
+    "positiveInteger",
+    "nonPositiveInteger",
+    "negativeInteger",
+    "unsignedLong",
+    "unsignedInt",
+    "unsignedShort",
+    "unsignedByte",
+    "date",
+    "dateTime",
+    "time",
+    "gYear",
+];
+
+/// Place where an XML file does not match its schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XsdViolation {
+    /// Line of the offending element, starting at 1
+    pub line: usize,
+    /// Byte column of the offending element within its line, starting at 1
+    pub column: usize,
+    /// What is wrong
+    pub message: String,
+}
+
+impl fmt::Display for XsdViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+// ============================================================================
+// Schema
+// ============================================================================
+
+/// Element of the XSD document itself
+struct Node {
+    name: String,
+    attributes: HashMap<String, String>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+
+    fn required(&self, name: &str) -> Result<&str> {
+        self.attribute(name)
+            .with_context(|| format!("<xs:{}> without a {name} attribute", self.name))
+    }
+}
+
+/// Strips the namespace prefix of an element or type name.
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn read_nodes(xsd: &str) -> Result<Node> {
+    let mut reader = Reader::from_str(xsd);
+    let mut stack: Vec<Node> = Vec::new();
+    loop {
+        match reader.read_event().context("Malformed XSD")? {
+            Event::Start(ref e) => stack.push(node(e)?),
+            Event::Empty(ref e) => {
+                let node = node(e)?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => return Ok(node),
+                }
+            }
+            Event::End(_) => {
+                let node = stack.pop().context("Malformed XSD")?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => return Ok(node),
+                }
+            }
+            Event::Eof => anyhow::bail!("XSD has no schema element"),
+            _ => {}
+        }
+    }
+}
+
+fn node(e: &BytesStart) -> Result<Node> {
+    let mut attributes = HashMap::new();
+    for attribute in e.attributes() {
+        let attribute = attribute.context("Malformed XSD attribute")?;
+        attributes.insert(
+            String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+            attribute
+                .normalized_value(XmlVersion::Implicit1_0)?
+                .into_owned(),
+        );
+    }
+    Ok(Node {
+        name: local_name(&String::from_utf8_lossy(e.name().as_ref())).to_string(),
+        attributes,
+        children: Vec::new(),
+    })
+}
+
+/// Declared type of an element
+enum TypeDef {
+    /// Named type, either defined in the schema or built-in
+    Named(String),
+    Complex(Box<ComplexType>),
+    Simple(SimpleType),
+}
+
+struct ComplexType {
+    mixed: bool,
+    /// `None` for elements without children
+    content: Option<Particle>,
+}
+
+struct SimpleType {
+    /// Named simple type or built-in type restricted
+    base: String,
+    facets: Facets,
+}
+
+#[derive(Default)]
+struct Facets {
+    enumeration: Vec<String>,
+    length: Option<usize>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    patterns: Vec<Regex>,
+    min_inclusive: Option<f64>,
+    max_inclusive: Option<f64>,
+    min_exclusive: Option<f64>,
+    max_exclusive: Option<f64>,
+}
+
+struct ElementDecl {
+    name: String,
+    type_def: TypeDef,
+}
+
+struct Particle {
+    min: usize,
+    /// `None` when unbounded
+    max: Option<usize>,
+    term: Term,
+}
+
+enum Term {
+    Element(ElementDecl),
+    /// Reference to a global element
+    Ref(String),
+    Sequence(Vec<Particle>),
+    Choice(Vec<Particle>),
+    All(Vec<Particle>),
+    /// `xs:any`: any one element, whose content is not checked
+    Any,
+}
+
+/// Compiled XSD schema
+struct Schema {
+    elements: HashMap<String, ElementDecl>,
+    complex_types: HashMap<String, ComplexType>,
+    simple_types: HashMap<String, SimpleType>,
+}
+
+/// Type of an element once named types are looked up
+#[derive(Clone, Copy)]
+enum Resolved<'s> {
+    Complex(&'s ComplexType),
+    Simple(&'s SimpleType),
+    Builtin(&'s str),
+}
+
+impl Schema {
+    fn load(xsd_path: &Path) -> Result<Self> {
+        let xsd = fs::read_to_string(xsd_path)
+            .with_context(|| format!("Failed to read {}", xsd_path.display()))?;
+        Self::parse(&xsd).with_context(|| format!("Failed to load {}", xsd_path.display()))
+    }
+
+    fn parse(xsd: &str) -> Result<Self> {
+        let root = read_nodes(xsd)?;
+        anyhow::ensure!(
+            root.name == "schema",
+            "Expected <xs:schema>, found <{}>",
+            root.name
+        );
+        let mut schema = Schema {
+            elements: HashMap::new(),
+            complex_types: HashMap::new(),
+            simple_types: HashMap::new(),
+        };
+        for child in &root.children {
+            match child.name.as_str() {
+                "element" => {
+                    let decl = element_decl(child)?;
+                    schema.elements.insert(decl.name.clone(), decl);
+                }
+                "complexType" => {
+                    let name = child.required("name")?.to_string();
+                    schema.complex_types.insert(name, complex_type(child)?);
+                }
+                "simpleType" => {
+                    let name = child.required("name")?.to_string();
+                    schema.simple_types.insert(name, simple_type(child)?);
+                }
+                "include" | "import" | "redefine" => {
+                    anyhow::bail!("<xs:{}> is not supported", child.name)
+                }
+                _ => {}
+            }
+        }
+        schema.check_references()?;
+        Ok(schema)
+    }
+
+    /// Fails on type and element references that resolve to nothing.
+    fn check_references(&self) -> Result<()> {
+        let check_type = |name: &str| {
+            anyhow::ensure!(
+                self.complex_types.contains_key(name)
+                    || self.simple_types.contains_key(name)
+                    || BUILTIN_TYPES.contains(&name),
+                "Unknown type {name}"
+            );
+            Ok(())
+        };
+        let check_decl = |decl: &ElementDecl| -> Result<()> {
+            if let TypeDef::Named(name) = &decl.type_def {
+                check_type(name)?;
+            }
+            Ok(())
+        };
+        let mut particles: Vec<&Particle> = Vec::new();
+        for decl in self.elements.values() {
+            check_decl(decl)?;
+            if let TypeDef::Complex(complex) = &decl.type_def {
+                particles.extend(&complex.content);
+            }
+        }
+        particles.extend(
+            self.complex_types
+                .values()
+                .filter_map(|complex| complex.content.as_ref()),
+        );
+        for simple in self.simple_types.values() {
+            check_type(&simple.base)?;
+        }
+        while let Some(particle) = particles.pop() {
+            match &particle.term {
+                Term::Element(decl) => {
+                    check_decl(decl)?;
+                    match &decl.type_def {
+                        TypeDef::Complex(complex) => particles.extend(&complex.content),
+                        TypeDef::Simple(simple) => check_type(&simple.base)?,
+                        TypeDef::Named(_) => {}
+                    }
+                }
+                Term::Ref(name) => {
+                    anyhow::ensure!(self.elements.contains_key(name), "Unknown element {name}")
+                }
+                Term::Sequence(items) | Term::Choice(items) | Term::All(items) => {
+                    particles.extend(items)
+                }
+                Term::Any => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve<'s>(&'s self, type_def: &'s TypeDef) -> Resolved<'s> {
+        match type_def {
+            TypeDef::Complex(complex) => Resolved::Complex(complex),
+            TypeDef::Simple(simple) => Resolved::Simple(simple),
+            TypeDef::Named(name) => {
+                if let Some(complex) = self.complex_types.get(name) {
+                    Resolved::Complex(complex)
+                } else if let Some(simple) = self.simple_types.get(name) {
+                    Resolved::Simple(simple)
+                } else {
+                    Resolved::Builtin(name)
+                }
+            }
+        }
+    }
+
+    /// Declaration of the child `name` within `particle`, if it may appear there.
+    fn find_child<'s>(&'s self, particle: &'s Particle, name: &str) -> Option<Child<'s>> {
+        match &particle.term {
+            Term::Element(decl) if decl.name == name => Some(Child::Decl(decl)),
+            Term::Ref(reference) if reference == name => self.elements.get(name).map(Child::Decl),
+            Term::Sequence(items) | Term::Choice(items) | Term::All(items) => {
+                items.iter().find_map(|item| self.find_child(item, name))
+            }
+            Term::Any => Some(Child::Any),
+            _ => None,
+        }
+    }
+
+    /// Name of the element `term` matches, if it is an element.
+    fn element_name<'s>(&'s self, term: &'s Term) -> Option<&'s str> {
+        match term {
+            Term::Element(decl) => Some(&decl.name),
+            Term::Ref(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Checks `value` against a simple type, returning the reason it does not match.
+    fn check_simple(&self, simple: &SimpleType, value: &str) -> Option<String> {
+        let base_error = match self.simple_types.get(&simple.base) {
+            Some(base) => self.check_simple(base, value),
+            None => check_builtin(&simple.base, value),
+        };
+        base_error.or_else(|| check_facets(&simple.facets, collapse(&simple.base, value)))
+    }
+}
+
+fn occurs(node: &Node) -> Result<(usize, Option<usize>)> {
+    let min = match node.attribute("minOccurs") {
+        Some(min) => min.parse().context("Invalid minOccurs")?,
+        None => 1,
+    };
+    let max = match node.attribute("maxOccurs") {
+        Some("unbounded") => None,
+        Some(max) => Some(max.parse().context("Invalid maxOccurs")?),
+        None => Some(1),
+    };
+    Ok((min, max))
+}
+
+fn element_decl(node: &Node) -> Result<ElementDecl> {
+    let name = node.required("name")?.to_string();
+    let type_def = match node.attribute("type") {
+        Some(type_name) => TypeDef::Named(local_name(type_name).to_string()),
+        None => match node
+            .children
+            .iter()
+            .find(|child| child.name == "complexType" || child.name == "simpleType")
+        {
+            Some(child) if child.name == "complexType" => {
+                TypeDef::Complex(Box::new(complex_type(child)?))
+            }
+            Some(child) => TypeDef::Simple(simple_type(child)?),
+            None => TypeDef::Named("anyType".to_string()),
+        },
+    };
+    Ok(ElementDecl { name, type_def })
+}
+
+fn complex_type(node: &Node) -> Result<ComplexType> {
+    let mut content = None;
+    for child in &node.children {
+        match child.name.as_str() {
+            "sequence" | "choice" | "all" => content = Some(particle(child)?),
+            "simpleContent" | "complexContent" => {
+                anyhow::bail!("<xs:{}> is not supported", child.name)
+            }
+            _ => {}
+        }
+    }
+    Ok(ComplexType {
+        mixed: node.attribute("mixed") == Some("true"),
+        content,
+    })
+}
+
+fn particle(node: &Node) -> Result<Particle> {
+    let (min, max) = occurs(node)?;
+    let items = || -> Result<Vec<Particle>> {
+        node.children
+            .iter()
+            .filter(|child| {
+                matches!(
+                    child.name.as_str(),
+                    "element" | "sequence" | "choice" | "all" | "any"
+                )
+            })
+            .map(particle)
+            .collect()
+    };
+    let term = match node.name.as_str() {
+        "element" => match node.attribute("ref") {
+            Some(reference) => Term::Ref(local_name(reference).to_string()),
+            None => Term::Element(element_decl(node)?),
+        },
+        "sequence" => Term::Sequence(items()?),
+        "choice" => Term::Choice(items()?),
+        "all" => Term::All(items()?),
+        "any" => Term::Any,
+        other => anyhow::bail!("<xs:{other}> is not supported in a content model"),
+    };
+    Ok(Particle { min, max, term })
+}
+
+fn simple_type(node: &Node) -> Result<SimpleType> {
+    let Some(restriction) = node.children.iter().find(|c| c.name == "restriction") else {
+        // Lists and unions are accepted without checks
+        return Ok(SimpleType {
+            base: "anySimpleType".to_string(),
+            facets: Facets::default(),
+        });
+    };
+    let mut facets = Facets::default();
+    for facet in &restriction.children {
+        let value = || facet.required("value");
+        let number = || -> Result<f64> {
+            value()?
+                .parse()
+                .with_context(|| format!("Invalid {} facet", facet.name))
+        };
+        let length = || -> Result<usize> {
+            value()?
+                .parse()
+                .with_context(|| format!("Invalid {} facet", facet.name))
+        };
+        match facet.name.as_str() {
+            "enumeration" => facets.enumeration.push(value()?.to_string()),
+            "length" => facets.length = Some(length()?),
+            "minLength" => facets.min_length = Some(length()?),
+            "maxLength" => facets.max_length = Some(length()?),
+            "pattern" => facets.patterns.push(
+                Regex::new(&format!("^(?:{})$", value()?))
+                    .with_context(|| format!("Invalid pattern {}", value().unwrap_or_default()))?,
+            ),
+            "minInclusive" => facets.min_inclusive = Some(number()?),
+            "maxInclusive" => facets.max_inclusive = Some(number()?),
+            "minExclusive" => facets.min_exclusive = Some(number()?),
+            "maxExclusive" => facets.max_exclusive = Some(number()?),
+            _ => {}
+        }
+    }
+    Ok(SimpleType {
+        base: local_name(restriction.required("base")?).to_string(),
+        facets,
+    })
+}
+
+/// Value of `value` after the whitespace handling of the built-in `base`: only
+/// strings keep their surrounding whitespace.
+fn collapse<'a>(base: &str, value: &'a str) -> &'a str {
+    if base == "string" {
+        value
+    } else {
+        value.trim()
+    }
+}
+
+fn check_builtin(base: &str, value: &str) -> Option<String> {
+    let trimmed = value.trim();
+    let valid = match base {
+        "boolean" => matches!(trimmed, "true" | "false" | "1" | "0"),
+        "decimal" => is_decimal(trimmed),
+        "float" | "double" => {
+            matches!(trimmed, "INF" | "-INF" | "NaN") || trimmed.parse::<f64>().is_ok()
+        }
+        "integer" => is_integer(trimmed, None, None),
+        "long" => is_integer(trimmed, Some(i64::MIN.into()), Some(i64::MAX.into())),
+        "int" => is_integer(trimmed, Some(i32::MIN.into()), Some(i32::MAX.into())),
+        "short" => is_integer(trimmed, Some(i16::MIN.into()), Some(i16::MAX.into())),
+        "byte" => is_integer(trimmed, Some(i8::MIN.into()), Some(i8::MAX.into())),
+        "nonNegativeInteger" => is_integer(trimmed, Some(0), None),
+        "positiveInteger" => is_integer(trimmed, Some(1), None),
+        "nonPositiveInteger" => is_integer(trimmed, None, Some(0)),
+        "negativeInteger" => is_integer(trimmed, None, Some(-1)),
+        "unsignedLong" => is_integer(trimmed, Some(0), Some(u64::MAX.into())),
+        "unsignedInt" => is_integer(trimmed, Some(0), Some(u32::MAX.into())),
+        "unsignedShort" => is_integer(trimmed, Some(0), Some(u16::MAX.into())),
+        "unsignedByte" => is_integer(trimmed, Some(0), Some(u8::MAX.into())),
+        "date" => chrono::NaiveDate::parse_from_str(trimmed, "%Y-%m-%d").is_ok(),
+        "dateTime" => {
+            chrono::NaiveDateTime::parse_from_str(trimmed, "%Y-%m-%dT%H:%M:%S%.f").is_ok()
+        }
+        "time" => chrono::NaiveTime::parse_from_str(trimmed, "%H:%M:%S%.f").is_ok(),
+        "gYear" => trimmed.len() >= 4 && is_integer(trimmed, None, None),
+        _ => true,
+    };
+    (!valid).then(|| format!("'{value}' is not a valid {base}"))
+}
+
+fn is_integer(value: &str, min: Option<i128>, max: Option<i128>) -> bool {
+    let digits = value.strip_prefix(['+', '-']).unwrap_or(value);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+    match value.parse::<i128>() {
+        Ok(number) => min.is_none_or(|min| number >= min) && max.is_none_or(|max| number <= max),
+        // Only unbounded types accept numbers beyond i128
+        Err(_) => min.is_none() && max.is_none(),
+    }
+}
+
+fn is_decimal(value: &str) -> bool {
+    let unsigned = value.strip_prefix(['+', '-']).unwrap_or(value);
+    let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    !(integer.is_empty() && fraction.is_empty())
+        && integer.bytes().all(|b| b.is_ascii_digit())
+        && fraction.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn check_facets(facets: &Facets, value: &str) -> Option<String> {
+    let length = value.chars().count();
+    if !facets.enumeration.is_empty() && !facets.enumeration.iter().any(|v| v == value) {
+        return Some(format!(
+            "'{value}' is not one of {}",
+            facets.enumeration.join(", ")
+        ));
+    }
+    if let Some(expected) = facets.length
+        && length != expected
+    {
+        return Some(format!("'{value}' is not {expected} characters long"));
+    }
+    if let Some(min) = facets.min_length
+        && length < min
+    {
+        return Some(format!("'{value}' is shorter than {min} characters"));
+    }
+    if let Some(max) = facets.max_length
+        && length > max
+    {
+        return Some(format!("'{value}' is longer than {max} characters"));
+    }
+    if let Some(pattern) = facets.patterns.iter().find(|p| !p.is_match(value)) {
+        let pattern = pattern.as_str();
+        let pattern = &pattern[4..pattern.len() - 2];
+        return Some(format!("'{value}' does not match the pattern {pattern}"));
+    }
+    let bounds = [
+        (
+            facets.min_inclusive,
+            "less than",
+            f64::lt as fn(&f64, &f64) -> bool,
+        ),
+        (facets.max_inclusive, "greater than", f64::gt),
+        (facets.min_exclusive, "not greater than", f64::le),
+        (facets.max_exclusive, "not less than", f64::ge),
+    ];
+    for (bound, relation, violates) in bounds {
+        if let Some(bound) = bound {
+            match value.parse::<f64>() {
+                Ok(number) if !violates(&number, &bound) => {}
+                _ => return Some(format!("'{value}' is {relation} {bound}")),
+            }
+        }
+    }
+    None
+}
+
+// ============================================================================
+// Content models
+// ============================================================================
+
+/// Child element as read, for the content model check of its parent
+struct ChildElement {
+    name: String,
+    line: usize,
+    column: usize,
+}
+
+/// How a child element is checked
+enum Child<'s> {
+    Decl(&'s ElementDecl),
+    /// Matched by `xs:any`, its content is not checked
+    Any,
+}
+
+/// Matches child elements against a content model, remembering the furthest position
+/// reached and what was expected there for the error message.
+///
+/// Schemas must be deterministic (the Unique Particle Attribution rule), so a greedy
+/// match without backtracking is enough.
+struct Matcher<'a, 's> {
+    schema: &'s Schema,
+    children: &'a [ChildElement],
+    furthest: usize,
+    expected: Vec<&'s str>,
+}
+
+impl<'a, 's> Matcher<'a, 's> {
+    fn expect(&mut self, position: usize, name: &'s str) {
+        if position > self.furthest {
+            self.furthest = position;
+            self.expected.clear();
+        }
+        if position == self.furthest && !self.expected.contains(&name) {
+            self.expected.push(name);
+        }
+    }
+
+    /// Position after matching `particle` from `position`, or `None` when it
+    /// cannot match there.
+    fn particle(&mut self, particle: &'s Particle, position: usize) -> Option<usize> {
+        let mut count = 0;
+        let mut position = position;
+        while particle.max.is_none_or(|max| count < max) {
+            match self.term(&particle.term, position) {
+                Some(next) if next > position => {
+                    position = next;
+                    count += 1;
+                }
+                // The term matched nothing, so it matches any number of times
+                Some(_) => return Some(position),
+                None => break,
+            }
+        }
+        (count >= particle.min).then_some(position)
+    }
+
+    fn term(&mut self, term: &'s Term, position: usize) -> Option<usize> {
+        match term {
+            Term::Element(_) | Term::Ref(_) => {
+                let name = self.schema.element_name(term)?;
+                match self.children.get(position) {
+                    Some(child) if child.name == name => Some(position + 1),
+                    _ => {
+                        self.expect(position, name);
+                        None
+                    }
+                }
+            }
+            Term::Sequence(items) => items
+                .iter()
+                .try_fold(position, |position, item| self.particle(item, position)),
+            Term::Choice(items) => {
+                let mut empty = None;
+                for item in items {
+                    match self.particle(item, position) {
+                        Some(next) if next > position => return Some(next),
+                        Some(next) => empty = empty.or(Some(next)),
+                        None => {}
+                    }
+                }
+                empty
+            }
+            Term::All(items) => {
+                let mut seen = vec![false; items.len()];
+                let mut position = position;
+                'children: while position < self.children.len() {
+                    for (index, item) in items.iter().enumerate() {
+                        if !seen[index]
+                            && let Some(next) = self.particle(item, position)
+                            && next > position
+                        {
+                            seen[index] = true;
+                            position = next;
+                            continue 'children;
+                        }
+                    }
+                    break;
+                }
+                let mut complete = true;
+                for (item, seen) in items.iter().zip(&seen) {
+                    if !seen && item.min > 0 {
+                        complete = false;
+                        if let Some(name) = self.schema.element_name(&item.term) {
+                            self.expect(position, name);
+                        }
+                    }
+                }
+                complete.then_some(position)
+            }
+            Term::Any => (position < self.children.len()).then_some(position + 1),
+        }
+    }
+}
+
+// ============================================================================
+// Validation
+// ============================================================================
+
+/// Buffered reader recording where lines start, to turn byte offsets into lines
+/// and columns
+struct LineTracker<R> {
+    inner: R,
+    /// Bytes consumed so far
+    offset: u64,
+    /// Offsets of the newlines consumed but not yet passed by [`Self::position`]
+    newlines: VecDeque<u64>,
+    /// Newlines already passed
+    line: usize,
+    /// Offset of the first byte of the current line
+    line_start: u64,
+}
+
+impl<R: BufRead> LineTracker<R> {
+    fn new(inner: R) -> Self {
+        LineTracker {
+            inner,
+            offset: 0,
+            newlines: VecDeque::new(),
+            line: 0,
+            line_start: 0,
+        }
+    }
+
+    /// Line and column of `offset`, which must not be less than a previous call.
+    fn position(&mut self, offset: u64) -> (usize, usize) {
+        while let Some(&newline) = self.newlines.front()
+            && newline < offset
+        {
+            self.newlines.pop_front();
+            self.line += 1;
+            self.line_start = newline + 1;
+        }
+        (self.line + 1, (offset - self.line_start) as usize + 1)
+    }
+}
+
+impl<R: BufRead> Read for LineTracker<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let amt = available.len().min(buf.len());
+        buf[..amt].copy_from_slice(&available[..amt]);
+        self.consume(amt);
+        Ok(amt)
+    }
+}
+
+impl<R: BufRead> BufRead for LineTracker<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Ok(buffer) = self.inner.fill_buf() {
+            let start = self.offset;
+            self.newlines.extend(
+                buffer[..amt.min(buffer.len())]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, byte)| **byte == b'\n')
+                    .map(|(index, _)| start + index as u64),
+            );
+        }
+        self.offset += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+/// Element being read, with what is needed to check it once closed
+struct Frame<'s> {
+    name: String,
+    line: usize,
+    column: usize,
+    /// `None` for elements that are not checked
+    type_def: Option<Resolved<'s>>,
+    text: String,
+    children: Vec<ChildElement>,
+}
+
+struct Validator<'s> {
+    schema: &'s Schema,
+    stack: Vec<Frame<'s>>,
+    violations: Vec<XsdViolation>,
+    root_seen: bool,
+}
+
+impl<'s> Validator<'s> {
+    fn violation(&mut self, line: usize, column: usize, message: String) {
+        if self.violations.len() < MAX_VIOLATIONS {
+            self.violations.push(XsdViolation {
+                line,
+                column,
+                message,
+            });
+        }
+    }
+
+    fn start(&mut self, name: String, line: usize, column: usize) {
+        let type_def = match self.stack.last_mut() {
+            None => {
+                if self.root_seen {
+                    self.violation(line, column, format!("Second root element <{name}>"));
+                }
+                self.root_seen = true;
+                match self.schema.elements.get(&name) {
+                    Some(decl) => Some(self.schema.resolve(&decl.type_def)),
+                    None => {
+                        self.violation(
+                            line,
+                            column,
+                            format!("Element <{name}> is not declared in the schema"),
+                        );
+                        None
+                    }
+                }
+            }
+            Some(parent) => {
+                parent.children.push(ChildElement {
+                    name: name.clone(),
+                    line,
+                    column,
+                });
+                match parent.type_def {
+                    Some(Resolved::Complex(complex)) => complex
+                        .content
+                        .as_ref()
+                        .and_then(|content| self.schema.find_child(content, &name))
+                        .and_then(|child| match child {
+                            Child::Decl(decl) => Some(self.schema.resolve(&decl.type_def)),
+                            Child::Any => None,
+                        }),
+                    // Children of simple types are reported when the parent closes
+                    _ => None,
+                }
+            }
+        };
+        self.stack.push(Frame {
+            name,
+            line,
+            column,
+            type_def,
+            text: String::new(),
+            children: Vec::new(),
+        });
+    }
+
+    fn text(&mut self, text: &str) {
+        if let Some(frame) = self.stack.last_mut() {
+            frame.text.push_str(text);
+        }
+    }
+
+    /// Closes the current element, whose end tag is at `line` and `column`.
+    fn end(&mut self, line: usize, column: usize) {
+        let Some(frame) = self.stack.pop() else {
+            return;
+        };
+        let name = &frame.name;
+        match frame.type_def {
+            None => {}
+            Some(Resolved::Complex(complex)) => {
+                if !complex.mixed && !frame.text.trim().is_empty() {
+                    self.violation(
+                        frame.line,
+                        frame.column,
+                        format!("Text is not allowed in <{name}>"),
+                    );
+                }
+                self.check_children(&frame, complex, line, column);
+            }
+            Some(simple) => {
+                if let Some(child) = frame.children.first() {
+                    let message = format!("Element <{}> is not allowed in <{name}>", child.name);
+                    self.violation(child.line, child.column, message);
+                } else {
+                    let error = match simple {
+                        Resolved::Simple(simple) => self.schema.check_simple(simple, &frame.text),
+                        Resolved::Builtin(base) => check_builtin(base, &frame.text),
+                        Resolved::Complex(_) => None,
+                    };
+                    if let Some(error) = error {
+                        self.violation(frame.line, frame.column, format!("<{name}>: {error}"));
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_children(
+        &mut self,
+        frame: &Frame,
+        complex: &'s ComplexType,
+        line: usize,
+        column: usize,
+    ) {
+        let name = &frame.name;
+        let Some(content) = &complex.content else {
+            if let Some(child) = frame.children.first() {
+                let message = format!("Element <{}> is not allowed in <{name}>", child.name);
+                self.violation(child.line, child.column, message);
+            }
+            return;
+        };
+        let mut matcher = Matcher {
+            schema: self.schema,
+            children: &frame.children,
+            furthest: 0,
+            expected: Vec::new(),
+        };
+        let matched = matcher.particle(content, 0);
+        let (furthest, expected) = (matcher.furthest, matcher.expected);
+        let position = match matched {
+            Some(position) if position == frame.children.len() => return,
+            Some(position) if position >= furthest => position,
+            _ => furthest,
+        };
+        let expected = expected
+            .iter()
+            .map(|name| format!("<{name}>"))
+            .collect::<Vec<_>>()
+            .join(" or ");
+        match frame.children.get(position) {
+            Some(child) => {
+                let mut message = format!("Unexpected element <{}> in <{name}>", child.name);
+                if !expected.is_empty() && position == furthest {
+                    message.push_str(&format!(", expected {expected}"));
+                }
+                self.violation(child.line, child.column, message);
+            }
+            None => {
+                self.violation(line, column, format!("Missing {expected} in <{name}>"));
+            }
+        }
+    }
+}
+
+/// Validates an XML file against the XSD schema at `xsd_path`, returning where it does
+/// not match, in file order. An empty list means the file is valid.
+///
+/// See [`validate_against_xsd_from_reader`].
+pub fn validate_against_xsd<P: AsRef<Path>>(xml_path: P, xsd_path: P) -> Result<Vec<XsdViolation>> {
+    let schema = Schema::load(xsd_path.as_ref())?;
+    validate(open_xml(xml_path)?, &schema)
+}
+
+/// Validates XML from a buffered reader against the XSD schema at `xsd_path`,
+/// returning where it does not match, in file order.
+///
+/// Malformed XML, such as a truncated file, is reported as a violation at the place
+/// it breaks off, and ends the validation. At most 1000 violations are returned. An
+/// unreadable or unsupported schema is an error.
+pub fn validate_against_xsd_from_reader<R: BufRead, P: AsRef<Path>>(
+    reader: R,
+    xsd_path: P,
+) -> Result<Vec<XsdViolation>> {
+    let schema = Schema::load(xsd_path.as_ref())?;
+    validate(decode_xml(reader)?, &schema)
+}
+
+fn validate<R: BufRead>(reader: R, schema: &Schema) -> Result<Vec<XsdViolation>> {
+    let mut reader = Reader::from_reader(LineTracker::new(reader));
+    let mut validator = Validator {
+        schema,
+        stack: Vec::new(),
+        violations: Vec::new(),
+        root_seen: false,
+    };
+    let mut buf = Vec::new();
+
+    while validator.violations.len() < MAX_VIOLATIONS {
+        let offset = reader.buffer_position();
+        let event = match reader.read_event_into(&mut buf) {
+            Ok(event) => event,
+            Err(quick_xml::Error::Io(e)) => {
+                return Err(anyhow::Error::new(e).context("Failed to read XML"));
+            }
+            Err(e) => {
+                let error_offset = reader.error_position();
+                let (line, column) = reader.get_mut().position(error_offset);
+                validator.violation(line, column, format!("Malformed XML: {e}"));
+                break;
+            }
+        };
+        let (line, column) = reader.get_mut().position(offset);
+        match event {
+            Event::Start(ref e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                validator.start(name, line, column);
+            }
+            Event::Empty(ref e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                validator.start(name, line, column);
+                validator.end(line, column);
+            }
+            Event::End(_) => validator.end(line, column),
+            Event::Text(ref text) => validator.text(&text.decode()?),
+            Event::CData(ref text) => validator.text(&text.decode()?),
+            Event::GeneralRef(ref reference) => match reference.resolve_char_ref()? {
+                Some(c) => validator.text(c.encode_utf8(&mut [0; 4])),
+                None => {
+                    let entity = reference.decode()?;
+                    match quick_xml::escape::resolve_predefined_entity(&entity) {
+                        Some(resolved) => validator.text(resolved),
+                        None => {
+                            validator.violation(line, column, format!("Unknown entity &{entity};"))
+                        }
+                    }
+                }
+            },
+            Event::Eof => {
+                if let Some(frame) = validator.stack.last() {
+                    let message = format!("Unexpected end of file inside <{}>", frame.name);
+                    validator.violation(line, column, message);
+                } else if !validator.root_seen {
+                    validator.violation(line, column, "Document has no root element".to_string());
+                }
+                break;
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+    // Elements are checked when they close, after their children
+    let mut violations = validator.violations;
+    violations.sort_by_key(|violation| (violation.line, violation.column));
+    Ok(violations)
+}
+
+/// Runs `parse` after validating `xml_path` against the schema of
+/// [`ParserOptions::validate_first`], if any, as configured by
+/// [`ParserOptions::on_violation`].
+pub(crate) fn validated(
+    xml_path: &Path,
+    options: &ParserOptions,
+    parse: impl FnOnce() -> Result<ParseReport>,
+) -> Result<ParseReport> {
+    let Some(xsd_path) = &options.validate_first else {
+        return parse();
+    };
+    let violations = validate_against_xsd(xml_path, xsd_path)?;
+    if violations.is_empty() {
+        tracing::debug!(xml = %xml_path.display(), "XML matches its schema");
+        return parse();
+    }
+    match options.on_violation {
+        OnViolation::Abort => {
+            let listed: Vec<String> = violations
+                .iter()
+                .take(REPORTED_VIOLATIONS)
+                .map(ToString::to_string)
+                .collect();
+            anyhow::bail!(
+                "{} does not match {} ({} violations):\n{}",
+                xml_path.display(),
+                xsd_path.display(),
+                violations.len(),
+                listed.join("\n")
+            )
+        }
+        OnViolation::Warn => {
+            for violation in violations.iter().take(REPORTED_VIOLATIONS) {
+                tracing::warn!(xml = %xml_path.display(), %violation, "XML does not match its schema");
+            }
+            let mut report = parse()?;
+            report.xsd_violations = violations;
+            Ok(report)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        parse_atc_xml_to_csv_with_options, parse_prescription_xml_to_csvs_with_options,
+    };
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    fn fixture(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(path)
+    }
+
+    fn schema(content: &str) -> Schema {
+        Schema::parse(&format!(
+            r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">{content}</xs:schema>"#
+        ))
+        .unwrap()
+    }
+
+    fn violations(schema: &Schema, xml: &str) -> Vec<String> {
+        validate(xml.as_bytes(), schema)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_content_model() {
+        let schema = schema(
+            r#"<xs:element name="root"><xs:complexType><xs:sequence>
+                <xs:element name="a" type="xs:int"/>
+                <xs:choice minOccurs="0" maxOccurs="unbounded">
+                    <xs:element name="b" type="xs:string"/>
+                    <xs:element name="c" type="xs:string"/>
+                </xs:choice>
+                <xs:element name="d" type="xs:string" minOccurs="0"/>
+            </xs:sequence></xs:complexType></xs:element>"#,
+        );
+        assert!(violations(&schema, "<root><a>1</a><c/><b/><c/><d/></root>").is_empty());
+        assert!(violations(&schema, "<root><a>1</a></root>").is_empty());
+        assert_eq!(
+            violations(&schema, "<root><a>1</a><d/><b/></root>"),
+            ["1:19: Unexpected element <b> in <root>"]
+        );
+        assert_eq!(
+            violations(&schema, "<root>\n  <b/>\n</root>"),
+            ["2:3: Unexpected element <b> in <root>, expected <a>"]
+        );
+        assert_eq!(
+            violations(&schema, "<root></root>"),
+            ["1:7: Missing <a> in <root>"]
+        );
+        assert_eq!(
+            violations(&schema, "<root><a>x</a>oops</root>"),
+            [
+                "1:1: Text is not allowed in <root>",
+                "1:7: <a>: 'x' is not a valid int"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_simple_type_facets() {
+        let schema = schema(
+            r#"<xs:simpleType name="flag"><xs:restriction base="xs:string">
+                <xs:enumeration value="0"/><xs:enumeration value="1"/>
+            </xs:restriction></xs:simpleType>
+            <xs:simpleType name="code"><xs:restriction base="xs:string">
+                <xs:pattern value="[A-Z][0-9]{2}"/><xs:maxLength value="3"/>
+            </xs:restriction></xs:simpleType>
+            <xs:simpleType name="small"><xs:restriction base="xs:integer">
+                <xs:minInclusive value="1"/><xs:maxExclusive value="10"/>
+            </xs:restriction></xs:simpleType>
+            <xs:element name="root"><xs:complexType><xs:all>
+                <xs:element name="flag" type="flag"/>
+                <xs:element name="code" type="code"/>
+                <xs:element name="small" type="small" minOccurs="0"/>
+            </xs:all></xs:complexType></xs:element>"#,
+        );
+        assert!(violations(&schema, "<root><code>A01</code><flag>1</flag></root>").is_empty());
+        assert_eq!(
+            violations(
+                &schema,
+                "<root><flag>S</flag><code>A1</code><small>10</small></root>"
+            ),
+            [
+                "1:7: <flag>: 'S' is not one of 0, 1",
+                "1:21: <code>: 'A1' does not match the pattern [A-Z][0-9]{2}",
+                "1:36: <small>: '10' is not less than 10",
+            ]
+        );
+        assert_eq!(
+            violations(&schema, "<root><flag>0</flag><small>x</small></root>"),
+            [
+                "1:21: <small>: 'x' is not a valid integer",
+                "1:37: Missing <code> in <root>",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_malformed_xml() {
+        let schema = schema(r#"<xs:element name="root" type="xs:string"/>"#);
+        assert_eq!(
+            violations(&schema, "<root>\n<"),
+            ["2:1: Malformed XML: syntax error: tag not closed: `>` not found before end of input"]
+        );
+        assert_eq!(
+            violations(&schema, "<root>text"),
+            ["1:11: Unexpected end of file inside <root>"]
+        );
+        assert_eq!(
+            violations(&schema, "<other/>"),
+            ["1:1: Element <other> is not declared in the schema"]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_schema() {
+        let xsd = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+            <xs:element name="root" type="missing"/></xs:schema>"#;
+        let err = Schema::parse(xsd).err().unwrap();
+        assert_eq!(err.to_string(), "Unknown type missing");
+
+        let xsd = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+            <xs:include schemaLocation="other.xsd"/></xs:schema>"#;
+        let err = Schema::parse(xsd).err().unwrap();
+        assert_eq!(err.to_string(), "<xs:include> is not supported");
+    }
+
+    #[test]
+    fn test_fixtures_match_their_schemas() {
+        for (xml, xsd) in [
+            ("delta/Prescripcion.xml", "xsd/Prescripcion.xsd"),
+            ("nomenclator/DICCIONARIO_ATC.xml", "xsd/DICCIONARIO_ATC.xsd"),
+        ] {
+            let violations = validate_against_xsd(fixture(xml), fixture(xsd)).unwrap();
+            assert!(violations.is_empty(), "{xml}: {violations:?}");
+        }
+    }
+
+    #[test]
+    fn test_broken_prescription_file() {
+        let violations: Vec<String> = validate_against_xsd(
+            fixture("xsd/Prescripcion_invalid.xml"),
+            fixture("xsd/Prescripcion.xsd"),
+        )
+        .unwrap()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        assert_eq!(
+            violations,
+            [
+                "12:5: <sw_receta>: 'S' is not one of 0, 1",
+                "34:5: Unexpected element <des_prese> in <prescription>, expected <des_nomco>",
+                "62:51: Unexpected end of file inside <des_prese>",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_first() {
+        let dir = TempDir::new().unwrap();
+        let csv_path = dir.path().join("atc.csv");
+        let mut options = ParserOptions {
+            validate_first: Some(fixture("xsd/DICCIONARIO_ATC.xsd")),
+            ..Default::default()
+        };
+
+        let err = parse_atc_xml_to_csv_with_options(
+            fixture("drift/DICCIONARIO_ATC.xml"),
+            csv_path.clone(),
+            &options,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("(2 violations):\n7:9: Unexpected element <nuevocampo> in <atc>\n18:9: Unexpected element <nuevocampo> in <atc>"),
+            "{err}"
+        );
+        assert!(!csv_path.exists());
+
+        options.on_violation = OnViolation::Warn;
+        let report = parse_atc_xml_to_csv_with_options(
+            fixture("drift/DICCIONARIO_ATC.xml"),
+            csv_path.clone(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(report.records, 3);
+        assert_eq!(report.xsd_violations.len(), 2);
+        assert_eq!(report.xsd_violations[0].line, 7);
+
+        options.validate_first = Some(fixture("xsd/Prescripcion.xsd"));
+        options.on_violation = OnViolation::Abort;
+        let report = parse_prescription_xml_to_csvs_with_options(
+            fixture("delta/Prescripcion.xml"),
+            dir.path().to_path_buf(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(report.records, 3);
+        assert!(report.xsd_violations.is_empty());
+        assert!(
+            parse_prescription_xml_to_csvs_with_options(
+                fixture("xsd/Prescripcion_invalid.xml"),
+                dir.path().to_path_buf(),
+                &options,
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! The types needed to use the crate, for a single glob import.
+//!
+//! ```
+//! use cima_rs::prelude::*;
+//! ```
+//!
+//! The parser's `SupplyProblem` and `ActiveIngredient` are renamed
+//! `PrescriptionSupplyProblem` and `PrescriptionActiveIngredient`, as the API models
+//! keep those names.
+
+#[cfg(feature = "api")]
+pub use crate::api_client::{
+    ApiStatusError, CimaClient, CimaClientBuilder, CimaClientOptions, EndpointError, RetryPolicy,
+};
+#[cfg(feature = "record-replay")]
+pub use crate::cassette::{Cassette, RecordMode, Scrub};
+#[cfg(feature = "dataset")]
+pub use crate::dataset::{Dataset, DatasetOptions, SyncReport};
+#[cfg(feature = "downloader")]
+pub use crate::downloader::{
+    DownloadOptions, DownloadOutcome, DownloadProgress, DownloadProgressCallback, ExtractedFile,
+    ExtractionReport,
+};
+#[cfg(feature = "api")]
+pub use crate::endpoints::{
+    ClinicalDescriptionFetch, ClinicalDescriptionFetchOpts, DocumentDownload,
+    DocumentDownloadOptions, DocumentDownloadStatus, Endpoint, MasterDataParams, MedicationId,
+    PhotoDownload, QueryParams, RawEndpoint, SafetyNoteDownload, SearchClinicalDescriptionParams,
+    SearchMedicationsParams, SearchPresentationsParams, TechnicalSheetQuery,
+};
+#[cfg(all(feature = "api", feature = "parser"))]
+pub use crate::enrich::{NregistroOptions, NregistroReport};
+#[cfg(feature = "api")]
+pub use crate::export::{CsvExport, CsvRecord};
+#[cfg(feature = "api")]
+pub use crate::models::{
+    ActiveIngredient, AtcCode, AuthorizationStatus, ChangeAspect, ChangeRecord, ChangeType,
+    ClinicalDescription, Document, DocumentType, Excipient, MasterDataType, MasterItem,
+    MaterialDocument, Medication, MedicationSummary, PaginatedResponse, Photo, PhotoType,
+    Presentation, PresentationSummary, SafetyMaterial, SafetyNote, Section, SupplyProblem,
+};
+#[cfg(feature = "validate-xml")]
+pub use crate::parser::OnViolation;
+#[cfg(feature = "parser")]
+pub use crate::parser::{
+    ActiveIngredient as PrescriptionActiveIngredient, ActiveIngredientList, ActiveIngridientRecord,
+    AdminRoute, AdministrationRouteList, AdministrationRouteRecord, AtcDuplicate, AtcList,
+    AtcRecord, BoolParsing, BoolRepr, ContainerList, ContainerRecord, ContainerUnitList,
+    ContainerUnitRecord, DcpList, DcpRecord, DcpfList, DcpfRecord, DcsaList, DcsaRecord,
+    DictionaryKind, DiffOptions, ExcipientList, ExcipientRecord, FileStatus, Header, HeaderStyle,
+    LaboratoryList, LaboratoryRecord, NomenclatorFile, NomenclatorOptions, NomenclatorReport,
+    OnDuplicate, OnError, OutputFormat, ParseReport, ParseStats, ParserOptions,
+    PharmaceuticalFormList, PharmaceuticalFormRecord, PrescriptionAtc, PrescriptionExcipient,
+    PrescriptionForm, PrescriptionKey, PrescriptionList, PrescriptionNote, PrescriptionReader,
+    PrescriptionRecord, QuoteStyle, RecordError, RegistrationStatusList, RegistrationStatusRecord,
+    SimplifiedPharmaceuticalFormList, SimplifiedPharmaceuticalFormRecord, StatsOptions,
+    SupplyProblem as PrescriptionSupplyProblem, ValidationReport,
+};
+#[cfg(feature = "test-util")]
+pub use crate::testing::fixtures::FixtureOptions;
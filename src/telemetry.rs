@@ -0,0 +1,52 @@
+//! Metrics emitted with the `metrics` feature.
+//!
+//! The values go to the recorder installed by the application, such as
+//! `metrics-exporter-prometheus`; without the feature these functions do nothing.
+
+#[cfg(any(feature = "api", feature = "parser"))]
+use std::time::Duration;
+
+/// Counter of API responses, labelled with `endpoint` and `status`
+pub const REQUESTS: &str = "cima_requests_total";
+/// Histogram of the seconds taken by API requests, labelled with `endpoint`
+pub const REQUEST_DURATION: &str = "cima_request_duration_seconds";
+/// Histogram of the seconds taken to parse a file, labelled with `file`
+pub const PARSE_DURATION: &str = "cima_parse_duration_seconds";
+/// Counter of the rows written by the parser, labelled with `file`
+pub const ROWS_WRITTEN: &str = "cima_rows_written_total";
+/// Counter of the bytes downloaded from the nomenclator
+pub const DOWNLOADED_BYTES: &str = "cima_downloaded_bytes_total";
+
+/// Counts a request to `endpoint` answered with `status`, or `"error"` without response
+#[cfg(feature = "api")]
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn record_request(endpoint: &str, status: Option<u16>, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        let status = status.map_or_else(|| "error".to_string(), |status| status.to_string());
+        metrics::counter!(REQUESTS, "endpoint" => endpoint.to_string(), "status" => status)
+            .increment(1);
+        metrics::histogram!(REQUEST_DURATION, "endpoint" => endpoint.to_string())
+            .record(elapsed.as_secs_f64());
+    }
+}
+
+/// Records the parse of `file` into `rows` rows
+#[cfg(feature = "parser")]
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn record_parse(file: &str, rows: usize, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        metrics::histogram!(PARSE_DURATION, "file" => file.to_string())
+            .record(elapsed.as_secs_f64());
+        metrics::counter!(ROWS_WRITTEN, "file" => file.to_string()).increment(rows as u64);
+    }
+}
+
+/// Counts `bytes` downloaded from the nomenclator
+#[cfg(feature = "downloader")]
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn record_download(bytes: u64) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(DOWNLOADED_BYTES).increment(bytes);
+}
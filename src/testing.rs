@@ -0,0 +1,3 @@
+//! Helpers for the tests and benchmarks of the crate and of its users.
+
+pub mod fixtures;
@@ -0,0 +1,658 @@
+//! Synthetic nomenclator files of any size, for tests and benchmarks.
+//!
+//! The files follow the layout read by the parser, and the same `seed` always gives
+//! the same bytes. Codes referenced by the prescriptions exist in the dictionaries
+//! written by [`generate_nomenclator`], so its output also passes
+//! [`validate_nomenclator_output`](crate::parser::validate_nomenclator_output).
+
+use crate::parser::{DictionaryKind, PRESCRIPTION_XML};
+use quick_xml::escape::escape;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Shape of the generated prescriptions
+///
+/// Shares go from 0.0, never, to 1.0, always.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixtureOptions {
+    /// Share of prescriptions with a pharmaceutical form, which has one to three active
+    /// ingredients and one or two administration routes
+    pub forms: f64,
+    /// Share of prescriptions with one or two ATC codes
+    pub atc_codes: f64,
+    /// Share of ATC codes with a duplicity
+    pub duplicities: f64,
+    /// Share of prescriptions with a supply problem
+    pub supply_problems: f64,
+    /// Share of prescriptions with one or two excipients
+    pub excipients: f64,
+    /// Share of prescriptions with an informative note
+    pub notes: f64,
+    /// Records of each dictionary, whose codes the prescriptions reference
+    pub dictionary_records: usize,
+}
+
+impl Default for FixtureOptions {
+    fn default() -> Self {
+        Self {
+            forms: 0.95,
+            atc_codes: 0.9,
+            duplicities: 0.2,
+            supply_problems: 0.1,
+            excipients: 0.33,
+            notes: 0.15,
+            dictionary_records: 50,
+        }
+    }
+}
+
+/// SplitMix64, small and stable across platforms and versions
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Number in `0..n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+
+    /// Whether an event of probability `share` happens
+    fn chance(&mut self, share: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < share
+    }
+
+    fn flag(&mut self, share: f64) -> u8 {
+        u8::from(self.chance(share))
+    }
+
+    fn pick<'a>(&mut self, values: &[&'a str]) -> &'a str {
+        values[self.below(values.len())]
+    }
+
+    /// `dd/mm/yyyy` date between 2000 and 2024
+    fn date(&mut self) -> String {
+        format!(
+            "{:02}/{:02}/{}",
+            1 + self.below(28),
+            1 + self.below(12),
+            2000 + self.below(25)
+        )
+    }
+}
+
+const SUBSTANCES: &[&str] = &[
+    "PARACETAMOL",
+    "IBUPROFENO",
+    "AMOXICILINA",
+    "ÁCIDO ACETILSALICÍLICO",
+    "OMEPRAZOL",
+    "METFORMINA",
+    "SALBUTAMOL",
+    "ENALAPRIL",
+    "LORAZEPAM",
+    "DICLOFENACO",
+];
+const FORMS: &[&str] = &[
+    "COMPRIMIDO",
+    "CÁPSULA DURA",
+    "SOLUCIÓN ORAL",
+    "POLVO Y DISOLVENTE PARA SOLUCIÓN INYECTABLE",
+    "CREMA",
+    "COMPRIMIDO RECUBIERTO CON PELÍCULA",
+];
+const CONTAINERS: &[&str] = &["BLISTER", "FRASCO", "AMPOLLA", "TUBO", "SOBRE"];
+const UNITS: &[&str] = &["COMPRIMIDOS", "ML", "G", "CÁPSULAS", "SOBRES"];
+const ROUTES: &[&str] = &[
+    "VÍA ORAL",
+    "VÍA INTRAVENOSA",
+    "VÍA CUTÁNEA",
+    "VÍA INHALATORIA",
+];
+const STATUSES: &[&str] = &["AUTORIZADO", "SUSPENDIDO", "REVOCADO", "ANULADO"];
+const EXCIPIENTS: &[&str] = &["LACTOSA", "SACAROSA", "SODIO", "ALCOHOL BENCÍLICO"];
+const CITIES: &[&str] = &["MADRID", "BARCELONA", "SEVILLA", "A CORUÑA", "L'HOSPITALET"];
+const LABORATORIES: &[&str] = &["FARMA", "LABORATORIOS", "PHARMA & CO", "INDUSTRIA QUÍMICA"];
+const ATC_GROUPS: &[&str] = &[
+    "A", "B", "C", "D", "G", "H", "J", "L", "M", "N", "P", "R", "S", "V",
+];
+
+/// ATC code of the dictionary record `index`, seven characters long up to 14 million
+fn atc_code(index: usize) -> String {
+    format!(
+        "{}{:06}",
+        ATC_GROUPS[index % ATC_GROUPS.len()],
+        index / ATC_GROUPS.len()
+    )
+}
+
+/// Writes `<name>value</name>`, escaping `value`
+fn element<W: Write>(out: &mut W, name: &str, value: &str) -> io::Result<()> {
+    write!(out, "<{name}>{}</{name}>", escape(value))
+}
+
+/// Writes a Prescripcion.xml with `n_records` prescriptions generated from `seed` and
+/// the default [`FixtureOptions`] to `into`.
+pub fn generate_prescription_xml<W: Write>(n_records: usize, seed: u64, into: W) -> io::Result<()> {
+    generate_prescription_xml_with_options(n_records, seed, &FixtureOptions::default(), into)
+}
+
+/// Writes a Prescripcion.xml with `n_records` prescriptions shaped after `options` to
+/// `into`.
+///
+/// `cod_nacion` counts up from 100000, so every code is unique and up to 900 000
+/// records keep the six digits of the schema.
+pub fn generate_prescription_xml_with_options<W: Write>(
+    n_records: usize,
+    seed: u64,
+    options: &FixtureOptions,
+    into: W,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(into);
+    let mut rng = Rng(seed);
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, "<aemps_prescripcion>")?;
+    write!(out, "<header>")?;
+    element(&mut out, "listprescriptiondate", &rng.date())?;
+    writeln!(out, "</header>")?;
+    for index in 0..n_records {
+        write_prescription(&mut out, &mut rng, options, index)?;
+    }
+    writeln!(out, "</aemps_prescripcion>")?;
+    out.flush()
+}
+
+fn write_prescription<W: Write>(
+    out: &mut W,
+    rng: &mut Rng,
+    options: &FixtureOptions,
+    index: usize,
+) -> io::Result<()> {
+    let records = options.dictionary_records;
+    let code = |rng: &mut Rng| (1 + rng.below(records)).to_string();
+    let nro_definitivo = 60000 + index;
+    let substance = rng.pick(SUBSTANCES);
+    let dose = 5 * (1 + rng.below(200));
+    let units = 10 * (1 + rng.below(6));
+    let name = format!("{substance} {dose} mg {}", rng.pick(FORMS));
+
+    write!(out, "<prescription>")?;
+    element(out, "cod_nacion", &format!("{:06}", 100000 + index))?;
+    element(out, "nro_definitivo", &nro_definitivo.to_string())?;
+    element(out, "des_nomco", &name)?;
+    element(out, "des_prese", &format!("{name}, {units} unidades"))?;
+    element(out, "cod_dcsa", &code(rng))?;
+    element(out, "cod_dcp", &code(rng))?;
+    element(out, "cod_dcpf", &code(rng))?;
+    element(out, "des_dosific", &format!("{dose} mg"))?;
+    element(out, "cod_envase", &code(rng))?;
+    element(out, "contenido", &units.to_string())?;
+    element(out, "unid_contenido", &code(rng))?;
+    element(out, "nro_conte", "1")?;
+    for (flag, share) in [
+        ("sw_psicotropo", 0.08),
+        ("sw_estupefaciente", 0.02),
+        ("sw_afecta_conduccion", 0.25),
+        ("sw_triangulo_negro", 0.06),
+    ] {
+        element(out, flag, &rng.flag(share).to_string())?;
+    }
+    element(
+        out,
+        "url_fictec",
+        &format!("https://cima.aemps.es/cima/dochtml/ft/{nro_definitivo}/FT_{nro_definitivo}.html"),
+    )?;
+    element(
+        out,
+        "url_prosp",
+        &format!("https://cima.aemps.es/cima/dochtml/p/{nro_definitivo}/P_{nro_definitivo}.html"),
+    )?;
+    for (flag, share) in [
+        ("sw_receta", 0.8),
+        ("sw_generico", 0.5),
+        ("sw_sustituible", 0.9),
+        ("sw_envase_clinico", 0.05),
+        ("sw_uso_hospitalario", 0.05),
+        ("sw_diagnostico_hospitalario", 0.05),
+        ("sw_tld", 0.01),
+        ("sw_especial_control_medico", 0.02),
+        ("sw_huerfano", 0.02),
+        ("sw_base_a_plantas", 0.01),
+    ] {
+        element(out, flag, &rng.flag(share).to_string())?;
+    }
+    let laboratory = code(rng);
+    element(out, "laboratorio_titular", &laboratory)?;
+    element(out, "laboratorio_comercializador", &laboratory)?;
+    element(out, "fecha_autorizacion", &rng.date())?;
+    let commercialized = rng.flag(0.9);
+    element(out, "sw_comercializado", &commercialized.to_string())?;
+    if commercialized == 1 {
+        element(out, "fec_comer", &rng.date())?;
+    }
+    element(out, "cod_sitreg", &code(rng))?;
+    element(out, "cod_sitreg_presen", &code(rng))?;
+    element(out, "fecha_situacion_registro", &rng.date())?;
+    element(out, "fec_sitreg_presen", &rng.date())?;
+    let excipients = rng.chance(options.excipients);
+    element(
+        out,
+        "sw_tiene_excipientes_decl_obligatoria",
+        &u8::from(excipients).to_string(),
+    )?;
+    for (flag, share) in [
+        ("biosimilar", 0.03),
+        ("importacion_paralela", 0.02),
+        ("radiofarmaco", 0.01),
+        ("serializacion", 0.95),
+    ] {
+        element(out, flag, &rng.flag(share).to_string())?;
+    }
+
+    if rng.chance(options.forms) {
+        let ingredients = 1 + rng.below(3);
+        write!(out, "<formasfarmaceuticas>")?;
+        element(out, "cod_forfar", &code(rng))?;
+        element(out, "cod_forfar_simplificada", &code(rng))?;
+        element(out, "nro_pactiv", &ingredients.to_string())?;
+        for order in 1..=ingredients {
+            let dose = rng.below(1000);
+            write!(out, "<composicion_pa>")?;
+            element(out, "cod_principio_activo", &code(rng))?;
+            element(out, "orden_colacion", &order.to_string())?;
+            element(out, "dosis_pa", &format!("{dose},5"))?;
+            element(out, "unidad_dosis_pa", "mg")?;
+            element(out, "dosis_composicion", &dose.to_string())?;
+            element(out, "unidad_composicion", "mg")?;
+            element(out, "dosis_administracion", "1")?;
+            element(out, "unidad_administracion", "comprimido")?;
+            element(out, "dosis_prescripcion", &dose.to_string())?;
+            element(out, "unidad_prescripcion", "mg")?;
+            write!(out, "</composicion_pa>")?;
+        }
+        for _ in 0..1 + rng.below(2) {
+            write!(out, "<viasadministracion>")?;
+            element(out, "cod_via_admin", &code(rng))?;
+            write!(out, "</viasadministracion>")?;
+        }
+        write!(out, "</formasfarmaceuticas>")?;
+    }
+    if rng.chance(options.atc_codes) {
+        for _ in 0..1 + rng.below(2) {
+            write!(out, "<atc>")?;
+            element(out, "cod_atc", &atc_code(rng.below(records)))?;
+            if rng.chance(options.duplicities) {
+                write!(out, "<duplicidades>")?;
+                element(out, "atc_duplicidad", &atc_code(rng.below(records)))?;
+                element(
+                    out,
+                    "descripcion_atc_duplicidad",
+                    &format!("Duplicidad con {}", rng.pick(SUBSTANCES)),
+                )?;
+                element(out, "efecto_duplicidad", "Riesgo de sobredosis")?;
+                element(out, "recomendacion_duplicidad", "Evitar el uso conjunto")?;
+                write!(out, "</duplicidades>")?;
+            }
+            write!(out, "</atc>")?;
+        }
+    }
+    if rng.chance(options.supply_problems) {
+        write!(out, "<problemassuministro>")?;
+        element(out, "fecha_inicio", &rng.date())?;
+        element(
+            out,
+            "observaciones",
+            &format!(
+                "Problemas de fabricación; \"retraso\" de {} días",
+                rng.below(90)
+            ),
+        )?;
+        write!(out, "</problemassuministro>")?;
+    }
+    if excipients {
+        for _ in 0..1 + rng.below(2) {
+            write!(out, "<excipientes>")?;
+            element(out, "cod_excipiente", &code(rng))?;
+            element(out, "cantidad", &format!("{},25", rng.below(100)))?;
+            element(out, "unidad", "mg")?;
+            write!(out, "</excipientes>")?;
+        }
+    }
+    if rng.chance(options.notes) {
+        write!(out, "<notas>")?;
+        element(out, "tipo_nota", "1")?;
+        element(out, "num_nota", &index.to_string())?;
+        element(out, "referencia_nota", &format!("MUH/{index}"))?;
+        element(
+            out,
+            "asunto_nota",
+            &format!("Nota informativa sobre {}", rng.pick(SUBSTANCES)),
+        )?;
+        element(out, "fecha_nota", &rng.date())?;
+        element(
+            out,
+            "url_nota",
+            &format!("https://www.aemps.gob.es/notas/{index}.htm"),
+        )?;
+        write!(out, "</notas>")?;
+    }
+    writeln!(out, "</prescription>")
+}
+
+/// Value of a dictionary field
+#[derive(Clone, Copy)]
+enum Field {
+    /// Code of the record: its position from 1, or an ATC code
+    Code,
+    /// Text picked from the list, followed by the position of the record
+    Name(&'static [&'static str]),
+    /// Code of a record of a dictionary of the same size
+    Reference,
+    /// Text picked from the list, left out of some records
+    Optional(&'static [&'static str]),
+}
+
+/// Root element, record element and fields of the dictionary `kind`
+fn layout(kind: DictionaryKind) -> (&'static str, &'static str, Vec<(&'static str, Field)>) {
+    use Field::*;
+    match kind {
+        DictionaryKind::Atc => (
+            "aemps_prescripcion_atc",
+            "atc",
+            vec![
+                ("nroatc", Code),
+                ("codigoatc", Code),
+                ("descatc", Name(SUBSTANCES)),
+            ],
+        ),
+        DictionaryKind::Dcp => (
+            "aemps_prescripcion_dcp",
+            "dcp",
+            vec![
+                ("codigodcp", Code),
+                ("nombredcp", Name(SUBSTANCES)),
+                ("codigodcsa", Reference),
+            ],
+        ),
+        DictionaryKind::Dcpf => (
+            "aemps_prescripcion_dcpf",
+            "dcpf",
+            vec![
+                ("codigodcpf", Code),
+                ("nombredcpf", Name(FORMS)),
+                ("codigodcp", Reference),
+            ],
+        ),
+        DictionaryKind::Dcsa => (
+            "aemps_prescripcion_dcsa",
+            "dcsa",
+            vec![("codigodcsa", Code), ("nombredcsa", Name(SUBSTANCES))],
+        ),
+        DictionaryKind::Containers => (
+            "aemps_prescripcion_envases",
+            "envases",
+            vec![("codigoenvase", Code), ("envase", Name(CONTAINERS))],
+        ),
+        DictionaryKind::Excipients => (
+            "aemps_prescripcion_excipientes",
+            "excipientes",
+            vec![("codigoedo", Code), ("edo", Name(EXCIPIENTS))],
+        ),
+        DictionaryKind::PharmaceuticalForms => (
+            "aemps_prescripcion_formas_farmaceuticas",
+            "formasfarmaceuticas",
+            vec![
+                ("codigoformafarmaceutica", Code),
+                ("formafarmaceutica", Name(FORMS)),
+                ("codigoformafarmaceuticasimplificada", Reference),
+            ],
+        ),
+        DictionaryKind::SimplifiedForms => (
+            "aemps_prescripcion_formas_farmaceuticas_simplificadas",
+            "formasfarmaceuticassimplificadas",
+            vec![
+                ("codigoformafarmaceuticasimplificada", Code),
+                ("formafarmaceuticasimplificada", Name(FORMS)),
+            ],
+        ),
+        DictionaryKind::Laboratories => (
+            "aemps_prescripcion_laboratorios",
+            "laboratorios",
+            vec![
+                ("codigolaboratorio", Code),
+                ("laboratorio", Name(LABORATORIES)),
+                (
+                    "direccion",
+                    Optional(&["CALLE MAYOR 1", "AVENIDA DE EUROPA 20"]),
+                ),
+                ("codigopostal", Optional(&["28001", "08001", "41001"])),
+                ("localidad", Optional(CITIES)),
+                ("cif", Optional(&["A00000000", "B12345678"])),
+            ],
+        ),
+        DictionaryKind::ActiveIngredients => (
+            "aemps_prescripcion_principios_activos",
+            "principiosactivos",
+            vec![
+                ("nroprincipioactivo", Code),
+                ("codigoprincipioactivo", Code),
+                ("principioactivo", Name(SUBSTANCES)),
+            ],
+        ),
+        DictionaryKind::RegistrationStatuses => (
+            "aemps_prescripcion_situacion_registro",
+            "situacionesregistro",
+            vec![
+                ("codigosituacionregistro", Code),
+                ("situacionregistro", Name(STATUSES)),
+            ],
+        ),
+        DictionaryKind::ContainerUnits => (
+            "aemps_prescripcion_unidad_contenido",
+            "unidadescontenido",
+            vec![
+                ("codigounidadcontenido", Code),
+                ("unidadcontenido", Name(UNITS)),
+            ],
+        ),
+        DictionaryKind::AdministrationRoutes => (
+            "aemps_prescripcion_vias_administracion",
+            "viasadministracion",
+            vec![
+                ("codigoviaadministracion", Code),
+                ("viaadministracion", Name(ROUTES)),
+            ],
+        ),
+    }
+}
+
+/// Writes the dictionary `kind` with `n_records` records generated from `seed` to
+/// `into`.
+///
+/// Codes are the positions of the records from 1, and ATC codes a letter followed by
+/// six digits, so the codes the prescriptions reference exist when the dictionary has
+/// [`FixtureOptions::dictionary_records`] records.
+pub fn generate_dictionary_xml<W: Write>(
+    kind: DictionaryKind,
+    n_records: usize,
+    seed: u64,
+    into: W,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(into);
+    let mut rng = Rng(seed);
+    let (root, record, fields) = layout(kind);
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, "<{root}>")?;
+    for index in 0..n_records {
+        write!(out, "<{record}>")?;
+        for (name, field) in &fields {
+            let value = match field {
+                Field::Code if name == &"codigoatc" => atc_code(index),
+                Field::Code if name == &"codigoprincipioactivo" => (10000 + index).to_string(),
+                Field::Code => (index + 1).to_string(),
+                Field::Name(names) => format!("{} {}", rng.pick(names), index + 1),
+                Field::Reference => (1 + rng.below(n_records)).to_string(),
+                Field::Optional(values) => {
+                    if !rng.chance(0.7) {
+                        continue;
+                    }
+                    rng.pick(values).to_string()
+                }
+            };
+            element(&mut out, name, &value)?;
+        }
+        writeln!(out, "</{record}>")?;
+    }
+    writeln!(out, "</{root}>")?;
+    out.flush()
+}
+
+/// Writes every dictionary, with [`FixtureOptions::dictionary_records`] records, and a
+/// Prescripcion.xml with `n_records` prescriptions to `dir`, under the file names of
+/// the nomenclator archive.
+pub fn generate_nomenclator<P: AsRef<Path>>(
+    dir: P,
+    n_records: usize,
+    seed: u64,
+    options: &FixtureOptions,
+) -> io::Result<()> {
+    let dir = dir.as_ref();
+    for (offset, kind) in DictionaryKind::ALL.into_iter().enumerate() {
+        let file = File::create(dir.join(kind.default_xml_filename()))?;
+        generate_dictionary_xml(
+            kind,
+            options.dictionary_records,
+            seed.wrapping_add(offset as u64 + 1),
+            file,
+        )?;
+    }
+    let file = File::create(dir.join(PRESCRIPTION_XML))?;
+    generate_prescription_xml_with_options(n_records, seed, options, file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{
+        ParserOptions, parse_prescription_xml_from_reader, validate_nomenclator_output,
+    };
+
+    fn prescriptions(n_records: usize, seed: u64, options: &FixtureOptions) -> Vec<u8> {
+        let mut xml = Vec::new();
+        generate_prescription_xml_with_options(n_records, seed, options, &mut xml).unwrap();
+        xml
+    }
+
+    #[test]
+    fn test_same_seed_same_bytes() {
+        let options = FixtureOptions::default();
+        let xml = prescriptions(200, 7, &options);
+        assert_eq!(xml, prescriptions(200, 7, &options));
+        assert_ne!(xml, prescriptions(200, 8, &options));
+        for kind in DictionaryKind::ALL {
+            let (mut first, mut second) = (Vec::new(), Vec::new());
+            generate_dictionary_xml(kind, 30, 7, &mut first).unwrap();
+            generate_dictionary_xml(kind, 30, 7, &mut second).unwrap();
+            assert_eq!(first, second, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn test_proportions() {
+        let none = FixtureOptions {
+            forms: 0.0,
+            atc_codes: 0.0,
+            supply_problems: 0.0,
+            excipients: 0.0,
+            notes: 0.0,
+            ..Default::default()
+        };
+        let list = parse_prescription_xml_from_reader(&prescriptions(100, 1, &none)[..]).unwrap();
+        assert_eq!(list.records.len(), 100);
+        assert!(list.records.iter().all(|record| record.forms.is_none()
+            && record.atc_codes.is_empty()
+            && record.supply_problems.is_empty()
+            && record.excipients.is_empty()
+            && record.notes.is_empty()));
+
+        let all = FixtureOptions {
+            forms: 1.0,
+            atc_codes: 1.0,
+            duplicities: 1.0,
+            supply_problems: 1.0,
+            excipients: 1.0,
+            notes: 1.0,
+            ..Default::default()
+        };
+        let list = parse_prescription_xml_from_reader(&prescriptions(100, 1, &all)[..]).unwrap();
+        assert!(list.records.iter().all(|record| {
+            record.forms.is_some()
+                && record
+                    .atc_codes
+                    .iter()
+                    .all(|atc| !atc.duplicates.is_empty())
+                && !record.atc_codes.is_empty()
+                && !record.supply_problems.is_empty()
+                && !record.excipients.is_empty()
+                && !record.notes.is_empty()
+        }));
+    }
+
+    #[test]
+    fn test_dictionaries_parse() {
+        let dir = tempfile::tempdir().unwrap();
+        for kind in DictionaryKind::ALL {
+            let xml = dir.path().join(kind.default_xml_filename());
+            generate_dictionary_xml(kind, 40, 3, File::create(&xml).unwrap()).unwrap();
+            let csv = dir.path().join(kind.default_csv_filename());
+            let report = kind.parse(&xml, &csv, &ParserOptions::default()).unwrap();
+            assert_eq!(report.records, 40, "{kind:?}");
+            assert_eq!(report.duplicates, 0, "{kind:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nomenclator_references_are_valid() {
+        let (work_dir, output_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        generate_nomenclator(work_dir.path(), 300, 11, &FixtureOptions::default()).unwrap();
+
+        let report = crate::parser::parse_all_nomenclator(
+            work_dir.path(),
+            output_dir.path(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        assert!(report.is_success());
+        let validation = validate_nomenclator_output(output_dir.path()).unwrap();
+        assert!(validation.is_valid(), "{validation}");
+        assert!(validation.skipped.is_empty(), "{:?}", validation.skipped);
+    }
+
+    #[cfg(feature = "validate-xml")]
+    #[test]
+    fn test_matches_schema() {
+        use crate::parser::validate_against_xsd;
+
+        let dir = tempfile::tempdir().unwrap();
+        let xsd = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/xsd");
+        let xml = dir.path().join(PRESCRIPTION_XML);
+        generate_prescription_xml(500, 5, File::create(&xml).unwrap()).unwrap();
+        let violations =
+            validate_against_xsd(xml.as_path(), &xsd.join("Prescripcion.xsd")).unwrap();
+        assert!(violations.is_empty(), "{violations:?}");
+
+        let xml = dir.path().join("DICCIONARIO_ATC.xml");
+        generate_dictionary_xml(DictionaryKind::Atc, 500, 5, File::create(&xml).unwrap()).unwrap();
+        let violations =
+            validate_against_xsd(xml.as_path(), &xsd.join("DICCIONARIO_ATC.xsd")).unwrap();
+        assert!(violations.is_empty(), "{violations:?}");
+    }
+}
@@ -1,17 +1,35 @@
+//! Client tests over the responses recorded in tests/fixtures/api. With the
+//! `live-tests` feature the same assertions run against the live CIMA API, but only
+//! when asked for, as they need network access:
+//! `cargo test --features live-tests --test api_integration_tests -- --ignored`.
+#![cfg(feature = "api")]
+
+mod support;
+
 use anyhow::Result;
 use cima_rs::{
     CimaClient, MasterDataParams, MasterDataType, SearchClinicalDescriptionParams,
     SearchMedicationsParams, SearchPresentationsParams,
 };
+use wiremock::MockServer;
+
+/// Client of the live API, without a mock server
+#[cfg(feature = "live-tests")]
+async fn create_client() -> Result<(Option<MockServer>, CimaClient)> {
+    Ok((None, CimaClient::new()?))
+}
 
-/// Helper to create a client for tests
-fn create_client() -> Result<CimaClient> {
-    CimaClient::new()
+/// Client of the recorded responses, with the mock server serving them
+#[cfg(not(feature = "live-tests"))]
+async fn create_client() -> Result<(Option<MockServer>, CimaClient)> {
+    let (server, client) = support::recorded_client().await?;
+    Ok((Some(server), client))
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_get_medication_by_registration_number() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     let med = client.get_medication(Some("72112"), None).await?;
 
@@ -23,8 +41,9 @@ async fn test_get_medication_by_registration_number() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_get_medication_by_national_code() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     let med = client.get_medication(None, Some("672442")).await?;
 
@@ -35,8 +54,9 @@ async fn test_get_medication_by_national_code() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_search_medications() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     let params = SearchMedicationsParams {
         name: Some("paracetamol".to_string()),
@@ -53,8 +73,9 @@ async fn test_search_medications() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_search_medications_with_filters() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     let params = SearchMedicationsParams {
         name: Some("ibuprofeno".to_string()),
@@ -76,8 +97,9 @@ async fn test_search_medications_with_filters() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_get_presentation() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     let pres = client.get_presentation("672442").await?;
 
@@ -88,8 +110,9 @@ async fn test_get_presentation() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_search_presentations() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     let params = SearchPresentationsParams {
         registration_number: Some("72112".to_string()),
@@ -106,8 +129,9 @@ async fn test_search_presentations() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_get_all_supply_problems() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     let response = client.get_all_supply_problems().await?;
 
@@ -119,8 +143,9 @@ async fn test_get_all_supply_problems() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_get_supply_problems_by_national_code() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     // First get all problems to find a CN with issues
     let all_response = client.get_all_supply_problems().await?;
@@ -137,8 +162,9 @@ async fn test_get_supply_problems_by_national_code() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_get_safety_notes() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     // Use a known medication with safety notes
     let notas = client.get_safety_notes("72112").await?;
@@ -150,8 +176,9 @@ async fn test_get_safety_notes() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_get_informative_materials() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     // Use the same medication - this now returns a single SafetyMaterial object
     let _material = client.get_informative_materials("72112").await?;
@@ -163,8 +190,9 @@ async fn test_get_informative_materials() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_get_master_data_by_name() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     let params = MasterDataParams {
         name: Some("par".to_string()),
@@ -189,8 +217,9 @@ async fn test_get_master_data_by_name() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_get_master_data_by_id() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     let params = MasterDataParams {
         id: Some(12), // PARACETAMOL
@@ -208,8 +237,9 @@ async fn test_get_master_data_by_id() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_get_master_data_pharmaceutical_forms() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     let params = MasterDataParams {
         name: Some("comp".to_string()),
@@ -227,8 +257,9 @@ async fn test_get_master_data_pharmaceutical_forms() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_get_change_log() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     // Use a recent date to get some changes
     let response = client.get_change_log("01/01/2024", None).await?;
@@ -244,7 +275,7 @@ async fn test_get_change_log() -> Result<()> {
 #[ignore] // API BUG: Passing registration_number parameter returns 500 error for numeric IDs,
 // null response for complex IDs. Verified via curl - this is a CIMA API issue.
 async fn test_get_change_log_specific_medication() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     // This test would work if the API supported the registration_number parameter correctly
     // Workaround: Fetch all and filter client-side
@@ -259,8 +290,9 @@ async fn test_get_change_log_specific_medication() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_search_clinical_descriptions() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     // Search by name
     let params = SearchClinicalDescriptionParams {
@@ -278,8 +310,9 @@ async fn test_search_clinical_descriptions() -> Result<()> {
 }
 
 #[tokio::test]
+#[cfg_attr(feature = "live-tests", ignore = "requests the live CIMA API")]
 async fn test_pagination_works() -> Result<()> {
-    let client = create_client()?;
+    let (_server, client) = create_client().await?;
 
     let params = SearchMedicationsParams {
         name: Some("a".to_string()), // Short name to get many results
@@ -296,3 +329,65 @@ async fn test_pagination_works() -> Result<()> {
 
     Ok(())
 }
+
+/// Error handling, only reproducible with the mock server
+#[cfg(not(feature = "live-tests"))]
+mod errors {
+    use super::*;
+    use cima_rs::{ApiStatusError, DocumentType};
+
+    #[tokio::test]
+    async fn test_medication_not_found_status() -> Result<()> {
+        let (_server, client) = create_client().await?;
+
+        let err = client
+            .get_medication(Some(support::MISSING_NREGISTRO), None)
+            .await
+            .expect_err("missing medication");
+
+        let status = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<ApiStatusError>())
+            .expect("status error in the chain");
+        assert_eq!(status.status, reqwest::StatusCode::NOT_FOUND);
+        assert!(status.url.contains("nregistro=00000"), "{}", status.url);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_malformed_json_response() -> Result<()> {
+        let (_server, client) = create_client().await?;
+
+        let err = client
+            .get_medication(Some(support::MALFORMED_NREGISTRO), None)
+            .await
+            .expect_err("malformed response");
+
+        assert!(
+            format!("{err:#}").contains("Failed to deserialize JSON response"),
+            "{err:#}"
+        );
+        assert!(
+            err.chain()
+                .all(|cause| cause.downcast_ref::<ApiStatusError>().is_none())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_content_sections_are_empty() -> Result<()> {
+        let (_server, client) = create_client().await?;
+
+        let sections = client
+            .get_document_sections(DocumentType::TechnicalSheet, support::NO_CONTENT_NREGISTRO)
+            .await?;
+        let missing = client.get_presentation("000000").await;
+
+        assert!(sections.is_empty());
+        assert!(missing.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+#![cfg(feature = "record-replay")]
+
+mod support;
+
+use anyhow::Result;
+use cima_rs::cassette::SCRUBBED;
+use cima_rs::{
+    Cassette, CimaClient, CimaClientOptions, MasterDataParams, MasterDataType, RecordMode, Scrub,
+    SearchMedicationsParams,
+};
+use std::sync::Arc;
+
+/// Client of `base_url` using `cassette`
+fn cassette_client(base_url: &str, cassette: Cassette) -> Result<CimaClient> {
+    CimaClient::with_options(CimaClientOptions {
+        base_url: base_url.to_string(),
+        cassette: Some(Arc::new(cassette)),
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn test_record_then_replay_offline() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("cassettes/paracetamol.json");
+    let server = support::recorded_server().await?;
+    let base_url = server.uri();
+
+    let recorder = cassette_client(&base_url, Cassette::open(&path, RecordMode::Auto)?)?;
+    let recorded = recorder.get_medication(Some("72112"), None).await?;
+    let search = SearchMedicationsParams {
+        name: Some("ibuprofeno".to_string()),
+        commercialized: Some(1),
+        ..Default::default()
+    };
+    let recorded_search = recorder.search_medications(&search).await?;
+    let missing = recorder
+        .get_medication(Some(support::MISSING_NREGISTRO), None)
+        .await;
+    assert!(missing.is_err());
+    assert_eq!(
+        server.received_requests().await.unwrap_or_default().len(),
+        3
+    );
+    drop(server);
+
+    let replay = Cassette::open(&path, RecordMode::Auto)?;
+    assert!(replay.is_replaying());
+    assert_eq!(replay.interactions().len(), 3);
+    let client = cassette_client(&base_url, replay)?;
+    let medication = client.get_medication(Some("72112"), None).await?;
+    let results = client.search_medications(&search).await?;
+    let missing = client
+        .get_medication(Some(support::MISSING_NREGISTRO), None)
+        .await
+        .expect_err("recorded 404");
+    let unmatched = client
+        .get_medication(Some("60000"), None)
+        .await
+        .expect_err("request not in the cassette");
+
+    assert_eq!(medication.nregistro, recorded.nregistro);
+    assert_eq!(medication.name, recorded.name);
+    assert_eq!(results.total_rows, recorded_search.total_rows);
+    assert!(format!("{missing:#}").contains("404"), "{missing:#}");
+    let message = format!("{unmatched:#}");
+    assert!(
+        message.contains("No interaction of cassette") && message.contains("nregistro=60000"),
+        "{message}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replay_matches_any_query_order() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("maestras.json");
+    let server = support::recorded_server().await?;
+    let base_url = server.uri();
+    let params = MasterDataParams {
+        name: Some("par".to_string()),
+        ..Default::default()
+    };
+
+    let recorder = cassette_client(&base_url, Cassette::open(&path, RecordMode::Record)?)?;
+    recorder
+        .get_master_data(MasterDataType::ActiveIngredients, &params)
+        .await?;
+    drop(server);
+
+    let cassette = std::fs::read_to_string(&path)?;
+    assert!(
+        cassette.contains("/maestras?maestra=1&nombre=par"),
+        "{cassette}"
+    );
+    let reordered = cassette.replace("maestra=1&nombre=par", "nombre=par&maestra=1");
+    std::fs::write(&path, reordered)?;
+
+    let client = cassette_client(&base_url, Cassette::open(&path, RecordMode::Replay)?)?;
+    let response = client
+        .get_master_data(MasterDataType::ActiveIngredients, &params)
+        .await?;
+    assert_eq!(response.results[0].name, "PARACETAMOL");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_scrubbed_cassette() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("cambios.json");
+    let server = support::recorded_server().await?;
+    let base_url = server.uri();
+    let scrubbed = |mode| -> Result<Cassette> {
+        Ok(Cassette::open(&path, mode)?
+            .with_scrub(Scrub::query_param("fecha"))
+            .with_scrub(Scrub::json_field("fecha"))
+            .with_scrub(Scrub::text(&base_url, "http://cima.test")))
+    };
+
+    let recorder = cassette_client(&base_url, scrubbed(RecordMode::Record)?)?;
+    let live = recorder.get_change_log("01/01/2024", None).await?;
+    drop(server);
+
+    let cassette = std::fs::read_to_string(&path)?;
+    assert!(!cassette.contains(&base_url), "{cassette}");
+    assert!(!cassette.contains("1704236400000"), "{cassette}");
+    assert!(
+        cassette.contains("http://cima.test/registroCambios"),
+        "{cassette}"
+    );
+    assert_ne!(live.results[0].date, 0);
+
+    // Another date matches the scrubbed one, and the scrubbed dates are text
+    let client = cassette_client(&base_url, scrubbed(RecordMode::Replay)?)?;
+    let err = client
+        .get_change_log("02/02/2025", None)
+        .await
+        .expect_err("scrubbed dates are not numbers");
+    assert!(
+        format!("{err:#}").contains("Failed to deserialize JSON response"),
+        "{err:#}"
+    );
+    assert!(cassette.contains(SCRUBBED));
+    Ok(())
+}
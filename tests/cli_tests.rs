@@ -0,0 +1,2724 @@
+#![cfg(feature = "cli")]
+
+mod support;
+
+use anyhow::Result;
+use assert_cmd::Command;
+use serde_json::{Value, json};
+use support::fixture;
+use wiremock::matchers::{body_json, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Runs `nomenclator api` with `args` against the mock server, returning its output.
+async fn run_api(server: &MockServer, args: &[&str]) -> Result<std::process::Output> {
+    let mut command = Command::cargo_bin("nomenclator")?;
+    command
+        .arg("api")
+        .args(["--base-url", &server.uri()])
+        .args(args)
+        .env("RUST_LOG", "info");
+    Ok(tokio::task::spawn_blocking(move || command.output()).await??)
+}
+
+fn medication() -> Value {
+    json!({
+        "nregistro": "60000",
+        "nombre": "PARACETAMOL EJEMPLO 500 MG COMPRIMIDOS",
+        "pactivos": "PARACETAMOL",
+        "labtitular": "Laboratorio Ejemplo",
+        "estado": { "aut": 1_000_000_000 },
+        "cpresc": "Sin receta",
+        "comerc": true,
+        "presentaciones": [
+            { "cn": "600000", "nombre": "20 comprimidos", "estado": {}, "comerc": true }
+        ]
+    })
+}
+
+#[tokio::test]
+async fn test_medicamento_json_output() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .and(query_param("nregistro", "60000"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(medication()))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = run_api(
+        &server,
+        &["--format", "json", "medicamento", "--nregistro", "60000"],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(value["nregistro"], "60000");
+    assert_eq!(value["nombre"], "PARACETAMOL EJEMPLO 500 MG COMPRIMIDOS");
+    assert_eq!(value["presentaciones"][0]["cn"], "600000");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search_medicamentos_json_output() -> Result<()> {
+    let server = MockServer::start().await;
+    let summary = |nregistro: &str| {
+        json!({
+            "nregistro": nregistro,
+            "nombre": format!("MEDICAMENTO {nregistro}"),
+            "labtitular": "Laboratorio Ejemplo",
+            "estado": {},
+            "cpresc": "Con receta"
+        })
+    };
+    Mock::given(method("GET"))
+        .and(path("/medicamentos"))
+        .and(query_param("nombre", "paracetamol"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": 3,
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": [summary("1"), summary("2"), summary("3")]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = run_api(
+        &server,
+        &[
+            "search-medicamentos",
+            "--nombre",
+            "paracetamol",
+            "--limit",
+            "2",
+            "--format",
+            "json",
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    // The log line with the totals goes to standard error
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(value["totalFilas"], 3);
+    let names: Vec<&str> = value["resultados"]
+        .as_array()
+        .expect("results")
+        .iter()
+        .map(|result| result["nregistro"].as_str().expect("nregistro"))
+        .collect();
+    assert_eq!(names, ["1", "2"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Found 3 total medications"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_text_output_unchanged_by_default() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(medication()))
+        .mount(&server)
+        .await;
+
+    let output = run_api(&server, &["medicamento", "--nregistro", "60000"]).await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(
+        stdout.starts_with("=== Medicamento ===\nNº Registro: 60000\n"),
+        "{stdout}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_api_error_fails_in_json_format() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+
+    let output = run_api(
+        &server,
+        &["--format", "json", "medicamento", "--nregistro", "60000"],
+    )
+    .await?;
+
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    assert!(output.stdout.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_medicamento_not_found_exit_code() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_medications(&server).await;
+
+    let output = run_api(&server, &["medicamento", "--nregistro", "99999"]).await?;
+
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    assert!(String::from_utf8(output.stderr)?.contains("404 Not Found"));
+    Ok(())
+}
+
+/// [`medication`] with its package leaflet
+async fn mount_medication_with_docs(server: &MockServer) {
+    let mut body = medication();
+    body["docs"] = json!([
+        { "tipo": 1, "url": "https://cima.aemps.es/cima/pdfs/ft/60000/FT_60000.pdf", "secc": true },
+        { "tipo": 2, "url": "https://cima.aemps.es/cima/pdfs/p/60000/P_60000.pdf", "secc": true }
+    ]);
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_medicamento_fields_text() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_medication_with_docs(&server).await;
+
+    let two = run_api(
+        &server,
+        &[
+            "medicamento",
+            "--nregistro",
+            "60000",
+            "--fields",
+            "comercializado,url_prospecto",
+        ],
+    )
+    .await?;
+    let one = run_api(
+        &server,
+        &[
+            "medicamento",
+            "--nregistro",
+            "60000",
+            "--fields",
+            "url_prospecto",
+        ],
+    )
+    .await?;
+    let unknown = run_api(
+        &server,
+        &[
+            "medicamento",
+            "--nregistro",
+            "60000",
+            "--fields",
+            "nombre,prospecto",
+        ],
+    )
+    .await?;
+
+    assert!(two.status.success(), "{two:?}");
+    assert_eq!(
+        String::from_utf8(two.stdout)?,
+        "comercializado: Sí\n\
+         url_prospecto: https://cima.aemps.es/cima/pdfs/p/60000/P_60000.pdf\n"
+    );
+    assert_eq!(
+        String::from_utf8(one.stdout)?,
+        "https://cima.aemps.es/cima/pdfs/p/60000/P_60000.pdf\n"
+    );
+    assert_eq!(unknown.status.code(), Some(2));
+    let stderr = String::from_utf8(unknown.stderr)?;
+    assert!(stderr.contains("invalid value 'prospecto'"), "{stderr}");
+    assert!(stderr.contains("url_ft, url_prospecto, atcs"), "{stderr}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_medicamento_fields_json_to_file() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_medication_with_docs(&server).await;
+    let dir = tempfile::tempdir()?;
+    let out = dir.path().join("medicamento.json");
+
+    let output = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "medicamento",
+            "--nregistro",
+            "60000",
+            "--fields",
+            "nregistro,url_ft",
+            "--out",
+            out.to_str().expect("UTF-8 path"),
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stdout.is_empty());
+    let value: Value = serde_json::from_str(&std::fs::read_to_string(&out)?)?;
+    assert_eq!(
+        value,
+        json!({
+            "nregistro": "60000",
+            "url_ft": "https://cima.aemps.es/cima/pdfs/ft/60000/FT_60000.pdf"
+        })
+    );
+    Ok(())
+}
+
+/// Serves 60000 and 60001 by registration number, and 404 for any other medication
+async fn mount_medications(server: &MockServer) {
+    for nregistro in ["60000", "60001"] {
+        let mut body = medication();
+        body["nregistro"] = json!(nregistro);
+        Mock::given(method("GET"))
+            .and(path("/medicamento"))
+            .and(query_param("nregistro", nregistro))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .with_priority(1)
+            .mount(server)
+            .await;
+    }
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(server)
+        .await;
+}
+
+/// Responds with [`medication`], recording when each request arrived.
+struct TimedMedication(std::sync::Arc<std::sync::Mutex<Vec<std::time::Instant>>>);
+
+impl wiremock::Respond for TimedMedication {
+    fn respond(&self, _: &wiremock::Request) -> ResponseTemplate {
+        self.0
+            .lock()
+            .expect("unpoisoned")
+            .push(std::time::Instant::now());
+        ResponseTemplate::new(200).set_body_json(medication())
+    }
+}
+
+#[tokio::test]
+async fn test_api_rate_limit_spaces_requests() -> Result<()> {
+    let server = MockServer::start().await;
+    let arrivals = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .respond_with(TimedMedication(arrivals.clone()))
+        .expect(4)
+        .mount(&server)
+        .await;
+
+    let output = run_api(
+        &server,
+        &[
+            "--rate-limit",
+            "2",
+            "medicamento",
+            "--nregistro",
+            "60000,60001,60002,60003",
+            "--concurrency",
+            "4",
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let arrivals = arrivals.lock().expect("unpoisoned");
+    let gaps: Vec<_> = arrivals.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    assert_eq!(gaps.len(), 3);
+    for gap in gaps {
+        assert!(gap >= std::time::Duration::from_millis(450), "{gap:?}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_medicamento_several_continues_past_failures() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_medications(&server).await;
+
+    let output = run_api(
+        &server,
+        &[
+            "medicamento",
+            "--nregistro",
+            "60000,99999",
+            "--nregistro",
+            "60001",
+        ],
+    )
+    .await?;
+
+    assert_eq!(output.status.code(), Some(5), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    let first = stdout.find("━ nregistro 60000 ━").expect("60000 header");
+    let second = stdout.find("━ nregistro 60001 ━").expect("60001 header");
+    assert!(first < second, "{stdout}");
+    assert!(stdout.contains("Nº Registro: 60001"));
+    assert!(!stdout.contains("nregistro 99999 ━"));
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("✗ nregistro 99999:"), "{stderr}");
+    assert!(
+        stderr.contains("1 of 3 medications could not be fetched: nregistro 99999"),
+        "{stderr}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_medicamento_several_json_array() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_medications(&server).await;
+
+    let output = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "medicamento",
+            "--nregistro",
+            "60000,60001",
+            "--cn",
+            "123456",
+        ],
+    )
+    .await?;
+
+    assert!(!output.status.success());
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    let nregistros: Vec<&str> = value
+        .as_array()
+        .expect("array")
+        .iter()
+        .map(|med| med["nregistro"].as_str().expect("nregistro"))
+        .collect();
+    assert_eq!(nregistros, ["60000", "60001"]);
+    assert!(String::from_utf8(output.stderr)?.contains("✗ cn 123456:"));
+    Ok(())
+}
+
+/// Serves a change log page with a new, a deleted and a modified medication
+async fn mount_change_log(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/registroCambios"))
+        .and(query_param("fecha", "01/01/2024"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": 3,
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": [
+                { "nregistro": "60000", "fecha": 1_705_273_200_000_i64, "tipoCambio": 1,
+                  "cambios": ["estado"] },
+                { "nregistro": "60001", "fecha": 1_705_273_200_000_i64, "tipoCambio": 2,
+                  "cambios": ["estado", "comerc"] },
+                { "nregistro": "60002", "fecha": 1_705_273_200_000_i64, "tipoCambio": 3,
+                  "cambios": ["ft", "prosp"] }
+            ]
+        })))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_changes_renders_labels() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_change_log(&server).await;
+
+    let output = run_api(&server, &["changes", "--desde", "01/01/2024"]).await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains(
+        "3. Nº Registro: 60002\n   Fecha: 2024-01-15T01:00:00+02:00\n   Tipo: modificado\n   \
+         Cambios: ficha técnica actualizada, prospecto actualizado\n"
+    ));
+    assert!(stdout.contains("   Tipo: baja\n"));
+    assert!(stdout.contains("   Cambios: estado de autorización modificado\n"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_changes_filters() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_change_log(&server).await;
+
+    let by_type = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "changes",
+            "--desde",
+            "01/01/2024",
+            "--tipo",
+            "nuevo,baja",
+        ],
+    )
+    .await?;
+    let by_aspect = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "changes",
+            "--desde",
+            "01/01/2024",
+            "--cambio",
+            "comerc",
+            "--cambio",
+            "ft",
+        ],
+    )
+    .await?;
+    let both = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "changes",
+            "--desde",
+            "01/01/2024",
+            "--tipo",
+            "nuevo",
+            "--cambio",
+            "ft",
+        ],
+    )
+    .await?;
+
+    let nregistros = |output: &std::process::Output| -> Result<Vec<String>> {
+        assert!(output.status.success(), "{output:?}");
+        let value: Value = serde_json::from_slice(&output.stdout)?;
+        Ok(value["resultados"]
+            .as_array()
+            .expect("results")
+            .iter()
+            .map(|change| change["nregistro"].as_str().expect("nregistro").to_string())
+            .collect())
+    };
+    assert_eq!(nregistros(&by_type)?, ["60000", "60001"]);
+    assert_eq!(nregistros(&by_aspect)?, ["60001", "60002"]);
+    assert!(nregistros(&both)?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_doc_lists_sections() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/docSegmentado/secciones/1"))
+        .and(query_param("nregistro", "60000"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            { "seccion": "4", "titulo": "DATOS CLÍNICOS", "orden": 4 },
+            { "seccion": "4.3", "titulo": "Contraindicaciones", "orden": 7 }
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = run_api(
+        &server,
+        &["doc", "--nregistro", "60000", "--tipo", "ft", "--list"],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "4 DATOS CLÍNICOS\n  4.3 Contraindicaciones\n"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_doc_prints_section_content() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/docSegmentado/contenido/2"))
+        .and(query_param("nregistro", "60000"))
+        .and(query_param("seccion", "2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([{
+            "seccion": "2",
+            "titulo": "Qué necesita saber antes de empezar a tomar",
+            "orden": 2,
+            "contenido": "<p>No tome este medicamento si es al&eacute;rgico.</p>"
+        }])))
+        .expect(2)
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("seccion.html");
+
+    let text = run_api(
+        &server,
+        &[
+            "doc",
+            "--nregistro",
+            "60000",
+            "--tipo",
+            "p",
+            "--seccion",
+            "2",
+        ],
+    )
+    .await?;
+    let html = run_api(
+        &server,
+        &[
+            "doc",
+            "--nregistro",
+            "60000",
+            "--tipo",
+            "p",
+            "--seccion",
+            "2",
+            "--html",
+            "--out",
+            file.to_str().expect("UTF-8 path"),
+        ],
+    )
+    .await?;
+
+    assert!(text.status.success(), "{text:?}");
+    assert_eq!(
+        String::from_utf8(text.stdout)?,
+        "=== 2 Qué necesita saber antes de empezar a tomar ===\n\
+         No tome este medicamento si es alérgico.\n\n"
+    );
+    assert!(html.status.success(), "{html:?}");
+    assert_eq!(
+        std::fs::read_to_string(&file)?,
+        "<p>No tome este medicamento si es al&eacute;rgico.</p>\n"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_doc_not_segmented() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/docSegmentado/contenido/1"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let output = run_api(&server, &["doc", "--nregistro", "60000", "--tipo", "ft"]).await?;
+    let section = run_api(
+        &server,
+        &[
+            "doc",
+            "--nregistro",
+            "60000",
+            "--tipo",
+            "ft",
+            "--seccion",
+            "4.3",
+        ],
+    )
+    .await?;
+
+    assert_eq!(output.status.code(), Some(4));
+    assert!(output.stdout.is_empty());
+    assert!(
+        String::from_utf8(output.stderr)?
+            .contains("No hay documento segmentado (ficha técnica) para el medicamento 60000")
+    );
+    assert_eq!(section.status.code(), Some(4), "{section:?}");
+    assert!(section.stdout.is_empty());
+    assert!(String::from_utf8(section.stderr)?.contains(
+        "La sección 4.3 no está en el documento segmentado (ficha técnica) del medicamento 60000"
+    ));
+    Ok(())
+}
+
+/// Serves the safety notes of 60000, from notas_60000.json, with the documents of the
+/// 2023 and 2024 notes
+async fn mount_safety_notes(server: &MockServer) -> Result<()> {
+    let mut notes = fixture("notas_60000.json")?;
+    for note in notes.as_array_mut().expect("array of notes") {
+        note["url"] = json!(format!(
+            "{}{}",
+            server.uri(),
+            note["url"].as_str().expect("url")
+        ));
+    }
+    Mock::given(method("GET"))
+        .and(path("/notas"))
+        .and(query_param("nregistro", "60000"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(notes))
+        .mount(server)
+        .await;
+    for name in ["MUH_FV_03-2024.pdf", "MUH_FV_06-2023.pdf"] {
+        Mock::given(method("GET"))
+            .and(path(format!("/informa/{name}")))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!("%PDF {name}")))
+            .mount(server)
+            .await;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_safety_notes_newest_first_since_date() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_safety_notes(&server).await?;
+
+    let all = run_api(&server, &["safety-notes", "--nregistro", "60000"]).await?;
+    let recent = run_api(
+        &server,
+        &[
+            "safety-notes",
+            "--nregistro",
+            "60000",
+            "--desde",
+            "01/01/2023",
+        ],
+    )
+    .await?;
+    let none = run_api(
+        &server,
+        &[
+            "safety-notes",
+            "--nregistro",
+            "60000",
+            "--desde",
+            "2025-01-01",
+        ],
+    )
+    .await?;
+
+    assert!(all.status.success(), "{all:?}");
+    let numbered = |output: &std::process::Output| -> Result<Vec<String>> {
+        Ok(String::from_utf8(output.stdout.clone())?
+            .lines()
+            .filter(|line| line.starts_with(char::is_numeric))
+            .map(str::to_string)
+            .collect())
+    };
+    assert_eq!(
+        numbered(&all)?,
+        [
+            "1. 2024-03-05 MUH (FV), 3/2024 - Nuevas restricciones de uso",
+            "2. 2023-06-10 MUH (FV), 6/2023 - Actualización de la ficha técnica",
+            "3. 2022-01-20 MUH (FV), 1/2022 - Riesgo de daño hepático",
+        ]
+    );
+    assert!(recent.status.success(), "{recent:?}");
+    assert_eq!(numbered(&recent)?.len(), 2);
+    assert!(String::from_utf8(recent.stdout)?.contains("Notas de Seguridad: 2"));
+    assert!(none.status.success(), "{none:?}");
+    assert!(String::from_utf8(none.stdout)?.contains("Sin notas de seguridad"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_safety_notes_download() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_safety_notes(&server).await?;
+    let dir = tempfile::tempdir()?;
+    let out = dir.path().join("notas");
+
+    let output = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "safety-notes",
+            "--nregistro",
+            "60000",
+            "--desde",
+            "2023-01-01",
+            "--download",
+            out.to_str().expect("UTF-8 path"),
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    let downloads = value.as_array().expect("array of downloads");
+    assert_eq!(downloads.len(), 2);
+    assert_eq!(downloads[0]["note"]["num"], "MUH (FV), 3/2024");
+    assert_eq!(downloads[0]["status"], "downloaded");
+    assert_eq!(
+        std::fs::read_to_string(out.join("MUH_FV_03-2024.pdf"))?,
+        "%PDF MUH_FV_03-2024.pdf"
+    );
+    assert!(out.join("MUH_FV_06-2023.pdf").is_file());
+    assert!(!out.join("MUH_FV_01-2022.pdf").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_vmpp_flat_output() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/vmpp"))
+        .and(query_param("practiv1", "paracetamol"))
+        .and(query_param("forma", "comprimido"))
+        .and(query_param("dosis", "500 mg"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fixture("vmpp_paracetamol.json")?))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = run_api(
+        &server,
+        &[
+            "vmpp",
+            "--principio-activo",
+            "paracetamol",
+            "--forma",
+            "comprimido",
+            "--dosis",
+            "500 mg",
+            "--limit",
+            "2",
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    // Logs go to standard output too with the text format
+    assert!(String::from_utf8(output.stdout)?.ends_with(
+        "1. VMPP: 3936511000122109 - Paracetamol 500 mg 20 comprimidos\n   \
+         VMP: 3936411000122105 - Paracetamol 500 mg comprimido\n   \
+         Presentaciones comercializadas: 12\n\n\
+         2. VMPP: 3936611000122108 - Paracetamol 500 mg 40 comprimidos\n   \
+         VMP: 3936411000122105 - Paracetamol 500 mg comprimido\n   \
+         Presentaciones comercializadas: 3\n\n"
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_vmpp_tree_output() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/vmpp"))
+        .and(query_param("practiv1", "paracetamol"))
+        .and(query_param("modoArbol", "true"))
+        .and(query_param("pagina", "1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fixture("vmpp_paracetamol.json")?))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = run_api(
+        &server,
+        &[
+            "vmpp",
+            "--principio-activo",
+            "paracetamol",
+            "--arbol",
+            "--all",
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8(output.stdout)?.ends_with(
+        "VMP: 3936411000122105 - Paracetamol 500 mg comprimido\n  \
+         VMPP: 3936511000122109 - Paracetamol 500 mg 20 comprimidos (12 comercializadas)\n  \
+         VMPP: 3936611000122108 - Paracetamol 500 mg 40 comprimidos (3 comercializadas)\n\
+         VMP: 3937011000122101 - Paracetamol 1 g comprimido\n  \
+         VMPP: 3937111000122102 - Paracetamol 1 g 40 comprimidos (0 comercializadas)\n"
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_vmpp_all_json_output() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/vmpp"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fixture("vmpp_paracetamol.json")?))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "vmpp",
+            "--nombre",
+            "paracetamol",
+            "--all",
+            "--min-comercializadas",
+            "1",
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    let vmpps: Vec<_> = value
+        .as_array()
+        .expect("array of descriptions")
+        .iter()
+        .map(|d| d["vmpp"].as_str().unwrap_or_default())
+        .collect();
+    assert_eq!(vmpps, ["3936511000122109", "3936611000122108"]);
+    Ok(())
+}
+
+fn medication_summary(nregistro: &str, nombre: &str) -> Value {
+    json!({
+        "nregistro": nregistro,
+        "nombre": nombre,
+        "labtitular": "Laboratorio Ejemplo",
+        "estado": {},
+        "cpresc": "Con receta",
+        "comerc": true
+    })
+}
+
+#[tokio::test]
+async fn test_buscar_ft_sends_query_pairs() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/buscarEnFichaTecnica"))
+        .and(body_json(json!([
+            { "seccion": "4.1", "texto": "migraña", "contiene": 1 },
+            { "seccion": "4.3", "texto": "embarazo", "contiene": 0 },
+            { "seccion": "5", "texto": "triptán", "contiene": 1 }
+        ])))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            medication_summary("60000", "SUMATRIPTAN EJEMPLO 50 MG"),
+            medication_summary("60001", "ZOLMITRIPTAN EJEMPLO 2,5 MG")
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = run_api(
+        &server,
+        &[
+            "buscar-ft",
+            "--seccion",
+            "4.1",
+            "--texto",
+            "migraña",
+            "--no-contiene",
+            "--seccion",
+            "4.3",
+            "--texto",
+            "embarazo",
+            "--seccion",
+            "5",
+            "--texto",
+            "triptán",
+            "--limit",
+            "1",
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains(
+        "1. SUMATRIPTAN EJEMPLO 50 MG (60000)\n   \
+         Laboratorio: Laboratorio Ejemplo\n   \
+         Comercializado: Sí\n\n"
+    ));
+    assert!(!stdout.contains("ZOLMITRIPTAN"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_buscar_ft_json_output() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/buscarEnFichaTecnica"))
+        .and(body_json(json!([
+            { "seccion": "4.1", "texto": "migraña", "contiene": 1 }
+        ])))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!([medication_summary(
+                "60000",
+                "SUMATRIPTAN EJEMPLO 50 MG"
+            )])),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "buscar-ft",
+            "--seccion",
+            "4.1",
+            "--texto",
+            "migraña",
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(value[0]["nregistro"], "60000");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_buscar_ft_rejects_invalid_section() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    for (section, extra) in [
+        ("4.", &[][..]),
+        ("11", &[][..]),
+        ("4.1", &["--no-contiene"][..]),
+    ] {
+        let mut args = vec!["buscar-ft", "--seccion", section, "--texto", "migraña"];
+        args.extend_from_slice(extra);
+        let output = run_api(&server, &args).await?;
+
+        assert_eq!(output.status.code(), Some(2), "{output:?}");
+        let stderr = String::from_utf8(output.stderr)?;
+        if extra.is_empty() {
+            assert!(
+                stderr.contains(&format!("Invalid section '{section}'")),
+                "{stderr}"
+            );
+        } else {
+            assert!(
+                stderr.contains("--no-contiene must be followed"),
+                "{stderr}"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_maestra_id_lookup() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/maestras"))
+        .and(query_param("maestra", "6"))
+        .and(query_param("id", "1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": 1,
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": [{ "id": 1234, "nombre": "Laboratorio Ejemplo, S.A." }]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let output = run_api(&server, &["maestra", "--tipo", "lab", "--id", "1234"]).await?;
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8(output.stdout)?.contains("\n1. Laboratorio Ejemplo, S.A. (ID: 1234)\n")
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_maestra_rejects_unsupported_filter() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let narcotic_labs = run_api(&server, &["maestra", "--tipo", "lab", "--estupefaciente"]).await?;
+    let no_filter = run_api(&server, &["maestra", "--tipo", "pa"]).await?;
+
+    assert!(!narcotic_labs.status.success());
+    assert!(String::from_utf8(narcotic_labs.stderr)?.contains("only apply to active ingredients"));
+    assert_eq!(no_filter.status.code(), Some(2));
+    assert!(String::from_utf8(no_filter.stderr)?.contains("required arguments were not provided"));
+    Ok(())
+}
+
+/// Mounts three pages of five, five and two presentations of the registration number 60000.
+async fn mount_presentation_pages(server: &MockServer) {
+    for (page, cns) in [(1, 1..=5), (2, 6..=10), (3, 11..=12)] {
+        let results: Vec<_> = cns
+            .map(|cn| {
+                json!({
+                    "cn": format!("{cn:06}"),
+                    "nombre": format!("PRESENTACION {cn}"),
+                    "estado": {},
+                    "comerc": true
+                })
+            })
+            .collect();
+        Mock::given(method("GET"))
+            .and(path("/presentaciones"))
+            .and(query_param("nregistro", "60000"))
+            .and(query_param("pagina", page.to_string()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "totalFilas": 12,
+                "pagina": page,
+                "tamanioPagina": 5,
+                "resultados": results
+            })))
+            .named(format!("page {page}"))
+            .mount(server)
+            .await;
+    }
+}
+
+async fn requested_pages(server: &MockServer) -> Vec<String> {
+    server
+        .received_requests()
+        .await
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|request| {
+            request
+                .url
+                .query_pairs()
+                .find(|(key, _)| key == "pagina")
+                .map(|(_, page)| page.into_owned())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_all_pages_print_every_row() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_presentation_pages(&server).await;
+
+    let output = run_api(
+        &server,
+        &["search-presentaciones", "--nregistro", "60000", "--all"],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    let rows: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.contains(". CN: "))
+        .collect();
+    assert_eq!(rows.len(), 12, "{stdout}");
+    assert_eq!(rows[0], "1. CN: 000001 - PRESENTACION 1");
+    assert_eq!(rows[11], "12. CN: 000012 - PRESENTACION 12");
+    assert!(stdout.contains("Showing 12 of 12 presentations"));
+    assert_eq!(requested_pages(&server).await, ["1", "2", "3"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_all_pages_stop_at_limit() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_presentation_pages(&server).await;
+
+    let output = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "search-presentaciones",
+            "--nregistro",
+            "60000",
+            "--all",
+            "--limit",
+            "7",
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    let cns: Vec<_> = value
+        .as_array()
+        .expect("array of presentations")
+        .iter()
+        .map(|p| p["cn"].as_str().unwrap_or_default())
+        .collect();
+    assert_eq!(cns.len(), 7);
+    assert_eq!(cns[6], "000007");
+    assert!(String::from_utf8(output.stderr)?.contains("Showing 7 of 12 presentations"));
+    assert_eq!(requested_pages(&server).await, ["1", "2"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_page_requests_only_that_page() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_presentation_pages(&server).await;
+
+    let output = run_api(
+        &server,
+        &[
+            "search-presentaciones",
+            "--nregistro",
+            "60000",
+            "--page",
+            "2",
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    let rows: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.contains(". CN: "))
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            "1. CN: 000006 - PRESENTACION 6",
+            "2. CN: 000007 - PRESENTACION 7",
+            "3. CN: 000008 - PRESENTACION 8",
+            "4. CN: 000009 - PRESENTACION 9",
+            "5. CN: 000010 - PRESENTACION 10"
+        ]
+    );
+    assert!(stdout.contains("Found 12 total presentations (page 2 of 3)"));
+    assert_eq!(requested_pages(&server).await, ["2"]);
+    Ok(())
+}
+
+/// Serves one page with the presentation 712729 for the requests matching `param`.
+async fn mount_presentation_search(server: &MockServer, param: (&str, &str)) {
+    Mock::given(method("GET"))
+        .and(path("/presentaciones"))
+        .and(query_param(param.0, param.1))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": 1,
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": [
+                { "cn": "712729", "nombre": "IBUPROFENO EJEMPLO 600 MG", "estado": {}, "comerc": true }
+            ]
+        })))
+        .expect(1)
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_search_presentaciones_by_cn() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_presentation_search(&server, ("cn", "712729")).await;
+
+    let output = run_api(
+        &server,
+        &[
+            "search-presentaciones",
+            "--cn",
+            "712729",
+            "--comercializados",
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("1. CN: 712729 - IBUPROFENO EJEMPLO 600 MG"));
+    let requests = server.received_requests().await.unwrap_or_default();
+    assert_eq!(requests[0].url.query(), Some("cn=712729&comerc=1"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search_presentaciones_by_atc_exports_all() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_presentation_search(&server, ("atc", "M01AE01")).await;
+    let dir = tempfile::tempdir()?;
+    let export = dir.path().join("ibuprofeno.csv");
+
+    let output = run_api(
+        &server,
+        &[
+            "search-presentaciones",
+            "--atc",
+            "M01AE01",
+            "--psicotropos",
+            "--all",
+            "--export",
+            export.to_str().expect("UTF-8 path"),
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let requests = server.received_requests().await.unwrap_or_default();
+    assert_eq!(
+        requests[0].url.query(),
+        Some("atc=M01AE01&psicotropo=1&pagina=1")
+    );
+    let csv = std::fs::read_to_string(&export)?;
+    assert!(csv.contains("712729"), "{csv}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search_presentaciones_rejects_conflicting_filters() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let output = run_api(
+        &server,
+        &[
+            "search-presentaciones",
+            "--estupefacientes",
+            "--psicotropos",
+        ],
+    )
+    .await?;
+
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    assert!(String::from_utf8(output.stderr)?.contains("Only one of the narcotic"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_export_all_pages_to_csv() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_presentation_pages(&server).await;
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("presentaciones.csv");
+
+    let output = run_api(
+        &server,
+        &[
+            "search-presentaciones",
+            "--nregistro",
+            "60000",
+            "--all",
+            "--export",
+            file.to_str().expect("UTF-8 path"),
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("12. CN: 000012 - PRESENTACION 12"));
+    assert!(stdout.contains("✓ Exported 12 presentations to"));
+    let mut reader = csv::Reader::from_path(&file)?;
+    assert_eq!(
+        reader.headers()?,
+        vec![
+            "cn",
+            "name",
+            "commercialized",
+            "psum",
+            "authorization_date",
+            "suspension_date",
+            "revocation_date"
+        ]
+    );
+    let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(records.len(), 12);
+    assert_eq!(&records[11][0], "000012");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_export_medications_to_csv() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/medicamentos"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": 2,
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": [
+                medication_summary("60000", "SUMATRIPTAN EJEMPLO 50 MG"),
+                medication_summary("60001", "ZOLMITRIPTAN EJEMPLO 2,5 MG")
+            ]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("medicamentos.csv");
+
+    let output = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "search-medicamentos",
+            "--nombre",
+            "triptan",
+            "--export",
+            file.to_str().expect("UTF-8 path"),
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(value["resultados"].as_array().map(Vec::len), Some(2));
+    let mut reader = csv::Reader::from_path(&file)?;
+    let headers: Vec<_> = reader.headers()?.iter().take(3).collect();
+    assert_eq!(headers, ["nregistro", "name", "labtitular"]);
+    let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(records.len(), 2);
+    assert_eq!(&records[1][1], "ZOLMITRIPTAN EJEMPLO 2,5 MG");
+    assert_eq!(&records[1][4], "true");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_export_needs_force_to_overwrite() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/psuministro"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": 1,
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": [{
+                "cn": "600000",
+                "nombre": "PARACETAMOL EJEMPLO 500 MG 20 COMPRIMIDOS",
+                "fini": 1_709_244_000_000_i64,
+                "activo": true
+            }]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let file = tempfile::NamedTempFile::new()?;
+    std::fs::write(file.path(), "keep me")?;
+    let path = file.path().to_str().expect("UTF-8 path");
+
+    let refused = run_api(&server, &["supply-problems", "--export", path]).await?;
+    let contents = std::fs::read_to_string(file.path())?;
+    let forced = run_api(&server, &["supply-problems", "--export", path, "--force"]).await?;
+
+    assert!(!refused.status.success());
+    assert!(String::from_utf8(refused.stderr)?.contains("use --force to overwrite it"));
+    assert_eq!(contents, "keep me");
+    assert!(forced.status.success(), "{forced:?}");
+    assert_eq!(
+        std::fs::read_to_string(file.path())?,
+        "cn,name,start_date,end_date,active,observations\n\
+         600000,PARACETAMOL EJEMPLO 500 MG 20 COMPRIMIDOS,2024-03-01,,true,\n"
+    );
+    Ok(())
+}
+
+/// Mounts two pages of supply problems, three of them active and two resolved.
+async fn mount_supply_problem_pages(server: &MockServer) {
+    let pages = [
+        json!([
+            { "cn": "600003", "nombre": "CARBAMAZEPINA EJEMPLO 200 MG",
+              "fini": 1_709_244_000_000_i64, "activo": true },
+            { "cn": "600001", "nombre": "AMOXICILINA EJEMPLO 500 MG",
+              "fini": 1_704_060_000_000_i64, "ffin": 1_706_738_400_000_i64, "activo": false },
+            { "cn": "600005", "nombre": "BISOPROLOL EJEMPLO 5 MG",
+              "fini": 1_706_738_400_000_i64, "ffin": 1_719_784_800_000_i64, "activo": true }
+        ]),
+        json!([
+            { "cn": "600002", "nombre": "ENALAPRIL EJEMPLO 20 MG",
+              "fini": 1_701_381_600_000_i64, "activo": true },
+            { "cn": "600004", "nombre": "DIAZEPAM EJEMPLO 5 MG",
+              "fini": 1_711_922_400_000_i64, "ffin": 1_712_527_200_000_i64, "activo": false }
+        ]),
+    ];
+    for (page, results) in (1..).zip(pages) {
+        Mock::given(method("GET"))
+            .and(path("/psuministro"))
+            .and(query_param("pagina", page.to_string()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "totalFilas": 5,
+                "pagina": page,
+                "tamanioPagina": 3,
+                "resultados": results
+            })))
+            .mount(server)
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn test_supply_problems_dates_and_duration() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_supply_problem_pages(&server).await;
+
+    let output = run_api(&server, &["supply-problems", "--page", "1"]).await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("   Inicio: 01/01/2024\n   Fin: 01/02/2024\n   Duración: 31 días"));
+    assert!(
+        stdout
+            .contains("   Inicio: 01/02/2024\n   Fin previsto: 01/07/2024\n   Duración: 151 días")
+    );
+    assert!(stdout.contains("   Inicio: 01/03/2024\n\n"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_supply_problems_active_sorted_export() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_supply_problem_pages(&server).await;
+    let dir = tempfile::tempdir()?;
+    let all = dir.path().join("all.csv");
+    let active = dir.path().join("active.csv");
+
+    let everything = run_api(
+        &server,
+        &[
+            "supply-problems",
+            "--all",
+            "--sort",
+            "cn",
+            "--export",
+            all.to_str().expect("UTF-8 path"),
+        ],
+    )
+    .await?;
+    let filtered = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "supply-problems",
+            "--all",
+            "--activos",
+            "--sort",
+            "inicio",
+            "--export",
+            active.to_str().expect("UTF-8 path"),
+        ],
+    )
+    .await?;
+
+    assert!(everything.status.success(), "{everything:?}");
+    let stdout = String::from_utf8(everything.stdout)?;
+    let cns: Vec<_> = stdout
+        .lines()
+        .filter_map(|line| line.split_once(". CN: "))
+        .map(|(_, rest)| &rest[..6])
+        .collect();
+    assert_eq!(cns, ["600001", "600002", "600003", "600004", "600005"]);
+    assert_eq!(csv::Reader::from_path(&all)?.records().count(), 5);
+
+    assert!(filtered.status.success(), "{filtered:?}");
+    let value: Value = serde_json::from_slice(&filtered.stdout)?;
+    let cns: Vec<_> = value
+        .as_array()
+        .expect("JSON array")
+        .iter()
+        .map(|problem| problem["cn"].as_str().unwrap_or_default())
+        .collect();
+    assert_eq!(cns, ["600002", "600005", "600003"]);
+    let records = csv::Reader::from_path(&active)?
+        .records()
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(records.len(), 3);
+    assert!(records.iter().all(|record| &record[4] == "true"));
+    Ok(())
+}
+
+/// Mounts the medications 60000 to 60003: the technical sheet of 60000 downloads, 60001
+/// has none, 60002 does not exist and the technical sheet of 60003 fails.
+async fn mount_documents(server: &MockServer) {
+    for (nregistro, docs) in [
+        (
+            "60000",
+            json!([{ "tipo": 1, "url": format!("{}/pdfs/ft/60000", server.uri()), "secc": false }]),
+        ),
+        (
+            "60001",
+            json!([{ "tipo": 2, "url": format!("{}/pdfs/p/60001", server.uri()), "secc": false }]),
+        ),
+        (
+            "60003",
+            json!([{ "tipo": 1, "url": format!("{}/pdfs/ft/60003", server.uri()), "secc": false }]),
+        ),
+    ] {
+        let mut medication = medication();
+        medication["nregistro"] = json!(nregistro);
+        medication["docs"] = docs;
+        Mock::given(method("GET"))
+            .and(path("/medicamento"))
+            .and(query_param("nregistro", nregistro))
+            .respond_with(ResponseTemplate::new(200).set_body_json(medication))
+            .mount(server)
+            .await;
+    }
+    Mock::given(method("GET"))
+        .and(path("/pdfs/ft/60000"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"%PDF-1.4 ficha".to_vec()))
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/pdfs/ft/60003"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_download_docs_mixed_outcomes() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_documents(&server).await;
+    let dir = tempfile::tempdir()?;
+    let out = dir.path().join("fichas");
+    std::fs::create_dir(&out)?;
+    std::fs::write(out.join("FT_60004.pdf"), "already here")?;
+    let list = dir.path().join("nregistros.txt");
+    std::fs::write(
+        &list,
+        "# Fichas técnicas\n60001\n\n60002  # retirado\n60003\n",
+    )?;
+    let out_arg = out.to_str().expect("UTF-8 path");
+    let list_arg = list.to_str().expect("UTF-8 path");
+    let args = [
+        "download-docs",
+        "--tipo",
+        "ft",
+        "--out",
+        out_arg,
+        "--nregistro",
+        "60000,60004",
+        "--from-file",
+        list_arg,
+    ];
+
+    let strict = run_api(&server, &args).await?;
+    let partial = run_api(&server, &[&args[..], &["--allow-partial"]].concat()).await?;
+
+    assert_eq!(strict.status.code(), Some(5), "{strict:?}");
+    assert!(String::from_utf8(strict.stderr)?.contains("3 of 5 documents could not be downloaded"));
+    let stdout = String::from_utf8(strict.stdout)?;
+    for row in [
+        "60000        descargado",
+        "60004        existente",
+        "60001        no existe   no ft pdf document",
+        "60002        no existe   medication not found",
+        "60003        error",
+    ] {
+        assert!(stdout.contains(row), "{row} not in {stdout}");
+    }
+    assert_eq!(std::fs::read(out.join("FT_60000.pdf"))?, b"%PDF-1.4 ficha");
+    assert_eq!(
+        std::fs::read_to_string(out.join("FT_60004.pdf"))?,
+        "already here"
+    );
+
+    assert!(partial.status.success(), "{partial:?}");
+    let report: Value = serde_json::from_str(&std::fs::read_to_string(out.join("report.json"))?)?;
+    let nregistros = |key: &str| -> Vec<String> {
+        report[key]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|item| item["nregistro"].as_str().unwrap_or_default().to_string())
+            .collect()
+    };
+    assert_eq!(nregistros("successes"), ["60000", "60004"]);
+    assert_eq!(nregistros("not_found"), ["60001", "60002"]);
+    assert_eq!(nregistros("errors"), ["60003"]);
+    assert_eq!(report["successes"][1]["status"], "skipped");
+    assert_eq!(report["not_found"][1]["reason"], "medication not found");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_watch_dedups_and_runs_exec() -> Result<()> {
+    let server = MockServer::start().await;
+    let change = |nregistro: &str| {
+        json!({ "nregistro": nregistro, "fecha": 1_717_228_800_000_i64, "tipoCambio": 3,
+                "cambios": ["ft"] })
+    };
+    let page = |results: Value| {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": results.as_array().map_or(0, Vec::len),
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": results
+        }))
+    };
+    Mock::given(method("GET"))
+        .and(path("/registroCambios"))
+        .respond_with(page(json!([change("60000"), change("60001")])))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/registroCambios"))
+        .respond_with(page(json!([
+            change("60000"),
+            change("60001"),
+            change("60002")
+        ])))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir()?;
+    let received = dir.path().join("received.jsonl");
+    let exec = format!(
+        "cat >> '{}' && echo >> '{}'",
+        received.display(),
+        received.display()
+    );
+
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin("nomenclator"))
+        .args([
+            "api",
+            "--base-url",
+            &server.uri(),
+            "--format",
+            "json",
+            "watch",
+        ])
+        .args(["--desde", "01/06/2024", "--interval", "1s"])
+        .args(["--nregistro", "60000,60002", "--exec", &exec])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    for _ in 0..200 {
+        let lines = std::fs::read_to_string(&received).map_or(0, |s| s.lines().count());
+        if lines >= 2 && server.received_requests().await.unwrap_or_default().len() >= 3 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()?;
+    let output = tokio::task::spawn_blocking(move || child.wait_with_output()).await??;
+
+    assert!(output.status.success(), "{output:?}");
+    let printed: Vec<Value> = String::from_utf8(output.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let nregistros: Vec<_> = printed.iter().map(|c| c["nregistro"].clone()).collect();
+    assert_eq!(nregistros, [json!("60000"), json!("60002")]);
+    let executed: Vec<Value> = std::fs::read_to_string(&received)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(executed, printed);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_watch_rejects_invalid_interval() -> Result<()> {
+    let server = MockServer::start().await;
+
+    let output = run_api(
+        &server,
+        &["watch", "--desde", "01/06/2024", "--interval", "15x"],
+    )
+    .await?;
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)?.contains("expected a value such as 90s, 15m or 1h"));
+    Ok(())
+}
+
+/// Runs `nomenclator` with `args`, returning its output.
+async fn run_nomenclator(args: &[&str]) -> Result<std::process::Output> {
+    let mut command = Command::cargo_bin("nomenclator")?;
+    command.args(args).env("RUST_LOG", "info");
+    Ok(tokio::task::spawn_blocking(move || command.output()).await??)
+}
+
+/// Runs `nomenclator` with `args` and the environment variables `envs`, returning its
+/// output.
+async fn run_nomenclator_with_env(
+    args: &[&str],
+    envs: &[(&str, &std::path::Path)],
+) -> Result<std::process::Output> {
+    let mut command = Command::cargo_bin("nomenclator")?;
+    command
+        .args(args)
+        .env("RUST_LOG", "info")
+        .envs(envs.iter().copied());
+    Ok(tokio::task::spawn_blocking(move || command.output()).await??)
+}
+
+#[tokio::test]
+async fn test_config_file_precedence() -> Result<()> {
+    let work_dir = nomenclator_work_dir()?;
+    let dir = tempfile::tempdir()?;
+    let config = dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "[csv]\nwork_dir = {:?}\noutput_dir = {:?}\nconcurrency = 2\noutput_format = \"csv\"\n\
+             zip_cache = {:?}\nmirrors = []\ncolour = true\n\n[plugins]\nx = 1\n",
+            work_dir.path(),
+            dir.path().join("from_config"),
+            dir.path().join("zips")
+        ),
+    )?;
+    let config_arg = config.to_str().expect("UTF-8 path");
+    let from_env = dir.path().join("from_env");
+    let from_flag = dir.path().join("from_flag");
+    let args = [
+        "csv",
+        "--skip-download",
+        "--only",
+        "atc",
+        "--config",
+        config_arg,
+    ];
+
+    let file_only = run_nomenclator_with_env(&args, &[]).await?;
+    let with_env =
+        run_nomenclator_with_env(&args, &[("CIMA_OUTPUT_DIR", from_env.as_path())]).await?;
+    let with_flag = run_nomenclator_with_env(
+        &[
+            &args[..],
+            &["--output-dir", from_flag.to_str().expect("UTF-8 path")],
+        ]
+        .concat(),
+        &[("CIMA_OUTPUT_DIR", from_env.as_path())],
+    )
+    .await?;
+
+    for output in [&file_only, &with_env, &with_flag] {
+        assert!(output.status.success(), "{output:?}");
+    }
+    let stdout = String::from_utf8(file_only.stdout)?;
+    assert!(stdout.contains("Unknown key 'csv.colour'"), "{stdout}");
+    assert!(stdout.contains("Unknown section [plugins]"), "{stdout}");
+    assert!(dir.path().join("from_config/atc.csv").exists());
+    assert!(from_env.join("atc.csv").exists());
+    assert!(from_flag.join("atc.csv").exists());
+    assert!(!from_env.join("laboratorios.csv").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_config_file_default_location() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .and(query_param("nregistro", "60000"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(medication()))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let config_home = tempfile::tempdir()?;
+    std::fs::create_dir(config_home.path().join("cima-rs"))?;
+    std::fs::write(
+        config_home.path().join("cima-rs/config.toml"),
+        format!(
+            "[api]\nbase_url = \"{}\"\nformat = \"json\"\ntimeout = 10\nrate_limit = 50.0\nretries = 1\n",
+            server.uri()
+        ),
+    )?;
+
+    let output = run_nomenclator_with_env(
+        &["api", "medicamento", "--nregistro", "60000"],
+        &[("XDG_CONFIG_HOME", config_home.path())],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(value["nregistro"], "60000");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_config_file_malformed() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let config = dir.path().join("config.toml");
+    std::fs::write(&config, "[csv]\nwork_dir = \n")?;
+
+    let output = run_nomenclator_with_env(
+        &[
+            "csv",
+            "--skip-download",
+            "--config",
+            config.to_str().expect("UTF-8 path"),
+        ],
+        &[],
+    )
+    .await?;
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("Failed to parse config file"), "{stderr}");
+    assert!(stderr.contains("line 2"), "{stderr}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csv_skip_download_parses_work_dir() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let work_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/nomenclator");
+    for entry in std::fs::read_dir(fixtures)? {
+        let entry = entry?;
+        std::fs::copy(entry.path(), work_dir.path().join(entry.file_name()))?;
+    }
+    let url = format!("{}/nomenclator.zip", server.uri());
+
+    let output = run_nomenclator(&[
+        "csv",
+        "--skip-download",
+        "--download-url",
+        &url,
+        "--work-dir",
+        work_dir.path().to_str().expect("UTF-8 path"),
+        "--output-dir",
+        output_dir.path().to_str().expect("UTF-8 path"),
+    ])
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("File not found in the work directory"));
+    assert!(stdout.contains("Prescripcion.xml"));
+    assert!(output_dir.path().join("schema.sql").exists());
+    let csv_files = std::fs::read_dir(output_dir.path())?
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .is_ok_and(|e| e.path().extension().is_some_and(|ext| ext == "csv"))
+        })
+        .count();
+    assert!(csv_files >= 13, "{csv_files} CSV files");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csv_skip_download_needs_xml_files() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+
+    let empty = run_nomenclator(&[
+        "csv",
+        "--skip-download",
+        "--work-dir",
+        work_dir.path().to_str().expect("UTF-8 path"),
+        "--output-dir",
+        output_dir.path().to_str().expect("UTF-8 path"),
+    ])
+    .await?;
+    let conflicting = run_nomenclator(&["csv", "--skip-download", "--force-download"]).await?;
+
+    assert!(!empty.status.success());
+    assert!(String::from_utf8(empty.stderr)?.contains("No nomenclator XML files in"));
+    assert_eq!(conflicting.status.code(), Some(2));
+    assert!(String::from_utf8(conflicting.stderr)?.contains("cannot be used with"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csv_only_selects_files() -> Result<()> {
+    let work_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/nomenclator");
+    for entry in std::fs::read_dir(fixtures)? {
+        let entry = entry?;
+        std::fs::copy(entry.path(), work_dir.path().join(entry.file_name()))?;
+    }
+
+    let output = run_nomenclator(&[
+        "csv",
+        "--skip-download",
+        "--only",
+        "atc",
+        "--work-dir",
+        work_dir.path().to_str().expect("UTF-8 path"),
+        "--output-dir",
+        output_dir.path().to_str().expect("UTF-8 path"),
+    ])
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("✓ Completed: atc.csv"));
+    assert!(stdout.contains("- Excluded: DICCIONARIO_LABORATORIOS.xml"));
+    assert!(stdout.contains("- Prescription parsing: Excluded"));
+    assert!(!stdout.contains("File not found in the work directory"));
+    let csv_files: Vec<_> = std::fs::read_dir(output_dir.path())?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".csv"))
+        .collect();
+    assert_eq!(csv_files, ["atc.csv"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csv_only_rejects_unknown_file() -> Result<()> {
+    let unknown = run_nomenclator(&["csv", "--only", "atc,labs"]).await?;
+    let nothing_left = run_nomenclator(&[
+        "csv",
+        "--skip-download",
+        "--only",
+        "atc",
+        "--exclude",
+        "atc",
+    ])
+    .await?;
+
+    assert_eq!(unknown.status.code(), Some(2));
+    let stderr = String::from_utf8(unknown.stderr)?;
+    assert!(
+        stderr.contains("Unknown nomenclator file 'labs'"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("atc, dcp"), "{stderr}");
+    assert!(stderr.contains("prescripciones"), "{stderr}");
+    assert!(!nothing_left.status.success());
+    assert!(String::from_utf8(nothing_left.stderr)?.contains("leave no nomenclator file"));
+    Ok(())
+}
+
+/// Work directory holding the dictionary fixtures and the delta Prescripcion.xml
+fn nomenclator_work_dir() -> Result<tempfile::TempDir> {
+    let work_dir = tempfile::tempdir()?;
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+    for entry in std::fs::read_dir(format!("{fixtures}/nomenclator"))? {
+        let entry = entry?;
+        std::fs::copy(entry.path(), work_dir.path().join(entry.file_name()))?;
+    }
+    std::fs::copy(
+        format!("{fixtures}/delta/Prescripcion.xml"),
+        work_dir.path().join("Prescripcion.xml"),
+    )?;
+    Ok(work_dir)
+}
+
+#[tokio::test]
+async fn test_csv_enrich_nregistro_uses_client_flags() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/medicamentos"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/medicamentos"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": 1,
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": [medication_summary("60000", "EJEMPLO")]
+        })))
+        .expect(3)
+        .mount(&server)
+        .await;
+    let work_dir = nomenclator_work_dir()?;
+    let output_dir = tempfile::tempdir()?;
+
+    let output = run_nomenclator(&[
+        "csv",
+        "--skip-download",
+        "--enrich-nregistro",
+        "--base-url",
+        &server.uri(),
+        "--retries",
+        "1",
+        "--work-dir",
+        work_dir.path().to_str().expect("UTF-8 path"),
+        "--output-dir",
+        output_dir.path().to_str().expect("UTF-8 path"),
+    ])
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let mapping = std::fs::read_to_string(output_dir.path().join("prescription_nregistro.csv"))?;
+    assert_eq!(
+        mapping
+            .lines()
+            .filter(|line| line.ends_with(",60000"))
+            .count(),
+        3
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csv_validate_fails_on_dangling_references() -> Result<()> {
+    let work_dir = nomenclator_work_dir()?;
+    let output_dir = tempfile::tempdir()?;
+
+    let output = run_nomenclator(&[
+        "csv",
+        "--skip-download",
+        "--validate",
+        "--work-dir",
+        work_dir.path().to_str().expect("UTF-8 path"),
+        "--output-dir",
+        output_dir.path().to_str().expect("UTF-8 path"),
+    ])
+    .await?;
+
+    assert_eq!(output.status.code(), Some(6), "{output:?}");
+    assert!(String::from_utf8(output.stdout)?.contains("✗ Validation:"));
+    assert!(String::from_utf8(output.stderr)?.contains("dangling references"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csv_summary() -> Result<()> {
+    let work_dir = nomenclator_work_dir()?;
+    let output_dir = tempfile::tempdir()?;
+
+    let output = run_nomenclator(&[
+        "csv",
+        "--skip-download",
+        "--exclude",
+        "laboratorios",
+        "--compress",
+        "--work-dir",
+        work_dir.path().to_str().expect("UTF-8 path"),
+        "--output-dir",
+        output_dir.path().to_str().expect("UTF-8 path"),
+    ])
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    let summary: Vec<_> = stdout
+        .lines()
+        .skip_while(|line| *line != "Summary:")
+        .take(4)
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            "Summary:",
+            "  ✓ Dictionary files successful: 12",
+            "  - XML files excluded: 1",
+            "  ✓ Prescription parsing: Success (9 csv files)",
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csv_no_progress() -> Result<()> {
+    let work_dir = nomenclator_work_dir()?;
+    let output_dir = tempfile::tempdir()?;
+    let dirs = [
+        "--work-dir",
+        work_dir.path().to_str().expect("UTF-8 path"),
+        "--output-dir",
+        output_dir.path().to_str().expect("UTF-8 path"),
+    ];
+
+    let sequential = run_nomenclator(
+        &[
+            &[
+                "csv",
+                "--skip-download",
+                "--no-progress",
+                "--concurrency",
+                "1",
+            ][..],
+            &dirs,
+        ]
+        .concat(),
+    )
+    .await?;
+    let parallel = run_nomenclator(
+        &[
+            &[
+                "csv",
+                "--skip-download",
+                "--no-progress",
+                "--concurrency",
+                "4",
+            ][..],
+            &dirs,
+        ]
+        .concat(),
+    )
+    .await?;
+    let zero =
+        run_nomenclator(&[&["csv", "--skip-download", "--concurrency", "0"][..], &dirs].concat())
+            .await?;
+
+    for output in [&sequential, &parallel] {
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8(output.stdout.clone())?;
+        let summary: Vec<_> = stdout
+            .lines()
+            .skip_while(|line| *line != "Summary:")
+            .take(3)
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                "Summary:",
+                "  ✓ Dictionary files successful: 13",
+                "  ✓ Prescription parsing: Success (9 csv files)",
+            ]
+        );
+        assert!(output.stderr.is_empty(), "{output:?}");
+    }
+    assert_eq!(zero.status.code(), Some(2), "{zero:?}");
+    assert!(String::from_utf8(zero.stderr)?.contains("--concurrency must be greater than 0"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csv_output_format_ndjson() -> Result<()> {
+    let work_dir = nomenclator_work_dir()?;
+    let output_dir = tempfile::tempdir()?;
+
+    let output = run_nomenclator(&[
+        "csv",
+        "--skip-download",
+        "--output-format",
+        "ndjson",
+        "--work-dir",
+        work_dir.path().to_str().expect("UTF-8 path"),
+        "--output-dir",
+        output_dir.path().to_str().expect("UTF-8 path"),
+    ])
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("✓ Completed: atc.ndjson"));
+    assert!(stdout.contains("✓ Completed: prescriptions.ndjson"));
+    assert!(stdout.contains("Prescription parsing: Success (1 ndjson files)"));
+    let ndjson = std::fs::read_to_string(output_dir.path().join("prescriptions.ndjson"))?;
+    assert_eq!(ndjson.lines().count(), 3);
+    for line in ndjson.lines() {
+        let value: serde_json::Value = serde_json::from_str(line)?;
+        assert!(value.is_object(), "{line}");
+        assert!(value["cod_nacion"].is_string(), "{line}");
+    }
+    assert!(!output_dir.path().join("prescriptions.csv").exists());
+    assert!(!output_dir.path().join("schema.sql").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csv_output_format_ndjson_compressed() -> Result<()> {
+    let work_dir = nomenclator_work_dir()?;
+    let output_dir = tempfile::tempdir()?;
+
+    let output = run_nomenclator(&[
+        "csv",
+        "--skip-download",
+        "--output-format",
+        "ndjson",
+        "--compress",
+        "--only",
+        "atc,prescripciones",
+        "--work-dir",
+        work_dir.path().to_str().expect("UTF-8 path"),
+        "--output-dir",
+        output_dir.path().to_str().expect("UTF-8 path"),
+    ])
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("✓ Completed: atc.ndjson.gz"));
+    assert!(stdout.contains("✓ Completed: prescriptions.ndjson.gz"));
+    assert!(!output_dir.path().join("prescriptions.ndjson").exists());
+    let file = std::fs::File::open(output_dir.path().join("prescriptions.ndjson.gz"))?;
+    let mut ndjson = String::new();
+    std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(file), &mut ndjson)?;
+    assert_eq!(ndjson.lines().count(), 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csv_output_format_rejects_csv_only_options() -> Result<()> {
+    let validate = run_nomenclator(&["csv", "--output-format", "ndjson", "--validate"]).await?;
+    let compress = run_nomenclator(&["csv", "--output-format", "sqlite", "--compress"]).await?;
+
+    assert_eq!(validate.status.code(), Some(2), "{validate:?}");
+    assert!(String::from_utf8(validate.stderr)?.contains("--validate needs --output-format csv"));
+    assert_eq!(compress.status.code(), Some(2), "{compress:?}");
+    assert!(String::from_utf8(compress.stderr)?.contains("--compress only applies"));
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+#[tokio::test]
+async fn test_csv_output_format_needs_feature() -> Result<()> {
+    let output = run_nomenclator(&["csv", "--skip-download", "--output-format", "parquet"]).await?;
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("needs the 'parquet' cargo feature"),
+        "{stderr}"
+    );
+    Ok(())
+}
+
+/// Mounts medication 60000 with two packaging photos, the second missing, and one of
+/// the pharmaceutical form, and medication 70000 without photos.
+async fn mount_photos(server: &MockServer) {
+    let mut medication = medication();
+    medication["fotos"] = json!([
+        { "tipo": "materialas", "url": format!("{}/fotos/60000_materialas.jpg", server.uri()) },
+        { "tipo": "materialas", "url": format!("{}/fotos/borrada.jpg", server.uri()) },
+        { "tipo": "formafarmac", "url": format!("{}/fotos/60000_formafarmac.png", server.uri()) }
+    ]);
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .and(query_param("nregistro", "60000"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(medication))
+        .mount(server)
+        .await;
+    let mut without_photos = self::medication();
+    without_photos["nregistro"] = json!("70000");
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .and(query_param("nregistro", "70000"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(without_photos))
+        .mount(server)
+        .await;
+    for (name, size) in [
+        ("60000_materialas.jpg", 2048),
+        ("60000_formafarmac.png", 10),
+    ] {
+        Mock::given(method("GET"))
+            .and(path(format!("/fotos/{name}")))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![0u8; size]))
+            .mount(server)
+            .await;
+    }
+    Mock::given(method("GET"))
+        .and(path("/fotos/borrada.jpg"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_fotos_downloads_every_type() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_photos(&server).await;
+    let dir = tempfile::tempdir()?;
+    let out = dir.path().to_str().expect("UTF-8 path");
+
+    let output = run_api(&server, &["fotos", "--nregistro", "60000", "--out", out]).await?;
+
+    // The missing photo does not stop the others, but fails the command
+    assert_eq!(output.status.code(), Some(5), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    let lines: Vec<_> = stdout
+        .lines()
+        .filter(|line| !line.contains("INFO"))
+        .collect();
+    assert_eq!(lines[0], "TIPO        TAMAÑO  ARCHIVO");
+    assert_eq!(
+        lines[1],
+        format!("envase    2.00 KiB  {out}/60000_materialas_1.jpg")
+    );
+    assert!(
+        lines[2].starts_with("envase           -  no existe: "),
+        "{stdout}"
+    );
+    assert!(lines[2].ends_with("/fotos/borrada.jpg returned 404 Not Found"));
+    assert_eq!(
+        lines[3],
+        format!("forma         10 B  {out}/60000_formafarmac_1.png")
+    );
+    assert!(String::from_utf8(output.stderr)?.contains("1 of 3 photos could not be downloaded"));
+    assert_eq!(
+        std::fs::metadata(dir.path().join("60000_materialas_1.jpg"))?.len(),
+        2048
+    );
+    assert!(!dir.path().join("60000_materialas_2.jpg").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fotos_json_lists_saved_paths() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_photos(&server).await;
+    let dir = tempfile::tempdir()?;
+    let out = dir.path().to_str().expect("UTF-8 path");
+
+    let output = run_api(
+        &server,
+        &[
+            "--format",
+            "json",
+            "fotos",
+            "--nregistro",
+            "60000",
+            "--tipo",
+            "forma",
+            "--out",
+            out,
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let report: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(report["errors"], json!([]));
+    assert_eq!(report["saved"].as_array().map(Vec::len), Some(1));
+    assert_eq!(report["saved"][0]["photo"]["tipo"], "formafarmac");
+    assert_eq!(report["saved"][0]["status"], "downloaded");
+    assert_eq!(
+        report["saved"][0]["path"],
+        format!("{out}/60000_formafarmac_1.png")
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fotos_without_photos() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_photos(&server).await;
+    let dir = tempfile::tempdir()?;
+
+    let output = run_api(
+        &server,
+        &[
+            "fotos",
+            "--nregistro",
+            "70000",
+            "--tipo",
+            "envase",
+            "--out",
+            dir.path().to_str().expect("UTF-8 path"),
+        ],
+    )
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8(output.stdout)?
+            .contains("El medicamento 70000 no tiene fotos de tipo envase")
+    );
+    Ok(())
+}
+
+const DIFF_OLD: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/diff/old");
+const DIFF_NEW: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/diff/new");
+
+#[tokio::test]
+async fn test_diff_text_output() -> Result<()> {
+    let output = run_nomenclator(&["diff", DIFF_OLD, DIFF_NEW]).await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    let lines: Vec<_> = stdout
+        .lines()
+        .filter(|line| !line.contains("INFO"))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "~ atc J01CA04: description",
+            "- atc M01AE01",
+            "+ atc R05CB01",
+            "~ prescriptions 600001: sw_receta, sw_comercializado",
+            "- prescriptions 600002",
+            "+ prescriptions 600003",
+            "- prescription_atc 600002/M01AE01",
+            "+ prescription_atc 600003/N02BE01",
+            "atc: 1 added, 1 removed, 1 changed, 1 unchanged",
+            "prescriptions: 1 added, 1 removed, 1 changed, 1 unchanged",
+            "prescription_atc: 1 added, 1 removed, 0 changed, 2 unchanged",
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_diff_json_output() -> Result<()> {
+    let output = run_nomenclator(&[
+        "diff",
+        DIFF_OLD,
+        DIFF_NEW,
+        "--table",
+        "prescriptions",
+        "--format",
+        "json",
+    ])
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let diff: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(
+        diff,
+        json!({
+            "changes": [
+                {
+                    "table": "prescriptions",
+                    "change": "changed",
+                    "key": ["600001"],
+                    "columns": ["sw_receta", "sw_comercializado"]
+                },
+                { "table": "prescriptions", "change": "removed", "key": ["600002"], "columns": [] },
+                { "table": "prescriptions", "change": "added", "key": ["600003"], "columns": [] }
+            ],
+            "tables": [
+                { "table": "prescriptions", "added": 1, "removed": 1, "changed": 1, "unchanged": 1 }
+            ]
+        })
+    );
+    // The counts are logged to standard error
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("prescriptions: 1 added"), "{stderr}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_diff_csv_output() -> Result<()> {
+    let output = run_nomenclator(&[
+        "diff",
+        DIFF_OLD,
+        DIFF_NEW,
+        "--table",
+        "atc,prescription_atc",
+        "--format",
+        "csv",
+    ])
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "table,change,key,columns\n\
+         atc,changed,J01CA04,description\n\
+         atc,removed,M01AE01,\n\
+         atc,added,R05CB01,\n\
+         prescription_atc,removed,600002/M01AE01,\n\
+         prescription_atc,added,600003/N02BE01,\n"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_diff_rejects_unknown_table() -> Result<()> {
+    let output = run_nomenclator(&["diff", DIFF_OLD, DIFF_NEW, "--table", "recetas"]).await?;
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("invalid value 'recetas'"));
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_db_loads_csv_dir() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = dir.path().join("nomenclator.sqlite");
+    let db_arg = db.to_str().expect("UTF-8 path");
+
+    let output = run_nomenclator(&["db", "--input", DIFF_NEW, "--db", db_arg]).await?;
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("✓ prescriptions: 3 rows"), "{stdout}");
+    assert!(stdout.contains("✓ laboratorios: 0 rows"), "{stdout}");
+
+    let conn = rusqlite::Connection::open(&db)?;
+    let description: String = conn.query_row(
+        "SELECT a.description FROM prescriptions p \
+         JOIN prescription_atc pa ON pa.prescription_id = p.cod_nacion \
+         JOIN atc a ON a.code = pa.atc_code \
+         WHERE p.cod_nacion = '600001' AND p.sw_comercializado = 0",
+        [],
+        |row| row.get(0),
+    )?;
+    assert_eq!(description, "AMOXICILINA SOLA");
+    drop(conn);
+
+    // An existing database is only replaced with --recreate
+    let output = run_nomenclator(&["db", "--input", DIFF_OLD, "--db", db_arg]).await?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("use --recreate"));
+    let output =
+        run_nomenclator(&["db", "--input", DIFF_OLD, "--db", db_arg, "--recreate"]).await?;
+    assert!(output.status.success(), "{output:?}");
+    let conn = rusqlite::Connection::open(&db)?;
+    let removed: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM prescriptions WHERE cod_nacion = '600002'",
+        [],
+        |row| row.get(0),
+    )?;
+    assert_eq!(removed, 1);
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_db_rejects_stale_csv() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("atc.csv"), "nroatc,codigoatc\n1,A\n")?;
+    let db = dir.path().join("nomenclator.sqlite");
+
+    let output = run_nomenclator(&[
+        "db",
+        "--input",
+        dir.path().to_str().expect("UTF-8 path"),
+        "--db",
+        db.to_str().expect("UTF-8 path"),
+    ])
+    .await?;
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("does not match the atc table of this version of cima-rs"),
+        "{stderr}"
+    );
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+#[tokio::test]
+async fn test_db_needs_feature() -> Result<()> {
+    let output =
+        run_nomenclator(&["db", "--input", DIFF_NEW, "--db", "nomenclator.sqlite"]).await?;
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("needs the 'sqlite' cargo feature"),
+        "{stderr}"
+    );
+    Ok(())
+}
+
+/// Output directory with an empty prescription_notes.csv and a report.json where
+/// atc.csv had 5 rows
+const STATS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/stats");
+
+#[tokio::test]
+async fn test_stats_text_output() -> Result<()> {
+    let output = run_nomenclator(&["stats", STATS_DIR]).await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout)?;
+    for line in [
+        "  atc.csv                        3 rows        98 B",
+        "  prescription_notes.csv         0 rows         0 B",
+        "Prescriptions: 3",
+        "  Commercialized:     2",
+        "  Not commercialized: 1",
+        "  Psychotropic:       0",
+        "   1. LABORATORIO EJEMPLO (1): 3",
+        "  ⚠ atc.csv has 3 rows, 5 in the previous run",
+        "  ⚠ prescription_notes.csv is empty",
+    ] {
+        assert!(stdout.contains(line), "{line} not in {stdout}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stats_json_output() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let previous = dir.path().join("report.json");
+    std::fs::write(
+        &previous,
+        r#"{"Prescripcion.xml": {"rows_written": {"prescriptions.csv": 2}}}"#,
+    )?;
+
+    let output = run_nomenclator(&[
+        "stats",
+        STATS_DIR,
+        "--format",
+        "json",
+        "--previous",
+        previous.to_str().expect("UTF-8 path"),
+    ])
+    .await?;
+
+    assert!(output.status.success(), "{output:?}");
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    let rows: Vec<(&str, u64)> = value["files"]
+        .as_array()
+        .expect("files")
+        .iter()
+        .map(|file| {
+            (
+                file["file"].as_str().expect("file"),
+                file["rows"].as_u64().expect("rows"),
+            )
+        })
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            ("atc.csv", 3),
+            ("laboratorios.csv", 1),
+            ("prescriptions.csv", 3),
+            ("prescription_atc.csv", 3),
+            ("prescription_notes.csv", 0),
+        ]
+    );
+    assert_eq!(value["files"][0]["bytes"], 98);
+    assert_eq!(
+        value["prescriptions"],
+        json!({
+            "total": 3,
+            "commercialized": 2,
+            "not_commercialized": 1,
+            "psychotropic": 0,
+            "top_laboratories": [
+                { "code": "1", "name": "LABORATORIO EJEMPLO", "prescriptions": 3 }
+            ]
+        })
+    );
+    assert_eq!(
+        value["anomalies"],
+        json!([
+            { "kind": "deviation", "file": "prescriptions.csv", "previous": 2, "current": 3 },
+            { "kind": "empty", "file": "prescription_notes.csv" }
+        ])
+    );
+    Ok(())
+}
+
+#[test]
+fn test_completions() -> Result<()> {
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let output = Command::cargo_bin("nomenclator")?
+            .args(["completions", shell])
+            .output()?;
+
+        assert!(output.status.success(), "{shell}: {output:?}");
+        let script = String::from_utf8(output.stdout)?;
+        assert!(script.contains("search-medicamentos"), "{shell}");
+        // PowerShell completes subcommands and flags only
+        if shell != "powershell" {
+            assert!(script.contains("laboratorios"), "{shell}");
+            assert!(script.contains("pa-snomed"), "{shell}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+#![cfg(feature = "parser")]
+
+use cima_rs::parser::{
+    DictionaryKind, ENUM_DICTIONARIES, ParserOptions, generate_dictionary_enums,
+};
+use std::path::{Path, PathBuf};
+
+/// The enums generated from the nomenclator fixtures, compiled as part of this test
+mod generated {
+    // Only some of the enums and methods are exercised below
+    #![allow(dead_code)]
+    include!("fixtures/codegen/nomenclator_enums.rs");
+}
+
+use generated::{AdministrationRoute, ContainerUnit, RegistrationStatus, SimplifiedForm};
+
+const GENERATED: &str = include_str!("fixtures/codegen/nomenclator_enums.rs");
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nomenclator")
+}
+
+#[test]
+fn test_generated_enums_are_up_to_date() {
+    assert_eq!(generate_dictionary_enums(fixtures()).unwrap(), GENERATED);
+}
+
+#[test]
+fn test_generation_from_csv_files() {
+    let dir = tempfile::tempdir().unwrap();
+    for (kind, _) in ENUM_DICTIONARIES {
+        kind.parse(
+            fixtures().join(kind.default_xml_filename()),
+            dir.path().join(kind.default_csv_filename()),
+            &ParserOptions::default(),
+        )
+        .unwrap();
+    }
+    assert_eq!(generate_dictionary_enums(dir.path()).unwrap(), GENERATED);
+
+    std::fs::remove_file(
+        dir.path()
+            .join(DictionaryKind::Containers.default_csv_filename()),
+    )
+    .unwrap();
+    let err = generate_dictionary_enums(dir.path()).unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with("Neither DICCIONARIO_ENVASES.xml nor envases.csv")
+    );
+}
+
+#[test]
+fn test_generated_enums() {
+    assert_eq!(
+        RegistrationStatus::from_code("1"),
+        RegistrationStatus::Autorizado
+    );
+    assert_eq!(RegistrationStatus::Suspendido.as_code(), "2");
+    assert_eq!(
+        AdministrationRoute::from_code("48"),
+        AdministrationRoute::ViaOral
+    );
+    assert_eq!(
+        AdministrationRoute::IntraperitonealUsoCronico.as_code(),
+        "12"
+    );
+    assert_eq!(SimplifiedForm::from_code("20"), SimplifiedForm::Capsula);
+
+    let unknown = ContainerUnit::from_code("99");
+    assert_eq!(unknown, ContainerUnit::Unknown("99".to_string()));
+    assert_eq!(unknown.as_code(), "99");
+}
@@ -0,0 +1,227 @@
+//! Builds a miniature dataset from synthetic nomenclator files and a mock API, then
+//! syncs it once.
+
+#![cfg(all(feature = "dataset", feature = "test-util"))]
+
+use anyhow::Result;
+use cima_rs::CimaClient;
+use cima_rs::dataset::{Dataset, DatasetOptions, SyncReport};
+use cima_rs::testing::fixtures::{FixtureOptions, generate_nomenclator};
+use serde_json::{Value, json};
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+/// Registration number the mock API gives the national code `cn`
+fn nregistro_of(cn: &str) -> String {
+    (cn.parse::<u64>().expect("numeric code") - 40_000).to_string()
+}
+
+fn medication(nregistro: &str, name: &str, commercialized: bool) -> Value {
+    let number: u64 = nregistro.parse().expect("numeric registration number");
+    let atc = if number.is_multiple_of(2) {
+        "N02BE01"
+    } else {
+        "C09AA02"
+    };
+    json!({
+        "nregistro": nregistro,
+        "nombre": name,
+        "pactivos": "PARACETAMOL",
+        "labtitular": "Laboratorio Ejemplo",
+        "estado": { "aut": 1_000_000_000 },
+        "cpresc": "Sin receta",
+        "comerc": commercialized,
+        "atcs": [{ "codigo": atc, "nombre": "Ejemplo", "nivel": 5 }],
+        "presentaciones": [
+            { "cn": (number + 40_000).to_string(), "nombre": "20 comprimidos",
+              "estado": {}, "comerc": commercialized }
+        ]
+    })
+}
+
+/// `medicamento`: national codes ending in 7 are unknown, and `nregistro` lookups
+/// answer the medications as modified, except 99999 which is not commercialized
+struct MedicationResponder;
+
+impl Respond for MedicationResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let param = |name: &str| {
+            request
+                .url
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        if let Some(cn) = param("cn") {
+            if cn.ends_with('7') {
+                return ResponseTemplate::new(404);
+            }
+            return ResponseTemplate::new(200).set_body_json(medication(
+                &nregistro_of(&cn),
+                "ORIGINAL",
+                true,
+            ));
+        }
+        match param("nregistro").as_deref() {
+            Some("99999") => ResponseTemplate::new(200).set_body_json(medication(
+                "99999",
+                "NO COMERCIALIZADO",
+                false,
+            )),
+            Some(nregistro) => {
+                ResponseTemplate::new(200).set_body_json(medication(nregistro, "MODIFICADO", true))
+            }
+            None => ResponseTemplate::new(400),
+        }
+    }
+}
+
+async fn build(server: &MockServer, dir: &std::path::Path) -> Result<Dataset> {
+    let work_dir = dir.join("nomenclator");
+    std::fs::create_dir(&work_dir)?;
+    generate_nomenclator(&work_dir, 40, 7, &FixtureOptions::default())?;
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .respond_with(MedicationResponder)
+        .mount(server)
+        .await;
+
+    let client = CimaClient::builder().base_url(&server.uri()).build()?;
+    let options = DatasetOptions {
+        download: false,
+        ..Default::default()
+    };
+    Dataset::build(&work_dir, dir.join("cima.db"), &client, &options).await
+}
+
+fn commercialized_codes(dataset: &Dataset) -> Result<Vec<String>> {
+    let mut stmt = dataset
+        .connection()
+        .prepare("SELECT cod_nacion FROM prescriptions WHERE sw_comercializado = 1 ORDER BY 1")?;
+    let codes = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(codes)
+}
+
+#[tokio::test]
+async fn test_build_mirrors_commercialized_prescriptions() -> Result<()> {
+    let server = MockServer::start().await;
+    let dir = tempfile::tempdir()?;
+    let dataset = build(&server, dir.path()).await?;
+
+    let codes = commercialized_codes(&dataset)?;
+    let (unknown, known): (Vec<_>, Vec<_>) = codes.iter().partition(|cn| cn.ends_with('7'));
+    assert!(!known.is_empty() && !unknown.is_empty(), "{codes:?}");
+
+    for cn in &known {
+        let medication = dataset
+            .medication_by_national_code(cn)?
+            .expect("mirrored medication");
+        assert_eq!(medication.nregistro, nregistro_of(cn));
+        assert_eq!(medication.name, "ORIGINAL");
+        assert!(
+            dataset
+                .medication_by_nregistro(&nregistro_of(cn))?
+                .is_some()
+        );
+    }
+    for cn in &unknown {
+        assert!(dataset.medication_by_national_code(cn)?.is_none());
+    }
+
+    let analgesics = dataset.medications_by_atc_prefix("N02")?;
+    let expected: Vec<String> = known
+        .iter()
+        .map(|cn| nregistro_of(cn))
+        .filter(|nregistro| nregistro.parse::<u64>().unwrap().is_multiple_of(2))
+        .collect();
+    let found: Vec<String> = analgesics.into_iter().map(|m| m.nregistro).collect();
+    assert_eq!(found, expected);
+    assert!(dataset.medications_by_atc_prefix("X")?.is_empty());
+
+    assert!(dataset.last_sync()?.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sync_applies_change_log() -> Result<()> {
+    let server = MockServer::start().await;
+    let dir = tempfile::tempdir()?;
+    let mut dataset = build(&server, dir.path()).await?;
+    dataset.connection().execute(
+        "UPDATE dataset_state SET value = '01/01/2024' WHERE key = 'last_sync'",
+        [],
+    )?;
+
+    let mirrored: Vec<String> = dataset
+        .connection()
+        .prepare("SELECT nregistro FROM medications ORDER BY 1 LIMIT 2")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    let [modified, deleted] = &mirrored[..] else {
+        panic!("two mirrored medications expected, got {mirrored:?}");
+    };
+
+    Mock::given(method("GET"))
+        .and(path("/registroCambios"))
+        .and(query_param("fecha", "01/01/2024"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": 3,
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": [
+                { "nregistro": modified, "fecha": 1_705_273_200_000_i64, "tipoCambio": 3,
+                  "cambios": ["ft"] },
+                { "nregistro": deleted, "fecha": 1_705_273_200_000_i64, "tipoCambio": 2,
+                  "cambios": ["estado"] },
+                { "nregistro": "99999", "fecha": 1_705_100_400_000_i64, "tipoCambio": 1,
+                  "cambios": ["estado"] }
+            ]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = CimaClient::builder().base_url(&server.uri()).build()?;
+    let report = dataset.sync(&client).await?;
+
+    assert_eq!(
+        report,
+        SyncReport {
+            since: "01/01/2024".to_string(),
+            changes: 3,
+            updated: 1,
+            removed: 1,
+            skipped: 1,
+        }
+    );
+    let medication = dataset
+        .medication_by_nregistro(modified)?
+        .expect("still mirrored");
+    assert_eq!(medication.name, "MODIFICADO");
+    assert!(dataset.medication_by_nregistro(deleted)?.is_none());
+    assert!(
+        dataset
+            .medication_by_national_code(&(deleted.parse::<u64>()? + 40_000).to_string())?
+            .is_none()
+    );
+    assert!(dataset.medication_by_nregistro("99999")?.is_none());
+    assert_eq!(dataset.last_sync()?.as_deref(), Some("15/01/2024"));
+
+    // Only the modified and the new medication were requested by registration number
+    let lookups = server
+        .received_requests()
+        .await
+        .unwrap_or_default()
+        .iter()
+        .filter(|request| {
+            request
+                .url
+                .query()
+                .is_some_and(|q| q.starts_with("nregistro="))
+        })
+        .count();
+    assert_eq!(lookups, 2);
+    Ok(())
+}
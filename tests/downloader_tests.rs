@@ -0,0 +1,1507 @@
+#![cfg(feature = "downloader")]
+
+use anyhow::Result;
+use cima_rs::downloader::{
+    DOWNLOAD_MANIFEST_JSON, DOWNLOAD_STATE_JSON, DownloadOptions, DownloadOutcome,
+    DownloadProgress, ExtractionReport, download_and_extract,
+    download_and_extract_nomenclator_with_options, nomenclator_xml_files, prune_zip_cache,
+};
+use cima_rs::parser::DictionaryKind;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const PRESCRIPTION_XML: &str = "<aemps_prescripcion></aemps_prescripcion>";
+
+/// ZIP archive holding `files`, stored uncompressed
+fn zip_of(files: &[(&str, &[u8])]) -> Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for (name, content) in files {
+        zip.start_file(*name, stored)?;
+        zip.write_all(content)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+/// ZIP archive holding Prescripcion.xml and a dictionary
+fn nomenclator_zip() -> Result<Vec<u8>> {
+    zip_of(&[
+        ("Prescripcion.xml", PRESCRIPTION_XML.as_bytes()),
+        ("DICCIONARIO_ATC.xml", b"<aemps_atc></aemps_atc>"),
+    ])
+}
+
+const LAST_MODIFIED: &str = "Mon, 02 Sep 2024 06:00:00 GMT";
+
+/// Serves `zip` at /prescripcion.zip with the given `Last-Modified` header, expecting
+/// `gets` downloads and `heads` freshness checks
+async fn mount_dump(
+    server: &MockServer,
+    zip: &[u8],
+    last_modified: Option<&str>,
+    gets: u64,
+    heads: u64,
+) {
+    let mut response = ResponseTemplate::new(200).set_body_bytes(zip);
+    if let Some(last_modified) = last_modified {
+        response = response.insert_header("Last-Modified", last_modified);
+    }
+    Mock::given(method("GET"))
+        .and(path("/prescripcion.zip"))
+        .respond_with(response.clone())
+        .expect(gets)
+        .mount(server)
+        .await;
+    Mock::given(method("HEAD"))
+        .and(path("/prescripcion.zip"))
+        .respond_with(response)
+        .expect(heads)
+        .mount(server)
+        .await;
+}
+
+/// Files of [`nomenclator_zip`] and their sizes
+const EXTRACTED_FILES: [(&str, u64); 2] = [
+    ("DICCIONARIO_ATC.xml", 23),
+    ("Prescripcion.xml", PRESCRIPTION_XML.len() as u64),
+];
+
+fn file_sizes(report: &ExtractionReport) -> Vec<(&str, u64)> {
+    report
+        .files
+        .iter()
+        .map(|file| (file.name.as_str(), file.size))
+        .collect()
+}
+
+fn options(server: &MockServer, force: bool) -> DownloadOptions {
+    DownloadOptions {
+        force,
+        url: format!("{}/prescripcion.zip", server.uri()),
+        backoff: Duration::from_millis(1),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_download_extracts_into_empty_dir() -> Result<()> {
+    let server = MockServer::start().await;
+    let zip = nomenclator_zip()?;
+    mount_dump(&server, &zip, Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+    let target = dir.path().join("nomenclator_data");
+
+    let report =
+        download_and_extract_nomenclator_with_options(&target, &options(&server, false)).await?;
+
+    assert_eq!(report.target_dir, target);
+    assert_eq!(
+        report.outcome,
+        DownloadOutcome::Downloaded {
+            url: format!("{}/prescripcion.zip", server.uri()),
+            bytes: zip.len() as u64,
+            last_modified: Some(LAST_MODIFIED.to_string()),
+            retries: 0,
+            cached: false,
+        }
+    );
+    assert!(!report.skipped());
+    assert_eq!(report.downloaded_bytes(), zip.len() as u64);
+    assert_eq!(file_sizes(&report), EXTRACTED_FILES);
+    assert!(report.files.iter().all(|file| file.modified.is_some()));
+    assert_eq!(
+        report.extracted_bytes(),
+        (PRESCRIPTION_XML.len() + "<aemps_atc></aemps_atc>".len()) as u64
+    );
+    assert_eq!(
+        fs::read_to_string(target.join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    assert!(target.join("DICCIONARIO_ATC.xml").is_file());
+    assert!(target.join(DOWNLOAD_STATE_JSON).is_file());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_skipped_when_remote_unchanged() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 1, 1).await;
+    let dir = tempfile::tempdir()?;
+    let options = options(&server, false);
+
+    download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+    let report = download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+
+    assert_eq!(report.outcome, DownloadOutcome::UpToDate);
+    assert!(report.skipped());
+    assert_eq!(report.downloaded_bytes(), 0);
+    assert_eq!(report.target_dir, dir.path());
+    assert_eq!(file_sizes(&report), EXTRACTED_FILES);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_repeated_when_remote_changed() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+    let options = options(&server, false);
+    download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+
+    server.verify().await;
+    server.reset().await;
+    let newer = "Tue, 03 Sep 2024 06:00:00 GMT";
+    let updated = "<aemps_prescripcion><prescription/></aemps_prescripcion>";
+    let zip = zip_of(&[("Prescripcion.xml", updated.as_bytes())])?;
+    mount_dump(&server, &zip, Some(newer), 1, 1).await;
+    let outcome = download_and_extract_nomenclator_with_options(dir.path(), &options)
+        .await?
+        .outcome;
+
+    assert!(matches!(
+        outcome,
+        DownloadOutcome::Downloaded { last_modified: Some(ref date), .. } if date == newer
+    ));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        updated
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_repeated_without_last_modified() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, None, 2, 1).await;
+    let dir = tempfile::tempdir()?;
+    let options = options(&server, false);
+
+    download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+    let outcome = download_and_extract_nomenclator_with_options(dir.path(), &options)
+        .await?
+        .outcome;
+
+    assert!(matches!(
+        outcome,
+        DownloadOutcome::Downloaded {
+            last_modified: None,
+            ..
+        }
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_repeated_without_state_file() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+    // Extracted before the state file existed
+    fs::write(dir.path().join("Prescripcion.xml"), "old")?;
+
+    let outcome =
+        download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false))
+            .await?
+            .outcome;
+
+    assert!(matches!(outcome, DownloadOutcome::Downloaded { .. }));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_force_download_overwrites_existing_files() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 2, 0).await;
+    let dir = tempfile::tempdir()?;
+    download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)).await?;
+    fs::write(dir.path().join("Prescripcion.xml"), "old")?;
+
+    let outcome =
+        download_and_extract_nomenclator_with_options(dir.path(), &options(&server, true))
+            .await?
+            .outcome;
+
+    assert!(matches!(outcome, DownloadOutcome::Downloaded { .. }));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_repairs_dir_missing_prescription_xml() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 2, 0).await;
+    let dir = tempfile::tempdir()?;
+    download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)).await?;
+    // Leftovers of an interrupted extraction
+    fs::remove_file(dir.path().join("Prescripcion.xml"))?;
+    fs::write(dir.path().join("DICCIONARIO_ATC.xml"), "partial")?;
+
+    download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)).await?;
+
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("DICCIONARIO_ATC.xml"))?,
+        "<aemps_atc></aemps_atc>"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_reports_progress() -> Result<()> {
+    // Stored uncompressed, so the archive is a few MB and arrives in many chunks
+    let prescriptions: String = (0..100_000)
+        .map(|i| format!("<prescription><cod_nacion>{i:06}</cod_nacion></prescription>\n"))
+        .collect();
+    let zip = zip_of(&[
+        ("Prescripcion.xml", prescriptions.as_bytes()),
+        ("DICCIONARIO_ATC.xml", b"<aemps_atc></aemps_atc>"),
+    ])?;
+    assert!(zip.len() > 4_000_000);
+
+    let server = MockServer::start().await;
+    mount_dump(&server, &zip, Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+    let updates = Arc::new(Mutex::new(Vec::new()));
+    let received = Arc::clone(&updates);
+    let options = DownloadOptions {
+        progress: Some(Arc::new(move |progress| {
+            received.lock().unwrap().push(progress)
+        })),
+        ..options(&server, false)
+    };
+
+    download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+
+    let updates = updates.lock().unwrap();
+    let downloaded: Vec<u64> = updates
+        .iter()
+        .filter_map(|update| match update {
+            DownloadProgress::Downloading { bytes, total } => {
+                assert_eq!(*total, Some(zip.len() as u64));
+                Some(*bytes)
+            }
+            DownloadProgress::Extracting { .. } => None,
+        })
+        .collect();
+    assert!(
+        downloaded.len() > 1,
+        "{} download updates",
+        downloaded.len()
+    );
+    assert!(downloaded.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(downloaded.last(), Some(&(zip.len() as u64)));
+
+    let extracted: Vec<_> = updates
+        .iter()
+        .skip_while(|update| matches!(update, DownloadProgress::Downloading { .. }))
+        .collect();
+    assert_eq!(
+        extracted,
+        [
+            &DownloadProgress::Extracting {
+                files: 1,
+                total: 2,
+                name: "Prescripcion.xml".to_string(),
+            },
+            &DownloadProgress::Extracting {
+                files: 2,
+                total: 2,
+                name: "DICCIONARIO_ATC.xml".to_string(),
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_downloaded_zip_removed_after_extraction() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+
+    download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)).await?;
+
+    let mut names: Vec<_> = fs::read_dir(dir.path())?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<_, _>>()?;
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "DICCIONARIO_ATC.xml",
+            "Prescripcion.xml",
+            DOWNLOAD_MANIFEST_JSON,
+            DOWNLOAD_STATE_JSON
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_downloaded_zip_kept_on_request() -> Result<()> {
+    let server = MockServer::start().await;
+    let zip = nomenclator_zip()?;
+    mount_dump(&server, &zip, Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+    let options = DownloadOptions {
+        keep_zip: true,
+        ..options(&server, false)
+    };
+
+    download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+
+    assert_eq!(fs::read(dir.path().join("prescripcion.zip"))?, zip);
+    assert!(dir.path().join("Prescripcion.xml").is_file());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_invalid_zip_kept_for_inspection() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, b"not a zip", Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+
+    let result =
+        download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)).await;
+
+    assert!(result.is_err());
+    assert_eq!(fs::read(dir.path().join("prescripcion.zip"))?, b"not a zip");
+    assert!(!dir.path().join(DOWNLOAD_STATE_JSON).exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_modified_file_triggers_download() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 2, 0).await;
+    let dir = tempfile::tempdir()?;
+    let options = options(&server, false);
+    download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+    // Same size, different content
+    fs::write(
+        dir.path().join("DICCIONARIO_ATC.xml"),
+        "<aemps_atc></aemps_xxx>",
+    )?;
+
+    let outcome = download_and_extract_nomenclator_with_options(dir.path(), &options)
+        .await?
+        .outcome;
+
+    assert!(matches!(outcome, DownloadOutcome::Downloaded { .. }));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("DICCIONARIO_ATC.xml"))?,
+        "<aemps_atc></aemps_atc>"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_missing_manifest_triggers_download() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 2, 0).await;
+    let dir = tempfile::tempdir()?;
+    let options = options(&server, false);
+    download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+    fs::remove_file(dir.path().join(DOWNLOAD_MANIFEST_JSON))?;
+
+    let outcome = download_and_extract_nomenclator_with_options(dir.path(), &options)
+        .await?
+        .outcome;
+
+    assert!(matches!(outcome, DownloadOutcome::Downloaded { .. }));
+    assert!(dir.path().join(DOWNLOAD_MANIFEST_JSON).is_file());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_corrupted_entry_fails_with_its_name() -> Result<()> {
+    let mut zip = nomenclator_zip()?;
+    // Flip a byte of the stored dictionary content, leaving its CRC as it was
+    let at = zip
+        .windows(b"<aemps_atc>".len())
+        .position(|window| window == b"<aemps_atc>")
+        .expect("dictionary content in the archive");
+    zip[at + 1] = b'x';
+    let server = MockServer::start().await;
+    mount_dump(&server, &zip, Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+
+    let error = download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false))
+        .await
+        .unwrap_err();
+
+    assert!(
+        format!("{error:#}").contains("DICCIONARIO_ATC.xml"),
+        "{error:#}"
+    );
+    assert!(!dir.path().join(DOWNLOAD_MANIFEST_JSON).exists());
+    Ok(())
+}
+
+/// Answers one connection after another with `responses`, closing each connection
+/// after writing its response. Returns the URL to request and the request heads
+/// received.
+async fn serve_raw(responses: Vec<Vec<u8>>) -> Result<(String, Arc<Mutex<Vec<String>>>)> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let received = Arc::clone(&requests);
+    tokio::spawn(async move {
+        for response in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            let mut buffer = [0; 1024];
+            while !head.ends_with(b"\r\n\r\n") {
+                let read = socket.read(&mut buffer).await.unwrap();
+                head.extend_from_slice(&buffer[..read]);
+            }
+            received
+                .lock()
+                .unwrap()
+                .push(String::from_utf8_lossy(&head).to_lowercase());
+            socket.write_all(&response).await.unwrap();
+        }
+    });
+    Ok((format!("http://{address}/prescripcion.zip"), requests))
+}
+
+/// Response with `headers` announcing `announced` bytes and sending `body`
+fn raw_response(status: &str, headers: &str, announced: usize, body: &[u8]) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 {status}\r\n{headers}Content-Length: {announced}\r\nConnection: close\r\n\r\n"
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+fn raw_options(url: String) -> DownloadOptions {
+    DownloadOptions {
+        url,
+        backoff: Duration::from_millis(1),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_truncated_download_fails() -> Result<()> {
+    let zip = nomenclator_zip()?;
+    let truncated = raw_response("200 OK", "", zip.len(), &zip[..zip.len() / 2]);
+    let (url, requests) = serve_raw(vec![truncated; 2]).await?;
+    let options = DownloadOptions {
+        max_retries: 1,
+        ..raw_options(url)
+    };
+    let dir = tempfile::tempdir()?;
+
+    let result = download_and_extract_nomenclator_with_options(dir.path(), &options).await;
+
+    assert!(result.is_err());
+    assert_eq!(requests.lock().unwrap().len(), 2);
+    assert!(!dir.path().join("Prescripcion.xml").exists());
+    assert!(!dir.path().join(DOWNLOAD_STATE_JSON).exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cut_download_resumed() -> Result<()> {
+    let zip = nomenclator_zip()?;
+    let half = zip.len() / 2;
+    let last_modified = format!("Last-Modified: {LAST_MODIFIED}\r\nAccept-Ranges: bytes\r\n");
+    let content_range = format!(
+        "{last_modified}Content-Range: bytes {half}-{}/{}\r\n",
+        zip.len() - 1,
+        zip.len()
+    );
+    let (url, requests) = serve_raw(vec![
+        raw_response("200 OK", &last_modified, zip.len(), &zip[..half]),
+        raw_response(
+            "206 Partial Content",
+            &content_range,
+            zip.len() - half,
+            &zip[half..],
+        ),
+    ])
+    .await?;
+    let dir = tempfile::tempdir()?;
+    let options = raw_options(url);
+
+    let outcome = download_and_extract_nomenclator_with_options(dir.path(), &options)
+        .await?
+        .outcome;
+
+    assert_eq!(
+        outcome,
+        DownloadOutcome::Downloaded {
+            url: options.url.clone(),
+            bytes: zip.len() as u64,
+            last_modified: Some(LAST_MODIFIED.to_string()),
+            retries: 1,
+            cached: false,
+        }
+    );
+    let requests = requests.lock().unwrap();
+    assert!(requests[1].contains(&format!("range: bytes={half}-")));
+    assert!(requests[1].contains(&format!("if-range: {}", LAST_MODIFIED.to_lowercase())));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cut_download_restarted_without_last_modified() -> Result<()> {
+    let zip = nomenclator_zip()?;
+    let (url, requests) = serve_raw(vec![
+        raw_response("200 OK", "", zip.len(), &zip[..zip.len() / 2]),
+        raw_response("200 OK", "", zip.len(), &zip),
+    ])
+    .await?;
+    let dir = tempfile::tempdir()?;
+
+    let outcome = download_and_extract_nomenclator_with_options(dir.path(), &raw_options(url))
+        .await?
+        .outcome;
+
+    assert!(matches!(
+        outcome,
+        DownloadOutcome::Downloaded { retries: 1, .. }
+    ));
+    assert!(!requests.lock().unwrap()[1].contains("range:"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    Ok(())
+}
+
+/// Serves the first half of the nomenclator archive with `headers` and then `second`,
+/// downloading it in two runs. Returns the result of the second run and the request
+/// heads received.
+async fn download_in_two_runs(
+    headers: &str,
+    second: Vec<u8>,
+    resume: bool,
+) -> Result<(Result<ExtractionReport>, Vec<String>, tempfile::TempDir)> {
+    let zip = nomenclator_zip()?;
+    let (url, requests) = serve_raw(vec![
+        raw_response("200 OK", headers, zip.len(), &zip[..zip.len() / 2]),
+        second,
+    ])
+    .await?;
+    let dir = tempfile::tempdir()?;
+    let options = DownloadOptions {
+        max_retries: 0,
+        resume,
+        ..raw_options(url)
+    };
+
+    let first = download_and_extract_nomenclator_with_options(dir.path(), &options).await;
+    assert!(first.is_err());
+    assert!(dir.path().join("prescripcion.zip.part").exists());
+    let second = download_and_extract_nomenclator_with_options(dir.path(), &options).await;
+
+    let requests = requests.lock().unwrap().clone();
+    Ok((second, requests, dir))
+}
+
+#[tokio::test]
+async fn test_interrupted_download_resumed_in_next_run() -> Result<()> {
+    let zip = nomenclator_zip()?;
+    let half = zip.len() / 2;
+    let headers = "ETag: \"v1\"\r\nAccept-Ranges: bytes\r\n";
+    let content_range = format!(
+        "{headers}Content-Range: bytes {half}-{}/{}\r\n",
+        zip.len() - 1,
+        zip.len()
+    );
+    let rest = raw_response(
+        "206 Partial Content",
+        &content_range,
+        zip.len() - half,
+        &zip[half..],
+    );
+
+    let (report, requests, dir) = download_in_two_runs(headers, rest, true).await?;
+
+    assert!(matches!(
+        report?.outcome,
+        DownloadOutcome::Downloaded { bytes, .. } if bytes == zip.len() as u64
+    ));
+    assert!(!requests[0].contains("range:"));
+    assert!(requests[1].contains(&format!("range: bytes={half}-")));
+    assert!(requests[1].contains("if-range: \"v1\""));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    assert!(!dir.path().join("prescripcion.zip.part").exists());
+    assert!(!dir.path().join("prescripcion.zip.part.json").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interrupted_download_restarted_when_resume_disabled() -> Result<()> {
+    let zip = nomenclator_zip()?;
+    let headers = "ETag: \"v1\"\r\nAccept-Ranges: bytes\r\n";
+    let full = raw_response("200 OK", headers, zip.len(), &zip);
+
+    let (report, requests, _dir) = download_in_two_runs(headers, full, false).await?;
+
+    report?;
+    assert!(!requests[1].contains("range:"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interrupted_download_restarted_without_accept_ranges() -> Result<()> {
+    let zip = nomenclator_zip()?;
+    let headers = "ETag: \"v1\"\r\n";
+    let full = raw_response("200 OK", headers, zip.len(), &zip);
+
+    let (report, requests, _dir) = download_in_two_runs(headers, full, true).await?;
+
+    report?;
+    assert!(!requests[1].contains("range:"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interrupted_download_restarted_when_remote_changed() -> Result<()> {
+    let zip = nomenclator_zip()?;
+    // The server ignores the range as the archive is no longer the one of the ETag
+    let full = raw_response(
+        "200 OK",
+        "ETag: \"v2\"\r\nAccept-Ranges: bytes\r\n",
+        zip.len(),
+        &zip,
+    );
+
+    let (report, requests, dir) =
+        download_in_two_runs("ETag: \"v1\"\r\nAccept-Ranges: bytes\r\n", full, true).await?;
+
+    assert!(matches!(
+        report?.outcome,
+        DownloadOutcome::Downloaded { bytes, .. } if bytes == zip.len() as u64
+    ));
+    assert!(requests[1].contains("if-range: \"v1\""));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    Ok(())
+}
+
+/// Leaves `part` in `dir` as the archive at `url` a previous run was cut off
+/// downloading, with ETag "v1" and the size of the nomenclator archive.
+fn leave_part(dir: &std::path::Path, url: &str, part: &[u8]) -> Result<()> {
+    let state = serde_json::json!({
+        "state": {
+            "url": url,
+            "last_modified": null,
+            "etag": "\"v1\"",
+            "content_length": nomenclator_zip()?.len(),
+        },
+        "accept_ranges": true,
+    });
+    fs::write(dir.join("prescripcion.zip.part"), part)?;
+    fs::write(
+        dir.join("prescripcion.zip.part.json"),
+        serde_json::to_vec(&state)?,
+    )?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_complete_part_used_without_request() -> Result<()> {
+    let zip = nomenclator_zip()?;
+    let (url, requests) = serve_raw(Vec::new()).await?;
+    let dir = tempfile::tempdir()?;
+    leave_part(dir.path(), &url, &zip)?;
+
+    let report =
+        download_and_extract_nomenclator_with_options(dir.path(), &raw_options(url)).await?;
+
+    assert!(matches!(
+        report.outcome,
+        DownloadOutcome::Downloaded { bytes, retries: 0, .. } if bytes == zip.len() as u64
+    ));
+    assert!(requests.lock().unwrap().is_empty());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    assert!(!dir.path().join("prescripcion.zip.part").exists());
+    assert!(!dir.path().join("prescripcion.zip.part.json").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_corrupt_complete_part_downloaded_again() -> Result<()> {
+    let zip = nomenclator_zip()?;
+    let (url, requests) = serve_raw(vec![raw_response(
+        "200 OK",
+        "ETag: \"v1\"\r\nAccept-Ranges: bytes\r\n",
+        zip.len(),
+        &zip,
+    )])
+    .await?;
+    let dir = tempfile::tempdir()?;
+    // Same size, but Prescripcion.xml no longer matches its CRC-32
+    let mut corrupt = zip.clone();
+    let at = zip
+        .windows(PRESCRIPTION_XML.len())
+        .position(|window| window == PRESCRIPTION_XML.as_bytes())
+        .expect("stored entry");
+    corrupt[at + 1] = b'A';
+    leave_part(dir.path(), &url, &corrupt)?;
+
+    download_and_extract_nomenclator_with_options(dir.path(), &raw_options(url)).await?;
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(!requests[0].contains("range:"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unsatisfiable_range_restarts_download() -> Result<()> {
+    let zip = nomenclator_zip()?;
+    let headers = "ETag: \"v1\"\r\nAccept-Ranges: bytes\r\n";
+    let (url, requests) = serve_raw(vec![
+        raw_response("416 Range Not Satisfiable", headers, 0, b""),
+        raw_response("200 OK", headers, zip.len(), &zip),
+    ])
+    .await?;
+    let dir = tempfile::tempdir()?;
+    leave_part(dir.path(), &url, &zip[..zip.len() / 2])?;
+    let options = DownloadOptions {
+        max_retries: 0,
+        ..raw_options(url)
+    };
+
+    let report = download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+
+    assert!(matches!(
+        report.outcome,
+        DownloadOutcome::Downloaded { bytes, .. } if bytes == zip.len() as u64
+    ));
+    let requests = requests.lock().unwrap();
+    assert!(requests[0].contains("range:"));
+    assert!(!requests[1].contains("range:"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_misplaced_content_range_restarts_download() -> Result<()> {
+    let zip = nomenclator_zip()?;
+    let half = zip.len() / 2;
+    let headers = "ETag: \"v1\"\r\nAccept-Ranges: bytes\r\n";
+    // The server sends the start of the archive again, as if the range were ignored
+    let content_range = format!(
+        "{headers}Content-Range: bytes 0-{}/{}\r\n",
+        half - 1,
+        zip.len()
+    );
+    let (url, requests) = serve_raw(vec![
+        raw_response("206 Partial Content", &content_range, half, &zip[..half]),
+        raw_response("200 OK", headers, zip.len(), &zip),
+    ])
+    .await?;
+    let dir = tempfile::tempdir()?;
+    leave_part(dir.path(), &url, &zip[..half])?;
+    let options = DownloadOptions {
+        max_retries: 0,
+        ..raw_options(url)
+    };
+
+    download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+
+    let requests = requests.lock().unwrap();
+    assert!(requests[0].contains(&format!("range: bytes={half}-")));
+    assert!(!requests[1].contains("range:"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_error_retried() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/prescripcion.zip"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+
+    let outcome =
+        download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false))
+            .await?
+            .outcome;
+
+    assert!(matches!(
+        outcome,
+        DownloadOutcome::Downloaded { retries: 1, .. }
+    ));
+    assert!(dir.path().join("Prescripcion.xml").is_file());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_not_found_not_retried() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/prescripcion.zip"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir()?;
+
+    let error = download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false))
+        .await
+        .unwrap_err();
+
+    assert!(format!("{error:#}").contains("404"), "{error:#}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mirror_used_when_url_fails() -> Result<()> {
+    let primary = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/prescripcion.zip"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(2)
+        .mount(&primary)
+        .await;
+    let mirror = MockServer::start().await;
+    mount_dump(&mirror, &nomenclator_zip()?, Some(LAST_MODIFIED), 1, 1).await;
+    let dir = tempfile::tempdir()?;
+    let mirror_url = format!("{}/prescripcion.zip", mirror.uri());
+    let options = DownloadOptions {
+        mirrors: vec![mirror_url.clone()],
+        max_retries: 1,
+        ..options(&primary, false)
+    };
+
+    let outcome = download_and_extract_nomenclator_with_options(dir.path(), &options)
+        .await?
+        .outcome;
+
+    assert!(matches!(
+        outcome,
+        DownloadOutcome::Downloaded { ref url, retries: 0, .. } if *url == mirror_url
+    ));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+
+    // The freshness check asks the mirror that served the archive
+    let outcome = download_and_extract_nomenclator_with_options(dir.path(), &options)
+        .await?
+        .outcome;
+    assert_eq!(outcome, DownloadOutcome::UpToDate);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_every_url_failing_fails() -> Result<()> {
+    let primary = MockServer::start().await;
+    let mirror = MockServer::start().await;
+    for server in [&primary, &mirror] {
+        Mock::given(method("GET"))
+            .and(path("/prescripcion.zip"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+    let dir = tempfile::tempdir()?;
+    let options = DownloadOptions {
+        mirrors: vec![format!("{}/prescripcion.zip", mirror.uri())],
+        ..options(&primary, false)
+    };
+
+    let error = download_and_extract_nomenclator_with_options(dir.path(), &options)
+        .await
+        .unwrap_err();
+
+    assert!(format!("{error:#}").contains("2 URL(s)"), "{error:#}");
+    Ok(())
+}
+
+/// Archive of five files: Prescripcion.xml, two dictionaries, an XSD and a PDF
+fn five_file_zip() -> Result<Vec<u8>> {
+    zip_of(&[
+        ("Prescripcion.xml", PRESCRIPTION_XML.as_bytes()),
+        ("DICCIONARIO_ATC.xml", b"<aemps_atc></aemps_atc>"),
+        (
+            "DICCIONARIO_LABORATORIOS.xml",
+            b"<aemps_laboratorios></aemps_laboratorios>",
+        ),
+        ("Prescripcion.xsd", b"<xs:schema/>"),
+        ("Nomenclator.pdf", b"%PDF-1.4"),
+    ])
+}
+
+#[tokio::test]
+async fn test_include_extracts_matching_files() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &five_file_zip()?, Some(LAST_MODIFIED), 1, 1).await;
+    let dir = tempfile::tempdir()?;
+    let options = DownloadOptions {
+        include: Some(vec![
+            "DICCIONARIO_*.xml".to_string(),
+            "Nomenclator.pdf".to_string(),
+        ]),
+        ..options(&server, false)
+    };
+
+    let report = download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+
+    let files: Vec<_> = report.files.iter().map(|file| &file.name).collect();
+    assert_eq!(
+        files,
+        [
+            "DICCIONARIO_ATC.xml",
+            "DICCIONARIO_LABORATORIOS.xml",
+            "Nomenclator.pdf"
+        ]
+    );
+    assert!(!dir.path().join("Prescripcion.xml").exists());
+    assert!(!dir.path().join("Prescripcion.xsd").exists());
+
+    // Nothing else is needed while the selection stays the same
+    let outcome = download_and_extract_nomenclator_with_options(dir.path(), &options)
+        .await?
+        .outcome;
+    assert_eq!(outcome, DownloadOutcome::UpToDate);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_include_by_dictionary_kind() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &five_file_zip()?, Some(LAST_MODIFIED), 2, 0).await;
+    let dir = tempfile::tempdir()?;
+    let options = DownloadOptions {
+        include: Some(nomenclator_xml_files(
+            &[DictionaryKind::Atc, DictionaryKind::Laboratories],
+            false,
+        )),
+        ..options(&server, false)
+    };
+
+    let report = download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+
+    let files: Vec<_> = report.files.iter().map(|file| &file.name).collect();
+    assert_eq!(
+        files,
+        ["DICCIONARIO_ATC.xml", "DICCIONARIO_LABORATORIOS.xml"]
+    );
+
+    // A wider selection extracts again
+    let options = DownloadOptions {
+        include: None,
+        ..options
+    };
+    let report = download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+    assert!(!report.skipped());
+    assert_eq!(report.files.len(), 5);
+    Ok(())
+}
+
+/// Names of the files in `dir`, sorted
+fn file_names(dir: &std::path::Path) -> Result<Vec<String>> {
+    let mut names = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<Vec<_>>>()?;
+    names.sort();
+    Ok(names)
+}
+
+#[tokio::test]
+async fn test_zip_cache_reused_by_next_run() -> Result<()> {
+    let server = MockServer::start().await;
+    let zip = nomenclator_zip()?;
+    mount_dump(&server, &zip, Some(LAST_MODIFIED), 1, 2).await;
+    let dir = tempfile::tempdir()?;
+    let cache = dir.path().join("cache");
+    let options = DownloadOptions {
+        zip_cache_dir: Some(cache.clone()),
+        ..options(&server, false)
+    };
+
+    let first =
+        download_and_extract_nomenclator_with_options(dir.path().join("csv"), &options).await?;
+    let second =
+        download_and_extract_nomenclator_with_options(dir.path().join("sqlite"), &options).await?;
+
+    assert!(matches!(
+        first.outcome,
+        DownloadOutcome::Downloaded { cached: false, .. }
+    ));
+    assert!(matches!(
+        second.outcome,
+        DownloadOutcome::Downloaded { cached: true, bytes, .. } if bytes == zip.len() as u64
+    ));
+    assert_eq!(file_sizes(&second), EXTRACTED_FILES);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("sqlite").join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    assert!(!dir.path().join("sqlite").join("prescripcion.zip").exists());
+    let cached = file_names(&cache)?;
+    assert_eq!(cached.len(), 1);
+    assert!(cached[0].starts_with("prescripcion-"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zip_cache_downloads_new_edition() -> Result<()> {
+    let server = MockServer::start().await;
+    let zip = nomenclator_zip()?;
+    mount_dump(&server, &zip, Some(LAST_MODIFIED), 1, 1).await;
+    let dir = tempfile::tempdir()?;
+    let cache = dir.path().join("cache");
+    let options = DownloadOptions {
+        zip_cache_dir: Some(cache.clone()),
+        ..options(&server, true)
+    };
+    download_and_extract_nomenclator_with_options(dir.path().join("data"), &options).await?;
+    server.verify().await;
+    server.reset().await;
+    mount_dump(&server, &zip, Some("Mon, 09 Sep 2024 06:00:00 GMT"), 1, 1).await;
+
+    let report =
+        download_and_extract_nomenclator_with_options(dir.path().join("data"), &options).await?;
+
+    assert!(matches!(
+        report.outcome,
+        DownloadOutcome::Downloaded { cached: false, .. }
+    ));
+    assert_eq!(file_names(&cache)?.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_prune_zip_cache_keeps_newest_editions() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let editions = [
+        "prescripcion-0000000000000001.zip",
+        "prescripcion-0000000000000002.zip",
+        "prescripcion-0000000000000003.zip",
+        "delta-00000000000000aa.zip",
+    ];
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    for (i, name) in editions.iter().enumerate() {
+        let file = fs::File::create(dir.path().join(name))?;
+        file.set_modified(start + Duration::from_secs(i as u64))?;
+    }
+    fs::write(dir.path().join("prescripcion.zip.part"), b"")?;
+    fs::write(dir.path().join("notes-1.zip"), b"")?;
+
+    let removed = prune_zip_cache(dir.path(), 2)?;
+
+    assert_eq!(
+        removed,
+        vec![dir.path().join("prescripcion-0000000000000001.zip")]
+    );
+    assert_eq!(
+        file_names(dir.path())?,
+        [
+            "delta-00000000000000aa.zip",
+            "notes-1.zip",
+            "prescripcion-0000000000000002.zip",
+            "prescripcion-0000000000000003.zip",
+            "prescripcion.zip.part",
+        ]
+    );
+    assert_eq!(prune_zip_cache(dir.path(), 0)?.len(), 3);
+    Ok(())
+}
+
+/// Names of the staging directories in `dir`
+fn staging_dirs(dir: &std::path::Path) -> Vec<String> {
+    file_names(dir)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|name| name.starts_with(".extracting-"))
+        .collect()
+}
+
+/// Asserts that `dir` holds either every file of [`nomenclator_zip`] and its manifest
+/// or none of them, and no staging directory.
+fn assert_absent_or_complete(dir: &std::path::Path) {
+    let present: Vec<bool> = [
+        "Prescripcion.xml",
+        "DICCIONARIO_ATC.xml",
+        DOWNLOAD_MANIFEST_JSON,
+    ]
+    .iter()
+    .map(|name| dir.join(name).exists())
+    .collect();
+    assert!(
+        present.iter().all(|&p| p) || present.iter().all(|&p| !p),
+        "{present:?}"
+    );
+    assert_eq!(staging_dirs(dir), Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn test_download_cancelled_mid_extraction() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+    let extracting = Arc::new(tokio::sync::Notify::new());
+    let notify = Arc::clone(&extracting);
+    let (done, finished) = std::sync::mpsc::channel();
+    let options = DownloadOptions {
+        progress: Some(Arc::new(move |progress| {
+            if let DownloadProgress::Extracting { files: 1, .. } = progress {
+                notify.notify_one();
+                // Keep the extraction going until the future is dropped
+                std::thread::sleep(Duration::from_millis(200));
+                done.send(()).unwrap();
+            }
+        })),
+        ..options(&server, false)
+    };
+
+    tokio::select! {
+        result = download_and_extract_nomenclator_with_options(dir.path(), &options) => {
+            panic!("Download not cancelled: {result:?}")
+        }
+        _ = extracting.notified() => {}
+    }
+    tokio::task::spawn_blocking(move || finished.recv()).await??;
+    // Let the extraction task clean up after noticing the cancellation
+    for _ in 0..100 {
+        if staging_dirs(dir.path()).is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_absent_or_complete(dir.path());
+    assert!(!dir.path().join("Prescripcion.xml").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_cancelled_mid_body() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/prescripcion.zip"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_bytes(nomenclator_zip()?)
+                .set_delay(Duration::from_secs(5)),
+        )
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir()?;
+
+    let result = tokio::time::timeout(
+        Duration::from_millis(100),
+        download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)),
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert_absent_or_complete(dir.path());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_extraction_panic_reported_as_error() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+    let options = DownloadOptions {
+        progress: Some(Arc::new(|progress| {
+            if let DownloadProgress::Extracting { .. } = progress {
+                panic!("progress bar gone");
+            }
+        })),
+        ..options(&server, false)
+    };
+
+    let error = download_and_extract_nomenclator_with_options(dir.path(), &options)
+        .await
+        .unwrap_err();
+
+    assert!(
+        format!("{error:#}").contains("Extraction task panicked: progress bar gone"),
+        "{error:#}"
+    );
+    assert_absent_or_complete(dir.path());
+    assert!(!dir.path().join("Prescripcion.xml").exists());
+    Ok(())
+}
+
+/// Mounts `zip` at `/presentaciones.zip`, expecting `gets` downloads and `heads` checks
+async fn mount_dataset(server: &MockServer, zip: &[u8], gets: u64, heads: u64) {
+    let response = ResponseTemplate::new(200)
+        .set_body_bytes(zip)
+        .insert_header("Last-Modified", LAST_MODIFIED);
+    Mock::given(method("GET"))
+        .and(path("/presentaciones.zip"))
+        .respond_with(response.clone())
+        .expect(gets)
+        .mount(server)
+        .await;
+    Mock::given(method("HEAD"))
+        .and(path("/presentaciones.zip"))
+        .respond_with(response)
+        .expect(heads)
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_generic_download_extracts_any_zip() -> Result<()> {
+    let server = MockServer::start().await;
+    let zip = zip_of(&[
+        ("Presentaciones.xls", b"cn;nregistro\n600000;60000\n"),
+        ("LEEME.txt", b"Presentaciones"),
+    ])?;
+    mount_dataset(&server, &zip, 1, 1).await;
+    let dir = tempfile::tempdir()?;
+    let url = format!("{}/presentaciones.zip", server.uri());
+    // Only the URL given to the function is downloaded
+    let options = DownloadOptions {
+        url: format!("{}/prescripcion.zip", server.uri()),
+        ..options(&server, false)
+    };
+
+    let report = download_and_extract(&url, dir.path(), &options).await?;
+    let again = download_and_extract(&url, dir.path(), &options).await?;
+
+    assert_eq!(
+        report.outcome,
+        DownloadOutcome::Downloaded {
+            url,
+            bytes: zip.len() as u64,
+            last_modified: Some(LAST_MODIFIED.to_string()),
+            retries: 0,
+            cached: false,
+        }
+    );
+    assert_eq!(
+        file_sizes(&report),
+        [("LEEME.txt", 14), ("Presentaciones.xls", 26)]
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("LEEME.txt"))?,
+        "Presentaciones"
+    );
+    assert!(!dir.path().join("presentaciones.zip").exists());
+    assert!(again.skipped());
+    assert_eq!(file_sizes(&again), file_sizes(&report));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_generic_download_falls_back_to_mirror() -> Result<()> {
+    let server = MockServer::start().await;
+    let zip = zip_of(&[("LEEME.txt", b"Presentaciones")])?;
+    mount_dataset(&server, &zip, 1, 0).await;
+    let dir = tempfile::tempdir()?;
+    let options = DownloadOptions {
+        mirrors: vec![format!("{}/presentaciones.zip", server.uri())],
+        ..options(&server, false)
+    };
+
+    let error = download_and_extract(
+        &format!("{}/missing/presentaciones.zip", server.uri()),
+        dir.path(),
+        &DownloadOptions {
+            mirrors: Vec::new(),
+            ..options.clone()
+        },
+    )
+    .await
+    .unwrap_err();
+    let report = download_and_extract(
+        &format!("{}/missing/presentaciones.zip", server.uri()),
+        dir.path(),
+        &options,
+    )
+    .await?;
+
+    assert!(
+        error
+            .to_string()
+            .contains("Failed to download presentaciones.zip from 1 URL(s)"),
+        "{error:#}"
+    );
+    assert!(matches!(
+        report.outcome,
+        DownloadOutcome::Downloaded { ref url, .. } if url == &options.mirrors[0]
+    ));
+    assert!(dir.path().join("LEEME.txt").is_file());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_extraction_leaves_previous_files() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 1, 0).await;
+    let dir = tempfile::tempdir()?;
+    download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)).await?;
+    let manifest = fs::read(dir.path().join(DOWNLOAD_MANIFEST_JSON))?;
+    server.reset().await;
+    // A new edition whose first entry extracts fine and whose second one is corrupted
+    let mut zip = zip_of(&[
+        (
+            "Prescripcion.xml",
+            b"<aemps_prescripcion>new</aemps_prescripcion>",
+        ),
+        ("DICCIONARIO_ATC.xml", b"<aemps_atc></aemps_atc>"),
+    ])?;
+    let at = zip
+        .windows(b"<aemps_atc>".len())
+        .position(|window| window == b"<aemps_atc>")
+        .expect("dictionary content in the archive");
+    zip[at + 1] = b'x';
+    mount_dump(&server, &zip, Some(LAST_MODIFIED), 1, 0).await;
+
+    let result =
+        download_and_extract_nomenclator_with_options(dir.path(), &options(&server, true)).await;
+
+    assert!(result.is_err());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Prescripcion.xml"))?,
+        PRESCRIPTION_XML
+    );
+    assert_eq!(fs::read(dir.path().join(DOWNLOAD_MANIFEST_JSON))?, manifest);
+    assert_eq!(staging_dirs(dir.path()), Vec::<String>::new());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stale_staging_dir_removed_on_next_run() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 1, 1).await;
+    let dir = tempfile::tempdir()?;
+    download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)).await?;
+    // Left by a run that crashed mid-extraction
+    let stale = dir.path().join(".extracting-1");
+    fs::create_dir(&stale)?;
+    fs::write(stale.join("Prescripcion.xml"), "<aemps_prescripcion>")?;
+
+    let report =
+        download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)).await?;
+
+    assert!(report.skipped());
+    assert!(!stale.exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_staging_dir_of_running_extraction_kept() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_dump(&server, &nomenclator_zip()?, Some(LAST_MODIFIED), 1, 1).await;
+    let dir = tempfile::tempdir()?;
+    download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)).await?;
+    // Held by another run still extracting into the same directory
+    let running = dir.path().join(".extracting-1");
+    fs::create_dir(&running)?;
+    let lock = fs::File::create(dir.path().join(".extracting-1.lock"))?;
+    lock.lock()?;
+    // Left by a run that crashed after taking its lock
+    let crashed = dir.path().join(".extracting-2");
+    fs::create_dir(&crashed)?;
+    fs::File::create(dir.path().join(".extracting-2.lock"))?;
+
+    download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)).await?;
+
+    assert!(running.exists());
+    assert_eq!(
+        staging_dirs(dir.path()),
+        [".extracting-1", ".extracting-1.lock"]
+    );
+    lock.unlock()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_provided_client_used_for_every_request() -> Result<()> {
+    let server = MockServer::start().await;
+    let zip = nomenclator_zip()?;
+    let response = ResponseTemplate::new(200)
+        .set_body_bytes(zip)
+        .insert_header("Last-Modified", LAST_MODIFIED);
+    for verb in ["GET", "HEAD"] {
+        Mock::given(method(verb))
+            .and(path("/prescripcion.zip"))
+            .and(header("user-agent", "pipeline/1.0"))
+            .respond_with(response.clone())
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+    let dir = tempfile::tempdir()?;
+    let options = DownloadOptions {
+        client: Some(
+            reqwest::Client::builder()
+                .user_agent("pipeline/1.0")
+                .build()?,
+        ),
+        ..options(&server, false)
+    };
+
+    download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+    let report = download_and_extract_nomenclator_with_options(dir.path(), &options).await?;
+
+    assert!(report.skipped());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_default_client_sends_crate_user_agent() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/prescripcion.zip"))
+        .and(header("user-agent", cima_rs::USER_AGENT))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(nomenclator_zip()?))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir()?;
+
+    download_and_extract_nomenclator_with_options(dir.path(), &options(&server, false)).await?;
+
+    Ok(())
+}
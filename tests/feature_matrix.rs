@@ -0,0 +1,51 @@
+//! Checks that the library builds with each supported combination of features.
+//!
+//! Every combination is checked twice, the library alone and then with the tests,
+//! benches and examples, so the test is ignored by default:
+//! `cargo test --test feature_matrix -- --ignored`.
+
+use std::process::Command;
+
+/// Feature sets that must build on their own, with `--no-default-features`
+const FEATURE_SETS: &[&str] = &[
+    "",
+    "api",
+    "parser",
+    "downloader",
+    "api,parser",
+    "api,downloader",
+    "arrow",
+    "parquet",
+    "sqlite",
+    "validate-xml",
+    "cli",
+    "record-replay",
+    "metrics",
+    "parser,metrics",
+    "test-util",
+    "dataset",
+];
+
+#[test]
+#[ignore = "runs cargo check twice per feature set"]
+fn test_feature_sets_build() {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let target_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/target/feature-matrix");
+    for features in FEATURE_SETS {
+        // The library first, so that no dev-dependency can enable a feature the set lacks
+        for targets in ["--lib", "--all-targets"] {
+            let status = Command::new(&cargo)
+                .args(["check", targets, "--no-default-features", "--features"])
+                .arg(features)
+                .arg("--manifest-path")
+                .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+                .env("CARGO_TARGET_DIR", target_dir)
+                .status()
+                .expect("failed to run cargo");
+            assert!(
+                status.success(),
+                "features [{features}] do not build with {targets}"
+            );
+        }
+    }
+}
@@ -0,0 +1,157 @@
+// @generated by `nomenclator codegen` from the AEMPS nomenclator dictionaries.
+// Do not edit: regenerate it instead.
+
+/// Codes of DICCIONARIO_ENVASES.xml
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Container {
+    /// AMPOLLA
+    Ampolla,
+    /// BLISTER
+    Blister,
+    /// Code missing from the dictionary the enum was generated from
+    Unknown(String),
+}
+
+impl Container {
+    /// Variant of an AEMPS code
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "1" => Self::Ampolla,
+            "2" => Self::Blister,
+            _ => Self::Unknown(code.to_string()),
+        }
+    }
+
+    /// AEMPS code of the variant
+    pub fn as_code(&self) -> &str {
+        match self {
+            Self::Ampolla => "1",
+            Self::Blister => "2",
+            Self::Unknown(code) => code,
+        }
+    }
+}
+
+/// Codes of DICCIONARIO_FORMA_FARMACEUTICA_SIMPLIFICADAS.xml
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SimplifiedForm {
+    /// COMPRIMIDO
+    Comprimido,
+    /// CÁPSULA
+    Capsula,
+    /// Code missing from the dictionary the enum was generated from
+    Unknown(String),
+}
+
+impl SimplifiedForm {
+    /// Variant of an AEMPS code
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "10" => Self::Comprimido,
+            "20" => Self::Capsula,
+            _ => Self::Unknown(code.to_string()),
+        }
+    }
+
+    /// AEMPS code of the variant
+    pub fn as_code(&self) -> &str {
+        match self {
+            Self::Comprimido => "10",
+            Self::Capsula => "20",
+            Self::Unknown(code) => code,
+        }
+    }
+}
+
+/// Codes of DICCIONARIO_SITUACION_REGISTRO.xml
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RegistrationStatus {
+    /// AUTORIZADO
+    Autorizado,
+    /// SUSPENDIDO
+    Suspendido,
+    /// Code missing from the dictionary the enum was generated from
+    Unknown(String),
+}
+
+impl RegistrationStatus {
+    /// Variant of an AEMPS code
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "1" => Self::Autorizado,
+            "2" => Self::Suspendido,
+            _ => Self::Unknown(code.to_string()),
+        }
+    }
+
+    /// AEMPS code of the variant
+    pub fn as_code(&self) -> &str {
+        match self {
+            Self::Autorizado => "1",
+            Self::Suspendido => "2",
+            Self::Unknown(code) => code,
+        }
+    }
+}
+
+/// Codes of DICCIONARIO_UNIDAD_CONTENIDO.xml
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContainerUnit {
+    /// COMPRIMIDOS
+    Comprimidos,
+    /// ML
+    Ml,
+    /// Code missing from the dictionary the enum was generated from
+    Unknown(String),
+}
+
+impl ContainerUnit {
+    /// Variant of an AEMPS code
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "1" => Self::Comprimidos,
+            "2" => Self::Ml,
+            _ => Self::Unknown(code.to_string()),
+        }
+    }
+
+    /// AEMPS code of the variant
+    pub fn as_code(&self) -> &str {
+        match self {
+            Self::Comprimidos => "1",
+            Self::Ml => "2",
+            Self::Unknown(code) => code,
+        }
+    }
+}
+
+/// Codes of DICCIONARIO_VIAS_ADMINISTRACION.xml
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AdministrationRoute {
+    /// INTRAPERITONEAL – USO CRÓNICO
+    IntraperitonealUsoCronico,
+    /// VÍA ORAL
+    ViaOral,
+    /// Code missing from the dictionary the enum was generated from
+    Unknown(String),
+}
+
+impl AdministrationRoute {
+    /// Variant of an AEMPS code
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "12" => Self::IntraperitonealUsoCronico,
+            "48" => Self::ViaOral,
+            _ => Self::Unknown(code.to_string()),
+        }
+    }
+
+    /// AEMPS code of the variant
+    pub fn as_code(&self) -> &str {
+        match self {
+            Self::IntraperitonealUsoCronico => "12",
+            Self::ViaOral => "48",
+            Self::Unknown(code) => code,
+        }
+    }
+}
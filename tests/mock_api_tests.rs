@@ -0,0 +1,787 @@
+#![cfg(all(feature = "api", feature = "parser"))]
+
+use anyhow::Result;
+use cima_rs::enrich::{
+    NregistroOptions, NregistroReport, PRESCRIPTION_NREGISTRO_CSV,
+    enrich_prescriptions_with_nregistro, enrich_prescriptions_with_nregistro_with_options,
+};
+use cima_rs::parser::{ParserOptions, part_file_name};
+use cima_rs::{
+    ApiStatusError, CimaClient, CimaClientOptions, ClinicalDescriptionFetchOpts,
+    DocumentDownloadOptions, DocumentDownloadStatus, DocumentType, Endpoint, EndpointError,
+    MasterDataParams, MasterDataType, MedicationId, RetryPolicy, SearchClinicalDescriptionParams,
+};
+use serde_json::json;
+use std::fs;
+use std::time::Duration;
+use wiremock::matchers::{header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Helper to create a client pointing at the mock server
+fn create_client(server: &MockServer) -> Result<CimaClient> {
+    CimaClient::builder().base_url(&server.uri()).build()
+}
+
+#[tokio::test]
+async fn test_get_clinical_description_by_vmpp() -> Result<()> {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/vmpp"))
+        .and(query_param("vmpp", "1234567"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": 1,
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": [{
+                "vmp": "3456789",
+                "vmpDesc": "Paracetamol 1 g comprimido",
+                "vmpp": "1234567",
+                "vmppDesc": "Paracetamol 1 g 40 comprimidos",
+                "presComerc": 12
+            }]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = create_client(&server)?;
+    let description = client
+        .get_clinical_description("1234567")
+        .await?
+        .expect("Description should be found");
+
+    assert_eq!(description.vmpp, "1234567");
+    assert_eq!(description.vmp, "3456789");
+    assert_eq!(description.commercialized_presentations, 12);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_get_clinical_description_not_found() -> Result<()> {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/vmpp"))
+        .and(query_param("vmpp", "0000000"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": 0,
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": []
+        })))
+        .mount(&server)
+        .await;
+
+    let client = create_client(&server)?;
+    let description = client.get_clinical_description("0000000").await?;
+
+    assert!(description.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_get_medications_keeps_order_and_errors() -> Result<()> {
+    let server = MockServer::start().await;
+    for (param, value) in [("nregistro", "60000"), ("cn", "600001")] {
+        Mock::given(method("GET"))
+            .and(path("/medicamento"))
+            .and(query_param(param, value))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "nregistro": value,
+                "nombre": "MEDICAMENTO",
+                "pactivos": "PARACETAMOL",
+                "labtitular": "Laboratorio Ejemplo",
+                "estado": {},
+                "cpresc": "Sin receta"
+            })))
+            .expect(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let client = create_client(&server)?;
+    let ids = [
+        MedicationId::RegistrationNumber("60000".to_string()),
+        MedicationId::RegistrationNumber("99999".to_string()),
+        MedicationId::NationalCode("600001".to_string()),
+    ];
+    let results = client.get_medications(&ids, 2).await;
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().nregistro, "60000");
+    assert!(results[1].is_err());
+    assert_eq!(results[2].as_ref().unwrap().nregistro, "600001");
+    assert_eq!(ids[2].to_string(), "cn 600001");
+
+    Ok(())
+}
+
+fn clinical_description_page(page: u32, total: u32, vmpps: &[&str]) -> serde_json::Value {
+    let results: Vec<_> = vmpps
+        .iter()
+        .map(|vmpp| {
+            json!({
+                "vmp": "3456789",
+                "vmpDesc": "Paracetamol 1 g comprimido",
+                "vmpp": vmpp,
+                "vmppDesc": format!("Paracetamol {}", vmpp),
+                "presComerc": 1
+            })
+        })
+        .collect();
+
+    json!({
+        "totalFilas": total,
+        "pagina": page,
+        "tamanioPagina": 2,
+        "resultados": results
+    })
+}
+
+#[tokio::test]
+async fn test_search_all_clinical_descriptions() -> Result<()> {
+    let server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/vmpp"))
+        .and(query_param("pagina", "1"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(clinical_description_page(1, 3, &["1", "2"])),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/vmpp"))
+        .and(query_param("pagina", "2"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(clinical_description_page(2, 3, &["3"])),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = create_client(&server)?;
+    let params = SearchClinicalDescriptionParams {
+        name: Some("paracetamol".to_string()),
+        ..Default::default()
+    };
+    let descriptions = client.search_all_clinical_descriptions(&params).await?;
+
+    assert_eq!(descriptions.len(), 3);
+    assert_eq!(descriptions[2].vmpp, "3");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search_all_clinical_descriptions_min_commercialized() -> Result<()> {
+    let server = MockServer::start().await;
+
+    let page = |page: u32, items: &[(&str, i32)]| {
+        let results: Vec<_> = items
+            .iter()
+            .map(|(vmpp, count)| {
+                json!({
+                    "vmp": "3456789",
+                    "vmpDesc": "Ibuprofeno 600 mg comprimido",
+                    "vmpp": vmpp,
+                    "vmppDesc": format!("Ibuprofeno {}", vmpp),
+                    "presComerc": count
+                })
+            })
+            .collect();
+        json!({
+            "totalFilas": 5,
+            "pagina": page,
+            "tamanioPagina": 2,
+            "resultados": results
+        })
+    };
+
+    for (number, items) in [
+        (1, vec![("1", 0), ("2", 3)]),
+        (2, vec![("3", 1), ("4", 0)]),
+        (3, vec![("5", 2)]),
+    ] {
+        Mock::given(method("GET"))
+            .and(path("/vmpp"))
+            .and(query_param("pagina", number.to_string()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page(number, &items)))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let client = create_client(&server)?;
+    let opts = ClinicalDescriptionFetchOpts {
+        min_commercialized: Some(1),
+    };
+    let fetch = client
+        .search_all_clinical_descriptions_with_opts(&Default::default(), &opts)
+        .await?;
+
+    assert_eq!(fetch.scanned, 5);
+    assert_eq!(fetch.kept(), 3);
+    let vmpps: Vec<_> = fetch.results.iter().map(|d| d.vmpp.as_str()).collect();
+    assert_eq!(vmpps, vec!["2", "3", "5"]);
+
+    Ok(())
+}
+
+/// Mounts a `medicamentos?cn=` search finding `nregistro`, or nothing, expected `times` times
+async fn mount_national_code(server: &MockServer, cn: &str, nregistro: Option<&str>, times: u64) {
+    let results: Vec<_> = nregistro
+        .iter()
+        .map(|nregistro| {
+            json!({
+                "nregistro": nregistro,
+                "nombre": format!("Medicamento {}", cn),
+                "labtitular": "Laboratorio",
+                "estado": {"aut": 1_000_000_000_000_i64},
+                "cpresc": "Con receta"
+            })
+        })
+        .collect();
+    Mock::given(method("GET"))
+        .and(path("/medicamentos"))
+        .and(query_param("cn", cn))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "totalFilas": results.len(),
+            "pagina": 1,
+            "tamanioPagina": 25,
+            "resultados": results
+        })))
+        .expect(times)
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_enrich_prescriptions_with_nregistro() -> Result<()> {
+    let server = MockServer::start().await;
+    // Resolved codes come from the mapping file on the second run
+    mount_national_code(&server, "600000", Some("72112"), 1).await;
+    mount_national_code(&server, "600001", Some("65432"), 1).await;
+    mount_national_code(&server, "600002", None, 2).await;
+    mount_national_code(&server, "600003", Some("80001"), 1).await;
+
+    let dir = tempfile::tempdir()?;
+    fs::write(
+        dir.path().join("prescriptions.csv"),
+        "cod_nacion,des_nomco\n600001,B\n600000,A\n600002,C\n600003,D\n600000,A\n",
+    )?;
+    let client = create_client(&server)?;
+    let report = enrich_prescriptions_with_nregistro(dir.path(), &client, 2).await?;
+
+    assert_eq!(
+        report,
+        NregistroReport {
+            resolved: 3,
+            cached: 0,
+            unresolved: vec!["600002".to_string()],
+        }
+    );
+    let mapping = dir.path().join(PRESCRIPTION_NREGISTRO_CSV);
+    assert_eq!(
+        fs::read_to_string(&mapping)?,
+        "cod_nacion,nregistro\n600000,72112\n600001,65432\n600003,80001\n"
+    );
+
+    // Codes no longer in prescriptions.csv are dropped from the mapping file
+    fs::write(
+        dir.path().join("prescriptions.csv"),
+        "cod_nacion,des_nomco\n600000,A\n600001,B\n600002,C\n",
+    )?;
+    let report = enrich_prescriptions_with_nregistro(dir.path(), &client, 2).await?;
+    assert_eq!(report.cached, 2);
+    assert_eq!(report.resolved, 2);
+    assert_eq!(report.unresolved, ["600002"]);
+    assert_eq!(
+        fs::read_to_string(&mapping)?,
+        "cod_nacion,nregistro\n600000,72112\n600001,65432\n"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_enrich_split_prescriptions_with_options() -> Result<()> {
+    let server = MockServer::start().await;
+    mount_national_code(&server, "700000", Some("1"), 1).await;
+    mount_national_code(&server, "700001", None, 1).await;
+    mount_national_code(&server, "700002", Some("3"), 1).await;
+
+    let dir = tempfile::tempdir()?;
+    let name = "prescriptions.csv";
+    fs::write(
+        dir.path().join(part_file_name(name, 1)),
+        "cod_nacion;des_nomco\n700000;A\n700001;B\n",
+    )?;
+    fs::write(
+        dir.path().join(part_file_name(name, 2)),
+        "cod_nacion;des_nomco\n700002;C\n",
+    )?;
+    let options = NregistroOptions {
+        concurrency: 1,
+        requests_per_second: None,
+        batch_size: 1,
+        parser: ParserOptions {
+            delimiter: b';',
+            ..Default::default()
+        },
+    };
+    let client = create_client(&server)?;
+    let report =
+        enrich_prescriptions_with_nregistro_with_options(dir.path(), &client, &options).await?;
+
+    assert_eq!(report.resolved, 2);
+    assert_eq!(report.unresolved, ["700001"]);
+    assert_eq!(
+        fs::read_to_string(dir.path().join(PRESCRIPTION_NREGISTRO_CSV))?,
+        "cod_nacion;nregistro\n700000;1\n700002;3\n"
+    );
+
+    // Without prescriptions.csv nothing is requested
+    let empty = tempfile::tempdir()?;
+    let error = enrich_prescriptions_with_nregistro(empty.path(), &client, 1)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().starts_with("No prescriptions.csv in"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_documents_html_and_overwrite() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .and(query_param("nregistro", "60000"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "nregistro": "60000",
+            "nombre": "MEDICAMENTO",
+            "pactivos": "PARACETAMOL",
+            "labtitular": "Laboratorio Ejemplo",
+            "estado": {},
+            "cpresc": "Sin receta",
+            "docs": [{
+                "tipo": 2,
+                "url": format!("{}/pdfs/p/60000/P_60000.pdf", server.uri()),
+                "secc": true,
+                "urlHtml": format!("{}/dochtml/p/60000/Prospecto.html", server.uri())
+            }]
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/dochtml/p/60000/Prospecto.html"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<h1>Prospecto</h1>"))
+        .expect(2)
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir()?;
+    let client = create_client(&server)?;
+    let ids = ["60000".to_string()];
+    let mut options = DocumentDownloadOptions {
+        html: true,
+        ..Default::default()
+    };
+
+    let first = client
+        .download_documents(DocumentType::PackageLeaflet, &ids, dir.path(), &options)
+        .await;
+    let skipped = client
+        .download_documents(DocumentType::PackageLeaflet, &ids, dir.path(), &options)
+        .await;
+    options.overwrite = true;
+    let replaced = client
+        .download_documents(DocumentType::PackageLeaflet, &ids, dir.path(), &options)
+        .await;
+
+    let file = dir.path().join("P_60000.html");
+    assert!(matches!(
+        &first[0].status,
+        DocumentDownloadStatus::Downloaded { path, bytes: 18 } if *path == file
+    ));
+    assert!(matches!(
+        &skipped[0].status,
+        DocumentDownloadStatus::Skipped { .. }
+    ));
+    assert!(matches!(
+        &replaced[0].status,
+        DocumentDownloadStatus::Downloaded { .. }
+    ));
+    assert_eq!(fs::read_to_string(&file)?, "<h1>Prospecto</h1>");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_retries_server_errors() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/notas"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(2)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/notas"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .mount(&server)
+        .await;
+    let client = |max_attempts| {
+        CimaClient::builder()
+            .base_url(&server.uri())
+            .retry_policy(RetryPolicy {
+                max_attempts,
+                initial_delay: Duration::from_millis(10),
+                ..Default::default()
+            })
+            .build()
+    };
+
+    let without_retries = client(1)?.get_safety_notes("60000").await;
+    let with_retries = client(2)?.get_safety_notes("60000").await;
+
+    assert!(without_retries.is_err());
+    assert!(with_retries?.is_empty());
+    assert_eq!(
+        server.received_requests().await.unwrap_or_default().len(),
+        3
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_retry_policy_recovers_from_two_failures() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/notas"))
+        .respond_with(ResponseTemplate::new(502))
+        .up_to_n_times(2)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/notas"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/materiales"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = CimaClient::builder()
+        .base_url(&server.uri())
+        .retry_policy(RetryPolicy {
+            initial_delay: Duration::from_millis(10),
+            ..Default::default()
+        })
+        .build()?;
+
+    // The default policy makes three attempts
+    assert!(client.get_safety_notes("60000").await?.is_empty());
+    assert_eq!(
+        server.received_requests().await.unwrap_or_default().len(),
+        3
+    );
+    // Client errors are never retried
+    assert!(client.get_informative_materials("60000").await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_builder_retries_transient_statuses() -> Result<()> {
+    let server = MockServer::start().await;
+    for status in [429, 500, 502, 503, 504] {
+        Mock::given(method("GET"))
+            .and(path("/notas"))
+            .respond_with(ResponseTemplate::new(status))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("GET"))
+        .and(path("/notas"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/materiales"))
+        .respond_with(ResponseTemplate::new(501))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = CimaClient::builder()
+        .base_url(&server.uri())
+        .max_retries(5)
+        .initial_backoff(Duration::from_millis(1))
+        .build()?;
+
+    assert!(client.get_safety_notes("60000").await?.is_empty());
+    assert_eq!(
+        server.received_requests().await.unwrap_or_default().len(),
+        6
+    );
+    assert!(client.get_informative_materials("60000").await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_malformed_response_and_redirect_loop_not_retried() -> Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Answers every connection with something that is not HTTP
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    let connections = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            accepted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let mut buffer = [0; 1024];
+            let _ = socket.read(&mut buffer).await;
+            let _ = socket.write_all(b"NOT HTTP AT ALL\r\n\r\n").await;
+        }
+    });
+    let policy = RetryPolicy {
+        initial_delay: Duration::from_millis(1),
+        ..Default::default()
+    };
+    let client = CimaClient::builder()
+        .base_url(&format!("http://{address}"))
+        .retry_policy(policy.clone())
+        .build()?;
+
+    assert!(client.get_safety_notes("60000").await.is_err());
+    assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    // Every redirect of a loop is followed until reqwest gives up, once
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/notas"))
+        .respond_with(ResponseTemplate::new(302).insert_header("Location", "/notas"))
+        .mount(&server)
+        .await;
+    let once = CimaClient::builder()
+        .base_url(&server.uri())
+        .retry_policy(RetryPolicy::none())
+        .build()?;
+    assert!(once.get_safety_notes("60000").await.is_err());
+    let single_attempt = server.received_requests().await.unwrap_or_default().len();
+    server.reset().await;
+    Mock::given(method("GET"))
+        .and(path("/notas"))
+        .respond_with(ResponseTemplate::new(302).insert_header("Location", "/notas"))
+        .mount(&server)
+        .await;
+    let client = CimaClient::builder()
+        .base_url(&server.uri())
+        .retry_policy(policy)
+        .build()?;
+
+    assert!(client.get_safety_notes("60000").await.is_err());
+    assert_eq!(
+        server.received_requests().await.unwrap_or_default().len(),
+        single_attempt
+    );
+    Ok(())
+}
+
+#[test]
+fn test_retry_policy_delays() {
+    let policy = RetryPolicy {
+        initial_delay: Duration::from_millis(100),
+        multiplier: 3.0,
+        jitter: 0.0,
+        ..Default::default()
+    };
+    assert_eq!(policy.delay(0), Duration::from_millis(100));
+    assert_eq!(policy.delay(2), Duration::from_millis(900));
+
+    let jittered = RetryPolicy {
+        jitter: 0.5,
+        ..policy
+    };
+    for _ in 0..20 {
+        let delay = jittered.delay(1);
+        assert!(delay >= Duration::from_millis(150) && delay <= Duration::from_millis(450));
+    }
+}
+
+#[tokio::test]
+async fn test_client_timeout() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/notas"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!([]))
+                .set_delay(Duration::from_secs(5)),
+        )
+        .mount(&server)
+        .await;
+    let client = CimaClient::with_options(CimaClientOptions {
+        base_url: server.uri(),
+        timeout: Duration::from_millis(100),
+        retry: RetryPolicy::none(),
+        ..Default::default()
+    })?;
+
+    let start = tokio::time::Instant::now();
+    let result = client.get_safety_notes("60000").await;
+
+    assert!(result.is_err());
+    assert!(start.elapsed() < Duration::from_secs(2));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_builder_configures_client() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/notas"))
+        .and(header("user-agent", "my-exporter/1.0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/presentacion/600000"))
+        .respond_with(ResponseTemplate::new(404).set_delay(Duration::from_secs(5)))
+        .mount(&server)
+        .await;
+    let client = CimaClient::builder()
+        .base_url(&server.uri())
+        .user_agent("my-exporter/1.0")
+        .timeout(Duration::from_millis(200))
+        .connect_timeout(Duration::from_secs(1))
+        .retry_policy(RetryPolicy::none())
+        .build()?;
+
+    assert!(client.get_safety_notes("60000").await?.is_empty());
+
+    let start = tokio::time::Instant::now();
+    let error = client
+        .get_presentation("600000")
+        .await
+        .expect_err("the response is slower than the timeout");
+    assert!(start.elapsed() < Duration::from_secs(2));
+    let timed_out = error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .any(reqwest::Error::is_timeout);
+    assert!(timed_out, "{error:#}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_raw_request() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/nuevoEndpoint/72112"))
+        .and(query_param("nombre", "ácido & co"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"total": 1})))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/nuevoEndpoint/texto"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("hola"))
+        .mount(&server)
+        .await;
+    let client = create_client(&server)?;
+
+    let params = vec![("nombre", "ácido & co".to_string())];
+    let value: serde_json::Value = client
+        .request(&Endpoint::raw("nuevoEndpoint/72112")?, &params)
+        .await?;
+    assert_eq!(value, json!({"total": 1}));
+
+    let text = client
+        .request_text(&Endpoint::raw("/nuevoEndpoint/texto")?, &())
+        .await?;
+    assert_eq!(text, "hola");
+
+    let missing = client
+        .request::<serde_json::Value, _>(&Endpoint::raw("desconocido")?, &())
+        .await
+        .unwrap_err();
+    let status = missing.downcast_ref::<ApiStatusError>().unwrap().status;
+    assert_eq!(status, 404);
+
+    let invalid = Endpoint::Presentation("672442/../maestras".to_string());
+    assert!(
+        client
+            .request::<serde_json::Value, _>(&invalid, &())
+            .await
+            .is_err()
+    );
+    assert_eq!(
+        server.received_requests().await.unwrap_or_default().len(),
+        3
+    );
+    Ok(())
+}
+
+/// Method and identifying parameters of the [`EndpointError`] in the chain of `error`
+fn endpoint_error(error: &anyhow::Error) -> &EndpointError {
+    error
+        .downcast_ref::<EndpointError>()
+        .unwrap_or_else(|| panic!("no EndpointError in {error:#}"))
+}
+
+#[tokio::test]
+async fn test_errors_name_method_and_parameters() -> Result<()> {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    let client = create_client(&server)?;
+
+    let error = client
+        .get_medication(Some("72112"), None)
+        .await
+        .unwrap_err();
+    assert_eq!(endpoint_error(&error).method, "get_medication");
+    assert_eq!(endpoint_error(&error).param("nregistro"), Some("72112"));
+    assert!(
+        format!("{error:#}").contains("nregistro=72112"),
+        "{error:#}"
+    );
+    assert_eq!(error.downcast_ref::<ApiStatusError>().unwrap().status, 404);
+
+    let error = client.get_presentation("672442").await.unwrap_err();
+    assert_eq!(endpoint_error(&error).method, "get_presentation");
+    assert_eq!(endpoint_error(&error).param("cn"), Some("672442"));
+
+    let params = MasterDataParams {
+        name: Some("par".to_string()),
+        page: Some(3),
+        ..Default::default()
+    };
+    let error = client
+        .get_master_data(MasterDataType::ActiveIngredients, &params)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "get_master_data failed for maestra=1, nombre=par, pagina=3"
+    );
+    Ok(())
+}
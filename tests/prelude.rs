@@ -0,0 +1,49 @@
+//! Uses one item of each public module through `cima_rs::prelude` only.
+
+#![cfg(all(feature = "api", feature = "parser", feature = "downloader"))]
+
+use anyhow::Result;
+use cima_rs::prelude::*;
+
+#[test]
+fn test_prelude_covers_each_module() -> Result<()> {
+    // api_client
+    let _client = CimaClient::with_options(CimaClientOptions::default())?;
+
+    // endpoints
+    let params = SearchMedicationsParams {
+        name: Some("paracetamol".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(params.query(), vec![("nombre", "paracetamol".to_string())]);
+    assert_eq!(Endpoint::raw("medicamento")?.path()?, "medicamento");
+
+    // models
+    let page: PaginatedResponse<MedicationSummary> = serde_json::from_str(
+        r#"{"totalFilas": 0, "pagina": 1, "tamanioPagina": 25, "resultados": []}"#,
+    )?;
+    assert!(page.results.is_empty());
+
+    // export
+    assert!(!MedicationSummary::HEADERS.is_empty());
+
+    // parser
+    let xml = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/nomenclator/DICCIONARIO_ATC.xml"
+    );
+    let dir = tempfile::tempdir()?;
+    let csv = dir.path().join("atc.csv");
+    let report: ParseReport =
+        DictionaryKind::Atc.parse(std::path::Path::new(xml), &csv, &ParserOptions::default())?;
+    assert!(report.records > 0);
+    let _: fn(&PrescriptionRecord) -> &str = |record| &record.cod_nacion;
+    let _: Option<PrescriptionSupplyProblem> = None;
+
+    // downloader
+    assert!(!DownloadOptions::default().force);
+
+    // enrich
+    let _ = NregistroOptions::default();
+    Ok(())
+}
@@ -0,0 +1,72 @@
+#![cfg(feature = "test-util")]
+
+use cima_rs::parser::{ParserOptions, parse_prescription_xml_to_csvs_with_options};
+use cima_rs::testing::fixtures::generate_prescription_xml;
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const RECORDS: usize = 50_000;
+
+fn write_synthetic_prescription_xml(path: &Path, records: usize) {
+    generate_prescription_xml(records, 42, File::create(path).unwrap()).unwrap();
+}
+
+fn timed_parse(xml_path: &Path, workers: usize) -> (Duration, Vec<u8>) {
+    let output_dir = tempfile::tempdir().unwrap();
+    let options = ParserOptions {
+        workers,
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let report =
+        parse_prescription_xml_to_csvs_with_options(xml_path, output_dir.path(), &options).unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(report.records, RECORDS);
+    let main = std::fs::read(output_dir.path().join("prescriptions.csv")).unwrap();
+    (elapsed, main)
+}
+
+/// Compares single-threaded and 4-worker wall time.
+///
+/// Timings are only meaningful in release mode:
+/// `cargo test --release --features test-util --test prescription_pipeline_bench -- --ignored --nocapture`
+#[test]
+#[ignore = "benchmark"]
+fn bench_prescription_workers() {
+    let work_dir = tempfile::tempdir().unwrap();
+    let xml_path = work_dir.path().join("Prescripcion.xml");
+    write_synthetic_prescription_xml(&xml_path, RECORDS);
+
+    let (single, expected) = timed_parse(&xml_path, 1);
+    let (parallel, output) = timed_parse(&xml_path, 4);
+
+    assert_eq!(output, expected);
+    println!(
+        "{} records: 1 worker {:?}, 4 workers {:?} ({:.2}x)",
+        RECORDS,
+        single,
+        parallel,
+        single.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
+
+#[test]
+fn test_synthetic_prescriptions_fill_every_file() {
+    let work_dir = tempfile::tempdir().unwrap();
+    let xml_path = work_dir.path().join("Prescripcion.xml");
+    write_synthetic_prescription_xml(&xml_path, 1000);
+
+    let output_dir = tempfile::tempdir().unwrap();
+    let report = parse_prescription_xml_to_csvs_with_options(
+        xml_path.as_path(),
+        output_dir.path(),
+        &ParserOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(report.records, 1000);
+    assert!(report.errors.is_empty() && report.duplicates == 0);
+    for (file, rows) in &report.rows {
+        assert!(*rows > 0, "{file}");
+    }
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "test-util")]
+
+use cima_rs::parser::parse_prescription_xml_to_csvs;
+use cima_rs::testing::fixtures::generate_prescription_xml;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::File;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Allocator wrapper tracking current and peak heap usage
+struct CountingAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const RECORDS: usize = 100_000;
+
+fn write_synthetic_prescription_xml(path: &std::path::Path) {
+    generate_prescription_xml(RECORDS, 42, File::create(path).unwrap()).unwrap();
+}
+
+#[test]
+fn test_prescription_parse_memory_is_bounded() {
+    let work_dir = tempfile::tempdir().unwrap();
+    let xml_path = work_dir.path().join("Prescripcion.xml");
+    write_synthetic_prescription_xml(&xml_path);
+    let xml_size = std::fs::metadata(&xml_path).unwrap().len() as usize;
+
+    let output_dir = tempfile::tempdir().unwrap();
+    let baseline = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+
+    parse_prescription_xml_to_csvs(xml_path.as_path(), output_dir.path()).unwrap();
+
+    let peak = PEAK.load(Ordering::SeqCst) - baseline;
+    assert!(
+        peak < 4 * 1024 * 1024,
+        "peak heap usage {} bytes for a {} byte input",
+        peak,
+        xml_size
+    );
+
+    let rows = csv::Reader::from_path(output_dir.path().join("prescriptions.csv"))
+        .unwrap()
+        .records()
+        .count();
+    assert_eq!(rows, RECORDS);
+}
@@ -0,0 +1,162 @@
+#![cfg(feature = "parser")]
+
+use cima_rs::parser::{
+    DictionaryKind, PRESCRIPTION_CSV_FILES, PRESCRIPTIONS_CSV, ParserOptions,
+    parse_prescription_xml_to_csv_with_options, parse_prescription_xml_to_csvs_with_options,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn fixture(dir: &str, name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(dir)
+        .join(name)
+}
+
+/// The fixture with its records, the children of the root element, in reverse order
+fn shuffled(path: &Path, dir: &Path) -> PathBuf {
+    let xml = fs::read_to_string(path).unwrap();
+    let lines: Vec<&str> = xml.lines().collect();
+    let open = lines[2].trim();
+    let close = open.replacen('<', "</", 1);
+    let mut records: Vec<Vec<&str>> = Vec::new();
+    let mut end = 2;
+    while lines[end].trim() == open {
+        let length = lines[end..]
+            .iter()
+            .position(|line| line.trim() == close)
+            .unwrap();
+        records.push(lines[end..=end + length].to_vec());
+        end += length + 1;
+    }
+    assert!(records.len() > 1, "{}", path.display());
+    records.reverse();
+    let shuffled: Vec<&str> = lines[..2]
+        .iter()
+        .copied()
+        .chain(records.into_iter().flatten())
+        .chain(lines[end..].iter().copied())
+        .collect();
+    let shuffled_path = dir.join(path.file_name().unwrap());
+    fs::write(&shuffled_path, shuffled.join("\n") + "\n").unwrap();
+    shuffled_path
+}
+
+fn sorted(options: ParserOptions) -> ParserOptions {
+    ParserOptions {
+        sort_output: true,
+        ..options
+    }
+}
+
+/// Files of `dir` with their contents, in name order
+fn files(dir: &Path) -> Vec<(String, String)> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            (
+                path.file_name().unwrap().to_string_lossy().into_owned(),
+                fs::read_to_string(&path).unwrap(),
+            )
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_sorted_prescription_csvs_do_not_depend_on_input_order() {
+    let input = tempfile::tempdir().unwrap();
+    let xml_path = fixture("delta", "Prescripcion.xml");
+    let shuffled_path = shuffled(&xml_path, input.path());
+
+    for options in [
+        sorted(ParserOptions::default()),
+        sorted(ParserOptions {
+            workers: 4,
+            ..Default::default()
+        }),
+        sorted(ParserOptions {
+            max_rows_per_file: Some(2),
+            ..Default::default()
+        }),
+    ] {
+        let (original, reversed) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        parse_prescription_xml_to_csvs_with_options(xml_path.as_path(), original.path(), &options)
+            .unwrap();
+        parse_prescription_xml_to_csvs_with_options(
+            shuffled_path.as_path(),
+            reversed.path(),
+            &options,
+        )
+        .unwrap();
+        let files = files(original.path());
+        assert!(files.len() >= PRESCRIPTION_CSV_FILES.len(), "{options:?}");
+        assert_eq!(files, self::files(reversed.path()), "{options:?}");
+    }
+
+    // Unsorted output follows the input order
+    let dir = tempfile::tempdir().unwrap();
+    parse_prescription_xml_to_csvs_with_options(
+        shuffled_path.as_path(),
+        dir.path(),
+        &ParserOptions::default(),
+    )
+    .unwrap();
+    let prescriptions = fs::read_to_string(dir.path().join(PRESCRIPTIONS_CSV)).unwrap();
+    assert!(prescriptions.lines().nth(1).unwrap().starts_with("600002,"));
+
+    let csv_path = dir.path().join("sorted.csv");
+    parse_prescription_xml_to_csv_with_options(
+        shuffled_path.as_path(),
+        csv_path.as_path(),
+        &sorted(ParserOptions::default()),
+    )
+    .unwrap();
+    let keys: Vec<_> = fs::read_to_string(csv_path)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap().to_string())
+        .collect();
+    assert_eq!(keys, ["600000", "600001", "600002"]);
+}
+
+#[test]
+fn test_sorted_dictionary_csvs_do_not_depend_on_input_order() {
+    let input = tempfile::tempdir().unwrap();
+    let (original, reversed) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let options = sorted(ParserOptions::default());
+    for kind in DictionaryKind::ALL {
+        let xml_path = fixture("nomenclator", kind.default_xml_filename());
+        kind.parse(
+            xml_path.clone(),
+            original.path().join(kind.default_csv_filename()),
+            &options,
+        )
+        .unwrap();
+        kind.parse(
+            shuffled(&xml_path, input.path()),
+            reversed.path().join(kind.default_csv_filename()),
+            &options,
+        )
+        .unwrap();
+    }
+    assert_eq!(files(original.path()), files(reversed.path()));
+
+    // Numeric codes are ordered by value
+    let routes = fs::read_to_string(
+        original
+            .path()
+            .join(DictionaryKind::AdministrationRoutes.default_csv_filename()),
+    )
+    .unwrap();
+    let codes: Vec<_> = routes
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert_eq!(codes, ["12", "48"]);
+}
@@ -0,0 +1,152 @@
+//! Mock CIMA API answering with the responses recorded in tests/fixtures/api.
+//!
+//! Besides the recorded routes it serves a few error cases: a 404 for the medication
+//! [`MISSING_NREGISTRO`], a malformed body for [`MALFORMED_NREGISTRO`] and 204 No
+//! Content for the sections of the technical sheet of [`NO_CONTENT_NREGISTRO`]. Any
+//! other request gets wiremock's default 404.
+
+// Each test crate uses a different part of the module
+#![allow(dead_code)]
+
+use anyhow::{Context, Result};
+use cima_rs::CimaClient;
+use serde_json::Value;
+use std::path::PathBuf;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Medication answered with 404 Not Found
+pub const MISSING_NREGISTRO: &str = "00000";
+/// Medication answered with truncated JSON
+pub const MALFORMED_NREGISTRO: &str = "99999";
+/// Medication whose technical sheet sections are answered with 204 No Content
+pub const NO_CONTENT_NREGISTRO: &str = "88888";
+
+/// Query parameters matched by a recorded response
+type Query = &'static [(&'static str, &'static str)];
+
+/// Endpoint, query parameters and fixture of each recorded response
+const RECORDED: &[(&str, Query, &str)] = &[
+    (
+        "/medicamento",
+        &[("nregistro", "72112")],
+        "medicamento_72112.json",
+    ),
+    (
+        "/medicamento",
+        &[("cn", "672442")],
+        "medicamento_72112.json",
+    ),
+    (
+        "/medicamentos",
+        &[("nombre", "paracetamol")],
+        "medicamentos_paracetamol.json",
+    ),
+    (
+        "/medicamentos",
+        &[("nombre", "ibuprofeno"), ("comerc", "1")],
+        "medicamentos_ibuprofeno_comerc.json",
+    ),
+    (
+        "/medicamentos",
+        &[("nombre", "a"), ("pagina", "2")],
+        "medicamentos_a_pagina_2.json",
+    ),
+    ("/presentacion/672442", &[], "presentacion_672442.json"),
+    (
+        "/presentaciones",
+        &[("nregistro", "72112")],
+        "presentaciones_72112.json",
+    ),
+    ("/psuministro", &[], "psuministro.json"),
+    ("/psuministro/712729", &[], "psuministro_712729.json"),
+    ("/notas", &[("nregistro", "72112")], "notas_72112.json"),
+    ("/notas", &[("nregistro", "60000")], "notas_60000.json"),
+    (
+        "/materiales",
+        &[("nregistro", "72112")],
+        "materiales_72112.json",
+    ),
+    (
+        "/maestras",
+        &[("maestra", "1"), ("nombre", "par")],
+        "maestras_pactivos_par.json",
+    ),
+    (
+        "/maestras",
+        &[("maestra", "1"), ("id", "12")],
+        "maestras_pactivos_12.json",
+    ),
+    (
+        "/maestras",
+        &[("maestra", "3"), ("nombre", "comp")],
+        "maestras_formas_comp.json",
+    ),
+    (
+        "/registroCambios",
+        &[("fecha", "01/01/2024")],
+        "registro_cambios_2024.json",
+    ),
+    (
+        "/vmpp",
+        &[("nombre", "paracetamol")],
+        "vmpp_paracetamol.json",
+    ),
+];
+
+/// Path of the recorded response `name`
+pub fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/api")).join(name)
+}
+
+/// Recorded response `name`, parsed
+pub fn fixture(name: &str) -> Result<Value> {
+    let path = fixture_path(name);
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Starts a mock server serving every recorded response and the error cases
+pub async fn recorded_server() -> Result<MockServer> {
+    let server = MockServer::start().await;
+    for (endpoint, query, name) in RECORDED {
+        let mock = query.iter().fold(
+            Mock::given(method("GET")).and(path(*endpoint)),
+            |mock, (key, value)| mock.and(query_param(*key, *value)),
+        );
+        mock.respond_with(ResponseTemplate::new(200).set_body_json(fixture(name)?))
+            .mount(&server)
+            .await;
+    }
+
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .and(query_param("nregistro", MISSING_NREGISTRO))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/medicamento"))
+        .and(query_param("nregistro", MALFORMED_NREGISTRO))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(
+            std::fs::read(fixture_path("medicamento_malformed.json"))?,
+            "application/json",
+        ))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/docSegmentado/secciones/1"))
+        .and(query_param("nregistro", NO_CONTENT_NREGISTRO))
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&server)
+        .await;
+    Ok(server)
+}
+
+/// Client of a [`recorded_server`], which must live as long as the client is used
+pub async fn recorded_client() -> Result<(MockServer, CimaClient)> {
+    let server = recorded_server().await?;
+    let client = CimaClient::builder().base_url(&server.uri()).build()?;
+    Ok((server, client))
+}
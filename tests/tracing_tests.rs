@@ -0,0 +1,122 @@
+#![cfg(all(feature = "api", feature = "parser"))]
+
+mod support;
+
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Subscriber, subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::{Layer, Registry};
+
+/// Span with the values of its fields, as text
+#[derive(Debug, Clone)]
+struct SpanRecord {
+    name: &'static str,
+    fields: BTreeMap<String, String>,
+}
+
+impl Visit for SpanRecord {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields
+            .insert(field.name().to_string(), value.to_string());
+    }
+}
+
+/// Layer collecting every closed span
+#[derive(Clone, Default)]
+struct Collector {
+    open: Arc<Mutex<HashMap<Id, SpanRecord>>>,
+    closed: Arc<Mutex<Vec<SpanRecord>>>,
+}
+
+impl Collector {
+    fn subscriber(&self) -> impl Subscriber + Send + Sync + 'static {
+        Registry::default().with(self.clone())
+    }
+
+    /// Closed spans named `name`
+    fn spans(&self, name: &str) -> Vec<SpanRecord> {
+        let closed = self.closed.lock().unwrap();
+        closed
+            .iter()
+            .filter(|span| span.name == name)
+            .cloned()
+            .collect()
+    }
+}
+
+impl<S: Subscriber> Layer<S> for Collector {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+        let mut span = SpanRecord {
+            name: attrs.metadata().name(),
+            fields: BTreeMap::new(),
+        };
+        attrs.record(&mut span);
+        self.open.lock().unwrap().insert(id.clone(), span);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        if let Some(span) = self.open.lock().unwrap().get_mut(id) {
+            values.record(span);
+        }
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        if let Some(span) = self.open.lock().unwrap().remove(&id) {
+            self.closed.lock().unwrap().push(span);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_medication_fetch_spans() -> Result<()> {
+    let collector = Collector::default();
+    let _guard = subscriber::set_default(collector.subscriber());
+    let (_server, client) = support::recorded_client().await?;
+
+    client.get_medication(Some("72112"), None).await?;
+
+    let endpoint = collector.spans("get_medication");
+    assert_eq!(endpoint.len(), 1, "{endpoint:?}");
+    assert_eq!(endpoint[0].fields["nregistro"], "72112");
+    assert!(!endpoint[0].fields.contains_key("cn"));
+    let request = collector.spans("request");
+    assert_eq!(request.len(), 1, "{request:?}");
+    assert_eq!(request[0].fields["endpoint"], "medicamento");
+    assert_eq!(request[0].fields["status"], "200");
+    assert!(request[0].fields["url"].ends_with("/medicamento?nregistro=72112"));
+    Ok(())
+}
+
+#[test]
+fn test_fixture_parse_spans() -> Result<()> {
+    let xml = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/nomenclator/DICCIONARIO_ATC.xml");
+    let dir = tempfile::tempdir()?;
+    let csv = dir.path().join("atc.csv");
+    let expected = cima_rs::parser::parse_atc_xml(&xml)?.len();
+
+    let collector = Collector::default();
+    subscriber::with_default(collector.subscriber(), || {
+        cima_rs::parser::parse_atc_xml_to_csv(xml.as_path(), csv.as_path())
+    })?;
+
+    let parses = collector.spans("parse");
+    assert_eq!(parses.len(), 1, "{parses:?}");
+    let fields = &parses[0].fields;
+    assert_eq!(fields["file"], "ATC");
+    assert_eq!(fields["format"], "csv");
+    assert_eq!(fields["records"], expected.to_string());
+    assert_eq!(fields["rows"], expected.to_string());
+    Ok(())
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "parser")]
+
+use cima_rs::parser::{
+    DictionaryKind, PRESCRIPTION_CSV_FILES, ParserOptions, PrescriptionSinks, open_xml,
+    parse_prescription_xml_to_csvs_with_options, parse_prescription_xml_to_sinks_with_options,
+};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+fn fixture(dir: &str, name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(dir)
+        .join(name)
+}
+
+#[test]
+fn test_dictionary_writer_matches_file_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = ParserOptions {
+        delimiter: b';',
+        ..Default::default()
+    };
+    for kind in DictionaryKind::ALL {
+        let xml_path = fixture("nomenclator", kind.default_xml_filename());
+        let csv_path = dir.path().join(kind.default_csv_filename());
+        kind.parse(xml_path.clone(), csv_path.clone(), &options)
+            .unwrap();
+
+        let mut buffer = Vec::new();
+        let report = kind
+            .parse_to_writer(open_xml(&xml_path).unwrap(), &mut buffer, &options)
+            .unwrap();
+        assert_eq!(report.records, 2, "{kind}");
+        assert_eq!(buffer, fs::read(&csv_path).unwrap(), "{kind}");
+    }
+}
+
+#[test]
+fn test_prescription_sinks_match_file_output() {
+    let xml_path = fixture("delta", "Prescripcion.xml");
+    for workers in [1, 4] {
+        let dir = tempfile::tempdir().unwrap();
+        let options = ParserOptions {
+            workers,
+            ..Default::default()
+        };
+        parse_prescription_xml_to_csvs_with_options(xml_path.as_path(), dir.path(), &options)
+            .unwrap();
+
+        let mut sinks = PrescriptionSinks::from_fn(|_| Ok(Vec::new())).unwrap();
+        let report = parse_prescription_xml_to_sinks_with_options(
+            open_xml(&xml_path).unwrap(),
+            &mut sinks,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(report.records, 3);
+        for (name, buffer) in PRESCRIPTION_CSV_FILES.iter().zip(sinks.each_mut()) {
+            assert_eq!(*buffer, fs::read(dir.path().join(name)).unwrap(), "{name}");
+        }
+    }
+}
+
+/// Writer appending to a buffer that outlives it, standing in for an upload
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_boxed_prescription_sinks() {
+    let xml_path = fixture("delta", "Prescripcion.xml");
+    let dir = tempfile::tempdir().unwrap();
+    parse_prescription_xml_to_csvs_with_options(
+        xml_path.as_path(),
+        dir.path(),
+        &ParserOptions::default(),
+    )
+    .unwrap();
+
+    let buffers: Vec<SharedBuffer> = PRESCRIPTION_CSV_FILES
+        .iter()
+        .map(|_| SharedBuffer::default())
+        .collect();
+    let mut next = buffers.iter().cloned();
+    let mut sinks: PrescriptionSinks =
+        PrescriptionSinks::from_fn(|_| Ok(Box::new(next.next().unwrap()) as Box<dyn Write>))
+            .unwrap();
+    // Splitting needs a writer per part, so it is ignored with sinks
+    let options = ParserOptions {
+        max_rows_per_file: Some(1),
+        ..Default::default()
+    };
+    parse_prescription_xml_to_sinks_with_options(
+        open_xml(&xml_path).unwrap(),
+        &mut sinks,
+        &options,
+    )
+    .unwrap();
+    drop(sinks);
+
+    for (name, buffer) in PRESCRIPTION_CSV_FILES.iter().zip(&buffers) {
+        assert_eq!(
+            *buffer.0.borrow(),
+            fs::read(dir.path().join(name)).unwrap(),
+            "{name}"
+        );
+    }
+}